@@ -252,6 +252,16 @@ fn main() -> ! {
 
     // this will block all initialization until the prereqs are met
     let (token, mut allow_redraw) = prereqs::prereqs(sid, time_conn);
+    // keep other apps out of the passwords and TOTP secrets
+    let pddb = pddb::Pddb::new();
+    match pddb.identify(token) {
+        Ok(_) => for dict in [actions::VAULT_PASSWORD_DICT, actions::VAULT_TOTP_DICT].iter() {
+            if let Err(e) = pddb.claim_dict(dict, None) {
+                log::warn!("couldn't claim {}: {:?}", dict, e);
+            }
+        },
+        Err(e) => log::warn!("couldn't identify to the PDDB: {:?}", e),
+    }
     let mut vaultux = VaultUx::new(token, &xns, sid, menu_mgr, actions_conn, mode.clone(), item_list, action_active.clone());
     // Trigger the mode update in the actions
     send_message(actions_conn,
@@ -418,6 +428,10 @@ fn main() -> ! {
                         modals.dynamic_notification_update(Some(t!("vault.error.nothing_selected", xous::LANG)), None).ok();
                         tt.sleep_ms(ERR_TIMEOUT_MS).unwrap();
                     },
+                    Err(xous::Error::Timeout) => { // wall clock not set, so there's no TOTP code to type
+                        modals.dynamic_notification_update(Some(t!("vault.error.time_not_set", xous::LANG)), None).ok();
+                        tt.sleep_ms(ERR_TIMEOUT_MS).unwrap();
+                    },
                     Err(xous::Error::OutOfMemory) => { // trouble updating the key
                         modals.dynamic_notification_update(Some(t!("vault.error.update_error", xous::LANG)), None).ok();
                         tt.sleep_ms(ERR_TIMEOUT_MS).unwrap();
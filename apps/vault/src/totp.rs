@@ -1,10 +1,7 @@
 use crypto_mac::InvalidKeyLength;
 use sha1::Sha1;
 use hmac::{Hmac, Mac, NewMac};
-use std::{
-    convert::TryFrom,
-    time::{SystemTime, SystemTimeError},
-};
+use std::convert::TryFrom;
 use std::sync::{Arc, Mutex};
 use xous::{Message, send_message};
 use std::thread;
@@ -75,10 +72,9 @@ impl From<InvalidKeyLength> for Error {
     }
 }
 
-pub(crate) fn get_current_unix_time() -> Result<u64, SystemTimeError> {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map(|duration| duration.as_secs())
+/// Returns `None` if the wall clock has not been set yet, rather than the raw RTC count.
+pub(crate) fn get_current_unix_time() -> Option<u64> {
+    llio::LocalTime::new().get_utc_time_ms().map(|ms| ms / 1000)
 }
 
 fn unpack_u64(v: u64) -> [u8; 8] {
@@ -93,8 +93,9 @@ pub(crate) struct VaultUx {
     usb_dev: usb_device_xous::UsbHid,
 
     /// totp redraw state
-    last_epoch: u64,
-    current_time: u64,
+    /// `None` while the wall clock isn't set
+    last_epoch: Option<u64>,
+    current_time: Option<u64>,
 }
 
 pub(crate) const DEFAULT_FONT: GlyphStyle = GlyphStyle::Regular;
@@ -154,7 +155,7 @@ impl VaultUx {
         let item_height = (glyph_height * 2) as i16 + margin.y * 2 + 2; // +2 because of the border width
         let items_per_screen = available_height / item_height;
 
-        let current_time = totp::get_current_unix_time().unwrap_or(0);
+        let current_time = totp::get_current_unix_time();
 
         VaultUx {
             content,
@@ -176,7 +177,7 @@ impl VaultUx {
             actions_conn,
             action_active,
             usb_dev: usb_device_xous::UsbHid::new(),
-            last_epoch: current_time / 30,
+            last_epoch: current_time.map(|t| t / 30),
             current_time,
             last_query: String::new(),
         }
@@ -465,9 +466,9 @@ impl VaultUx {
         if mode_at_entry == VaultMode::Totp { // always redraw the title in TOTP mode
             self.title_dirty = true;
             // always grab the current time, regardless of the mode? saves an extra call to get time...
-            self.current_time = totp::get_current_unix_time().unwrap_or(0);
+            self.current_time = totp::get_current_unix_time();
             // duration bar is hard-coded to once every 30 seconds, even if the keys may not change that often.
-            let epoch = self.current_time / 30;
+            let epoch = self.current_time.map(|t| t / 30);
             if self.last_epoch != epoch {
                 self.last_epoch = epoch;
                 // force a redraw of all the items if the epoch has changed
@@ -497,11 +498,12 @@ impl VaultUx {
                 VaultMode::Password => write!(title_text, "🔐****").ok(),
             };
             self.gam.post_textview(&mut title_text).expect("couldn't post title");
-            if mode_at_entry == VaultMode::Totp {
+            // no duration bar until the clock is set, as there's no code to count down
+            if let (VaultMode::Totp, Some(current_time), Some(last_epoch)) = (mode_at_entry, self.current_time, self.last_epoch) {
                 const BAR_HEIGHT: i16 = 5;
                 const BAR_GAP: i16 = -10;
                 // draw the duration bar
-                let delta = (self.current_time - (self.last_epoch * 30)) as i32;
+                let delta = (current_time - (last_epoch * 30)) as i32;
                 let width = (self.screensize.x - (self.margin.x * 2)) as i32;
                 let delta_width = (delta * width * 100) / (30 * 100);
                 self.gam.draw_rectangle(
@@ -586,10 +588,12 @@ impl VaultUx {
                                 digit_count,
                                 algorithm
                             };
-                            let code = generate_totp_code(
-                                totp::get_current_unix_time().unwrap_or(0),
-                                &totp
-                            ).unwrap_or(t!("vault.error.record_error", xous::LANG).to_string());
+                            // a code for the wrong time would look as good as a right one, so none is shown
+                            let code = match self.current_time {
+                                Some(current_time) => generate_totp_code(current_time, &totp)
+                                    .unwrap_or(t!("vault.error.record_error", xous::LANG).to_string()),
+                                None => t!("vault.error.time_not_set", xous::LANG).to_string(),
+                            };
                             // why code on top? because the item.name can be very long, and it can wrap which would cause
                             // the code to become hidden.
                             write!(box_text, "{}\n{}", code, item.name).ok();
@@ -747,10 +751,9 @@ impl VaultUx {
                         digit_count,
                         algorithm
                     };
-                    let code = generate_totp_code(
-                        totp::get_current_unix_time().unwrap_or(0),
-                        &totp
-                    ).unwrap_or(t!("vault.error.record_error", xous::LANG).to_string());
+                    let current_time = totp::get_current_unix_time().ok_or(xous::Error::Timeout)?;
+                    let code = generate_totp_code(current_time, &totp)
+                        .unwrap_or(t!("vault.error.record_error", xous::LANG).to_string());
                     self.usb_dev.send_str(&code).ok();
                 }
             }
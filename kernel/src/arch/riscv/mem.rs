@@ -932,6 +932,40 @@ pub fn ensure_page_exists_inner(address: usize) -> Result<usize, xous_kernel::Er
     Ok(new_page)
 }
 
+/// Finds the run of pages around `virt` that are mapped or reserved for the
+/// current process and aren't shared out, i.e. the memory around `virt` that
+/// the process can read without faulting. Returns the `(start, end)` of the
+/// run, or `None` if the page `virt` is in isn't such a page itself.
+pub fn owned_extent(virt: usize) -> Option<(usize, usize)> {
+    let owned = |page: usize| {
+        if page >= USER_AREA_END {
+            return false;
+        }
+        match pagetable_entry(page) {
+            // Reserved pages have their flags set but aren't valid yet; they're
+            // backed as soon as they're touched.
+            Ok(entry) => {
+                let flags = unsafe { entry.read_volatile() } & 0x1ff;
+                flags != 0 && flags & MMUFlags::S.bits() == 0
+            }
+            Err(_) => false,
+        }
+    };
+    let page = virt & !(PAGE_SIZE - 1);
+    if !owned(page) {
+        return None;
+    }
+    let mut start = page;
+    while start >= PAGE_SIZE && owned(start - PAGE_SIZE) {
+        start -= PAGE_SIZE;
+    }
+    let mut end = page + PAGE_SIZE;
+    while owned(end) {
+        end += PAGE_SIZE;
+    }
+    Some((start, end))
+}
+
 /// Determine whether a virtual address has been mapped
 pub fn address_available(virt: usize) -> bool {
     if let Err(e) = virt_to_phys(virt) {
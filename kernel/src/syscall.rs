@@ -936,6 +936,15 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             }),
             _ => Err(xous_kernel::Error::InvalidLimit),
         },
+        #[cfg(baremetal)]
+        SysCall::GetStackBounds => {
+            let sp = ArchProcess::with_current(|process| process.current_thread().stack_pointer());
+            let (start, end) =
+                arch::mem::owned_extent(sp).ok_or(xous_kernel::Error::BadAddress)?;
+            Ok(xous_kernel::Result::MemoryRange(unsafe {
+                MemoryRange::new(start, end - start)
+            }?))
+        }
         /* https://github.com/betrusted-io/xous-core/issues/90
         SysCall::SetExceptionHandler(pc, sp) => SystemServices::with_mut(|ss| {
             ss.set_exception_handler(pid, pc, sp)
@@ -16,4 +16,8 @@ pub enum Opcode {
     TestScalar, //(u32),
     TestMemory, //(TestStruct),
     TestMemorySend,
+    /// A raw page-aligned buffer of any size, lent immutably
+    TestLend,
+    /// A raw page-aligned buffer of any size, lent mutably; the first and last bytes are incremented
+    TestLendMut,
 }
@@ -46,3 +46,21 @@ pub fn test_memory_send(cid: CID, testvar: u32) -> Result<u32, xous::Error> {
         .or(Err(xous::Error::InternalError))?;
     Ok(testvar + 2)
 }
+
+/// Lends `buf` to the target, which reads its first and last bytes.
+pub fn test_lend(cid: CID, buf: xous::MemoryRange) -> Result<(), xous::Error> {
+    send_message(
+        cid,
+        xous::Message::new_lend(Opcode::TestLend.to_usize().unwrap(), buf, None, None),
+    )
+    .map(|_| ())
+}
+
+/// Lends `buf` mutably to the target, which increments its first and last bytes.
+pub fn test_lend_mut(cid: CID, buf: xous::MemoryRange) -> Result<(), xous::Error> {
+    send_message(
+        cid,
+        xous::Message::new_lend_mut(Opcode::TestLendMut.to_usize().unwrap(), buf, None, None),
+    )
+    .map(|_| ())
+}
@@ -45,6 +45,17 @@ fn main() -> ! {
                 let reg = buffer.to_original::<TestStruct, _>().unwrap();
                 state += reg.challenge[0];
             }
+            Some(Opcode::TestLend) => {
+                // touch both ends of the buffer, so that every page of it has to be mapped in
+                let buf = envelope.body.memory_message().unwrap().buf.as_slice::<u8>();
+                state = state.wrapping_add(buf[0] as u32).wrapping_add(buf[buf.len() - 1] as u32);
+            }
+            Some(Opcode::TestLendMut) => {
+                let buf = envelope.body.memory_message_mut().unwrap().buf.as_slice_mut::<u8>();
+                let last = buf.len() - 1;
+                buf[0] = buf[0].wrapping_add(1);
+                buf[last] = buf[last].wrapping_add(1);
+            }
             None => {
                 error!("BENCHTARGET: couldn't convert opcode");
             }
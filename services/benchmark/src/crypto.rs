@@ -0,0 +1,87 @@
+//! Hashing, ciphers and the curve25519 engine. Each hardware-accelerated primitive is measured next to
+//! its software fallback, so the two can be compared on the same build. In hosted mode both sides
+//! run in software.
+//!
+//! Inputs are fixed patterns rather than TRNG output, so that every run does exactly the same work.
+use crate::harness::Harness;
+use sha2::{Digest, FallbackStrategy, Sha512};
+
+const HASH_LEN: usize = 4096;
+const AES_LEN: usize = 4096;
+
+fn pattern(buf: &mut [u8]) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = (i as u32).wrapping_mul(2_654_435_761).to_le_bytes()[3];
+    }
+}
+
+pub(crate) fn sha512_suite(h: &mut Harness) {
+    h.suite("sha512");
+    let mut data = [0u8; HASH_LEN];
+    pattern(&mut data);
+    let mut check = [0u8; 2];
+    for &(name, strategy) in [
+        ("hw", FallbackStrategy::WaitForHardware),
+        ("sw", FallbackStrategy::SoftwareOnly),
+    ].iter() {
+        let mut hasher = Sha512::new_with_strategy(strategy);
+        let mut digest = [0u8; 64];
+        h.bench_bytes(&format!("{}-{}", name, HASH_LEN), HASH_LEN, || {
+            hasher.update(&data);
+            digest.copy_from_slice(&hasher.finalize_reset());
+        });
+        check[if name == "hw" { 0 } else { 1 }] = digest[0];
+    }
+    // a benchmark that computes the wrong answer quickly isn't much of a result
+    if check[0] != check[1] {
+        log::error!("BENCHMARK: hardware and software sha512 disagree");
+    }
+}
+
+pub(crate) fn aes_suite(h: &mut Harness) {
+    use aes::cipher::{BlockEncryptMut, KeyInit};
+    h.suite("aes");
+    let mut key = [0u8; 32];
+    pattern(&mut key);
+    let mut blocks = [aes::Block::default(); AES_LEN / aes::BLOCK_SIZE];
+    for (i, block) in blocks.iter_mut().enumerate() {
+        block[0] = i as u8;
+    }
+
+    let mut cipher = aes::Aes256::new_from_slice(&key).unwrap();
+    h.bench_bytes(&format!("aes256-enc-{}", AES_LEN), AES_LEN, || {
+        cipher.encrypt_blocks_mut(&mut blocks);
+    });
+    let mut soft = aes::Aes256Soft::new_from_slice(&key).unwrap();
+    h.bench_bytes(&format!("aes256-soft-enc-{}", AES_LEN), AES_LEN, || {
+        soft.encrypt_blocks_mut(&mut blocks);
+    });
+    h.bench("aes256-key-schedule", || {
+        cipher = aes::Aes256::new_from_slice(&key).unwrap();
+    });
+}
+
+pub(crate) fn curve25519_suite(h: &mut Harness) {
+    use x25519_dalek::{PublicKey, StaticSecret};
+    h.suite("curve25519");
+    let mut alice_bytes = [0u8; 32];
+    pattern(&mut alice_bytes);
+    let mut bob_bytes = alice_bytes;
+    bob_bytes.reverse();
+    let alice = StaticSecret::from(alice_bytes);
+    let bob = StaticSecret::from(bob_bytes);
+    let bob_public = PublicKey::from(&bob);
+
+    // both of these are a scalar multiply, which runs on the engine when it's available
+    let mut public = PublicKey::from(&alice);
+    h.bench("x25519-public-key", || {
+        public = PublicKey::from(&alice);
+    });
+    let mut shared = [0u8; 32];
+    h.bench("x25519-diffie-hellman", || {
+        shared = alice.diffie_hellman(&bob_public).to_bytes();
+    });
+    if shared != bob.diffie_hellman(&public).to_bytes() {
+        log::error!("BENCHMARK: x25519 shared secrets disagree");
+    }
+}
@@ -0,0 +1,119 @@
+//! Timing loop and result reporting shared by all the suites.
+//!
+//! Each benchmark is first calibrated: the iteration count doubles until one run takes at least
+//! `TARGET_RUN_MS`, which keeps the ticktimer's millisecond resolution from swamping the result. The
+//! benchmark is then run `RUNS` times at that count, and the median is reported along with every run,
+//! so that noisy results can be spotted.
+//!
+//! Results are written to the log as one JSON object per line, prefixed with `BENCH|`. Pull them out of
+//! the console output with `tools/benchcmp.py`, which can also compare them against a previous run.
+use std::fmt::Write;
+
+const TARGET_RUN_MS: u64 = 200;
+const MAX_ITERS: u64 = 1 << 20;
+const RUNS: usize = 5;
+
+pub(crate) struct Harness {
+    tt: ticktimer_server::Ticktimer,
+    suite: &'static str,
+    count: usize,
+}
+
+impl Harness {
+    pub(crate) fn new() -> Self {
+        Harness {
+            tt: ticktimer_server::Ticktimer::new().unwrap(),
+            suite: "",
+            count: 0,
+        }
+    }
+
+    /// Writes the header that marks the start of a set of results.
+    pub(crate) fn begin(&self) {
+        let version = self.tt.get_version();
+        let target = if cfg!(target_os = "xous") { "hardware" } else { "hosted" };
+        log::info!(
+            "BENCH|{{\"event\":\"begin\",\"version\":\"{}\",\"target\":\"{}\"}}",
+            escape(version.lines().next().unwrap_or("")),
+            target
+        );
+    }
+
+    /// Writes the trailer that marks a complete set of results.
+    pub(crate) fn end(&self) {
+        log::info!("BENCH|{{\"event\":\"end\",\"count\":{}}}", self.count);
+    }
+
+    pub(crate) fn suite(&mut self, suite: &'static str) {
+        log::info!("running suite {}", suite);
+        self.suite = suite;
+    }
+
+    /// Measures `f`, which does one operation per call.
+    pub(crate) fn bench<F: FnMut()>(&mut self, name: &str, f: F) {
+        self.run(name, 0, f);
+    }
+
+    /// Measures `f`, which processes `bytes` bytes per call; throughput is reported as well.
+    pub(crate) fn bench_bytes<F: FnMut()>(&mut self, name: &str, bytes: usize, f: F) {
+        self.run(name, bytes, f);
+    }
+
+    fn time<F: FnMut()>(&self, iters: u64, f: &mut F) -> u64 {
+        let start = self.tt.elapsed_ms();
+        for _ in 0..iters {
+            f();
+        }
+        self.tt.elapsed_ms() - start
+    }
+
+    fn run<F: FnMut()>(&mut self, name: &str, bytes: usize, mut f: F) {
+        // calibration doubles as the warm-up
+        let mut iters = 1;
+        while self.time(iters, &mut f) < TARGET_RUN_MS && iters < MAX_ITERS {
+            iters *= 2;
+        }
+        let mut runs = [0u64; RUNS];
+        for run in runs.iter_mut() {
+            *run = self.time(iters, &mut f);
+        }
+        let mut sorted = runs;
+        sorted.sort_unstable();
+        let median_ms = sorted[RUNS / 2];
+
+        let mut line = String::new();
+        write!(
+            line,
+            "BENCH|{{\"suite\":\"{}\",\"name\":\"{}\",\"iters\":{},\"runs_ms\":[",
+            self.suite,
+            escape(name),
+            iters
+        )
+        .unwrap();
+        for (i, run) in runs.iter().enumerate() {
+            write!(line, "{}{}", if i == 0 { "" } else { "," }, run).unwrap();
+        }
+        write!(line, "],\"median_ns\":{}", median_ms * 1_000_000 / iters).unwrap();
+        if bytes != 0 && median_ms != 0 {
+            // bytes per millisecond is kilobytes (1000) per second
+            write!(line, ",\"bytes\":{},\"kb_per_s\":{}", bytes, bytes as u64 * iters / median_ms).unwrap();
+        }
+        line.push('}');
+        log::info!("{}", line);
+        self.count += 1;
+    }
+}
+
+/// Escapes a string for inclusion in a JSON string literal
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,36 @@
+//! Round-trips to `benchmark-target`, for each kind of message.
+use crate::harness::Harness;
+
+/// Sizes of the raw buffers lent to the target. Lends are always whole pages.
+const LEND_SIZES: [usize; 3] = [4096, 16384, 65536];
+
+pub(crate) fn suite(h: &mut Harness) {
+    h.suite("ipc");
+    let xns = xous_names::XousNames::new().unwrap();
+    let target = xns
+        .request_connection_blocking(benchmark_target::api::SERVER_NAME_BENCHMARK)
+        .expect("BENCHMARK: can't connect to benchmark target");
+
+    let mut count = 0;
+    h.bench("scalar", || {
+        count = benchmark_target::test_scalar(target, count).expect("BENCHMARK: scalar failed");
+    });
+    h.bench("rkyv-lend-mut", || {
+        count = benchmark_target::test_memory(target, count).expect("BENCHMARK: rkyv lend failed");
+    });
+    h.bench("rkyv-send", || {
+        count = benchmark_target::test_memory_send(target, count).expect("BENCHMARK: rkyv send failed");
+    });
+
+    for &size in LEND_SIZES.iter() {
+        let buf = xous::map_memory(None, None, size, xous::MemoryFlags::R | xous::MemoryFlags::W)
+            .expect("BENCHMARK: couldn't allocate lend buffer");
+        h.bench_bytes(&format!("lend-{}", size), size, || {
+            benchmark_target::test_lend(target, buf).expect("BENCHMARK: lend failed");
+        });
+        h.bench_bytes(&format!("lend-mut-{}", size), size, || {
+            benchmark_target::test_lend_mut(target, buf).expect("BENCHMARK: mutable lend failed");
+        });
+        xous::unmap_memory(buf).unwrap();
+    }
+}
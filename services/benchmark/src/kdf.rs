@@ -0,0 +1,38 @@
+//! The password hashes that secret PDDB bases are keyed with. Hashing dominates the time it takes to
+//! unlock a basis, and a bad password costs a run of every KDF the PDDB probes, so these numbers are what
+//! the Argon2id parameters in `pddb-format/src/kdf.rs` are picked from.
+//!
+//! bcrypt is run at the cost the PDDB uses and at the OWASP target; Argon2id is run at the PDDB's
+//! parameters, and at a few others that trade memory against passes.
+use crate::harness::Harness;
+use pddb_format::*;
+
+const PASSWORD: &str = "correct horse battery staple";
+/// (memory in KiB, passes). The low-memory settings bound what a smaller heap would cost. The next three
+/// are the alternatives to the PDDB's parameters: about the same memory-passes product, up to
+/// `ARGON2_MAX_M_COST`. The last is what new archives are keyed at.
+const ARGON2_PARAMS: [(u32, u32); 8] = [
+    (256, 8), (512, 6), (2048, 2), (4096, 1),
+    (4096, 8), (6144, 6), (8192, 4), (8192, 10),
+];
+
+pub(crate) fn suite(h: &mut Harness) {
+    h.suite("kdf");
+    let salt = [0x5au8; 16];
+    let mut output = [0u8; KDF_OUTPUT_LEN];
+
+    for &cost in [BCRYPT_COST, 10].iter() {
+        h.bench(&format!("bcrypt-{}", cost), || {
+            bcrypt(cost, &salt, PASSWORD, &mut output[..24]);
+        });
+    }
+    h.bench(&format!("argon2id-m{}-t{}-default", ARGON2_M_COST, ARGON2_T_COST), || {
+        BasisKdf::DEFAULT.hash_password(&salt, PASSWORD, &mut output);
+    });
+    for &(m_cost, t_cost) in ARGON2_PARAMS.iter() {
+        let kdf = BasisKdf::Argon2id { m_cost, t_cost, p_cost: ARGON2_P_COST };
+        h.bench(&format!("argon2id-m{}-t{}", m_cost, t_cost), || {
+            kdf.hash_password(&salt, PASSWORD, &mut output);
+        });
+    }
+}
@@ -0,0 +1,47 @@
+//! Key reads and writes in the PDDB. Only built with the `pddb-suite` feature, as it needs the whole
+//! stack of services under the PDDB, and it can't start until someone unlocks the PDDB.
+use crate::harness::Harness;
+use pddb::{Pddb, PddbMountPoller};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const BENCH_DICT: &str = "benchmark";
+const KEY_SIZES: [usize; 3] = [64, 4096, 32768];
+
+pub(crate) fn suite(h: &mut Harness) {
+    h.suite("pddb");
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let poller = PddbMountPoller::new();
+    while !poller.is_mounted_nonblocking() {
+        tt.sleep_ms(1000).unwrap();
+    }
+    let pddb = Pddb::new();
+    // start from a clean slate, in case a previous run was interrupted
+    pddb.delete_dict(BENCH_DICT, None).ok();
+
+    for &size in KEY_SIZES.iter() {
+        let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        let name = format!("key{}", size);
+        let mut key = pddb
+            .get(BENCH_DICT, &name, None, true, true, Some(size), None::<fn()>)
+            .expect("BENCHMARK: couldn't create key");
+        h.bench_bytes(&format!("write-{}", size), size, || {
+            key.seek(SeekFrom::Start(0)).unwrap();
+            key.write_all(&data).unwrap();
+        });
+        h.bench_bytes(&format!("write-sync-{}", size), size, || {
+            key.seek(SeekFrom::Start(0)).unwrap();
+            key.write_all(&data).unwrap();
+            pddb.sync().unwrap();
+        });
+        let mut readback = vec![0u8; size];
+        h.bench_bytes(&format!("read-{}", size), size, || {
+            key.seek(SeekFrom::Start(0)).unwrap();
+            key.read_exact(&mut readback).unwrap();
+        });
+        if readback != data {
+            log::error!("BENCHMARK: PDDB readback mismatch on {}", name);
+        }
+    }
+    pddb.delete_dict(BENCH_DICT, None).ok();
+    pddb.sync().ok();
+}
@@ -3,6 +3,8 @@
 pub(crate) const SERVER_NAME_DNS: &str = "_DNS Resolver Middleware_";
 use net::NetIpAddr;
 use rkyv::{Archive, Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[allow(dead_code)]
 pub(crate) const DNS_NAME_LENGTH_LIMIT: usize = 256;
@@ -49,6 +51,133 @@ pub(crate) enum Opcode {
     ///     * 4: Ipv4 Address -- 4 octets follow, for a total of 5 bytes
     ///     * 6: Ipv6 Address -- 16 octets follow, for a total of 17 bytes
     RawLookup = 6,
+
+    /// Look up the records of one type for a name, following CNAMEs.
+    ///
+    /// The query is a `MutableBorrow` of a `RecordPage`, with the name at the start of it, the
+    /// `valid` parameter set to the length of the name, and the `offset` parameter set to the
+    /// record type, as a `DnsRecordType`.
+    ///
+    /// The response has the same two-byte header as `RawLookup`. On success, the entries that
+    /// follow are `DnsRecord`s, each encoded as a big-endian record type and data length, then
+    /// the data; see `DnsRecord::encode()`.
+    RecordLookup = 7,
+
+    /// Sets or clears the encrypted resolver. A `MutableBorrow` of an `Option<SecureResolver>`;
+    /// the response replaces it with a `DnsResponseCode`, which is `NoError` once the setting has
+    /// been saved to the PDDB.
+    SetSecureResolver = 8,
+
+    /// A `MutableBorrow` of an `Option<SecureResolver>`, filled in with the resolver in use
+    GetSecureResolver = 9,
+
+    /// used internally to load the encrypted resolver setting, once the PDDB is mounted
+    PddbMounted = 10,
+}
+
+/// The record types that `RecordLookup` can ask for
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive, Copy, Clone, PartialEq, Eq)]
+#[repr(u16)]
+pub enum DnsRecordType {
+    A = 1,
+    Cname = 5,
+    Mx = 15,
+    Txt = 16,
+    Aaaa = 28,
+    Srv = 33,
+}
+
+/// A record from a `RecordLookup`. Names are in lower case, without the trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRecord {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(std::string::String),
+    Mx { preference: u16, exchange: std::string::String },
+    /// the character-strings of the record, which aren't necessarily UTF-8
+    Txt(Vec<Vec<u8>>),
+    Srv { priority: u16, weight: u16, port: u16, target: std::string::String },
+}
+#[allow(dead_code)]
+impl DnsRecord {
+    pub fn record_type(&self) -> DnsRecordType {
+        match self {
+            DnsRecord::A(_) => DnsRecordType::A,
+            DnsRecord::Aaaa(_) => DnsRecordType::Aaaa,
+            DnsRecord::Cname(_) => DnsRecordType::Cname,
+            DnsRecord::Mx { .. } => DnsRecordType::Mx,
+            DnsRecord::Txt(_) => DnsRecordType::Txt,
+            DnsRecord::Srv { .. } => DnsRecordType::Srv,
+        }
+    }
+    /// Appends the record to `out` in the `RecordLookup` response format. Names are written out as
+    /// text; TXT data is the character-strings, each preceded by its length, as on the wire.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        let mut data = Vec::new();
+        match self {
+            DnsRecord::A(addr) => data.extend_from_slice(&addr.octets()),
+            DnsRecord::Aaaa(addr) => data.extend_from_slice(&addr.octets()),
+            DnsRecord::Cname(name) => data.extend_from_slice(name.as_bytes()),
+            DnsRecord::Mx { preference, exchange } => {
+                data.extend_from_slice(&preference.to_be_bytes());
+                data.extend_from_slice(exchange.as_bytes());
+            }
+            DnsRecord::Txt(strings) => {
+                for s in strings.iter() {
+                    data.push(s.len() as u8);
+                    data.extend_from_slice(s);
+                }
+            }
+            DnsRecord::Srv { priority, weight, port, target } => {
+                data.extend_from_slice(&priority.to_be_bytes());
+                data.extend_from_slice(&weight.to_be_bytes());
+                data.extend_from_slice(&port.to_be_bytes());
+                data.extend_from_slice(target.as_bytes());
+            }
+        }
+        out.extend_from_slice(&(self.record_type() as u16).to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+    /// The reverse of `encode()`. Returns the record and the number of bytes it took up.
+    pub(crate) fn decode(entry: &[u8]) -> Option<(DnsRecord, usize)> {
+        let rtype = u16::from_be_bytes(entry.get(0..2)?.try_into().unwrap());
+        let len = u16::from_be_bytes(entry.get(2..4)?.try_into().unwrap()) as usize;
+        let data = entry.get(4..4 + len)?;
+        let text = |bytes: &[u8]| std::str::from_utf8(bytes).ok().map(|s| s.to_string());
+        let record = match num_traits::FromPrimitive::from_u16(rtype)? {
+            DnsRecordType::A => DnsRecord::A(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?)),
+            DnsRecordType::Aaaa => DnsRecord::Aaaa(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?)),
+            DnsRecordType::Cname => DnsRecord::Cname(text(data)?),
+            DnsRecordType::Mx => DnsRecord::Mx {
+                preference: u16::from_be_bytes(data.get(0..2)?.try_into().unwrap()),
+                exchange: text(&data[2..])?,
+            },
+            DnsRecordType::Txt => {
+                let mut strings = Vec::new();
+                let mut rest = data;
+                while let Some((&slen, tail)) = rest.split_first() {
+                    strings.push(tail.get(..slen as usize)?.to_vec());
+                    rest = &tail[slen as usize..];
+                }
+                DnsRecord::Txt(strings)
+            }
+            DnsRecordType::Srv => DnsRecord::Srv {
+                priority: u16::from_be_bytes(data.get(0..2)?.try_into().unwrap()),
+                weight: u16::from_be_bytes(data.get(2..4)?.try_into().unwrap()),
+                port: u16::from_be_bytes(data.get(4..6)?.try_into().unwrap()),
+                target: text(&data[6..])?,
+            },
+        };
+        Some((record, 4 + len))
+    }
+}
+
+/// The page that a `RecordLookup` is lent in
+#[allow(dead_code)]
+#[repr(C, align(4096))]
+pub(crate) struct RecordPage {
+    pub(crate) data: [u8; 4096],
 }
 
 #[derive(
@@ -75,6 +204,74 @@ pub enum DnsResponseCode {
     NoServerSpecified = 8,
 }
 
+/// How queries travel to a `SecureResolver`
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum SecureTransport {
+    /// DNS over TLS (RFC 7858), on port 853
+    Tls,
+    /// DNS over HTTPS (RFC 8484), on port 443
+    Https,
+}
+
+/// What to do when the encrypted resolver can't be reached
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum FallbackPolicy {
+    /// fail the lookup, so that no name is ever sent in the clear
+    Strict,
+    /// ask the servers that DHCP handed out, over plain UDP
+    Plaintext,
+}
+
+/// A resolver that names are looked up through over an encrypted channel, instead of the servers
+/// that the network hands out. It's given by address, as its name can't be looked up without
+/// leaking that lookup; its certificate has to be valid for `server_name`, and issued by one of
+/// the CAs that the `tls` crate trusts. `.local` names always go to multicast DNS.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct SecureResolver {
+    pub transport: SecureTransport,
+    pub addr: NetIpAddr,
+    pub port: u16,
+    pub server_name: xous_ipc::String<DNS_NAME_LENGTH_LIMIT>,
+    /// the path of the DoH endpoint; not used for DNS over TLS
+    pub path: xous_ipc::String<DNS_NAME_LENGTH_LIMIT>,
+    pub fallback: FallbackPolicy,
+}
+impl SecureResolver {
+    /// A DNS over TLS resolver on the standard port, which doesn't fall back to plain DNS
+    pub fn tls(addr: IpAddr, server_name: &str) -> SecureResolver {
+        SecureResolver {
+            transport: SecureTransport::Tls,
+            addr: NetIpAddr::from(addr),
+            port: 853,
+            server_name: xous_ipc::String::from_str(server_name),
+            path: xous_ipc::String::new(),
+            fallback: FallbackPolicy::Strict,
+        }
+    }
+    /// A DNS over HTTPS resolver at `https://<server_name><path>`, which doesn't fall back to
+    /// plain DNS
+    pub fn https(addr: IpAddr, server_name: &str, path: &str) -> SecureResolver {
+        SecureResolver {
+            transport: SecureTransport::Https,
+            addr: NetIpAddr::from(addr),
+            port: 443,
+            server_name: xous_ipc::String::from_str(server_name),
+            path: xous_ipc::String::from_str(path),
+            fallback: FallbackPolicy::Strict,
+        }
+    }
+    /// Whether the settings could possibly work: a server name, a port, and for DoH a path
+    pub fn is_valid(&self) -> bool {
+        let name = self.server_name.as_str().unwrap_or("");
+        let path = self.path.as_str().unwrap_or("");
+        !name.is_empty()
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+            && self.port != 0
+            && (self.transport == SecureTransport::Tls
+                || (path.starts_with('/') && !path.contains(|c: char| c.is_whitespace() || c.is_control())))
+    }
+}
+
 #[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
 pub struct DnsResponse {
     pub addr: Option<NetIpAddr>,
@@ -1,6 +1,6 @@
 use net::NetIpAddr;
 use std::net::ToSocketAddrs;
-use crate::DnsResponseCode;
+use crate::{DnsRecord, DnsRecordType, DnsResponseCode, SecureResolver};
 
 #[derive(Debug)]
 pub struct Dns {
@@ -34,6 +34,17 @@ impl Dns {
             }
         }
     }
+    pub fn lookup_records(&self, name: &str, rtype: DnsRecordType) -> Result<Vec<DnsRecord>, DnsResponseCode> {
+        log::warn!("DNS record lookups ({} {:?}) not implemented in hosted mode!", name, rtype);
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn set_secure_resolver(&self, _resolver: Option<SecureResolver>) -> Result<(), DnsResponseCode> {
+        log::warn!("encrypted DNS not implemented in hosted mode!");
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn secure_resolver(&self) -> Result<Option<SecureResolver>, DnsResponseCode> {
+        Ok(None)
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         log::warn!("DNS cache flush not implemented in hosted mode!");
         Ok(())
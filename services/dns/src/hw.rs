@@ -39,6 +39,62 @@ impl Dns {
             }
         }
     }
+    /// Looks up the `rtype` records of `name`, following CNAMEs. An empty list means that the name
+    /// exists, but has no records of that type.
+    pub fn lookup_records(&self, name: &str, rtype: DnsRecordType) -> Result<Vec<DnsRecord>, DnsResponseCode> {
+        if name.len() == 0 || name.len() > DNS_NAME_LENGTH_LIMIT {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let mut page = RecordPage { data: [0; 4096] };
+        page.data[..name.len()].copy_from_slice(name.as_bytes());
+        let buf = unsafe {
+            xous::MemoryRange::new(
+                &mut page as *mut RecordPage as usize,
+                core::mem::size_of::<RecordPage>(),
+            )
+            .unwrap()
+        };
+        xous::send_message(
+            self.conn,
+            xous::Message::new_lend_mut(
+                Opcode::RecordLookup.to_usize().unwrap(),
+                buf,
+                xous::MemoryAddress::new(rtype as usize),
+                xous::MemorySize::new(name.len()),
+            ),
+        )
+        .or(Err(DnsResponseCode::UnknownError))?;
+        if page.data[0] != 0 {
+            return Err(num_traits::FromPrimitive::from_u8(page.data[1]).unwrap_or(DnsResponseCode::UnknownError));
+        }
+        let mut records = Vec::new();
+        let mut offset = 2;
+        for _ in 0..page.data[1] {
+            let (record, len) = DnsRecord::decode(&page.data[offset..]).ok_or(DnsResponseCode::FormatError)?;
+            records.push(record);
+            offset += len;
+        }
+        Ok(records)
+    }
+    /// Sends all lookups other than those of `.local` names through `resolver`, over an encrypted
+    /// channel, or goes back to plain DNS with `None`. The setting is saved in the PDDB, and stays
+    /// in effect across reboots. Fails with `FormatError` if the settings can't be right.
+    pub fn set_secure_resolver(&self, resolver: Option<SecureResolver>) -> Result<(), DnsResponseCode> {
+        let mut buf = Buffer::into_buf(resolver).or(Err(DnsResponseCode::UnknownError))?;
+        buf.lend_mut(self.conn, Opcode::SetSecureResolver.to_u32().unwrap())
+            .or(Err(DnsResponseCode::UnknownError))?;
+        match buf.to_original::<DnsResponseCode, _>().or(Err(DnsResponseCode::UnknownError))? {
+            DnsResponseCode::NoError => Ok(()),
+            e => Err(e),
+        }
+    }
+    /// The encrypted resolver in use, if any
+    pub fn secure_resolver(&self) -> Result<Option<SecureResolver>, DnsResponseCode> {
+        let mut buf = Buffer::into_buf(None::<SecureResolver>).or(Err(DnsResponseCode::UnknownError))?;
+        buf.lend_mut(self.conn, Opcode::GetSecureResolver.to_u32().unwrap())
+            .or(Err(DnsResponseCode::UnknownError))?;
+        buf.to_original::<Option<SecureResolver>, _>().or(Err(DnsResponseCode::UnknownError))
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         xous::send_message(
             self.conn,
@@ -3,6 +3,7 @@
 
 mod api;
 use api::*;
+mod secure;
 
 use net::NetIpAddr;
 use num_traits::*;
@@ -12,7 +13,7 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread;
 use xous_ipc::{Buffer, String};
 
@@ -22,29 +23,48 @@ use xous_ipc::{Buffer, String};
 // MOROS is MIT licensed.
 // See RFC 1035 for implementation details
 
-#[repr(u16)]
-enum QueryType {
-    A = 1,
-    // NS = 2,
-    // MD = 3,
-    // MF = 4,
-    // CNAME = 5,
-    // SOA = 6,
-    // MX = 15,
-    // TXT = 16,
-}
-
 #[repr(u16)]
 enum QueryClass {
     IN = 1,
 }
 
+/// SOA records only matter to us for how long to cache a negative answer
+const TYPE_SOA: u16 = 6;
+
+/// How many CNAMEs we follow before deciding that a chain is broken
+const MAX_CNAME_CHAIN: usize = 8;
+/// Negative answers are believed for no longer than this, whatever the SOA says (RFC 2308 section 5)
+const MAX_NEGATIVE_TTL: u32 = 3 * 3600;
+
 struct Message {
     pub datagram: Vec<u8>,
 }
 
 const FLAG_RD: u16 = 0x0100; // Recursion desired
 
+// Multicast DNS, for `.local` names; see RFC 6762
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+/// hosts on the link answer quickly, and don't answer at all for names that aren't theirs
+const MDNS_TIMEOUT_MS: u64 = 2_000;
+
+enum Rdata {
+    Record(DnsRecord),
+    Soa { minimum: u32 },
+    /// a type we don't use; it's only parsed so that it can be skipped over
+    Other,
+}
+
+/// A record from the answer or authority section of a response, with its names expanded
+struct ResourceRecord {
+    name: std::string::String,
+    rtype: u16,
+    ttl: u32,
+    /// whether it's from the authority section, rather than the answer section
+    authority: bool,
+    rdata: Rdata,
+}
+
 impl Message {
     pub fn from(datagram: &[u8]) -> Self {
         Self {
@@ -52,7 +72,7 @@ impl Message {
         }
     }
 
-    pub fn query(qname: &str, qtype: QueryType, qclass: QueryClass, id: u16) -> Self {
+    pub fn query(qname: &str, qtype: u16, qclass: QueryClass, id: u16) -> Self {
         let mut datagram = Vec::new();
 
         for b in id.to_be_bytes().iter() {
@@ -74,7 +94,7 @@ impl Message {
             }
         }
         datagram.push(0); // Root null label
-        for b in (qtype as u16).to_be_bytes().iter() {
+        for b in qtype.to_be_bytes().iter() {
             datagram.push(*b); // QTYPE
         }
         for b in (qclass as u16).to_be_bytes().iter() {
@@ -84,6 +104,12 @@ impl Message {
         Self { datagram }
     }
 
+    /// mDNS queries go out with the recursion desired bit cleared (RFC 6762 section 18.6)
+    pub fn without_recursion(mut self) -> Self {
+        self.datagram[2] &= !((FLAG_RD >> 8) as u8);
+        self
+    }
+
     pub fn id(&self) -> u16 {
         u16::from_be_bytes(self.datagram[0..2].try_into().unwrap())
     }
@@ -100,136 +126,139 @@ impl Message {
         }
     }
 
-    fn fast_foward_name(&self, start: usize) -> Result<usize, DnsResponseCode> {
+    fn u16_at(&self, index: usize) -> Result<u16, DnsResponseCode> {
+        let bytes = self.datagram.get(index..index + 2).ok_or(DnsResponseCode::FormatError)?;
+        Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32_at(&self, index: usize) -> Result<u32, DnsResponseCode> {
+        let bytes = self.datagram.get(index..index + 4).ok_or(DnsResponseCode::FormatError)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Reads the name at `start`, following compression pointers, in lower case and without the
+    /// trailing dot. Returns the name and the index just past it.
+    fn read_name(&self, start: usize) -> Result<(std::string::String, usize), DnsResponseCode> {
         use DnsResponseCode::FormatError;
+        let mut name = std::string::String::new();
         let mut index = start;
+        let mut end = None;
+        // every pointer has to go backwards, so a loop of them can't hang us
+        let mut limit = start;
         loop {
-            log::trace!("cname index: {}", index);
-            if *(self.datagram.get(index).ok_or(FormatError)?) == 0 {
-                index += 1;
-                break;
+            log::trace!("name index: {}", index);
+            let len = *(self.datagram.get(index).ok_or(FormatError)?) as usize;
+            if len >= 0xc0 {
+                let pointer = (self.u16_at(index)? & 0x3fff) as usize;
+                if pointer >= limit {
+                    return Err(FormatError);
+                }
+                end.get_or_insert(index + 2);
+                limit = pointer;
+                index = pointer;
+            } else if len > 63 {
+                return Err(FormatError);
+            } else if len == 0 {
+                return Ok((name, end.unwrap_or(index + 1)));
             } else {
-                index += *(self.datagram.get(index).ok_or(FormatError)?) as usize;
-                index += 1;
+                let label = self.datagram.get(index + 1..index + 1 + len).ok_or(FormatError)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&std::string::String::from_utf8_lossy(label).to_ascii_lowercase());
+                index += 1 + len;
             }
         }
-        Ok(index)
     }
 
-    pub fn parse_response(&self) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
+    /// All the records in the answer and authority sections. The additional section isn't
+    /// looked at: nothing in it can be trusted any more than the answers we asked for.
+    fn records(&self) -> Result<Vec<ResourceRecord>, DnsResponseCode> {
         use DnsResponseCode::FormatError;
         log::trace!("parsing packet: {:?}", self.datagram);
 
-        let mut map = HashMap::<IpAddr, u32>::new();
         // ASSUME: the query ID and response bit fields have already been checked
-        // and that the rcode is valid
-        let qdcount = u16::from_be_bytes(self.datagram[4..6].try_into().unwrap());
-        let ancount = u16::from_be_bytes(self.datagram[6..8].try_into().unwrap());
+        let qdcount = self.u16_at(4)?;
+        let ancount = self.u16_at(6)? as usize;
+        let nscount = self.u16_at(8)? as usize;
 
         let mut index = 12;
-        // fast forward past the qname
+        // fast forward past the questions
         for queries in 0..qdcount {
             log::trace!("parsing query{}, index {}", queries, index);
-            index = self.fast_foward_name(index)?;
-            log::trace!("fast forward through qname to {}", index);
-            // index is now at qtype
-            let qtype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if qtype != 1 && qtype != 28 {
-                log::error!("Problem parsing qname, qtype is not 1 or 28: {}", qtype);
+            let (_, next) = self.read_name(index)?;
+            // QTYPE and QCLASS
+            index = next + 4;
+        }
+        let mut records = Vec::new();
+        for n in 0..ancount + nscount {
+            log::trace!("parsing record{}, index {}", n, index);
+            let (name, next) = self.read_name(index)?;
+            let rtype = self.u16_at(next)?;
+            // mDNS uses the top bit of the class as a flag
+            let class = self.u16_at(next + 2)? & 0x7fff;
+            let ttl = self.u32_at(next + 4)?;
+            let rdlength = self.u16_at(next + 8)? as usize;
+            let start = next + 10;
+            if start + rdlength > self.datagram.len() {
                 return Err(FormatError);
             }
-            index += 2;
-            let qclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if qclass != 1 {
-                log::error!("Problem parsing qname, qclass is not 1: {}", qclass);
-                return Err(FormatError);
+            index = start + rdlength;
+            if class != QueryClass::IN as u16 {
+                continue;
             }
-            index += 2;
-        }
-        // index is now at the aname section
-        for aname in 0..ancount {
-            log::trace!("parsing aname{}, index {}", aname, index);
-            // first check to see if we're dealing with a pointer or a name
-            if self.datagram[index] >= 0xc0 {
-                // pointer
-                index += 1;
-                if self.datagram[index] != 0xc {
-                    log::error!(
-                        "Found aname pointer, but value does not conform to length of aname header"
-                    );
-                    return Err(FormatError);
-                }
-                index += 1;
-            } else {
-                // name, fast forward past the name
-                index = self.fast_foward_name(index)?;
-                log::trace!("fast forward aname to {}", index);
-            }
-            // index is now at type
-            let atype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if atype != 1 && atype != 28 {
-                log::error!("Problem parsing aname, type is not 1 or 28: {}", atype);
-                return Err(FormatError);
+            records.push(ResourceRecord {
+                name,
+                rtype,
+                ttl,
+                authority: n >= ancount,
+                rdata: self.parse_rdata(rtype, start, rdlength)?,
+            });
+        }
+        Ok(records)
+    }
+
+    fn parse_rdata(&self, rtype: u16, start: usize, len: usize) -> Result<Rdata, DnsResponseCode> {
+        use DnsResponseCode::FormatError;
+        let data = &self.datagram[start..start + len];
+        let record = match FromPrimitive::from_u16(rtype) {
+            Some(DnsRecordType::A) => {
+                let octets: [u8; 4] = data.try_into().map_err(|_| FormatError)?;
+                DnsRecord::A(Ipv4Addr::from(octets))
             }
-            index += 2;
-            let aclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if aclass != 1 {
-                log::error!("Problem parsing aname, aclass is not 1: {}", aclass);
-                return Err(FormatError);
+            Some(DnsRecordType::Aaaa) => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| FormatError)?;
+                DnsRecord::Aaaa(Ipv6Addr::from(octets))
             }
-            index += 2;
-            // this is our TTL
-            let ttl = u32::from_be_bytes(self.datagram[index..index + 4].try_into().unwrap());
-            log::trace!("got ttl: {}", ttl);
-            index += 4;
-            // this is the payload length
-            let addr_len = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            index += 2;
-            match addr_len {
-                // ipv4
-                4 => {
-                    if atype != 1 {
-                        log::error!("Got a 4-byte address, but ATYPE != A (1)");
-                        return Err(FormatError);
-                    }
-                    // this copy happens because I can't figure out how to get Ipv4Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 4] = [0; 4];
-                    for (&src, dst) in self.datagram[index..index + 4].iter().zip(rdata.iter_mut())
-                    {
-                        *dst = src;
-                    }
-                    let addr = IpAddr::V4(Ipv4Addr::from(rdata));
-                    index += 4;
-                    map.insert(addr, ttl);
-                }
-                // ipv6
-                16 => {
-                    if atype != 28 {
-                        log::error!("Got a 16-byte address, but ATYPE != AAAA (28)");
-                        return Err(FormatError);
-                    }
-                    // this copy happens because I can't figure out how to get Ipv6Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 16] = [0; 16];
-                    for (&src, dst) in self.datagram[index..index + 16]
-                        .iter()
-                        .zip(rdata.iter_mut())
-                    {
-                        *dst = src;
-                    }
-                    let addr = IpAddr::V6(Ipv6Addr::from(rdata));
-                    index += 16;
-                    map.insert(addr, ttl);
-                }
-                _ => {
-                    log::error!("Length field does not match a known record type");
-                    return Err(FormatError);
+            Some(DnsRecordType::Cname) => DnsRecord::Cname(self.read_name(start)?.0),
+            Some(DnsRecordType::Mx) => DnsRecord::Mx {
+                preference: self.u16_at(start)?,
+                exchange: self.read_name(start + 2)?.0,
+            },
+            Some(DnsRecordType::Txt) => {
+                let mut strings = Vec::new();
+                let mut rest = data;
+                while let Some((&slen, tail)) = rest.split_first() {
+                    strings.push(tail.get(..slen as usize).ok_or(FormatError)?.to_vec());
+                    rest = &tail[slen as usize..];
                 }
+                DnsRecord::Txt(strings)
             }
-        }
-
-        Ok(map)
+            Some(DnsRecordType::Srv) => DnsRecord::Srv {
+                priority: self.u16_at(start)?,
+                weight: self.u16_at(start + 2)?,
+                port: self.u16_at(start + 4)?,
+                target: self.read_name(start + 6)?.0,
+            },
+            None if rtype == TYPE_SOA => {
+                // MNAME and RNAME, then SERIAL, REFRESH, RETRY, EXPIRE and MINIMUM
+                let (_, next) = self.read_name(start)?;
+                let (_, next) = self.read_name(next)?;
+                return Ok(Rdata::Soa { minimum: self.u32_at(next + 16)? });
+            }
+            None => return Ok(Rdata::Other),
+        };
+        Ok(Rdata::Record(record))
     }
 
     /*
@@ -292,7 +321,8 @@ impl Message {
     */
 
     pub fn rcode(&self) -> DnsResponseCode {
-        match (self.header() >> 11) & 0xF {
+        // RCODE is the bottom four bits of the flags
+        match self.header() & 0xF {
             0 => DnsResponseCode::NoError,
             1 => DnsResponseCode::FormatError,
             2 => DnsResponseCode::ServerFailure,
@@ -304,6 +334,11 @@ impl Message {
     }
 }
 
+/// How long to wait for a server to answer before trying the next one
+const DNS_TIMEOUT_MS: u64 = 3_000;
+/// How many times to go around the list of servers before giving up
+const DNS_ATTEMPTS: usize = 2;
+
 pub struct Resolver {
     /// DnsServerManager is a service of the Net crate that automatically updates the DNS server list
     mgr: net::DnsServerManager,
@@ -311,6 +346,11 @@ pub struct Resolver {
     buf: [u8; DNS_PKT_MAX_LEN],
     trng: trng::Trng,
     freeze: bool,
+    /// Names that don't exist (`None`), or that have no records of a type, with the seconds left
+    /// until we ask again. See RFC 2308.
+    negative_cache: HashMap<(std::string::String, Option<u16>), u32>,
+    /// the encrypted resolver, when one is set
+    secure: secure::SecureChannel,
 }
 impl Resolver {
     pub fn new(xns: &xous_names::XousNames) -> Resolver {
@@ -320,7 +360,7 @@ impl Resolver {
             format!("0.0.0.0:{}", local_port),
         )
         .expect("couldn't create socket for DNS resolver");
-        let timeout = Duration::from_millis(10_000); // 10 seconds for DNS to resolve by default
+        let timeout = Duration::from_millis(DNS_TIMEOUT_MS); // each exchange sets its own timeout, but start somewhere sane
         socket.set_read_timeout(Some(timeout)).unwrap();
         socket.set_nonblocking(false).unwrap(); // we want this to block.
                                                 // we /could/ do a non-blocking DNS resolver, but...what would you do in the meantime??
@@ -333,8 +373,20 @@ impl Resolver {
             buf: [0; DNS_PKT_MAX_LEN],
             trng,
             freeze: false,
+            negative_cache: HashMap::new(),
+            secure: secure::SecureChannel::new(),
         }
     }
+    /// Picks up the encrypted resolver setting; called once the PDDB is mounted
+    pub fn load_secure_resolver(&mut self) {
+        self.secure.load();
+    }
+    pub fn secure_resolver(&self) -> Option<SecureResolver> {
+        self.secure.config()
+    }
+    pub fn set_secure_resolver(&mut self, config: Option<SecureResolver>) -> std::io::Result<()> {
+        self.secure.store(config)
+    }
     pub fn add_server(&mut self, addr: IpAddr) {
         self.mgr.add_server(addr);
     }
@@ -355,43 +407,237 @@ impl Resolver {
     pub fn trng_u32(&self) -> u32 {
         self.trng.get_u32().unwrap()
     }
+    /// Ages the negative cache by `secs`, dropping what has run out
+    pub fn expire(&mut self, secs: u32) {
+        self.negative_cache.retain(|name, ttl| {
+            if *ttl <= secs {
+                log::debug!("DNS negative cache expiring {:?}", name);
+                false
+            } else {
+                *ttl -= secs;
+                true
+            }
+        });
+    }
+    pub fn flush(&mut self) {
+        self.negative_cache.clear();
+    }
+    /// The addresses of `name`, with their TTLs
     pub fn resolve(&mut self, name: &str) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
-        if let Some(dns_address) = self.mgr.get_random() {
-            let dns_port = 53;
-            let server = SocketAddr::new(dns_address, dns_port);
-
-            let qname = name;
-            let qtype = QueryType::A;
-            let qclass = QueryClass::IN;
-            let query = Message::query(qname, qtype, qclass, self.trng.get_u32().unwrap() as u16);
+        let mut map = HashMap::<IpAddr, u32>::new();
+        for (record, ttl) in self.lookup(name, DnsRecordType::A as u16)? {
+            match record {
+                DnsRecord::A(addr) => map.insert(IpAddr::V4(addr), ttl),
+                DnsRecord::Aaaa(addr) => map.insert(IpAddr::V6(addr), ttl),
+                _ => None,
+            };
+        }
+        Ok(map)
+    }
+    /// The `qtype` records of `name`, with their TTLs, following CNAMEs. An empty list means that
+    /// the name exists, but has no records of that type.
+    pub fn lookup(&mut self, name: &str, qtype: u16) -> Result<Vec<(DnsRecord, u32)>, DnsResponseCode> {
+        let mut name = name.trim_end_matches('.').to_ascii_lowercase();
+        // what we hand back is good for no longer than the CNAMEs that led to it
+        let mut chain_ttl = u32::MAX;
+        let mut hops = 0;
+        loop {
+            if self.negative_cache.contains_key(&(name.clone(), None)) {
+                log::debug!("DNS negative cache: {} doesn't exist", name);
+                return Err(DnsResponseCode::NameError);
+            }
+            if self.negative_cache.contains_key(&(name.clone(), Some(qtype))) {
+                log::debug!("DNS negative cache: {} has no type {} records", name, qtype);
+                return Ok(Vec::new());
+            }
+            let message = self.ask(&name, qtype)?;
+            let records = message.records()?;
 
-            self.socket
-                .send_to(&query.datagram, &server)
-                .map_err(|_| DnsResponseCode::NetworkError)?;
+            // follow the chain as far as this response takes it
+            let asked = name.clone();
+            loop {
+                let found: Vec<(DnsRecord, u32)> = records
+                    .iter()
+                    .filter(|r| !r.authority && r.rtype == qtype && r.name == name)
+                    .filter_map(|r| match &r.rdata {
+                        Rdata::Record(record) => Some((record.clone(), r.ttl.min(chain_ttl))),
+                        _ => None,
+                    })
+                    .collect();
+                if !found.is_empty() {
+                    return Ok(found);
+                }
+                let cname = records.iter().find_map(|r| match &r.rdata {
+                    Rdata::Record(DnsRecord::Cname(target)) if !r.authority && r.name == name => {
+                        Some((target.clone(), r.ttl))
+                    }
+                    _ => None,
+                });
+                match cname {
+                    Some((target, ttl)) => {
+                        hops += 1;
+                        if hops > MAX_CNAME_CHAIN {
+                            log::warn!("CNAME chain from {} is too long", asked);
+                            return Err(DnsResponseCode::ServerFailure);
+                        }
+                        log::debug!("DNS {} is an alias for {}", name, target);
+                        chain_ttl = chain_ttl.min(ttl);
+                        name = target;
+                    }
+                    None => break,
+                }
+            }
+            if let DnsResponseCode::NameError = message.rcode() {
+                // the name at the end of the chain doesn't exist
+                self.cache_negative(name, None, &records);
+                return Err(DnsResponseCode::NameError);
+            }
+            if name == asked {
+                // the name exists, but has no records of the type
+                self.cache_negative(name, Some(qtype), &records);
+                return Ok(Vec::new());
+            }
+            // otherwise the chain leads out of this response, so ask about where it went
+        }
+    }
+    /// Remembers a negative answer for as long as the SOA that came with it says, which is the
+    /// smaller of its TTL and its MINIMUM field. Without a SOA, it isn't cached (RFC 2308 section 5).
+    fn cache_negative(&mut self, name: std::string::String, qtype: Option<u16>, records: &[ResourceRecord]) {
+        let ttl = records.iter().filter(|r| r.authority).find_map(|r| match r.rdata {
+            Rdata::Soa { minimum } => Some(r.ttl.min(minimum).min(MAX_NEGATIVE_TTL)),
+            _ => None,
+        });
+        if let Some(ttl) = ttl {
+            if ttl > 0 {
+                self.negative_cache.insert((name, qtype), ttl);
+            }
+        }
+    }
+    /// Asks the configured servers about `name`, going around them until one of them gives an answer
+    /// that counts: the records, or that the name doesn't exist.
+    fn ask(&mut self, name: &str, qtype: u16) -> Result<Message, DnsResponseCode> {
+        if is_mdns_name(name) {
+            return self.ask_mdns(name, qtype);
+        }
+        if let Some(config) = self.secure.config() {
+            match self.ask_secure(name, qtype, config.transport) {
+                Ok(message) => return Ok(message),
+                Err(e) => match config.fallback {
+                    FallbackPolicy::Strict => {
+                        log::warn!("encrypted DNS lookup of {} failed: {:?}", name, e);
+                        return Err(e);
+                    }
+                    FallbackPolicy::Plaintext => {
+                        log::warn!("encrypted DNS lookup of {} failed: {:?}; asking in the clear", name, e)
+                    }
+                },
+            }
+        }
+        let servers = self.mgr.get_all();
+        if servers.is_empty() {
+            return Err(DnsResponseCode::NoServerSpecified);
+        }
+        let mut error = DnsResponseCode::NetworkError;
+        for _ in 0..DNS_ATTEMPTS {
+            for &server in servers.iter() {
+                let query = Message::query(name, qtype, QueryClass::IN, self.trng.get_u32().unwrap() as u16);
+                match self.exchange(&query, SocketAddr::new(server, 53), Some(server), DNS_TIMEOUT_MS) {
+                    Ok(message) => match message.rcode() {
+                        DnsResponseCode::NoError | DnsResponseCode::NameError => return Ok(message),
+                        // this server can't or won't answer; another one might
+                        rcode => {
+                            log::debug!("DNS server {:?} said {:?} for {}", server, rcode, name);
+                            error = rcode;
+                        }
+                    },
+                    Err(e) => {
+                        log::debug!("DNS server {:?} didn't answer for {}: {:?}", server, name, e);
+                        error = e;
+                    }
+                }
+            }
+        }
+        Err(error)
+    }
+    /// Asks the encrypted resolver about `name`
+    fn ask_secure(&mut self, name: &str, qtype: u16, transport: SecureTransport) -> Result<Message, DnsResponseCode> {
+        // DoH queries go out with an ID of 0, so that HTTP caches can tell that they're the same
+        // (RFC 8484 section 4.1); the ID is no defence against forgery inside TLS anyway
+        let id = match transport {
+            SecureTransport::Tls => self.trng.get_u32().unwrap() as u16,
+            SecureTransport::Https => 0,
+        };
+        let query = Message::query(name, qtype, QueryClass::IN, id);
+        let response = self.secure.exchange(&query.datagram).map_err(|e| {
+            log::debug!("encrypted DNS exchange failed: {:?}", e);
+            DnsResponseCode::NetworkError
+        })?;
+        let message = Message::from(&response);
+        if message.datagram.len() < 12 || message.id() != query.id() || !message.is_response() {
+            return Err(DnsResponseCode::FormatError);
+        }
+        match message.rcode() {
+            DnsResponseCode::NoError | DnsResponseCode::NameError => Ok(message),
+            rcode => Err(rcode),
+        }
+    }
+    /// Asks the link for a `.local` name, with a one-shot multicast DNS query (RFC 6762 section 5.1).
+    /// The query goes out from our ordinary port, so responders answer us directly, the same way a
+    /// unicast server would. Nobody answering means nobody has the name.
+    fn ask_mdns(&mut self, name: &str, qtype: u16) -> Result<Message, DnsResponseCode> {
+        let query = Message::query(name, qtype, QueryClass::IN, self.trng.get_u32().unwrap() as u16)
+            .without_recursion();
+        match self.exchange(&query, SocketAddr::new(IpAddr::V4(MDNS_GROUP), MDNS_PORT), None, MDNS_TIMEOUT_MS) {
+            Err(DnsResponseCode::NetworkError) => Err(DnsResponseCode::NameError),
+            result => result,
+        }
+    }
+    /// Sends `query` to `dest`, and waits up to `timeout_ms` for the response to it. Anything with the
+    /// wrong ID, or that comes from somewhere other than `from` when that's given, is skipped:
+    /// late responses to earlier queries can still be trickling in.
+    fn exchange(
+        &mut self,
+        query: &Message,
+        dest: SocketAddr,
+        from: Option<IpAddr>,
+        timeout_ms: u64,
+    ) -> Result<Message, DnsResponseCode> {
+        self.socket
+            .send_to(&query.datagram, &dest)
+            .map_err(|_| DnsResponseCode::NetworkError)?;
 
-            match self.socket.recv(&mut self.buf) {
-                Ok(len) => {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0 {
+                return Err(DnsResponseCode::NetworkError);
+            }
+            self.socket.set_read_timeout(Some(remaining)).unwrap();
+            match self.socket.recv_from(&mut self.buf) {
+                Ok((len, src)) => {
+                    if len < 12 || from.map(|addr| addr != src.ip()).unwrap_or(false) {
+                        continue;
+                    }
                     let message = Message::from(&self.buf[..len]);
                     if message.id() == query.id() && message.is_response() {
-                        return match message.rcode() {
-                            DnsResponseCode::NoError => message.parse_response(),
-                            rcode => Err(rcode),
-                        };
-                    } else {
-                        Err(DnsResponseCode::NetworkError)
+                        return Ok(message);
                     }
                 }
                 Err(e) => match e.kind() {
-                    ErrorKind::WouldBlock => Err(DnsResponseCode::NetworkError),
-                    _ => Err(DnsResponseCode::UnknownError),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => return Err(DnsResponseCode::NetworkError),
+                    _ => return Err(DnsResponseCode::UnknownError),
                 },
             }
-        } else {
-            Err(DnsResponseCode::NoServerSpecified)
         }
     }
 }
 
+/// Names under `.local` belong to multicast DNS (RFC 6762 section 3)
+fn is_mdns_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    name.len() > ".local".len() && name.to_ascii_lowercase().ends_with(".local")
+}
+
 #[derive(PartialEq, Debug)]
 #[repr(C)]
 enum NameConversionError {
@@ -468,6 +714,29 @@ fn fill_response(mut env: xous::MessageEnvelope, entries: &HashMap<IpAddr, u32>)
     None
 }
 
+fn fill_records(mut env: xous::MessageEnvelope, records: &[(DnsRecord, u32)]) -> Option<()> {
+    let mem = env.body.memory_message_mut()?;
+    let s: &mut [u8] = mem.buf.as_slice_mut();
+
+    // as many records as fit, up to 255 of them
+    let mut entries = Vec::new();
+    let mut count = 0u8;
+    for (record, _ttl) in records.iter() {
+        let mut entry = Vec::new();
+        record.encode(&mut entry);
+        if 2 + entries.len() + entry.len() > s.len() || count == u8::MAX {
+            log::warn!("{} records don't fit in a lookup response, returning {}", records.len(), count);
+            break;
+        }
+        entries.extend_from_slice(&entry);
+        count += 1;
+    }
+    s[0] = 0;
+    s[1] = count;
+    s[2..2 + entries.len()].copy_from_slice(&entries);
+    None
+}
+
 fn fill_error(mut env: xous::MessageEnvelope, code: DnsResponseCode) -> Option<()> {
     let mem = env.body.memory_message_mut()?;
 
@@ -520,6 +789,20 @@ fn main() -> ! {
         }
     });
 
+    // the encrypted resolver setting is in the PDDB, so it can only be read once that's mounted
+    thread::spawn({
+        let local_cid = xous::connect(dns_sid).unwrap();
+        move || {
+            let pddb = pddb::Pddb::new();
+            pddb.is_mounted_blocking();
+            xous::send_message(
+                local_cid,
+                xous::Message::new_scalar(Opcode::PddbMounted.to_usize().unwrap(), 0, 0, 0, 0),
+            )
+            .expect("couldn't tell DNS that the PDDB is mounted");
+        }
+    });
+
     log::trace!("ready to accept requests");
     loop {
         let mut msg = xous::receive_message(dns_sid).unwrap();
@@ -538,7 +821,10 @@ fn main() -> ! {
                         match resolver.resolve(&owned_name) {
                             Ok(cache_entry) => {
                                 fill_response(msg, &cache_entry);
-                                dns_cache.insert(owned_name, cache_entry);
+                                // an empty entry would never expire, and would trip up `Lookup`
+                                if cache_entry.len() > 0 {
+                                    dns_cache.insert(owned_name, cache_entry);
+                                }
                                 continue;
                             }
                             Err(e) => {
@@ -554,6 +840,30 @@ fn main() -> ! {
                     }
                 };
             }
+            Some(Opcode::RecordLookup) => {
+                let rtype = msg
+                    .body
+                    .memory_message()
+                    .and_then(|m| m.offset)
+                    .and_then(|t| FromPrimitive::from_usize(t.get()));
+                match (name_from_msg(&msg).map(|s| s.to_owned()), rtype) {
+                    (Ok(name), Some(rtype)) => {
+                        let rtype: DnsRecordType = rtype;
+                        log::trace!("looking up {:?} records of {}", rtype, name);
+                        match resolver.lookup(&name, rtype as u16) {
+                            Ok(records) => fill_records(msg, &records),
+                            Err(e) => fill_error(msg, e),
+                        };
+                    }
+                    (Err(e), _) => {
+                        log::error!("unable to do record lookup: {:?}", e);
+                        fill_error(msg, DnsResponseCode::NameError);
+                    }
+                    (_, None) => {
+                        fill_error(msg, DnsResponseCode::NotImplemented);
+                    }
+                }
+            }
             Some(Opcode::Lookup) => {
                 let mut buf = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
@@ -655,10 +965,47 @@ fn main() -> ! {
                         log::debug!("DNS cache removing {}", &name);
                         dns_cache.remove(&name);
                     }
+                    resolver.expire(increment);
                 }
             }),
             Some(Opcode::Flush) => {
                 dns_cache.clear();
+                resolver.flush();
+            }
+            Some(Opcode::PddbMounted) => {
+                resolver.load_secure_resolver();
+                // anything cached so far came over plain DNS
+                if resolver.secure_resolver().is_some() {
+                    dns_cache.clear();
+                    resolver.flush();
+                }
+            }
+            Some(Opcode::SetSecureResolver) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let config = buf.to_original::<Option<SecureResolver>, _>().unwrap();
+                let code = if config.map(|c| c.is_valid()).unwrap_or(true) {
+                    // answers from the old resolver are only as trustworthy as it was
+                    dns_cache.clear();
+                    resolver.flush();
+                    match resolver.set_secure_resolver(config) {
+                        Ok(_) => DnsResponseCode::NoError,
+                        Err(e) => {
+                            log::error!("couldn't save the encrypted DNS setting: {:?}", e);
+                            DnsResponseCode::UnknownError
+                        }
+                    }
+                } else {
+                    DnsResponseCode::FormatError
+                };
+                buf.replace(code).unwrap();
+            }
+            Some(Opcode::GetSecureResolver) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                buf.replace(resolver.secure_resolver()).unwrap();
             }
             Some(Opcode::FreezeConfig) => {
                 resolver.set_freeze_config(true);
@@ -682,3 +1029,87 @@ fn main() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// www.example.com CNAME example.com, example.com A 93.184.216.34, with compressed names, and
+    /// a broken record in the authority section
+    const CNAME_RESPONSE: [u8; 76] = [
+        0x12, 0x34, 0x81, 0x80, 0, 1, 0, 2, 0, 1, 0, 0,
+        3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+        0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 16,
+        0xc0, 16, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 93, 184, 216, 34,
+        0xc0, 63, 0, 16, 0, 1, 0, 0, 0, 1, 0, 1, 0,
+    ];
+
+    #[test]
+    fn parses_compressed_records() {
+        // the last record (a TXT in the authority section, whose name points at itself) is invalid
+        let message = Message::from(&CNAME_RESPONSE);
+        assert!(message.records().is_err());
+
+        let mut datagram = CNAME_RESPONSE.to_vec();
+        datagram.truncate(76 - 13);
+        datagram[9] = 0;
+        let message = Message::from(&datagram);
+        let records = message.records().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "www.example.com");
+        match &records[0].rdata {
+            Rdata::Record(DnsRecord::Cname(target)) => assert_eq!(target, "example.com"),
+            _ => panic!("expected a CNAME"),
+        }
+        assert_eq!(records[1].name, "example.com");
+        assert_eq!(records[1].ttl, 3600);
+        match &records[1].rdata {
+            Rdata::Record(DnsRecord::A(addr)) => assert_eq!(*addr, Ipv4Addr::new(93, 184, 216, 34)),
+            _ => panic!("expected an A record"),
+        }
+    }
+
+    #[test]
+    fn reads_the_soa_minimum() {
+        let mut datagram = vec![0, 1, 0x81, 0x83, 0, 1, 0, 0, 0, 1, 0, 0];
+        datagram.extend_from_slice(&[2, b'n', b'x', 0, 0, 1, 0, 1]);
+        // ". SOA a. b. 1 2 3 4 300", with a TTL of 900
+        datagram.extend_from_slice(&[0, 0, 6, 0, 1, 0, 0, 0x03, 0x84, 0, 26]);
+        datagram.extend_from_slice(&[1, b'a', 0, 1, b'b', 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 1, 44]);
+        let message = Message::from(&datagram);
+        assert!(matches!(message.rcode(), DnsResponseCode::NameError));
+        let records = message.records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].authority);
+        assert!(matches!(records[0].rdata, Rdata::Soa { minimum: 300 }));
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = [
+            DnsRecord::Srv { priority: 1, weight: 2, port: 5222, target: "xmpp.example.com".to_string() },
+            DnsRecord::Mx { preference: 10, exchange: "mail.example.com".to_string() },
+            DnsRecord::Txt(vec![b"v=spf1".to_vec(), vec![]]),
+            DnsRecord::Aaaa(Ipv6Addr::LOCALHOST),
+        ];
+        let mut encoded = Vec::new();
+        for record in records.iter() {
+            record.encode(&mut encoded);
+        }
+        let mut offset = 0;
+        for record in records.iter() {
+            let (decoded, len) = DnsRecord::decode(&encoded[offset..]).unwrap();
+            assert_eq!(&decoded, record);
+            offset += len;
+        }
+        assert_eq!(offset, encoded.len());
+    }
+
+    #[test]
+    fn local_names_go_to_mdns() {
+        assert!(is_mdns_name("precursor.local"));
+        assert!(is_mdns_name("Precursor.LOCAL."));
+        assert!(!is_mdns_name("local"));
+        assert!(!is_mdns_name("precursor.locale"));
+    }
+}
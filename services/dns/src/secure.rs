@@ -0,0 +1,270 @@
+//! DNS over TLS (RFC 7858) and DNS over HTTPS (RFC 8484), so that whoever runs the network we're
+//! on can neither read nor forge our lookups.
+//!
+//! The resolver is reached by its address: looking its name up would mean asking ourselves, in the
+//! clear. The setting is kept in the system basis of the PDDB, so it only comes into effect once
+//! the PDDB is mounted; lookups before then go out over plain DNS. A DoT connection is kept open
+//! between queries, and made again when the server has closed it. DoH requests each use a
+//! connection of their own, as that's what the HTTP client does, which makes DoT the cheaper of
+//! the two.
+
+use crate::api::*;
+use net::NetIpAddr;
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+const SECURE_DICT: &str = "sys.dns";
+const SECURE_KEY: &str = "secure_resolver";
+/// Responses over TCP aren't held to the 512 bytes of UDP, but nothing we ask for needs more than this
+const SECURE_MAX_LEN: usize = 4096;
+/// Longer than a UDP exchange is allowed, as it can include a TCP and a TLS handshake
+const SECURE_TIMEOUT_MS: u64 = 6_000;
+
+pub(crate) struct SecureChannel {
+    config: Option<SecureResolver>,
+    /// made when the PDDB is mounted; until then there is no setting to load, nor CAs to check
+    /// certificates against
+    pddb: Option<pddb::Pddb>,
+    tls: Option<tls::Tls>,
+    /// the open DoT connection, if any
+    stream: Option<tls::TlsStream>,
+    /// the DoH client, which knows the address of the resolver
+    http: Option<http_client::Client>,
+}
+impl SecureChannel {
+    pub fn new() -> SecureChannel {
+        SecureChannel { config: None, pddb: None, tls: None, stream: None, http: None }
+    }
+    pub fn config(&self) -> Option<SecureResolver> {
+        self.config
+    }
+    /// Reads the setting from the PDDB. Called once it's mounted.
+    pub fn load(&mut self) {
+        let pddb = self.pddb.get_or_insert_with(pddb::Pddb::new);
+        let mut text = std::string::String::new();
+        let config = match pddb.get(
+            SECURE_DICT, SECURE_KEY, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS),
+            false, false, None, None::<fn()>
+        ) {
+            Ok(mut key) => match key.read_to_string(&mut text) {
+                Ok(_) => from_config(&text),
+                Err(e) => {
+                    log::error!("couldn't read the encrypted DNS setting: {:?}", e);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        match config {
+            Some(config) => log::info!("encrypted DNS through {:?} over {:?}", config.addr, config.transport),
+            None if !text.is_empty() => log::error!("ignoring a malformed encrypted DNS setting"),
+            None => {}
+        }
+        self.apply(config);
+    }
+    /// Changes the resolver, and saves the change to the PDDB. The new setting is in effect even if
+    /// it couldn't be saved.
+    pub fn store(&mut self, config: Option<SecureResolver>) -> Result<()> {
+        self.apply(config);
+        let pddb = self.pddb.as_ref().ok_or(Error::new(ErrorKind::NotConnected, "the PDDB isn't mounted"))?;
+        match pddb.delete_key(SECURE_DICT, SECURE_KEY, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        if let Some(config) = config {
+            let text = to_config(&config);
+            let mut key = pddb.get(
+                SECURE_DICT, SECURE_KEY, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS),
+                true, true, Some(text.len()), None::<fn()>
+            )?;
+            key.write_all(text.as_bytes())?;
+        }
+        pddb.sync()
+    }
+    fn apply(&mut self, config: Option<SecureResolver>) {
+        self.config = config;
+        // whatever was connected to the old resolver is no use with the new one
+        self.stream = None;
+        self.http = None;
+    }
+
+    /// Sends `query` to the resolver, and returns its response
+    pub fn exchange(&mut self, query: &[u8]) -> Result<Vec<u8>> {
+        let config = self.config.ok_or(Error::new(ErrorKind::NotFound, "no encrypted resolver is set"))?;
+        if self.pddb.is_none() {
+            // can't happen as things stand, since the setting comes out of the PDDB
+            return Err(Error::new(ErrorKind::NotConnected, "the PDDB isn't mounted"));
+        }
+        match config.transport {
+            SecureTransport::Tls => self.exchange_tls(&config, query),
+            SecureTransport::Https => self.exchange_https(&config, query),
+        }
+    }
+
+    fn exchange_tls(&mut self, config: &SecureResolver, query: &[u8]) -> Result<Vec<u8>> {
+        loop {
+            // a connection that the server has since closed only shows up as such when it's used
+            let reused = self.stream.is_some();
+            if self.stream.is_none() {
+                let addr = SocketAddr::new(IpAddr::from(config.addr), config.port);
+                let stream = self.tls.get_or_insert_with(tls::Tls::new)
+                    .connect_to(addr, config.server_name.as_str().unwrap_or(""))?;
+                let timeout = Some(Duration::from_millis(SECURE_TIMEOUT_MS));
+                stream.sock.set_read_timeout(timeout)?;
+                stream.sock.set_write_timeout(timeout)?;
+                self.stream = Some(stream);
+            }
+            match framed_exchange(self.stream.as_mut().unwrap(), query) {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    self.stream = None;
+                    if !reused {
+                        return Err(e);
+                    }
+                    log::debug!("DoT connection went away ({:?}), reconnecting", e);
+                }
+            }
+        }
+    }
+
+    fn exchange_https(&mut self, config: &SecureResolver, query: &[u8]) -> Result<Vec<u8>> {
+        let server_name = config.server_name.as_str().unwrap_or("");
+        let client = self.http.get_or_insert_with(|| {
+            let mut client = http_client::Client::new();
+            client.resolve_to(server_name, IpAddr::from(config.addr));
+            client.timeout = Some(Duration::from_millis(SECURE_TIMEOUT_MS));
+            // a redirect could be to anywhere, and we'd have to look that up in the clear
+            client.max_redirects = 0;
+            client
+        });
+        let url = format!("https://{}:{}{}", server_name, config.port, config.path.as_str().unwrap_or(""));
+        let mut response = client.post(&url, "application/dns-message", query)
+            .header("Accept", "application/dns-message")
+            .send()?;
+        if response.status != 200 {
+            return Err(Error::new(ErrorKind::Other, format!("DoH server said {} {}", response.status, response.reason)));
+        }
+        response.bytes(SECURE_MAX_LEN)
+    }
+}
+
+/// One query and its response over a DoT connection; each message goes with its length in front of
+/// it, as DNS over TCP does (RFC 1035 section 4.2.2).
+fn framed_exchange<S: Read + Write>(stream: &mut S, query: &[u8]) -> Result<Vec<u8>> {
+    let mut frame = Vec::with_capacity(2 + query.len());
+    frame.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    frame.extend_from_slice(query);
+    stream.write_all(&frame)?;
+    stream.flush()?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len > SECURE_MAX_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, "DoT response is too long"));
+    }
+    let mut response = vec![0u8; len];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+/// The setting as it's kept in the PDDB: one `name=value` per line
+fn to_config(config: &SecureResolver) -> std::string::String {
+    format!("transport={}\naddr={}\nport={}\nserver_name={}\npath={}\nfallback={}\n",
+        match config.transport {
+            SecureTransport::Tls => "tls",
+            SecureTransport::Https => "https",
+        },
+        IpAddr::from(config.addr),
+        config.port,
+        config.server_name.as_str().unwrap_or(""),
+        config.path.as_str().unwrap_or(""),
+        match config.fallback {
+            FallbackPolicy::Strict => "strict",
+            FallbackPolicy::Plaintext => "plaintext",
+        },
+    )
+}
+
+fn from_config(text: &str) -> Option<SecureResolver> {
+    let mut config = SecureResolver::tls(IpAddr::from([0, 0, 0, 0]), "");
+    let mut addr = None;
+    for line in text.lines() {
+        let (name, value) = line.split_once('=')?;
+        match name {
+            "transport" => config.transport = match value {
+                "tls" => SecureTransport::Tls,
+                "https" => SecureTransport::Https,
+                _ => return None,
+            },
+            "addr" => addr = Some(value.parse::<IpAddr>().ok()?),
+            "port" => config.port = value.parse().ok()?,
+            "server_name" => config.server_name = xous_ipc::String::from_str(value),
+            "path" => config.path = xous_ipc::String::from_str(value),
+            "fallback" => config.fallback = match value {
+                "strict" => FallbackPolicy::Strict,
+                "plaintext" => FallbackPolicy::Plaintext,
+                _ => return None,
+            },
+            // written by some later version; what we know of should still work
+            _ => log::warn!("unknown encrypted DNS setting {}", name),
+        }
+    }
+    config.addr = NetIpAddr::from(addr?);
+    if config.is_valid() { Some(config) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_round_trips() {
+        let mut config = SecureResolver::https(IpAddr::from([1, 1, 1, 1]), "cloudflare-dns.com", "/dns-query");
+        config.fallback = FallbackPolicy::Plaintext;
+        let parsed = from_config(&to_config(&config)).unwrap();
+        assert_eq!(parsed.transport, SecureTransport::Https);
+        assert_eq!(IpAddr::from(parsed.addr), IpAddr::from([1, 1, 1, 1]));
+        assert_eq!(parsed.port, 443);
+        assert_eq!(parsed.server_name.as_str().unwrap(), "cloudflare-dns.com");
+        assert_eq!(parsed.path.as_str().unwrap(), "/dns-query");
+        assert_eq!(parsed.fallback, FallbackPolicy::Plaintext);
+
+        // an address is a must, and so is a path for DoH
+        assert!(from_config("transport=tls\nserver_name=dns.quad9.net\n").is_none());
+        assert!(from_config("transport=https\naddr=9.9.9.9\nserver_name=dns.quad9.net\n").is_none());
+        assert!(from_config("transport=tls\naddr=9.9.9.9\nserver_name=dns.quad9.net\n").is_some());
+    }
+
+    /// reads come from `input`, writes go to `output`
+    struct Pipe {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.input.read(buf)
+        }
+    }
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dot_messages_are_length_prefixed() {
+        let mut pipe = Pipe { input: std::io::Cursor::new(vec![0, 3, 7, 8, 9, 0xff]), output: Vec::new() };
+        assert_eq!(framed_exchange(&mut pipe, &[1, 2]).unwrap(), vec![7, 8, 9]);
+        assert_eq!(pipe.output, vec![0, 2, 1, 2]);
+
+        // the connection closing partway through a response is an error, not a short answer
+        let mut pipe = Pipe { input: std::io::Cursor::new(vec![0, 3, 7]), output: Vec::new() };
+        assert!(framed_exchange(&mut pipe, &[1, 2]).is_err());
+    }
+}
@@ -205,6 +205,10 @@ pub(crate) enum Opcode {
     Bip39toBytes = 30,
     BytestoBip39 = 31,
     Bip39Suggestions = 32,
+
+    /// Maps a token to the name of the context it was issued to. Lets other servers (e.g. the PDDB) identify
+    /// the app that presents a token; the token itself is never handed out by this call.
+    TokenToName = 33,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
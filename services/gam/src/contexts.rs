@@ -130,6 +130,9 @@ impl ContextManager {
     pub(crate) fn allow_untrusted_code(&self) -> bool {
         self.tm.allow_untrusted_code()
     }
+    pub(crate) fn token_to_name(&self, token: &[u32; 4]) -> Option<String> {
+        self.tm.lookup_name(token)
+    }
     pub(crate) fn is_token_valid(&self, token: [u32; 4]) -> bool {
         self.tm.is_token_valid(token)
     }
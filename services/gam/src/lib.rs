@@ -280,6 +280,17 @@ impl Gam {
 
         Ok(returned_claim.token)
     }
+    /// Returns the name of the context `token` was issued to, or None if the GAM never issued it.
+    pub fn token_to_name(&self, token: [u32; 4]) -> Result<Option<std::string::String>, xous::Error> {
+        let lookup = TokenClaim {
+            token: Some(token),
+            name: String::<128>::new(),
+        };
+        let mut buf = Buffer::into_buf(lookup).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::TokenToName.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let returned = buf.to_original::<TokenClaim, _>().unwrap();
+        Ok(returned.token.map(|_| returned.name.as_str().unwrap_or("").to_string()))
+    }
     pub fn set_predictor_api_token(&self, api_token: [u32; 4], gam_token: [u32; 4]) -> Result<(), xous::Error> {
         let at = ApiToken {
             gam_token,
@@ -664,6 +664,18 @@ fn wrapped_main() -> ! {
                 }
                 buffer.replace(spec).unwrap();
             }
+            Some(Opcode::TokenToName) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut lookup = buffer.to_original::<TokenClaim, _>().unwrap();
+                lookup.name.clear();
+                if let Some(name) = lookup.token.and_then(|token| context_mgr.token_to_name(&token)) {
+                    lookup.name.append(&name).ok();
+                } else {
+                    // not a token we issued
+                    lookup.token = None;
+                }
+                buffer.replace(lookup).unwrap();
+            }
             Some(Opcode::Quit) => break,
             None => {log::error!("unhandled message {:?}", msg);}
         }
@@ -0,0 +1,134 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{Result, Error, ErrorKind};
+use std::net::TcpStream;
+
+/// Longest status, header or chunk size line that is accepted
+const MAX_LINE: u64 = 4096;
+
+pub(crate) enum Conn {
+    Plain(TcpStream),
+    Tls(Box<tls::TlsStream>),
+}
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Conn::Plain(s) => s.read(buf),
+            Conn::Tls(s) => s.read(buf),
+        }
+    }
+}
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Conn::Plain(s) => s.write(buf),
+            Conn::Tls(s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Conn::Plain(s) => s.flush(),
+            Conn::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// Reads one CRLF (or bare LF) terminated line, without its terminator.
+pub(crate) fn read_line(r: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    r.by_ref().take(MAX_LINE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(if line.len() as u64 >= MAX_LINE {
+            Error::new(ErrorKind::InvalidData, "line too long in HTTP response")
+        } else {
+            Error::new(ErrorKind::UnexpectedEof, "connection closed in the middle of an HTTP response")
+        });
+    }
+    line.pop();
+    if line.ends_with('\r') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+/// How the end of a response body is found (RFC 7230, section 3.3.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// no body, e.g. the response to a HEAD, or a 204
+    Empty,
+    /// `Content-Length` bytes remain
+    Length(u64),
+    /// `Transfer-Encoding: chunked`; this many bytes remain of the current chunk, and `done` is set once
+    /// the last chunk and the trailers have been read
+    Chunked { remaining: u64, done: bool },
+    /// the body runs until the server closes the connection
+    Close,
+}
+
+/// The body of a response. Reads return the decoded body, and end where the body ends.
+pub struct Body {
+    pub(crate) conn: BufReader<Conn>,
+    pub(crate) framing: Framing,
+}
+impl Body {
+    /// The length of the body, if the server said what it is up front.
+    pub fn len_hint(&self) -> Option<u64> {
+        match self.framing {
+            Framing::Empty => Some(0),
+            Framing::Length(len) => Some(len),
+            _ => None,
+        }
+    }
+    /// Starts the next chunk of a chunked body, and returns its length; 0 means the body has ended.
+    fn next_chunk(&mut self) -> Result<u64> {
+        let line = read_line(&mut self.conn)?;
+        // chunk extensions are allowed after a ';', and are of no interest
+        let size = line.split(';').next().unwrap_or("").trim();
+        let len = u64::from_str_radix(size, 16)
+            .or(Err(Error::new(ErrorKind::InvalidData, "bad chunk size in HTTP response")))?;
+        if len == 0 {
+            // trailers are discarded; they end with an empty line
+            while !read_line(&mut self.conn)?.is_empty() {}
+        }
+        Ok(len)
+    }
+}
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.framing {
+            Framing::Empty => Ok(0),
+            Framing::Length(0) => Ok(0),
+            Framing::Length(remaining) => {
+                let len = remaining.min(buf.len() as u64) as usize;
+                let read = self.conn.read(&mut buf[..len])?;
+                if read == 0 {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before the end of the body"));
+                }
+                self.framing = Framing::Length(remaining - read as u64);
+                Ok(read)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { remaining: 0, .. } => {
+                let len = self.next_chunk()?;
+                self.framing = Framing::Chunked { remaining: len, done: len == 0 };
+                self.read(buf)
+            }
+            Framing::Chunked { remaining, .. } => {
+                let len = remaining.min(buf.len() as u64) as usize;
+                let read = self.conn.read(&mut buf[..len])?;
+                if read == 0 {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed in the middle of a chunk"));
+                }
+                let remaining = remaining - read as u64;
+                if remaining == 0 && !read_line(&mut self.conn)?.is_empty() {
+                    return Err(Error::new(ErrorKind::InvalidData, "chunk is longer than its size"));
+                }
+                self.framing = Framing::Chunked { remaining, done: false };
+                Ok(read)
+            }
+            Framing::Close => self.conn.read(buf),
+        }
+    }
+}
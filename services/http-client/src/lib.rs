@@ -0,0 +1,330 @@
+//! A small HTTP/1.1 client for Xous apps, over the TCP sockets of the net service.
+//!
+//! ```no_run
+//! let client = http_client::Client::new();
+//! let mut response = client.get("https://ci.betrusted.io/releases/latest/version.txt").send().unwrap();
+//! if response.status == 200 {
+//!     let version = response.text().unwrap();
+//! }
+//! ```
+//!
+//! `https://` URLs go through the `tls` crate, so they are only reachable once the root CA store has been
+//! set up (see `tls::Tls::trust_mozilla_roots()`). Each request uses a connection of its own, which is
+//! closed when the response is dropped. Redirects are followed, and chunked responses are decoded; bodies
+//! are read as a stream, and can be written straight into a PDDB key with `Response::save_to_key()`, so
+//! large downloads don't have to fit in memory.
+
+mod body;
+pub use body::Body;
+use body::*;
+mod url;
+pub use url::Url;
+#[cfg(test)]
+mod tests;
+
+use std::cell::RefCell;
+use std::io::{BufReader, Read, Write};
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+pub const DEFAULT_USER_AGENT: &str = "Precursor/0.9.6";
+/// Responses with more headers than this are refused
+const MAX_HEADERS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+}
+impl Method {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+        }
+    }
+}
+
+pub struct Client {
+    /// redirects followed before a request fails with an error
+    pub max_redirects: usize,
+    /// read and write timeout of the connections
+    pub timeout: Option<Duration>,
+    pub user_agent: String,
+    /// made on the first https request, so plain http never touches the PDDB
+    tls: RefCell<Option<tls::Tls>>,
+    /// hosts connected to at a fixed address instead of the one DNS gives
+    resolved: Vec<(String, IpAddr)>,
+}
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            max_redirects: 5,
+            timeout: Some(Duration::from_millis(10_000)),
+            user_agent: String::from(DEFAULT_USER_AGENT),
+            tls: RefCell::new(None),
+            resolved: Vec::new(),
+        }
+    }
+    /// Connects to `host` at `addr` from now on, without looking its name up. The `Host` header and
+    /// the name the TLS certificate is checked against are still `host`; this is how the DNS service
+    /// reaches a DNS-over-HTTPS server, as it can't ask itself where that server is.
+    pub fn resolve_to(&mut self, host: &str, addr: IpAddr) {
+        self.resolved.retain(|(h, _)| !h.eq_ignore_ascii_case(host));
+        self.resolved.push((host.to_string(), addr));
+    }
+    pub fn get(&self, url: &str) -> Request<'_> {
+        Request::new(self, Method::Get, url)
+    }
+    pub fn head(&self, url: &str) -> Request<'_> {
+        Request::new(self, Method::Head, url)
+    }
+    pub fn post(&self, url: &str, content_type: &str, body: &[u8]) -> Request<'_> {
+        let mut request = Request::new(self, Method::Post, url);
+        request.headers.push((String::from("Content-Type"), content_type.to_string()));
+        request.body = body.to_vec();
+        request
+    }
+
+    fn connect(&self, url: &Url) -> Result<Conn> {
+        let addr = self.resolved.iter()
+            .find(|(host, _)| host.eq_ignore_ascii_case(&url.host))
+            .map(|&(_, ip)| SocketAddr::new(ip, url.port));
+        let conn = if url.https {
+            let mut session = self.tls.borrow_mut();
+            let tls = session.get_or_insert_with(tls::Tls::new);
+            let stream = match addr {
+                Some(addr) => tls.connect_to(addr, &url.host)?,
+                None => tls.connect(&url.host, url.port)?,
+            };
+            stream.sock.set_read_timeout(self.timeout)?;
+            stream.sock.set_write_timeout(self.timeout)?;
+            Conn::Tls(Box::new(stream))
+        } else {
+            let stream = match addr {
+                Some(addr) => TcpStream::connect(addr)?,
+                None => TcpStream::connect((url.host.as_str(), url.port))?,
+            };
+            stream.set_read_timeout(self.timeout)?;
+            stream.set_write_timeout(self.timeout)?;
+            Conn::Plain(stream)
+        };
+        Ok(conn)
+    }
+}
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+pub struct Request<'a> {
+    client: &'a Client,
+    method: Method,
+    url: Result<Url>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+impl<'a> Request<'a> {
+    fn new(client: &'a Client, method: Method, url: &str) -> Request<'a> {
+        Request { client, method, url: Url::parse(url), headers: Vec::new(), body: Vec::new() }
+    }
+    /// Adds a header to the request. `Host`, `Content-Length`, `Connection` and `User-Agent` are filled
+    /// in by the client.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sends the request, and follows redirects until a response that isn't one comes back. Only the
+    /// status line and headers have been read when this returns; the body is read from the `Response`.
+    pub fn send(self) -> Result<Response> {
+        let Request { client, mut method, url, mut headers, mut body } = self;
+        let mut url = url?;
+        let mut redirects = 0;
+        loop {
+            let mut conn = BufReader::new(client.connect(&url)?);
+            write_request(conn.get_mut(), &client.user_agent, method, &url, &headers, &body)?;
+            let (status, reason, response_headers) = read_head(&mut conn)?;
+            log::debug!("{} {}:{}{} -> {} {}", method.as_str(), url.host, url.port, url.path, status, reason);
+
+            let is_redirect = (300..400).contains(&status) && status != 304;
+            if let Some(location) = header_value(&response_headers, "Location").filter(|_| is_redirect) {
+                redirects += 1;
+                if redirects > client.max_redirects {
+                    return Err(Error::new(ErrorKind::Other, "too many redirects"));
+                }
+                let next = url.join(location)?;
+                redirect_headers(&url, &next, &mut headers)?;
+                url = next;
+                // 307 and 308 repeat the request as it was; the others turn it into a GET
+                if status != 307 && status != 308 && method == Method::Post {
+                    method = Method::Get;
+                    body.clear();
+                    headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Type"));
+                }
+                continue;
+            }
+
+            let framing = if method == Method::Head || status == 204 || status == 304 {
+                Framing::Empty
+            } else if header_value(&response_headers, "Transfer-Encoding")
+                .map_or(false, |te| te.to_ascii_lowercase().contains("chunked")) {
+                Framing::Chunked { remaining: 0, done: false }
+            } else if let Some(len) = header_value(&response_headers, "Content-Length") {
+                Framing::Length(len.trim().parse::<u64>()
+                    .or(Err(Error::new(ErrorKind::InvalidData, "bad Content-Length in HTTP response")))?)
+            } else {
+                Framing::Close
+            };
+            return Ok(Response { status, reason, headers: response_headers, url, body: Body { conn, framing } });
+        }
+    }
+}
+
+/// Checks that a redirect from `from` to `to` may be followed, and drops the credentials in `headers` if it
+/// leaves the origin they were meant for.
+fn redirect_headers(from: &Url, to: &Url, headers: &mut Vec<(String, String)>) -> Result<()> {
+    if from.https && !to.https {
+        return Err(Error::new(ErrorKind::PermissionDenied, "refusing to follow a redirect from https to http"));
+    }
+    if !from.same_origin(to) {
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Authorization") && !name.eq_ignore_ascii_case("Cookie"));
+    }
+    Ok(())
+}
+
+fn write_request(conn: &mut Conn, user_agent: &str, method: Method, url: &Url, headers: &[(String, String)], body: &[u8]) -> Result<()> {
+    // assemble the head first, so it goes out in as few packets as possible
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: close\r\n",
+        method.as_str(), url.path, url.host_header(), user_agent);
+    if method == Method::Post {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    for (name, value) in headers {
+        if name.contains(|c: char| c == ':' || c.is_ascii_control()) || value.contains(&['\r', '\n'][..]) {
+            return Err(Error::new(ErrorKind::InvalidInput, "bad request header"));
+        }
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    conn.write_all(head.as_bytes())?;
+    conn.write_all(body)?;
+    conn.flush()
+}
+
+/// status, reason phrase and headers of a response
+type Head = (u16, String, Vec<(String, String)>);
+
+/// Reads the status line and headers of a response, skipping over any `100 Continue`s.
+fn read_head(conn: &mut BufReader<Conn>) -> Result<Head> {
+    loop {
+        let status_line = read_line(conn)?;
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().map_or(false, |version| version.starts_with("HTTP/1.")) {
+            return Err(Error::new(ErrorKind::InvalidData, "not an HTTP/1.x response"));
+        }
+        let status = parts.next().and_then(|s| s.parse::<u16>().ok())
+            .ok_or(Error::new(ErrorKind::InvalidData, "bad status in HTTP response"))?;
+        let reason = parts.next().unwrap_or("").to_string();
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(conn)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(Error::new(ErrorKind::InvalidData, "too many headers in HTTP response"));
+            }
+            let (name, value) = line.split_once(':')
+                .ok_or(Error::new(ErrorKind::InvalidData, "bad header in HTTP response"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        if status >= 200 || status == 101 {
+            return Ok((status, reason, headers));
+        }
+    }
+}
+
+fn header_value<'h>(headers: &'h [(String, String)], name: &str) -> Option<&'h str> {
+    headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+}
+
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    /// where the response came from, after any redirects
+    pub url: Url,
+    body: Body,
+}
+impl Response {
+    /// The value of the first header called `name`, compared without regard to case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header_value(&self.headers, name)
+    }
+    pub fn body(&mut self) -> &mut Body {
+        &mut self.body
+    }
+    /// Reads the whole body into memory, failing if it is longer than `max_len`.
+    pub fn bytes(&mut self, max_len: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.body).take(max_len as u64 + 1).read_to_end(&mut data)?;
+        if data.len() > max_len {
+            return Err(Error::new(ErrorKind::OutOfMemory, "HTTP response body is too long"));
+        }
+        Ok(data)
+    }
+    /// Reads the whole body as UTF-8 text, up to 64 KiB.
+    pub fn text(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes(64 * 1024)?)
+            .or(Err(Error::new(ErrorKind::InvalidData, "HTTP response body is not UTF-8")))
+    }
+    /// Streams the body into `key` of `dict`, replacing whatever the key held, and returns the number of
+    /// bytes written. The key and dictionary are created if they don't exist.
+    ///
+    /// The body is first saved to `<key>.partial`, and only copied over `key` once all of it has arrived,
+    /// so a download that fails part way leaves `key` as it was.
+    pub fn save_to_key(&mut self, pddb: &pddb::Pddb, dict: &str, key: &str, basis: Option<&str>) -> Result<u64> {
+        let partial = format!("{}.partial", key);
+        delete_if_present(pddb, dict, &partial, basis)?;
+        let alloc_hint = self.body.len_hint().map(|len| len as usize);
+        let mut pk = pddb.get(dict, &partial, basis, true, true, alloc_hint, None::<fn()>)?;
+        let written = match std::io::copy(&mut self.body, &mut pk) {
+            Ok(written) => written,
+            Err(e) => {
+                drop(pk);
+                pddb.delete_key(dict, &partial, basis).ok();
+                return Err(e);
+            }
+        };
+        drop(pk);
+        pddb.sync()?;
+        // the PDDB can't rename a key, so the download is copied into place. The old contents are deleted
+        // rather than written over, so a shorter download doesn't keep their tail.
+        delete_if_present(pddb, dict, key, basis)?;
+        let mut src = pddb.get(dict, &partial, basis, false, false, None, None::<fn()>)?;
+        let mut dst = pddb.get(dict, key, basis, true, true, Some(written as usize), None::<fn()>)?;
+        std::io::copy(&mut src, &mut dst)?;
+        drop(src);
+        drop(dst);
+        pddb.delete_key(dict, &partial, basis)?;
+        pddb.sync()?;
+        Ok(written)
+    }
+}
+fn delete_if_present(pddb: &pddb::Pddb, dict: &str, key: &str, basis: Option<&str>) -> Result<()> {
+    match pddb.delete_key(dict, key, basis) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.body.read(buf)
+    }
+}
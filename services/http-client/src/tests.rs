@@ -0,0 +1,203 @@
+// These run in hosted mode (`cargo test -p http-client`), against a stand-in server on the loopback
+// interface of the host.
+use super::*;
+use std::io::BufRead;
+use std::net::TcpListener;
+use std::thread;
+
+/// Starts a server that answers one connection with each of `responses`, in order. Returns the base URL
+/// of the server, and a handle that yields the requests it got (head and body) once it is done.
+fn serve(responses: Vec<&'static str>) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let handle = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            requests.push(request);
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+        requests
+    });
+    (base, handle)
+}
+
+#[test]
+fn content_length() {
+    let (base, server) = serve(vec!["HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Test: yes\r\n\r\nhello"]);
+    let mut response = Client::new().get(&format!("{}/a?b=c", base)).send().unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("x-test"), Some("yes"));
+    assert_eq!(response.text().unwrap(), "hello");
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("GET /a?b=c HTTP/1.1\r\n"));
+    assert!(requests[0].contains(&format!("Host: {}\r\n", base.trim_start_matches("http://"))));
+}
+
+#[test]
+fn chunked() {
+    let (base, server) = serve(vec![
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Trailer: yes\r\n\r\n"
+    ]);
+    let mut response = Client::new().get(&base).send().unwrap();
+    assert_eq!(response.text().unwrap(), "hello, world");
+    server.join().unwrap();
+}
+
+#[test]
+fn close_delimited() {
+    let (base, server) = serve(vec!["HTTP/1.0 200 OK\r\n\r\nuntil the connection closes"]);
+    let mut response = Client::new().get(&base).send().unwrap();
+    assert_eq!(response.text().unwrap(), "until the connection closes");
+    server.join().unwrap();
+}
+
+#[test]
+fn truncated_body() {
+    let (base, server) = serve(vec!["HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc"]);
+    let mut response = Client::new().get(&base).send().unwrap();
+    assert_eq!(response.text().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    server.join().unwrap();
+}
+
+#[test]
+fn continue_is_skipped() {
+    let (base, server) = serve(vec!["HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok"]);
+    let mut response = Client::new().post(&base, "text/plain", b"data").send().unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.text().unwrap(), "ok");
+    server.join().unwrap();
+}
+
+#[test]
+fn post() {
+    let (base, server) = serve(vec!["HTTP/1.1 204 No Content\r\n\r\n"]);
+    let mut response = Client::new().post(&format!("{}/hook", base), "application/json", b"{\"a\":1}")
+        .header("X-Token", "1234")
+        .send().unwrap();
+    assert_eq!(response.status, 204);
+    assert_eq!(response.text().unwrap(), "");
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /hook HTTP/1.1\r\n"));
+    assert!(requests[0].contains("Content-Type: application/json\r\n"));
+    assert!(requests[0].contains("Content-Length: 7\r\n"));
+    assert!(requests[0].contains("X-Token: 1234\r\n"));
+    assert!(requests[0].ends_with("\r\n\r\n{\"a\":1}"));
+}
+
+#[test]
+fn redirects() {
+    let (base, server) = serve(vec![
+        "HTTP/1.1 302 Found\r\nLocation: b/c\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 301 Moved Permanently\r\nLocation: /d\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone",
+    ]);
+    let mut response = Client::new().get(&format!("{}/a/index", base)).send().unwrap();
+    assert_eq!(response.text().unwrap(), "done");
+    assert_eq!(response.url.path, "/d");
+    let requests = server.join().unwrap();
+    assert!(requests[1].starts_with("GET /a/b/c HTTP/1.1\r\n"));
+    assert!(requests[2].starts_with("GET /d HTTP/1.1\r\n"));
+}
+
+#[test]
+fn see_other_after_post() {
+    let (base, server) = serve(vec![
+        "HTTP/1.1 303 See Other\r\nLocation: /result\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+    ]);
+    let mut response = Client::new().post(&base, "text/plain", b"form").send().unwrap();
+    assert_eq!(response.text().unwrap(), "ok");
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("POST / HTTP/1.1\r\n"));
+    assert!(requests[1].starts_with("GET /result HTTP/1.1\r\n"));
+    assert!(!requests[1].contains("Content-Type"));
+}
+
+#[test]
+fn too_many_redirects() {
+    let (base, server) = serve(vec![
+        "HTTP/1.1 307 Temporary Redirect\r\nLocation: /loop\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 307 Temporary Redirect\r\nLocation: /loop\r\nContent-Length: 0\r\n\r\n",
+    ]);
+    let mut client = Client::new();
+    client.max_redirects = 1;
+    assert!(client.get(&base).send().is_err());
+    server.join().unwrap();
+}
+
+#[test]
+fn credentials_stay_with_their_origin() {
+    let (other, other_server) = serve(vec!["HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"]);
+    let to_other: &'static str =
+        Box::leak(format!("HTTP/1.1 302 Found\r\nLocation: {}/x\r\nContent-Length: 0\r\n\r\n", other).into_boxed_str());
+    let (base, server) = serve(vec![
+        "HTTP/1.1 302 Found\r\nLocation: /same\r\nContent-Length: 0\r\n\r\n",
+        to_other,
+    ]);
+    let mut response = Client::new().get(&base)
+        .header("Authorization", "Bearer secret")
+        .header("Cookie", "session=1")
+        .header("X-Token", "1234")
+        .send().unwrap();
+    assert_eq!(response.text().unwrap(), "ok");
+    let requests = server.join().unwrap();
+    assert!(requests[1].contains("Authorization: Bearer secret\r\n"));
+    assert!(requests[1].contains("Cookie: session=1\r\n"));
+    let requests = other_server.join().unwrap();
+    assert!(!requests[0].contains("Authorization"));
+    assert!(!requests[0].contains("Cookie"));
+    assert!(requests[0].contains("X-Token: 1234\r\n"));
+}
+
+#[test]
+fn no_downgrade() {
+    let secure = Url::parse("https://example.com/a").unwrap();
+    let mut headers = vec![(String::from("Authorization"), String::from("Basic x"))];
+    let err = redirect_headers(&secure, &Url::parse("http://example.com/a").unwrap(), &mut headers).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    redirect_headers(&secure, &Url::parse("https://example.com/b").unwrap(), &mut headers).unwrap();
+    assert_eq!(headers.len(), 1);
+    // a different port is another origin, even on the same host
+    redirect_headers(&secure, &Url::parse("https://example.com:8443/a").unwrap(), &mut headers).unwrap();
+    assert!(headers.is_empty());
+    // upgrading is fine
+    redirect_headers(&Url::parse("http://example.com/").unwrap(), &secure, &mut headers).unwrap();
+}
+
+#[test]
+fn urls() {
+    let url = Url::parse("https://example.com").unwrap();
+    assert_eq!(url, Url { https: true, host: "example.com".into(), port: 443, path: "/".into() });
+    assert_eq!(url.host_header(), "example.com");
+    let url = Url::parse("http://[fe80::1]:8080/x?y#z").unwrap();
+    assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("fe80::1", 8080, "/x?y"));
+    assert_eq!(url.host_header(), "[fe80::1]:8080");
+    assert_eq!(Url::parse("http://host?q").unwrap().path, "/?q");
+    assert!(Url::parse("ftp://example.com/").is_err());
+    assert!(Url::parse("http://user@example.com/").is_err());
+    assert!(Url::parse("http://example.com:99999/").is_err());
+
+    let base = Url::parse("http://example.com/a/b?c").unwrap();
+    assert_eq!(base.join("d").unwrap().path, "/a/d");
+    assert_eq!(base.join("/e#f").unwrap().path, "/e");
+    assert_eq!(base.join("//other.org/g").unwrap().host, "other.org");
+    assert!(base.join("https://secure.org/").unwrap().https);
+}
@@ -0,0 +1,90 @@
+use std::io::{Result, Error, ErrorKind};
+
+/// The parts of an `http://` or `https://` URL that a request needs. There is deliberately no support for
+/// user info, and the fragment is dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub https: bool,
+    /// host name or address; IPv6 addresses are kept without their brackets
+    pub host: String,
+    pub port: u16,
+    /// path and query, always starting with `/`
+    pub path: String,
+}
+impl Url {
+    pub fn parse(url: &str) -> Result<Url> {
+        let (https, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(Error::new(ErrorKind::InvalidInput, "only http:// and https:// URLs are supported"));
+        };
+        let rest = rest.split('#').next().unwrap_or("");
+        let (authority, path) = match rest.find(&['/', '?'][..]) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.contains('@') {
+            return Err(Error::new(ErrorKind::InvalidInput, "URLs with user info are not supported"));
+        }
+        let default_port = if https { 443 } else { 80 };
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (host, after) = bracketed.split_once(']')
+                .ok_or(Error::new(ErrorKind::InvalidInput, "unterminated IPv6 address in URL"))?;
+            match after.strip_prefix(':') {
+                Some(port) => (host, parse_port(port)?),
+                None if after.is_empty() => (host, default_port),
+                None => return Err(Error::new(ErrorKind::InvalidInput, "junk after IPv6 address in URL")),
+            }
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, parse_port(port)?),
+                None => (authority, default_port),
+            }
+        };
+        if host.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "URL has no host"));
+        }
+        let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+        Ok(Url { https, host: host.to_string(), port, path })
+    }
+
+    /// Resolves the target of a `Location` header against this URL.
+    pub fn join(&self, location: &str) -> Result<Url> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            Url::parse(location)
+        } else if location.starts_with("//") {
+            Url::parse(&format!("{}:{}", if self.https { "https" } else { "http" }, location))
+        } else {
+            let path = if location.starts_with('/') {
+                location.to_string()
+            } else {
+                // relative to the "directory" of the current path
+                let dir = self.path.split('?').next().unwrap_or("/");
+                let dir = &dir[..dir.rfind('/').map_or(0, |i| i + 1)];
+                format!("{}{}", dir, location)
+            };
+            Ok(Url { path: path.split('#').next().unwrap_or("/").to_string(), ..self.clone() })
+        }
+    }
+
+    /// Whether `other` has the same scheme, host and port as this URL.
+    pub fn same_origin(&self, other: &Url) -> bool {
+        self.https == other.https && self.host.eq_ignore_ascii_case(&other.host) && self.port == other.port
+    }
+
+    /// The value for the `Host` header: the port is only given if it isn't the default one.
+    pub fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == if self.https { 443 } else { 80 } {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16> {
+    port.parse::<u16>().or(Err(Error::new(ErrorKind::InvalidInput, "bad port in URL")))
+}
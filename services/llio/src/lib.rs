@@ -9,6 +9,7 @@ pub mod i2c_lib;
 pub use i2c_lib::I2c;
 pub mod llio_lib;
 pub use llio_lib::Llio;
+pub mod tz;
 
 use core::sync::atomic::{AtomicU32, Ordering};
 static TIME_REFCOUNT: AtomicU32 = AtomicU32::new(0);
@@ -71,7 +72,68 @@ impl LocalTime {
             }
         }
     }
-    // Note: to get the UTC time since EPOCH, use the std::SystemTime::now()
+    /// Returns UTC time as milliseconds since EPOCH, or `None` if the wall clock has not been set.
+    ///
+    /// Prefer this over `std::SystemTime::now()` when a wrong time is worse than no time (e.g. TOTP):
+    /// `SystemTime` will happily report the raw RTC count if the UTC offset was never set.
+    pub fn get_utc_time_ms(&self) -> Option<u64> {
+        match xous::send_message(self.conn,
+            xous::Message::new_blocking_scalar(
+                10, // UtcTimeInit
+                0, 0, 0, 0
+            )
+        ).expect("couldn't get init status") {
+            xous::Result::Scalar1(is_init) => {
+                if is_init == 0 {
+                    return None;
+                }
+            }
+            _ => {
+                log::error!("error retrieving time");
+                return None;
+            }
+        }
+        match xous::send_message(self.conn,
+            xous::Message::new_blocking_scalar(
+                3, // GetUtcTimeMs -- this should not change because it's a libstd mapping
+                0, 0, 0, 0
+            )
+        ).expect("couldn't get time") {
+            xous::Result::Scalar2(hi, lo) => {
+                Some((hi as u64) << 32 | (lo as u64))
+            }
+            _ => {
+                log::error!("error retrieving time");
+                None
+            }
+        }
+    }
+    /// Returns the time zone currently in use, or `None` if the user only gave a fixed UTC offset
+    /// (or hasn't set anything yet).
+    pub fn get_timezone(&self) -> Option<&'static tz::TimeZone> {
+        match xous::send_message(self.conn,
+            xous::Message::new_blocking_scalar(
+                9, // GetTzIndex
+                0, 0, 0, 0
+            )
+        ).expect("couldn't get timezone") {
+            // the index is biased by 1 so that 0 can encode "no zone"
+            xous::Result::Scalar1(index) if index > 0 => tz::ZONES.get(index - 1),
+            _ => None,
+        }
+    }
+    /// Sets the time zone by IANA name (e.g. "Europe/Berlin"). The selection is persisted in the PDDB
+    /// by the time server, so this requires the PDDB to be mounted.
+    pub fn set_timezone(&self, name: &str) -> Result<(), xous::Error> {
+        let index = tz::find(name).ok_or(xous::Error::InvalidString)?;
+        xous::send_message(self.conn,
+            xous::Message::new_scalar(
+                8, // SetTzIndex
+                index, 0, 0, 0
+            )
+        ).map(|_| ())
+    }
+    // Note: `std::SystemTime::now()` also returns UTC, without the initialization check.
 }
 impl Drop for LocalTime {
     fn drop(&mut self) {
@@ -0,0 +1,308 @@
+//! A compact time zone database.
+//!
+//! This is not a full copy of the IANA database: historical transitions are not kept, because
+//! we only ever need to render "now" and the near past. Instead, each zone carries its current
+//! standard offset plus the DST rule that is in force today, in the same form as a POSIX `TZ`
+//! string (e.g. `CET-1CEST,M3.5.0,M10.5.0/3`). This fits in a couple kiB of flash and needs
+//! no `libc` to evaluate.
+//!
+//! Zones are stored by their IANA names, and the name is what gets persisted; the index into
+//! `ZONES` is only used as a compact handle over IPC between processes built from the same image,
+//! and is not stable across releases.
+
+/// A DST transition rule, in the POSIX `Mm.w.d/time` form.
+#[derive(Debug, Copy, Clone)]
+pub struct TransitionRule {
+    /// 1-12
+    pub month: u8,
+    /// 1-5, where 5 means "the last such weekday of the month"
+    pub week: u8,
+    /// 0 = Sunday, 6 = Saturday
+    pub weekday: u8,
+    /// local wall-clock time of the transition in minutes; may exceed 24 hours, as some zones
+    /// (e.g. Asia/Jerusalem) express their rule relative to a neighboring day.
+    pub minute: u16,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DstRule {
+    /// additional offset applied while DST is in effect, in minutes
+    pub save: i16,
+    /// transition into DST, expressed in local standard time
+    pub start: TransitionRule,
+    /// transition out of DST, expressed in local daylight time
+    pub end: TransitionRule,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TimeZone {
+    pub name: &'static str,
+    /// standard offset east of UTC, in minutes
+    pub std_offset: i16,
+    pub dst: Option<DstRule>,
+}
+
+const fn m(month: u8, week: u8, weekday: u8, hours: u16, minutes: u16) -> TransitionRule {
+    TransitionRule { month, week, weekday, minute: hours * 60 + minutes }
+}
+const fn fixed(name: &'static str, std_offset: i16) -> TimeZone {
+    TimeZone { name, std_offset, dst: None }
+}
+const fn dst(name: &'static str, std_offset: i16, start: TransitionRule, end: TransitionRule) -> TimeZone {
+    TimeZone { name, std_offset, dst: Some(DstRule { save: 60, start, end }) }
+}
+
+// rule sets shared by many zones
+/// US/Canada: M3.2.0,M11.1.0
+const US_START: TransitionRule = m(3, 2, 0, 2, 0);
+const US_END: TransitionRule = m(11, 1, 0, 2, 0);
+/// EU: last Sunday of March to last Sunday of October, at 01:00 UTC, expressed in local time
+/// for each of the western, central and eastern European zones.
+const WET_START: TransitionRule = m(3, 5, 0, 1, 0);
+const WET_END: TransitionRule = m(10, 5, 0, 2, 0);
+const CET_START: TransitionRule = m(3, 5, 0, 2, 0);
+const CET_END: TransitionRule = m(10, 5, 0, 3, 0);
+const EET_START: TransitionRule = m(3, 5, 0, 3, 0);
+const EET_END: TransitionRule = m(10, 5, 0, 4, 0);
+/// AU south-east: M10.1.0,M4.1.0/3
+const AU_START: TransitionRule = m(10, 1, 0, 2, 0);
+const AU_END: TransitionRule = m(4, 1, 0, 3, 0);
+
+/// The database. Keep this sorted by name within a region: the UX presents it in this order.
+pub const ZONES: &[TimeZone] = &[
+    fixed("UTC", 0),
+    // Africa
+    fixed("Africa/Abidjan", 0),
+    fixed("Africa/Algiers", 60),
+    dst("Africa/Cairo", 120, m(4, 5, 5, 0, 0), m(10, 5, 4, 24, 0)),
+    fixed("Africa/Casablanca", 60),
+    fixed("Africa/Johannesburg", 120),
+    fixed("Africa/Lagos", 60),
+    fixed("Africa/Nairobi", 180),
+    // America
+    dst("America/Anchorage", -540, US_START, US_END),
+    fixed("America/Bogota", -300),
+    fixed("America/Buenos_Aires", -180),
+    fixed("America/Caracas", -240),
+    dst("America/Chicago", -360, US_START, US_END),
+    dst("America/Denver", -420, US_START, US_END),
+    dst("America/Halifax", -240, US_START, US_END),
+    fixed("America/Lima", -300),
+    dst("America/Los_Angeles", -480, US_START, US_END),
+    fixed("America/Mexico_City", -360),
+    dst("America/New_York", -300, US_START, US_END),
+    fixed("America/Phoenix", -420),
+    dst("America/Santiago", -240, m(9, 1, 6, 24, 0), m(4, 1, 6, 24, 0)),
+    fixed("America/Sao_Paulo", -180),
+    dst("America/St_Johns", -210, US_START, US_END),
+    // Asia
+    fixed("Asia/Bangkok", 420),
+    fixed("Asia/Dhaka", 360),
+    fixed("Asia/Dubai", 240),
+    fixed("Asia/Hong_Kong", 480),
+    fixed("Asia/Jakarta", 420),
+    dst("Asia/Jerusalem", 120, m(3, 4, 4, 26, 0), m(10, 5, 0, 2, 0)),
+    fixed("Asia/Karachi", 300),
+    fixed("Asia/Kathmandu", 345),
+    fixed("Asia/Kolkata", 330),
+    fixed("Asia/Manila", 480),
+    fixed("Asia/Riyadh", 180),
+    fixed("Asia/Seoul", 540),
+    fixed("Asia/Shanghai", 480),
+    fixed("Asia/Singapore", 480),
+    fixed("Asia/Taipei", 480),
+    fixed("Asia/Tehran", 210),
+    fixed("Asia/Tokyo", 540),
+    // Atlantic
+    dst("Atlantic/Azores", -60, m(3, 5, 0, 0, 0), m(10, 5, 0, 1, 0)),
+    fixed("Atlantic/Reykjavik", 0),
+    // Australia
+    dst("Australia/Adelaide", 570, AU_START, AU_END),
+    fixed("Australia/Brisbane", 600),
+    fixed("Australia/Darwin", 570),
+    dst("Australia/Hobart", 600, AU_START, AU_END),
+    dst("Australia/Melbourne", 600, AU_START, AU_END),
+    fixed("Australia/Perth", 480),
+    dst("Australia/Sydney", 600, AU_START, AU_END),
+    // Europe
+    dst("Europe/Amsterdam", 60, CET_START, CET_END),
+    dst("Europe/Athens", 120, EET_START, EET_END),
+    dst("Europe/Berlin", 60, CET_START, CET_END),
+    dst("Europe/Helsinki", 120, EET_START, EET_END),
+    fixed("Europe/Istanbul", 180),
+    dst("Europe/Kyiv", 120, EET_START, EET_END),
+    dst("Europe/Lisbon", 0, WET_START, WET_END),
+    dst("Europe/London", 0, WET_START, WET_END),
+    dst("Europe/Madrid", 60, CET_START, CET_END),
+    fixed("Europe/Moscow", 180),
+    dst("Europe/Paris", 60, CET_START, CET_END),
+    dst("Europe/Rome", 60, CET_START, CET_END),
+    dst("Europe/Stockholm", 60, CET_START, CET_END),
+    dst("Europe/Warsaw", 60, CET_START, CET_END),
+    dst("Europe/Zurich", 60, CET_START, CET_END),
+    // Pacific
+    dst("Pacific/Auckland", 720, m(9, 5, 0, 2, 0), m(4, 1, 0, 3, 0)),
+    dst("Pacific/Chatham", 765, m(9, 5, 0, 2, 45), m(4, 1, 0, 3, 45)),
+    fixed("Pacific/Fiji", 720),
+    fixed("Pacific/Guam", 600),
+    fixed("Pacific/Honolulu", -600),
+    fixed("Pacific/Kiritimati", 840),
+    fixed("Pacific/Tongatapu", 780),
+];
+
+/// Returns the index of a zone by its IANA name.
+pub fn find(name: &str) -> Option<usize> {
+    ZONES.iter().position(|z| z.name == name)
+}
+
+/// Returns the distinct region prefixes of the database (the part before the `/`), in order.
+/// Zones without a region (e.g. `UTC`) are reported under their own name.
+pub fn regions() -> impl Iterator<Item = &'static str> {
+    ZONES.iter().enumerate().filter_map(|(i, z)| {
+        let region = region_of(z.name);
+        if i == 0 || region_of(ZONES[i - 1].name) != region { Some(region) } else { None }
+    })
+}
+
+/// Returns the indices of the zones in a given region.
+pub fn zones_in<'a>(region: &'a str) -> impl Iterator<Item = usize> + 'a {
+    ZONES.iter().enumerate().filter(move |(_, z)| region_of(z.name) == region).map(|(i, _)| i)
+}
+
+fn region_of(name: &str) -> &str {
+    name.split_once('/').map(|(r, _)| r).unwrap_or(name)
+}
+
+impl TimeZone {
+    /// The part of the name after the region, e.g. `New_York` for `America/New_York`.
+    pub fn city(&self) -> &'static str {
+        self.name.split_once('/').map(|(_, c)| c).unwrap_or(self.name)
+    }
+
+    /// Total offset east of UTC in milliseconds (standard + DST) in effect at the given UTC time.
+    pub fn offset_ms_at(&self, utc_ms: i64) -> i64 {
+        let std_ms = self.std_offset as i64 * 60_000;
+        if let Some(dst) = self.dst {
+            let save_ms = dst.save as i64 * 60_000;
+            let year = civil_from_days((utc_ms + std_ms).div_euclid(86_400_000)).0;
+            let start = transition_local_ms(year, &dst.start) - std_ms;
+            let end = transition_local_ms(year, &dst.end) - std_ms - save_ms;
+            let in_dst = if start < end {
+                // northern hemisphere: DST is a window inside the year
+                utc_ms >= start && utc_ms < end
+            } else {
+                // southern hemisphere: DST straddles the new year
+                utc_ms >= start || utc_ms < end
+            };
+            if in_dst { std_ms + save_ms } else { std_ms }
+        } else {
+            std_ms
+        }
+    }
+}
+
+/// Local time (ms since EPOCH, in the frame the rule is expressed in) of a transition in a given year.
+fn transition_local_ms(year: i64, rule: &TransitionRule) -> i64 {
+    let first = days_from_civil(year, rule.month as i64, 1);
+    let next_month = if rule.month == 12 {
+        days_from_civil(year + 1, 1, 1)
+    } else {
+        days_from_civil(year, rule.month as i64 + 1, 1)
+    };
+    let mut day = first + (rule.weekday as i64 - weekday(first)).rem_euclid(7) + 7 * (rule.week as i64 - 1);
+    while day >= next_month {
+        day -= 7;
+    }
+    day * 86_400_000 + rule.minute as i64 * 60_000
+}
+
+/// 0 = Sunday; 1970-01-01 was a Thursday
+fn weekday(days: i64) -> i64 {
+    (days + 4).rem_euclid(7)
+}
+
+// From Howard Hinnant's `chrono`-compatible date algorithms (public domain).
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc_ms(y: i64, mo: i64, d: i64, h: i64, mi: i64) -> i64 {
+        (days_from_civil(y, mo, d) * 86_400 + h * 3600 + mi * 60) * 1000
+    }
+    fn offset_hours(name: &str, t: i64) -> f64 {
+        ZONES[find(name).unwrap()].offset_ms_at(t) as f64 / 3_600_000.0
+    }
+
+    #[test]
+    fn civil_roundtrip() {
+        for days in [-1, 0, 1, 11016, 19723, 20000, 47482] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+        assert_eq!(weekday(days_from_civil(2024, 3, 10)), 0);
+    }
+
+    #[test]
+    fn us_transitions() {
+        // 2024: DST from 2024-03-10 02:00 EST (07:00 UTC) to 2024-11-03 02:00 EDT (06:00 UTC)
+        assert_eq!(offset_hours("America/New_York", utc_ms(2024, 3, 10, 6, 59)), -5.0);
+        assert_eq!(offset_hours("America/New_York", utc_ms(2024, 3, 10, 7, 0)), -4.0);
+        assert_eq!(offset_hours("America/New_York", utc_ms(2024, 11, 3, 5, 59)), -4.0);
+        assert_eq!(offset_hours("America/New_York", utc_ms(2024, 11, 3, 6, 0)), -5.0);
+        assert_eq!(offset_hours("America/Phoenix", utc_ms(2024, 7, 1, 0, 0)), -7.0);
+    }
+
+    #[test]
+    fn eu_transitions() {
+        // 2024: last Sunday of March is the 31st, last Sunday of October is the 27th, both at 01:00 UTC
+        for name in ["Europe/London", "Europe/Berlin", "Europe/Helsinki"] {
+            let base = offset_hours(name, utc_ms(2024, 1, 15, 12, 0));
+            assert_eq!(offset_hours(name, utc_ms(2024, 3, 31, 0, 59)), base);
+            assert_eq!(offset_hours(name, utc_ms(2024, 3, 31, 1, 0)), base + 1.0);
+            assert_eq!(offset_hours(name, utc_ms(2024, 10, 27, 0, 59)), base + 1.0);
+            assert_eq!(offset_hours(name, utc_ms(2024, 10, 27, 1, 0)), base);
+        }
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        // 2024: AEDT ends 2024-04-07 03:00 local (16:00 UTC on the 6th), starts 2024-10-06 02:00 local (16:00 UTC on the 5th)
+        assert_eq!(offset_hours("Australia/Sydney", utc_ms(2024, 1, 1, 0, 0)), 11.0);
+        assert_eq!(offset_hours("Australia/Sydney", utc_ms(2024, 4, 6, 15, 59)), 11.0);
+        assert_eq!(offset_hours("Australia/Sydney", utc_ms(2024, 4, 6, 16, 0)), 10.0);
+        assert_eq!(offset_hours("Australia/Sydney", utc_ms(2024, 10, 5, 15, 59)), 10.0);
+        assert_eq!(offset_hours("Australia/Sydney", utc_ms(2024, 10, 5, 16, 0)), 11.0);
+        assert_eq!(offset_hours("Australia/Adelaide", utc_ms(2024, 7, 1, 0, 0)), 9.5);
+    }
+
+    #[test]
+    fn regions_are_grouped() {
+        let regions: Vec<&str> = regions().collect();
+        let mut dedup = regions.clone();
+        dedup.dedup();
+        dedup.sort();
+        dedup.dedup();
+        assert_eq!(regions.len(), dedup.len(), "regions are not contiguous in ZONES");
+        assert!(zones_in("Europe").all(|i| ZONES[i].name.starts_with("Europe/")));
+    }
+}
@@ -16,6 +16,247 @@ impl Default for LogRecord {
     }
 }
 
+/// A runtime level filter, applied by the log server to incoming `LogRecord`s.
+///
+/// A `pid` of 0 matches every process. An empty `module` matches every module, otherwise it is
+/// matched as a prefix of the record's module path. When several filters match a record, the most
+/// specific one wins: a PID match beats a wildcard PID, then the longer module prefix wins.
+#[repr(C, align(4096))]
+pub struct FilterRecord {
+    pub pid: u32,
+    /// a `log::LevelFilter` as u32 (0 = Off ... 5 = Trace)
+    pub level: u32,
+    pub module_length: u32,
+    pub module: [u8; 128],
+}
+
+impl Default for FilterRecord {
+    fn default() -> Self {
+        FilterRecord { pid: 0, level: 0, module_length: 0, module: [0u8; 128] }
+    }
+}
+
+/// A page of records retrieved from the log server's ring buffer.
+#[repr(C, align(4096))]
+pub struct RecordPage {
+    /// in: return records with a sequence number greater than or equal to this
+    pub since: u64,
+    /// in: least severe `log::Level` (as u32) to return
+    pub level: u32,
+    /// out: number of records packed into `data`
+    pub count: u32,
+    /// out: the value to pass as `since` to continue reading
+    pub next: u64,
+    pub data: [u8; 4072],
+}
+
+impl Default for RecordPage {
+    fn default() -> Self {
+        RecordPage { since: 0, level: log::Level::Trace as u32, count: 0, next: 0, data: [0u8; 4072] }
+    }
+}
+
+/// Records in the ring buffer keep at most this many bytes of their module path...
+pub const RING_MODULE_LEN: usize = 64;
+/// ...and at most this many bytes of their formatted arguments.
+pub const RING_ARGS_LEN: usize = 192;
+/// seq (u64), pid (u32), level (u8), module length (u8), args length (u16)
+const RING_HEADER_LEN: usize = 16;
+
+/// PDDB dictionary where the persistence agent in `status` keeps warnings and errors from the ring buffer.
+/// The log server itself can't use the PDDB, as the PDDB depends on it.
+pub const LOG_PERSIST_DICT: &'static str = "sys.log";
+/// Key in `LOG_PERSIST_DICT` holding the least severe level to persist ("error" or "warn"); absent means off.
+pub const LOG_PERSIST_LEVEL_KEY: &'static str = "persist";
+/// Key in `LOG_PERSIST_DICT` holding the index (u32) of the file currently being appended to.
+pub const LOG_PERSIST_HEAD_KEY: &'static str = "head";
+/// Persisted records are kept in a rotating set of `LOG_PERSIST_FILES` keys named `saved.N`.
+pub const LOG_PERSIST_FILES: u32 = 4;
+
+/// Frames (return address and frame pointer) recovered by walking the frame-pointer chain of a
+/// panicking thread
+pub const CRASH_MAX_FRAMES: usize = 32;
+/// Words copied from the top of a panicking thread's stack, for the host symbolizer to scan
+pub const CRASH_STACK_WORDS: usize = 64;
+/// PDDB dictionary where `status` saves crash reports drained from the log server
+pub const CRASH_PERSIST_DICT: &'static str = "sys.crash";
+/// Key in `CRASH_PERSIST_DICT` holding the number (u32) of the next report to be saved
+pub const CRASH_PERSIST_NEXT_KEY: &'static str = "next";
+/// Reports are kept in a rotating set of `CRASH_PERSIST_SLOTS` keys named `report.N`.
+pub const CRASH_PERSIST_SLOTS: u32 = 8;
+/// First line of the text form of a crash report, see `CrashRecord::write_report()`
+pub const CRASH_REPORT_HEADER: &'static str = "xous-crash-report v2";
+
+/// A crash report, sent to the log server by the panic hook of a panicking process.
+///
+/// Registers are sampled inside the panic hook, so `pc` is in the hook itself. Each frame has the
+/// return address into its caller and the frame pointer it was found under, which is the caller's
+/// stack pointer at the call; that's how `symbolize-crash` recovers the `pc`, `sp`, `ra` and `fp` at
+/// the call to `panic!`. The stack is read up to the end of the thread's stack as the kernel reports
+/// it. The frame walk is only complete for code built with frame pointers, which is why a raw copy
+/// of the top of the stack rides along as well.
+#[derive(Copy, Clone)]
+#[repr(C, align(4096))]
+pub struct CrashRecord {
+    /// PID of the process that crashed; 0 marks an empty record
+    pub pid: u32,
+    pub frame_count: u32,
+    pub stack_count: u32,
+    pub name_length: u32,
+    pub message_length: u32,
+    pub pc: u64,
+    pub sp: u64,
+    pub ra: u64,
+    pub fp: u64,
+    pub frames: [u64; CRASH_MAX_FRAMES],
+    pub frame_fps: [u64; CRASH_MAX_FRAMES],
+    pub stack: [u64; CRASH_STACK_WORDS],
+    pub name: [u8; 32],
+    pub message: [u8; 2048],
+}
+
+impl Default for CrashRecord {
+    fn default() -> Self {
+        CrashRecord {
+            pid: 0, frame_count: 0, stack_count: 0, name_length: 0, message_length: 0,
+            pc: 0, sp: 0, ra: 0, fp: 0,
+            frames: [0u64; CRASH_MAX_FRAMES], frame_fps: [0u64; CRASH_MAX_FRAMES], stack: [0u64; CRASH_STACK_WORDS],
+            name: [0u8; 32], message: [0u8; 2048],
+        }
+    }
+}
+
+impl CrashRecord {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..(self.name_length as usize).min(self.name.len())]).unwrap_or("?")
+    }
+    pub fn message(&self) -> &str {
+        let message = &self.message[..(self.message_length as usize).min(self.message.len())];
+        match core::str::from_utf8(message) {
+            Ok(s) => s,
+            // the message may have been cut off in the middle of a character
+            Err(e) => core::str::from_utf8(&message[..e.valid_up_to()]).unwrap(),
+        }
+    }
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..(self.frame_count as usize).min(CRASH_MAX_FRAMES)]
+    }
+    pub fn frame_fps(&self) -> &[u64] {
+        &self.frame_fps[..(self.frame_count as usize).min(CRASH_MAX_FRAMES)]
+    }
+    pub fn stack(&self) -> &[u64] {
+        &self.stack[..(self.stack_count as usize).min(CRASH_STACK_WORDS)]
+    }
+
+    /// Writes the text form of the report, which is what gets saved to the PDDB and what
+    /// `tools/src/bin/symbolize-crash.rs` reads. `time` is the UTC time of the crash in seconds, if known.
+    /// The message always comes last, as it runs to the end of the report.
+    pub fn write_report(&self, w: &mut dyn core::fmt::Write, time: Option<u64>) -> core::fmt::Result {
+        writeln!(w, "{}", CRASH_REPORT_HEADER)?;
+        writeln!(w, "pid: {}", self.pid)?;
+        writeln!(w, "name: {}", self.name())?;
+        match time {
+            Some(t) => writeln!(w, "time: {}", t)?,
+            None => writeln!(w, "time: -")?,
+        }
+        writeln!(w, "pc: {:#x}", self.pc)?;
+        writeln!(w, "sp: {:#x}", self.sp)?;
+        writeln!(w, "ra: {:#x}", self.ra)?;
+        writeln!(w, "fp: {:#x}", self.fp)?;
+        for (frame, fp) in self.frames().iter().zip(self.frame_fps()) {
+            writeln!(w, "frame: {:#x} {:#x}", frame, fp)?;
+        }
+        for words in self.stack().chunks(8) {
+            write!(w, "stack:")?;
+            for word in words {
+                write!(w, " {:#x}", word)?;
+            }
+            writeln!(w)?;
+        }
+        writeln!(w, "message:")?;
+        writeln!(w, "{}", self.message())
+    }
+}
+
+/// One record of the ring buffer, as packed into `RecordPage::data`.
+pub struct RingRecord<'a> {
+    pub seq: u64,
+    pub pid: u32,
+    /// a `log::Level` as u32
+    pub level: u32,
+    pub module: &'a str,
+    pub args: &'a str,
+}
+
+impl<'a> RingRecord<'a> {
+    /// Packs the record into `dest`, returning the number of bytes used, or `None` if it doesn't fit.
+    pub fn pack(&self, dest: &mut [u8]) -> Option<usize> {
+        let module = self.module.as_bytes();
+        let args = self.args.as_bytes();
+        let len = RING_HEADER_LEN + module.len() + args.len();
+        if dest.len() < len || module.len() > u8::MAX as usize || args.len() > u16::MAX as usize {
+            return None;
+        }
+        dest[0..8].copy_from_slice(&self.seq.to_le_bytes());
+        dest[8..12].copy_from_slice(&self.pid.to_le_bytes());
+        dest[12] = self.level as u8;
+        dest[13] = module.len() as u8;
+        dest[14..16].copy_from_slice(&(args.len() as u16).to_le_bytes());
+        dest[RING_HEADER_LEN..RING_HEADER_LEN + module.len()].copy_from_slice(module);
+        dest[RING_HEADER_LEN + module.len()..len].copy_from_slice(args);
+        Some(len)
+    }
+
+    /// Unpacks a record from the start of `src`, returning it with the number of bytes consumed.
+    pub fn unpack(src: &'a [u8]) -> Option<(Self, usize)> {
+        if src.len() < RING_HEADER_LEN {
+            return None;
+        }
+        let mut seq = [0u8; 8];
+        seq.copy_from_slice(&src[0..8]);
+        let mut pid = [0u8; 4];
+        pid.copy_from_slice(&src[8..12]);
+        let module_len = src[13] as usize;
+        let args_len = u16::from_le_bytes([src[14], src[15]]) as usize;
+        let len = RING_HEADER_LEN + module_len + args_len;
+        if src.len() < len {
+            return None;
+        }
+        Some((
+            RingRecord {
+                seq: u64::from_le_bytes(seq),
+                pid: u32::from_le_bytes(pid),
+                level: src[12] as u32,
+                module: core::str::from_utf8(&src[RING_HEADER_LEN..RING_HEADER_LEN + module_len]).unwrap_or("?"),
+                args: core::str::from_utf8(&src[RING_HEADER_LEN + module_len..len]).unwrap_or("<invalid utf-8>"),
+            },
+            len,
+        ))
+    }
+}
+
+/// A `log::Level` (as u32) as four characters, so that log lines line up
+pub fn level_name(level: u32) -> &'static str {
+    match level {
+        1 => "ERR ",
+        2 => "WARN",
+        3 => "INFO",
+        4 => "DBG ",
+        _ => "TRCE",
+    }
+}
+
+pub fn level_filter_from_u32(level: u32) -> log::LevelFilter {
+    match level {
+        0 => log::LevelFilter::Off,
+        1 => log::LevelFilter::Error,
+        2 => log::LevelFilter::Warn,
+        3 => log::LevelFilter::Info,
+        4 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
 #[derive(Debug, PartialEq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum Opcode {
     /// A `LogRecord` message, delivering structured log output
@@ -30,6 +271,21 @@ pub enum Opcode {
     /// A `xous::StringBuffer` containing this program's name
     ProgramName = 3,
 
+    /// A `FilterRecord`, adding or replacing a runtime level filter
+    SetFilter = 4,
+
+    /// Remove all runtime level filters
+    ClearFilters = 5,
+
+    /// A `RecordPage` to be filled from the ring buffer of recent records
+    GetRecords = 6,
+
+    /// A `CrashRecord` from the panic hook of a crashing process
+    CrashReport = 7,
+
+    /// A `CrashRecord` to be filled with the oldest report not yet taken (`pid` is left at 0 if there is none)
+    TakeCrashReport = 8,
+
     /// A panic occurred, and a panic log is forthcoming
     PanicStarted = 1000,
 
@@ -0,0 +1,157 @@
+//! Panic hook that sends a crash report to the log server before the regular panic output.
+//!
+//! This runs in the context of the panicking thread, so it has to be careful: it doesn't allocate,
+//! and nothing in here may panic, or the process aborts with no report at all.
+use crate::api::{self, CrashRecord};
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+use num_traits::ToPrimitive;
+
+pub(crate) fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        send_crash_report(info);
+        previous(info);
+    }));
+}
+
+fn send_crash_report(info: &std::panic::PanicInfo) {
+    let mut record = CrashRecord::default();
+
+    let (pc, sp, ra, fp) = registers();
+    record.pc = pc as u64;
+    record.sp = sp as u64;
+    record.ra = ra as u64;
+    record.fp = fp as u64;
+    let top = stack_top(sp);
+    record.frame_count = walk_frames(fp, sp, top, &mut record.frames, &mut record.frame_fps) as u32;
+    record.stack_count = copy_stack(sp, top, &mut record.stack) as u32;
+
+    let name = info.location().map(|l| process_name(l.file())).unwrap_or("").as_bytes();
+    let name = &name[..name.len().min(record.name.len())];
+    record.name[..name.len()].copy_from_slice(name);
+    record.name_length = name.len() as u32;
+
+    let mut wrapper = crate::cursor::BufferWrapper::new(&mut record.message);
+    write!(wrapper, "{}", info).ok(); // truncate if error
+    record.message_length = wrapper.len() as u32;
+
+    let buf = unsafe {
+        xous::MemoryRange::new(&record as *const CrashRecord as usize, core::mem::size_of::<CrashRecord>())
+            .unwrap()
+    };
+    xous::send_message(
+        crate::XOUS_LOGGER_CONNECTION.load(Ordering::Relaxed),
+        xous::Message::new_lend(api::Opcode::CrashReport.to_usize().unwrap(), buf, None, None),
+    )
+    .ok();
+}
+
+/// Guesses the name of the crashing process from the source file of the panic. Crates in this tree
+/// live at e.g. `services/pddb/src/main.rs`; anything else gets the file name.
+fn process_name(file: &str) -> &str {
+    let mut parts = file.split(|c| c == '/' || c == '\\');
+    while let Some(part) = parts.next() {
+        if part == "services" || part == "apps" || part == "libs" {
+            if let Some(name) = parts.next() {
+                return name;
+            }
+        }
+    }
+    file.rsplit(|c| c == '/' || c == '\\').next().unwrap_or(file)
+}
+
+#[cfg(target_arch = "riscv32")]
+#[inline(always)]
+fn registers() -> (usize, usize, usize, usize) {
+    let (pc, sp, ra, fp): (usize, usize, usize, usize);
+    unsafe {
+        core::arch::asm!(
+            "auipc {pc_out}, 0",
+            "mv {sp_out}, sp",
+            "mv {ra_out}, ra",
+            "mv {fp_out}, s0",
+            pc_out = out(reg) pc,
+            sp_out = out(reg) sp,
+            ra_out = out(reg) ra,
+            fp_out = out(reg) fp,
+        );
+    }
+    (pc, sp, ra, fp)
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+fn registers() -> (usize, usize, usize, usize) {
+    (0, 0, 0, 0)
+}
+
+/// The end of the memory above `sp` that can be read without faulting inside the panic hook, as the
+/// kernel reports it for the panicking thread's stack. If the kernel can't say, nothing above `sp` is
+/// read, and the report only has the registers.
+#[cfg(target_arch = "riscv32")]
+fn stack_top(sp: usize) -> usize {
+    match xous::stack_bounds() {
+        Ok(range) if range.as_ptr() as usize <= sp => range.as_ptr() as usize + range.len(),
+        _ => sp,
+    }
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+fn stack_top(_sp: usize) -> usize {
+    0
+}
+
+/// Follows the chain of saved frame pointers up the stack, recording each return address along with
+/// the frame pointer it was found under, so the registers of every caller, and in particular those at
+/// the call to `panic!`, can be worked out from the report. With frame pointers, the return address is
+/// saved just below the frame pointer, and the caller's frame pointer just below that. Without them,
+/// `s0` is an ordinary register, so every step is sanity checked and the walk stops at the first thing
+/// that doesn't look like a frame, or that's outside `sp..top`.
+#[cfg(target_arch = "riscv32")]
+fn walk_frames(fp: usize, sp: usize, top: usize, frames: &mut [u64], fps: &mut [u64]) -> usize {
+    let word = core::mem::size_of::<usize>();
+    let mut fp = fp;
+    let mut count = 0;
+    while count < frames.len() {
+        // the two words read are just below `fp`, so `fp` itself may be the top
+        if fp % word != 0 || fp < sp + 2 * word || fp > top {
+            break;
+        }
+        let (ra, prev) = unsafe {
+            (((fp - word) as *const usize).read_volatile(), ((fp - 2 * word) as *const usize).read_volatile())
+        };
+        if ra == 0 {
+            break;
+        }
+        frames[count] = ra as u64;
+        fps[count] = fp as u64;
+        count += 1;
+        // frames grow down, so the caller's frame is always higher up
+        if prev <= fp {
+            break;
+        }
+        fp = prev;
+    }
+    count
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+fn walk_frames(_fp: usize, _sp: usize, _top: usize, _frames: &mut [u64], _fps: &mut [u64]) -> usize {
+    0
+}
+
+/// Copies the words at the top of the stack, up to `top`
+#[cfg(target_arch = "riscv32")]
+fn copy_stack(sp: usize, top: usize, stack: &mut [u64]) -> usize {
+    let word = core::mem::size_of::<usize>();
+    let count = stack.len().min(top.saturating_sub(sp) / word);
+    for (i, slot) in stack[..count].iter_mut().enumerate() {
+        *slot = unsafe { ((sp + i * word) as *const usize).read_volatile() } as u64;
+    }
+    count
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+fn copy_stack(_sp: usize, _top: usize, _stack: &mut [u64]) -> usize {
+    0
+}
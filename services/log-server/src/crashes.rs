@@ -0,0 +1,38 @@
+//! Crash reports waiting to be picked up, received with `Opcode::CrashReport` and handed out with
+//! `Opcode::TakeCrashReport`.
+//!
+//! The log server is the first service to come up and the last one standing, so it holds on to reports
+//! until the persistence agent in `status` can save them to the PDDB. Only the most recent few are kept:
+//! a process that crashes in a loop shouldn't exhaust the log server's memory.
+use crate::api::CrashRecord;
+use std::collections::VecDeque;
+
+const MAX_PENDING: usize = 4;
+
+pub struct CrashQueue {
+    pending: VecDeque<Box<CrashRecord>>,
+}
+
+impl CrashQueue {
+    pub fn new() -> Self {
+        CrashQueue { pending: VecDeque::new() }
+    }
+
+    /// Queues a copy of `record`. The PID is taken from the message sender, not from the record.
+    pub fn push(&mut self, pid: u32, record: &CrashRecord) {
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front();
+        }
+        let mut copy = Box::new(*record);
+        copy.pid = pid;
+        self.pending.push_back(copy);
+    }
+
+    /// Moves the oldest pending report into `dest`, or sets `dest.pid` to 0 if there is none.
+    pub fn take(&mut self, dest: &mut CrashRecord) {
+        match self.pending.pop_front() {
+            Some(record) => *dest = *record,
+            None => dest.pid = 0,
+        }
+    }
+}
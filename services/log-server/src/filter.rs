@@ -0,0 +1,170 @@
+//! Runtime level filters, set over IPC with `Opcode::SetFilter`.
+//!
+//! Filters are applied here, in the server, rather than in each client: the clients only learn the
+//! most verbose level that applies to them (piggybacked on the return of every `LogRecord`), so that
+//! they start sending records that a filter asks for. Quieting is done entirely on the server side,
+//! because a client that stops logging would never hear about a later change.
+use crate::api::level_filter_from_u32;
+use log::LevelFilter;
+
+const MAX_FILTERS: usize = 32;
+const MAX_MODULE_LEN: usize = 128;
+/// The level clients are assumed to run at. Records from modules that don't match any filter are held
+/// to this when a filter has raised the verbosity of their process, so that raising one module doesn't
+/// flood the log with everything else in the same process.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+#[derive(Copy, Clone)]
+struct Filter {
+    pid: u32,
+    level: LevelFilter,
+    module: [u8; MAX_MODULE_LEN],
+    module_len: usize,
+}
+
+impl Filter {
+    fn module(&self) -> &[u8] {
+        &self.module[..self.module_len]
+    }
+    fn applies_to(&self, pid: u32) -> bool {
+        self.pid == 0 || self.pid == pid
+    }
+    fn matches(&self, pid: u32, module: &[u8]) -> bool {
+        self.applies_to(pid) && module.starts_with(self.module())
+    }
+    /// Sort key for "most specific wins"
+    fn specificity(&self) -> (bool, usize) {
+        (self.pid != 0, self.module_len)
+    }
+}
+
+pub struct Filters {
+    entries: [Option<Filter>; MAX_FILTERS],
+}
+
+impl Filters {
+    pub fn new() -> Self {
+        Filters { entries: [None; MAX_FILTERS] }
+    }
+
+    /// Adds a filter, replacing any filter with the same PID and module. Returns `false` if the table is full.
+    pub fn set(&mut self, pid: u32, module: &[u8], level: u32) -> bool {
+        let module = &module[..module.len().min(MAX_MODULE_LEN)];
+        let mut filter = Filter { pid, level: level_filter_from_u32(level), module: [0u8; MAX_MODULE_LEN], module_len: module.len() };
+        filter.module[..module.len()].copy_from_slice(module);
+
+        if let Some(slot) = self.entries.iter_mut().find(|e| {
+            e.map(|f| f.pid == pid && f.module() == module).unwrap_or(false)
+        }) {
+            *slot = Some(filter);
+            return true;
+        }
+        if let Some(slot) = self.entries.iter_mut().find(|e| e.is_none()) {
+            *slot = Some(filter);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = [None; MAX_FILTERS];
+    }
+
+    /// Whether a record of `level` (a `log::Level` as u32) from `pid`/`module` should be kept.
+    pub fn allows(&self, pid: u32, module: &[u8], level: u32) -> bool {
+        let best = self.entries.iter()
+            .filter_map(|e| e.as_ref())
+            .filter(|f| f.matches(pid, module))
+            .max_by_key(|f| f.specificity());
+        let limit = match best {
+            Some(filter) => filter.level,
+            None => match self.client_level(pid) {
+                Some(raised) if raised > DEFAULT_LEVEL => DEFAULT_LEVEL,
+                _ => return true,
+            },
+        };
+        level as usize <= limit as usize
+    }
+
+    /// The most verbose level any filter asks of `pid`, or `None` if no filter applies to it.
+    pub fn client_level(&self, pid: u32) -> Option<LevelFilter> {
+        self.entries.iter()
+            .filter_map(|e| e.as_ref())
+            .filter(|f| f.applies_to(pid))
+            .map(|f| f.level)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn no_filters_allow_everything() {
+        let filters = Filters::new();
+        assert!(filters.allows(3, b"net::dns", Level::Trace as u32));
+        assert_eq!(filters.client_level(3), None);
+    }
+
+    #[test]
+    fn most_specific_filter_wins() {
+        let mut filters = Filters::new();
+        assert!(filters.set(0, b"", LevelFilter::Error as u32));
+        assert!(filters.set(0, b"net", LevelFilter::Warn as u32));
+        assert!(filters.set(3, b"net::dns", LevelFilter::Debug as u32));
+
+        // a PID match beats a longer module on the wildcard PID, and the longer prefix beats the shorter
+        assert!(filters.allows(3, b"net::dns::resolver", Level::Debug as u32));
+        assert!(!filters.allows(3, b"net::dns::resolver", Level::Trace as u32));
+        assert!(filters.allows(4, b"net::dns", Level::Warn as u32));
+        assert!(!filters.allows(4, b"net::dns", Level::Info as u32));
+        assert!(!filters.allows(4, b"pddb", Level::Warn as u32));
+        assert!(filters.allows(4, b"pddb", Level::Error as u32));
+        // the module is matched as a prefix
+        assert!(!filters.allows(4, b"ne", Level::Warn as u32));
+    }
+
+    #[test]
+    fn raising_one_module_holds_the_rest_of_the_process_to_the_default() {
+        let mut filters = Filters::new();
+        filters.set(5, b"shellchat::cmds", LevelFilter::Trace as u32);
+        assert_eq!(filters.client_level(5), Some(LevelFilter::Trace));
+        assert_eq!(filters.client_level(6), None);
+        assert!(filters.allows(5, b"shellchat::cmds::net", Level::Trace as u32));
+        assert!(filters.allows(5, b"shellchat", Level::Info as u32));
+        assert!(!filters.allows(5, b"shellchat", Level::Debug as u32));
+        // other processes are left alone
+        assert!(filters.allows(6, b"shellchat", Level::Trace as u32));
+    }
+
+    #[test]
+    fn set_replaces_and_fills_up() {
+        let mut filters = Filters::new();
+        filters.set(0, b"net", LevelFilter::Warn as u32);
+        filters.set(0, b"net", LevelFilter::Trace as u32);
+        assert!(filters.allows(1, b"net", Level::Trace as u32));
+
+        for i in 1..MAX_FILTERS {
+            assert!(filters.set(i as u32, b"", LevelFilter::Info as u32));
+        }
+        assert!(!filters.set(0, b"pddb", LevelFilter::Info as u32));
+        // replacing one still works when the table is full
+        assert!(filters.set(0, b"net", LevelFilter::Off as u32));
+
+        filters.clear();
+        assert!(filters.allows(1, b"net", Level::Trace as u32));
+        assert!(filters.set(0, b"pddb", LevelFilter::Info as u32));
+    }
+
+    #[test]
+    fn long_modules_are_truncated() {
+        let mut filters = Filters::new();
+        let long = [b'a'; MAX_MODULE_LEN + 10];
+        filters.set(0, &long, LevelFilter::Error as u32);
+        assert!(!filters.allows(1, &long, Level::Warn as u32));
+        assert!(filters.allows(1, &long[..MAX_MODULE_LEN - 1], Level::Warn as u32));
+    }
+}
@@ -1,10 +1,12 @@
 #![cfg_attr(target_os = "none", no_std)]
 use core::fmt::Write;
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use num_traits::ToPrimitive;
 
 pub mod api;
 mod cursor;
+#[cfg(not(target_os = "none"))]
+mod crash;
 
 #[derive(Debug)]
 pub enum LogError {
@@ -15,6 +17,8 @@ pub enum LogError {
 struct XousLogger;
 static XOUS_LOGGER: XousLogger = XousLogger {};
 static XOUS_LOGGER_CONNECTION: AtomicU32 = AtomicU32::new(0);
+/// The max level this process had before the log server first raised it, biased by 1 (0 = not raised).
+static SAVED_MAX_LEVEL: AtomicUsize = AtomicUsize::new(0);
 
 impl XousLogger {
     fn log_impl(&self, record: &log::Record) {
@@ -51,7 +55,7 @@ impl XousLogger {
             .unwrap()
         };
 
-        xous::send_message(
+        let result = xous::send_message(
             XOUS_LOGGER_CONNECTION.load(Ordering::Relaxed),
             xous::Message::new_lend(
                 crate::api::Opcode::LogRecord.to_usize().unwrap(),
@@ -61,6 +65,9 @@ impl XousLogger {
             ),
         )
         .unwrap();
+        if let xous::Result::MemoryReturned(offset, _valid) = result {
+            apply_server_level(offset.map(|l| api::level_filter_from_u32(l.get() as u32 - 1)));
+        }
     }
 
     fn resume(&self) {
@@ -92,6 +99,8 @@ pub fn init() -> Result<(), LogError> {
     );
     log::set_logger(&XOUS_LOGGER).map_err(|_| LogError::LoggerExists)?;
     log::set_max_level(log::LevelFilter::Info);
+    #[cfg(not(target_os = "none"))]
+    crash::install_panic_hook();
     Ok(())
 }
 
@@ -102,9 +111,130 @@ pub fn init_wait() -> Result<(), ()> {
     );
     log::set_logger(&XOUS_LOGGER).or(Err(()))?;
     log::set_max_level(log::LevelFilter::Info);
+    #[cfg(not(target_os = "none"))]
+    crash::install_panic_hook();
     Ok(())
 }
 
 pub fn resume() {
     XOUS_LOGGER.resume();
 }
+
+/// Follows the level requested by the log server's runtime filters. The server can only raise our
+/// verbosity: it does all the quieting itself, so that we keep sending records and hear about changes.
+fn apply_server_level(requested: Option<log::LevelFilter>) {
+    match requested {
+        Some(level) => {
+            let saved = SAVED_MAX_LEVEL.load(Ordering::Relaxed);
+            let base = if saved == 0 {
+                let current = log::max_level();
+                SAVED_MAX_LEVEL.store(current as usize + 1, Ordering::Relaxed);
+                current
+            } else {
+                api::level_filter_from_u32(saved as u32 - 1)
+            };
+            log::set_max_level(level.max(base));
+        }
+        None => {
+            let saved = SAVED_MAX_LEVEL.swap(0, Ordering::Relaxed);
+            if saved != 0 {
+                log::set_max_level(api::level_filter_from_u32(saved as u32 - 1));
+            }
+        }
+    }
+}
+
+/// Adds or replaces a runtime level filter in the log server. `pid` of `None` applies to every process,
+/// and an empty `module` to every module of the process; otherwise `module` is matched as a prefix of
+/// the module path (e.g. "xous_pddb::backend").
+pub fn set_filter(pid: Option<xous::PID>, module: &str, level: log::LevelFilter) -> Result<(), xous::Error> {
+    let mut filter = api::FilterRecord::default();
+    filter.pid = pid.map(|p| p.get() as u32).unwrap_or(0);
+    filter.level = level as u32;
+    let module = module.as_bytes();
+    if module.len() > filter.module.len() {
+        return Err(xous::Error::OutOfMemory);
+    }
+    filter.module[..module.len()].copy_from_slice(module);
+    filter.module_length = module.len() as u32;
+    let buf = unsafe {
+        xous::MemoryRange::new(
+            &filter as *const api::FilterRecord as usize,
+            core::mem::size_of::<api::FilterRecord>(),
+        )
+        .unwrap()
+    };
+    xous::send_message(
+        XOUS_LOGGER_CONNECTION.load(Ordering::Relaxed),
+        xous::Message::new_lend(api::Opcode::SetFilter.to_usize().unwrap(), buf, None, None),
+    )
+    .map(|_| ())
+}
+
+/// Removes all runtime level filters.
+pub fn clear_filters() -> Result<(), xous::Error> {
+    xous::send_message(
+        XOUS_LOGGER_CONNECTION.load(Ordering::Relaxed),
+        xous::Message::new_scalar(api::Opcode::ClearFilters.to_usize().unwrap(), 0, 0, 0, 0),
+    )
+    .map(|_| ())
+}
+
+/// Calls `f` on every record still in the log server's ring buffer that has a sequence number of at
+/// least `since` and is at least as severe as `level`. Returns the sequence number to pass as `since`
+/// on the next call to pick up where this one left off.
+pub fn for_each_record<F>(since: u64, level: log::Level, mut f: F) -> Result<u64, xous::Error>
+where
+    F: FnMut(&api::RingRecord),
+{
+    let mut page = api::RecordPage::default();
+    let mut next = since;
+    loop {
+        page.since = next;
+        page.level = level as u32;
+        page.count = 0;
+        let buf = unsafe {
+            xous::MemoryRange::new(
+                &mut page as *mut api::RecordPage as usize,
+                core::mem::size_of::<api::RecordPage>(),
+            )
+            .unwrap()
+        };
+        xous::send_message(
+            XOUS_LOGGER_CONNECTION.load(Ordering::Relaxed),
+            xous::Message::new_lend_mut(api::Opcode::GetRecords.to_usize().unwrap(), buf, None, None),
+        )?;
+        let mut offset = 0;
+        for _ in 0..page.count {
+            match api::RingRecord::unpack(&page.data[offset..]) {
+                Some((record, len)) => {
+                    f(&record);
+                    offset += len;
+                }
+                None => break,
+            }
+        }
+        if page.count == 0 || page.next == next {
+            return Ok(page.next.max(next));
+        }
+        next = page.next;
+    }
+}
+
+/// Moves the oldest crash report held by the log server into `record`. Returns `false` if there
+/// were none waiting.
+pub fn take_crash_report(record: &mut api::CrashRecord) -> Result<bool, xous::Error> {
+    record.pid = 0;
+    let buf = unsafe {
+        xous::MemoryRange::new(
+            record as *mut api::CrashRecord as usize,
+            core::mem::size_of::<api::CrashRecord>(),
+        )
+        .unwrap()
+    };
+    xous::send_message(
+        XOUS_LOGGER_CONNECTION.load(Ordering::Relaxed),
+        xous::Message::new_lend_mut(api::Opcode::TakeCrashReport.to_usize().unwrap(), buf, None, None),
+    )?;
+    Ok(record.pid != 0)
+}
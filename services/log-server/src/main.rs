@@ -3,6 +3,9 @@
 
 mod api;
 use api::*;
+mod filter;
+mod ring;
+mod crashes;
 
 #[cfg(any(target_os = "none", target_os = "xous"))]
 #[macro_use]
@@ -302,13 +305,23 @@ fn handle_scalar(
 
 fn handle_opcode(
     output: &mut implementation::OutputWriter,
+    filters: &mut filter::Filters,
+    ring: &mut ring::Ring,
+    crashes: &mut crashes::CrashQueue,
     sender: xous::MessageSender,
     opcode: api::Opcode,
-    message: &xous::Message,
+    message: &mut xous::Message,
 ) {
+    let sender_pid = sender.pid().map(|p| p.get() as u32).unwrap_or(0);
     if let Some(mem) = message.memory_message() {
         match opcode {
             api::Opcode::LogRecord => {
+                // tell the client the most verbose level any filter wants from it, via the (otherwise unused)
+                // offset field of the returned memory. Level is biased by 1 as the field is non-zero.
+                if let xous::Message::Borrow(mem) = message {
+                    mem.offset = filters.client_level(sender_pid).and_then(|l| xous::MemorySize::new(l as usize + 1));
+                }
+                let mem = message.memory_message().unwrap();
                 // This transmute is safe because even if the resulting buffer is garbage,
                 // there are no invalid values in the resulting struct.
                 let lr = unsafe { &*(mem.buf.as_ptr() as *const LogRecord) };
@@ -341,6 +354,11 @@ fn handle_opcode(
 
                 let module_slice = &lr.module[0..lr.module_length as usize];
 
+                if !filters.allows(sender_pid, module_slice, lr.level) {
+                    return;
+                }
+                ring.push(sender_pid, lr.level, module_slice, args_slice);
+
                 write!(output, "{}:", level).ok();
                 for c in module_slice {
                     output.putc(*c);
@@ -384,11 +402,41 @@ fn handle_opcode(
                 output.write_all(buffer).unwrap();
                 // TODO: If the buffer is mutable, set `length` to 0.
             }
+            api::Opcode::SetFilter => {
+                // Safe for the same reason as `LogRecord`: any bit pattern is a valid `FilterRecord`.
+                let fr = unsafe { &*(mem.buf.as_ptr() as *const FilterRecord) };
+                let module_slice = &fr.module[0..(fr.module_length as usize).min(fr.module.len())];
+                if !filters.set(fr.pid, module_slice, fr.level) {
+                    writeln!(output, "LOG: filter table full, ignoring filter from PID {}", sender_pid).ok();
+                }
+            }
+            api::Opcode::GetRecords => {
+                if let Some(mem) = message.memory_message_mut() {
+                    let page = unsafe { &mut *(mem.buf.as_mut_ptr() as *mut RecordPage) };
+                    ring.fill(page);
+                }
+            }
+            api::Opcode::CrashReport => {
+                // Safe for the same reason as `LogRecord`: any bit pattern is a valid `CrashRecord`.
+                let cr = unsafe { &*(mem.buf.as_ptr() as *const CrashRecord) };
+                writeln!(output, "CRASH: report captured for PID {} ({})", sender_pid, cr.name()).ok();
+                crashes.push(sender_pid, cr);
+            }
+            api::Opcode::TakeCrashReport => {
+                if let Some(mem) = message.memory_message_mut() {
+                    let cr = unsafe { &mut *(mem.buf.as_mut_ptr() as *mut CrashRecord) };
+                    crashes.take(cr);
+                }
+            }
             _ => {
                 writeln!(output, "Unhandled opcode").unwrap();
             }
         }
     } else if let Some(scalar) = message.scalar_message() {
+        if opcode == api::Opcode::ClearFilters {
+            filters.clear();
+            return;
+        }
         // Scalar message
         handle_scalar(output, sender, scalar, sender.pid().unwrap());
     }
@@ -401,6 +449,9 @@ fn reader_thread(arg: usize) {
     writeln!(output, "LOG: Server listening on address {:?}", server_addr).unwrap();
 
     println!("LOG: my PID is {}", xous::process::id());
+    let mut filters = filter::Filters::new();
+    let mut ring = ring::Ring::new();
+    let mut crashes = crashes::CrashQueue::new();
     let mut counter: usize = 0;
     loop {
         if counter.trailing_zeros() >= 12 {
@@ -408,10 +459,10 @@ fn reader_thread(arg: usize) {
         }
         counter += 1;
         // writeln!(output, "LOG: Waiting for an event...").unwrap();
-        let envelope = xous::syscall::receive_message(server_addr).expect("couldn't get address");
+        let mut envelope = xous::syscall::receive_message(server_addr).expect("couldn't get address");
         let sender = envelope.sender;
         if let Some(opcode) = FromPrimitive::from_usize(envelope.body.id()) {
-            handle_opcode(output, sender, opcode, &envelope.body);
+            handle_opcode(output, &mut filters, &mut ring, &mut crashes, sender, opcode, &mut envelope.body);
         } else {
             writeln!(
                 output,
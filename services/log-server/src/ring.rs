@@ -0,0 +1,163 @@
+//! An in-RAM ring of the most recent log records, queryable over IPC with `Opcode::GetRecords`.
+//!
+//! Records are identified by a sequence number that counts up from 0 at boot. Long module paths and
+//! messages are truncated to fit a fixed-size slot, so the ring never allocates after startup.
+use crate::api::{RecordPage, RingRecord, RING_ARGS_LEN, RING_MODULE_LEN};
+
+const RING_ENTRIES: usize = 128;
+
+#[derive(Copy, Clone)]
+struct Entry {
+    seq: u64,
+    pid: u32,
+    level: u8,
+    module_len: u8,
+    args_len: u16,
+    module: [u8; RING_MODULE_LEN],
+    args: [u8; RING_ARGS_LEN],
+}
+
+impl Default for Entry {
+    fn default() -> Self {
+        Entry { seq: 0, pid: 0, level: 0, module_len: 0, args_len: 0, module: [0u8; RING_MODULE_LEN], args: [0u8; RING_ARGS_LEN] }
+    }
+}
+
+pub struct Ring {
+    entries: Vec<Entry>,
+    next_seq: u64,
+}
+
+/// Returns the longest prefix of `s` that is at most `max` bytes and doesn't split a UTF-8 character.
+fn utf8_prefix(s: &[u8], max: usize) -> &[u8] {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    // back off over continuation bytes (0b10xx_xxxx)
+    while end > 0 && (s[end] & 0xC0) == 0x80 {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+impl Ring {
+    pub fn new() -> Self {
+        Ring { entries: vec![Entry::default(); RING_ENTRIES], next_seq: 0 }
+    }
+
+    pub fn push(&mut self, pid: u32, level: u32, module: &[u8], args: &[u8]) {
+        let module = utf8_prefix(module, RING_MODULE_LEN);
+        let args = utf8_prefix(args, RING_ARGS_LEN);
+        let entry = &mut self.entries[(self.next_seq % RING_ENTRIES as u64) as usize];
+        entry.seq = self.next_seq;
+        entry.pid = pid;
+        entry.level = level as u8;
+        entry.module_len = module.len() as u8;
+        entry.args_len = args.len() as u16;
+        entry.module[..module.len()].copy_from_slice(module);
+        entry.args[..args.len()].copy_from_slice(args);
+        self.next_seq += 1;
+    }
+
+    /// Packs as many records as fit into `page`, starting at `page.since` and skipping anything less
+    /// severe than `page.level`. Records that have already been overwritten are silently skipped.
+    pub fn fill(&self, page: &mut RecordPage) {
+        let oldest = self.next_seq.saturating_sub(RING_ENTRIES as u64);
+        let mut seq = page.since.max(oldest);
+        let mut offset = 0;
+        page.count = 0;
+        while seq < self.next_seq {
+            let entry = &self.entries[(seq % RING_ENTRIES as u64) as usize];
+            if entry.level as u32 <= page.level {
+                let record = RingRecord {
+                    seq: entry.seq,
+                    pid: entry.pid,
+                    level: entry.level as u32,
+                    module: core::str::from_utf8(&entry.module[..entry.module_len as usize]).unwrap_or("?"),
+                    args: core::str::from_utf8(&entry.args[..entry.args_len as usize]).unwrap_or("<invalid utf-8>"),
+                };
+                match record.pack(&mut page.data[offset..]) {
+                    Some(len) => {
+                        offset += len;
+                        page.count += 1;
+                    }
+                    None => break,
+                }
+            }
+            seq += 1;
+        }
+        page.next = seq;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(ring: &Ring, since: u64, level: u32) -> (Vec<(u64, u32, String, String)>, u64) {
+        let mut page = RecordPage::default();
+        page.since = since;
+        page.level = level;
+        ring.fill(&mut page);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        for _ in 0..page.count {
+            let (record, len) = RingRecord::unpack(&page.data[offset..]).unwrap();
+            records.push((record.seq, record.pid, record.module.to_string(), record.args.to_string()));
+            offset += len;
+        }
+        (records, page.next)
+    }
+
+    #[test]
+    fn records_come_back_in_order() {
+        let mut ring = Ring::new();
+        ring.push(2, 1, b"net", b"first");
+        ring.push(3, 3, b"pddb", b"second");
+        let (records, next) = read(&ring, 0, log::Level::Trace as u32);
+        assert_eq!(records, vec![
+            (0, 2, "net".to_string(), "first".to_string()),
+            (1, 3, "pddb".to_string(), "second".to_string()),
+        ]);
+        assert_eq!(next, 2);
+        // less severe records are skipped, and picking up from `next` finds nothing new
+        assert_eq!(read(&ring, 0, log::Level::Error as u32).0.len(), 1);
+        assert_eq!(read(&ring, next, log::Level::Trace as u32), (vec![], 2));
+    }
+
+    #[test]
+    fn wrap_around_keeps_the_newest() {
+        let mut ring = Ring::new();
+        let total = RING_ENTRIES as u64 + 10;
+        for i in 0..total {
+            ring.push(1, 1, b"m", i.to_string().as_bytes());
+        }
+        let mut seqs = Vec::new();
+        let mut since = 0;
+        loop {
+            let (records, next) = read(&ring, since, log::Level::Trace as u32);
+            if records.is_empty() {
+                break;
+            }
+            for (seq, _, _, args) in records {
+                assert_eq!(args, seq.to_string());
+                seqs.push(seq);
+            }
+            since = next;
+        }
+        // the overwritten records are skipped without a gap in what's left
+        assert_eq!(seqs, (10..total).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn long_records_are_cut_on_a_character_boundary() {
+        let mut ring = Ring::new();
+        let args = "é".repeat(RING_ARGS_LEN);
+        ring.push(1, 1, b"m", args.as_bytes());
+        let (records, _) = read(&ring, 0, log::Level::Trace as u32);
+        let kept = &records[0].3;
+        assert!(kept.len() <= RING_ARGS_LEN && kept.len() >= RING_ARGS_LEN - 1);
+        assert!(args.starts_with(kept.as_str()));
+    }
+}
@@ -1,7 +1,7 @@
 pub(crate) mod ping;
 pub(crate) use ping::*;
 pub(crate) mod tcp;
-pub use ping::NetPingCallback;
+pub use ping::{NetPingCallback, NET_PING_IPV6};
 // needed to keep hosted mode quiet, since the Tcp implementation is a bodge
 #[allow(unused_imports)]
 pub(crate) use tcp::*;
@@ -9,20 +9,30 @@ pub(crate) use tcp::*;
 use com::SsidRecord;
 use rkyv::{Archive, Deserialize, Serialize};
 use smoltcp::wire::IpAddress;
-use std::convert::TryInto;
 use std::fmt;
 use std::fmt::Debug;
 use std::io::Write;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use xous_semver::SemVer;
 
-// republish this so we can decode the icmpv4 error codes
-pub use smoltcp::wire::Icmpv4DstUnreachable;
+// republish these so we can decode the icmp error codes
+pub use smoltcp::wire::{Icmpv4DstUnreachable, Icmpv6DstUnreachable};
 
 // note: this name cannot be changed, because it is baked into `libstd`
 pub(crate) const SERVER_NAME_NET: &str = "_Middleware Network Server_";
 #[allow(dead_code)]
 pub const AP_DICT_NAME: &'static str = "wlan.networks";
+/// the settings of the networks in `AP_DICT_NAME`, under the same keys; see `profiles`
+#[allow(dead_code)]
+pub const AP_PROFILE_DICT_NAME: &'static str = "wlan.profiles";
+/// where packet captures are saved, as pcap files, under the names they're given
+#[allow(dead_code)]
+pub const CAPTURE_DICT_NAME: &'static str = "net.capture";
+#[allow(dead_code)]
+pub const CAPTURE_DEFAULT_RING_SIZE: usize = 64 * 1024;
+/// the capture lives in the net service's heap, so it can't be allowed to grow without bound
+#[allow(dead_code)]
+pub const CAPTURE_MAX_RING_SIZE: usize = 1024 * 1024;
 
 #[allow(dead_code)]
 /// minimum revision required for compatibility with Net crate
@@ -357,6 +367,141 @@ pub(crate) enum Opcode {
     StdTcpAccept = 45,
 
     StdTcpStreamShutdown = 46,
+
+    /// Link-local and SLAAC addresses, router and DNS server of the IPv6 side of the interface
+    GetIpv6Config = 47,
+
+    /// Sets the name that the mDNS responder answers to, as `<name>.local`. The name is a
+    /// `xous_ipc::String<64>` in a `MutableBorrow`, which is replaced with a `NetMemResponse`.
+    MdnsSetName = 48,
+
+    /// Advertises an `MdnsService` over DNS-SD, or updates it if its service type is already
+    /// advertised. Sent as a `MutableBorrow`, which is replaced with a `NetMemResponse`.
+    MdnsRegisterService = 49,
+
+    /// Stops advertising a service type, given as a `xous_ipc::String<64>` in a `MutableBorrow`,
+    /// which is replaced with a `NetMemResponse`.
+    MdnsUnregisterService = 50,
+
+    /// Saves the WireGuard configuration, in the `wg-quick` format as a `xous_ipc::String<1024>` in a
+    /// `MutableBorrow`, which is replaced with a `NetMemResponse`. The tunnel is brought up again with it
+    /// if it's enabled.
+    WireguardSetConfig = 51,
+    /// Brings the WireGuard tunnel up (arg1 = 1) or down (arg1 = 0), and remembers which. Blocking
+    /// scalar; returns 1 if it worked.
+    WireguardEnable = 52,
+    /// How the WireGuard tunnel is doing; a `WireguardStatus` in a `MutableBorrow`
+    WireguardStatus = 53,
+    /// Our WireGuard public key, base64 encoded for the peer's configuration, as a
+    /// `xous_ipc::String<64>` in a `MutableBorrow`. It's empty if the PDDB isn't mounted yet.
+    WireguardPublicKey = 54,
+    /// Internal: the connection manager has changed what the tunnel is to be
+    WireguardUpdate = 55,
+    /// Internal: the connection manager has joined a network that has a static address, so there's no
+    /// DHCP to wait on
+    WlanStaticConfig = 56,
+    /// Starts capturing the frames that go through the device, with a `CaptureConfig` in a `MutableBorrow`,
+    /// which is replaced with a `NetMemResponse`. Whatever was captured before is thrown away.
+    CaptureStart = 57,
+    /// Stops the capture, keeping what was captured. Blocking scalar; returns the number of frames kept.
+    CaptureStop = 58,
+    /// How the capture is doing; a `CaptureStatus` in a `MutableBorrow`
+    CaptureStatus = 59,
+    /// Writes out what's been captured as a pcap file: into `CAPTURE_DICT_NAME` under the name given as a
+    /// `xous_ipc::String<64>` in a `MutableBorrow`, or, if the name is empty, to the log as base64. The
+    /// buffer is replaced with a `NetMemResponse`.
+    CaptureExport = 60,
+}
+
+/// Where the WireGuard tunnel is at
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum WireguardState {
+    Disabled,
+    /// it's enabled, but there's no configuration (or the PDDB isn't mounted)
+    Unconfigured,
+    /// there's no address on the LAN to reach the peer from
+    WaitingForNetwork,
+    /// a handshake is under way
+    Connecting,
+    Connected,
+    /// there's no session, and nothing to send that needs one
+    Idle,
+}
+/// The state of the WireGuard tunnel, for the status bar and the shell. Addresses are in network byte order.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct WireguardStatus {
+    pub state: WireguardState,
+    /// our address inside the tunnel
+    pub address: Option<[u8; 4]>,
+    /// the peer, where we last heard from it
+    pub endpoint: Option<[u8; 4]>,
+    pub endpoint_port: u16,
+    /// seconds since the last handshake
+    pub last_handshake_secs: Option<u32>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+impl Default for WireguardStatus {
+    fn default() -> Self {
+        WireguardStatus {
+            state: WireguardState::Disabled,
+            address: None,
+            endpoint: None,
+            endpoint_port: 0,
+            last_handshake_secs: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+        }
+    }
+}
+
+/// What to capture; see `capture::Filter` for what goes in the filter
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct CaptureConfig {
+    pub filter: xous_ipc::String<128>,
+    /// the most the capture may hold, in bytes of pcap file
+    pub ring_size: u32,
+}
+/// The state of the packet capture
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
+pub struct CaptureStatus {
+    pub running: bool,
+    pub filter: xous_ipc::String<128>,
+    pub ring_size: u32,
+    /// frames held, and the size of the pcap records they make up
+    pub frames: u32,
+    pub bytes: u32,
+    /// frames that matched the filter since the capture was started
+    pub seen: u32,
+    /// frames that were pushed out of the ring to make room for newer ones
+    pub dropped: u32,
+}
+
+/// A service for the mDNS responder to advertise. Its instance name is the device's name, so it shows up
+/// as `<name>.<service>.local`.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct MdnsService {
+    /// the service type and protocol, e.g. `_http._tcp`
+    pub service: xous_ipc::String<64>,
+    pub port: u16,
+    /// `key=value` entries for the TXT record
+    pub txt: [Option<xous_ipc::String<64>>; 4],
+}
+
+/// The IPv6 configuration of the interface. Addresses are in network byte order.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
+pub struct Ipv6Conf {
+    /// the fe80::/64 address derived from the MAC; this is always there
+    pub link_local: [u8; 16],
+    /// the address made by SLAAC from a prefix that a router advertised
+    pub global: Option<[u8; 16]>,
+    pub prefix_len: u8,
+    /// seconds until `global` stops being valid; `u32::MAX` is forever
+    pub valid_secs: u32,
+    /// the default router
+    pub gateway: Option<[u8; 16]>,
+    /// the DNS server from the router's RDNSS option, if it gave one
+    pub dns: Option<[u8; 16]>,
 }
 
 #[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
@@ -495,16 +640,7 @@ impl From<NetIpAddr> for IpAddress {
             NetIpAddr::Ipv4([a, b, c, d]) => {
                 IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(a, b, c, d))
             }
-            NetIpAddr::Ipv6(ipv6) => IpAddress::Ipv6(smoltcp::wire::Ipv6Address::new(
-                u16::from_be_bytes(ipv6[0..1].try_into().unwrap()),
-                u16::from_be_bytes(ipv6[2..3].try_into().unwrap()),
-                u16::from_be_bytes(ipv6[4..5].try_into().unwrap()),
-                u16::from_be_bytes(ipv6[6..7].try_into().unwrap()),
-                u16::from_be_bytes(ipv6[8..9].try_into().unwrap()),
-                u16::from_be_bytes(ipv6[10..11].try_into().unwrap()),
-                u16::from_be_bytes(ipv6[12..13].try_into().unwrap()),
-                u16::from_be_bytes(ipv6[14..15].try_into().unwrap()),
-            )),
+            NetIpAddr::Ipv6(ipv6) => IpAddress::Ipv6(smoltcp::wire::Ipv6Address(ipv6)),
         }
     }
 }
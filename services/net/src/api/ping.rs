@@ -2,9 +2,10 @@ use rkyv::{Archive, Deserialize, Serialize};
 use crate::api::*;
 
 /// Scalar responses to pings have the following format:
-/// arg1: bottom byte = NetPingCallback as below; top byte = DstUnreachable code as u8
-/// arg2: remote IP address hint (IPv4 is full address; IPv6 is just bottom 4 bytes)
-/// arg3: sequence number (if echo response) or top 4 bytes of an IPv6 address (if reporting timeout or drop)
+/// arg1: bottom byte = NetPingCallback as below; `NET_PING_IPV6` is set if the remote is an IPv6 address;
+///       top byte = DstUnreachable code as u8 (`Icmpv6DstUnreachable` for IPv6, `Icmpv4DstUnreachable` otherwise)
+/// arg2: remote IP address hint (IPv4 is full address; IPv6 is just the bottom 4 bytes, in network order)
+/// arg3: sequence number (if echo response or timeout)
 /// arg4: elapsed time
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum NetPingCallback {
@@ -19,6 +20,9 @@ pub enum NetPingCallback {
     Drop,
 }
 
+/// Flag in arg1 of a ping callback, set when the remote is an IPv6 address
+pub const NET_PING_IPV6: usize = 0x100;
+
 //////// Intra-crate Ping structures
 #[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
 pub(crate) struct NetPingPacket {
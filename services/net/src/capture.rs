@@ -0,0 +1,325 @@
+//! Packet capture. While it's running, the frames that go through `NetPhy` in either direction and
+//! match the filter are copied into a ring that holds at most `ring_size` bytes of pcap records; when
+//! it's full, the oldest frames go to make room. What's in the ring can be exported as a pcap file,
+//! with Ethernet framing, for Wireshark and friends.
+//!
+//! Frames that go around through the loopback or the WireGuard tunnel show up as they leave and as
+//! they come back in, and the tunnel's traffic shows up both before it's encrypted and after.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Write};
+use std::net::IpAddr;
+
+use smoltcp::time::Instant;
+
+use crate::api::{CAPTURE_DICT_NAME, CAPTURE_MAX_RING_SIZE};
+
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+const LINKTYPE_ETHERNET: u32 = 1;
+/// bytes of pcap per line of log, which come out as 76 characters of base64
+const LOG_CHUNK: usize = 57;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// from the network to the stack
+    Rx,
+    /// from the stack to the network
+    Tx,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Protocol {
+    Icmp,
+    Tcp,
+    Udp,
+}
+
+/// Which frames to keep. Every term that's given has to match.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filter {
+    direction: Option<Direction>,
+    ethertype: Option<u16>,
+    protocol: Option<Protocol>,
+    port: Option<u16>,
+    host: Option<IpAddr>,
+}
+impl Filter {
+    /// Space separated terms, after tcpdump: `in`, `out`, `arp`, `ip`, `ip6`, `icmp`, `tcp`, `udp`,
+    /// `port <n>` (TCP or UDP, either end) and `host <address>` (either end). Nothing at all matches
+    /// every frame. Returns `None` if there's a term it doesn't know.
+    pub fn parse(text: &str) -> Option<Filter> {
+        let mut filter = Filter::default();
+        let mut terms = text.split_whitespace();
+        while let Some(term) = terms.next() {
+            match term {
+                "in" => filter.direction = Some(Direction::Rx),
+                "out" => filter.direction = Some(Direction::Tx),
+                "arp" => filter.ethertype = Some(ETHERTYPE_ARP),
+                "ip" => filter.ethertype = Some(ETHERTYPE_IPV4),
+                "ip6" => filter.ethertype = Some(ETHERTYPE_IPV6),
+                "icmp" => filter.protocol = Some(Protocol::Icmp),
+                "tcp" => filter.protocol = Some(Protocol::Tcp),
+                "udp" => filter.protocol = Some(Protocol::Udp),
+                "port" => filter.port = Some(terms.next()?.parse().ok()?),
+                "host" => filter.host = Some(terms.next()?.parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(filter)
+    }
+
+    pub fn matches(&self, direction: Direction, frame: &[u8]) -> bool {
+        if self.direction.map_or(false, |d| d != direction) {
+            return false;
+        }
+        if frame.len() < 14 {
+            return false;
+        }
+        let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+        if self.ethertype.map_or(false, |e| e != ethertype) {
+            return false;
+        }
+        if self.protocol.is_none() && self.port.is_none() && self.host.is_none() {
+            return true;
+        }
+        let payload = &frame[14..];
+        // the protocol, the two ends, and what the protocol carries; IPv6 extension headers aren't followed
+        let (protocol, src, dst, l4) = match ethertype {
+            ETHERTYPE_IPV4 if payload.len() >= 20 => {
+                let header_len = (payload[0] & 0xf) as usize * 4;
+                let addr = |at: usize| IpAddr::from([payload[at], payload[at + 1], payload[at + 2], payload[at + 3]]);
+                (payload[9], addr(12), addr(16), payload.get(header_len..).unwrap_or(&[]))
+            }
+            ETHERTYPE_IPV6 if payload.len() >= 40 => {
+                let addr = |at: usize| {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(&payload[at..at + 16]);
+                    IpAddr::from(octets)
+                };
+                (payload[6], addr(8), addr(24), &payload[40..])
+            }
+            // ARP only has the two ends
+            ETHERTYPE_ARP if payload.len() >= 28 && self.protocol.is_none() && self.port.is_none() => {
+                let addr = |at: usize| IpAddr::from([payload[at], payload[at + 1], payload[at + 2], payload[at + 3]]);
+                return self.host.map_or(true, |host| host == addr(14) || host == addr(24));
+            }
+            _ => return false,
+        };
+        let protocol = match protocol {
+            1 | 58 => Some(Protocol::Icmp),
+            6 => Some(Protocol::Tcp),
+            17 => Some(Protocol::Udp),
+            _ => None,
+        };
+        if self.protocol.is_some() && self.protocol != protocol {
+            return false;
+        }
+        if let Some(port) = self.port {
+            if !(protocol == Some(Protocol::Tcp) || protocol == Some(Protocol::Udp)) || l4.len() < 4 {
+                return false;
+            }
+            if port != u16::from_be_bytes([l4[0], l4[1]]) && port != u16::from_be_bytes([l4[2], l4[3]]) {
+                return false;
+            }
+        }
+        self.host.map_or(true, |host| host == src || host == dst)
+    }
+}
+
+struct Record {
+    /// microseconds since the Unix epoch
+    time: u64,
+    frame: Vec<u8>,
+}
+
+pub struct Capture {
+    filter: Filter,
+    /// what the filter was made from, to report back
+    filter_text: String,
+    ring_size: usize,
+    records: VecDeque<Record>,
+    /// the size of `records` as pcap records
+    held: usize,
+    /// frames that matched the filter
+    seen: u32,
+    /// frames that were pushed out of the ring to make room, or were too big for it
+    dropped: u32,
+    running: bool,
+    /// what to add to the stack's timestamps to get the time since the Unix epoch, in microseconds
+    epoch_offset: i64,
+}
+impl Capture {
+    /// Starts a capture. `now` is the stack's idea of the time, and `unix_micros` is the time of day
+    /// at the same moment, which the frames' timestamps are worked out from.
+    pub fn new(filter_text: &str, ring_size: usize, now: Instant, unix_micros: u64) -> Option<Capture> {
+        if ring_size <= PCAP_RECORD_HEADER_LEN || ring_size > CAPTURE_MAX_RING_SIZE {
+            return None;
+        }
+        Some(Capture {
+            filter: Filter::parse(filter_text)?,
+            filter_text: filter_text.trim().to_string(),
+            ring_size,
+            records: VecDeque::new(),
+            held: 0,
+            seen: 0,
+            dropped: 0,
+            running: true,
+            epoch_offset: unix_micros as i64 - now.total_micros(),
+        })
+    }
+
+    pub fn record(&mut self, direction: Direction, frame: &[u8], timestamp: Instant) {
+        if !self.running || !self.filter.matches(direction, frame) {
+            return;
+        }
+        self.seen = self.seen.wrapping_add(1);
+        let size = PCAP_RECORD_HEADER_LEN + frame.len();
+        if size > self.ring_size {
+            self.dropped = self.dropped.wrapping_add(1);
+            return;
+        }
+        while self.held + size > self.ring_size {
+            match self.records.pop_front() {
+                Some(oldest) => {
+                    self.held -= PCAP_RECORD_HEADER_LEN + oldest.frame.len();
+                    self.dropped = self.dropped.wrapping_add(1);
+                }
+                None => break,
+            }
+        }
+        self.records.push_back(Record {
+            time: (timestamp.total_micros() + self.epoch_offset).max(0) as u64,
+            frame: frame.to_vec(),
+        });
+        self.held += size;
+    }
+
+    /// Stops taking frames in; what's been captured stays until the next capture is started
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    pub fn status(&self) -> crate::api::CaptureStatus {
+        crate::api::CaptureStatus {
+            running: self.running,
+            filter: xous_ipc::String::<128>::from_str(&self.filter_text),
+            ring_size: self.ring_size as u32,
+            frames: self.records.len() as u32,
+            bytes: self.held as u32,
+            seen: self.seen,
+            dropped: self.dropped,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// What's in the ring, as a pcap file
+    pub fn to_pcap(&self) -> Vec<u8> {
+        let mut pcap = Vec::with_capacity(PCAP_HEADER_LEN + self.held);
+        pcap.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        pcap.extend_from_slice(&2u16.to_le_bytes());
+        pcap.extend_from_slice(&4u16.to_le_bytes());
+        // the time zone and the accuracy of the timestamps, which nobody fills in
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&(u16::MAX as u32).to_le_bytes());
+        pcap.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for record in self.records.iter() {
+            pcap.extend_from_slice(&((record.time / 1_000_000) as u32).to_le_bytes());
+            pcap.extend_from_slice(&((record.time % 1_000_000) as u32).to_le_bytes());
+            pcap.extend_from_slice(&(record.frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&(record.frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&record.frame);
+        }
+        pcap
+    }
+}
+
+/// Saves a pcap file into `CAPTURE_DICT_NAME`, replacing whatever had the same name
+pub fn save(pddb: &pddb::Pddb, name: &str, pcap: &[u8]) -> std::io::Result<()> {
+    match pddb.delete_key(CAPTURE_DICT_NAME, name, None) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let mut key = pddb.get(CAPTURE_DICT_NAME, name, None, true, true, Some(pcap.len()), None::<fn()>)?;
+    key.write_all(pcap)?;
+    pddb.sync()
+}
+
+/// Writes a pcap file to the log, which is the serial console on hardware and the terminal in hosted
+/// mode, as numbered lines of base64. `tools/pcap_from_log.py` puts it back together on the host.
+pub fn log_pcap(pcap: &[u8]) {
+    log::info!("pcap begin: {} bytes", pcap.len());
+    for (index, chunk) in pcap.chunks(LOG_CHUNK).enumerate() {
+        log::info!("pcap {}: {}", index, base64::encode(chunk));
+    }
+    log::info!("pcap end");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp_frame(src: [u8; 4], dst: [u8; 4], src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let mut ipv4 = [0u8; 20];
+        ipv4[0] = 0x45;
+        ipv4[9] = 17;
+        ipv4[12..16].copy_from_slice(&src);
+        ipv4[16..20].copy_from_slice(&dst);
+        frame.extend_from_slice(&ipv4);
+        frame.extend_from_slice(&src_port.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame
+    }
+
+    #[test]
+    fn filter_terms_all_have_to_match() {
+        let dns = udp_frame([10, 0, 0, 2], [10, 0, 0, 1], 49152, 53);
+        assert!(Filter::parse("").unwrap().matches(Direction::Rx, &dns));
+        assert!(Filter::parse("udp port 53").unwrap().matches(Direction::Tx, &dns));
+        assert!(Filter::parse("ip host 10.0.0.1").unwrap().matches(Direction::Tx, &dns));
+        assert!(!Filter::parse("out udp port 53").unwrap().matches(Direction::Rx, &dns));
+        assert!(!Filter::parse("tcp port 53").unwrap().matches(Direction::Tx, &dns));
+        assert!(!Filter::parse("port 80").unwrap().matches(Direction::Tx, &dns));
+        assert!(!Filter::parse("ip6").unwrap().matches(Direction::Tx, &dns));
+        assert!(!Filter::parse("host 10.0.0.3").unwrap().matches(Direction::Tx, &dns));
+        assert_eq!(Filter::parse("port"), None);
+        assert_eq!(Filter::parse("host example.com"), None);
+        assert_eq!(Filter::parse("udp or tcp"), None);
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_frames() {
+        let frame = udp_frame([10, 0, 0, 2], [10, 0, 0, 1], 49152, 53);
+        let size = PCAP_RECORD_HEADER_LEN + frame.len();
+        let mut capture = Capture::new("udp", size * 2, Instant::from_millis(1_000), 1_650_000_000_000_000).unwrap();
+        for i in 0..3 {
+            capture.record(Direction::Tx, &frame, Instant::from_millis(1_000 + i));
+        }
+        let status = capture.status();
+        assert_eq!((status.frames, status.seen, status.dropped), (2, 3, 1));
+
+        let pcap = capture.to_pcap();
+        assert_eq!(pcap.len(), PCAP_HEADER_LEN + size * 2);
+        assert_eq!(pcap[..4], [0xd4, 0xc3, 0xb2, 0xa1]);
+        // the first frame that's left was the second one recorded, a millisecond after the capture started
+        let first = &pcap[PCAP_HEADER_LEN..];
+        assert_eq!(u32::from_le_bytes([first[0], first[1], first[2], first[3]]), 1_650_000_000);
+        assert_eq!(u32::from_le_bytes([first[4], first[5], first[6], first[7]]), 1_000);
+        assert_eq!(first[16..size], frame[..]);
+
+        capture.stop();
+        capture.record(Direction::Tx, &frame, Instant::from_millis(2_000));
+        assert_eq!(capture.status().seen, 3);
+    }
+}
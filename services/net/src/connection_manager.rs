@@ -1,9 +1,11 @@
 use crate::api::*;
-use std::sync::Arc;
+use crate::wireguard;
+use std::sync::{Arc, Mutex};
 use core::sync::atomic::{AtomicU32, AtomicBool, Ordering};
 use com::{WlanStatus, WlanStatusIpc, SsidRecord};
 use com_rs_ref::{ConnectResult, LinkState};
 use net::MIN_EC_REV;
+use net::profiles::{self, WlanProfile};
 use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack, send_message, try_send_message, Message};
 use xous_ipc::Buffer;
 use num_traits::*;
@@ -30,6 +32,12 @@ pub(crate) enum ConnectionManagerOpcode {
     ComInt,
     SuspendResume,
     EcReset,
+    /// the PDDB is mounted, so the WireGuard settings can be read
+    WireguardMounted,
+    WireguardSetConfig,
+    WireguardEnable,
+    WireguardStatus,
+    WireguardPublicKey,
     Quit,
 }
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
@@ -56,12 +64,44 @@ enum SsidScanState {
     Scanning,
 }
 
-pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU32>) {
+/// What the WireGuard tunnel is to be, as far as the settings in the PDDB go
+struct WireguardSettings {
+    /// `None` until the PDDB is mounted
+    enabled: Option<bool>,
+    config: Option<wireguard::Config>,
+    /// ours, from the PDDB; a key in the configuration takes precedence
+    private_key: Option<[u8; 32]>,
+}
+impl WireguardSettings {
+    fn secret(&self) -> Option<[u8; 32]> {
+        self.config.as_ref().and_then(|config| config.private_key).or(self.private_key)
+    }
+    /// Tells the main loop to bring the tunnel up with the settings, or down if they say it isn't to be up
+    fn apply(&self, shared: &Mutex<wireguard::Shared>, net_conn: xous::CID) {
+        let request = match (self.enabled, self.config.as_ref(), self.secret()) {
+            (Some(true), Some(config), Some(secret)) => Some((config.clone(), secret)),
+            _ => None,
+        };
+        shared.lock().unwrap().request = request;
+        // not blocking: the main loop can be waiting on us
+        send_message(net_conn, Message::new_scalar(Opcode::WireguardUpdate.to_usize().unwrap(), 0, 0, 0, 0))
+            .expect("couldn't send WireGuard update");
+    }
+}
+
+pub(crate) fn connection_manager(
+    sid: xous::SID,
+    activity_interval: Arc<AtomicU32>,
+    wlan_profile: Arc<Mutex<Option<WlanProfile>>>,
+    wg_shared: Arc<Mutex<wireguard::Shared>>,
+    net_conn: xous::CID,
+) {
     let tt = ticktimer_server::Ticktimer::new().unwrap();
     let xns = xous_names::XousNames::new().unwrap();
     let mut com = com::Com::new(&xns).unwrap();
     let netmgr = net::NetManager::new();
     let pddb = pddb::Pddb::new();
+    let mut trng = trng::Trng::new(&xns).unwrap();
     let self_cid = xous::connect(sid).unwrap();
     // give the system some time to boot before trying to run a check on the EC minimum version, as it is in reset on boot
     tt.sleep_ms(POLL_INTERVAL_MS).unwrap();
@@ -87,6 +127,20 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
     let mut ssid_attempted = HashSet::<String>::new();
     let mut wait_count = 0;
     let mut scan_count = 0;
+    let mut wg_settings = WireguardSettings { enabled: None, config: None, private_key: None };
+    // a network with a static address isn't waited on for DHCP
+    let joined_static = || wlan_profile.lock().unwrap().as_ref().map_or(false, |p| p.static_ipv4.is_some());
+
+    // the WireGuard settings are read once the PDDB is mounted
+    let _ = std::thread::spawn({
+        let main_cid = self_cid.clone();
+        move || {
+            let pddb = pddb::Pddb::new();
+            pddb.is_mounted_blocking();
+            send_message(main_cid, Message::new_scalar(ConnectionManagerOpcode::WireguardMounted.to_usize().unwrap(), 0, 0, 0, 0))
+                .expect("couldn't send PDDB mount notification");
+        }
+    });
 
     let run_sid = xous::create_server().unwrap();
     let run_cid = xous::connect(run_sid).unwrap();
@@ -226,7 +280,14 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                                 ConnectResult::Success => {
                                     scan_state = SsidScanState::Idle;
                                     activity_interval.store(0, Ordering::SeqCst);
-                                    WifiState::WaitDhcp
+                                    if joined_static() {
+                                        send_message(net_conn, Message::new_scalar(Opcode::WlanStaticConfig.to_usize().unwrap(), 0, 0, 0, 0))
+                                            .expect("couldn't send static config");
+                                        note_connected(&pddb, &wlan_profile);
+                                        WifiState::Connected
+                                    } else {
+                                        WifiState::WaitDhcp
+                                    }
                                 },
                                 ConnectResult::NoMatchingAp => WifiState::InvalidAp,
                                 ConnectResult::Timeout => WifiState::Retry,
@@ -279,7 +340,10 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                                 let buf = Buffer::into_buf(com::WlanStatusIpc::from_status(wifi_stats_cache)).or(Err(xous::Error::InternalError)).unwrap();
                                 buf.send(sub, WifiStateCallback::Update.to_u32().unwrap()).or(Err(xous::Error::InternalError)).unwrap();
                             }
-                            if wifi_stats_cache.ipv4.dhcp == com_rs_ref::DhcpState::Bound {
+                            if wifi_stats_cache.ipv4.dhcp == com_rs_ref::DhcpState::Bound || joined_static() {
+                                if wifi_state != WifiState::Connected {
+                                    note_connected(&pddb, &wlan_profile);
+                                }
                                 wifi_state = WifiState::Connected;
                             } else {
                                 wifi_state = WifiState::WaitDhcp;
@@ -313,7 +377,7 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                                 }
                                 log::info!("Link state mismatch: moving state to disconnected ({:?})", wifi_stats_cache.link_state);
                                 netmgr.reset();
-                            } else if wifi_stats_cache.ipv4.dhcp != com_rs_ref::DhcpState::Bound {
+                            } else if wifi_stats_cache.ipv4.dhcp != com_rs_ref::DhcpState::Bound && !joined_static() {
                                 log::info!("DHCP state mismatch: moving state to disconnected ({:?})", wifi_stats_cache.ipv4.dhcp);
                                 netmgr.reset();
                             }
@@ -330,22 +394,21 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                             }
                         }
 
-                        if let Ok(ap_list_vec) = pddb.list_keys(AP_DICT_NAME, None) {
-                            let mut ap_list = HashSet::<String>::new();
-                            for ap in ap_list_vec {
-                                ap_list.insert(ap);
-                            }
+                        if let Ok(saved) = profiles::list(&pddb) {
                             match wifi_state {
                                 WifiState::Unknown | WifiState::Disconnected | WifiState::InvalidAp | WifiState::InvalidAuth => {
                                     if (scan_state == SsidScanState::Idle) || scan_count > SCAN_COUNT_MAX {
                                         scan_count = 0;
                                         // wait until we're done scanning before trying to connect
-                                        if let Some(ssid) = get_next_ssid(&mut ssid_list, &mut ssid_attempted, ap_list) {
+                                        if let Some(profile) = get_next_profile(&ssid_list, &mut ssid_attempted, saved) {
+                                            let ssid = profile.ssid.clone();
                                             let mut wpa_pw_file = pddb.get(AP_DICT_NAME, &ssid, None, false, false, None, Some(||{})).expect("couldn't retrieve AP password");
                                             let mut wp_pw_raw = [0u8; com::api::WF200_PASS_MAX_LEN];
                                             if let Ok(readlen) = wpa_pw_file.read(&mut wp_pw_raw) {
                                                 let pw = std::str::from_utf8(&wp_pw_raw[..readlen]).expect("password was not valid utf-8");
                                                 log::info!("Attempting wifi connection: {}", ssid);
+                                                // the main loop takes the address and DNS settings from the profile
+                                                *wlan_profile.lock().unwrap() = Some(profile);
                                                 com.wlan_set_ssid(&ssid).expect("couldn't set SSID");
                                                 com.wlan_set_pass(pw).expect("couldn't set password");
                                                 com.wlan_join().expect("couldn't issue join command");
@@ -353,7 +416,7 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                                             }
                                         } else {
                                             // no SSIDs available, scan again
-                                            log::info!("No SSIDs found, restarting SSID scan...");
+                                            log::info!("No SSIDs left to try, restarting SSID scan...");
                                             com.set_ssid_scanning(true).unwrap();
                                             scan_state = SsidScanState::Scanning;
                                         }
@@ -466,6 +529,8 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
             }),
             Some(ConnectionManagerOpcode::Stop) => msg_scalar_unpack!(msg, _, _, _, _, {
                 run.store(false, Ordering::SeqCst);
+                // whatever gets joined by hand has nothing to do with the profile of the network we joined last
+                *wlan_profile.lock().unwrap() = None;
             }),
             Some(ConnectionManagerOpcode::EcReset) => msg_scalar_unpack!(msg, _, _, _, _, {
                 // this opcode is used by other processes to inform us that the net link was reset by something other than us.
@@ -477,6 +542,98 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                 intervals_without_activity = 0;
                 scan_count = 0;
             }),
+            Some(ConnectionManagerOpcode::WireguardMounted) => msg_scalar_unpack!(msg, _, _, _, _, {
+                wg_settings.enabled = Some(
+                    wireguard::read_setting(&pddb, wireguard::WG_ENABLED_KEY).map_or(false, |v| v == b"1")
+                );
+                wg_settings.config = wireguard::read_setting(&pddb, wireguard::WG_CONFIG_KEY).and_then(|text| {
+                    match wireguard::Config::parse(&String::from_utf8_lossy(&text)) {
+                        Ok(config) => Some(config),
+                        Err(e) => {
+                            log::error!("the saved WireGuard configuration is no good: {}", e);
+                            None
+                        }
+                    }
+                });
+                wg_settings.private_key = wireguard::private_key(&pddb, &mut trng);
+                if wg_settings.enabled == Some(true) {
+                    wg_settings.apply(&wg_shared, net_conn);
+                }
+            }),
+            Some(ConnectionManagerOpcode::WireguardSetConfig) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let ipc_text = buffer.to_original::<xous_ipc::String<1024>, _>().unwrap();
+                let text = ipc_text.as_str().unwrap_or("");
+                let response = match wireguard::Config::parse(text) {
+                    Err(e) => {
+                        log::warn!("WireGuard configuration rejected: {}", e);
+                        NetMemResponse::Invalid
+                    }
+                    Ok(_) if wg_settings.enabled.is_none() => NetMemResponse::AccessDenied,
+                    Ok(config) => match wireguard::write_setting(&pddb, wireguard::WG_CONFIG_KEY, Some(text.as_bytes())) {
+                        Ok(_) => {
+                            wg_settings.config = Some(config);
+                            if wg_settings.enabled == Some(true) {
+                                wg_settings.apply(&wg_shared, net_conn);
+                            }
+                            NetMemResponse::Ok
+                        }
+                        Err(e) => {
+                            log::error!("couldn't save the WireGuard configuration: {:?}", e);
+                            NetMemResponse::LibraryError
+                        }
+                    },
+                };
+                buffer.replace(response).expect("couldn't return response");
+            }
+            Some(ConnectionManagerOpcode::WireguardEnable) => msg_blocking_scalar_unpack!(msg, enable, _, _, _, {
+                let enable = enable != 0;
+                let ok = wg_settings.enabled.is_some()
+                    && match wireguard::write_setting(&pddb, wireguard::WG_ENABLED_KEY, Some(if enable { b"1" } else { b"0" })) {
+                        Ok(_) => true,
+                        Err(e) => {
+                            log::error!("couldn't save the WireGuard setting: {:?}", e);
+                            false
+                        }
+                    };
+                if ok {
+                    wg_settings.enabled = Some(enable);
+                    wg_settings.apply(&wg_shared, net_conn);
+                }
+                xous::return_scalar(msg.sender, if ok { 1 } else { 0 }).expect("couldn't ack WireGuard enable");
+            }),
+            Some(ConnectionManagerOpcode::WireguardStatus) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let status = if wg_settings.enabled != Some(true) {
+                    WireguardStatus::default()
+                } else if wg_settings.config.is_none() || wg_settings.secret().is_none() {
+                    WireguardStatus { state: WireguardState::Unconfigured, ..Default::default() }
+                } else {
+                    let shared = wg_shared.lock().unwrap();
+                    let mut status = shared.status.unwrap_or(WireguardStatus {
+                        // the main loop hasn't got to it yet
+                        state: WireguardState::WaitingForNetwork,
+                        ..Default::default()
+                    });
+                    status.last_handshake_secs = shared.last_handshake.map(|at| (tt.elapsed_ms().saturating_sub(at) / 1000) as u32);
+                    status
+                };
+                buffer.replace(status).expect("couldn't return WireGuard status");
+            }
+            Some(ConnectionManagerOpcode::WireguardPublicKey) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let key = match wg_settings.secret() {
+                    Some(secret) => base64::encode(wireguard::public_key(&secret)),
+                    None => String::new(),
+                };
+                buffer.replace(xous_ipc::String::<64>::from_str(&key)).expect("couldn't return WireGuard key");
+            }
             Some(ConnectionManagerOpcode::Quit) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 send_message(run_cid, Message::new_blocking_scalar(PumpOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)).expect("couldn't tell Pump to quit");
                 unsafe{xous::disconnect(run_cid).ok()};
@@ -493,57 +650,37 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
     xous::destroy_server(sid).unwrap();
 }
 
-fn get_next_ssid(ssid_list_map: &mut HashMap<String, u8>, ssid_attempted: &mut HashSet<String>, ap_list: HashSet::<String>) -> Option<String> {
-    log::trace!("ap_list: {:?}", ap_list);
-    log::trace!("ssid_list: {:?}", ssid_list_map);
-    // 0. convert the HashMap of ssid_list into a HashSet
-    let mut ssid_list = HashSet::<String>::new();
-    for ssid in ssid_list_map.keys() {
-        ssid_list.insert(ssid.to_string());
-    }
-    // 1. find the intersection of ap_list and ssid_list to create a candidate_list
-    let all_candidate_list_ref = ap_list.intersection(&ssid_list).collect::<HashSet<_>>();
-    // this copy is required to perform the next set computation
-    let mut all_candidate_list = HashSet::<String>::new();
-    for c in all_candidate_list_ref {
-        all_candidate_list.insert(String::from(c));
+/// Records that we got onto the network we were joining
+fn note_connected(pddb: &pddb::Pddb, wlan_profile: &Mutex<Option<WlanProfile>>) {
+    if let Some(profile) = wlan_profile.lock().unwrap().as_ref() {
+        if let Err(e) = profiles::mark_connected(pddb, &profile.ssid) {
+            log::warn!("couldn't note the connection to {}: {:?}", profile.ssid, e);
+        }
     }
-    log::trace!("intersection: {:?}", all_candidate_list);
+}
 
+/// The saved network to try next: the best one we haven't tried since we last went through them all.
+/// Once they all have been tried, this returns `None` so the caller scans again, as hidden networks are
+/// candidates whether they were seen or not.
+fn get_next_profile(ssid_list: &HashMap<String, u8>, ssid_attempted: &mut HashSet<String>, saved: Vec<WlanProfile>) -> Option<WlanProfile> {
+    log::trace!("saved: {:?}", saved);
+    log::trace!("ssid_list: {:?}", ssid_list);
+    let candidates = profiles::join_order(saved, ssid_list);
+    log::trace!("candidates, best first: {:?}", candidates);
     log::trace!("ssids already attempted: {:?}", ssid_attempted);
-    // 2. find the complement of ssid_attempted and candidate_list
-    let untried_candidate_list_ref = all_candidate_list.difference(ssid_attempted).collect::<HashSet<_>>();
-    // this copy breaks the mutability issue with changing ssid_attempted after the difference is computed
-    let mut untried_candidate_list = HashSet::<String>::new();
-    for c in untried_candidate_list_ref {
-        untried_candidate_list.insert(String::from(c));
-    }
-    log::trace!("untried_candidates: {:?}", untried_candidate_list);
 
-    if untried_candidate_list.len() > 0 {
-        if let Some(candidate) = untried_candidate_list.into_iter().next() {
-            ssid_attempted.insert(candidate.to_string());
-            log::debug!("SSID connect attempt: {:?}", candidate);
-            Some(candidate.to_string())
-        } else {
-            log::error!("We should have had at least one item in the candidate list, but found none.");
-            None
+    match candidates.into_iter().find(|p| !ssid_attempted.contains(&p.ssid)) {
+        Some(candidate) => {
+            ssid_attempted.insert(candidate.ssid.clone());
+            log::debug!("SSID connect attempt: {:?}", candidate.ssid);
+            Some(candidate)
         }
-    } else {
-        // clear the ssid_attempted list and start from scratch
-        log::debug!("Exhausted all candidates, starting over again...");
-        ssid_attempted.clear();
-        if let Some(candidate) = all_candidate_list.iter().next() {
-            ssid_attempted.insert(candidate.to_string());
-            log::debug!("SSID connect attempt: {:?}", candidate);
-            Some(candidate.to_string())
-        } else {
-            log::info!("No SSID candidates visible. Debug dump:");
-            log::info!("ap_list: {:?}", ap_list);
-            log::info!("ssid_list: {:?}", ssid_list_map);
-            log::info!("candidate list: {:?}", all_candidate_list);
-            log::info!("untried candidate list: {:?}", untried_candidate_list);
+        None => {
+            // clear the ssid_attempted list and start from scratch
+            log::debug!("Exhausted all candidates, starting over again after a scan...");
+            log::info!("ssid_list: {:?}", ssid_list);
+            ssid_attempted.clear();
             None
         }
     }
-}
\ No newline at end of file
+}
@@ -3,27 +3,132 @@ use com::api::NET_MTU;
 
 use smoltcp::Result;
 use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::wire::{IpCidr, Ipv4Address, Ipv6Address};
 
 use smoltcp::{
     time::Instant,
 };
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use crate::capture::{Capture, Direction};
+use crate::wireguard::Tap;
+
+/// Frames that wait in the loopback queue before they are dropped
+const LOOPBACK_DEPTH: usize = 16;
+
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+const ETHERTYPE_ARP: [u8; 2] = [0x08, 0x06];
+const ETHERTYPE_IPV6: [u8; 2] = [0x86, 0xdd];
+
+/// The addresses of the loopback interface, which go in the interface's address list after all the others
+/// so smoltcp never picks them as the source of a packet for the outside world.
+pub fn loopback_cidrs() -> [IpCidr; 2] {
+    [
+        IpCidr::new(Ipv4Address::new(127, 0, 0, 1).into(), 8),
+        IpCidr::new(Ipv6Address::LOOPBACK.into(), 128),
+    ]
+}
+
+/// The loopback side of the device. The interface stays an Ethernet one, so frames for 127.0.0.0/8 and ::1
+/// are made like any other, and the ones that are for us are turned around here instead of being sent to
+/// the EC: frames to our own MAC, and the ARP requests and neighbor solicitations that smoltcp sends to find
+/// out that the loopback addresses are at our MAC. They come back in ahead of anything from the EC.
+pub struct Loopback {
+    mac: [u8; 6],
+    queue: VecDeque<Vec<u8>>,
+}
+impl Loopback {
+    pub fn new(mac: [u8; 6]) -> Loopback {
+        Loopback { mac, queue: VecDeque::new() }
+    }
+    /// Whether `frame`, which is about to be sent, is for ourselves.
+    pub fn is_local(&self, frame: &[u8]) -> bool {
+        if frame.len() < 14 {
+            return false;
+        }
+        if frame[..6] == self.mac {
+            return true;
+        }
+        let payload = &frame[14..];
+        match [frame[12], frame[13]] {
+            // an ARP request for 127.x.x.x
+            ETHERTYPE_ARP => payload.len() >= 28 && payload[6..8] == [0, 1] && payload[24] == 127,
+            ETHERTYPE_IPV4 => payload.len() >= 20 && payload[16] == 127,
+            ETHERTYPE_IPV6 => {
+                let is_loopback = |addr: &[u8]| addr == Ipv6Address::LOOPBACK.as_bytes();
+                if payload.len() < 40 {
+                    false
+                } else if is_loopback(&payload[24..40]) {
+                    true
+                } else {
+                    // a neighbor solicitation for ::1
+                    payload[6] == 58 && payload.len() >= 64 && payload[40] == 135 && is_loopback(&payload[48..64])
+                }
+            }
+            _ => false,
+        }
+    }
+    /// Queues a frame to be received. Returns `false` if the queue is full, and the frame was dropped.
+    pub fn push(&mut self, frame: &[u8]) -> bool {
+        if self.queue.len() >= LOOPBACK_DEPTH {
+            return false;
+        }
+        self.queue.push_back(frame.to_vec());
+        true
+    }
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.queue.pop_front()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
 
 pub struct NetPhy {
     rx_buffer: [u8; NET_MTU],
     tx_buffer: [u8; NET_MTU],
     com: Com,
     rx_avail: Option<u16>,
+    loopback: Loopback,
+    tunnel: Option<Tap>,
+    // both tokens record into it, and they're handed out together
+    capture: RefCell<Option<Capture>>,
 }
 
 impl<'a> NetPhy {
-    pub fn new(xns: &xous_names::XousNames) -> NetPhy {
+    pub fn new(xns: &xous_names::XousNames, mac: [u8; 6]) -> NetPhy {
         NetPhy {
             rx_buffer: [0; NET_MTU],
             tx_buffer: [0; NET_MTU],
             com: Com::new(&xns).unwrap(),
             rx_avail: None,
+            loopback: Loopback::new(mac),
+            tunnel: None,
+            capture: RefCell::new(None),
         }
     }
+    /// Whether there are looped-back frames, or frames from the tunnel, that the stack hasn't picked up yet
+    pub fn loopback_pending(&self) -> bool {
+        !self.loopback.is_empty() || self.tunnel.as_ref().map_or(false, |tap| tap.is_pending())
+    }
+    /// Puts the WireGuard tunnel in the path of the frames that go through the device, or takes it out
+    pub fn set_tunnel(&mut self, tap: Option<Tap>) {
+        self.tunnel = tap;
+    }
+    pub fn tunnel(&self) -> Option<&Tap> {
+        self.tunnel.as_ref()
+    }
+    pub fn tunnel_mut(&mut self) -> Option<&mut Tap> {
+        self.tunnel.as_mut()
+    }
+    /// Starts recording the frames that go through the device, or throws the capture away
+    pub fn set_capture(&mut self, capture: Option<Capture>) {
+        *self.capture.get_mut() = capture;
+    }
+    pub fn capture_mut(&mut self) -> Option<&mut Capture> {
+        self.capture.get_mut().as_mut()
+    }
     // returns None if there was a slot to put the availability into
     // returns Some(len) if not
     pub fn push_rx_avail(&mut self, len: u16) -> Option<u16> {
@@ -41,18 +146,26 @@ impl<'a> phy::Device<'a> for NetPhy {
     type TxToken = NetPhyTxToken<'a>;
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        if let Some(rx_len) = self.rx_avail.take() {
+        // looped-back frames go first, then what came out of the tunnel; the EC holds on to its packet until
+        // it is fetched
+        let rx_len = if let Some(frame) = self.loopback.pop() {
+            self.rx_buffer[..frame.len()].copy_from_slice(&frame);
+            frame.len()
+        } else if let Some(frame) = self.tunnel.as_mut().and_then(|tap| tap.pop_inbound()) {
+            self.rx_buffer[..frame.len()].copy_from_slice(&frame);
+            frame.len()
+        } else if let Some(rx_len) = self.rx_avail.take() {
             self.com.wlan_fetch_packet(&mut self.rx_buffer[..rx_len as usize]).expect("Couldn't call wlan_fetch_packet in device adapter");
-
-            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize]},
-            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com}))
+            rx_len as usize
         } else {
-            None
-        }
+            return None;
+        };
+        Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len], capture: &self.capture},
+        NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, loopback: &mut self.loopback, tunnel: &mut self.tunnel, capture: &self.capture}))
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com})
+        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com, loopback: &mut self.loopback, tunnel: &mut self.tunnel, capture: &self.capture})
     }
 
     fn capabilities(&self) -> DeviceCapabilities {
@@ -66,12 +179,16 @@ impl<'a> phy::Device<'a> for NetPhy {
 
 pub struct NetPhyRxToken<'a> {
     buf: &'a mut [u8],
+    capture: &'a RefCell<Option<Capture>>,
 }
 
 impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
-    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> Result<R>
+    fn consume<R, F>(mut self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
+        if let Some(capture) = self.capture.borrow_mut().as_mut() {
+            capture.record(Direction::Rx, self.buf, timestamp);
+        }
         let result = f(&mut self.buf);
         //log::info!("rx: {:x?}", self.buf);
         result
@@ -81,18 +198,93 @@ impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
 pub struct NetPhyTxToken<'a> {
     buf: &'a mut [u8],
     com: &'a Com,
+    loopback: &'a mut Loopback,
+    tunnel: &'a mut Option<Tap>,
+    capture: &'a RefCell<Option<Capture>>,
 }
 
 impl<'a> phy::TxToken for NetPhyTxToken<'a> {
-    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
         let result = f(&mut self.buf[..len]);
         //log::info!("txlen: {}", len);
 
         if result.is_ok() {
-            self.com.wlan_send_packet(&self.buf[..len]).map_err(|_| smoltcp::Error::Dropped)?;
+            let frame = &mut self.buf[..len];
+            if let Some(capture) = self.capture.borrow_mut().as_mut() {
+                capture.record(Direction::Tx, frame, timestamp);
+            }
+            if self.loopback.is_local(frame) {
+                if !self.loopback.push(frame) {
+                    return Err(smoltcp::Error::Exhausted);
+                }
+            } else if self.tunnel.as_mut().map_or(false, |tap| tap.take(frame)) {
+                // it's the tunnel's to send, or to drop so it doesn't go around the tunnel
+            } else {
+                self.com.wlan_send_packet(frame).map_err(|_| smoltcp::Error::Dropped)?;
+            }
         }
         result
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [2, 2, 4, 5, 6, 2];
+
+    fn frame(dst_mac: [u8; 6], ethertype: [u8; 2], payload: &[u8]) -> Vec<u8> {
+        let mut frame = dst_mac.to_vec();
+        frame.extend_from_slice(&MAC);
+        frame.extend_from_slice(&ethertype);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn frames_for_us_are_local() {
+        let loopback = Loopback::new(MAC);
+        assert!(loopback.is_local(&frame(MAC, ETHERTYPE_IPV4, &[0; 20])));
+        let mut ipv4 = [0u8; 20];
+        ipv4[16..20].copy_from_slice(&[127, 0, 0, 1]);
+        assert!(loopback.is_local(&frame([0x10; 6], ETHERTYPE_IPV4, &ipv4)));
+        ipv4[16..20].copy_from_slice(&[10, 0, 0, 1]);
+        assert!(!loopback.is_local(&frame([0x10; 6], ETHERTYPE_IPV4, &ipv4)));
+        assert!(!loopback.is_local(&MAC));
+    }
+
+    #[test]
+    fn address_resolution_for_loopback_is_local() {
+        let loopback = Loopback::new(MAC);
+        let mut arp = [0u8; 28];
+        arp[6..8].copy_from_slice(&[0, 1]);
+        arp[24..28].copy_from_slice(&[127, 0, 0, 1]);
+        assert!(loopback.is_local(&frame([0xff; 6], ETHERTYPE_ARP, &arp)));
+        arp[24..28].copy_from_slice(&[10, 0, 0, 1]);
+        assert!(!loopback.is_local(&frame([0xff; 6], ETHERTYPE_ARP, &arp)));
+
+        // a neighbor solicitation for ::1, sent to its solicited-node multicast address
+        let mut ns = [0u8; 64];
+        ns[6] = 58;
+        ns[24..40].copy_from_slice(&[0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0, 1]);
+        ns[40] = 135;
+        ns[63] = 1;
+        assert!(loopback.is_local(&frame([0x33, 0x33, 0xff, 0, 0, 1], ETHERTYPE_IPV6, &ns)));
+        ns[63] = 2;
+        assert!(!loopback.is_local(&frame([0x33, 0x33, 0xff, 0, 0, 2], ETHERTYPE_IPV6, &ns)));
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        let mut loopback = Loopback::new(MAC);
+        for i in 0..LOOPBACK_DEPTH {
+            assert!(loopback.push(&[i as u8]));
+        }
+        assert!(!loopback.push(&[0xff]));
+        assert_eq!(loopback.pop(), Some(vec![0]));
+        assert!(loopback.push(&[0xff]));
+        while loopback.pop().is_some() {}
+        assert!(loopback.is_empty());
+    }
+}
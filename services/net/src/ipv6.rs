@@ -0,0 +1,552 @@
+//! IPv6 stateless address autoconfiguration (RFC 4862), done by hand because smoltcp 0.8 doesn't
+//! have it. A link-local address is derived from the MAC at boot; once the link is up, routers are
+//! solicited, and the first prefix with the autonomous flag that a router advertises is turned into
+//! a global address. The advertising router becomes the default route, and a DNS server in an RDNSS
+//! option (RFC 8106) is passed on to the DNS service.
+//!
+//! Only one global address and one router are kept at a time. Duplicate address detection is not
+//! done: the addresses are EUI-64 ones, which only collide if the MAC does.
+//!
+//! Addresses are kept in slots of the interface's address list, after the IPv4 address in slot 0.
+//! smoltcp takes the first address of a family as the source of outgoing packets, so the global
+//! address goes first, and the link-local one stands in for it until there is one. The loopback
+//! addresses come after them.
+
+use smoltcp::iface::Interface;
+use smoltcp::phy::Device;
+use smoltcp::wire::{IpAddress, IpCidr, IpProtocol, Ipv6Address, Ipv6Cidr};
+use smoltcp::wire::{Icmpv6Message, Icmpv6Packet, Ipv6Packet, Ipv6Repr};
+use crate::api::Ipv6Conf;
+
+const ALL_ROUTERS: Ipv6Address = Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+/// RFC 4861, section 10
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+const RTR_SOLICITATION_INTERVAL_MS: u64 = 4_000;
+/// RFC 4862, section 5.5.3 (e)
+const TWO_HOURS_MS: u64 = 2 * 60 * 60 * 1000;
+
+const ND_OPT_SOURCE_LL_ADDR: u8 = 1;
+const ND_OPT_PREFIX_INFO: u8 = 3;
+const ND_OPT_RDNSS: u8 = 25;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+/// a router solicitation with a source link-layer address option, in an IPv6 packet
+pub(crate) const SOLICITATION_LEN: usize = 40 + 8 + 8;
+
+pub(crate) struct Slaac {
+    mac: [u8; 6],
+    link_local: Ipv6Address,
+    /// the SLAAC address, and when it stops being valid (ms of uptime; `u64::MAX` is forever)
+    global: Option<(Ipv6Cidr, u64)>,
+    /// the default router, and when it stops being one
+    router: Option<(Ipv6Address, u64)>,
+    /// the advertised DNS server, and when it expires
+    dns: Option<(Ipv6Address, u64)>,
+    /// set when the DNS server changes, until it is picked up with `take_new_dns()`
+    dns_changed: bool,
+    solicits_sent: u8,
+    next_solicit: Option<u64>,
+}
+
+impl Slaac {
+    pub(crate) fn new(mac: [u8; 6]) -> Slaac {
+        Slaac {
+            mac,
+            link_local: eui64_address(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0], &mac),
+            global: None,
+            router: None,
+            dns: None,
+            dns_changed: false,
+            solicits_sent: 0,
+            next_solicit: None,
+        }
+    }
+
+    /// What goes in the two IPv6 slots of the interface's address list
+    pub(crate) fn ipv6_slots(&self) -> [IpCidr; 2] {
+        let link_local = IpCidr::Ipv6(Ipv6Cidr::new(self.link_local, 64));
+        match self.global {
+            Some((cidr, _)) => [IpCidr::Ipv6(cidr), link_local],
+            // the slot can't be left unspecified, as `::/0` would put every IPv6 address on-link
+            None => [link_local, link_local],
+        }
+    }
+    /// Writes the addresses and the default route into the interface.
+    pub(crate) fn apply<DeviceT>(&self, iface: &mut Interface<'_, DeviceT>)
+    where
+        DeviceT: for<'d> Device<'d>,
+    {
+        let slots = self.ipv6_slots();
+        iface.update_ip_addrs(|addrs| {
+            for (dest, src) in addrs.iter_mut().skip(1).zip(slots.iter()) {
+                *dest = *src;
+            }
+        });
+        iface.routes_mut().remove_default_ipv6_route();
+        if let Some((router, _)) = self.router {
+            match iface.routes_mut().add_default_ipv6_route(router) {
+                Ok(_) => log::info!("IPv6 default route is now via {}", router),
+                Err(e) => log::error!("IPv6 routing table update error: {}", e),
+            }
+        }
+    }
+
+    /// Forgets everything learned from routers, e.g. because the link went down. `apply()` has to be
+    /// called after this.
+    pub(crate) fn clear(&mut self) {
+        self.global = None;
+        self.router = None;
+        if self.dns.take().is_some() {
+            self.dns_changed = true;
+        }
+        self.solicits_sent = 0;
+        self.next_solicit = None;
+    }
+    /// Starts soliciting routers, e.g. because the link came up.
+    pub(crate) fn restart(&mut self, now: u64) {
+        self.solicits_sent = 0;
+        self.next_solicit = Some(now);
+    }
+
+    /// Writes a router solicitation into `buf`, if one is due, and returns its length. Solicitations
+    /// stop once a router has been heard from.
+    pub(crate) fn solicitation(&mut self, now: u64, buf: &mut [u8; SOLICITATION_LEN]) -> Option<usize> {
+        if self.router.is_some() || self.next_solicit.map_or(true, |at| now < at) {
+            return None;
+        }
+        self.solicits_sent += 1;
+        self.next_solicit = if self.solicits_sent < MAX_RTR_SOLICITATIONS {
+            Some(now + RTR_SOLICITATION_INTERVAL_MS)
+        } else {
+            None
+        };
+
+        let ip_repr = Ipv6Repr {
+            src_addr: self.link_local,
+            dst_addr: ALL_ROUTERS,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: SOLICITATION_LEN - 40,
+            hop_limit: 255,
+        };
+        ip_repr.emit(&mut Ipv6Packet::new_unchecked(&mut buf[..]));
+        let icmp = &mut buf[40..];
+        icmp[..8].copy_from_slice(&[u8::from(Icmpv6Message::RouterSolicit), 0, 0, 0, 0, 0, 0, 0]);
+        icmp[8] = ND_OPT_SOURCE_LL_ADDR;
+        icmp[9] = 1; // length, in units of 8 bytes
+        icmp[10..16].copy_from_slice(&self.mac);
+        Icmpv6Packet::new_unchecked(icmp).fill_checksum(&self.link_local.into(), &ALL_ROUTERS.into());
+        log::debug!("soliciting IPv6 routers ({} of {})", self.solicits_sent, MAX_RTR_SOLICITATIONS);
+        Some(SOLICITATION_LEN)
+    }
+
+    /// Takes in an ICMPv6 packet from the raw socket, IPv6 header and all, and learns from it if it is a
+    /// router advertisement. Returns `true` if the addresses or the default route changed, in which case
+    /// `apply()` has to be called.
+    pub(crate) fn handle_packet(&mut self, packet: &[u8], now: u64) -> bool {
+        let ip_packet = match Ipv6Packet::new_checked(packet) {
+            Ok(p) => p,
+            Err(_) => return false,
+        };
+        let ip_repr = match Ipv6Repr::parse(&ip_packet) {
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+        // RFC 4861, section 6.1.2: advertisements come from a link-local address, and can't have been routed
+        if ip_repr.next_header != IpProtocol::Icmpv6 || ip_repr.hop_limit != 255 || !ip_repr.src_addr.is_link_local() {
+            return false;
+        }
+        let icmp = match Icmpv6Packet::new_checked(ip_packet.payload()) {
+            Ok(p) => p,
+            Err(_) => return false,
+        };
+        if icmp.msg_type() != Icmpv6Message::RouterAdvert || icmp.msg_code() != 0 {
+            return false;
+        }
+        if !icmp.verify_checksum(&ip_repr.src_addr.into(), &ip_repr.dst_addr.into()) {
+            log::warn!("router advertisement from {} has a bad checksum", ip_repr.src_addr);
+            return false;
+        }
+        let msg = ip_packet.payload();
+        if msg.len() < 16 {
+            return false;
+        }
+        let mut changed = false;
+
+        let router_lifetime = u16::from_be_bytes([msg[6], msg[7]]) as u32;
+        match self.router {
+            Some((router, _)) if router == ip_repr.src_addr => {
+                if router_lifetime == 0 {
+                    log::info!("IPv6 router {} is going away", router);
+                    self.router = None;
+                    changed = true;
+                } else {
+                    self.router = Some((router, expiry(now, router_lifetime)));
+                }
+            }
+            None if router_lifetime != 0 => {
+                log::info!("IPv6 router found: {}", ip_repr.src_addr);
+                self.router = Some((ip_repr.src_addr, expiry(now, router_lifetime)));
+                self.next_solicit = None;
+                changed = true;
+            }
+            _ => {}
+        }
+
+        let mut options = &msg[16..];
+        while options.len() >= 8 {
+            let len = options[1] as usize * 8;
+            if len == 0 || len > options.len() {
+                log::warn!("malformed option in router advertisement from {}", ip_repr.src_addr);
+                break;
+            }
+            let option = &options[..len];
+            match option[0] {
+                ND_OPT_PREFIX_INFO if len == 32 => changed |= self.handle_prefix(option, now),
+                ND_OPT_RDNSS if len >= 24 => self.handle_rdnss(option, now),
+                _ => {}
+            }
+            options = &options[len..];
+        }
+        changed
+    }
+
+    /// RFC 4862, section 5.5.3
+    fn handle_prefix(&mut self, option: &[u8], now: u64) -> bool {
+        let prefix_len = option[2];
+        let valid = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+        let preferred = u32::from_be_bytes([option[8], option[9], option[10], option[11]]);
+        let prefix = &option[16..24];
+        if option[3] & PREFIX_FLAG_AUTONOMOUS == 0 || prefix[..2] == [0xfe, 0x80] || preferred > valid {
+            return false;
+        }
+        if prefix_len != 64 {
+            // an EUI-64 interface identifier needs a /64
+            log::info!("ignoring advertised IPv6 prefix of length {}", prefix_len);
+            return false;
+        }
+        let addr = eui64_address(prefix, &self.mac);
+        match self.global {
+            Some((cidr, valid_until)) if cidr.address() == addr => {
+                // the "two hours" rule keeps a spoofed advertisement from cutting the lifetime short
+                let remaining = valid_until.saturating_sub(now);
+                let new_until = expiry(now, valid);
+                if new_until - now > TWO_HOURS_MS || new_until > valid_until {
+                    self.global = Some((cidr, new_until));
+                } else if remaining > TWO_HOURS_MS {
+                    self.global = Some((cidr, now + TWO_HOURS_MS));
+                }
+                false
+            }
+            _ if valid == 0 => false,
+            _ => {
+                log::info!("IPv6 address configured: {}/{}", addr, prefix_len);
+                self.global = Some((Ipv6Cidr::new(addr, prefix_len), expiry(now, valid)));
+                true
+            }
+        }
+    }
+
+    fn handle_rdnss(&mut self, option: &[u8], now: u64) {
+        let lifetime = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+        let server = Ipv6Address::from_bytes(&option[8..24]);
+        if lifetime == 0 {
+            if self.dns.map_or(false, |(dns, _)| dns == server) {
+                self.dns = None;
+            }
+        } else {
+            if self.dns.map_or(true, |(dns, _)| dns != server) {
+                log::info!("IPv6 DNS server: {}", server);
+                self.dns_changed = true;
+            }
+            self.dns = Some((server, expiry(now, lifetime)));
+        }
+    }
+
+    /// The DNS server, if it changed since the last time this was called.
+    pub(crate) fn take_new_dns(&mut self) -> Option<Ipv6Address> {
+        if !self.dns_changed {
+            return None;
+        }
+        self.dns_changed = false;
+        self.dns.map(|(dns, _)| dns)
+    }
+
+    /// Drops whatever has outlived its lifetime. Returns `true` if the addresses or the default route
+    /// changed, in which case `apply()` has to be called.
+    pub(crate) fn expire(&mut self, now: u64) -> bool {
+        let mut changed = false;
+        if self.global.map_or(false, |(_, until)| until <= now) {
+            log::info!("IPv6 address {} expired", self.global.unwrap().0);
+            self.global = None;
+            changed = true;
+        }
+        if self.router.map_or(false, |(_, until)| until <= now) {
+            log::info!("IPv6 router {} expired", self.router.unwrap().0);
+            self.router = None;
+            changed = true;
+        }
+        if self.dns.map_or(false, |(_, until)| until <= now) {
+            self.dns = None;
+        }
+        changed
+    }
+
+    pub(crate) fn config(&self, now: u64) -> Ipv6Conf {
+        Ipv6Conf {
+            link_local: self.link_local.0,
+            global: self.global.map(|(cidr, _)| cidr.address().0),
+            prefix_len: self.global.map_or(0, |(cidr, _)| cidr.prefix_len()),
+            valid_secs: self.global.map_or(0, |(_, until)| {
+                if until == u64::MAX { u32::MAX } else { (until.saturating_sub(now) / 1000).min(u32::MAX as u64 - 1) as u32 }
+            }),
+            gateway: self.router.map(|(router, _)| router.0),
+            dns: self.dns.map(|(dns, _)| dns.0),
+        }
+    }
+}
+
+/// When a lifetime of `secs` seconds, as given by a router, runs out. All-ones is forever.
+fn expiry(now: u64, secs: u32) -> u64 {
+    if secs == u32::MAX {
+        u64::MAX
+    } else {
+        now + secs as u64 * 1000
+    }
+}
+
+/// The address made of the top 64 bits of `prefix`, and the modified EUI-64 interface identifier of
+/// `mac` (RFC 4291, appendix A).
+fn eui64_address(prefix: &[u8], mac: &[u8; 6]) -> Ipv6Address {
+    let mut addr = [0u8; 16];
+    addr[..8].copy_from_slice(&prefix[..8]);
+    addr[8..].copy_from_slice(&[mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]]);
+    Ipv6Address(addr)
+}
+
+/// The address smoltcp sends IPv6 packets from, when a socket doesn't pick one.
+pub(crate) fn source_addr<DeviceT>(iface: &Interface<'_, DeviceT>) -> Option<Ipv6Address>
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    iface.ip_addrs().iter().find_map(|cidr| match cidr.address() {
+        IpAddress::Ipv6(addr) => Some(addr),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const ROUTER: Ipv6Address = Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    const OTHER_ROUTER: Ipv6Address = Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9]);
+    const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 2];
+    const GLOBAL: Ipv6Address =
+        Ipv6Address([0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 2, 0x02, 0x11, 0x22, 0xff, 0xfe, 0x33, 0x44, 0x55]);
+    const HOUR_MS: u64 = 60 * 60 * 1000;
+
+    /// A router advertisement from `src`, IPv6 header and all, with a correct checksum.
+    fn advert(src: Ipv6Address, router_lifetime: u16, options: &[Vec<u8>]) -> Vec<u8> {
+        let mut icmp = vec![u8::from(Icmpv6Message::RouterAdvert), 0, 0, 0, 64, 0];
+        icmp.extend_from_slice(&router_lifetime.to_be_bytes());
+        icmp.extend_from_slice(&[0; 8]); // reachable time and retransmission timer
+        for option in options {
+            icmp.extend_from_slice(option);
+        }
+        let dst = Ipv6Address::LINK_LOCAL_ALL_NODES;
+        let mut packet = vec![0u8; 40 + icmp.len()];
+        let ip_repr = Ipv6Repr {
+            src_addr: src,
+            dst_addr: dst,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: icmp.len(),
+            hop_limit: 255,
+        };
+        ip_repr.emit(&mut Ipv6Packet::new_unchecked(&mut packet[..]));
+        packet[40..].copy_from_slice(&icmp);
+        Icmpv6Packet::new_unchecked(&mut packet[40..]).fill_checksum(&src.into(), &dst.into());
+        packet
+    }
+    fn prefix_option(prefix: [u8; 8], prefix_len: u8, flags: u8, valid: u32, preferred: u32) -> Vec<u8> {
+        let mut option = vec![ND_OPT_PREFIX_INFO, 4, prefix_len, flags];
+        option.extend_from_slice(&valid.to_be_bytes());
+        option.extend_from_slice(&preferred.to_be_bytes());
+        option.extend_from_slice(&[0; 4]);
+        option.extend_from_slice(&prefix);
+        option.extend_from_slice(&[0; 8]);
+        option
+    }
+    fn slaac_prefix(valid: u32) -> Vec<u8> {
+        prefix_option(PREFIX, 64, PREFIX_FLAG_AUTONOMOUS, valid, valid / 2)
+    }
+    fn valid_until(slaac: &Slaac) -> Option<u64> {
+        slaac.global.map(|(_, until)| until)
+    }
+
+    #[test]
+    fn eui64() {
+        let slaac = Slaac::new(MAC);
+        assert_eq!(&slaac.link_local.0[..8], &[0xfe, 0x80, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&slaac.link_local.0[8..], &GLOBAL.0[8..]);
+        assert_eq!(eui64_address(&PREFIX, &MAC), GLOBAL);
+        // the universal/local bit is flipped, not set
+        let local = eui64_address(&PREFIX, &[0x02, 0, 0, 0, 0, 1]);
+        assert_eq!(&local.0[8..], &[0x00, 0, 0, 0xff, 0xfe, 0, 0, 1]);
+        // until there is a global address, the link-local one stands in for it
+        assert_eq!(slaac.ipv6_slots()[0], IpCidr::Ipv6(Ipv6Cidr::new(slaac.link_local, 64)));
+    }
+
+    #[test]
+    fn prefix_and_lifetimes() {
+        let mut slaac = Slaac::new(MAC);
+        let now = 1_000;
+        assert!(slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(3600)]), now));
+        assert_eq!(slaac.ipv6_slots()[0], IpCidr::Ipv6(Ipv6Cidr::new(GLOBAL, 64)));
+        assert_eq!(valid_until(&slaac), Some(now + 3600 * 1000));
+        let config = slaac.config(now);
+        assert_eq!(config.global, Some(GLOBAL.0));
+        assert_eq!(config.prefix_len, 64);
+        assert_eq!(config.valid_secs, 3600);
+        assert_eq!(config.gateway, Some(ROUTER.0));
+        // the same prefix again only refreshes the lifetime
+        assert!(!slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(7200)]), now + 10));
+        assert_eq!(valid_until(&slaac), Some(now + 10 + 7200 * 1000));
+        // all-ones is forever
+        assert!(!slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(u32::MAX)]), now + 20));
+        assert_eq!(valid_until(&slaac), Some(u64::MAX));
+        assert_eq!(slaac.config(now).valid_secs, u32::MAX);
+    }
+
+    #[test]
+    fn prefixes_that_are_ignored() {
+        let link_local = [0xfe, 0x80, 0, 0, 0, 0, 0, 0];
+        let ignored = [
+            // not autonomous
+            prefix_option(PREFIX, 64, 0, 3600, 1800),
+            prefix_option(link_local, 64, PREFIX_FLAG_AUTONOMOUS, 3600, 1800),
+            // not a /64
+            prefix_option(PREFIX, 48, PREFIX_FLAG_AUTONOMOUS, 3600, 1800),
+            // preferred for longer than it is valid
+            prefix_option(PREFIX, 64, PREFIX_FLAG_AUTONOMOUS, 1800, 3600),
+            // a new address that has already expired
+            prefix_option(PREFIX, 64, PREFIX_FLAG_AUTONOMOUS, 0, 0),
+        ];
+        for option in ignored.iter() {
+            let mut slaac = Slaac::new(MAC);
+            // the router is still taken, but no address is made
+            assert!(slaac.handle_packet(&advert(ROUTER, 1800, &[option.clone()]), 0));
+            assert!(slaac.global.is_none(), "{:x?}", option);
+        }
+    }
+
+    #[test]
+    fn two_hour_rule() {
+        let mut slaac = Slaac::new(MAC);
+        slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(10 * 3600)]), 0);
+        // a short lifetime can't cut more than two hours off the remaining ten
+        let now = HOUR_MS;
+        slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(60)]), now);
+        assert_eq!(valid_until(&slaac), Some(now + 2 * HOUR_MS));
+        // nor a zero one
+        let mut zeroed = Slaac::new(MAC);
+        zeroed.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(10 * 3600)]), 0);
+        zeroed.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(0)]), now);
+        assert_eq!(valid_until(&zeroed), Some(now + 2 * HOUR_MS));
+        // with two hours or less left, shorter lifetimes are ignored
+        let later = now + HOUR_MS;
+        slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(60)]), later);
+        assert_eq!(valid_until(&slaac), Some(now + 2 * HOUR_MS));
+        // but one longer than what is left is taken, as is one longer than two hours
+        slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(90 * 60)]), later);
+        assert_eq!(valid_until(&slaac), Some(later + 90 * 60 * 1000));
+        slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(3 * 3600)]), later);
+        assert_eq!(valid_until(&slaac), Some(later + 3 * HOUR_MS));
+    }
+
+    #[test]
+    fn router_expiry() {
+        let mut slaac = Slaac::new(MAC);
+        slaac.restart(0);
+        let mut buf = [0u8; SOLICITATION_LEN];
+        assert_eq!(slaac.solicitation(0, &mut buf), Some(SOLICITATION_LEN));
+        assert!(slaac.handle_packet(&advert(ROUTER, 60, &[slaac_prefix(3600)]), 0));
+        // routers aren't solicited while there is one
+        assert_eq!(slaac.solicitation(RTR_SOLICITATION_INTERVAL_MS, &mut buf), None);
+        // another router isn't taken while the first one lasts
+        assert!(!slaac.handle_packet(&advert(OTHER_ROUTER, 1800, &[]), 10));
+        assert!(!slaac.expire(59_999));
+        assert!(slaac.expire(60_000));
+        assert!(slaac.router.is_none());
+        assert_eq!(slaac.config(60_000).gateway, None);
+        // the address outlives the router
+        assert!(slaac.global.is_some());
+        assert!(slaac.expire(3600 * 1000));
+        assert!(slaac.global.is_none());
+
+        // a router lifetime of zero takes the router away at once
+        let mut slaac = Slaac::new(MAC);
+        assert!(slaac.handle_packet(&advert(ROUTER, 1800, &[]), 0));
+        assert!(slaac.handle_packet(&advert(ROUTER, 0, &[]), 10));
+        assert!(slaac.router.is_none());
+        // and a router that announces it isn't one is never taken
+        assert!(!slaac.handle_packet(&advert(OTHER_ROUTER, 0, &[]), 20));
+        assert!(slaac.router.is_none());
+    }
+
+    #[test]
+    fn malformed() {
+        let mut slaac = Slaac::new(MAC);
+        // an option with a length of zero stops the parsing, so the prefix after it isn't looked at
+        let zero_len = vec![ND_OPT_RDNSS, 0, 0, 0, 0, 0, 0, 0];
+        assert!(slaac.handle_packet(&advert(ROUTER, 1800, &[zero_len, slaac_prefix(3600)]), 0));
+        assert!(slaac.global.is_none());
+        // as does one that runs past the end of the packet
+        let mut slaac = Slaac::new(MAC);
+        let mut overlong = slaac_prefix(3600);
+        overlong[1] = 5;
+        slaac.handle_packet(&advert(ROUTER, 1800, &[overlong]), 0);
+        assert!(slaac.global.is_none());
+        // a prefix option of the wrong length, and an RDNSS option too short to hold an address
+        let mut short_prefix = slaac_prefix(3600);
+        short_prefix.truncate(24);
+        short_prefix[1] = 3;
+        let short_rdnss = vec![ND_OPT_RDNSS, 1, 0, 0, 0, 0, 0x0e, 0x10];
+        slaac.handle_packet(&advert(ROUTER, 1800, &[short_prefix, short_rdnss]), 0);
+        assert!(slaac.global.is_none());
+        assert_eq!(slaac.take_new_dns(), None);
+        // trailing bytes that don't make an option are ignored
+        let mut slaac = Slaac::new(MAC);
+        assert!(slaac.handle_packet(&advert(ROUTER, 1800, &[slaac_prefix(3600), vec![ND_OPT_RDNSS, 1, 0]]), 0));
+        assert!(slaac.global.is_some());
+
+        let good = advert(ROUTER, 1800, &[slaac_prefix(3600)]);
+        // a bad checksum
+        let mut bad = good.clone();
+        bad[40 + 16 + 20] ^= 1;
+        assert!(!Slaac::new(MAC).handle_packet(&bad, 0));
+        // forwarded by a router, so not from this link
+        let mut forwarded = good.clone();
+        forwarded[7] = 254;
+        assert!(!Slaac::new(MAC).handle_packet(&forwarded, 0));
+        // truncated anywhere
+        for len in 0..good.len() {
+            assert!(!Slaac::new(MAC).handle_packet(&good[..len], 0), "accepted {} bytes", len);
+        }
+        // from an address that isn't link-local
+        assert!(!Slaac::new(MAC).handle_packet(&advert(GLOBAL, 1800, &[slaac_prefix(3600)]), 0));
+    }
+
+    #[test]
+    fn rdnss() {
+        let mut slaac = Slaac::new(MAC);
+        let dns = Ipv6Address([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x53]);
+        let mut option = vec![ND_OPT_RDNSS, 3, 0, 0];
+        option.extend_from_slice(&600u32.to_be_bytes());
+        option.extend_from_slice(&dns.0);
+        slaac.handle_packet(&advert(ROUTER, 1800, &[option.clone()]), 0);
+        assert_eq!(slaac.take_new_dns(), Some(dns));
+        assert_eq!(slaac.take_new_dns(), None);
+        slaac.expire(600 * 1000);
+        assert_eq!(slaac.config(600 * 1000).dns, None);
+    }
+}
@@ -8,6 +8,7 @@ use num_traits::*;
 
 pub mod protocols;
 pub use protocols::*;
+pub mod profiles;
 pub use smoltcp::time::Duration;
 pub use api::*;
 pub use smoltcp::wire::IpEndpoint;
@@ -72,6 +73,148 @@ impl NetManager {
             None
         }
     }
+    /// The IPv6 side of the interface. The link-local address is always there; the rest comes once a
+    /// router has advertised itself.
+    pub fn get_ipv6_config(&self) -> Ipv6Conf {
+        let mut buf = Buffer::into_buf(Ipv6Conf::default()).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.netconn.conn(), Opcode::GetIpv6Config.to_u32().unwrap()).expect("Couldn't execute GetIpv6Config opcode");
+        buf.to_original().expect("couldn't restore config structure")
+    }
+    /// Sets the name the device answers to over mDNS, as `<name>.local`. The name is a single DNS
+    /// label: letters, digits and hyphens. The default is `precursor`.
+    pub fn mdns_set_name(&self, name: &str) -> Result<(), xous::Error> {
+        let mut buf = Buffer::into_buf(xous_ipc::String::<64>::from_str(name)).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::MdnsSetName.to_u32().unwrap())?;
+        match buf.to_original::<NetMemResponse, _>().or(Err(xous::Error::InternalError))? {
+            NetMemResponse::Ok => Ok(()),
+            _ => Err(xous::Error::InvalidString),
+        }
+    }
+    /// Advertises `service` (e.g. `_http._tcp`) on `port` over DNS-SD, with up to four `key=value`
+    /// entries in its TXT record. Registering a service type again updates it.
+    pub fn mdns_register_service(&self, service: &str, port: u16, txt: &[&str]) -> Result<(), xous::Error> {
+        let mut record = MdnsService {
+            service: xous_ipc::String::<64>::from_str(service),
+            port,
+            txt: Default::default(),
+        };
+        if txt.len() > record.txt.len() {
+            return Err(xous::Error::OutOfMemory);
+        }
+        for (dst, &src) in record.txt.iter_mut().zip(txt.iter()) {
+            *dst = Some(xous_ipc::String::<64>::from_str(src));
+        }
+        let mut buf = Buffer::into_buf(record).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::MdnsRegisterService.to_u32().unwrap())?;
+        match buf.to_original::<NetMemResponse, _>().or(Err(xous::Error::InternalError))? {
+            NetMemResponse::Ok => Ok(()),
+            _ => Err(xous::Error::InvalidString),
+        }
+    }
+    /// Stops advertising `service`, and tells the LAN that it's gone.
+    pub fn mdns_unregister_service(&self, service: &str) -> Result<(), xous::Error> {
+        let mut buf = Buffer::into_buf(xous_ipc::String::<64>::from_str(service)).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::MdnsUnregisterService.to_u32().unwrap())?;
+        match buf.to_original::<NetMemResponse, _>().or(Err(xous::Error::InternalError))? {
+            NetMemResponse::Ok => Ok(()),
+            _ => Err(xous::Error::ServerNotFound),
+        }
+    }
+    /// Saves the WireGuard configuration, given in the `wg-quick` format; the tunnel is brought up again
+    /// with it if it's enabled. The peer's endpoint has to be an address, and the private key can be left
+    /// out to use the one made on the device (see `wireguard_public_key()`).
+    pub fn wireguard_set_config(&self, config: &str) -> Result<(), xous::Error> {
+        if config.len() > 1024 {
+            return Err(xous::Error::OutOfMemory);
+        }
+        let mut buf = Buffer::into_buf(xous_ipc::String::<1024>::from_str(config)).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::WireguardSetConfig.to_u32().unwrap())?;
+        match buf.to_original::<NetMemResponse, _>().or(Err(xous::Error::InternalError))? {
+            NetMemResponse::Ok => Ok(()),
+            NetMemResponse::Invalid => Err(xous::Error::InvalidString),
+            // the PDDB isn't mounted yet
+            NetMemResponse::AccessDenied => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Brings the WireGuard tunnel up or down, and remembers which for the next boot. Fails if the PDDB
+    /// isn't mounted yet.
+    pub fn wireguard_enable(&self, enable: bool) -> Result<(), xous::Error> {
+        match send_message(
+            self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::WireguardEnable.to_usize().unwrap(), if enable { 1 } else { 0 }, 0, 0, 0),
+        )? {
+            xous::Result::Scalar1(1) => Ok(()),
+            _ => Err(xous::Error::AccessDenied),
+        }
+    }
+    /// Starts capturing the frames that go through the net device into a ring of `ring_size` bytes (see
+    /// `CAPTURE_MAX_RING_SIZE`), throwing away what was captured before. The filter is made of tcpdump-like
+    /// terms that all have to match: `in`, `out`, `arp`, `ip`, `ip6`, `icmp`, `tcp`, `udp`, `port <n>` and
+    /// `host <address>`; an empty one takes everything.
+    pub fn capture_start(&self, filter: &str, ring_size: usize) -> Result<(), xous::Error> {
+        if filter.len() > 128 {
+            return Err(xous::Error::OutOfMemory);
+        }
+        let config = CaptureConfig { filter: xous_ipc::String::<128>::from_str(filter), ring_size: ring_size as u32 };
+        let mut buf = Buffer::into_buf(config).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::CaptureStart.to_u32().unwrap())?;
+        match buf.to_original::<NetMemResponse, _>().or(Err(xous::Error::InternalError))? {
+            NetMemResponse::Ok => Ok(()),
+            _ => Err(xous::Error::InvalidString),
+        }
+    }
+    /// Stops the capture, keeping what was captured for `capture_export()`. Returns the number of frames kept.
+    pub fn capture_stop(&self) -> Result<u32, xous::Error> {
+        match send_message(
+            self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::CaptureStop.to_usize().unwrap(), 0, 0, 0, 0),
+        )? {
+            xous::Result::Scalar1(frames) => Ok(frames as u32),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    pub fn capture_status(&self) -> CaptureStatus {
+        let mut buf = Buffer::into_buf(CaptureStatus::default()).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.netconn.conn(), Opcode::CaptureStatus.to_u32().unwrap()).expect("Couldn't execute CaptureStatus opcode");
+        buf.to_original().expect("couldn't restore status structure")
+    }
+    /// Writes out what's been captured as a pcap file: into the PDDB under `CAPTURE_DICT_NAME` if it's
+    /// given a name, or else to the log as base64, for `tools/pcap_from_log.py` to turn back into a file.
+    /// The capture can still be running.
+    pub fn capture_export(&self, name: Option<&str>) -> Result<(), xous::Error> {
+        let name = name.unwrap_or("");
+        if name.len() > 64 {
+            return Err(xous::Error::OutOfMemory);
+        }
+        let mut buf = Buffer::into_buf(xous_ipc::String::<64>::from_str(name)).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::CaptureExport.to_u32().unwrap())?;
+        match buf.to_original::<NetMemResponse, _>().or(Err(xous::Error::InternalError))? {
+            NetMemResponse::Ok => Ok(()),
+            // there's nothing captured
+            NetMemResponse::Invalid => Err(xous::Error::UseBeforeInit),
+            // the PDDB isn't mounted yet
+            NetMemResponse::AccessDenied => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    pub fn wireguard_status(&self) -> WireguardStatus {
+        let mut buf = Buffer::into_buf(WireguardStatus::default()).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.netconn.conn(), Opcode::WireguardStatus.to_u32().unwrap()).expect("Couldn't execute WireguardStatus opcode");
+        buf.to_original().expect("couldn't restore status structure")
+    }
+    /// Our WireGuard public key, base64 encoded, for the peer's configuration. `None` until the PDDB is
+    /// mounted.
+    pub fn wireguard_public_key(&self) -> Option<String> {
+        let mut buf = Buffer::into_buf(xous_ipc::String::<64>::new()).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.netconn.conn(), Opcode::WireguardPublicKey.to_u32().unwrap()).expect("Couldn't execute WireguardPublicKey opcode");
+        let key = buf.to_original::<xous_ipc::String<64>, _>().expect("couldn't restore key");
+        if key.len() > 0 {
+            Some(key.to_str().to_string())
+        } else {
+            None
+        }
+    }
     pub fn reset(&self) {
         send_message(
             self.netconn.conn(),
@@ -15,8 +15,12 @@ use std_tcplistener::*;
 use com::api::{ComIntSources, Ipv4Conf, NET_MTU};
 use num_traits::*;
 
+mod capture;
 mod connection_manager;
 mod device;
+mod ipv6;
+mod mdns;
+mod wireguard;
 
 use std::collections::{BTreeMap, HashMap, BTreeSet};
 use std::convert::TryInto;
@@ -25,9 +29,10 @@ use xous_ipc::Buffer;
 
 use byteorder::{ByteOrder, NetworkEndian};
 use smoltcp::iface::{Interface, InterfaceBuilder, NeighborCache, Routes};
-use smoltcp::phy::{Device, Medium};
+use smoltcp::phy::{ChecksumCapabilities, Device, Medium};
 use smoltcp::socket::{IcmpEndpoint, IcmpPacketMetadata, IcmpSocket, IcmpSocketBuffer};
-use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr, IpEndpoint};
+use smoltcp::socket::{RawPacketMetadata, RawSocket, RawSocketBuffer};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr, Ipv6Address, IpEndpoint};
 use smoltcp::wire::{Icmpv4Packet, Icmpv4Repr, Icmpv6Packet, Icmpv6Repr};
 use crate::device::NetPhy;
 
@@ -38,7 +43,7 @@ use smoltcp::socket::{
 };
 use smoltcp::iface::SocketHandle;
 use smoltcp::time::{Duration, Instant};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{channel, Sender};
 use std::thread;
 use std::cmp::Ordering as CmpOrdering;
@@ -174,6 +179,123 @@ fn setup_icmp(iface: &mut Interface::<NetPhy>) -> SocketHandle {
     icmp_handle
 }
 
+/// A raw socket for ICMPv6, through which router solicitations go out and router advertisements come
+/// in for SLAAC; see `ipv6.rs`. smoltcp still does neighbor discovery and echo on its own.
+fn setup_ndisc(iface: &mut Interface::<NetPhy>) -> SocketHandle {
+    let rx_buffer = RawSocketBuffer::new(
+        vec![RawPacketMetadata::EMPTY, RawPacketMetadata::EMPTY],
+        vec![0; 1024],
+    );
+    let tx_buffer = RawSocketBuffer::new(
+        vec![RawPacketMetadata::EMPTY],
+        vec![0; ipv6::SOLICITATION_LEN],
+    );
+    let raw_socket = RawSocket::new(IpVersion::Ipv6, IpProtocol::Icmpv6, rx_buffer, tx_buffer);
+    iface.add_socket(raw_socket)
+}
+
+/// The mDNS responder's socket; see `mdns.rs`.
+fn setup_mdns(iface: &mut Interface::<NetPhy>) -> SocketHandle {
+    let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2 * mdns::MDNS_MAX_LEN]);
+    let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2 * mdns::MDNS_MAX_LEN]);
+    let mdns_handle = iface.add_socket(UdpSocket::new(rx_buffer, tx_buffer));
+    iface
+        .get_socket::<UdpSocket>(mdns_handle)
+        .bind(mdns::MDNS_PORT)
+        .expect("couldn't bind to mDNS socket");
+    mdns_handle
+}
+
+/// Puts our address from `config` on the interface, and points the default route at its gateway
+fn set_ipv4_config(iface: &mut Interface::<NetPhy>, config: &Ipv4Conf, prefix_len: u8) {
+    let ip_addr = Ipv4Cidr::new(
+        Ipv4Address::new(
+            config.addr[0],
+            config.addr[1],
+            config.addr[2],
+            config.addr[3],
+        ),
+        prefix_len,
+    );
+    set_ipv4_addr(iface, ip_addr);
+    let default_v4_gw = Ipv4Address::new(
+        config.gtwy[0],
+        config.gtwy[1],
+        config.gtwy[2],
+        config.gtwy[3],
+    );
+
+    // reset the default route, in case it has changed
+    iface.routes_mut().remove_default_ipv4_route();
+    match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
+        Ok(route) => log::info!(
+            "routing table updated successfully [{:?}]",
+            route
+        ),
+        Err(e) => log::error!("routing table update error: {}", e),
+    }
+}
+
+/// `config` as the profile of the network we're on would have it, along with the prefix length of our
+/// address. The EC always hands us a /24.
+fn apply_profile(mut config: Ipv4Conf, profile: Option<&net::profiles::WlanProfile>) -> (Ipv4Conf, u8) {
+    let mut prefix_len = 24;
+    if let Some(profile) = profile {
+        if let Some(ipv4) = profile.static_ipv4 {
+            config.dhcp = com_rs_ref::DhcpState::Bound;
+            config.addr = ipv4.addr.octets();
+            config.gtwy = ipv4.gateway.octets();
+            config.mask = ipv4.mask().octets();
+            config.dns1 = ipv4.gateway.octets();
+            config.dns2 = [0, 0, 0, 0];
+            prefix_len = ipv4.prefix_len;
+        }
+        if let Some(dns) = profile.dns {
+            config.dns1 = dns.octets();
+            config.dns2 = [0, 0, 0, 0];
+        }
+    }
+    (config, prefix_len)
+}
+
+/// Tells the DNS server which IPv4 servers to use: the WireGuard tunnel's while it's up and has one, or
+/// else the ones that DHCP gave us.
+fn notify_dns_ipv4(
+    allclear_hook: &mut XousScalarEndpoint,
+    ipv4_hook: &mut XousScalarEndpoint,
+    net_config: Option<Ipv4Conf>,
+    tunnel: Option<&wireguard::Tunnel>,
+) {
+    allclear_hook.notify();
+    if let Some(dns) = tunnel.and_then(|tunnel| tunnel.config().dns) {
+        ipv4_hook.notify_custom_args([Some(u32::from_be_bytes(dns.0)), None, None, None]);
+    } else if let Some(config) = net_config {
+        ipv4_hook.notify_custom_args([
+            Some(u32::from_be_bytes(config.dns1)),
+            None,
+            None,
+            None,
+        ]);
+        // the current implementation always returns 0.0.0.0 as the second dns,
+        // ignore this if that's what we've got; otherwise, pass it on.
+        if config.dns2 != [0, 0, 0, 0] {
+            ipv4_hook.notify_custom_args([
+                Some(u32::from_be_bytes(config.dns2)),
+                None,
+                None,
+                None,
+            ]);
+        }
+    }
+}
+
+/// The arg1 flag and the arg2 address hint of a ping callback for `remote`; see `NetPingCallback`.
+fn ping_addr_args(remote: &IpAddress) -> (usize, usize) {
+    let ra = remote.as_bytes();
+    let flag = if ra.len() == 16 { NET_PING_IPV6 } else { 0 };
+    (flag, u32::from_be_bytes(ra[ra.len() - 4..].try_into().unwrap()) as usize)
+}
+
 fn main() -> ! {
     log_server::init_wait().unwrap();
     log::set_max_level(log::LevelFilter::Info);
@@ -247,7 +369,6 @@ fn main() -> ! {
 
     // --------------- other link storage -------------
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
-    let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
     let routes = Routes::new(BTreeMap::new());
 
     // build the device
@@ -267,13 +388,31 @@ fn main() -> ! {
         }
     };
     log::debug!("My MAC address is: {:x?}", hw_config.mac);
-    let device = device::NetPhy::new(&xns);
+    // IPv6 addresses come from the MAC, and from router advertisements
+    let mut slaac = ipv6::Slaac::new(hw_config.mac);
+    let [ipv6_first, ipv6_second] = slaac.ipv6_slots();
+    let [loopback_v4, loopback_v6] = device::loopback_cidrs();
+    // no IPv4 address until DHCP is done; see `set_ipv4_addr()` and `ipv6.rs` for the order of the rest.
+    // The WireGuard tunnel's address goes last; see `wireguard::tunnel_cidr()`.
+    let ip_addrs = [
+        IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0),
+        ipv6_first,
+        ipv6_second,
+        loopback_v4,
+        loopback_v6,
+        wireguard::tunnel_cidr(None),
+    ];
+    let device = device::NetPhy::new(&xns, hw_config.mac);
     // needed by ICMP to determine if we should compute checksums
     let device_caps = device.capabilities();
     let medium = device.capabilities().medium;
+    // the mDNS group is joined from the start; the membership report goes out once we have an address
+    let mut multicast_groups = BTreeMap::new();
+    multicast_groups.insert(mdns::MDNS_GROUP_V4, ());
     let mut builder = InterfaceBuilder::new(device, vec![])
         .ip_addrs(ip_addrs)
-        .routes(routes);
+        .routes(routes)
+        .ipv4_multicast_groups(multicast_groups);
     if medium == Medium::Ethernet {
         builder = builder
             .hardware_addr(EthernetAddress::from_bytes(&hw_config.mac).into())
@@ -283,6 +422,9 @@ fn main() -> ! {
 
     // ------------- native variant -----------
     let icmp_handle = setup_icmp(&mut iface);
+    let ndisc_handle = setup_ndisc(&mut iface);
+    let mdns_handle = setup_mdns(&mut iface);
+    let mut mdns_responder = mdns::Responder::new();
     let mut seq: u16 = 0;
     // this record stores the origin time + IP address of the outgoing ping sequence number
     let mut ping_destinations = HashMap::<PingConnection, HashMap<u16, u64>>::new();
@@ -297,6 +439,15 @@ fn main() -> ! {
     let mut dns_ipv6_hook = XousScalarEndpoint::new();
     let mut dns_allclear_hook = XousScalarEndpoint::new();
 
+    // the profile of the wifi network the connection manager last joined
+    let wlan_profile = Arc::new(Mutex::new(None::<net::profiles::WlanProfile>));
+    // the WireGuard tunnel, when it's up; the connection manager keeps its settings
+    let wg_shared = Arc::new(Mutex::new(wireguard::Shared::default()));
+    let mut tunnel: Option<wireguard::Tunnel> = None;
+    // for saving packet captures; made the first time one is saved, as the PDDB comes up after us
+    let mut pddb_poller: Option<pddb::PddbMountPoller> = None;
+    let mut capture_pddb: Option<pddb::Pddb> = None;
+
     // wakeup polling management - kick off worker threads to wake up a poll in the future for certain rx events required by smoltcp
     // this is not reset on connection reset, because the stale timers are still
     // running and there is no way to reset them mid-run.
@@ -362,8 +513,11 @@ fn main() -> ! {
     #[cfg(not(feature = "renode-minimal"))]
     thread::spawn({
         let activity_interval = activity_interval.clone();
+        let wlan_profile = wlan_profile.clone();
+        let wg_shared = wg_shared.clone();
+        let net_conn = net_conn.clone();
         move || {
-            connection_manager::connection_manager(cm_sid, activity_interval);
+            connection_manager::connection_manager(cm_sid, activity_interval, wlan_profile, wg_shared, net_conn);
         }
     });
 
@@ -457,8 +611,9 @@ fn main() -> ! {
                             icmp_repr.emit(&mut icmp_packet, &device_caps.checksum);
                         }
                         IpAddress::Ipv6(_) => {
-                            // not sure if this is a valid thing to do, to just assign the source some number like this??
-                            let src_ipv6 = IpAddress::v6(0xfdaa, 0, 0, 0, 0, 0, 0, 1);
+                            // the checksum covers the source address, so it has to be the one smoltcp will send from
+                            let src_ipv6 = IpAddress::Ipv6(ipv6::source_addr(&iface).expect("interface has no IPv6 address"));
+                            let socket = iface.get_socket::<IcmpSocket>(icmp_handle);
                             let icmp_repr = Icmpv6Repr::EchoRequest {
                                 ident: PING_IDENT,
                                 seq_no: seq,
@@ -1058,8 +1213,6 @@ fn main() -> ! {
                                     log::warn!("Battery is critical! TODO: go into SHIP mode");
                                 }
                                 ComIntSources::WlanIpConfigUpdate => {
-                                    // right now the WLAN implementation only does IPV4. So IPV6 compatibility ends here.
-                                    // if IPV6 gets added to the EC/COM bus, ideally this is one of a couple spots in Xous that needs a tweak.
                                     let config = match com
                                     .wlan_get_config() {
                                         Ok(config) => config,
@@ -1068,6 +1221,8 @@ fn main() -> ! {
                                             continue;
                                         }
                                     };
+                                    // the network's profile can have us use an address of our own, or another DNS server
+                                    let (config, prefix_len) = apply_profile(config, wlan_profile.lock().unwrap().as_ref());
                                     log::info!("Network config acquired: {:?}", config);
                                     log::info!("{}NET.OK,{:?},{}",
                                         xous::BOOKEND_START,
@@ -1076,50 +1231,24 @@ fn main() -> ! {
                                     net_config = Some(config);
 
                                     // note: ARP cache is stale. Maybe that's ok?
-
-                                    let ip_addr = Ipv4Cidr::new(
-                                        Ipv4Address::new(
-                                            config.addr[0],
-                                            config.addr[1],
-                                            config.addr[2],
-                                            config.addr[3],
-                                        ),
-                                        24,
-                                    );
-                                    set_ipv4_addr(&mut iface, ip_addr);
-                                    let default_v4_gw = Ipv4Address::new(
-                                        config.gtwy[0],
-                                        config.gtwy[1],
-                                        config.gtwy[2],
-                                        config.gtwy[3],
-                                    );
-
-                                    // reset the default route, in case it has changed
-                                    iface.routes_mut().remove_default_ipv4_route();
-                                    match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
-                                        Ok(route) => log::info!(
-                                            "routing table updated successfully [{:?}]",
-                                            route
-                                        ),
-                                        Err(e) => log::error!("routing table update error: {}", e),
-                                    }
-                                    dns_allclear_hook.notify();
-                                    dns_ipv4_hook.notify_custom_args([
-                                        Some(u32::from_be_bytes(config.dns1)),
-                                        None,
-                                        None,
-                                        None,
-                                    ]);
-                                    // the current implementation always returns 0.0.0.0 as the second dns,
-                                    // ignore this if that's what we've got; otherwise, pass it on.
-                                    if config.dns2 != [0, 0, 0, 0] {
-                                        dns_ipv4_hook.notify_custom_args([
-                                            Some(u32::from_be_bytes(config.dns2)),
-                                            None,
-                                            None,
-                                            None,
-                                        ]);
+                                    set_ipv4_config(&mut iface, &config, prefix_len);
+                                    notify_dns_ipv4(&mut dns_allclear_hook, &mut dns_ipv4_hook, net_config, tunnel.as_ref());
+                                    // re-join the mDNS group so the membership report goes out from the new address,
+                                    // then tell the LAN who we are
+                                    let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                                    iface.leave_multicast_group(mdns::MDNS_GROUP_V4, timestamp).ok();
+                                    if let Err(e) = iface.join_multicast_group(mdns::MDNS_GROUP_V4, timestamp) {
+                                        log::warn!("couldn't report mDNS group membership: {:?}", e);
                                     }
+                                    mdns_responder.announce(&mut iface, mdns_handle);
+                                    // the EC only does DHCP for IPv4; IPv6 is configured by us, from router advertisements.
+                                    // The solicitation goes out on the next pump.
+                                    slaac.restart(timer.elapsed_ms());
+                                    xous::try_send_message(
+                                        net_conn,
+                                        Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                                    )
+                                    .ok();
                                 }
                                 ComIntSources::WlanRxReady => {
                                     activity_interval.store(0, Ordering::Relaxed); // reset the activity interval to 0
@@ -1214,6 +1343,27 @@ fn main() -> ! {
                         log::debug!("poll error: {}", e);
                     }
                 }
+                // the tunnel's packets go through smoltcp on their way in and out, so it's pumped again if it left any
+                if let Some(tunnel) = tunnel.as_mut() {
+                    if tunnel.pump(&mut iface, now) {
+                        xous::try_send_message(
+                            net_conn,
+                            Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                        )
+                        .ok();
+                    }
+                    let mut shared = wg_shared.lock().unwrap();
+                    shared.status = Some(tunnel.status());
+                    shared.last_handshake = tunnel.last_handshake();
+                }
+                // a poll ends early on errors, which can leave looped-back frames in the device
+                if iface.device().loopback_pending() {
+                    xous::try_send_message(
+                        net_conn,
+                        Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                    )
+                    .ok();
+                }
 
                 // Connect calls take time to establish. This block checks to see if connections
                 // have been made and issues callbacks as necessary.
@@ -1478,6 +1628,51 @@ fn main() -> ! {
                     }
                 }
 
+                // this block runs IPv6 address autoconfiguration
+                log::trace!("pump: ipv6");
+                {
+                    let mut changed = false;
+                    let socket = iface.get_socket::<RawSocket>(ndisc_handle);
+                    while socket.can_recv() {
+                        match socket.recv() {
+                            Ok(packet) => changed |= slaac.handle_packet(packet, now),
+                            Err(_) => break,
+                        }
+                    }
+                    changed |= slaac.expire(now);
+                    let mut solicitation = [0u8; ipv6::SOLICITATION_LEN];
+                    if let Some(len) = slaac.solicitation(now, &mut solicitation) {
+                        if socket.send_slice(&solicitation[..len]).is_ok() {
+                            // pump again so it actually goes out
+                            xous::try_send_message(
+                                net_conn,
+                                Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                            )
+                            .ok();
+                        } else {
+                            log::warn!("couldn't queue IPv6 router solicitation");
+                        }
+                    }
+                    if changed {
+                        slaac.apply(&mut iface);
+                    }
+                    if let Some(dns) = slaac.take_new_dns() {
+                        // an IPv6 address doesn't fit in one 32-bit arg, so it goes out as four of them, MSW first
+                        let words: Vec<u32> = dns.0.chunks(4).map(|w| u32::from_be_bytes(w.try_into().unwrap())).collect();
+                        dns_ipv6_hook.notify_custom_args([Some(words[0]), Some(words[1]), Some(words[2]), Some(words[3])]);
+                    }
+                }
+
+                // this block answers mDNS queries
+                log::trace!("pump: mdns");
+                if mdns_responder.pump(&mut iface, mdns_handle) {
+                    xous::try_send_message(
+                        net_conn,
+                        Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                    )
+                    .ok();
+                }
+
                 // this block contains the ICMP Rx handler. Tx is initiated by an incoming message to the Net crate.
                 log::trace!("pump: icmp");
                 {
@@ -1487,13 +1682,19 @@ fn main() -> ! {
                     }
 
                     if socket.can_recv() {
-                        let (payload, _) = socket
+                        let (payload, from) = socket
                             .recv()
                             .expect("couldn't receive on socket despite asserting availability");
                         log::trace!("icmp payload: {:x?}", payload);
 
                         for (connection, waiting_queue) in ping_destinations.iter_mut() {
                             let remote_addr = connection.remote;
+                            // replies (and errors, which may come from a router) are parsed per address family
+                            match (remote_addr, from) {
+                                (IpAddress::Ipv4(_), IpAddress::Ipv4(_)) | (IpAddress::Ipv6(_), IpAddress::Ipv6(_)) => {}
+                                _ => continue,
+                            }
+                            let (addr_flag, addr_hint) = ping_addr_args(&remote_addr);
                             match remote_addr {
                                 IpAddress::Ipv4(_) => {
                                     let icmp_packet = Icmpv4Packet::new_checked(&payload).unwrap();
@@ -1516,10 +1717,7 @@ fn main() -> ! {
                                                 Message::new_scalar(
                                                     connection.retop,
                                                     NetPingCallback::NoErr.to_usize().unwrap(),
-                                                    u32::from_be_bytes(
-                                                        remote_addr.as_bytes().try_into().unwrap(),
-                                                    )
-                                                        as usize,
+                                                    addr_hint,
                                                     seq_no as usize,
                                                     (now as i64 - packet_timestamp_ms) as usize,
                                                 ),
@@ -1554,10 +1752,7 @@ fn main() -> ! {
                                                 connection.retop,
                                                 NetPingCallback::Unreachable.to_usize().unwrap()
                                                     | (reason_code as usize) << 24,
-                                                u32::from_be_bytes(
-                                                    remote_addr.as_bytes().try_into().unwrap(),
-                                                )
-                                                    as usize,
+                                                addr_hint,
                                                 0,
                                                 0,
                                             ),
@@ -1576,17 +1771,21 @@ fn main() -> ! {
                                 }
 
                                 IpAddress::Ipv6(_) => {
-                                    // NOTE: actually not sure what src_ipv6 should be. This is just from an example.
-                                    let src_ipv6 = IpAddress::v6(0xfdaa, 0, 0, 0, 0, 0, 0, 1);
-                                    let icmp_packet = Icmpv6Packet::new_checked(&payload).unwrap();
-                                    let icmp_repr = Icmpv6Repr::parse(
-                                        &remote_addr,
-                                        &src_ipv6,
+                                    // smoltcp checked the checksum on the way in, and re-made it against whichever
+                                    // of our addresses the packet went to; there is no need to work out which one that was
+                                    let icmp_packet = match Icmpv6Packet::new_checked(&payload) {
+                                        Ok(packet) => packet,
+                                        Err(_) => continue,
+                                    };
+                                    let icmp_repr = match Icmpv6Repr::parse(
+                                        &from,
+                                        &IpAddress::Ipv6(Ipv6Address::UNSPECIFIED),
                                         &icmp_packet,
-                                        &device_caps.checksum,
-                                    )
-                                    .unwrap();
-                                    let ra = remote_addr.as_bytes();
+                                        &ChecksumCapabilities::ignored(),
+                                    ) {
+                                        Ok(repr) => repr,
+                                        Err(_) => continue,
+                                    };
                                     if let Icmpv6Repr::EchoReply { seq_no, data, .. } = icmp_repr {
                                         if let Some(_) = waiting_queue.get(&seq_no) {
                                             let packet_timestamp_ms = NetworkEndian::read_i64(data);
@@ -1595,11 +1794,9 @@ fn main() -> ! {
                                                 connection.cid,
                                                 Message::new_scalar(
                                                     connection.retop,
-                                                    NetPingCallback::NoErr.to_usize().unwrap(),
-                                                    u32::from_be_bytes(ra[..4].try_into().unwrap())
-                                                        as usize,
-                                                    u32::from_be_bytes(ra[12..].try_into().unwrap())
-                                                        as usize,
+                                                    NetPingCallback::NoErr.to_usize().unwrap() | addr_flag,
+                                                    addr_hint,
+                                                    seq_no as usize,
                                                     (now as i64 - packet_timestamp_ms) as usize,
                                                 ),
                                             ) {
@@ -1632,13 +1829,11 @@ fn main() -> ! {
                                             Message::new_scalar(
                                                 connection.retop,
                                                 NetPingCallback::Unreachable.to_usize().unwrap()
+                                                    | addr_flag
                                                     | (reason_code as usize) << 24,
-                                                u32::from_be_bytes(ra[..4].try_into().unwrap())
-                                                    as usize,
-                                                u32::from_be_bytes(ra[8..12].try_into().unwrap())
-                                                    as usize,
-                                                u32::from_be_bytes(ra[12..].try_into().unwrap())
-                                                    as usize,
+                                                addr_hint,
+                                                0,
+                                                0,
                                             ),
                                         ) {
                                             Ok(_) => {}
@@ -1667,13 +1862,13 @@ fn main() -> ! {
                     ping_destinations.retain(|conn, v|
                         if v.len() == 0 {
                             log::debug!("Dropping ping record for {:?}", conn.remote);
-                            let ra = conn.remote.as_bytes();
+                            let (addr_flag, addr_hint) = ping_addr_args(&conn.remote);
                             match xous::send_message(conn.cid,
                                 Message::new_scalar( // we should wait if the queue is full, as the "Drop" message is important
                                     conn.retop,
-                                    NetPingCallback::Drop.to_usize().unwrap(),
-                                    u32::from_be_bytes(ra[..4].try_into().unwrap()) as usize,
-                                    if ra.len() == 16 {u32::from_be_bytes(ra[12..16].try_into().unwrap()) as usize} else {0},
+                                    NetPingCallback::Drop.to_usize().unwrap() | addr_flag,
+                                    addr_hint,
+                                    0,
                                     0,
                                 )
                             ) {
@@ -1702,15 +1897,15 @@ fn main() -> ! {
 
                     // now: sequence through the waiting_queue and remove entries that have hit our timeout
                     for (conn, waiting_queue) in ping_destinations.iter_mut() {
-                        let ra = conn.remote.as_bytes();
+                        let (addr_flag, addr_hint) = ping_addr_args(&conn.remote);
                         waiting_queue.retain(|&seq, &mut start_time|
                             if now - start_time > ping_timeout_ms as u64 {
                                 log::debug!("timeout - removing {:?}, {}", conn.remote, seq);
                                 match xous::try_send_message(conn.cid,
                                     Message::new_scalar( // we should wait if the queue is full, as the "Drop" message is important
                                         conn.retop,
-                                        NetPingCallback::Timeout.to_usize().unwrap(),
-                                        u32::from_be_bytes(ra[..4].try_into().unwrap()) as usize,
+                                        NetPingCallback::Timeout.to_usize().unwrap() | addr_flag,
+                                        addr_hint,
                                         seq as usize,
                                         (now - start_time) as usize,
                                     )
@@ -1737,7 +1932,12 @@ fn main() -> ! {
                 // establish our next check-up interval
                 log::trace!("pump: checkup");
                 let timestamp = Instant::from_millis(now as i64);
-                if let Some(delay) = iface.poll_delay(timestamp) {
+                let tunnel_delay = tunnel.as_ref().and_then(|tunnel| tunnel.poll_delay(now)).map(Duration::from_millis);
+                let delay = match (iface.poll_delay(timestamp), tunnel_delay) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                if let Some(delay) = delay {
                     const RANGE_MS: u64 = 100; // this will define the responsivity to "future" events that maybe 100's of ms away
                     let mut wakeup_needed = false;
                     // round all short delays to within the next quantum
@@ -1802,6 +2002,72 @@ fn main() -> ! {
                 };
                 buffer.replace(ser).expect("couldn't return config");
             }
+            Some(Opcode::GetIpv6Config) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                buffer.replace(slaac.config(timer.elapsed_ms())).expect("couldn't return config");
+            }
+            Some(Opcode::MdnsSetName) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let name = buffer.to_original::<xous_ipc::String<64>, _>().unwrap();
+                let response = if mdns_responder.set_hostname(name.as_str().unwrap_or("")) {
+                    mdns_responder.announce(&mut iface, mdns_handle);
+                    NetMemResponse::Ok
+                } else {
+                    NetMemResponse::Invalid
+                };
+                buffer.replace(response).expect("couldn't return response");
+                xous::try_send_message(
+                    net_conn,
+                    Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                )
+                .ok();
+            }
+            Some(Opcode::MdnsRegisterService) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let record = buffer.to_original::<MdnsService, _>().unwrap();
+                let txt: Vec<std::string::String> = record
+                    .txt
+                    .iter()
+                    .filter_map(|entry| entry.as_ref().and_then(|e| e.as_str().ok()).map(|e| e.to_string()))
+                    .collect();
+                let response = if mdns_responder.register(record.service.as_str().unwrap_or(""), record.port, txt) {
+                    mdns_responder.announce(&mut iface, mdns_handle);
+                    NetMemResponse::Ok
+                } else {
+                    NetMemResponse::Invalid
+                };
+                buffer.replace(response).expect("couldn't return response");
+                xous::try_send_message(
+                    net_conn,
+                    Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                )
+                .ok();
+            }
+            Some(Opcode::MdnsUnregisterService) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let service = buffer.to_original::<xous_ipc::String<64>, _>().unwrap();
+                let service = service.as_str().unwrap_or("");
+                mdns_responder.goodbye(&mut iface, mdns_handle, service);
+                let response = if mdns_responder.unregister(service) {
+                    NetMemResponse::Ok
+                } else {
+                    NetMemResponse::Invalid
+                };
+                buffer.replace(response).expect("couldn't return response");
+                xous::try_send_message(
+                    net_conn,
+                    Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                )
+                .ok();
+            }
             Some(Opcode::SubscribeWifiStats) => {
                 msg.forward(
                     cm_cid,
@@ -1814,6 +2080,147 @@ fn main() -> ! {
                     connection_manager::ConnectionManagerOpcode::UnsubWifiStats as _)
                 .expect("couldn't forward unsub request");
             },
+            Some(Opcode::WireguardSetConfig) => {
+                msg.forward(
+                    cm_cid,
+                    connection_manager::ConnectionManagerOpcode::WireguardSetConfig as _)
+                .expect("couldn't forward WireGuard config");
+            }
+            Some(Opcode::WireguardEnable) => {
+                msg.forward(
+                    cm_cid,
+                    connection_manager::ConnectionManagerOpcode::WireguardEnable as _)
+                .expect("couldn't forward WireGuard enable");
+            }
+            Some(Opcode::WireguardStatus) => {
+                msg.forward(
+                    cm_cid,
+                    connection_manager::ConnectionManagerOpcode::WireguardStatus as _)
+                .expect("couldn't forward WireGuard status request");
+            }
+            Some(Opcode::WireguardPublicKey) => {
+                msg.forward(
+                    cm_cid,
+                    connection_manager::ConnectionManagerOpcode::WireguardPublicKey as _)
+                .expect("couldn't forward WireGuard key request");
+            }
+            Some(Opcode::WlanStaticConfig) => msg_scalar_unpack!(msg, _, _, _, _, {
+                // there's no DHCP result to take the rest of the configuration from
+                let profile = wlan_profile.lock().unwrap().clone();
+                if profile.as_ref().map_or(true, |p| p.static_ipv4.is_none()) {
+                    log::warn!("static configuration asked for, but the network has none");
+                    continue;
+                }
+                let (config, prefix_len) = apply_profile(hw_config, profile.as_ref());
+                log::info!("Static network config: {:?}", config);
+                net_config = Some(config);
+                set_ipv4_config(&mut iface, &config, prefix_len);
+                notify_dns_ipv4(&mut dns_allclear_hook, &mut dns_ipv4_hook, net_config, tunnel.as_ref());
+                let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                iface.leave_multicast_group(mdns::MDNS_GROUP_V4, timestamp).ok();
+                if let Err(e) = iface.join_multicast_group(mdns::MDNS_GROUP_V4, timestamp) {
+                    log::warn!("couldn't report mDNS group membership: {:?}", e);
+                }
+                mdns_responder.announce(&mut iface, mdns_handle);
+                slaac.restart(timer.elapsed_ms());
+                xous::try_send_message(
+                    net_conn,
+                    Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                )
+                .ok();
+            }),
+            Some(Opcode::WireguardUpdate) => msg_scalar_unpack!(msg, _, _, _, _, {
+                // whatever the tunnel was, it's going to be something else now
+                if let Some(mut old) = tunnel.take() {
+                    old.close(&mut iface);
+                }
+                let request = {
+                    let mut shared = wg_shared.lock().unwrap();
+                    shared.status = None;
+                    shared.last_handshake = None;
+                    shared.request.take()
+                };
+                if let Some((config, secret)) = request {
+                    let mut wg_trng = trng::Trng::new(&xns).unwrap();
+                    let mut new = wireguard::Tunnel::new(config, secret, Box::new(move |buf: &mut [u8]| wg_trng.fill_bytes(buf)));
+                    new.open(&mut iface, hw_config.mac);
+                    tunnel = Some(new);
+                }
+                notify_dns_ipv4(&mut dns_allclear_hook, &mut dns_ipv4_hook, net_config, tunnel.as_ref());
+                xous::try_send_message(
+                    net_conn,
+                    Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+                )
+                .ok();
+            }),
+            Some(Opcode::CaptureStart) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let config = buffer.to_original::<CaptureConfig, _>().unwrap();
+                let filter = config.filter.as_str().unwrap_or("");
+                // the frames are stamped with the stack's time, which is since boot
+                let unix_micros = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_micros() as u64)
+                    .unwrap_or(0);
+                let now = Instant::from_millis(timer.elapsed_ms() as i64);
+                let response = match capture::Capture::new(filter, config.ring_size as usize, now, unix_micros) {
+                    Some(capture) => {
+                        log::info!("capturing '{}' into {} bytes", filter, config.ring_size);
+                        iface.device_mut().set_capture(Some(capture));
+                        NetMemResponse::Ok
+                    }
+                    None => NetMemResponse::Invalid,
+                };
+                buffer.replace(response).expect("couldn't return response");
+            }
+            Some(Opcode::CaptureStop) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                let frames = match iface.device_mut().capture_mut() {
+                    Some(capture) => {
+                        capture.stop();
+                        capture.status().frames
+                    }
+                    None => 0,
+                };
+                xous::return_scalar(msg.sender, frames as usize).unwrap();
+            }),
+            Some(Opcode::CaptureStatus) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let status = iface.device_mut().capture_mut().map(|capture| capture.status()).unwrap_or_default();
+                buffer.replace(status).expect("couldn't return capture status");
+            }
+            Some(Opcode::CaptureExport) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let name = buffer.to_original::<xous_ipc::String<64>, _>().unwrap();
+                let name = name.as_str().unwrap_or("");
+                // the network stands still while it's written out, so nothing new is captured meanwhile
+                let response = match iface.device_mut().capture_mut() {
+                    Some(capture) if !capture.is_empty() => {
+                        let pcap = capture.to_pcap();
+                        if name.is_empty() {
+                            capture::log_pcap(&pcap);
+                            NetMemResponse::Ok
+                        } else if !pddb_poller.get_or_insert_with(pddb::PddbMountPoller::new).is_mounted_nonblocking() {
+                            NetMemResponse::AccessDenied
+                        } else {
+                            match capture::save(capture_pddb.get_or_insert_with(pddb::Pddb::new), name, &pcap) {
+                                Ok(_) => NetMemResponse::Ok,
+                                Err(e) => {
+                                    log::warn!("couldn't save the capture as {}: {:?}", name, e);
+                                    NetMemResponse::LibraryError
+                                }
+                            }
+                        }
+                    }
+                    _ => NetMemResponse::Invalid,
+                };
+                buffer.replace(response).expect("couldn't return response");
+            }
             Some(Opcode::FetchSsidList) => {
                 let mut buffer = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
@@ -1879,6 +2286,8 @@ fn main() -> ! {
 
                 // note: ARP cache isn't reset
                 iface.routes_mut().remove_default_ipv4_route();
+                slaac.clear();
+                slaac.apply(&mut iface);
                 dns_allclear_hook.notify();
 
                 send_message(
@@ -0,0 +1,564 @@
+//! A multicast DNS responder (RFC 6762), which answers for `<name>.local` and advertises services
+//! with DNS-SD (RFC 6763) as `<name>.<service>.local`.
+//!
+//! The 224.0.0.251 group is joined when the interface is built. smoltcp has no MLD, so ff02::fb
+//! can't be joined; IPv6 queries are answered if the network floods them to us anyway.
+//!
+//! Queries from port 5353 are answered over multicast, unless they ask for a unicast reply; queries
+//! from any other port get a "legacy unicast" reply (section 6.7). No probing is done, so a name
+//! conflict on the LAN goes unnoticed, and known-answer suppression is not implemented.
+
+use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::socket::UdpSocket;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address, Ipv6Address};
+
+use crate::device::NetPhy;
+
+pub(crate) const MDNS_PORT: u16 = 5353;
+pub(crate) const MDNS_GROUP_V4: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+const MDNS_GROUP_V6: Ipv6Address = Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xfb]);
+/// the largest message we send or take in; big enough for the host and a handful of services
+pub(crate) const MDNS_MAX_LEN: usize = 1472;
+
+const DEFAULT_HOSTNAME: &str = "precursor";
+/// the services that can be advertised at once
+const MAX_SERVICES: usize = 8;
+/// the name that DNS-SD browsers query to find out which services exist
+const SERVICES_NAME: &str = "_services._dns-sd._udp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+/// in a question, the top bit of the class asks for a unicast reply
+const CLASS_UNICAST_RESPONSE: u16 = 0x8000;
+/// in a record, the top bit of the class says it replaces what caches hold for its name
+const CLASS_CACHE_FLUSH: u16 = 0x8000;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const OPCODE_MASK: u16 = 0x7800;
+
+/// record TTLs recommended by section 10: short for the ones that hold addresses, long for the rest
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+/// legacy unicast replies go to resolvers that aren't aware of mDNS, and shouldn't be cached for long
+const LEGACY_TTL: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Rdata {
+    A(Ipv4Address),
+    Aaaa(Ipv6Address),
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Record {
+    name: String,
+    ttl: u32,
+    rdata: Rdata,
+}
+impl Record {
+    fn new(name: &str, rdata: Rdata) -> Record {
+        let ttl = match rdata {
+            Rdata::A(_) | Rdata::Aaaa(_) | Rdata::Srv { .. } => HOST_TTL,
+            _ => OTHER_TTL,
+        };
+        Record { name: name.to_string(), ttl, rdata }
+    }
+    fn rtype(&self) -> u16 {
+        match self.rdata {
+            Rdata::A(_) => TYPE_A,
+            Rdata::Aaaa(_) => TYPE_AAAA,
+            Rdata::Ptr(_) => TYPE_PTR,
+            Rdata::Srv { .. } => TYPE_SRV,
+            Rdata::Txt(_) => TYPE_TXT,
+        }
+    }
+    /// PTR records are shared by every device offering a service; the rest are ours alone
+    fn is_unique(&self) -> bool {
+        !matches!(self.rdata, Rdata::Ptr(_))
+    }
+    fn emit(&self, out: &mut Vec<u8>, legacy: bool) {
+        emit_name(out, &self.name);
+        out.extend_from_slice(&self.rtype().to_be_bytes());
+        let class = if self.is_unique() && !legacy { CLASS_IN | CLASS_CACHE_FLUSH } else { CLASS_IN };
+        out.extend_from_slice(&class.to_be_bytes());
+        let ttl = if legacy { self.ttl.min(LEGACY_TTL) } else { self.ttl };
+        out.extend_from_slice(&ttl.to_be_bytes());
+        let mut rdata = Vec::new();
+        match &self.rdata {
+            Rdata::A(addr) => rdata.extend_from_slice(addr.as_bytes()),
+            Rdata::Aaaa(addr) => rdata.extend_from_slice(addr.as_bytes()),
+            Rdata::Ptr(target) => emit_name(&mut rdata, target),
+            Rdata::Srv { port, target } => {
+                rdata.extend_from_slice(&[0, 0, 0, 0]); // priority and weight
+                rdata.extend_from_slice(&port.to_be_bytes());
+                emit_name(&mut rdata, target);
+            }
+            Rdata::Txt(entries) => {
+                // an empty TXT record is a single empty string (RFC 6763 section 6.1)
+                if entries.is_empty() {
+                    rdata.push(0);
+                }
+                for entry in entries.iter() {
+                    rdata.push(entry.len() as u8);
+                    rdata.extend_from_slice(entry.as_bytes());
+                }
+            }
+        }
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&rdata);
+    }
+}
+
+struct Service {
+    /// the service type and protocol, e.g. `_http._tcp`
+    service: String,
+    port: u16,
+    txt: Vec<String>,
+}
+
+pub(crate) struct Responder {
+    hostname: String,
+    services: Vec<Service>,
+}
+impl Responder {
+    pub(crate) fn new() -> Responder {
+        Responder { hostname: DEFAULT_HOSTNAME.to_string(), services: Vec::new() }
+    }
+    /// `name` is a single DNS label: letters, digits and hyphens. Returns `false` if it isn't.
+    pub(crate) fn set_hostname(&mut self, name: &str) -> bool {
+        let valid = !name.is_empty()
+            && name.len() <= 63
+            && !name.starts_with('-')
+            && !name.ends_with('-')
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        if valid {
+            self.hostname = name.to_string();
+        }
+        valid
+    }
+    /// Adds `service` (e.g. `_http._tcp`) on `port`, or updates it if it's already there. Returns
+    /// `false` if the service type is malformed, the TXT entries are too long, or there's no room.
+    pub(crate) fn register(&mut self, service: &str, port: u16, txt: Vec<String>) -> bool {
+        let service = service.to_ascii_lowercase();
+        if !is_service_type(&service) || txt.iter().any(|entry| entry.is_empty() || entry.len() > 255) {
+            return false;
+        }
+        if let Some(existing) = self.services.iter_mut().find(|s| s.service == service) {
+            existing.port = port;
+            existing.txt = txt;
+            return true;
+        }
+        if self.services.len() >= MAX_SERVICES {
+            return false;
+        }
+        self.services.push(Service { service, port, txt });
+        true
+    }
+    /// Returns `false` if `service` wasn't registered.
+    pub(crate) fn unregister(&mut self, service: &str) -> bool {
+        let service = service.to_ascii_lowercase();
+        let before = self.services.len();
+        self.services.retain(|s| s.service != service);
+        self.services.len() != before
+    }
+
+    fn host(&self) -> String {
+        format!("{}.local", self.hostname)
+    }
+    fn instance(&self, service: &Service) -> String {
+        format!("{}.{}.local", self.hostname, service.service)
+    }
+    fn address_records(&self, addrs: &[IpAddress], qtype: u16, records: &mut Vec<Record>) {
+        let host = self.host();
+        for addr in addrs.iter() {
+            match addr {
+                IpAddress::Ipv4(v4) if qtype == TYPE_A || qtype == TYPE_ANY => {
+                    records.push(Record::new(&host, Rdata::A(*v4)))
+                }
+                IpAddress::Ipv6(v6) if qtype == TYPE_AAAA || qtype == TYPE_ANY => {
+                    records.push(Record::new(&host, Rdata::Aaaa(*v6)))
+                }
+                _ => {}
+            }
+        }
+    }
+    fn srv_record(&self, service: &Service) -> Record {
+        Record::new(&self.instance(service), Rdata::Srv { port: service.port, target: self.host() })
+    }
+    fn txt_record(&self, service: &Service) -> Record {
+        Record::new(&self.instance(service), Rdata::Txt(service.txt.clone()))
+    }
+    /// The records that answer `qname`/`qtype` go in `answers`; the ones a browser will ask for next,
+    /// as section 12 of RFC 6763 suggests, go in `additional`.
+    fn answer(&self, qname: &str, qtype: u16, addrs: &[IpAddress], answers: &mut Vec<Record>, additional: &mut Vec<Record>) {
+        if qname == self.host().to_ascii_lowercase() {
+            self.address_records(addrs, qtype, answers);
+        } else if qname == SERVICES_NAME {
+            if qtype == TYPE_PTR || qtype == TYPE_ANY {
+                for service in self.services.iter() {
+                    answers.push(Record::new(SERVICES_NAME, Rdata::Ptr(format!("{}.local", service.service))));
+                }
+            }
+        } else {
+            for service in self.services.iter() {
+                let instance = self.instance(service);
+                if qname == format!("{}.local", service.service) {
+                    if qtype == TYPE_PTR || qtype == TYPE_ANY {
+                        answers.push(Record::new(qname, Rdata::Ptr(instance)));
+                        additional.push(self.srv_record(service));
+                        additional.push(self.txt_record(service));
+                        self.address_records(addrs, TYPE_ANY, additional);
+                    }
+                } else if qname == instance.to_ascii_lowercase() {
+                    if qtype == TYPE_SRV || qtype == TYPE_ANY {
+                        answers.push(self.srv_record(service));
+                        self.address_records(addrs, TYPE_ANY, additional);
+                    }
+                    if qtype == TYPE_TXT || qtype == TYPE_ANY {
+                        answers.push(self.txt_record(service));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Works out the reply to the mDNS `query`, if it asks about anything of ours. `legacy` is set
+    /// when the query didn't come from port 5353. Also returns whether a unicast reply was asked for.
+    fn respond(&self, query: &[u8], addrs: &[IpAddress], legacy: bool) -> Option<(Vec<u8>, bool)> {
+        if query.len() < 12 {
+            return None;
+        }
+        let flags = u16::from_be_bytes([query[2], query[3]]);
+        if flags & (FLAG_RESPONSE | OPCODE_MASK) != 0 {
+            return None;
+        }
+        let qdcount = u16::from_be_bytes([query[4], query[5]]);
+        let mut index = 12;
+        let mut questions = Vec::new();
+        let mut unicast = false;
+        let mut answers = Vec::new();
+        let mut additional = Vec::new();
+        for _ in 0..qdcount {
+            let (qname, next) = read_name(query, index)?;
+            let fields = query.get(next..next + 4)?;
+            let qtype = u16::from_be_bytes([fields[0], fields[1]]);
+            let qclass = u16::from_be_bytes([fields[2], fields[3]]);
+            index = next + 4;
+            let class = qclass & !CLASS_UNICAST_RESPONSE;
+            if class != CLASS_IN && class != CLASS_ANY {
+                continue;
+            }
+            unicast |= qclass & CLASS_UNICAST_RESPONSE != 0;
+            self.answer(&qname, qtype, addrs, &mut answers, &mut additional);
+            questions.push((qname, qtype));
+        }
+        dedup(&mut answers);
+        if answers.is_empty() {
+            return None;
+        }
+        additional.retain(|r| !answers.contains(r));
+        dedup(&mut additional);
+        // a legacy reply carries the query's ID and questions, like a unicast DNS server's would
+        let (id, questions) = if legacy {
+            (u16::from_be_bytes([query[0], query[1]]), &questions[..])
+        } else {
+            (0, &[][..])
+        };
+        let mut reply = message(id, questions, &answers, &additional, legacy);
+        if reply.len() > MDNS_MAX_LEN {
+            reply = message(id, questions, &answers, &[], legacy);
+        }
+        if reply.len() > MDNS_MAX_LEN {
+            log::warn!("mDNS reply is {} bytes, too big to send", reply.len());
+            return None;
+        }
+        Some((reply, unicast))
+    }
+
+    /// An unsolicited response with all of our records, to send when our address or records change.
+    /// `ttl` overrides the record TTLs; a TTL of 0 is a goodbye that drops the records from caches.
+    fn announcement(&self, addrs: &[IpAddress], services: &[&Service], ttl: Option<u32>) -> Vec<u8> {
+        let mut records = Vec::new();
+        if ttl.is_none() {
+            self.address_records(addrs, TYPE_ANY, &mut records);
+        }
+        for service in services.iter() {
+            let type_name = format!("{}.local", service.service);
+            records.push(Record::new(SERVICES_NAME, Rdata::Ptr(type_name.clone())));
+            records.push(Record::new(&type_name, Rdata::Ptr(self.instance(service))));
+            records.push(self.srv_record(service));
+            records.push(self.txt_record(service));
+        }
+        if let Some(ttl) = ttl {
+            for record in records.iter_mut() {
+                record.ttl = ttl;
+            }
+        }
+        message(0, &[], &records, &[], false)
+    }
+
+    /// Answers the queries waiting on the mDNS socket. Returns `true` if anything was queued to send.
+    pub(crate) fn pump(&self, iface: &mut Interface::<NetPhy>, handle: SocketHandle) -> bool {
+        let addrs = host_addrs(iface);
+        let socket = iface.get_socket::<UdpSocket>(handle);
+        let mut query = [0u8; MDNS_MAX_LEN];
+        let mut sent = false;
+        while socket.can_recv() {
+            let (len, from) = match socket.recv_slice(&mut query) {
+                Ok(r) => r,
+                Err(_) => break,
+            };
+            let legacy = from.port != MDNS_PORT;
+            if let Some((reply, unicast)) = self.respond(&query[..len], &addrs, legacy) {
+                let dest = if legacy || unicast { from } else { group_for(&from.addr) };
+                match socket.send_slice(&reply, dest) {
+                    Ok(_) => sent = true,
+                    Err(e) => log::warn!("couldn't queue mDNS reply: {:?}", e),
+                }
+            }
+        }
+        sent
+    }
+    /// Tells the LAN about our name and services. Does nothing until we have an IPv4 address, since
+    /// that's the only group we're in. Returns `true` if the announcement was queued.
+    pub(crate) fn announce(&self, iface: &mut Interface::<NetPhy>, handle: SocketHandle) -> bool {
+        let addrs = host_addrs(iface);
+        if !addrs.iter().any(|a| matches!(a, IpAddress::Ipv4(_))) {
+            return false;
+        }
+        let services: Vec<&Service> = self.services.iter().collect();
+        self.send(iface, handle, &self.announcement(&addrs, &services, None))
+    }
+    /// Tells the LAN that `service` is going away; call it before unregistering the service.
+    pub(crate) fn goodbye(&self, iface: &mut Interface::<NetPhy>, handle: SocketHandle, service: &str) -> bool {
+        let service = service.to_ascii_lowercase();
+        let services: Vec<&Service> = self.services.iter().filter(|s| s.service == service).collect();
+        if services.is_empty() || iface.ipv4_addr().map(|a| a.is_unspecified()).unwrap_or(true) {
+            return false;
+        }
+        self.send(iface, handle, &self.announcement(&[], &services, Some(0)))
+    }
+    fn send(&self, iface: &mut Interface::<NetPhy>, handle: SocketHandle, packet: &[u8]) -> bool {
+        if packet.len() > MDNS_MAX_LEN {
+            log::warn!("mDNS announcement is {} bytes, too big to send", packet.len());
+            return false;
+        }
+        let socket = iface.get_socket::<UdpSocket>(handle);
+        match socket.send_slice(packet, IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT)) {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("couldn't queue mDNS announcement: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
+/// The addresses we answer `<name>.local` with: everything but the loopback and unspecified ones, and the
+/// WireGuard tunnel's, which is no business of the LAN's.
+fn host_addrs(iface: &Interface::<NetPhy>) -> Vec<IpAddress> {
+    let tunnel = iface.device().tunnel().map(|tap| tap.address());
+    iface
+        .ip_addrs()
+        .iter()
+        .map(|cidr| cidr.address())
+        .filter(|addr| match addr {
+            IpAddress::Ipv4(v4) => !v4.is_unspecified() && !v4.is_loopback() && Some(*v4) != tunnel,
+            IpAddress::Ipv6(v6) => !v6.is_unspecified() && !v6.is_loopback(),
+            _ => false,
+        })
+        .collect()
+}
+
+fn group_for(addr: &IpAddress) -> IpEndpoint {
+    match addr {
+        IpAddress::Ipv6(_) => IpEndpoint::new(MDNS_GROUP_V6.into(), MDNS_PORT),
+        _ => IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT),
+    }
+}
+
+/// `_name._tcp` or `_name._udp`
+fn is_service_type(service: &str) -> bool {
+    let mut labels = service.split('.');
+    match (labels.next(), labels.next(), labels.next()) {
+        (Some(name), Some(proto), None) => {
+            name.len() > 1
+                && name.len() <= 16
+                && name.starts_with('_')
+                && name[1..].bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && (proto == "_tcp" || proto == "_udp")
+        }
+        _ => false,
+    }
+}
+
+fn dedup(records: &mut Vec<Record>) {
+    let mut unique: Vec<Record> = Vec::with_capacity(records.len());
+    for record in records.drain(..) {
+        if !unique.contains(&record) {
+            unique.push(record);
+        }
+    }
+    *records = unique;
+}
+
+fn message(id: u16, questions: &[(String, u16)], answers: &[Record], additional: &[Record], legacy: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&(FLAG_RESPONSE | FLAG_AUTHORITATIVE).to_be_bytes());
+    out.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(additional.len() as u16).to_be_bytes());
+    for (qname, qtype) in questions.iter() {
+        emit_name(&mut out, qname);
+        out.extend_from_slice(&qtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    for record in answers.iter().chain(additional.iter()) {
+        record.emit(&mut out, legacy);
+    }
+    out
+}
+
+/// Names go out uncompressed; our messages are small enough that it doesn't matter.
+fn emit_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+/// Reads the (possibly compressed) name at `index` of `packet`, in lower case. Returns the name
+/// and the index just past it.
+fn read_name(packet: &[u8], mut index: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let len = *packet.get(index)? as usize;
+        if len & 0xc0 == 0xc0 {
+            // a pointer; bound the number of them, so a loop of pointers can't hang us
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            if end.is_none() {
+                end = Some(index + 2);
+            }
+            index = ((len & 0x3f) << 8) | *packet.get(index + 1)? as usize;
+        } else if len > 63 {
+            return None;
+        } else if len == 0 {
+            return Some((name, end.unwrap_or(index + 1)));
+        } else {
+            let label = packet.get(index + 1..index + 1 + len)?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(&std::str::from_utf8(label).ok()?.to_ascii_lowercase());
+            index += 1 + len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, qname: &str, qtype: u16, qclass: u16) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        emit_name(&mut packet, qname);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&qclass.to_be_bytes());
+        packet
+    }
+    fn addrs() -> Vec<IpAddress> {
+        vec![IpAddress::v4(10, 0, 0, 5), IpAddress::Ipv6(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 5))]
+    }
+    fn counts(reply: &[u8]) -> [u16; 4] {
+        let mut c = [0u16; 4];
+        for (i, count) in c.iter_mut().enumerate() {
+            *count = u16::from_be_bytes([reply[4 + i * 2], reply[5 + i * 2]]);
+        }
+        c
+    }
+
+    #[test]
+    fn answers_for_the_host_name() {
+        let responder = Responder::new();
+        let (reply, unicast) = responder.respond(&query(7, "Precursor.local", TYPE_A, CLASS_IN), &addrs(), false).unwrap();
+        assert!(!unicast);
+        assert_eq!(&reply[..2], &[0, 0]);
+        assert_eq!(counts(&reply), [0, 1, 0, 0]);
+        assert_eq!(&reply[reply.len() - 4..], &[10, 0, 0, 5]);
+
+        let (_, unicast) = responder
+            .respond(&query(7, "precursor.local", TYPE_ANY, CLASS_IN | CLASS_UNICAST_RESPONSE), &addrs(), false)
+            .unwrap();
+        assert!(unicast);
+        assert!(responder.respond(&query(7, "other.local", TYPE_A, CLASS_IN), &addrs(), false).is_none());
+    }
+
+    #[test]
+    fn legacy_replies_echo_the_query() {
+        let responder = Responder::new();
+        let (reply, _) = responder.respond(&query(0x1234, "precursor.local", TYPE_A, CLASS_IN), &addrs(), true).unwrap();
+        assert_eq!(&reply[..2], &[0x12, 0x34]);
+        assert_eq!(counts(&reply), [1, 1, 0, 0]);
+        // no cache-flush bit, and the TTL is capped
+        let record = 12 + 17 + 4 + 17;
+        assert_eq!(&reply[record..record + 8], &[0, 1, 0, 1, 0, 0, 0, LEGACY_TTL as u8]);
+    }
+
+    #[test]
+    fn browses_services() {
+        let mut responder = Responder::new();
+        assert!(!responder.register("http", 80, vec![]));
+        assert!(responder.register("_http._tcp", 80, vec!["path=/".to_string()]));
+        let (reply, _) = responder.respond(&query(0, SERVICES_NAME, TYPE_PTR, CLASS_IN), &addrs(), false).unwrap();
+        assert_eq!(counts(&reply), [0, 1, 0, 0]);
+        let (reply, _) = responder.respond(&query(0, "_http._tcp.local", TYPE_PTR, CLASS_IN), &addrs(), false).unwrap();
+        // PTR, then SRV, TXT and both addresses
+        assert_eq!(counts(&reply), [0, 1, 0, 4]);
+        let (reply, _) =
+            responder.respond(&query(0, "precursor._http._tcp.local", TYPE_SRV, CLASS_IN), &addrs(), false).unwrap();
+        assert_eq!(counts(&reply), [0, 1, 0, 2]);
+        assert!(responder.unregister("_HTTP._tcp"));
+        assert!(responder.respond(&query(0, "_http._tcp.local", TYPE_PTR, CLASS_IN), &addrs(), false).is_none());
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        let mut packet = vec![0u8; 12];
+        emit_name(&mut packet, "Precursor.local");
+        packet.extend_from_slice(&[4, b'_', b'x', b'y', b'z', 0xc0, 12 + 10]);
+        assert_eq!(read_name(&packet, 12), Some(("precursor.local".to_string(), 29)));
+        assert_eq!(read_name(&packet, 29), Some(("_xyz.local".to_string(), packet.len())));
+        // a pointer to itself
+        let looped = [0xc0, 0];
+        assert_eq!(read_name(&looped, 0), None);
+    }
+
+    #[test]
+    fn hostnames_are_single_labels() {
+        let mut responder = Responder::new();
+        assert!(responder.set_hostname("my-precursor"));
+        assert!(!responder.set_hostname("my.precursor"));
+        assert!(!responder.set_hostname("-x"));
+        assert!(!responder.set_hostname(""));
+        assert_eq!(responder.host(), "my-precursor.local");
+    }
+}
@@ -0,0 +1,235 @@
+//! Saved wifi networks. A network's password stays where it always was, in `AP_DICT_NAME` under its
+//! SSID; the rest of what we know about it is kept under the same key in `AP_PROFILE_DICT_NAME`, as
+//! one `name=value` per line. Networks that were saved before there were profiles get the defaults.
+
+use crate::api::{AP_DICT_NAME, AP_PROFILE_DICT_NAME};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::Ipv4Addr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An address to use on a network instead of asking DHCP for one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StaticIpv4 {
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Ipv4Addr,
+}
+impl StaticIpv4 {
+    /// `a.b.c.d/n` and the gateway's address
+    pub fn parse(cidr: &str, gateway: &str) -> Option<StaticIpv4> {
+        let (addr, prefix_len) = cidr.split_once('/')?;
+        let prefix_len = prefix_len.parse::<u8>().ok().filter(|&len| len > 0 && len <= 32)?;
+        Some(StaticIpv4 { addr: addr.parse().ok()?, prefix_len, gateway: gateway.parse().ok()? })
+    }
+    pub fn mask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX << (32 - self.prefix_len as u32))
+    }
+}
+
+/// What the connection manager knows about a saved network, other than its password
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WlanProfile {
+    pub ssid: String,
+    /// networks with a higher priority are tried first
+    pub priority: u8,
+    /// whether the connection manager joins it on its own
+    pub auto_join: bool,
+    /// it doesn't answer scans, so it's tried whether it was seen or not
+    pub hidden: bool,
+    /// used instead of DHCP
+    pub static_ipv4: Option<StaticIpv4>,
+    /// used instead of the DNS server that DHCP hands out; with a static address and no DNS server,
+    /// the gateway is asked
+    pub dns: Option<Ipv4Addr>,
+    /// when we last got onto it, in seconds since the Unix epoch
+    pub last_connected: Option<u64>,
+}
+impl WlanProfile {
+    /// The defaults, which are what networks saved without a profile get
+    pub fn new(ssid: &str) -> WlanProfile {
+        WlanProfile {
+            ssid: ssid.to_string(),
+            priority: 0,
+            auto_join: true,
+            hidden: false,
+            static_ipv4: None,
+            dns: None,
+            last_connected: None,
+        }
+    }
+
+    fn to_config(&self) -> String {
+        let mut config = format!("priority={}\nauto_join={}\nhidden={}\n", self.priority, self.auto_join, self.hidden);
+        if let Some(ipv4) = self.static_ipv4 {
+            config.push_str(&format!("static_ipv4={}/{}\ngateway={}\n", ipv4.addr, ipv4.prefix_len, ipv4.gateway));
+        }
+        if let Some(dns) = self.dns {
+            config.push_str(&format!("dns={}\n", dns));
+        }
+        if let Some(time) = self.last_connected {
+            config.push_str(&format!("last_connected={}\n", time));
+        }
+        config
+    }
+
+    /// Settings that don't make sense are left at their defaults, rather than losing the network
+    fn from_config(ssid: &str, text: &str) -> WlanProfile {
+        let mut profile = WlanProfile::new(ssid);
+        let mut static_cidr = None;
+        let mut gateway = None;
+        for line in text.lines() {
+            let (name, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let ok = match name {
+                "priority" => value.parse().map(|v| profile.priority = v).is_ok(),
+                "auto_join" => value.parse().map(|v| profile.auto_join = v).is_ok(),
+                "hidden" => value.parse().map(|v| profile.hidden = v).is_ok(),
+                "static_ipv4" => {
+                    static_cidr = Some(value);
+                    true
+                }
+                "gateway" => {
+                    gateway = Some(value);
+                    true
+                }
+                "dns" => value.parse().map(|v| profile.dns = Some(v)).is_ok(),
+                "last_connected" => value.parse().map(|v| profile.last_connected = Some(v)).is_ok(),
+                // written by some later version; what we know of should still work
+                _ => true,
+            };
+            if !ok {
+                log::warn!("bad setting for wifi network {}: {}", ssid, line);
+            }
+        }
+        if let (Some(cidr), Some(gateway)) = (static_cidr, gateway) {
+            profile.static_ipv4 = StaticIpv4::parse(cidr, gateway);
+        }
+        profile
+    }
+}
+
+/// The profile of a saved network, or the defaults if it hasn't got one
+pub fn load(pddb: &pddb::Pddb, ssid: &str) -> WlanProfile {
+    match pddb.get(AP_PROFILE_DICT_NAME, ssid, None, false, false, None, None::<fn()>) {
+        Ok(mut key) => {
+            let mut text = Vec::new();
+            match key.read_to_end(&mut text) {
+                Ok(_) => WlanProfile::from_config(ssid, &String::from_utf8_lossy(&text)),
+                Err(e) => {
+                    log::warn!("couldn't read the profile of {}: {:?}", ssid, e);
+                    WlanProfile::new(ssid)
+                }
+            }
+        }
+        Err(_) => WlanProfile::new(ssid),
+    }
+}
+
+pub fn store(pddb: &pddb::Pddb, profile: &WlanProfile) -> Result<()> {
+    match pddb.delete_key(AP_PROFILE_DICT_NAME, &profile.ssid, None) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let config = profile.to_config();
+    let mut key = pddb.get(AP_PROFILE_DICT_NAME, &profile.ssid, None, true, true, Some(config.len()), None::<fn()>)?;
+    key.write_all(config.as_bytes())?;
+    pddb.sync()
+}
+
+/// Every saved network. Fails if the PDDB isn't mounted.
+pub fn list(pddb: &pddb::Pddb) -> Result<Vec<WlanProfile>> {
+    Ok(pddb.list_keys(AP_DICT_NAME, None)?.iter().map(|ssid| load(pddb, ssid)).collect())
+}
+
+/// Removes a network's password and its profile
+pub fn forget(pddb: &pddb::Pddb, ssid: &str) -> Result<()> {
+    pddb.delete_key(AP_DICT_NAME, ssid, None)?;
+    match pddb.delete_key(AP_PROFILE_DICT_NAME, ssid, None) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    pddb.sync()
+}
+
+/// Notes that we just got onto `ssid`
+pub fn mark_connected(pddb: &pddb::Pddb, ssid: &str) -> Result<()> {
+    let mut profile = load(pddb, ssid);
+    profile.last_connected = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+    store(pddb, &profile)
+}
+
+/// The networks the connection manager should try, best first: the ones it may join on its own that
+/// were seen in the last scan (`visible`, by SSID with their RSSI), or that are hidden. Higher priorities
+/// go first, then the network we were on most recently, then the stronger signal.
+pub fn join_order(profiles: Vec<WlanProfile>, visible: &HashMap<String, u8>) -> Vec<WlanProfile> {
+    let mut candidates: Vec<WlanProfile> = profiles
+        .into_iter()
+        .filter(|p| p.auto_join && (p.hidden || visible.contains_key(&p.ssid)))
+        .collect();
+    // the RSSI is reported as a magnitude, so smaller is stronger; hidden networks that weren't seen go last
+    candidates.sort_by_key(|p| {
+        (
+            std::cmp::Reverse(p.priority),
+            std::cmp::Reverse(p.last_connected),
+            visible.get(&p.ssid).copied().unwrap_or(u8::MAX),
+        )
+    });
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_round_trips() {
+        let mut profile = WlanProfile::new("cafe wifi");
+        profile.priority = 7;
+        profile.auto_join = false;
+        profile.hidden = true;
+        profile.static_ipv4 = StaticIpv4::parse("10.0.0.20/24", "10.0.0.1");
+        profile.dns = Some(Ipv4Addr::new(9, 9, 9, 9));
+        profile.last_connected = Some(1_650_000_000);
+        assert_eq!(WlanProfile::from_config("cafe wifi", &profile.to_config()), profile);
+        assert_eq!(profile.static_ipv4.unwrap().mask(), Ipv4Addr::new(255, 255, 255, 0));
+
+        // what can't be made sense of is left at the default
+        let profile = WlanProfile::from_config("home", "priority=high\nhidden=true\nstatic_ipv4=10.0.0.20/24\n");
+        assert_eq!(profile.priority, 0);
+        assert!(profile.hidden);
+        assert_eq!(profile.static_ipv4, None);
+    }
+
+    #[test]
+    fn networks_are_tried_best_first() {
+        let profile = |ssid: &str, priority: u8, last_connected: Option<u64>| WlanProfile {
+            priority,
+            last_connected,
+            ..WlanProfile::new(ssid)
+        };
+        let mut hidden = profile("hidden", 0, None);
+        hidden.hidden = true;
+        let mut manual = profile("manual", 9, None);
+        manual.auto_join = false;
+        let profiles = vec![
+            profile("weak", 0, None),
+            profile("strong", 0, None),
+            profile("recent", 0, Some(100)),
+            profile("preferred", 5, None),
+            profile("away", 5, None),
+            hidden,
+            manual,
+        ];
+        let mut visible = HashMap::new();
+        for (ssid, rssi) in [("weak", 80), ("strong", 40), ("recent", 90), ("preferred", 90), ("manual", 30)] {
+            visible.insert(ssid.to_string(), rssi);
+        }
+        let order: Vec<String> = join_order(profiles, &visible).into_iter().map(|p| p.ssid).collect();
+        assert_eq!(order, vec!["preferred", "recent", "strong", "weak", "hidden"]);
+    }
+}
@@ -169,6 +169,10 @@ impl DnsServerManager {
     pub fn set_freeze(&mut self, freeze: bool) {
         self.freeze = freeze;
     }
+    /// All of the DNS servers, for trying one after another
+    pub fn get_all(&self) -> Vec<IpAddr> {
+        self.servers.lock().unwrap().iter().copied().collect()
+    }
     /// Get one of the DNS servers. Which one we get, we don't know!
     pub fn get_random(&self) -> Option<IpAddr> {
         if let Some(&addr) = self.servers.lock().unwrap().iter().next() {
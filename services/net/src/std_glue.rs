@@ -1,5 +1,6 @@
 use smoltcp::wire::IpAddress;
 use crate::*;
+use crate::device::NetPhy;
 
 
 pub(crate) fn parse_address(data: &[u8]) -> Option<smoltcp::wire::IpAddress> {
@@ -38,7 +39,7 @@ pub(crate) fn write_address(address: IpAddress, data: &mut [u8]) -> Option<usize
             for (dest, src) in i.zip(a.as_bytes().iter()) {
                 *dest = *src;
             }
-            Some(16)
+            Some(17)
         }
         _ => {
             *i.next()? = 0;
@@ -47,6 +48,23 @@ pub(crate) fn write_address(address: IpAddress, data: &mut [u8]) -> Option<usize
     }
 }
 
+/// The address of ours to send to (or receive from) `remote` with: the IPv4 address for IPv4, and the
+/// address smoltcp would pick on its own for IPv6. Loopback remotes are talked to from the loopback address,
+/// and the ones the WireGuard tunnel routes from the tunnel's address.
+pub(crate) fn local_addr_for(iface: &Interface::<NetPhy>, remote: IpAddress) -> Option<IpAddress> {
+    let tunnel = iface.device().tunnel();
+    match remote {
+        // smoltcp only answers to the addresses it has, so of 127.0.0.0/8 only 127.0.0.1 is reachable
+        IpAddress::Ipv4(addr) if addr.is_loopback() => Some(IpAddress::v4(127, 0, 0, 1)),
+        IpAddress::Ipv4(addr) if tunnel.map_or(false, |tap| tap.routes(addr)) => tunnel.map(|tap| IpAddress::Ipv4(tap.address())),
+        IpAddress::Ipv6(addr) if addr.is_loopback() => Some(remote),
+        // the tunnel is meant to take IPv6 beyond the link, and can't
+        IpAddress::Ipv6(addr) if tunnel.map_or(false, |tap| tap.blocks_ipv6()) && !addr.is_link_local() => None,
+        IpAddress::Ipv6(_) => crate::ipv6::source_addr(iface).map(IpAddress::Ipv6),
+        _ => iface.ipv4_addr().map(IpAddress::Ipv4),
+    }
+}
+
 pub(crate) fn respond_with_error(mut env: xous::MessageEnvelope, code: NetError) -> Option<()> {
     // If it's not a memory message, don't fill in the return information.
     let body = match env.body.memory_message_mut() {
@@ -39,6 +39,16 @@ pub(crate) fn std_tcp_connect(
         }
     };
 
+    // bind to our address of the same family as the remote, the same way the UDP calls do
+    let local_addr = match local_addr_for(iface, address) {
+        Some(addr) => addr,
+        None => {
+            log::trace!("no local address to reach {:?} from", address);
+            respond_with_error(msg, NetError::Unaddressable);
+            return;
+        }
+    };
+
     // initiates a new connection to a remote server consisting of an (Address:Port) tuple.
     // multiple connections can exist to a server, and they are further differentiated by the return port
     let tcp_socket = TcpSocket::new(
@@ -50,7 +60,7 @@ pub(crate) fn std_tcp_connect(
 
     // Attempt to connect, returning the error if there is one
     if let Err(e) = tcp_socket
-        .connect(cx, (address, remote_port), local_port)
+        .connect(cx, (address, remote_port), (local_addr, local_port))
         .map_err(|e| match e {
             smoltcp::Error::Illegal => NetError::SocketInUse,
             smoltcp::Error::Unaddressable => NetError::Unaddressable,
@@ -0,0 +1,1058 @@
+//! A WireGuard tunnel, so that traffic can go through a VPN server we trust rather than out onto
+//! whatever network we happen to be on.
+//!
+//! The tunnel isn't an interface of its own as far as smoltcp is concerned: its address goes last
+//! in the address list of the one interface there is, and packets are moved in and out at the
+//! device, the way the loopback does it. The `Tap` takes the packets that smoltcp sends from the
+//! tunnel address (and pings to places the tunnel routes) out of the way to the EC, and hands what
+//! comes out of the tunnel back as frames received. The `Tunnel` runs the protocol, and talks to
+//! the peer over a UDP socket of its own, from the address we have on the LAN.
+//!
+//! It's IPv4 inside and out, there's the one peer, and we only ever initiate. When the peer's
+//! allowed IPs include `::/0`, IPv6 is blocked rather than let past the tunnel. The endpoint has to
+//! be an address, as looking up a name would mean asking before the tunnel is up. Initiations carry
+//! the time, and a peer won't take one that's older than the last it saw from us, so the clock has
+//! to be set. Our private key is made on the device, and kept in the PDDB along with the peer's
+//! key and endpoint; it never leaves the device.
+
+mod blake2s;
+mod noise;
+
+use crate::api::{WireguardState, WireguardStatus};
+use crate::device::NetPhy;
+use noise::{Handshake, Keys, Session, MSG_RESPONSE, MSG_TRANSPORT, TRANSPORT_HEADER_LEN};
+
+use com::api::NET_MTU;
+use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::socket::{UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv4Cidr};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::{ErrorKind, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What fits inside the tunnel, once the outer IPv4 and UDP headers and WireGuard's own have been added
+pub(crate) const TUNNEL_MTU: usize = 1420;
+const MAX_MSS: u16 = (TUNNEL_MTU - 40) as u16;
+/// The source of the frames that come out of the tunnel. The group bit is set, so smoltcp never takes
+/// it for anyone's link-layer address.
+const TUNNEL_MAC: [u8; 6] = [0x03, 0x77, 0x67, 0, 0, 0];
+/// Packets that wait in the tap, in either direction, before they are dropped
+const TAP_DEPTH: usize = 32;
+/// Packets that wait for a handshake to finish before the oldest are dropped
+const PENDING_DEPTH: usize = 16;
+const SOCKET_DEPTH: usize = 8;
+
+// the timers of section 6 of the WireGuard paper, in milliseconds
+const REKEY_AFTER_TIME: u64 = 120_000;
+const REJECT_AFTER_TIME: u64 = 180_000;
+const REKEY_ATTEMPT_TIME: u64 = 90_000;
+const REKEY_TIMEOUT: u64 = 5_000;
+const KEEPALIVE_TIMEOUT: u64 = 10_000;
+
+/// TAI64 counts from 1970 too, but with this added
+const TAI64_BASE: u64 = (1 << 62) + 10;
+
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+const ETHERTYPE_ARP: [u8; 2] = [0x08, 0x06];
+const ETHERTYPE_IPV6: [u8; 2] = [0x86, 0xdd];
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_TCP: u8 = 6;
+
+pub(crate) const WG_DICT: &str = "net.wireguard";
+/// the configuration, as `wg-quick` would have it
+pub(crate) const WG_CONFIG_KEY: &str = "config";
+/// our private key, as 32 raw bytes
+pub(crate) const WG_PRIVATE_KEY: &str = "private_key";
+/// "1" when the tunnel is to be up
+pub(crate) const WG_ENABLED_KEY: &str = "enabled";
+
+/// A tunnel's configuration
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Config {
+    /// ours, if the configuration came with one; otherwise the one in the PDDB is used
+    pub private_key: Option<[u8; 32]>,
+    /// our address inside the tunnel
+    pub address: Ipv4Address,
+    /// the DNS server to use while the tunnel is up
+    pub dns: Option<Ipv4Address>,
+    /// a random one is picked if this isn't set
+    pub listen_port: Option<u16>,
+    pub peer_key: [u8; 32],
+    /// all zeros if there isn't one
+    pub preshared_key: [u8; 32],
+    pub endpoint: (Ipv4Address, u16),
+    /// where the peer may send from, and where we send through it
+    pub allowed_ips: Vec<Ipv4Cidr>,
+    /// the allowed IPs included `::/0`: nothing is to go around the tunnel, but it can't carry IPv6
+    pub block_ipv6: bool,
+    /// seconds between keepalives when nothing else is being sent, if the peer wants them
+    pub keepalive: Option<u16>,
+}
+impl Config {
+    /// Reads the configuration file format of `wg-quick`. IPv6 addresses in it are skipped, and
+    /// entries that only mean something to `wg-quick` on a computer (`MTU`, `PostUp` and the like)
+    /// are ignored.
+    pub(crate) fn parse(text: &str) -> Result<Config, &'static str> {
+        let mut section = "";
+        let mut peers = 0;
+        let mut private_key = None;
+        let mut address = None;
+        let mut dns = None;
+        let mut listen_port = None;
+        let mut peer_key = None;
+        let mut preshared_key = [0u8; 32];
+        let mut endpoint = None;
+        let mut allowed_ips = Vec::new();
+        let mut block_ipv6 = false;
+        let mut keepalive = None;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                section = if line.eq_ignore_ascii_case("[Interface]") {
+                    "interface"
+                } else if line.eq_ignore_ascii_case("[Peer]") {
+                    peers += 1;
+                    "peer"
+                } else {
+                    return Err("unknown section");
+                };
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or("expected name = value")?;
+            let (name, value) = (name.trim().to_ascii_lowercase(), value.trim());
+            match (section, name.as_str()) {
+                ("interface", "privatekey") => private_key = Some(parse_key(value)?),
+                ("interface", "address") => {
+                    for addr in value.split(',').map(str::trim).filter(|a| !a.contains(':')) {
+                        let cidr = parse_cidr(addr)?;
+                        address.get_or_insert(cidr.address());
+                    }
+                }
+                ("interface", "dns") => {
+                    // search domains can be in here too
+                    for server in value.split(',') {
+                        if let Ok(addr) = server.trim().parse::<std::net::Ipv4Addr>() {
+                            dns.get_or_insert(Ipv4Address::from(addr));
+                        }
+                    }
+                }
+                ("interface", "listenport") => listen_port = Some(value.parse().or(Err("bad listen port"))?),
+                ("interface", _) => {}
+                ("peer", "publickey") => peer_key = Some(parse_key(value)?),
+                ("peer", "presharedkey") => preshared_key = parse_key(value)?,
+                ("peer", "endpoint") => {
+                    let addr = value
+                        .parse::<std::net::SocketAddrV4>()
+                        .or(Err("the endpoint has to be an IPv4 address and port"))?;
+                    endpoint = Some((Ipv4Address::from(*addr.ip()), addr.port()));
+                }
+                ("peer", "allowedips") => {
+                    for cidr in value.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                        if cidr.contains(':') {
+                            block_ipv6 |= cidr.ends_with("/0");
+                        } else {
+                            allowed_ips.push(parse_cidr(cidr)?);
+                        }
+                    }
+                }
+                ("peer", "persistentkeepalive") => {
+                    keepalive = match value {
+                        "off" => None,
+                        _ => Some(value.parse::<u16>().or(Err("bad keepalive interval"))?).filter(|&k| k != 0),
+                    }
+                }
+                ("peer", _) => {}
+                _ => return Err("entry outside of a section"),
+            }
+        }
+        if peers > 1 {
+            return Err("only one peer is supported");
+        }
+        Ok(Config {
+            private_key,
+            address: address.ok_or("no IPv4 address for the interface")?,
+            dns,
+            listen_port,
+            peer_key: peer_key.ok_or("no public key for the peer")?,
+            preshared_key,
+            endpoint: endpoint.ok_or("no endpoint for the peer")?,
+            allowed_ips,
+            block_ipv6,
+            keepalive,
+        })
+    }
+}
+
+fn parse_key(text: &str) -> Result<[u8; 32], &'static str> {
+    base64::decode(text).ok().and_then(|key| key.as_slice().try_into().ok()).ok_or("keys are 32 bytes in base64")
+}
+
+/// `a.b.c.d/n`, or just `a.b.c.d` for a single address
+fn parse_cidr(text: &str) -> Result<Ipv4Cidr, &'static str> {
+    let (addr, prefix_len) = match text.split_once('/') {
+        Some((addr, prefix_len)) => (addr, prefix_len.parse::<u8>().or(Err("bad prefix length"))?),
+        None => (text, 32),
+    };
+    let addr = addr.parse::<std::net::Ipv4Addr>().or(Err("bad IPv4 address"))?;
+    if prefix_len > 32 {
+        return Err("bad prefix length");
+    }
+    Ok(Ipv4Cidr::new(Ipv4Address::from(addr), prefix_len))
+}
+
+/// The public key that goes with `secret`, for the peer's configuration
+pub(crate) fn public_key(secret: &[u8; 32]) -> [u8; 32] {
+    noise::public_key(secret)
+}
+
+/// What goes last in the interface's address list: the tunnel's address, or when there's no tunnel, an
+/// address that the loopback covers already. It can't be left unspecified, as `0.0.0.0/0` would put every
+/// IPv4 address on-link.
+pub(crate) fn tunnel_cidr(address: Option<Ipv4Address>) -> IpCidr {
+    IpCidr::Ipv4(Ipv4Cidr::new(address.unwrap_or(Ipv4Address::new(127, 0, 0, 1)), 32))
+}
+
+/// The tunnel's side of the device: see the module docs
+pub struct Tap {
+    mac: [u8; 6],
+    address: Ipv4Address,
+    lan: Option<Ipv4Cidr>,
+    endpoint: Ipv4Address,
+    allowed_ips: Vec<Ipv4Cidr>,
+    block_ipv6: bool,
+    /// packets on their way into the tunnel, without their Ethernet headers
+    outbound: VecDeque<Vec<u8>>,
+    /// frames that came out of it, for smoltcp to receive
+    inbound: VecDeque<Vec<u8>>,
+}
+impl Tap {
+    pub(crate) fn new(mac: [u8; 6], config: &Config) -> Tap {
+        Tap {
+            mac,
+            address: config.address,
+            lan: None,
+            endpoint: config.endpoint.0,
+            allowed_ips: config.allowed_ips.clone(),
+            block_ipv6: config.block_ipv6,
+            outbound: VecDeque::new(),
+            inbound: VecDeque::new(),
+        }
+    }
+    pub fn address(&self) -> Ipv4Address {
+        self.address
+    }
+    /// Whether IPv6 beyond the link is cut off, as the tunnel is meant to take it and can't
+    pub fn blocks_ipv6(&self) -> bool {
+        self.block_ipv6
+    }
+    pub(crate) fn set_lan(&mut self, lan: Option<Ipv4Cidr>) {
+        self.lan = lan;
+    }
+    pub(crate) fn set_endpoint(&mut self, endpoint: Ipv4Address) {
+        self.endpoint = endpoint;
+    }
+    /// Whether packets for `addr` go through the tunnel. The peer itself doesn't, nor does anything on the
+    /// LAN or that's only meaningful on a link; everything else does if the peer's allowed IPs cover it.
+    pub fn routes(&self, addr: Ipv4Address) -> bool {
+        if addr == self.endpoint
+            || addr == self.address
+            || addr.is_unspecified()
+            || addr.is_broadcast()
+            || addr.is_multicast()
+            || addr.is_link_local()
+            || addr.is_loopback()
+            || self.lan.map_or(false, |lan| lan.contains_addr(&addr))
+        {
+            return false;
+        }
+        self.allowed_ips.iter().any(|cidr| cidr.contains_addr(&addr))
+    }
+
+    /// Looks at a frame that smoltcp is about to send. Returns `true` if the tunnel took it, or dropped it
+    /// because it was for the tunnel but can't go through it; `false` if it's for the EC to send.
+    pub fn take(&mut self, frame: &mut [u8]) -> bool {
+        if frame.len() < 14 {
+            return false;
+        }
+        let ethertype = [frame[12], frame[13]];
+        let payload = &mut frame[14..];
+        match ethertype {
+            ETHERTYPE_ARP if payload.len() >= 28 && payload[14..18] == self.address.0 => {
+                // smoltcp asks after the next hop from the address of the packet that needs it, but the LAN
+                // has no business knowing the tunnel's address, nor hearing that it's ours
+                match (payload[6..8] == [0, 1], self.lan) {
+                    (true, Some(lan)) => {
+                        payload[14..18].copy_from_slice(lan.address().as_bytes());
+                        false
+                    }
+                    _ => true,
+                }
+            }
+            ETHERTYPE_IPV4 if payload.len() >= 20 && payload[0] >> 4 == 4 => {
+                let src = Ipv4Address::from_bytes(&payload[12..16]);
+                let dst = Ipv4Address::from_bytes(&payload[16..20]);
+                if !self.routes(dst) {
+                    // an answer to the tunnel's address could only come back through the tunnel
+                    return src == self.address;
+                }
+                if src != self.address {
+                    // smoltcp sends pings from the first address it has, which is the LAN one. Pings have
+                    // no pseudo-header in their checksum, so they can be moved over; anything else would
+                    // be going around the tunnel.
+                    if payload[9] != PROTOCOL_ICMP {
+                        log::debug!("dropping a packet for {} that would go around the tunnel", dst);
+                        return true;
+                    }
+                    payload[12..16].copy_from_slice(self.address.as_bytes());
+                    set_header_checksum(payload);
+                }
+                let len = (u16::from_be_bytes([payload[2], payload[3]]) as usize).min(payload.len());
+                if len > TUNNEL_MTU {
+                    log::warn!("dropping a {}-byte packet, which doesn't fit in the tunnel", len);
+                    return true;
+                }
+                if self.outbound.len() < TAP_DEPTH {
+                    let mut packet = payload[..len].to_vec();
+                    clamp_mss(&mut packet);
+                    self.outbound.push_back(packet);
+                } else {
+                    log::warn!("tunnel isn't keeping up, dropping a packet");
+                }
+                true
+            }
+            ETHERTYPE_IPV6 if self.block_ipv6 && payload.len() >= 40 => {
+                // neighbor discovery, router advertisements and mDNS stay on the link
+                let dst = &payload[24..40];
+                let on_link = dst[0] == 0xff || (dst[0] == 0xfe && dst[1] & 0xc0 == 0x80);
+                !on_link
+            }
+            _ => false,
+        }
+    }
+    /// Hands a packet that came out of the tunnel to smoltcp, as a frame from nobody in particular
+    pub(crate) fn push_inbound(&mut self, packet: &[u8]) {
+        if packet.len() > TUNNEL_MTU || self.inbound.len() >= TAP_DEPTH {
+            log::warn!("dropping a {}-byte packet from the tunnel", packet.len());
+            return;
+        }
+        let mut frame = Vec::with_capacity(14 + packet.len());
+        frame.extend_from_slice(&self.mac);
+        frame.extend_from_slice(&TUNNEL_MAC);
+        frame.extend_from_slice(&ETHERTYPE_IPV4);
+        frame.extend_from_slice(packet);
+        clamp_mss(&mut frame[14..]);
+        self.inbound.push_back(frame);
+    }
+    pub fn pop_inbound(&mut self) -> Option<Vec<u8>> {
+        self.inbound.pop_front()
+    }
+    pub(crate) fn pop_outbound(&mut self) -> Option<Vec<u8>> {
+        self.outbound.pop_front()
+    }
+    /// Whether there are frames from the tunnel that the stack hasn't picked up yet
+    pub fn is_pending(&self) -> bool {
+        !self.inbound.is_empty()
+    }
+}
+
+/// The Internet checksum of the concatenation of `parts`, all but the last of which have to be of even length
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for word in part.chunks(2) {
+            sum += (word[0] as u32) << 8 | *word.get(1).unwrap_or(&0) as u32;
+        }
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn set_header_checksum(packet: &mut [u8]) {
+    let header_len = ((packet[0] & 0xf) as usize * 4).min(packet.len());
+    packet[10..12].copy_from_slice(&[0, 0]);
+    let sum = checksum(&[&packet[..header_len]]);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// Lowers the MSS option of a TCP SYN in `packet` to what fits in the tunnel. smoltcp doesn't know that
+/// the tunnel's MTU is less than the EC's, and neither does whoever's at the other end.
+fn clamp_mss(packet: &mut [u8]) {
+    let header_len = (packet[0] & 0xf) as usize * 4;
+    if packet[9] != PROTOCOL_TCP || packet.len() < header_len + 20 || packet[6] & 0x3f != 0 || packet[7] != 0 {
+        // not TCP, or a fragment
+        return;
+    }
+    let pseudo_header = {
+        let mut pseudo_header = [0u8; 12];
+        pseudo_header[..8].copy_from_slice(&packet[12..20]);
+        pseudo_header[9] = PROTOCOL_TCP;
+        pseudo_header[10..].copy_from_slice(&((packet.len() - header_len) as u16).to_be_bytes());
+        pseudo_header
+    };
+    let tcp = &mut packet[header_len..];
+    let options_end = ((tcp[12] >> 4) as usize * 4).min(tcp.len());
+    if tcp[13] & 0x02 == 0 {
+        return;
+    }
+    let mut i = 20;
+    while i < options_end {
+        match tcp[i] {
+            0 => break,
+            1 => i += 1,
+            kind => {
+                let len = *tcp.get(i + 1).unwrap_or(&0) as usize;
+                if len < 2 || i + len > options_end {
+                    break;
+                }
+                if kind == 2 && len == 4 && u16::from_be_bytes([tcp[i + 2], tcp[i + 3]]) > MAX_MSS {
+                    tcp[i + 2..i + 4].copy_from_slice(&MAX_MSS.to_be_bytes());
+                    tcp[16..18].copy_from_slice(&[0, 0]);
+                    let sum = checksum(&[&pseudo_header, tcp]);
+                    tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+                    break;
+                }
+                i += len;
+            }
+        }
+    }
+}
+
+/// The protocol side of the tunnel: handshakes, sessions and timers, and the socket to the peer
+pub(crate) struct Tunnel {
+    config: Config,
+    keys: Keys,
+    random: Box<dyn FnMut(&mut [u8])>,
+    port: u16,
+    socket: Option<SocketHandle>,
+    /// where the peer is; this moves to wherever authenticated messages come from
+    endpoint: (Ipv4Address, u16),
+    /// whether we have an address on the LAN to reach the peer from
+    lan_up: bool,
+    /// the initiation we're waiting on a response to, and when it went
+    handshake: Option<(Handshake, u64)>,
+    /// when we started trying for the session we're after, so we know when to give up
+    attempting_since: Option<u64>,
+    /// whether to handshake without waiting for something to send
+    wanted: bool,
+    /// the session we send with, and when it was made
+    current: Option<(Session, u64)>,
+    /// the one before it, which the peer might still be sending with for a bit
+    previous: Option<(Session, u64)>,
+    /// packets waiting for a session
+    pending: VecDeque<Vec<u8>>,
+    /// messages for the peer
+    outbox: VecDeque<Vec<u8>>,
+    /// packets from the peer
+    inbox: VecDeque<Vec<u8>>,
+    timestamp: [u8; 12],
+    last_sent: u64,
+    /// when a packet came in that we haven't sent anything back since
+    keepalive_owed: Option<u64>,
+    last_handshake: Option<u64>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+impl Tunnel {
+    /// A tunnel for `config`, with `secret` as our private key. `random` fills its argument with random
+    /// bytes.
+    pub(crate) fn new(config: Config, secret: [u8; 32], mut random: Box<dyn FnMut(&mut [u8])>) -> Tunnel {
+        let port = config.listen_port.unwrap_or_else(|| {
+            let mut port = [0u8; 2];
+            random(&mut port);
+            // from the range that's left for ephemeral ports
+            49152 + u16::from_le_bytes(port) % 16384
+        });
+        let keys = Keys { secret, public: public_key(&secret), peer: config.peer_key, preshared: config.preshared_key };
+        Tunnel {
+            endpoint: config.endpoint,
+            config,
+            keys,
+            random,
+            port,
+            socket: None,
+            lan_up: false,
+            handshake: None,
+            attempting_since: None,
+            wanted: true,
+            current: None,
+            previous: None,
+            pending: VecDeque::new(),
+            outbox: VecDeque::new(),
+            inbox: VecDeque::new(),
+            timestamp: [0; 12],
+            last_sent: 0,
+            keepalive_owed: None,
+            last_handshake: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+        }
+    }
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Hands `packet` to the peer: now, if there's a session for it, or else once a handshake has made one
+    pub(crate) fn send(&mut self, now: u64, packet: Vec<u8>) {
+        if self.seal(now, &packet) {
+            return;
+        }
+        if self.pending.len() >= PENDING_DEPTH {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(packet);
+        if self.handshake.is_none() {
+            self.initiate(now);
+        }
+    }
+
+    /// Takes a message that came from the peer at `from`
+    pub(crate) fn receive(&mut self, now: u64, from: (Ipv4Address, u16), msg: &[u8]) {
+        match msg.first() {
+            Some(&MSG_RESPONSE) => {
+                let session = match self.handshake.as_ref().and_then(|(handshake, _)| handshake.complete(&self.keys, msg)) {
+                    Some(session) => session,
+                    None => return,
+                };
+                log::info!("WireGuard handshake with {}:{} done", from.0, from.1);
+                self.rx_bytes += msg.len() as u64;
+                self.endpoint = from;
+                self.handshake = None;
+                self.attempting_since = None;
+                self.wanted = self.config.keepalive.is_some();
+                self.previous = self.current.take();
+                self.current = Some((session, now));
+                self.last_handshake = Some(now);
+                // the peer can't use the session until it's heard something over it
+                if self.pending.is_empty() {
+                    self.seal(now, &[]);
+                }
+                while let Some(packet) = self.pending.pop_front() {
+                    self.seal(now, &packet);
+                }
+            }
+            Some(&MSG_TRANSPORT) if msg.len() >= TRANSPORT_HEADER_LEN => {
+                let receiver = u32::from_le_bytes(msg[4..8].try_into().unwrap());
+                let session = match (&mut self.current, &mut self.previous) {
+                    (Some((session, _)), _) if session.local == receiver => session,
+                    (_, Some((session, _))) if session.local == receiver => session,
+                    _ => return,
+                };
+                let mut packet = match session.decrypt(msg) {
+                    Some(packet) => packet,
+                    None => return,
+                };
+                self.rx_bytes += msg.len() as u64;
+                self.endpoint = from;
+                if packet.is_empty() {
+                    // a keepalive
+                    return;
+                }
+                self.keepalive_owed.get_or_insert(now);
+                if packet.len() < 20 || packet[0] >> 4 != 4 {
+                    return;
+                }
+                // the padding comes off, and the packet has to be from where the peer is allowed to send from
+                let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+                let src = Ipv4Address::from_bytes(&packet[12..16]);
+                if len < 20 || len > packet.len() || !self.config.allowed_ips.iter().any(|cidr| cidr.contains_addr(&src)) {
+                    log::debug!("dropping a packet from {} that the peer isn't allowed to send", src);
+                    return;
+                }
+                packet.truncate(len);
+                self.inbox.push_back(packet);
+            }
+            // we never answer initiations, and don't do cookies; see `noise`
+            _ => {}
+        }
+    }
+
+    /// Runs the timers: handshake retries, rekeying, expiry and keepalives
+    pub(crate) fn poll(&mut self, now: u64) {
+        if let Some((_, sent)) = self.handshake {
+            if now - sent >= REKEY_TIMEOUT {
+                self.handshake = None;
+                if now - self.attempting_since.unwrap_or(now) >= REKEY_ATTEMPT_TIME {
+                    log::warn!("no answer from the WireGuard peer at {}:{}", self.endpoint.0, self.endpoint.1);
+                    self.attempting_since = None;
+                    self.pending.clear();
+                    self.wanted = self.config.keepalive.is_some();
+                } else {
+                    self.initiate(now);
+                }
+            }
+        }
+        if self.current.as_ref().map_or(false, |(_, made)| now - made >= REJECT_AFTER_TIME) {
+            self.current = None;
+        }
+        if self.previous.as_ref().map_or(false, |(_, made)| now - made >= REJECT_AFTER_TIME) {
+            self.previous = None;
+        }
+        if self.handshake.is_none() {
+            let stale = match self.current {
+                // only a session that's in use is worth making again
+                Some((_, made)) => now - made >= REKEY_AFTER_TIME && self.last_sent > made,
+                None => self.wanted || !self.pending.is_empty(),
+            };
+            if stale {
+                self.initiate(now);
+            }
+        }
+        if self.current.is_some() {
+            let owed = self.keepalive_owed.map_or(false, |since| now - since >= KEEPALIVE_TIMEOUT);
+            let persistent = self.config.keepalive.map_or(false, |secs| now - self.last_sent >= secs as u64 * 1000);
+            if owed || persistent {
+                self.seal(now, &[]);
+            }
+        }
+    }
+
+    /// How long until `poll()` has something to do, if anything
+    pub(crate) fn poll_delay(&self, now: u64) -> Option<u64> {
+        let deadlines = [
+            self.handshake.as_ref().map(|(_, sent)| sent + REKEY_TIMEOUT),
+            self.current.as_ref().map(|(_, made)| made + REKEY_AFTER_TIME),
+            self.current.as_ref().map(|(_, made)| made + REJECT_AFTER_TIME),
+            self.previous.as_ref().map(|(_, made)| made + REJECT_AFTER_TIME),
+            self.current.as_ref().and(self.keepalive_owed).map(|since| since + KEEPALIVE_TIMEOUT),
+            self.current.as_ref().and(self.config.keepalive).map(|secs| self.last_sent + secs as u64 * 1000),
+        ];
+        // the ones that have passed have been dealt with, or are waiting on something other than time
+        deadlines.iter().flatten().filter(|&&at| at > now).min().map(|at| at - now)
+    }
+
+    pub(crate) fn status(&self) -> WireguardStatus {
+        WireguardStatus {
+            state: if !self.lan_up {
+                WireguardState::WaitingForNetwork
+            } else if self.current.is_some() {
+                WireguardState::Connected
+            } else if self.handshake.is_some() {
+                WireguardState::Connecting
+            } else {
+                WireguardState::Idle
+            },
+            address: Some(self.config.address.0),
+            endpoint: Some(self.endpoint.0 .0),
+            endpoint_port: self.endpoint.1,
+            last_handshake_secs: None,
+            rx_bytes: self.rx_bytes,
+            tx_bytes: self.tx_bytes,
+        }
+    }
+    /// When the last handshake was done, in ticktimer milliseconds
+    pub(crate) fn last_handshake(&self) -> Option<u64> {
+        self.last_handshake
+    }
+
+    fn initiate(&mut self, now: u64) {
+        if !self.lan_up {
+            // there's no way to reach the peer; `poll()` tries again once there is
+            return;
+        }
+        let mut ephemeral = [0u8; 32];
+        (self.random)(&mut ephemeral);
+        let mut sender = [0u8; 4];
+        (self.random)(&mut sender);
+        let timestamp = self.next_timestamp();
+        match Handshake::initiate(&self.keys, u32::from_le_bytes(sender), ephemeral, timestamp) {
+            Some((handshake, msg)) => {
+                self.attempting_since.get_or_insert(now);
+                self.handshake = Some((handshake, now));
+                self.transmit(now, msg);
+            }
+            None => {
+                log::error!("the WireGuard peer's public key is no good");
+                self.wanted = false;
+                self.pending.clear();
+            }
+        }
+    }
+
+    /// Sends `packet` over the current session, if there's one that can still be used
+    fn seal(&mut self, now: u64, packet: &[u8]) -> bool {
+        let msg = match self.current.as_mut() {
+            Some((session, made)) if now - *made < REJECT_AFTER_TIME => session.encrypt(packet),
+            _ => None,
+        };
+        match msg {
+            Some(msg) => {
+                self.transmit(now, msg);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn transmit(&mut self, now: u64, msg: Vec<u8>) {
+        self.tx_bytes += msg.len() as u64;
+        self.last_sent = now;
+        self.keepalive_owed = None;
+        self.outbox.push_back(msg);
+    }
+
+    /// A TAI64N timestamp for an initiation. The peer drops initiations that are no later than the last
+    /// one it saw, so the timestamp never goes back, even if the clock does.
+    fn next_timestamp(&mut self) -> [u8; 12] {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut timestamp = [0u8; 12];
+        timestamp[..8].copy_from_slice(&(TAI64_BASE + since_epoch.as_secs()).to_be_bytes());
+        timestamp[8..].copy_from_slice(&since_epoch.subsec_nanos().to_be_bytes());
+        if timestamp <= self.timestamp {
+            let secs = u64::from_be_bytes(self.timestamp[..8].try_into().unwrap());
+            let nanos = u32::from_be_bytes(self.timestamp[8..].try_into().unwrap());
+            let (secs, nanos) = if nanos + 1 >= 1_000_000_000 { (secs + 1, 0) } else { (secs, nanos + 1) };
+            timestamp[..8].copy_from_slice(&secs.to_be_bytes());
+            timestamp[8..].copy_from_slice(&nanos.to_be_bytes());
+        }
+        self.timestamp = timestamp;
+        timestamp
+    }
+
+    /// Makes the socket to talk to the peer over, and puts the tunnel into the device and its address
+    /// into the interface
+    pub(crate) fn open(&mut self, iface: &mut Interface<NetPhy>, mac: [u8; 6]) {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; SOCKET_DEPTH], vec![0; SOCKET_DEPTH * NET_MTU]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; SOCKET_DEPTH], vec![0; SOCKET_DEPTH * NET_MTU]);
+        self.socket = Some(iface.add_socket(UdpSocket::new(rx_buffer, tx_buffer)));
+        iface.device_mut().set_tunnel(Some(Tap::new(mac, &self.config)));
+        let address = self.config.address;
+        iface.update_ip_addrs(|addrs| {
+            if let Some(slot) = addrs.iter_mut().last() {
+                *slot = tunnel_cidr(Some(address));
+            }
+        });
+        log::info!("WireGuard tunnel to {}:{} is up on port {}", self.endpoint.0, self.endpoint.1, self.port);
+    }
+    /// Undoes `open()`
+    pub(crate) fn close(&mut self, iface: &mut Interface<NetPhy>) {
+        if let Some(handle) = self.socket.take() {
+            iface.get_socket::<UdpSocket>(handle).close();
+            iface.remove_socket(handle);
+        }
+        iface.device_mut().set_tunnel(None);
+        iface.update_ip_addrs(|addrs| {
+            if let Some(slot) = addrs.iter_mut().last() {
+                *slot = tunnel_cidr(None);
+            }
+        });
+        log::info!("WireGuard tunnel is down");
+    }
+
+    /// Moves packets between the tap and the peer, and runs the timers. Returns `true` if it left anything
+    /// for smoltcp to do.
+    pub(crate) fn pump(&mut self, iface: &mut Interface<NetPhy>, now: u64) -> bool {
+        let handle = match self.socket {
+            Some(handle) => handle,
+            None => return false,
+        };
+        // the outside of the tunnel goes from our address on the LAN, whatever that is right now
+        let lan = match iface.ip_addrs().first() {
+            Some(IpCidr::Ipv4(cidr)) if !cidr.address().is_unspecified() => Some(*cidr),
+            _ => None,
+        };
+        self.lan_up = lan.is_some();
+        if let Some(tap) = iface.device_mut().tunnel_mut() {
+            tap.set_lan(lan);
+        }
+        let socket = iface.get_socket::<UdpSocket>(handle);
+        if let Some(lan) = lan {
+            let local = IpEndpoint::new(lan.address().into(), self.port);
+            if socket.endpoint() != local {
+                if socket.is_open() {
+                    socket.close();
+                }
+                if let Err(e) = socket.bind(local) {
+                    log::error!("couldn't bind the WireGuard socket to {}: {:?}", local, e);
+                }
+            }
+        }
+        let mut received = Vec::new();
+        while let Ok((msg, from)) = socket.recv() {
+            if let IpAddress::Ipv4(addr) = from.addr {
+                received.push((msg.to_vec(), (addr, from.port)));
+            }
+        }
+        for (msg, from) in received {
+            self.receive(now, from, &msg);
+        }
+        while let Some(packet) = iface.device_mut().tunnel_mut().and_then(|tap| tap.pop_outbound()) {
+            self.send(now, packet);
+        }
+        self.poll(now);
+
+        let mut busy = false;
+        let endpoint = IpEndpoint::new(self.endpoint.0.into(), self.endpoint.1);
+        let socket = iface.get_socket::<UdpSocket>(handle);
+        while let Some(msg) = self.outbox.pop_front() {
+            match socket.send_slice(&msg, endpoint) {
+                Ok(_) => busy = true,
+                Err(e) => log::debug!("couldn't send to the WireGuard peer: {:?}", e),
+            }
+        }
+        if let Some(tap) = iface.device_mut().tunnel_mut() {
+            tap.set_endpoint(self.endpoint.0);
+            while let Some(packet) = self.inbox.pop_front() {
+                tap.push_inbound(&packet);
+                busy = true;
+            }
+        }
+        busy
+    }
+}
+
+/// What the connection manager and the main loop share about the tunnel
+#[derive(Default)]
+pub(crate) struct Shared {
+    /// what the main loop is to bring the tunnel up with, left there by the connection manager ahead of
+    /// a `WireguardUpdate`
+    pub request: Option<(Config, [u8; 32])>,
+    /// how the tunnel is doing as of the last pump, while it's up
+    pub status: Option<WireguardStatus>,
+    /// when the last handshake was, in ticktimer milliseconds
+    pub last_handshake: Option<u64>,
+}
+
+/// Reads one of the tunnel's settings out of the PDDB
+pub(crate) fn read_setting(pddb: &pddb::Pddb, key: &str) -> Option<Vec<u8>> {
+    let mut value = Vec::new();
+    match pddb.get(WG_DICT, key, None, false, false, None, None::<fn()>) {
+        Ok(mut handle) => match handle.read_to_end(&mut value) {
+            Ok(_) => Some(value),
+            Err(e) => {
+                log::error!("couldn't read WireGuard setting {}: {:?}", key, e);
+                None
+            }
+        },
+        Err(_) => None,
+    }
+}
+
+/// Saves one of the tunnel's settings, or removes it if `value` is `None`
+pub(crate) fn write_setting(pddb: &pddb::Pddb, key: &str, value: Option<&[u8]>) -> std::io::Result<()> {
+    match pddb.delete_key(WG_DICT, key, None) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    if let Some(value) = value {
+        let mut handle = pddb.get(WG_DICT, key, None, true, true, Some(value.len()), None::<fn()>)?;
+        handle.write_all(value)?;
+    }
+    pddb.sync()
+}
+
+/// Our private key. It's made the first time it's asked for.
+pub(crate) fn private_key(pddb: &pddb::Pddb, trng: &mut trng::Trng) -> Option<[u8; 32]> {
+    if let Some(key) = read_setting(pddb, WG_PRIVATE_KEY) {
+        return key.as_slice().try_into().ok();
+    }
+    let mut key = [0u8; 32];
+    trng.fill_bytes(&mut key);
+    match write_setting(pddb, WG_PRIVATE_KEY, Some(&key)) {
+        Ok(_) => {
+            log::info!("made a new WireGuard key");
+            Some(key)
+        }
+        Err(e) => {
+            log::error!("couldn't save a new WireGuard key: {:?}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+[Interface]
+PrivateKey = AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=
+Address = 10.66.0.2/32, fd00::2/128
+DNS = 10.66.0.1, vpn.example
+MTU = 1420 # not ours to set
+
+[Peer]
+PublicKey = AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=
+Endpoint = 203.0.113.7:51820
+AllowedIPs = 0.0.0.0/0, ::/0
+PersistentKeepalive = 25
+";
+    const MAC: [u8; 6] = [2, 2, 4, 5, 6, 2];
+
+    #[test]
+    fn config_is_read() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.private_key, Some([1; 32]));
+        assert_eq!(config.address, Ipv4Address::new(10, 66, 0, 2));
+        assert_eq!(config.dns, Some(Ipv4Address::new(10, 66, 0, 1)));
+        assert_eq!(config.listen_port, None);
+        assert_eq!(config.peer_key, [2; 32]);
+        assert_eq!(config.preshared_key, [0; 32]);
+        assert_eq!(config.endpoint, (Ipv4Address::new(203, 0, 113, 7), 51820));
+        assert_eq!(config.allowed_ips, vec![Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0)]);
+        assert!(config.block_ipv6);
+        assert_eq!(config.keepalive, Some(25));
+
+        assert!(Config::parse(&CONFIG.replace("203.0.113.7", "vpn.example")).is_err());
+        assert!(Config::parse(&CONFIG.replace("AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=", "AgIC")).is_err());
+        assert!(Config::parse(&format!("{}[Peer]\nPublicKey = AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=\n", CONFIG)).is_err());
+    }
+
+    fn ipv4_frame(src: [u8; 4], dst: [u8; 4], protocol: u8) -> Vec<u8> {
+        let mut frame = vec![0x10; 6];
+        frame.extend_from_slice(&MAC);
+        frame.extend_from_slice(&ETHERTYPE_IPV4);
+        let mut header = [0u8; 20];
+        header[0] = 0x45;
+        header[2..4].copy_from_slice(&20u16.to_be_bytes());
+        header[9] = protocol;
+        header[12..16].copy_from_slice(&src);
+        header[16..20].copy_from_slice(&dst);
+        frame.extend_from_slice(&header);
+        frame
+    }
+
+    #[test]
+    fn tap_takes_what_the_tunnel_routes() {
+        let mut tap = Tap::new(MAC, &Config::parse(CONFIG).unwrap());
+        tap.set_lan(Some(Ipv4Cidr::new(Ipv4Address::new(192, 168, 1, 20), 24)));
+        assert!(tap.routes(Ipv4Address::new(8, 8, 8, 8)));
+        assert!(!tap.routes(Ipv4Address::new(203, 0, 113, 7)));
+        assert!(!tap.routes(Ipv4Address::new(192, 168, 1, 1)));
+        assert!(!tap.routes(Ipv4Address::new(224, 0, 0, 251)));
+
+        assert!(tap.take(&mut ipv4_frame([10, 66, 0, 2], [8, 8, 8, 8], PROTOCOL_TCP)));
+        assert_eq!(tap.pop_outbound().unwrap().len(), 20);
+        // the outside of the tunnel, and the LAN, go to the EC
+        assert!(!tap.take(&mut ipv4_frame([192, 168, 1, 20], [203, 0, 113, 7], 17)));
+        assert!(!tap.take(&mut ipv4_frame([192, 168, 1, 20], [192, 168, 1, 1], 17)));
+        // pings are moved into the tunnel, the rest that would go around it are dropped
+        assert!(tap.take(&mut ipv4_frame([192, 168, 1, 20], [8, 8, 8, 8], PROTOCOL_ICMP)));
+        let ping = tap.pop_outbound().unwrap();
+        assert_eq!(ping[12..16], [10, 66, 0, 2]);
+        assert_eq!(checksum(&[&ping]), 0);
+        assert!(tap.take(&mut ipv4_frame([192, 168, 1, 20], [8, 8, 8, 8], 17)));
+        assert!(tap.pop_outbound().is_none());
+
+        // ARP requests ask from the LAN address
+        let mut arp = vec![0xff; 6];
+        arp.extend_from_slice(&MAC);
+        arp.extend_from_slice(&ETHERTYPE_ARP);
+        arp.extend_from_slice(&[0, 1, 8, 0, 6, 4, 0, 1]);
+        arp.extend_from_slice(&MAC);
+        arp.extend_from_slice(&[10, 66, 0, 2]);
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(&[192, 168, 1, 1]);
+        assert!(!tap.take(&mut arp));
+        assert_eq!(arp[28..32], [192, 168, 1, 20]);
+
+        tap.push_inbound(&ipv4_frame([8, 8, 8, 8], [10, 66, 0, 2], PROTOCOL_TCP)[14..]);
+        let frame = tap.pop_inbound().unwrap();
+        assert_eq!(frame[..6], MAC);
+        assert_eq!(frame[6..12], TUNNEL_MAC);
+    }
+
+    #[test]
+    fn syns_are_clamped() {
+        let mut frame = ipv4_frame([10, 66, 0, 2], [8, 8, 8, 8], PROTOCOL_TCP);
+        let mut tcp = [0u8; 24];
+        tcp[12] = 6 << 4;
+        tcp[13] = 0x02;
+        tcp[20..24].copy_from_slice(&[2, 4, 0x05, 0xb4]);
+        frame[16..18].copy_from_slice(&44u16.to_be_bytes());
+        frame.extend_from_slice(&tcp);
+        let mut tap = Tap::new(MAC, &Config::parse(CONFIG).unwrap());
+        assert!(tap.take(&mut frame));
+        let packet = tap.pop_outbound().unwrap();
+        assert_eq!(packet[40..44], [2, 4, (MAX_MSS >> 8) as u8, MAX_MSS as u8]);
+        let mut pseudo_header = [0u8; 12];
+        pseudo_header[..8].copy_from_slice(&packet[12..20]);
+        pseudo_header[9] = PROTOCOL_TCP;
+        pseudo_header[11] = 24;
+        assert_eq!(checksum(&[&pseudo_header, &packet[20..]]), 0);
+    }
+
+    fn tunnel(config: &Config) -> Tunnel {
+        let mut seed = 0u8;
+        let mut tunnel = Tunnel::new(
+            config.clone(),
+            [1; 32],
+            Box::new(move |buf: &mut [u8]| {
+                seed = seed.wrapping_add(1);
+                buf.iter_mut().for_each(|b| *b = seed);
+            }),
+        );
+        tunnel.lan_up = true;
+        tunnel
+    }
+
+    #[test]
+    fn tunnel_handshakes_and_carries_packets() {
+        let config = Config::parse(CONFIG).unwrap();
+        let responder = Keys { secret: [2; 32], public: public_key(&[2; 32]), peer: public_key(&[1; 32]), preshared: [0; 32] };
+        let mut config = config;
+        config.peer_key = responder.public;
+        let mut tunnel = tunnel(&config);
+
+        // something to send starts a handshake, and waits for it
+        let packet = ipv4_frame([10, 66, 0, 2], [8, 8, 8, 8], 17)[14..].to_vec();
+        tunnel.send(1_000, packet.clone());
+        let initiation = tunnel.outbox.pop_front().unwrap();
+        assert!(tunnel.outbox.is_empty());
+        let (response, mut session, _) = noise::respond(&responder, 7, [5; 32], &initiation).unwrap();
+
+        // which is retried if no answer comes
+        tunnel.poll(1_000 + REKEY_TIMEOUT);
+        let retry = tunnel.outbox.pop_front().unwrap();
+        assert_ne!(retry, initiation);
+        assert_eq!(tunnel.status().state, WireguardState::Connecting);
+
+        // the answer to the first is too late now, but the one to the retry isn't
+        let from = (Ipv4Address::new(198, 51, 100, 1), 4500);
+        tunnel.receive(7_000, from, &response);
+        assert!(tunnel.outbox.is_empty());
+        let (response, mut session_retry, _) = noise::respond(&responder, 8, [6; 32], &retry).unwrap();
+        tunnel.receive(7_000, from, &response);
+        assert_eq!(tunnel.status().state, WireguardState::Connected);
+        assert_eq!(tunnel.endpoint, from);
+        let sent = tunnel.outbox.pop_front().unwrap();
+        assert!(session.decrypt(&sent).is_none());
+        assert_eq!(&session_retry.decrypt(&sent).unwrap()[..20], &packet[..]);
+
+        // packets come out with their padding off, as long as the peer may send them
+        let reply = ipv4_frame([8, 8, 8, 8], [10, 66, 0, 2], 17)[14..].to_vec();
+        tunnel.receive(8_000, from, &session_retry.encrypt(&reply).unwrap());
+        assert_eq!(tunnel.inbox.pop_front().unwrap(), reply);
+        // and get a keepalive back if nothing else goes
+        assert_eq!(tunnel.poll_delay(8_000), Some(KEEPALIVE_TIMEOUT));
+        tunnel.poll(8_000 + KEEPALIVE_TIMEOUT);
+        assert_eq!(session_retry.decrypt(&tunnel.outbox.pop_front().unwrap()).unwrap(), Vec::<u8>::new());
+
+        let mut limited = config.clone();
+        limited.allowed_ips = vec![Ipv4Cidr::new(Ipv4Address::new(10, 66, 0, 0), 24)];
+        tunnel.config = limited;
+        tunnel.receive(9_000, from, &session_retry.encrypt(&reply).unwrap());
+        assert!(tunnel.inbox.is_empty());
+    }
+
+    #[test]
+    fn timestamps_only_go_forward() {
+        let mut tunnel = tunnel(&Config::parse(CONFIG).unwrap());
+        let first = tunnel.next_timestamp();
+        assert!(tunnel.next_timestamp() > first);
+
+        // a clock that's behind where we've been doesn't take us back
+        let future = TAI64_BASE + 10_000_000_000;
+        tunnel.timestamp[..8].copy_from_slice(&future.to_be_bytes());
+        tunnel.timestamp[8..].copy_from_slice(&999_999_999u32.to_be_bytes());
+        let next = tunnel.next_timestamp();
+        assert_eq!(next[..8], (future + 1).to_be_bytes());
+        assert_eq!(next[8..], [0; 4]);
+    }
+}
@@ -0,0 +1,445 @@
+//! The WireGuard protocol proper: the Noise_IKpsk2 handshake, and the transport sessions that come
+//! out of it. See section 5 of the WireGuard paper, which the names here follow.
+//!
+//! We only ever initiate. Handshake initiations from the peer are ignored, as are cookie replies, so a
+//! peer that is under load and insists on a cookie won't let us in until its load goes down.
+
+use super::blake2s::{hash, hmac, mac};
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use std::convert::TryInto;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+const LABEL_MAC1: &[u8] = b"mac1----";
+
+pub(crate) const MSG_INITIATION: u8 = 1;
+pub(crate) const MSG_RESPONSE: u8 = 2;
+pub(crate) const MSG_COOKIE_REPLY: u8 = 3;
+pub(crate) const MSG_TRANSPORT: u8 = 4;
+
+pub(crate) const INITIATION_LEN: usize = 148;
+pub(crate) const RESPONSE_LEN: usize = 92;
+/// type, reserved, receiver index and counter
+pub(crate) const TRANSPORT_HEADER_LEN: usize = 16;
+pub(crate) const TAG_LEN: usize = 16;
+
+/// Sessions are retired after this many messages, well short of where the nonces would run out
+pub(crate) const REJECT_AFTER_MESSAGES: u64 = u64::MAX - (1 << 13);
+/// Out-of-order packets are accepted this far behind the newest one
+const WINDOW: u64 = 128;
+
+/// The public key of `secret`
+pub(crate) fn public_key(secret: &[u8; 32]) -> [u8; 32] {
+    *PublicKey::from(&StaticSecret::from(*secret)).as_bytes()
+}
+
+/// X25519. A result of all zeros means `public` was a low-order point, and the handshake must fail.
+fn dh(secret: &[u8; 32], public: &[u8; 32]) -> Option<[u8; 32]> {
+    let shared = *StaticSecret::from(*secret).diffie_hellman(&PublicKey::from(*public)).as_bytes();
+    if shared.iter().all(|&b| b == 0) {
+        None
+    } else {
+        Some(shared)
+    }
+}
+
+fn kdf1(key: &[u8; 32], input: &[u8]) -> [u8; 32] {
+    let t0 = hmac(key, &[input]);
+    hmac(&t0, &[&[1]])
+}
+fn kdf2(key: &[u8; 32], input: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let t0 = hmac(key, &[input]);
+    let t1 = hmac(&t0, &[&[1]]);
+    let t2 = hmac(&t0, &[&t1, &[2]]);
+    (t1, t2)
+}
+fn kdf3(key: &[u8; 32], input: &[u8]) -> ([u8; 32], [u8; 32], [u8; 32]) {
+    let t0 = hmac(key, &[input]);
+    let t1 = hmac(&t0, &[&[1]]);
+    let t2 = hmac(&t0, &[&t1, &[2]]);
+    let t3 = hmac(&t0, &[&t2, &[3]]);
+    (t1, t2, t3)
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    Nonce::from(nonce)
+}
+
+/// ChaCha20-Poly1305, appending the tag to `data`
+fn seal(key: &[u8; 32], counter: u64, aad: &[u8], data: &mut Vec<u8>) {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt_in_place(&nonce(counter), aad, data)
+        .expect("ChaCha20-Poly1305 refused to seal");
+}
+
+/// Opens `data` in place, and returns the length of the plaintext. `data` is left as it was if it
+/// doesn't authenticate.
+fn open(key: &[u8; 32], counter: u64, aad: &[u8], data: &mut [u8]) -> Option<usize> {
+    let len = data.len().checked_sub(TAG_LEN)?;
+    let (ciphertext, tag) = data.split_at_mut(len);
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt_in_place_detached(&nonce(counter), aad, ciphertext, Tag::from_slice(tag))
+        .ok()?;
+    Some(len)
+}
+
+/// The keys of the two ends of a tunnel
+pub(crate) struct Keys {
+    pub secret: [u8; 32],
+    pub public: [u8; 32],
+    pub peer: [u8; 32],
+    /// all zeros when no preshared key is in use
+    pub preshared: [u8; 32],
+}
+
+/// An initiation that's waiting for its response
+pub(crate) struct Handshake {
+    pub sender: u32,
+    ephemeral: [u8; 32],
+    hash: [u8; 32],
+    chaining_key: [u8; 32],
+}
+impl Handshake {
+    /// Makes a handshake initiation from `ephemeral`, a fresh secret key, and `timestamp`, a TAI64N
+    /// time that has to be later than that of any initiation the peer has seen from us.
+    pub(crate) fn initiate(keys: &Keys, sender: u32, ephemeral: [u8; 32], timestamp: [u8; 12]) -> Option<(Handshake, Vec<u8>)> {
+        let mut chaining_key = hash(&[CONSTRUCTION]);
+        let mut h = hash(&[&chaining_key, IDENTIFIER]);
+        h = hash(&[&h, &keys.peer]);
+
+        let mut msg = vec![MSG_INITIATION, 0, 0, 0];
+        msg.extend_from_slice(&sender.to_le_bytes());
+        let ephemeral_public = public_key(&ephemeral);
+        msg.extend_from_slice(&ephemeral_public);
+        chaining_key = kdf1(&chaining_key, &ephemeral_public);
+        h = hash(&[&h, &ephemeral_public]);
+
+        let (ck, key) = kdf2(&chaining_key, &dh(&ephemeral, &keys.peer)?);
+        chaining_key = ck;
+        let mut sealed = keys.public.to_vec();
+        seal(&key, 0, &h, &mut sealed);
+        h = hash(&[&h, &sealed]);
+        msg.extend_from_slice(&sealed);
+
+        let (ck, key) = kdf2(&chaining_key, &dh(&keys.secret, &keys.peer)?);
+        chaining_key = ck;
+        let mut sealed = timestamp.to_vec();
+        seal(&key, 0, &h, &mut sealed);
+        h = hash(&[&h, &sealed]);
+        msg.extend_from_slice(&sealed);
+
+        let mac1 = mac(&hash(&[LABEL_MAC1, &keys.peer]), &msg);
+        msg.extend_from_slice(&mac1);
+        // no cookie, so no second MAC
+        msg.extend_from_slice(&[0; 16]);
+        debug_assert_eq!(msg.len(), INITIATION_LEN);
+        Some((Handshake { sender, ephemeral, hash: h, chaining_key }, msg))
+    }
+
+    /// Checks a response to this initiation, and makes the session it sets up
+    pub(crate) fn complete(&self, keys: &Keys, msg: &[u8]) -> Option<Session> {
+        if msg.len() != RESPONSE_LEN || msg[0] != MSG_RESPONSE {
+            return None;
+        }
+        let receiver = u32::from_le_bytes(msg[8..12].try_into().unwrap());
+        if receiver != self.sender {
+            return None;
+        }
+        if mac(&hash(&[LABEL_MAC1, &keys.public]), &msg[..60]) != msg[60..76] {
+            return None;
+        }
+        let remote = u32::from_le_bytes(msg[4..8].try_into().unwrap());
+        let ephemeral_remote: [u8; 32] = msg[12..44].try_into().unwrap();
+
+        let mut chaining_key = kdf1(&self.chaining_key, &ephemeral_remote);
+        let mut h = hash(&[&self.hash, &ephemeral_remote]);
+        chaining_key = kdf1(&chaining_key, &dh(&self.ephemeral, &ephemeral_remote)?);
+        chaining_key = kdf1(&chaining_key, &dh(&keys.secret, &ephemeral_remote)?);
+        let (ck, tau, key) = kdf3(&chaining_key, &keys.preshared);
+        chaining_key = ck;
+        h = hash(&[&h, &tau]);
+        let mut empty = msg[44..60].to_vec();
+        if open(&key, 0, &h, &mut empty)? != 0 {
+            return None;
+        }
+
+        let (send, receive) = kdf2(&chaining_key, &[]);
+        Some(Session::new(self.sender, remote, send, receive))
+    }
+}
+
+/// The keys and counters of one handshake's worth of transport messages
+pub(crate) struct Session {
+    /// our index, which the peer puts in what it sends us
+    pub local: u32,
+    /// the peer's index, which we put in what we send
+    pub remote: u32,
+    send_key: [u8; 32],
+    receive_key: [u8; 32],
+    send_counter: u64,
+    /// the highest counter received, and a bitmap of which of the `WINDOW` below it have been
+    receive_max: Option<u64>,
+    receive_window: u128,
+}
+impl Session {
+    fn new(local: u32, remote: u32, send_key: [u8; 32], receive_key: [u8; 32]) -> Session {
+        Session { local, remote, send_key, receive_key, send_counter: 0, receive_max: None, receive_window: 0 }
+    }
+    pub(crate) fn messages_sent(&self) -> u64 {
+        self.send_counter
+    }
+
+    /// Makes a transport message of `packet`, which is padded to a multiple of 16 bytes first. An
+    /// empty packet is a keepalive. Returns `None` once the session has sent all it may.
+    pub(crate) fn encrypt(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        if self.send_counter >= REJECT_AFTER_MESSAGES {
+            return None;
+        }
+        let counter = self.send_counter;
+        self.send_counter += 1;
+        let mut msg = vec![MSG_TRANSPORT, 0, 0, 0];
+        msg.extend_from_slice(&self.remote.to_le_bytes());
+        msg.extend_from_slice(&counter.to_le_bytes());
+        let padded = (packet.len() + 15) & !15;
+        let mut data = Vec::with_capacity(padded + TAG_LEN);
+        data.extend_from_slice(packet);
+        data.resize(padded, 0);
+        seal(&self.send_key, counter, &[], &mut data);
+        msg.extend_from_slice(&data);
+        Some(msg)
+    }
+
+    /// Opens a transport message for this session, returning the padded packet inside it. Replays,
+    /// and anything that doesn't authenticate, come back as `None`.
+    pub(crate) fn decrypt(&mut self, msg: &[u8]) -> Option<Vec<u8>> {
+        if msg.len() < TRANSPORT_HEADER_LEN + TAG_LEN || msg[0] != MSG_TRANSPORT {
+            return None;
+        }
+        let counter = u64::from_le_bytes(msg[8..16].try_into().unwrap());
+        if counter >= REJECT_AFTER_MESSAGES || !self.is_fresh(counter) {
+            return None;
+        }
+        let mut data = msg[TRANSPORT_HEADER_LEN..].to_vec();
+        let len = open(&self.receive_key, counter, &[], &mut data)?;
+        // only authenticated counters move the window
+        self.mark_received(counter);
+        data.truncate(len);
+        Some(data)
+    }
+
+    fn is_fresh(&self, counter: u64) -> bool {
+        match self.receive_max {
+            None => true,
+            Some(max) if counter > max => true,
+            Some(max) => max - counter < WINDOW && self.receive_window & (1 << (max - counter)) == 0,
+        }
+    }
+    fn mark_received(&mut self, counter: u64) {
+        match self.receive_max {
+            Some(max) if counter <= max => self.receive_window |= 1 << (max - counter),
+            Some(max) => {
+                let shift = counter - max;
+                self.receive_window = if shift >= WINDOW { 0 } else { self.receive_window << shift };
+                self.receive_window |= 1;
+                self.receive_max = Some(counter);
+            }
+            None => {
+                self.receive_window = 1;
+                self.receive_max = Some(counter);
+            }
+        }
+    }
+}
+
+/// The responder's side of a handshake, for testing against: checks an initiation from the peer in
+/// `keys`, and answers it. Returns the response, the session, and the initiation's timestamp.
+#[cfg(test)]
+pub(crate) fn respond(keys: &Keys, sender: u32, ephemeral: [u8; 32], msg: &[u8]) -> Option<(Vec<u8>, Session, [u8; 12])> {
+    if msg.len() != INITIATION_LEN || msg[0] != MSG_INITIATION {
+        return None;
+    }
+    if mac(&hash(&[LABEL_MAC1, &keys.public]), &msg[..116]) != msg[116..132] {
+        return None;
+    }
+    let remote = u32::from_le_bytes(msg[4..8].try_into().unwrap());
+    let ephemeral_remote: [u8; 32] = msg[8..40].try_into().unwrap();
+
+    let mut chaining_key = hash(&[CONSTRUCTION]);
+    let mut h = hash(&[&chaining_key, IDENTIFIER]);
+    h = hash(&[&h, &keys.public]);
+    chaining_key = kdf1(&chaining_key, &ephemeral_remote);
+    h = hash(&[&h, &ephemeral_remote]);
+
+    let (ck, key) = kdf2(&chaining_key, &dh(&keys.secret, &ephemeral_remote)?);
+    chaining_key = ck;
+    let mut static_remote = msg[40..88].to_vec();
+    let len = open(&key, 0, &h, &mut static_remote)?;
+    if static_remote[..len] != keys.peer {
+        return None;
+    }
+    h = hash(&[&h, &msg[40..88]]);
+
+    let (ck, key) = kdf2(&chaining_key, &dh(&keys.secret, &keys.peer)?);
+    chaining_key = ck;
+    let mut timestamp = msg[88..116].to_vec();
+    let len = open(&key, 0, &h, &mut timestamp)?;
+    h = hash(&[&h, &msg[88..116]]);
+
+    let mut response = vec![MSG_RESPONSE, 0, 0, 0];
+    response.extend_from_slice(&sender.to_le_bytes());
+    response.extend_from_slice(&remote.to_le_bytes());
+    let ephemeral_public = public_key(&ephemeral);
+    response.extend_from_slice(&ephemeral_public);
+    chaining_key = kdf1(&chaining_key, &ephemeral_public);
+    h = hash(&[&h, &ephemeral_public]);
+    chaining_key = kdf1(&chaining_key, &dh(&ephemeral, &ephemeral_remote)?);
+    chaining_key = kdf1(&chaining_key, &dh(&ephemeral, &keys.peer)?);
+    let (ck, tau, key) = kdf3(&chaining_key, &keys.preshared);
+    chaining_key = ck;
+    h = hash(&[&h, &tau]);
+    let mut empty = Vec::new();
+    seal(&key, 0, &h, &mut empty);
+    response.extend_from_slice(&empty);
+    let mac1 = mac(&hash(&[LABEL_MAC1, &keys.peer]), &response);
+    response.extend_from_slice(&mac1);
+    response.extend_from_slice(&[0; 16]);
+
+    let (receive, send) = kdf2(&chaining_key, &[]);
+    Some((response, Session::new(sender, remote, send, receive), timestamp[..len].try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_pair(seed: u8) -> ([u8; 32], [u8; 32]) {
+        let secret = [seed; 32];
+        (secret, public_key(&secret))
+    }
+
+    fn both_ends(preshared: [u8; 32]) -> (Session, Session) {
+        let (initiator_secret, initiator_public) = key_pair(1);
+        let (responder_secret, responder_public) = key_pair(2);
+        let initiator = Keys { secret: initiator_secret, public: initiator_public, peer: responder_public, preshared };
+        let responder = Keys { secret: responder_secret, public: responder_public, peer: initiator_public, preshared };
+
+        let timestamp = [7u8; 12];
+        let (handshake, initiation) = Handshake::initiate(&initiator, 0x1111, [3; 32], timestamp).unwrap();
+        assert_eq!(initiation.len(), INITIATION_LEN);
+        let (response, responder_session, seen) = respond(&responder, 0x2222, [4; 32], &initiation).unwrap();
+        assert_eq!(seen, timestamp);
+        assert_eq!(response.len(), RESPONSE_LEN);
+
+        // a response that was tampered with, or that is for some other initiation, is no good
+        let mut forged = response.clone();
+        forged[50] ^= 1;
+        assert!(handshake.complete(&initiator, &forged).is_none());
+        let initiator_session = handshake.complete(&initiator, &response).unwrap();
+        assert_eq!((initiator_session.local, initiator_session.remote), (0x1111, 0x2222));
+        (initiator_session, responder_session)
+    }
+
+    #[test]
+    fn handshake_makes_matching_sessions() {
+        for &preshared in [[0u8; 32], [9u8; 32]].iter() {
+            let (mut initiator, mut responder) = both_ends(preshared);
+            let msg = initiator.encrypt(b"hello").unwrap();
+            assert_eq!(msg.len(), TRANSPORT_HEADER_LEN + 16 + TAG_LEN);
+            let packet = responder.decrypt(&msg).unwrap();
+            assert_eq!(&packet[..5], b"hello");
+            assert!(packet[5..].iter().all(|&b| b == 0));
+            let msg = responder.encrypt(&[]).unwrap();
+            assert_eq!(initiator.decrypt(&msg).unwrap(), Vec::<u8>::new());
+        }
+    }
+
+    #[test]
+    fn replays_are_dropped() {
+        let (mut initiator, mut responder) = both_ends([0; 32]);
+        let msgs: Vec<Vec<u8>> = (0..200).map(|_| initiator.encrypt(b"x").unwrap()).collect();
+        assert!(responder.decrypt(&msgs[5]).is_some());
+        assert!(responder.decrypt(&msgs[5]).is_none());
+        // out of order is fine, as long as it's within the window
+        assert!(responder.decrypt(&msgs[2]).is_some());
+        assert!(responder.decrypt(&msgs[199]).is_some());
+        assert!(responder.decrypt(&msgs[100]).is_some());
+        assert!(responder.decrypt(&msgs[50]).is_none());
+        // and one that doesn't authenticate doesn't take up its counter
+        let mut forged = msgs[150].clone();
+        forged[20] ^= 1;
+        assert!(responder.decrypt(&forged).is_none());
+        assert!(responder.decrypt(&msgs[150]).is_some());
+    }
+
+    /// A handshake with every input fixed, checked byte for byte. What it all starts from is
+    /// boringtun's `INITIAL_CHAIN_KEY` and `INITIAL_CHAIN_HASH`; the messages were worked out from
+    /// the paper separately, with Python's hashlib and `cryptography`.
+    #[test]
+    fn known_answers() {
+        let chaining_key = hash(&[CONSTRUCTION]);
+        assert_eq!(
+            chaining_key,
+            [
+                0x60, 0xE2, 0x6D, 0xAE, 0xF3, 0x27, 0xEF, 0xC0, 0x2E, 0xC3, 0x35, 0xE2, 0xA0, 0x25, 0xD2, 0xD0,
+                0x16, 0xEB, 0x42, 0x06, 0xF8, 0x72, 0x77, 0xF5, 0x2D, 0x38, 0xD1, 0x98, 0x8B, 0x78, 0xCD, 0x36,
+            ]
+        );
+        assert_eq!(
+            hash(&[&chaining_key, IDENTIFIER]),
+            [
+                0x22, 0x11, 0xB3, 0x61, 0x08, 0x1A, 0xC5, 0x66, 0x69, 0x12, 0x43, 0xDB, 0x45, 0x8A, 0xD5, 0x32,
+                0x2D, 0x9C, 0x6C, 0x66, 0x22, 0x93, 0xE8, 0xB7, 0x0E, 0xE1, 0x9C, 0x65, 0xBA, 0x07, 0x9E, 0xF3,
+            ]
+        );
+
+        let (initiator_secret, initiator_public) = key_pair(1);
+        let (responder_secret, responder_public) = key_pair(2);
+        let preshared = [9; 32];
+        let initiator = Keys { secret: initiator_secret, public: initiator_public, peer: responder_public, preshared };
+        let responder = Keys { secret: responder_secret, public: responder_public, peer: initiator_public, preshared };
+        let (handshake, initiation) = Handshake::initiate(&initiator, 0x1111, [3; 32], [7; 12]).unwrap();
+        assert_eq!(
+            initiation,
+            [
+                0x01, 0x00, 0x00, 0x00, 0x11, 0x11, 0x00, 0x00, 0x5D, 0xFE, 0xDD, 0x3B, 0x6B, 0xD4, 0x7F, 0x6F,
+                0xA2, 0x8E, 0xE1, 0x5D, 0x96, 0x9D, 0x5B, 0xB0, 0xEA, 0x53, 0x77, 0x4D, 0x48, 0x8B, 0xDA, 0xF9,
+                0xDF, 0x1C, 0x6E, 0x01, 0x24, 0xB3, 0xEF, 0x22, 0x0A, 0x26, 0x59, 0x9F, 0xB2, 0x18, 0x8B, 0xB7,
+                0x23, 0x27, 0x6F, 0xC1, 0x73, 0xAF, 0x67, 0x61, 0x6C, 0x81, 0x7F, 0xC3, 0x2F, 0xD0, 0x4D, 0x68,
+                0x6D, 0x87, 0xEC, 0x15, 0x2F, 0xAC, 0x10, 0xEE, 0xD2, 0xBD, 0xA3, 0xBB, 0x3B, 0x6F, 0x1E, 0xDE,
+                0xD6, 0x97, 0x76, 0x84, 0x08, 0x22, 0x84, 0xD8, 0x50, 0xFC, 0x41, 0xB4, 0xCB, 0xCF, 0x9D, 0xEE,
+                0xBA, 0x20, 0x39, 0xD1, 0xC2, 0x17, 0x10, 0xE7, 0x08, 0x1F, 0x15, 0x8A, 0x3F, 0x5B, 0xB6, 0xCE,
+                0xB5, 0xC3, 0x44, 0xE3, 0x0E, 0x9E, 0x5F, 0xAC, 0x23, 0xCF, 0x2F, 0xAC, 0xBE, 0xE0, 0xEA, 0x82,
+                0x58, 0xA2, 0xD9, 0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00,
+            ]
+            .to_vec()
+        );
+        let (response, _, _) = respond(&responder, 0x2222, [4; 32], &initiation).unwrap();
+        assert_eq!(
+            response,
+            [
+                0x02, 0x00, 0x00, 0x00, 0x22, 0x22, 0x00, 0x00, 0x11, 0x11, 0x00, 0x00, 0xAC, 0x01, 0xB2, 0x20,
+                0x9E, 0x86, 0x35, 0x4F, 0xB8, 0x53, 0x23, 0x7B, 0x5D, 0xE0, 0xF4, 0xFA, 0xB1, 0x3C, 0x7F, 0xCB,
+                0xF4, 0x33, 0xA6, 0x1C, 0x01, 0x93, 0x69, 0x61, 0x7F, 0xEC, 0xF1, 0x0B, 0x4F, 0x45, 0x4C, 0x97,
+                0xE3, 0xCF, 0xC7, 0x15, 0x13, 0x99, 0x94, 0xA8, 0x02, 0x63, 0xCB, 0xCA, 0x22, 0xBE, 0x1E, 0xED,
+                0x43, 0xEF, 0xD9, 0x01, 0x93, 0x1D, 0xA0, 0x26, 0xDE, 0x09, 0x5C, 0xB6, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ]
+            .to_vec()
+        );
+        let mut session = handshake.complete(&initiator, &response).unwrap();
+        assert_eq!(
+            session.encrypt(b"hello").unwrap(),
+            [
+                0x04, 0x00, 0x00, 0x00, 0x22, 0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x4C, 0x10, 0x81, 0xA4, 0x37, 0x7E, 0x21, 0xDC, 0x88, 0xBD, 0x5E, 0x0A, 0x20, 0x0F, 0x68, 0x45,
+                0x1D, 0xD8, 0xBD, 0x2C, 0xEC, 0x79, 0xE8, 0x01, 0x80, 0x14, 0xE0, 0x6F, 0x87, 0x5B, 0x91, 0xE4,
+            ]
+            .to_vec()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! Sizes and flags that are fixed by the on-disk format. The PDDB's API re-exports the ones its callers
+//! see.
+use bitfield::bitfield;
+
+pub const BASIS_NAME_LEN: usize = 64; // don't want this too long anyways, because it's not recorded anywhere - users have to type it in.
+pub const DICT_NAME_LEN: usize = 127 - 4 - 4 - 4 - 4; // u32: flags, age, free index, numkeys = 111
+pub const KEY_NAME_LEN: usize = 127 - 8 - 8 - 8 - 4 - 4; // u64: vaddr/len/resvd, u32: flags, age = 95
+pub const PASSWORD_LEN: usize = 72; // this is actually set by bcrypt
+pub const PDDB_MAGIC: [u8; 4] = [0x50, 0x44, 0x44, 0x42];
+/// migrateable version pairs
+/// PDDB_MIGRATE_1:
+///   00.00.01.01 - xous 0.9.7 release (original base release)
+///   00.00.02.01 - xous 0.9.8 migration -> hkdf added on basis key derivation to make separate PT/data keys
+pub const PDDB_MIGRATE_1: (u32, u32) = (0x00_00_01_01, 0x00_00_02_01);
+pub const PDDB_VERSION: u32 = 0x00_00_02_01;
+
+// bases that are keyed with bcrypt fall short of the OWASP target; new bases use Argon2id, see kdf.rs
+pub const BCRYPT_COST: u32 = 7;   // 10 is the minimum recommended by OWASP; takes 5696 ms to verify @ 10 rounds; 804 ms to verify 7 rounds
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    pub struct KeyFlags(u32);
+    impl Debug;
+    /// set if the entry is valid -- in the cache, an invalid entry means it was previously allocated but then deleted, and needs a sync
+    pub valid, set_valid: 0;
+    /// resolved indicates that the "start" address isn't fully resolved yet in the cache
+    pub unresolved, set_unresolved: 1;
+}
@@ -0,0 +1,120 @@
+// Password hashing for the keys of secret Basis.
+//
+// Bases were originally keyed with bcrypt alone. bcrypt needs next to no memory, so it is cheap to attack
+// with dedicated hardware, and at the cost Precursor can afford to run it at (see BCRYPT_COST in format.rs)
+// it falls short of the OWASP target. Argon2id is memory-hard, and its memory cost can be traded against
+// its time cost to keep the unlock latency acceptable. `services/benchmark` has a `kdf` suite that times
+// both at a range of parameters.
+//
+// The KDF of a basis and its parameters are recorded in its root record. That record is encrypted with
+// the keys the KDF derives, so it can't be consulted to unlock the basis; instead, the KDFs in
+// `KDF_PROBE_ORDER` are tried in turn until one yields keys that open the basis. Keeping the choice of KDF
+// out of the clear means it can't be used to tell that a secret basis exists.
+
+use core::convert::TryInto;
+
+/// Argon2id memory cost, in KiB. This and `ARGON2_T_COST` are one of the OWASP recommended settings
+/// (m=7 MiB, t=5, p=1). The time they take to unlock a basis on a device is what the `kdf` suite of
+/// `services/benchmark` reports as `argon2id-m7168-t5-default`.
+pub const ARGON2_M_COST: u32 = 7 * 1024;
+/// Argon2id number of passes over the memory
+pub const ARGON2_T_COST: u32 = 5;
+/// Argon2id degree of parallelism. Precursor only has one core.
+pub const ARGON2_P_COST: u32 = 1;
+/// Parameters recorded on disk are only trusted up to this memory cost, so a damaged record can't
+/// make the PDDB try to allocate more memory than the device has.
+pub const ARGON2_MAX_M_COST: u32 = 8 * 1024;
+/// Length of the longest hash a KDF returns
+pub const KDF_OUTPUT_LEN: usize = 32;
+
+const KDF_BCRYPT: u32 = 0;
+const KDF_ARGON2ID: u32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BasisKdf {
+    /// bcrypt at `BCRYPT_COST`. This is what bases made before the KDF was recorded in the
+    /// root record use.
+    Bcrypt,
+    Argon2id { m_cost: u32, t_cost: u32, p_cost: u32 },
+}
+
+/// The KDFs a basis may have been keyed with, most likely first. If the default Argon2id parameters
+/// are ever changed, the old ones have to be added here, or bases keyed with them can't be unlocked.
+pub const KDF_PROBE_ORDER: [BasisKdf; 2] = [BasisKdf::DEFAULT, BasisKdf::Bcrypt];
+
+impl BasisKdf {
+    /// The KDF new bases, and bases that have their password changed, are keyed with.
+    pub const DEFAULT: BasisKdf = BasisKdf::Argon2id {
+        m_cost: ARGON2_M_COST,
+        t_cost: ARGON2_T_COST,
+        p_cost: ARGON2_P_COST,
+    };
+
+    /// Hashes `password` with `salt` into `output`, and returns the number of bytes of `output` that
+    /// hold the hash.
+    pub fn hash_password(&self, salt: &[u8; 16], password: &str, output: &mut [u8; KDF_OUTPUT_LEN]) -> usize {
+        match self {
+            BasisKdf::Bcrypt => {
+                // note: this internally makes a copy of the password, and destroys it
+                crate::bcrypt::bcrypt(crate::BCRYPT_COST, salt, password, &mut output[..24]);
+                24
+            }
+            BasisKdf::Argon2id { m_cost, t_cost, p_cost } => {
+                let params = argon2::Params::new(*m_cost, *t_cost, *p_cost, Some(KDF_OUTPUT_LEN))
+                    .expect("invalid Argon2id parameters");
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), salt, output)
+                    .expect("Argon2id hashing failed");
+                KDF_OUTPUT_LEN
+            }
+        }
+    }
+
+    /// Decodes the KDF recorded in a basis root. Returns None if the record is not one this
+    /// version of the PDDB knows how to use.
+    pub fn from_record(record: &KdfRecord) -> Option<BasisKdf> {
+        match record.algorithm {
+            KDF_BCRYPT => Some(BasisKdf::Bcrypt),
+            KDF_ARGON2ID => {
+                if record.m_cost == 0 || record.m_cost > ARGON2_MAX_M_COST
+                || record.t_cost == 0 || record.p_cost == 0 {
+                    None
+                } else {
+                    Some(BasisKdf::Argon2id { m_cost: record.m_cost, t_cost: record.t_cost, p_cost: record.p_cost })
+                }
+            }
+            _ => None,
+        }
+    }
+    pub fn to_record(&self) -> KdfRecord {
+        match self {
+            BasisKdf::Bcrypt => KdfRecord::default(),
+            BasisKdf::Argon2id { m_cost, t_cost, p_cost } => KdfRecord {
+                algorithm: KDF_ARGON2ID,
+                m_cost: *m_cost,
+                t_cost: *t_cost,
+                p_cost: *p_cost,
+            },
+        }
+    }
+}
+
+/// The KDF of a basis, as stored in its root record. Bases made before the KDF was recorded have
+/// zeroes here, which reads as bcrypt. The system basis has zeroes too, as its keys are wrapped by
+/// the root keys rather than derived from a password.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct KdfRecord {
+    pub algorithm: u32,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+#[allow(dead_code)] // used by the tools that decode images
+impl KdfRecord {
+    pub const LEN: usize = 16;
+    pub fn from_bytes(data: &[u8]) -> KdfRecord {
+        let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+        KdfRecord { algorithm: word(0), m_cost: word(1), t_cost: word(2), p_cost: word(3) }
+    }
+}
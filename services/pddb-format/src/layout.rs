@@ -0,0 +1,320 @@
+//! The on-disk layout of the PDDB: the placement of the structures in flash and in each basis' virtual memory
+//! space, and the records that hold bases, dictionaries and keys.
+use crate::{BASIS_NAME_LEN, DICT_NAME_LEN, KEY_NAME_LEN, PDDB_VERSION, KeyFlags};
+use crate::{PAGE_SIZE, AES_KEYSIZE, JournalType, KdfRecord};
+
+use aes_gcm_siv::{Nonce, Tag};
+use bitfield::bitfield;
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
+use std::io::{Result, Error, ErrorKind};
+
+/// Implementation-specific PDDB structures: for Precursor/Xous OS pair
+pub const MBBB_PAGES: usize = 10;
+pub const FSCB_PAGES: usize = 16;
+
+/// size of a virtual page -- after the AES encryption and journaling overhead is subtracted
+pub const VPAGE_SIZE: usize = PAGE_SIZE - size_of::<Nonce>() - size_of::<Tag>() - size_of::<JournalType>();
+
+/// length of the ciphertext in an AES-GCM-SIV page with key commitments
+/// equal to the total plaintext to be encrypted, including the journal number
+/// does not include the MAC overhead
+pub const KCOM_CT_LEN: usize = 4004;
+
+pub const WRAPPED_AES_KEYSIZE: usize = AES_KEYSIZE + 8;
+
+/// Layout of the virtual memory space of a basis. The basis root is at VPAGE_SIZE, the dictionary descriptors
+/// follow it in DICT_VSIZE regions, and then come the small pool, the transaction journal, the quarantine
+/// region, the space reserved for the medium pool, and the large pool. The table in `basis.rs` has to be
+/// kept in step with these.
+pub const SMALL_POOL_START: u64 = 0x0000_003F_8000_0000;
+pub const SMALL_POOL_END: u64 = 0x0000_007E_FF02_0000;
+pub const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
+/// The journal takes the 4GiB after the end of the small pool, up to the quarantine region. The header is
+/// at the start of the range, the body follows it.
+pub const TXN_JOURNAL_START: u64 = 0x0000_007F_0000_0000;
+/// Quarantined pages are remapped into the 4GiB that follow the journal. That's room for far more pages
+/// than the FLASH has. The medium pool, once there is one, starts at `QUARANTINE_END`.
+pub const QUARANTINE_START: u64 = 0x0000_0080_0000_0000;
+pub const QUARANTINE_END: u64 = 0x0000_0081_0000_0000;
+pub const LARGE_POOL_START: u64 = 0x0000_FE00_0000_0000;
+pub const KEY_MAXCOUNT: usize = 131_071; // 2^17 - 1
+/// This is a size limit on the biggest file you can create. It's currently 32GiB. No, this is not
+/// web scale, but it's big enough to hold a typical blu-ray movie as a single file. You can adjust
+/// this constant up or down, and the trade-off is, you get more or less total number of large files
+/// allocated over the life of the filesystem. We simply "increment a pointer" when a new large file
+/// is added to create the next virtual memory spot for the large file. So at 32GiB, you can create
+/// a lifetime total of about 200 million files (this includes files you've previously deleted, until
+/// we create a mechanism for sweeping through the memory space and tracking de-allocations). Note that
+/// a "large" file includes anything over 4kiB, so if you create a 5kiB file, it can potentially grow to
+/// 32 GiB without bumping into the next large file. This is a very "lazy" way to deal with large files.
+/// Given that the PDDB is designed for a 32-bit device with only 128MiB of memory and a read/write lifetime
+/// of 100k cycles for the FLASH, 200 million file allocations is probably greater than the lifetime of
+/// the device itself. If the PDDB migrates to a larger handphone-style application, I think it'll probably
+/// still hold up OK with 200 million total large file allocations over the device lifetime and a limit
+/// of 32GiB. That's about 73k files created per day for 10 years, or about 50 files per minute -- roughly
+/// one new file per second for 10 years straight before the PDDB runs out of virtual memory space.
+/// A web server creating a >4k temporary log file for every client that hit and then deleting it
+/// would probably crush this limit in months. So don't use the PDDB to back a high volume web server.
+/// But it's probably OK for a consumer electronics device with a typical lifetime of less than 10 years.
+/// If you really think you want larger files and also more write life, you'd need to implement an in-memory
+/// "free" file allocator, but honestly, this is not something I think we need to burn resources on for
+/// the initial target of the PDDB (that is, a 100MiB device with 100k read/write endurance lifetime).
+/// Anyways, the code is written so you can just slide this constant up or down and change the behavior
+/// of the system; it's recommended you reformat when you do that but I /think/ it should actually be OK
+/// if you made a change "on the fly".
+///
+/// Also note that in practice, a file size is limited to 4GiB on a 32-bit Precursor device anyways
+/// because the usize type isn't big enough. Recompiling for a 64-bit target, however, should give
+/// you access to the 32GiB file size limit.
+pub const LARGE_FILE_MAX_SIZE: u64 = 0x0000_0008_0000_0000;
+
+/// The chosen "stride" of a dict/key entry. Drives a lot of key parameters in the database's characteristics.
+/// This is chosen such that 32 of these entries fit evenly into a VPAGE.
+pub const DK_STRIDE: usize = 127;
+//// DK_STRIDES per VPAGE
+pub const DK_PER_VPAGE: usize = VPAGE_SIZE / DK_STRIDE; // should be 32 - use this for computing modulus on dictionary indices
+/// size of a dictionary region in virtual memory
+pub const DICT_VSIZE: u64 = 0xFE_0000;
+/// maximum number of dictionaries in a system
+pub const DICT_MAXCOUNT: usize = 16383;
+
+/// This is the format of the Basis as stored on disk
+#[derive(PartialEq, Debug, Default)]
+#[repr(C, align(8))]
+pub struct BasisRoot {
+    pub magic: [u8; 4],
+    pub version: u32,
+    /// increments every time the BasisRoot is modified. This field must saturate, not roll over.
+    pub age: u32,
+    /// number of dictionaries.
+    pub num_dictionaries: u32,
+    /* at this point, we are aligned to a 64-bit boundary. All data must stay aligned to this boundary from here out! */
+    /// 64-byte name; aligns to 64-bits
+    pub name: BasisRootName,
+    /// the KDF the keys of the basis are derived with. Reads as bcrypt on bases that predate it.
+    pub kdf: KdfRecord,
+}
+impl BasisRoot {
+    pub fn aad(&self, dna: u64) -> Vec::<u8> {
+        let mut aad = Vec::<u8>::new();
+        aad.extend_from_slice(&self.name.data[..self.name.len as usize]);
+        aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
+        aad.extend_from_slice(&dna.to_le_bytes());
+        aad
+    }
+}
+impl Deref for BasisRoot {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const BasisRoot as *const u8, core::mem::size_of::<BasisRoot>())
+                as &[u8]
+        }
+    }
+}
+impl DerefMut for BasisRoot {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self as *mut BasisRoot as *mut u8, core::mem::size_of::<BasisRoot>())
+                as &mut [u8]
+        }
+    }
+}
+
+/// Newtype for BasisRootName so we can give it a default initializer.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct BasisRootName {
+    pub len: u8,
+    pub data: [u8; BASIS_NAME_LEN - 1],
+}
+impl BasisRootName {
+    pub fn try_from_str(name: &str) -> Result<BasisRootName> {
+        let mut alloc = [0u8; BASIS_NAME_LEN - 1];
+        let bytes = name.as_bytes();
+        if bytes.len() > (BASIS_NAME_LEN - 1) {
+            Err(Error::new(ErrorKind::InvalidInput, "basis name is too long")) // FileNameTooLong is still nightly :-/
+        } else {
+            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
+                *dst = src;
+            }
+            Ok(BasisRootName {
+                len: bytes.len() as u8, // this as checked above to be short enough
+                data: alloc,
+            })
+        }
+    }
+}
+impl Default for BasisRootName {
+    fn default() -> BasisRootName {
+        BasisRootName{
+            len: 0,
+            data: [0; BASIS_NAME_LEN - 1]
+        }
+    }
+}
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    pub struct DictFlags(u32);
+    impl Debug;
+    pub valid, set_valid: 0;
+    /// set while a compaction is rewriting the dictionary. Descriptors may be duplicated on disk while
+    /// this is set, and the copy with the highest age is authoritative.
+    pub compacting, set_compacting: 1;
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[repr(C, align(8))]
+pub struct DictName {
+    pub len: u8,
+    pub data: [u8; DICT_NAME_LEN - 1],
+}
+impl DictName {
+    pub fn try_from_str(name: &str) -> Result<DictName> {
+        let mut alloc = [0u8; DICT_NAME_LEN - 1];
+        let bytes = name.as_bytes();
+        if bytes.len() > (DICT_NAME_LEN - 1) {
+            Err(Error::new(ErrorKind::InvalidInput, "dict name is too long"))
+        } else {
+            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
+                *dst = src;
+            }
+            Ok(DictName {
+                len: bytes.len() as u8, // this as checked above to be short enough
+                data: alloc,
+            })
+        }
+    }
+}
+impl Default for DictName {
+    fn default() -> DictName {
+        DictName {
+            len: 0,
+            data: [0; DICT_NAME_LEN - 1]
+        }
+    }
+}
+
+#[derive(Debug)]
+/// On-disk representation of the dictionary header. This structure is mainly for archival/unarchival
+/// purposes. To "functionalize" a stored disk entry, it needs to be deserialized into a DictionaryCacheEntry.
+#[repr(C, align(8))]
+pub struct Dictionary {
+    /// Reserved for flags on the record entry
+    pub flags: DictFlags,
+    /// Access count to the dicitionary
+    pub age: u32,
+    /// Number of keys in the dictionary
+    pub num_keys: u32,
+    /// Free index starting space. While this is a derived parameter, its value is recorded to avoid
+    /// an expensive, long search operation during the creation of a dictionary cache record. 0 is an invalid index,
+    /// as this is where the header goes. Maybe this should be a NonZeroU32.
+    pub free_key_index: u32,
+    /// Name. Length should pad out the record to exactly 127 bytes.
+    pub name: DictName,
+}
+impl Default for Dictionary {
+    fn default() -> Dictionary {
+        let mut flags = DictFlags(0);
+        flags.set_valid(true);
+        Dictionary { flags, age: 0, num_keys: 0, free_key_index: 1, name: DictName::default() }
+    }
+}
+impl Deref for Dictionary {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const Dictionary as *const u8, core::mem::size_of::<Dictionary>())
+                as &[u8]
+        }
+    }
+}
+impl DerefMut for Dictionary {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self as *mut Dictionary as *mut u8, core::mem::size_of::<Dictionary>())
+                as &mut [u8]
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[repr(C, align(8))]
+pub struct KeyName {
+    pub len: u8,
+    pub data: [u8; KEY_NAME_LEN - 1],
+}
+impl KeyName {
+    pub fn try_from_str(name: &str) -> Result<KeyName> {
+        let mut alloc = [0u8; KEY_NAME_LEN - 1];
+        let bytes = name.as_bytes();
+        if bytes.len() > (KEY_NAME_LEN - 1) {
+            Err(Error::new(ErrorKind::InvalidInput, "key name is too long"))
+        } else {
+            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
+                *dst = src;
+            }
+            Ok(KeyName {
+                len: bytes.len() as u8, // this as checked above to be short enough
+                data: alloc,
+            })
+        }
+    }
+}
+impl Default for KeyName {
+    fn default() -> KeyName {
+        KeyName {
+            len: 0,
+            data: [0; KEY_NAME_LEN - 1]
+        }
+    }
+}
+
+/// On-disk representation of the Key. Note that the storage on disk is mis-aligned relative
+/// to Rust's expecatation of in-RAM format, so any deserialization must essentially come with
+/// a copy step to re-align the record to meet Rust's placement rules.
+#[repr(C, align(8))]
+pub struct KeyDescriptor {
+    /// virtual address of the key's start
+    pub start: u64,
+    /// length of the key's stored data
+    pub len: u64,
+    /// amount of space reserved for the key. Must be >= len.
+    pub reserved: u64,
+    /// Reserved for flags on the record entry
+    pub flags: KeyFlags,
+    /// Access count to the key
+    pub age: u32,
+    /// Name. Length should pad out the record to exactly 127 bytes.
+    pub name: KeyName,
+}
+impl Default for KeyDescriptor {
+    fn default() -> Self {
+        KeyDescriptor {
+            start: 0,
+            len: 0,
+            reserved: 0,
+            flags: KeyFlags(0),
+            age: 0,
+            name: KeyName::default(),
+        }
+    }
+}
+impl Deref for KeyDescriptor {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const KeyDescriptor as *const u8, core::mem::size_of::<KeyDescriptor>())
+                as &[u8]
+        }
+    }
+}
+impl DerefMut for KeyDescriptor {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self as *mut KeyDescriptor as *mut u8, core::mem::size_of::<KeyDescriptor>())
+                as &mut [u8]
+        }
+    }
+}
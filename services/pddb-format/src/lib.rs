@@ -0,0 +1,23 @@
+//! The on-disk format of the Plausibly Deniable Database: the layout of the structures in flash, the page
+//! table entries, the page bookkeeping types and the password hashing that keys a basis.
+//!
+//! None of this depends on a running system, so the PDDB service and the tools that decode its images on
+//! the host (`tools/pddb-inspect`, the `kdf` suite of `services/benchmark`) share one definition of it.
+
+mod format;
+pub use format::*;
+mod murmur3;
+pub use murmur3::*;
+mod bcrypt;
+pub use bcrypt::*;
+mod kdf;
+pub use kdf::*;
+mod types;
+pub use types::*;
+mod pagetable;
+pub use pagetable::*;
+mod layout;
+pub use layout::*;
+
+/// size of a physical page; the PDDB service checks it against the erase size of the SPI flash
+pub const PAGE_SIZE: usize = 4096;
@@ -1,8 +1,6 @@
-use super::{PAGE_SIZE, TrngPool, VirtAddr, murmur3_32, VPAGE_SIZE};
+use crate::{PAGE_SIZE, VirtAddr, murmur3_32, VPAGE_SIZE};
 use core::mem::size_of;
 use aes_gcm_siv::{Nonce, Tag};
-use std::rc::Rc;
-use core::cell::RefCell;
 use core::ops::{Deref, DerefMut};
 use core::convert::TryInto;
 
@@ -42,7 +40,7 @@ impl Default for PtFlags {
 /// nonce and checksum structure.
 #[repr(packed)]
 #[derive(Default)]
-pub(crate) struct Pte {
+pub struct Pte {
     /// the virtual page number is 52 bits long (52 + 12 = 64). 4 bits are wasted in this representation.
     /// The storage format is in *page numbers* but the API accepts *addresses*. Therefore a division and
     /// multiplication by VPAGE_SIZE wraps the getters and setters for this field.
@@ -56,8 +54,8 @@ pub(crate) struct Pte {
     checksum: [u8; 4],
 }
 impl Pte {
-    pub fn new(va: VirtAddr, flags: PtFlags, entropy: Rc<RefCell<TrngPool>>) -> Self {
-        let nonce_u32 = entropy.borrow_mut().get_u32();
+    /// `nonce_u32` must be fresh entropy, so that identical entries don't encrypt to the same ciphertext.
+    pub fn new(va: VirtAddr, flags: PtFlags, nonce_u32: u32) -> Self {
         let mut pte = Pte {
             pddb_addr: (va.get() / VPAGE_SIZE as u64).to_le_bytes()[..7].try_into().unwrap(),
             flags,
@@ -115,8 +113,7 @@ impl Pte {
     /// Normally you should be using pt_patch_mapping(), which generates a new nonce every
     /// time the entry is patched. However, this function is provided for "bulk" operations
     /// such as migrations where we violate the abstractions to improve performance.
-    pub fn re_nonce(&mut self, entropy: Rc<RefCell<TrngPool>>) {
-        let nonce_u32 = entropy.borrow_mut().get_u32();
+    pub fn re_nonce(&mut self, nonce_u32: u32) {
         self.nonce = nonce_u32.to_le_bytes();
         let pte_data = self.deref();
         let checksum = murmur3_32(&pte_data[..12], nonce_u32);
@@ -142,29 +139,17 @@ impl DerefMut for Pte {
     }
 }
 
-pub const PDDB_SIZE_PAGES: usize = crate::PDDB_A_LEN as usize / PAGE_SIZE;
-/// This structure is mapped into the top of FLASH memory, starting at
-/// xous::PDDB_LOC. This actually slightly over-sizes the page table,
-/// because the page table does not map the locations for the page table
-/// itself, the MBBB, or the FSCB. However, the 0th entry of the page table
-/// always corresponds to the base of data in FLASH, which means the excess
-/// pages are going to be toward the high end of the page table range.
-#[repr(C, packed)]
-pub(crate) struct PageTableInFlash {
-    table: [Pte; PDDB_SIZE_PAGES],
-}
-
 /// This is the representation of a page of data on disk. Keys that span multiple
 /// pages have to decrypt individual pages, subtracting the nonce, journalrev, and tag, to find
 /// the actual data being retrieved.
 #[allow(dead_code)] // this structure is never explicitly created, but it's helpful to have to document our layout on disk.
-pub(crate) struct EncryptedPage {
+pub struct EncryptedPage {
     /// the nonce is not encrypted
     p_nonce: [u8; size_of::<Nonce>()],
     /// journal_rev is encrypted and indicates the current journal revision for the block (u32 le)
     journal_rev: [u8; 4],
     /// data is encrypted and holds the good stuff
-    data: [u8; (PAGE_SIZE - size_of::<Nonce>() - size_of::<Tag>() - size_of::<u32>())],
+    data: [u8; PAGE_SIZE - size_of::<Nonce>() - size_of::<Tag>() - size_of::<u32>()],
     /// tag is the authentication tag. If the page decrypts & authenticates, we know it's a valid data block for us.
     p_tag: [u8; size_of::<Tag>()],
 }
\ No newline at end of file
@@ -1,7 +1,6 @@
 use core::num::NonZeroU64;
 use core::ops::Add;
-use super::{PAGE_SIZE, VPAGE_SIZE};
-use crate::SpaceState;
+use crate::{PAGE_SIZE, VPAGE_SIZE};
 use bitfield::bitfield;
 use std::hash::{Hash, Hasher};
 use std::cmp::Ordering;
@@ -13,17 +12,47 @@ use std::cmp::Ordering;
 /// on docs.rs, they just pull the number 16 out of their ass instead of referring to a trait.
 /// So, maybe that's just what you're supposed to do. ¯\_(ツ)_/¯ Oddly enough, a BLOCK_SIZE constant /is/ defined,
 /// but maybe that's because it's constant regardless of the key size so it's easy to do.
-pub(crate) const AES_KEYSIZE: usize = 32;
+pub const AES_KEYSIZE: usize = 32;
 
 /// This has to be manually synchronized with the bit range of the `journal` field below. It doesn't look like
 /// there is a good way to automatically derive this.
-pub(crate) const PHYS_PAGE_JOURNAL_MAX: u8 = 15;
+pub const PHYS_PAGE_JOURNAL_MAX: u8 = 15;
 /// We should be able to change this to a u64 and everything should "just work", but
 /// we'd end up using 2x the amount of data for overhead and bookkeeping.
 #[cfg(not(feature = "u64_pa"))]
 pub type PhysAddr = u32;
 #[cfg(feature = "u64_pa")]
 pub type PhysAddr = u64;
+/// Free space tracking state of a physical page, as recorded in `PhysPage`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[repr(u8)]
+pub enum SpaceState {
+    /// pages that are completely un-spoken for
+    Free = 0,
+    /// pages that are in the process of being used, but the journal has yet to be committed
+    /// in other words, these are pages that might be in the RAM cache.
+    MaybeUsed = 1,
+    /// pages that are confirm plus chop fully used
+    Used = 2,
+    /// pages that are no longer used and need to be erased
+    Dirty = 3,
+}
+impl From<u8> for SpaceState {
+    fn from(arg: u8) -> Self {
+        match arg & 0x3 {
+            0 => SpaceState::Free,
+            1 => SpaceState::MaybeUsed,
+            2 => SpaceState::Used,
+            _ => SpaceState::Dirty,
+        }
+    }
+}
+impl From<SpaceState> for u8 {
+    fn from(arg: SpaceState) -> Self {
+        arg as u8
+    }
+}
+
 const BITFIELD_PAGE_WIDTH: usize = core::mem::size_of::<PhysAddr>() * 8 - 12; // "12" should be log2(PAGE_SIZE) but https://github.com/rust-lang/rust/issues/70887
 // Physical page information, coded as a bitfield, because space is a premium!
 bitfield! {
@@ -80,10 +109,10 @@ pub type VirtAddr = NonZeroU64;
 /// unless the given address happens to be exactly one page in size.
 #[allow(dead_code)]
 #[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, PartialEq, Eq, Copy, Clone, Debug)]
-pub(crate) struct PageAlignedVa(VirtAddr);
+pub struct PageAlignedVa(VirtAddr);
 impl PageAlignedVa {
     #[allow(dead_code)]
-    pub(crate) fn as_u32(&self) -> u32 {
+    pub fn as_u32(&self) -> u32 {
         if self.0 <= VirtAddr::new(u32::MAX as u64).unwrap() {
             self.0.get() as u32
         } else {
@@ -91,12 +120,12 @@ impl PageAlignedVa {
         }
     }
     #[allow(dead_code)]
-    pub(crate) fn as_u64(&self) -> u64 {self.0.get()}
+    pub fn as_u64(&self) -> u64 {self.0.get()}
     #[allow(dead_code)]
-    pub(crate) fn as_usize(&self) -> usize {self.0.get() as usize}
+    pub fn as_usize(&self) -> usize {self.0.get() as usize}
     /// This will turn a PageAlignedVa into a page number
     #[allow(dead_code)]
-    pub(crate) fn as_vpage_num(&self) -> usize {
+    pub fn as_vpage_num(&self) -> usize {
         // we're page-aligned, so we don't have to deal with remainders. This should divide cleanly.
         self.0.get() as usize / VPAGE_SIZE
     }
@@ -147,16 +176,16 @@ impl Add for PageAlignedVa {
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug)]
-pub(crate) struct PageAlignedPa(PhysAddr);
+pub struct PageAlignedPa(PhysAddr);
 impl PageAlignedPa {
     #[allow(dead_code)]
-    pub(crate) fn as_u32(&self) -> u32 {self.0 as u32}
+    pub fn as_u32(&self) -> u32 {self.0 as u32}
     #[allow(dead_code)]
-    pub(crate) fn as_u64(&self) -> u64 {self.0 as u64}
+    pub fn as_u64(&self) -> u64 {self.0 as u64}
     #[allow(dead_code)]
-    pub(crate) fn as_usize(&self) -> usize {self.0 as usize}
+    pub fn as_usize(&self) -> usize {self.0 as usize}
     #[allow(dead_code)]
-    pub(crate) fn as_phys_addr(&self) -> PhysAddr {self.0}
+    pub fn as_phys_addr(&self) -> PhysAddr {self.0}
 }
 impl From<u32> for PageAlignedPa {
     fn from(arg: u32) -> Self {
@@ -0,0 +1,480 @@
+//! Per-app access control on dictionaries.
+//!
+//! A process ties itself to an app by presenting the token the GAM issued to that app's context. An
+//! identified app may claim a dictionary, after which other processes need the owner's grant, or the user's,
+//! to read, write or list it. Dictionaries nobody claimed stay open to everyone, as they always were.
+//!
+//! The access list of each basis is kept in that basis, in a dictionary nobody can reach over IPC, so
+//! ownership of the dictionaries of a secret basis is as deniable as the dictionaries themselves.
+use crate::api::*;
+use crate::backend::{BasisCache, PddbOs};
+
+use locales::t;
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Result};
+
+/// Holds the access list of the basis it's in.
+pub(crate) const ACL_DICT: &'static str = "pddb.acl";
+const ACL_KEY: &'static str = "table";
+const ACL_VERSION: u8 = 1;
+
+#[derive(Clone, Debug)]
+struct DictAcl {
+    owner: String,
+    /// what other apps may do; processes that haven't identified themselves can only get session grants
+    grants: Vec<(String, PddbAccess)>,
+}
+
+/// The user's answers that last until the basis is locked, for (basis, dict, app)
+#[derive(Default)]
+struct SessionAnswer {
+    allowed: PddbAccess,
+    denied: PddbAccess,
+}
+
+enum Answer {
+    Always,
+    Session,
+    /// offered instead of `Session` to a process that hasn't identified itself
+    Once,
+    Deny,
+}
+
+/// What the access list and the answers given so far say about a request, before the user is asked.
+#[derive(Debug, PartialEq)]
+enum Decision {
+    Allow,
+    Deny,
+    Ask,
+}
+
+/// `app` is None for a process that hasn't identified itself.
+fn decide(acl: &DictAcl, app: Option<&str>, session: Option<&SessionAnswer>, need: PddbAccess) -> Decision {
+    if app == Some(acl.owner.as_str()) {
+        return Decision::Allow;
+    }
+    if let Some(app) = app {
+        if acl.grants.iter().any(|(grantee, access)| grantee == app && access.contains(need)) {
+            return Decision::Allow;
+        }
+    }
+    if let Some(answer) = session {
+        if answer.allowed.contains(need) {
+            return Decision::Allow;
+        }
+        if answer.denied.intersects(need) {
+            return Decision::Deny;
+        }
+    }
+    Decision::Ask
+}
+
+pub(crate) struct AccessControl {
+    modals: modals::Modals,
+    /// app name of every process that identified itself
+    identities: HashMap<xous::PID, String>,
+    /// access lists of the open bases, loaded on first use: basis -> dict -> acl
+    tables: HashMap<String, HashMap<String, DictAcl>>,
+    session: HashMap<(String, String, String), SessionAnswer>,
+    /// bases whose access list couldn't be read, and that the user has been told about
+    unreadable: HashSet<String>,
+}
+
+impl AccessControl {
+    pub(crate) fn new(xns: &xous_names::XousNames) -> Self {
+        AccessControl {
+            modals: modals::Modals::new(xns).expect("can't connect to Modals server"),
+            identities: HashMap::new(),
+            tables: HashMap::new(),
+            session: HashMap::new(),
+            unreadable: HashSet::new(),
+        }
+    }
+
+    /// A process is tied to the first app it identifies as, until it lets go of the PDDB.
+    pub(crate) fn identify(&mut self, pid: Option<xous::PID>, app: &str) -> bool {
+        let pid = match pid {
+            Some(pid) => pid,
+            None => return false,
+        };
+        match self.identities.get(&pid) {
+            Some(existing) => existing == app,
+            None => {
+                log::info!("PID {} identified as {}", pid.get(), app);
+                self.identities.insert(pid, app.to_string());
+                true
+            }
+        }
+    }
+    /// Forgets a process that let go of the PDDB, so that a process that later gets its PID doesn't inherit its
+    /// identity, or what the user answered it.
+    pub(crate) fn process_gone(&mut self, pid: Option<xous::PID>) {
+        let (app, label) = self.who(pid);
+        if app.is_none() {
+            self.session.retain(|(_, _, a), _| a != &label);
+        }
+        if let Some(pid) = pid {
+            self.identities.remove(&pid);
+        }
+    }
+    fn who(&self, pid: Option<xous::PID>) -> (Option<String>, String) {
+        match pid.and_then(|p| self.identities.get(&p)) {
+            Some(app) => (Some(app.to_string()), app.to_string()),
+            None => (None, format!("{}{}", t!("pddb.acl.unidentified", xous::LANG), pid.map_or(0, |p| p.get()))),
+        }
+    }
+
+    /// Checks that the process may access `dict` in the named basis, or in every open basis if none is named,
+    /// asking the user when the dictionary has an owner that isn't the caller and hasn't granted the access.
+    pub(crate) fn check(&mut self, hw: &mut PddbOs, cache: &mut BasisCache,
+        pid: Option<xous::PID>, basis: Option<&str>, dict: &str, need: PddbAccess
+    ) -> bool {
+        if dict == ACL_DICT {
+            log::warn!("denied access to the access list");
+            return false;
+        }
+        let bases = match basis {
+            Some(b) => vec![b.to_string()],
+            None => cache.access_list(),
+        };
+        let (app, label) = self.who(pid);
+        for basis in bases {
+            if !self.ensure_table(hw, cache, &basis) {
+                return false;
+            }
+            let acl = match self.tables.get(&basis).and_then(|t| t.get(dict)) {
+                Some(acl) => acl.clone(),
+                None => continue, // unowned
+            };
+            let session_key = (basis.to_string(), dict.to_string(), label.to_string());
+            match decide(&acl, app.as_deref(), self.session.get(&session_key), need) {
+                Decision::Allow => continue,
+                Decision::Deny => return false,
+                Decision::Ask => (),
+            }
+            match self.ask_access(&label, app.is_some(), dict, &acl.owner, need) {
+                Answer::Always => {
+                    // only offered to identified apps, as a PID means nothing after a reboot
+                    let app = app.as_ref().unwrap();
+                    let mut acl = acl;
+                    match acl.grants.iter_mut().find(|(grantee, _)| grantee == app) {
+                        Some((_, access)) => access.insert(need),
+                        None => acl.grants.push((app.to_string(), need)),
+                    }
+                    self.tables.get_mut(&basis).unwrap().insert(dict.to_string(), acl);
+                    if let Err(e) = self.save_table(hw, cache, &basis) {
+                        log::error!("couldn't save the access list of {}: {:?}", basis, e);
+                    }
+                }
+                Answer::Session => self.session.entry(session_key).or_default().allowed.insert(need),
+                // a PID is reused once its process is gone, so nothing is remembered in its favour
+                Answer::Once => (),
+                Answer::Deny => {
+                    // remembered, so that a process retrying in a loop doesn't keep raising the dialog
+                    self.session.entry(session_key).or_default().denied.insert(need);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Makes the calling app the owner of `dict`. Claiming a dictionary that already has keys in it takes the
+    /// user's consent, as it may be another app's data.
+    pub(crate) fn claim(&mut self, hw: &mut PddbOs, cache: &mut BasisCache,
+        pid: Option<xous::PID>, basis: Option<&str>, dict: &str
+    ) -> PddbRequestCode {
+        let app = match self.who(pid) {
+            (Some(app), _) => app,
+            (None, _) => return PddbRequestCode::AccessDenied,
+        };
+        if dict == ACL_DICT {
+            return PddbRequestCode::AccessDenied;
+        }
+        let basis = match basis.or(cache.basis_latest()) {
+            Some(b) => b.to_string(),
+            None => return PddbRequestCode::NotMounted,
+        };
+        if !self.ensure_table(hw, cache, &basis) {
+            return PddbRequestCode::AccessDenied;
+        }
+        if let Some(acl) = self.tables.get(&basis).and_then(|t| t.get(dict)) {
+            return if acl.owner == app { PddbRequestCode::NoErr } else { PddbRequestCode::AccessDenied };
+        }
+        let in_use = cache.key_list(hw, dict, Some(&basis)).map_or(false, |keys| !keys.is_empty());
+        if in_use && !self.ask_claim(&app, dict) {
+            return PddbRequestCode::AccessDenied;
+        }
+        self.tables.get_mut(&basis).unwrap().insert(dict.to_string(), DictAcl { owner: app, grants: Vec::new() });
+        match self.save_table(hw, cache, &basis) {
+            Ok(_) => PddbRequestCode::NoErr,
+            Err(e) => match e.kind() {
+                ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+                _ => PddbRequestCode::InternalError,
+            },
+        }
+    }
+
+    /// Sets what `grantee` may do with `dict`. Only the owner can do this.
+    pub(crate) fn set_access(&mut self, hw: &mut PddbOs, cache: &mut BasisCache,
+        pid: Option<xous::PID>, basis: Option<&str>, dict: &str, grantee: &str, access: PddbAccess
+    ) -> PddbRequestCode {
+        let (app, _) = self.who(pid);
+        let basis = match basis.or(cache.basis_latest()) {
+            Some(b) => b.to_string(),
+            None => return PddbRequestCode::NotMounted,
+        };
+        if !self.ensure_table(hw, cache, &basis) {
+            return PddbRequestCode::AccessDenied;
+        }
+        let acl = match self.tables.get_mut(&basis).and_then(|t| t.get_mut(dict)) {
+            Some(acl) => acl,
+            None => return PddbRequestCode::NotFound,
+        };
+        if app.as_ref() != Some(&acl.owner) {
+            return PddbRequestCode::AccessDenied;
+        }
+        acl.grants.retain(|(name, _)| name != grantee);
+        if !access.is_empty() {
+            acl.grants.push((grantee.to_string(), access));
+        }
+        // a revocation also cancels what the user allowed for this session
+        self.session.retain(|(b, d, a), _| !(b == &basis && d == dict && a == grantee));
+        match self.save_table(hw, cache, &basis) {
+            Ok(_) => PddbRequestCode::NoErr,
+            Err(_) => PddbRequestCode::InternalError,
+        }
+    }
+
+    /// Forgets the owner of a dictionary that was deleted, so whoever creates it next starts afresh.
+    pub(crate) fn dict_removed(&mut self, hw: &mut PddbOs, cache: &mut BasisCache, basis: &str, dict: &str) {
+        if self.ensure_table(hw, cache, basis) && self.tables.get_mut(basis).and_then(|t| t.remove(dict)).is_some() {
+            if let Err(e) = self.save_table(hw, cache, basis) {
+                log::error!("couldn't save the access list of {}: {:?}", basis, e);
+            }
+        }
+        self.session.retain(|(b, d, _), _| !(b == basis && d == dict));
+    }
+    /// Drops everything cached for a basis that was locked, including the answers given for this session.
+    pub(crate) fn basis_locked(&mut self, basis: &str) {
+        self.tables.remove(basis);
+        self.unreadable.remove(basis);
+        self.session.retain(|(b, _, _), _| b != basis);
+    }
+
+    fn ask_access(&self, label: &str, identified: bool, dict: &str, owner: &str, need: PddbAccess) -> Answer {
+        let mut access = Vec::new();
+        if need.contains(PddbAccess::READ) {
+            access.push(t!("pddb.acl.read", xous::LANG));
+        }
+        if need.contains(PddbAccess::WRITE) {
+            access.push(t!("pddb.acl.write", xous::LANG));
+        }
+        if need.contains(PddbAccess::LIST) {
+            access.push(t!("pddb.acl.list", xous::LANG));
+        }
+        let prompt = format!("{}{}{}\n{}{}\n{}{}\n{}{}",
+            t!("pddb.acl.request", xous::LANG),
+            t!("pddb.acl.app", xous::LANG), label,
+            t!("pddb.acl.dict", xous::LANG), dict,
+            t!("pddb.acl.owner", xous::LANG), owner,
+            t!("pddb.acl.access", xous::LANG), access.join(", "),
+        );
+        if identified {
+            self.modals.add_list_item(t!("pddb.acl.always", xous::LANG)).expect("couldn't build radio item list");
+            self.modals.add_list_item(t!("pddb.acl.session", xous::LANG)).expect("couldn't build radio item list");
+        } else {
+            self.modals.add_list_item(t!("pddb.acl.once", xous::LANG)).expect("couldn't build radio item list");
+        }
+        self.modals.add_list_item(t!("pddb.acl.deny", xous::LANG)).expect("couldn't build radio item list");
+        match self.modals.get_radiobutton(&prompt) {
+            Ok(response) if response.as_str() == t!("pddb.acl.always", xous::LANG) => Answer::Always,
+            Ok(response) if response.as_str() == t!("pddb.acl.session", xous::LANG) => Answer::Session,
+            Ok(response) if response.as_str() == t!("pddb.acl.once", xous::LANG) => Answer::Once,
+            _ => Answer::Deny,
+        }
+    }
+    fn ask_claim(&self, app: &str, dict: &str) -> bool {
+        let prompt = format!("{}{}{}\n{}{}",
+            t!("pddb.acl.claim", xous::LANG),
+            t!("pddb.acl.app", xous::LANG), app,
+            t!("pddb.acl.dict", xous::LANG), dict,
+        );
+        self.modals.add_list_item(t!("pddb.yes", xous::LANG)).expect("couldn't build radio item list");
+        self.modals.add_list_item(t!("pddb.no", xous::LANG)).expect("couldn't build radio item list");
+        match self.modals.get_radiobutton(&prompt) {
+            Ok(response) => response.as_str() == t!("pddb.yes", xous::LANG),
+            _ => false,
+        }
+    }
+
+    /// Loads the access list of a basis. Returns false if there is one but it can't be read: as it isn't known
+    /// who owns what, nobody is let into the basis, and the user is told why.
+    fn ensure_table(&mut self, hw: &mut PddbOs, cache: &mut BasisCache, basis: &str) -> bool {
+        if self.tables.contains_key(basis) {
+            return true;
+        }
+        let table = match cache.key_attributes(hw, ACL_DICT, ACL_KEY, Some(basis)) {
+            Ok(attr) => {
+                let mut data = vec![0u8; attr.len];
+                match cache.key_read(hw, ACL_DICT, ACL_KEY, &mut data, Some(0), Some(basis)) {
+                    Ok(len) if len == attr.len => table_decode(&data),
+                    _ => None,
+                }
+            }
+            // nobody has claimed anything in this basis yet
+            Err(e) if e.kind() == ErrorKind::NotFound => Some(HashMap::new()),
+            Err(_) => None,
+        };
+        match table {
+            Some(table) => {
+                self.tables.insert(basis.to_string(), table);
+                true
+            }
+            None => {
+                log::error!("access list of {} can't be read, denying access to the basis", basis);
+                // it's not cached, so a read that failed for a passing reason is retried; the user hears of it once
+                if self.unreadable.insert(basis.to_string()) {
+                    let note = format!("{}{}", t!("pddb.acl.unreadable", xous::LANG), basis);
+                    self.modals.show_notification(&note, None).ok();
+                }
+                false
+            }
+        }
+    }
+    fn save_table(&mut self, hw: &mut PddbOs, cache: &mut BasisCache, basis: &str) -> Result<()> {
+        let data = table_encode(self.tables.get(basis).ok_or(Error::new(ErrorKind::NotFound, "basis not loaded"))?);
+        if cache.dict_attributes(hw, ACL_DICT, Some(basis)).is_err() {
+            cache.dict_add(hw, ACL_DICT, Some(basis))?;
+        }
+        cache.key_update(hw, ACL_DICT, ACL_KEY, &data, Some(0), None, Some(basis), true)?;
+        cache.sync(hw, Some(basis))
+    }
+}
+
+// Table format: a version byte, then for each dictionary its name, its owner, a grant count, and each grant as a
+// name and a `PddbAccess` byte. Names are a length byte followed by UTF-8.
+fn table_encode(table: &HashMap<String, DictAcl>) -> Vec<u8> {
+    fn name(data: &mut Vec<u8>, s: &str) {
+        data.push(s.len() as u8);
+        data.extend_from_slice(s.as_bytes());
+    }
+    let mut data = vec![ACL_VERSION];
+    for (dict, acl) in table.iter() {
+        name(&mut data, dict);
+        name(&mut data, &acl.owner);
+        data.push(acl.grants.len() as u8);
+        for (grantee, access) in acl.grants.iter() {
+            name(&mut data, grantee);
+            data.push(access.bits());
+        }
+    }
+    data
+}
+fn table_decode(data: &[u8]) -> Option<HashMap<String, DictAcl>> {
+    fn byte(data: &[u8], pos: &mut usize) -> Option<u8> {
+        let b = *data.get(*pos)?;
+        *pos += 1;
+        Some(b)
+    }
+    fn name(data: &[u8], pos: &mut usize) -> Option<String> {
+        let len = byte(data, pos)? as usize;
+        let s = std::str::from_utf8(data.get(*pos..*pos + len)?).ok()?;
+        *pos += len;
+        Some(s.to_string())
+    }
+    if data.first() != Some(&ACL_VERSION) {
+        return None;
+    }
+    let mut table = HashMap::new();
+    let mut pos = 1;
+    while pos < data.len() {
+        let dict = name(data, &mut pos)?;
+        let owner = name(data, &mut pos)?;
+        let count = byte(data, &mut pos)?;
+        let mut grants = Vec::new();
+        for _ in 0..count {
+            let grantee = name(data, &mut pos)?;
+            grants.push((grantee, PddbAccess::from_bits_truncate(byte(data, &mut pos)?)));
+        }
+        table.insert(dict, DictAcl { owner, grants });
+    }
+    Some(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(owner: &str, grants: &[(&str, PddbAccess)]) -> DictAcl {
+        DictAcl { owner: owner.to_string(), grants: grants.iter().map(|(g, a)| (g.to_string(), *a)).collect() }
+    }
+
+    #[test]
+    fn table_round_trip() {
+        let mut table = HashMap::new();
+        table.insert(
+            "vault.passwords".to_string(),
+            acl("vault", &[("shellchat", PddbAccess::LIST), ("totp", PddbAccess::READ | PddbAccess::LIST)]),
+        );
+        table.insert("wlan.networks".to_string(), acl("net", &[]));
+        table.insert("日本語".to_string(), acl("ime", &[("vault", PddbAccess::all())]));
+        let decoded = table_decode(&table_encode(&table)).expect("table didn't decode");
+        assert_eq!(decoded.len(), table.len());
+        for (dict, expected) in table.iter() {
+            let got = decoded.get(dict).expect("dictionary missing");
+            assert_eq!(got.owner, expected.owner);
+            assert_eq!(got.grants, expected.grants);
+        }
+        let empty = table_decode(&table_encode(&HashMap::new())).expect("empty table didn't decode");
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn table_truncated() {
+        let mut table = HashMap::new();
+        table.insert(
+            "vault.passwords".to_string(),
+            acl("vault", &[("shellchat", PddbAccess::LIST), ("totp", PddbAccess::READ)]),
+        );
+        let data = table_encode(&table);
+        assert!(table_decode(&[]).is_none());
+        // cut right after the version byte, there are no records left; anywhere else, a record is cut short
+        assert!(table_decode(&data[..1]).unwrap().is_empty());
+        for len in 2..data.len() {
+            assert!(table_decode(&data[..len]).is_none(), "prefix of {} bytes was accepted", len);
+        }
+        let mut wrong_version = data.clone();
+        wrong_version[0] = ACL_VERSION + 1;
+        assert!(table_decode(&wrong_version).is_none());
+        // a name that isn't UTF-8
+        let mut bad_name = data.clone();
+        bad_name[2] = 0xff;
+        assert!(table_decode(&bad_name).is_none());
+    }
+
+    #[test]
+    fn decision_matrix() {
+        let acl = acl("vault", &[("totp", PddbAccess::READ | PddbAccess::LIST)]);
+        // the owner can do anything
+        assert_eq!(decide(&acl, Some("vault"), None, PddbAccess::all()), Decision::Allow);
+        // grants cover exactly what they name
+        assert_eq!(decide(&acl, Some("totp"), None, PddbAccess::READ), Decision::Allow);
+        assert_eq!(decide(&acl, Some("totp"), None, PddbAccess::WRITE), Decision::Ask);
+        assert_eq!(decide(&acl, Some("totp"), None, PddbAccess::READ | PddbAccess::WRITE), Decision::Ask);
+        assert_eq!(decide(&acl, Some("shellchat"), None, PddbAccess::READ), Decision::Ask);
+        // an unidentified process never matches the owner or a grant, whatever its label
+        assert_eq!(decide(&acl, None, None, PddbAccess::LIST), Decision::Ask);
+
+        let answered = SessionAnswer { allowed: PddbAccess::LIST, denied: PddbAccess::WRITE };
+        for app in [Some("shellchat"), None] {
+            assert_eq!(decide(&acl, app, Some(&answered), PddbAccess::LIST), Decision::Allow);
+            assert_eq!(decide(&acl, app, Some(&answered), PddbAccess::WRITE), Decision::Deny);
+            assert_eq!(decide(&acl, app, Some(&answered), PddbAccess::READ | PddbAccess::WRITE), Decision::Deny);
+            assert_eq!(decide(&acl, app, Some(&answered), PddbAccess::READ), Decision::Ask);
+        }
+        // a grant wins over an earlier denial in the session
+        let denied = SessionAnswer { allowed: PddbAccess::empty(), denied: PddbAccess::READ };
+        assert_eq!(decide(&acl, Some("totp"), Some(&denied), PddbAccess::READ), Decision::Allow);
+    }
+}
@@ -1,6 +1,11 @@
-use bitfield::bitfield;
+use bitflags::bitflags;
 use std::num::NonZeroU32;
 
+// the sizes and flags fixed by the on-disk format are defined along with the rest of it, in `pddb-format`
+#[allow(unused_imports)]
+pub(crate) use pddb_format::{BASIS_NAME_LEN, DICT_NAME_LEN, KEY_NAME_LEN, PASSWORD_LEN, PDDB_MAGIC, PDDB_MIGRATE_1, PDDB_VERSION, BCRYPT_COST};
+pub use pddb_format::KeyFlags;
+
 // on the "[allow(dead_code)]" directives: these constants are used to define the PDDB, and are
 // sometimes used by both `bin` (main.rs) and `lib` (lib.rs) views, but also, sometimes used
 // by only one. The API view is included in both, and if a constant is not used in both views it
@@ -18,24 +23,6 @@ pub(crate) const SERVER_NAME_PDDB_POLLER: &str     = "_PDDB Mount Poller_";
 /// depend upon this constant.
 pub const TIME_SERVER_PDDB: &'static str = "_dedicated pddb timeserver connection_";
 
-#[allow(dead_code)]
-pub(crate) const BASIS_NAME_LEN: usize = 64; // don't want this too long anyways, because it's not recorded anywhere - users have to type it in.
-#[allow(dead_code)]
-pub(crate) const DICT_NAME_LEN: usize = 127 - 4 - 4 - 4 - 4; // u32: flags, age, free index, numkeys = 111
-#[allow(dead_code)]
-pub(crate) const KEY_NAME_LEN: usize = 127 - 8 - 8 - 8 - 4 - 4; // u64: vaddr/len/resvd, u32: flags, age = 95
-#[allow(dead_code)]
-pub(crate) const PASSWORD_LEN: usize = 72; // this is actually set by bcrypt
-#[allow(dead_code)]
-pub(crate) const PDDB_MAGIC: [u8; 4] = [0x50, 0x44, 0x44, 0x42];
-/// migrateable version pairs
-/// PDDB_MIGRATE_1:
-///   00.00.01.01 - xous 0.9.7 release (original base release)
-///   00.00.02.01 - xous 0.9.8 migration -> hkdf added on basis key derivation to make separate PT/data keys
-#[allow(dead_code)]
-pub(crate) const PDDB_MIGRATE_1: (u32, u32) = (0x00_00_01_01, 0x00_00_02_01);
-#[allow(dead_code)]
-pub(crate) const PDDB_VERSION: u32 = 0x00_00_02_01;
 #[allow(dead_code)]
 // PDDB_A_LEN may be shorter than xous::PDDB_LEN, to speed up testing.
 #[allow(dead_code)]
@@ -102,9 +89,6 @@ pub const PDDB_DEFAULT_SYSTEM_BASIS: &'static str = ".System";
 #[allow(dead_code)]
 pub(crate) const PDDB_FAST_SPACE_SYSTEM_BASIS: &'static str = ".FastSpace";
 
-#[allow(dead_code)]
-// TODO: add hardware acceleration for BCRYPT so we can hit the OWASP target without excessive UX delay
-pub(crate) const BCRYPT_COST: u32 = 7;   // 10 is the minimum recommended by OWASP; takes 5696 ms to verify @ 10 rounds; 804 ms to verify 7 rounds
 
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum Opcode {
@@ -204,6 +188,47 @@ pub(crate) enum Opcode {
     /// Reset "don't ask to init root keys" flag - pass-through to root keys object - for end of OQC test
     ResetDontAskInit = 43,
 
+    /// Compact a dictionary, or all the dictionaries if none is named
+    CompactDict = 44,
+    /// Compact all the dictionaries, from the menu
+    MenuCompact = 45,
+
+    /// Remove a key, overwriting the space it occupied with noise
+    DeleteKeySecure = 46,
+    /// Remove a dictionary, scrubbing any cached plaintext of its keys
+    DeleteDictSecure = 47,
+    /// libstd equivalent of `DeleteKeySecure`. Takes the same arguments as `DeleteKeyStd`.
+    DeleteKeySecureStd = 48,
+
+    /// Check the consistency of the open Basis, and optionally repair them
+    Fsck = 49,
+
+    /// Start a transaction; returns its id
+    TxnBegin = 50,
+    /// Stage a write to a key in a transaction
+    TxnWrite = 51,
+    /// Stage the removal of a key in a transaction
+    TxnDelete = 52,
+    /// Apply all the staged updates of a transaction, atomically
+    TxnCommit = 53,
+    /// Drop a transaction without applying any of its updates
+    TxnAbort = 54,
+
+    /// Subscribe to changes to a dictionary or a key
+    Watch = 55,
+    /// Cancel a subscription
+    Unwatch = 56,
+
+    /// Tie the calling process to the app a GAM token was issued to, for access control
+    Identify = 57,
+    /// Make the calling app the owner of a dictionary
+    ClaimDict = 58,
+    /// Grant or revoke another app's access to a dictionary; only the owner may do this
+    SetDictAccess = 59,
+
+    /// The last `Pddb` handle of the calling process is going away; drops what the server holds for it
+    Disconnect = 60,
+
     /// This key type could not be decoded
     InvalidOpcode = u32::MAX as _,
 }
@@ -237,6 +262,8 @@ pub enum PddbRequestCode {
     InternalError = 8,
     AccessDenied = 9,
     Uninit = 10,
+    /// the request carried a field that is out of range
+    InvalidRequest = 11,
 }
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -297,6 +324,7 @@ pub(crate) enum PddbRetcode {
     UnexpectedEof = 4,
     InternalError = 5,
     DiskFull = 6,
+    NotFound = 7,
 }
 
 pub(crate) const PDDB_BUF_DATA_LEN: usize = 4072;
@@ -331,16 +359,6 @@ impl PddbBuf {
     }
 }
 
-bitfield! {
-    #[derive(Copy, Clone, PartialEq, Eq)]
-    pub struct KeyFlags(u32);
-    impl Debug;
-    /// set if the entry is valid -- in the cache, an invalid entry means it was previously allocated but then deleted, and needs a sync
-    pub valid, set_valid: 0;
-    /// resolved indicates that the "start" address isn't fully resolved yet in the cache
-    pub unresolved, set_unresolved: 1;
-}
-
 /// A structure for passing around key metadata
 #[derive(Debug)]
 pub struct KeyAttributes {
@@ -428,15 +446,11 @@ pub enum PddbRekeyOp {
     FromDnaSafe(u64),
     /// Basically the same as FromDnaSafe, but doing a self-to-self "safe" rekey
     Churn,
-    /*
-    // skip this implementation for now. This opcode fits generally into this code flow,
-    // but requires some rework of the UX to actually acquire the old and new passwords.
-    // this UX work is off-topic from the mission of getting backup restoration done,
-    // but the potential to integrate the password rotation scheme into this function is
-    // noted here for future efforts.
-    //
-    /// Requests a single secret basis to have its password changed. This will reveal the size
-    /// of the Basis if the attacker has a before-and-after image of the PDDB.
+    /// Requests a single secret basis to have its password changed. The basis has to be
+    /// unlocked, and the user is asked for its current password and then the new one. The
+    /// basis is re-keyed with the current KDF, so this is also how a basis keyed with bcrypt
+    /// is moved over to Argon2id. This will reveal the size of the Basis if the attacker has
+    /// a before-and-after image of the PDDB.
     /// Recommended to call `Churn` after this operation is done for optimal safety.
     ///
     /// Note: changing the password on the .System basis is a different flow. The
@@ -444,7 +458,6 @@ pub enum PddbRekeyOp {
     /// its password requires calling a routine in root_keys (that does not exist
     /// at this current time).
     ChangePass(xous_ipc::String<BASIS_NAME_LEN>),
-    */
 
     /// Return codes
     Success,
@@ -454,6 +467,169 @@ pub enum PddbRekeyOp {
     InternalError,
 }
 
+/// What the consistency checker should do about the problems it finds
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FsckMode {
+    /// Only report problems; nothing is written to disk other than a sync of the caches.
+    Check,
+    /// Erase orphaned and stale pages, drop damaged keys, and correct the dictionary and basis headers.
+    Repair,
+    /// As `Repair`, but orphaned, stale and damaged pages are remapped into a quarantine region of the
+    /// basis instead of being erased, so their contents can be recovered later with the offline tools.
+    Quarantine,
+}
+/// Problem counts from a consistency check. Only the open Basis are visited, and all of the
+/// counts are totals across them.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, Debug, Default)]
+pub struct FsckReport {
+    /// number of Basis checked
+    pub bases: u32,
+    /// number of pages mapped by the checked Basis
+    pub pages: u32,
+    /// pages mapped by a Basis, but not referenced by any of its structures
+    pub orphaned: u32,
+    /// stale page table entries for a virtual page that is mapped elsewhere, and pages that are
+    /// both mapped and on the free space list
+    pub double_mapped: u32,
+    /// pages that fail to authenticate on decryption
+    pub bad_pages: u32,
+    /// dictionaries with an inconsistent header, and Basis with a wrong dictionary count
+    pub bad_dicts: u32,
+    /// key descriptors that are malformed, overlap another key, or have unreadable data
+    pub bad_keys: u32,
+    /// pages sitting in the quarantine region, including any that were put there by this run
+    pub quarantined: u32,
+    /// number of fixes applied
+    pub repaired: u32,
+}
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned == 0 && self.double_mapped == 0 && self.bad_pages == 0 && self.bad_dicts == 0 && self.bad_keys == 0
+    }
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbFsckRequest {
+    pub mode: FsckMode,
+    pub report: FsckReport,
+    pub result: PddbRequestCode,
+}
+
+/// What happened to a watched dictionary or key
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PddbEventKind {
+    Created,
+    Updated,
+    /// a key was removed, or the whole dictionary if the event has no key
+    Deleted,
+    BasisUnlocked,
+    BasisLocked,
+}
+/// A change notification, as delivered to a watch callback
+#[derive(Clone, Debug)]
+pub struct PddbEvent {
+    pub kind: PddbEventKind,
+    pub basis: String,
+    /// None for basis events
+    pub dict: Option<String>,
+    /// None for basis events, and for the removal of a whole dictionary
+    pub key: Option<String>,
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbWatchRequest {
+    /// picked by the client, so that its callback is in place before the first event can arrive
+    pub id: u32,
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key_specified: bool,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    pub cb_sid: [u32; 4],
+    pub result: PddbRequestCode,
+}
+/// Sent by the server to the callback server of a watch
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbWatchEvent {
+    pub id: u32,
+    pub kind: PddbEventKind,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+}
+impl PddbWatchEvent {
+    #[allow(dead_code)]
+    pub(crate) fn to_event(&self) -> PddbEvent {
+        let dict = self.dict.as_str().unwrap_or("");
+        let key = self.key.as_str().unwrap_or("");
+        PddbEvent {
+            kind: self.kind,
+            basis: self.basis.as_str().unwrap_or("").to_string(),
+            dict: if dict.len() > 0 { Some(dict.to_string()) } else { None },
+            key: if key.len() > 0 { Some(key.to_string()) } else { None },
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub(crate) const APP_NAME_LEN: usize = 64; // GAM context names are much shorter than this
+bitflags! {
+    /// What an app may do with a dictionary it doesn't own. Owners can always do everything.
+    #[derive(Default)]
+    pub struct PddbAccess: u8 {
+        /// open keys and read them
+        const READ = 0b001;
+        /// create, write and delete keys, and delete the dictionary
+        const WRITE = 0b010;
+        /// list the keys, and watch them for changes
+        const LIST = 0b100;
+    }
+}
+/// Used by `ClaimDict` and `SetDictAccess`
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbAclRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    /// the app whose access is set; unused by `ClaimDict`
+    pub app: xous_ipc::String::<APP_NAME_LEN>,
+    /// `PddbAccess` bits; none revokes all access
+    pub access: u8,
+    pub result: PddbRequestCode,
+}
+
+/// Largest amount of key data carried by one `PddbTxnRequest`; longer writes are sent in several chunks.
+pub(crate) const TXN_CHUNK_LEN: usize = 2048;
+/// Used by all the transaction opcodes. Only the fields that an opcode needs are looked at; `id` is
+/// filled in by the server on `TxnBegin`.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbTxnRequest {
+    pub id: u32,
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    /// where `data` goes in the key's new contents; the chunks of a write are sent in order
+    pub offset: u64,
+    pub len: u16,
+    pub data: [u8; TXN_CHUNK_LEN],
+    pub result: PddbRequestCode,
+}
+impl PddbTxnRequest {
+    #[allow(dead_code)]
+    pub(crate) fn new(id: u32) -> Self {
+        PddbTxnRequest {
+            id,
+            basis_specified: false,
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::new(),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::new(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::new(),
+            offset: 0,
+            len: 0,
+            data: [0u8; TXN_CHUNK_LEN],
+            result: PddbRequestCode::Uninit,
+        }
+    }
+}
+
 /// Debugging commands, available only in hosted mode
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -4,23 +4,21 @@ mod dictionary;
 pub use dictionary::*;
 mod key;
 pub use key::*;
-mod pagetable;
-pub use pagetable::*;
 mod fastspace;
 pub use fastspace::*;
-mod types;
-pub use types::*;
-mod bcrypt;
-pub use bcrypt::*;
+// the on-disk format: layout, page table, page bookkeeping types and password hashing
+pub use pddb_format::*;
 
 // local to the backend
-mod murmur3;
-pub(crate) use murmur3::*;
 mod trngpool;
 pub(crate) use trngpool::*;
 
 mod hw;
 pub(crate) use hw::*;
+mod fsck;
+pub(crate) use fsck::*;
+mod txn;
+pub(crate) use txn::*;
 
 // hosted mode emulation structures
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
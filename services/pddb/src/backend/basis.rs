@@ -63,7 +63,12 @@
 /// | 0x0000_003F_80FE_0000  |    - Dict[1] pool = 16MiB                 |
 /// | 0x0000_007E_FE04_0000  |    - Dict[16383] pool                     |
 /// | 0x0000_007E_FF02_0000  |  Unused                                   |
-/// | 0x0000_007F_0000_0000  |  Medium data pool start                   |
+/// | 0x0000_007F_0000_0000  |  Transaction journal (4GiB)               |
+/// |                        |    - Journal header                       |
+/// |                 +FE0   |    - Journal body pages                   |
+/// | 0x0000_0080_0000_0000  |  Quarantine (4GiB)                        |
+/// |                        |    - Pages set aside by fsck              |
+/// | 0x0000_0081_0000_0000  |  Medium data pool start                   |
 /// |                        |    - TBD                                  |
 /// | 0x0000_FE00_0000_0000  |  Large data pool start  (~16mm TiB)       |
 /// |                        |    - Demand-allocated, bump-pointer       |
@@ -147,100 +152,21 @@ use std::cmp::Reverse;
 use std::cmp::Ordering;
 use core::num::NonZeroU32;
 
-pub(crate) const SMALL_POOL_START: u64 = 0x0000_003F_8000_0000;
-pub(crate) const SMALL_POOL_END: u64 = 0x0000_007E_FF02_0000;
-pub(crate) const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
 /// we don't want this bigger than VPAGE_SIZE, because a key goal of the small pool is to
 /// reduce # of writes to the disk of small data. While we could get some gain in memory efficiency
 /// if we made this larger than a VPAGE_SIZE, we don't get much gain in terms of write reduction,
 /// and it greatly complicates the implementation. So, SMALL_CAPACITY should be less than VPAGE_SIZE.
 pub(crate) const SMALL_CAPACITY: usize = VPAGE_SIZE;
-pub(crate) const LARGE_POOL_START: u64 = 0x0000_FE00_0000_0000;
-pub(crate) const KEY_MAXCOUNT: usize = 131_071; // 2^17 - 1
-/// This is a size limit on the biggest file you can create. It's currently 32GiB. No, this is not
-/// web scale, but it's big enough to hold a typical blu-ray movie as a single file. You can adjust
-/// this constant up or down, and the trade-off is, you get more or less total number of large files
-/// allocated over the life of the filesystem. We simply "increment a pointer" when a new large file
-/// is added to create the next virtual memory spot for the large file. So at 32GiB, you can create
-/// a lifetime total of about 200 million files (this includes files you've previously deleted, until
-/// we create a mechanism for sweeping through the memory space and tracking de-allocations). Note that
-/// a "large" file includes anything over 4kiB, so if you create a 5kiB file, it can potentially grow to
-/// 32 GiB without bumping into the next large file. This is a very "lazy" way to deal with large files.
-/// Given that the PDDB is designed for a 32-bit device with only 128MiB of memory and a read/write lifetime
-/// of 100k cycles for the FLASH, 200 million file allocations is probably greater than the lifetime of
-/// the device itself. If the PDDB migrates to a larger handphone-style application, I think it'll probably
-/// still hold up OK with 200 million total large file allocations over the device lifetime and a limit
-/// of 32GiB. That's about 73k files created per day for 10 years, or about 50 files per minute -- roughly
-/// one new file per second for 10 years straight before the PDDB runs out of virtual memory space.
-/// A web server creating a >4k temporary log file for every client that hit and then deleting it
-/// would probably crush this limit in months. So don't use the PDDB to back a high volume web server.
-/// But it's probably OK for a consumer electronics device with a typical lifetime of less than 10 years.
-/// If you really think you want larger files and also more write life, you'd need to implement an in-memory
-/// "free" file allocator, but honestly, this is not something I think we need to burn resources on for
-/// the initial target of the PDDB (that is, a 100MiB device with 100k read/write endurance lifetime).
-/// Anyways, the code is written so you can just slide this constant up or down and change the behavior
-/// of the system; it's recommended you reformat when you do that but I /think/ it should actually be OK
-/// if you made a change "on the fly".
-///
-/// Also note that in practice, a file size is limited to 4GiB on a 32-bit Precursor device anyways
-/// because the usize type isn't big enough. Recompiling for a 64-bit target, however, should give
-/// you access to the 32GiB file size limit.
-pub(crate) const LARGE_FILE_MAX_SIZE: u64 = 0x0000_0008_0000_0000;
-
-/// The chosen "stride" of a dict/key entry. Drives a lot of key parameters in the database's characteristics.
-/// This is chosen such that 32 of these entries fit evenly into a VPAGE.
-pub(crate) const DK_STRIDE: usize = 127;
-//// DK_STRIDES per VPAGE
-pub(crate) const DK_PER_VPAGE: usize = VPAGE_SIZE / DK_STRIDE; // should be 32 - use this for computing modulus on dictionary indices
-/// size of a dictionary region in virtual memory
-pub(crate) const DICT_VSIZE: u64 = 0xFE_0000;
-/// maximum number of dictionaries in a system
-pub(crate) const DICT_MAXCOUNT: usize = 16383;
+/// maximum number of decrypted vpages held in the read cache of a single large key.
+pub(crate) const LARGE_CACHE_PAGES: usize = 16;
 /// default alloc hint, if none is given (needs to be non-zero)
 /// this would be the typical "minimum space" reserved for a key
 /// users are of course allowed to specify something smaller, but it should be non-zero
 pub(crate) const DEFAULT_ALLOC_HINT: usize = 8;
-
-/// This is the format of the Basis as stored on disk
-#[derive(PartialEq, Debug, Default)]
-#[repr(C, align(8))]
-pub(crate) struct BasisRoot {
-    pub(crate) magic: [u8; 4],
-    pub(crate) version: u32,
-    /// increments every time the BasisRoot is modified. This field must saturate, not roll over.
-    pub(crate) age: u32,
-    /// number of dictionaries.
-    pub(crate) num_dictionaries: u32,
-    /* at this point, we are aligned to a 64-bit boundary. All data must stay aligned to this boundary from here out! */
-    /// 64-byte name; aligns to 64-bits
-    pub(crate) name: BasisRootName,
-}
-impl BasisRoot {
-    pub(crate) fn aad(&self, dna: u64) -> Vec::<u8> {
-        let mut aad = Vec::<u8>::new();
-        aad.extend_from_slice(&self.name.data[..self.name.len as usize]);
-        aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
-        aad.extend_from_slice(&dna.to_le_bytes());
-        aad
-    }
-}
-impl Deref for BasisRoot {
-    type Target = [u8];
-    fn deref(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(self as *const BasisRoot as *const u8, core::mem::size_of::<BasisRoot>())
-                as &[u8]
-        }
-    }
-}
-impl DerefMut for BasisRoot {
-    fn deref_mut(&mut self) -> &mut [u8] {
-        unsafe {
-            core::slice::from_raw_parts_mut(self as *mut BasisRoot as *mut u8, core::mem::size_of::<BasisRoot>())
-                as &mut [u8]
-        }
-    }
-}
+/// a dictionary is only compacted automatically if doing so returns at least this many pages...
+pub(crate) const COMPACT_MIN_PAGES: usize = 4;
+/// ...and if those pages are at least this percentage of the pages it currently occupies.
+pub(crate) const COMPACT_THRESHOLD_PERCENT: usize = 25;
 
 /// A list of open Basis that we can use to search and operate upon. Sort of the "root" data structure of the PDDB.
 ///
@@ -268,8 +194,11 @@ impl BasisCache {
         }
         al
     }
-    pub(crate) fn rekey(&self, hw: &mut PddbOs, op: PddbRekeyOp) -> PddbRekeyOp {
-        hw.pddb_rekey(op, &self.cache)
+    pub(crate) fn rekey(&mut self, hw: &mut PddbOs, op: PddbRekeyOp) -> PddbRekeyOp {
+        match op {
+            PddbRekeyOp::ChangePass(name) => self.basis_change_pass(hw, name.as_str().unwrap_or("")),
+            _ => hw.pddb_rekey(op, &self.cache),
+        }
     }
     fn select_basis(&self, basis_name: Option<&str>) -> Option<usize> {
         if self.cache.len() == 0 {
@@ -478,7 +407,14 @@ impl BasisCache {
                             if let Some(pp) = basis.v2p_map.get(&VirtAddr::new(start_vpage_addr).unwrap()) {
                                 let block_start_pos = (abs_cursor % VPAGE_SIZE as u64) as usize;
                                 assert!(pp.valid(), "v2p returned an invalid page");
-                                let pt_data = hw.data_decrypt_page(&basis.cipher, &basis.aad, pp).expect("Decryption auth error");
+                                let kld = match kcache.data.get_or_insert_with(|| KeyCacheData::Large(KeyLargeData::new())) {
+                                    KeyCacheData::Large(kld) => kld,
+                                    _ => panic!("Key allocated to large area but its cache data was not of the large type"),
+                                };
+                                if kld.get(start_vpage_addr).is_none() {
+                                    kld.insert(start_vpage_addr, hw.data_decrypt_page(&basis.cipher, &basis.aad, pp).expect("Decryption auth error"));
+                                }
+                                let pt_data = kld.get(start_vpage_addr).expect("page was just cached");
                                 if blocks_read != 0 {
                                     assert!(block_start_pos == 0, "algorithm error in handling offset data");
                                 }
@@ -535,6 +471,10 @@ impl BasisCache {
     pub(crate) fn key_remove(&mut self,
         hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>, paranoid: bool
     ) -> Result<()> {
+        // a paranoid remove re-writes a small pool and a descriptor page, which may need fresh pages
+        if paranoid && !hw.ensure_fast_space_alloc(2, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space to erase key"));
+        }
         if let Some(basis_index) = self.select_basis(basis_name) {
             let basis = &mut self.cache[basis_index];
             if !basis.ensure_dict_in_cache(hw, dict) {
@@ -544,13 +484,10 @@ impl BasisCache {
                 basis.age = basis.age.saturating_add(1);
                 basis.clean = false;
                 if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
-                    if !paranoid {
-                        dict_entry.key_remove(hw, &mut basis.v2p_map, &basis.cipher, key, false);
-                    } else {
-                        // this implementation is still in progress
-                        dict_entry.key_erase(key);
-
-                        // encrypt and write the dict entry to disk
+                    dict_entry.key_remove(hw, &mut basis.v2p_map, &basis.cipher, key, paranoid);
+                    if paranoid {
+                        // the key's data and descriptor are already overwritten; commit the re-packed
+                        // small pool's descriptors right away so the disk is consistent again.
                         basis.dict_sync(hw, dict)?;
                         // sync the root basis structure as well, while we're at it...
                         basis.basis_sync(hw);
@@ -803,8 +740,7 @@ impl BasisCache {
     /// assumptions and allows one to specify a persistence.
     pub(crate) fn basis_unlock(&mut self, hw: &mut PddbOs, name: &str, password: &str,
     policy: BasisRetentionPolicy) -> Option<BasisCacheEntry> {
-        let basis_key =  hw.basis_derive_key(name, password);
-        if let Some(basis_map) = hw.pt_scan_key(&basis_key.pt, &basis_key.data, name) {
+        if let Some((basis_key, kdf, basis_map)) = hw.basis_probe_keys(name, password) {
             let aad = hw.data_aad(name);
             if let Some(root_page) = basis_map.get(&VirtAddr::new(VPAGE_SIZE as u64).unwrap()) {
                 let vpage = match hw.data_decrypt_page_with_commit(&basis_key.data, &aad, root_page) {
@@ -828,6 +764,12 @@ impl BasisCache {
                     log::error!("PDDB mount requested {}, but got {}; aborting.", name, basis_name);
                     return None;
                 }
+                if BasisKdf::from_record(&basis_root.kdf) != Some(kdf) {
+                    log::warn!("Basis {} opened with {:?}, but its root records {:?}", name, kdf, basis_root.kdf);
+                }
+                if kdf == BasisKdf::Bcrypt {
+                    log::info!("Basis {} is keyed with bcrypt; changing its password moves it to {:?}", name, BasisKdf::DEFAULT);
+                }
                 log::debug!("Basis {} record found, generating cache entry", name);
                 BasisCacheEntry::mount(hw, &basis_name, &basis_key, false, policy)
             } else {
@@ -848,14 +790,15 @@ impl BasisCache {
             return Err(Error::new(ErrorKind::OutOfMemory, "No free space to create basis"));
         };
 
-        let basis_key =  hw.basis_derive_key(name, password);
+        let basis_key =  hw.basis_derive_key(name, password, BasisKdf::DEFAULT);
         let mut basis_v2p_map = HashMap::<VirtAddr, PhysPage>::new();
         let basis_root = BasisRoot {
             magic: PDDB_MAGIC,
             version: PDDB_VERSION,
             name: BasisRootName::try_from_str(name).unwrap(),
             age: 0,
-            num_dictionaries: 0
+            num_dictionaries: 0,
+            kdf: BasisKdf::DEFAULT.to_record(),
         };
         // allocate one page for the basis root
         if let Some(alloc) = hw.try_fast_space_alloc() {
@@ -907,8 +850,15 @@ impl BasisCache {
         }
     }
 
-    pub(crate) fn basis_add(&mut self, basis: BasisCacheEntry) {
+    /// Adds a mounted basis to the cache. A transaction whose commit was interrupted is finished here, once
+    /// the basis is in the cache, so the pages the replay allocates are picked knowing about every open basis.
+    pub(crate) fn basis_add(&mut self, hw: &mut PddbOs, basis: BasisCacheEntry) {
+        let name = basis.name.to_string();
+        let journaled = txn_journal_present(&basis);
         self.cache.push(basis);
+        if journaled {
+            self.txn_recover(hw, &name);
+        }
     }
 
     pub(crate) fn basis_unmount(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<()> {
@@ -922,9 +872,44 @@ impl BasisCache {
         }
     }
 
+    /// Changes the password of an open basis, and moves it over to the current KDF if it isn't keyed with it
+    /// already. The basis is re-mounted with its new keys in the same place in the cache.
+    pub(crate) fn basis_change_pass(&mut self, hw: &mut PddbOs, basis_name: &str) -> PddbRekeyOp {
+        if basis_name == PDDB_DEFAULT_SYSTEM_BASIS {
+            log::error!("The keys of the system basis are wrapped by the root keys, and can't be changed here.");
+            return PddbRekeyOp::InternalError;
+        }
+        let basis_index = match self.cache.iter().position(|bc| bc.name == basis_name) {
+            Some(index) => index,
+            None => {
+                log::error!("Basis {} has to be unlocked to change its password", basis_name);
+                return PddbRekeyOp::AuthFail;
+            }
+        };
+        // pages are re-encrypted as they are on disk, so everything has to be there
+        if let Err(e) = self.cache[basis_index].sync(hw) {
+            log::error!("couldn't sync {} before changing its password: {:?}", basis_name, e);
+            return PddbRekeyOp::InternalError;
+        }
+        let new_keys = match hw.basis_change_pass(&self.cache[basis_index]) {
+            Ok(keys) => keys,
+            Err(code) => return code,
+        };
+        let policy = self.cache[basis_index].policy;
+        match BasisCacheEntry::mount(hw, basis_name, &new_keys, false, policy) {
+            Some(entry) => {
+                self.cache[basis_index] = entry;
+                PddbRekeyOp::Success
+            }
+            None => {
+                log::error!("Basis {} did not mount with its new keys", basis_name);
+                self.cache.remove(basis_index);
+                PddbRekeyOp::VerifyFail
+            }
+        }
+    }
+
     /// note: you can "delete" a basis simply by forgetting its password, but this is more thorough.
-    /// there might also need to be a variant to make which is a "change my password" function, but that is actually
-    /// surprisingly hard.
     pub(crate) fn basis_delete(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<()> {
         if let Some(basis_index) = self.select_basis(Some(basis_name)) {
             let basis = &mut self.cache[basis_index];
@@ -1045,6 +1030,163 @@ impl BasisCache {
         }
         pruned
     }
+
+    /// Compacts the dictionary `dict`, or all the dictionaries if `dict` is None. If `basis_name` is None, every
+    /// open basis is visited. Returns the number of dictionaries compacted.
+    pub(crate) fn dict_compact(&mut self, hw: &mut PddbOs, dict: Option<&str>, basis_name: Option<&str>) -> Result<usize> {
+        let bases: Vec<usize> = if basis_name.is_some() {
+            vec![self.select_basis(basis_name).ok_or(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))?]
+        } else {
+            (0..self.cache.len()).collect()
+        };
+        let mut compacted = 0;
+        for basis_index in bases {
+            // flush pending updates, so the compaction starts from a state that matches the disk
+            self.cache[basis_index].sync(hw)?;
+            let dict_names = if let Some(name) = dict {
+                if !self.cache[basis_index].ensure_dict_in_cache(hw, name) {
+                    continue;
+                }
+                vec![name.to_string()]
+            } else {
+                self.cache[basis_index].populate_caches(hw);
+                self.cache[basis_index].dicts.iter()
+                    .filter(|(_, d)| d.flags.valid())
+                    .map(|(name, _)| name.to_string())
+                    .collect()
+            };
+            for name in dict_names {
+                let pages = self.cache[basis_index].dict_compact_estimate(hw, &name)?;
+                if !hw.ensure_fast_space_alloc(pages, &self.cache) {
+                    return Err(Error::new(ErrorKind::OutOfMemory, "No free space to compact dict"));
+                }
+                self.cache[basis_index].dict_compact(hw, &name)?;
+                compacted += 1;
+            }
+        }
+        if dict.is_some() && compacted == 0 {
+            Err(Error::new(ErrorKind::NotFound, "dictionary not found"))
+        } else {
+            Ok(compacted)
+        }
+    }
+    /// Finishes interrupted compactions, and compacts dictionaries whose fragmentation crossed the threshold
+    /// set by `COMPACT_MIN_PAGES` and `COMPACT_THRESHOLD_PERCENT`. Only fully cached dictionaries are
+    /// considered, so this is cheap enough to call periodically. Returns the number of dictionaries compacted.
+    pub(crate) fn dict_autocompact(&mut self, hw: &mut PddbOs) -> usize {
+        let mut candidates = Vec::<(String, String)>::new();
+        for basis in self.cache.iter() {
+            for (name, dict) in basis.dicts.iter() {
+                if dict.flags.valid() && dict.compaction_needed(&basis.v2p_map) {
+                    candidates.push((basis.name.to_string(), name.to_string()));
+                }
+            }
+        }
+        let mut compacted = 0;
+        for (basis, dict) in candidates {
+            match self.dict_compact(hw, Some(&dict), Some(&basis)) {
+                Ok(count) => compacted += count,
+                Err(e) => log::warn!("Couldn't compact {}:{}: {:?}", basis, dict, e),
+            }
+        }
+        compacted
+    }
+    /// Checks the consistency of every open basis, and of the FastSpace free list against them. In the repair
+    /// modes the problems found are fixed on disk, and the dictionary caches are dropped so they are re-read
+    /// from the repaired structures.
+    pub(crate) fn fsck(&mut self, hw: &mut PddbOs, mode: FsckMode) -> Result<FsckReport> {
+        // the check is done against the disk, so anything pending has to be flushed first
+        self.sync(hw, None)?;
+        let mut report = FsckReport::default();
+        let mut owners = HashMap::<PhysAddr, String>::new();
+        for basis in self.cache.iter_mut() {
+            log::info!("fsck: checking {}", basis.name);
+            fsck_basis(hw, basis, mode, &mut report, &mut owners);
+            if mode != FsckMode::Check {
+                basis.dicts.clear();
+                basis.free_dict_offset = None;
+            }
+        }
+        if fsck_fast_space(hw, &owners, &mut report) && mode != FsckMode::Check {
+            if hw.fast_space_rebuild(&self.cache) {
+                report.repaired += 1;
+            } else {
+                log::error!("fsck: couldn't regenerate the free space list");
+            }
+        }
+        log::info!("fsck: {:?}", report);
+        Ok(report)
+    }
+
+    /// Commits a transaction: the operations are journaled, then applied, so that either all or none of them
+    /// are visible after a power loss. Removing a key that doesn't exist is not an error.
+    pub(crate) fn txn_commit(&mut self, hw: &mut PddbOs, ops: &[TxnOp], basis_name: Option<&str>) -> Result<()> {
+        let basis_index = self.select_basis(basis_name)
+            .ok_or(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))?;
+        if ops.len() == 0 {
+            return Ok(());
+        }
+        let name = self.cache[basis_index].name.to_string();
+        // a committed journal that couldn't be replayed yet would be lost under this one, so it goes first
+        if txn_journal_present(&self.cache[basis_index]) {
+            self.txn_recover(hw, &name);
+            let basis_index = self.select_basis(Some(&name)).unwrap();
+            if txn_journal_present(&self.cache[basis_index]) {
+                return Err(Error::new(ErrorKind::Other, "An earlier transaction has yet to be replayed"));
+            }
+        }
+        let basis_index = self.select_basis(Some(&name)).unwrap();
+        let stream = txn_serialize(ops);
+        if !hw.ensure_fast_space_alloc(txn_journal_pages(stream.len()), &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space for the transaction journal"));
+        }
+        // anything that is pending is flushed first, so the journal's page table updates don't carry it along
+        self.cache[basis_index].sync(hw)?;
+        txn_journal_write(hw, &mut self.cache[basis_index], &stream, ops.len())?;
+        // from here on, the transaction is committed: a failure to apply it is finished on the next mount
+        self.txn_apply(hw, ops, &name)?;
+        let basis_index = self.select_basis(Some(&name)).unwrap();
+        txn_journal_retire(hw, &mut self.cache[basis_index]);
+        Ok(())
+    }
+
+    fn txn_apply(&mut self, hw: &mut PddbOs, ops: &[TxnOp], basis_name: &str) -> Result<()> {
+        for op in ops {
+            match op {
+                TxnOp::Write { dict, key, data } => {
+                    self.key_update(hw, dict, key, data, Some(0), None, Some(basis_name), true)?;
+                }
+                TxnOp::Delete { dict, key } => {
+                    match self.key_remove(hw, dict, key, Some(basis_name), false) {
+                        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        self.sync(hw, Some(basis_name))
+    }
+
+    /// Finishes a transaction whose commit was interrupted, or discards the remains of one that never
+    /// got committed. Called when a basis with a journal is added to the cache, and before a commit.
+    pub(crate) fn txn_recover(&mut self, hw: &mut PddbOs, basis_name: &str) {
+        let basis_index = match self.select_basis(Some(basis_name)) {
+            Some(index) => index,
+            None => return,
+        };
+        if let Some(ops) = txn_journal_read(hw, &self.cache[basis_index]) {
+            log::warn!("txn: {}: replaying an interrupted transaction of {} ops", basis_name, ops.len());
+            if let Err(e) = self.txn_apply(hw, &ops, basis_name) {
+                // keep the journal, so the replay is tried again on the next mount
+                log::error!("txn: {}: couldn't replay the transaction: {:?}", basis_name, e);
+                return;
+            }
+        } else {
+            log::warn!("txn: {}: discarding an uncommitted transaction", basis_name);
+        }
+        let basis_index = self.select_basis(Some(basis_name)).unwrap();
+        txn_journal_retire(hw, &mut self.cache[basis_index]);
+    }
 }
 // Revise this to use references instead of allocations once we've refactored the interior mutability
 // issues with the PDDB.
@@ -1113,6 +1255,8 @@ pub(crate) struct BasisCacheEntry {
     pub policy: BasisRetentionPolicy,
     // rention state
     pub policy_state: u32,
+    /// the KDF record of the basis, carried over whenever the root is re-written
+    pub kdf: KdfRecord,
 }
 impl BasisCacheEntry {
     /// given a pointer to the hardware, name of the basis, and its cryptographic key, try to derive
@@ -1166,6 +1310,7 @@ impl BasisCacheEntry {
                     large_alloc_ptr: None,
                     policy,
                     policy_state: policy.derive_init_state(),
+                    kdf: basis_root.kdf,
                 };
                 if !lazy {
                     bcache.populate_caches(hw);
@@ -1260,12 +1405,12 @@ impl BasisCacheEntry {
         }
     }
 
-    /// If `paranoid` is true, it recurses through each key and replaces its data with random junk.
-    /// Otherwise, it does a "shallow" delete and just removes the directory entry, which is much
-    /// more performant. Note that the intended "fast" way to secure-erase data is to store sensitive
-    /// data in its own Basis, and then remove the Basis itself. This is much faster than picking
-    /// through compounded data and re-writing partias sectors, and because of this, initially,
-    /// the `paranoid` erase is `unimplemented`.
+    /// Removes a dictionary and all of its keys. Every page the dictionary occupied -- large key pages,
+    /// small pools and the descriptor area -- is overwritten with noise before it is returned to FastSpace,
+    /// because a re-used dictionary slot would otherwise decode stale keys. Thus the on-disk result is the
+    /// same in both modes; `paranoid` additionally scrubs the plaintext held in the key cache. Note that
+    /// the intended "fast" way to secure-erase a lot of data is still to store it in its own Basis, and
+    /// then remove the Basis itself.
     pub(crate) fn dict_delete(&mut self, hw: &mut PddbOs, name: &str, paranoid: bool) -> Result<()> {
         if self.ensure_dict_in_cache(hw, name) {
             let dcache = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
@@ -1282,8 +1427,14 @@ impl BasisCacheEntry {
             }
             for key in key_list {
                 log::debug!("removing {}:{}", name, key);
-                // this will wipe any large pools if paranoid is set
-                dcache.key_remove(hw, &mut self.v2p_map, &self.cipher, &key, paranoid);
+                // this wipes the large pools; the small pools and descriptors are wiped wholesale below,
+                // so there is no point in paying for a per-key paranoid remove.
+                dcache.key_remove(hw, &mut self.v2p_map, &self.cipher, &key, false);
+            }
+            if paranoid {
+                for kcache in dcache.keys.values_mut() {
+                    kcache.scrub_data();
+                }
             }
             // wipe & de-allocate any small pages
             for index in 0..dcache.small_pool.len() {
@@ -1463,6 +1614,20 @@ impl BasisCacheEntry {
                     name: dict_name,
                 };
                 log::debug!("syncing dict {} with {} keys", name, dict.key_count);
+                // descriptors of deleted keys have to be blanked on disk, otherwise they would be counted against
+                // num_keys on the next fill. A key that was created and deleted between syncs has nothing to blank.
+                let live_indices: HashSet::<u32> = dict.keys.values()
+                    .filter(|k| k.flags.valid())
+                    .map(|k| k.descriptor_index.get())
+                    .collect();
+                for key in dict.keys.values_mut() {
+                    if !key.clean && !key.flags.valid() {
+                        let vpage = VirtAddr::new(dict_offset.get() + (key.descriptor_vpage_num() * VPAGE_SIZE) as u64).unwrap();
+                        if !self.v2p_map.contains_key(&vpage) || live_indices.contains(&key.descriptor_index.get()) {
+                            key.clean = true;
+                        }
+                    }
+                }
                 // log::info!("raw: {:x?}", dict_disk.deref());
                 // observation: all keys to be flushed to disk will be in the KeyCacheEntry. Some may be clean,
                 // but definitely all the dirty ones are in there (if they aren't, where else would they be??)
@@ -1507,6 +1672,14 @@ impl BasisCacheEntry {
                     // make, but for now, let's do it with a dumb O(N) scan through the KeyCacheEntry, running under
                     // the assumption that the KeyCacheEntry doesn't ever get to a very large N.
                     let next_vpage = VirtAddr::new(cur_vpage.get() + VPAGE_SIZE as u64).unwrap();
+                    for key in dict.keys.values_mut() {
+                        if !key.clean && !key.flags.valid() &&
+                        key.descriptor_vaddr(dict_offset) >= cur_vpage && key.descriptor_vaddr(dict_offset) < next_vpage {
+                            // an all-zero entry reads back as an unused slot
+                            dk_vpage.elements[key.descriptor_index.get() as usize % DK_PER_VPAGE] = Some(DictKeyEntry::default());
+                            key.clean = true;
+                        }
+                    }
                     for (key_name, key) in dict.keys.iter_mut() {
                         /*if key_name.contains("dict2|key6|len2347") {
                             log::warn!("TRACING: {}", key_name);
@@ -1567,7 +1740,7 @@ impl BasisCacheEntry {
                     // exit the loop
                     let mut found_next = false;
                     for key in dict.keys.values() {
-                        if !key.clean && key.flags.valid() {
+                        if !key.clean {
                             found_next = true;
                             // note: we don't care *which* vpage we do next -- so we just break after finding the first one
                             vpage_num = key.descriptor_vpage_num();
@@ -1589,12 +1762,150 @@ impl BasisCacheEntry {
         }
     }
 
-    /// Runs through the dictionary listing in a basis and compacts them. Call when the
-    /// the dictionary space becomes sufficiently fragmented that accesses are becoming
-    /// inefficient.
-    #[allow(dead_code)]
-    pub(crate) fn dict_compact(&self, _basis_name: Option<&str>) -> Result<()> {
-        unimplemented!();
+    /// Re-writes a dictionary so its key descriptors and small pool data are packed contiguously from the
+    /// start of their regions. Call when the dictionary space becomes sufficiently fragmented that accesses
+    /// are becoming inefficient.
+    ///
+    /// Every page is shadow-written (see `shadow_patch_page()`), and the writes are ordered so that a valid
+    /// copy of every key survives a power loss at any point:
+    ///   1. The `compacting` flag is set in the dictionary header. While it is set, a mount scans the whole
+    ///      descriptor region and resolves duplicate descriptors by age.
+    ///   2. Small pool data is only ever written to pools that no valid key refers to. If the packed pools
+    ///      overlap pools in use, the data is staged above them first, and then written to its final place.
+    ///   3. Descriptors are written in ascending page order. They only ever move to lower indices, so a key's
+    ///      new descriptor is on disk before the page holding its old one is overwritten.
+    ///   4. Pages outside of the compacted extent are retired, and the flag is cleared.
+    ///
+    /// The caller must ensure that `dict_compact_estimate()` pages of FastSpace are available.
+    pub(crate) fn dict_compact(&mut self, hw: &mut PddbOs, name: &str) -> Result<()> {
+        if !self.ensure_dict_in_cache(hw, name) {
+            return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
+        }
+        let dict = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
+        dict.fill(hw, &self.v2p_map, &self.cipher);
+        dict.fill_small_data(hw, &self.v2p_map, &self.cipher)?;
+        let plan = dict.compaction_plan();
+        let staging = dict.pool_staging_base(plan.pools.len())
+            .ok_or(Error::new(ErrorKind::OutOfMemory, "No free small pool space to stage the compaction"))?;
+        log::info!("compacting {}: {} keys, {} small pools, staging at {}", name, plan.order.len(), plan.pools.len(), staging);
+
+        dict.flags.set_compacting(true);
+        self.dict_write_key_page(hw, name, 0)?;
+        if staging != 0 {
+            self.dict_compact_pass(hw, name, &plan, staging)?;
+        }
+        self.dict_compact_pass(hw, name, &plan, 0)?;
+
+        let dict = self.dicts.get_mut(name).expect("dict vanished during compaction");
+        dict.flags.set_compacting(false);
+        dict.compaction_commit(&plan);
+        self.dict_write_key_page(hw, name, 0)?;
+        self.pt_sync(hw);
+        Ok(())
+    }
+    /// Upper bound on the FastSpace pages consumed by a `dict_compact()` call: every page is written
+    /// up to twice (once to the staging area, once to its final place), plus two header updates.
+    pub(crate) fn dict_compact_estimate(&mut self, hw: &mut PddbOs, name: &str) -> Result<usize> {
+        if !self.ensure_dict_in_cache(hw, name) {
+            return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
+        }
+        let dict = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
+        dict.fill(hw, &self.v2p_map, &self.cipher);
+        let plan = dict.compaction_plan();
+        Ok(2 * (plan.pools.len() + plan.order.len() / DK_PER_VPAGE + 1) + 2)
+    }
+    /// One pass of a compaction: writes the packed small pools starting at `pool_base`, points the keys
+    /// at them, re-writes every descriptor page, and retires whatever falls outside of the new layout.
+    fn dict_compact_pass(&mut self, hw: &mut PddbOs, name: &str, plan: &CompactionPlan, pool_base: usize) -> Result<()> {
+        let dict = self.dicts.get_mut(name).ok_or(Error::new(ErrorKind::NotFound, "dictionary not found"))?;
+        let dict_index = dict.index;
+        let old_extent = dict.last_disk_key_index as usize / DK_PER_VPAGE;
+        // 1. data goes first, to pools that nothing on disk refers to yet
+        for (offset, names) in plan.pools.iter().enumerate() {
+            let pool_vaddr = small_storage_base_vaddr_from_indices(dict_index, pool_base + offset);
+            let mut page = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+            let mut pool_offset = 0;
+            for key_name in names {
+                let kcache = dict.keys.get_mut(key_name).expect("planned key is missing");
+                if let Some(KeyCacheData::Small(data)) = kcache.data.as_mut() {
+                    for (&src, dst) in data.data.iter()
+                    .zip(page[size_of::<JournalType>() + pool_offset..size_of::<JournalType>() + pool_offset + kcache.reserved as usize].iter_mut())
+                    {*dst = src;}
+                    data.clean = true;
+                } else {
+                    return Err(Error::new(ErrorKind::InvalidData, "small key data is not in the cache"));
+                }
+                kcache.start = pool_vaddr + pool_offset as u64;
+                kcache.flags.set_unresolved(false);
+                pool_offset += kcache.reserved as usize;
+            }
+            shadow_patch_page(hw, &mut self.v2p_map, &self.cipher, &self.cipher_ecb, &self.aad,
+                VirtAddr::new(pool_vaddr).unwrap(), &mut page)?;
+        }
+        // 2. descriptors, in ascending page order. The bumped age makes the new copy win over any stale one.
+        for (i, key_name) in plan.order.iter().enumerate() {
+            let kcache = dict.keys.get_mut(key_name).expect("planned key is missing");
+            kcache.descriptor_index = NonZeroU32::new(i as u32 + 1).unwrap();
+            kcache.age = kcache.age.saturating_add(1);
+            kcache.clean = true;
+        }
+        let new_extent = plan.order.len() / DK_PER_VPAGE;
+        for vpage_num in 0..=new_extent {
+            self.dict_write_key_page(hw, name, vpage_num)?;
+        }
+        let dict_base = dict_index.get() as u64 * DICT_VSIZE;
+        for vpage_num in new_extent + 1..=old_extent {
+            retire_page(hw, &mut self.v2p_map, VirtAddr::new(dict_base + (vpage_num * VPAGE_SIZE) as u64).unwrap());
+        }
+        // 3. no key refers to pools outside of the ones just written anymore
+        let pool_start = small_storage_base_vaddr_from_indices(dict_index, 0);
+        let keep = small_storage_base_vaddr_from_indices(dict_index, pool_base)
+            ..small_storage_base_vaddr_from_indices(dict_index, pool_base + plan.pools.len());
+        let stale: Vec<VirtAddr> = self.v2p_map.keys()
+            .filter(|va| va.get() >= pool_start && va.get() < pool_start + SMALL_POOL_STRIDE && !keep.contains(&va.get()))
+            .copied()
+            .collect();
+        for vaddr in stale {
+            retire_page(hw, &mut self.v2p_map, vaddr);
+        }
+        Ok(())
+    }
+    /// Writes one complete vpage of a dictionary's descriptor region out of the cache: the dictionary header if
+    /// it is the first page, and every valid key whose descriptor falls on the page. Any other slot is zeroed, so
+    /// every key of the dictionary must be in the cache.
+    fn dict_write_key_page(&mut self, hw: &mut PddbOs, name: &str, vpage_num: usize) -> Result<()> {
+        let dict = self.dicts.get(name).ok_or(Error::new(ErrorKind::NotFound, "dictionary not found"))?;
+        let mut page = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+        if vpage_num == 0 {
+            let dict_disk = Dictionary {
+                flags: dict.flags,
+                age: dict.age,
+                num_keys: dict.key_count,
+                free_key_index: dict.last_disk_key_index,
+                name: DictName::try_from_str(name).or(Err(Error::new(ErrorKind::InvalidInput, "dictionary name invalid: invalid utf-8 or length")))?,
+            };
+            for (&src, dst) in dict_disk.deref().iter().zip(page[size_of::<JournalType>()..size_of::<JournalType>() + DK_STRIDE].iter_mut()) {
+                *dst = src;
+            }
+        }
+        for (key_name, key) in dict.keys.iter() {
+            if key.flags.valid() && key.descriptor_vpage_num() == vpage_num {
+                let key_desc = KeyDescriptor {
+                    start: key.start,
+                    len: key.len,
+                    reserved: key.reserved,
+                    flags: key.flags,
+                    age: key.age,
+                    name: KeyName::try_from_str(key_name).or(Err(Error::new(ErrorKind::InvalidInput, "key name invalid: invalid utf-8 or length")))?,
+                };
+                let start = size_of::<JournalType>() + key.descriptor_modulus() * DK_STRIDE;
+                for (&src, dst) in key_desc.deref().iter().zip(page[start..start + DK_STRIDE].iter_mut()) {
+                    *dst = src;
+                }
+            }
+        }
+        let vaddr = VirtAddr::new(dict.index.get() as u64 * DICT_VSIZE + (vpage_num * VPAGE_SIZE) as u64).unwrap();
+        shadow_patch_page(hw, &mut self.v2p_map, &self.cipher, &self.cipher_ecb, &self.aad, vaddr, &mut page)
     }
 
     /// Syncs *only* the basis header to disk.
@@ -1607,6 +1918,7 @@ impl BasisCacheEntry {
                 name: BasisRootName::try_from_str(&self.name).unwrap(),
                 age: self.age,
                 num_dictionaries: self.num_dicts,
+                kdf: self.kdf,
             };
             let pp = self.v2p_map.get(&VirtAddr::new(1 * VPAGE_SIZE as u64).unwrap())
                 .expect("Internal consistency error: Basis exists, but its root map was not allocated!");
@@ -1691,38 +2003,40 @@ impl BasisCacheEntry {
 
 }
 
-// ****
-// Beginning of serializers for the data structures in this file.
-// ****
-
-/// Newtype for BasisRootName so we can give it a default initializer.
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
-pub struct BasisRootName {
-    pub len: u8,
-    pub data: [u8; BASIS_NAME_LEN - 1],
-}
-impl BasisRootName {
-    pub fn try_from_str(name: &str) -> Result<BasisRootName> {
-        let mut alloc = [0u8; BASIS_NAME_LEN - 1];
-        let bytes = name.as_bytes();
-        if bytes.len() > (BASIS_NAME_LEN - 1) {
-            Err(Error::new(ErrorKind::InvalidInput, "basis name is too long")) // FileNameTooLong is still nightly :-/
-        } else {
-            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
-                *dst = src;
-            }
-            Ok(BasisRootName {
-                len: bytes.len() as u8, // this as checked above to be short enough
-                data: alloc,
-            })
-        }
+/// Writes `page` (one vpage with the journal on top) to `vaddr` without touching the physical page that
+/// currently backs it. The data goes to a freshly allocated page with a higher journal revision, and its PTE
+/// is written before the old page is retired. If power is lost in between, the journal-based conflict
+/// resolution in `pt_scan_key()` picks the new page.
+fn shadow_patch_page(hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>, cipher_ecb: &Aes256,
+    aad: &[u8], vaddr: VirtAddr, page: &mut [u8]) -> Result<()> {
+    let old_journal = v2p_map.get(&vaddr)
+        .and_then(|pp| hw.data_decrypt_page(cipher, aad, pp))
+        .map(|data| JournalType::from_le_bytes(data[..size_of::<JournalType>()].try_into().unwrap()));
+    let journal = old_journal.unwrap_or_else(|| hw.trng_u32() % JOURNAL_RAND_RANGE);
+    for (&src, dst) in journal.to_le_bytes().iter().zip(page[..size_of::<JournalType>()].iter_mut()) {
+        *dst = src;
     }
+    let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to shadow a page"))?;
+    pp.set_valid(true);
+    hw.data_encrypt_and_patch_page(cipher, aad, page, &pp);
+    hw.pt_patch_mapping(vaddr, pp.page_number(), cipher_ecb);
+    pp.set_clean(true);
+    if let Some(mut old_pp) = v2p_map.insert(vaddr, pp) {
+        scrub_page(hw, &mut old_pp);
+    }
+    Ok(())
 }
-impl Default for BasisRootName {
-    fn default() -> BasisRootName {
-        BasisRootName{
-            len: 0,
-            data: [0; BASIS_NAME_LEN - 1]
-        }
+/// Unmaps `vaddr`, if it is mapped, and scrubs the page that backed it.
+pub(crate) fn retire_page(hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, vaddr: VirtAddr) {
+    if let Some(mut pp) = v2p_map.remove(&vaddr) {
+        scrub_page(hw, &mut pp);
     }
 }
+/// Erases the PTE of a page that is no longer in the v2p map, overwrites its data with noise, and returns it to FastSpace.
+pub(crate) fn scrub_page(hw: &mut PddbOs, pp: &mut PhysPage) {
+    hw.pt_erase(pp.page_number());
+    let mut noise = [0u8; PAGE_SIZE];
+    hw.trng_slice(&mut noise);
+    hw.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+    hw.fast_space_free(pp);
+}
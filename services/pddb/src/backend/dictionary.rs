@@ -2,22 +2,14 @@ use crate::api::*;
 use super::*;
 
 use std::num::NonZeroU32;
-use core::ops::{Deref, DerefMut};
+use core::ops::DerefMut;
 use core::mem::size_of;
 use aes_gcm_siv::AesGcmSiv;
 use aes::Aes256;
 use std::collections::{HashMap, BinaryHeap, HashSet};
 use std::io::{Result, Error, ErrorKind};
-use bitfield::bitfield;
 use std::cmp::{Ordering, Reverse};
 
-bitfield! {
-    #[derive(Copy, Clone, PartialEq, Eq)]
-    pub struct DictFlags(u32);
-    impl Debug;
-    pub valid, set_valid: 0;
-}
-
 /// RAM based copy of the dictionary structures on disk. Most of the methods on this function operate on
 /// keys within the Dictionary. Operations on the Dictionary itself originate from the containing Basis
 /// structure.
@@ -47,8 +39,8 @@ pub(crate) struct DictCacheEntry {
     /// goes completely empty, the entry should still exist but indicate that it's got space. Thus if a key was found allocated
     /// to the Nth index position, but the previous N-1 positions are empty, the only way we could have gotten there was if we
     /// had allocated lots of small data, filled upo the pool to the Nth position, and then deleted all of that prior data.
-    /// This situation could create pathologies in the memory usage overhead of the small_pool, which until we have a "defrag"
-    /// operation for the small pool, we may just have to live with.
+    /// This situation could create pathologies in the memory usage overhead of the small_pool; `dict_compact()` in the
+    /// Basis repacks the pool starting from index 0 to clean this up.
     pub(crate) small_pool: Vec<KeySmallPool>,
     /// free space of each small pool element. It's a collection of free space along with the Vec index of the small_pool.
     /// We don't keep the KeySmallPool itself in the small_pool_free directly because it's presumed to be more common
@@ -126,7 +118,13 @@ impl DictCacheEntry {
             let mut index_cache = PlaintextCache { data: None, tag: None };
             let mut data_cache = PlaintextCache { data: None, tag: None };
             let mut errcnt = 0;
-            while try_entry < KEY_MAXCOUNT && key_count < self.key_count {
+            // An interrupted compaction can leave two descriptors for the same key on disk, so the key count
+            // can't bound the search. Scan the whole extent and keep the copy with the highest age; small key
+            // data is filled in only once the winning descriptor is known.
+            let compacting = self.flags.compacting();
+            let mut scanned = HashSet::<String>::new();
+            while try_entry < KEY_MAXCOUNT &&
+            if compacting { try_entry < self.last_disk_key_index as usize } else { key_count < self.key_count } {
                 // cache our decryption data -- there's about 32 entries per page, and the scan is largely linear/sequential, so this should
                 // be a substantial savings in effort.
                 let req_vaddr = dict_indices_to_vaddr(self.index, try_entry);
@@ -175,10 +173,21 @@ impl DictCacheEntry {
                                 // if the key is within the large pool space, note its allocation for the basis overall
                                 alloc_top = VirtAddr::new(keydesc.start + keydesc.reserved).unwrap();
                                 // nothing else needs to be done -- we don't pre-cache large key data.
+                            } else if compacting {
+                                scanned.insert(kname.to_string());
                             } else {
                                 // try to fill the small key cache entry details
                                 self.try_fill_small_key(hw, v2p_map, cipher, &mut data_cache, &kname);
                             }
+                        } else if compacting && self.keys.get(kname).map_or(false, |k| k.clean && keydesc.age > k.age) {
+                            log::info!("fill: newer copy of {} found at index {}", kname, try_entry);
+                            if keydesc.start + keydesc.reserved > alloc_top.get() {
+                                alloc_top = VirtAddr::new(keydesc.start + keydesc.reserved).unwrap();
+                                scanned.remove(kname);
+                            } else {
+                                scanned.insert(kname.to_string());
+                            }
+                            self.keys.insert(kname.to_string(), kcache);
                         } else {
                             log::trace!("fill: entry already present {}", kname);
                         }
@@ -187,6 +196,13 @@ impl DictCacheEntry {
                     try_entry += 1;
                 }
             }
+            if compacting {
+                for key_to_fill in scanned.iter() {
+                    self.try_fill_small_key(hw, v2p_map, cipher, &mut data_cache, key_to_fill);
+                }
+                // the header count is stale if the compaction dropped descriptors of deleted keys
+                self.key_count = self.keys.values().filter(|k| k.flags.valid()).count() as u32;
+            }
             // note where the scan left off, so we don't have to brute-force it in the future
             self.last_disk_key_index = try_entry as u32;
 
@@ -215,6 +231,11 @@ impl DictCacheEntry {
     /// if cache is hot or key was found on search.
     pub(crate) fn ensure_key_entry(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>,
         name_str: &str) -> bool {
+        if self.flags.compacting() && !self.keys.contains_key(name_str) {
+            // a targeted search could land on a stale duplicate of the descriptor; the full scan resolves these by age
+            self.fill(hw, v2p_map, cipher);
+            return self.keys.get(name_str).map_or(false, |k| k.flags.valid());
+        }
         // only fill if the key isn't in the cache.
         if !self.keys.contains_key(name_str) {
             log::debug!("searching for key {}", name_str);
@@ -295,8 +316,8 @@ impl DictCacheEntry {
                     // fill in the pool with blank entries. In general, we should have a low amount of blank entries, but
                     // one situation where we could get a leak is if we allocate a large amount of small data, and then delete
                     // all but the most recently allocated one, leaving an orphan at a high index, which is then subsequently
-                    // treated as read-only so none of the subsequent write/update ops would have occassion to move it. This would
-                    // need to be remedied with a "defrag" operation, but for now, we don't have that.
+                    // treated as read-only so none of the subsequent write/update ops would have occassion to move it. This is
+                    // remedied by compacting the dictionary.
                     let ksp = KeySmallPool::new();
                     self.small_pool.push(ksp);
                 }
@@ -459,112 +480,113 @@ impl DictCacheEntry {
                 // note: there is no need to update small_pool_free because the reserved size did not change.
             } else {
                 // it's a large key
-                if let Some(_kcd) = &kcache.data {
-                    unimplemented!("caching is not yet implemented for large data sets");
-                } else {
-                    kcache.age = kcache.age.saturating_add(1);
-                    kcache.clean = false;
-                    /* // this was for debugging a patching bug -- OK to remove
-                    if data.len() == 4 {
-                        use std::convert::TryInto;
-                        log::info!("patching checksum: {:x} at offset {}", u32::from_le_bytes(data.try_into().unwrap()), offset);
-                    }*/
-                    // 1. handle unaligned start offsets
-                    let mut written: usize = 0;
-                    if ((kcache.start + offset as u64 + written as u64) % VPAGE_SIZE as u64) != 0 {
-                        let start_vpage_addr = ((kcache.start + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                        let pp = v2p_map.get(&VirtAddr::new(start_vpage_addr).unwrap()).expect("large key data allocation missing");
-                        assert!(pp.valid(), "v2p returned an invalid page");
-                        let mut pt_data = match hw.data_decrypt_page(&cipher, &self.aad, pp) {
-                            Some(data) => data,
-                            None => {
-                                // this case is triggered by the following circumstance:
-                                //  - we reserved data that includes this current page
-                                //  - up until now, we've only written data into the previous page (so this page is not initialized -- it's garbage)
-                                //  - we just issued an update that causes the data to touch this page for the first time
-                                // in response to this, we allocate a fresh page of 0's.
-                                log::debug!("Reserved and uninitialized page encountered updating large block: {} {:x}..{}->{}; update @{}..{}",
-                                    name, kcache.start, kcache.len, kcache.reserved, offset, data.len());
-                                let mut d = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                                for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(d[..size_of::<JournalType>()].iter_mut()) {
-                                    *dst = src;
-                                }
-                                d
+                // the data goes straight to disk; drop any cached copies of the pages it touches
+                if let Some(KeyCacheData::Large(kld)) = kcache.data.as_mut() {
+                    let end = if truncate { u64::MAX } else { kcache.start + (offset + data.len()) as u64 };
+                    kld.invalidate(kcache.start + offset as u64, end);
+                }
+                kcache.age = kcache.age.saturating_add(1);
+                kcache.clean = false;
+                /* // this was for debugging a patching bug -- OK to remove
+                if data.len() == 4 {
+                    use std::convert::TryInto;
+                    log::info!("patching checksum: {:x} at offset {}", u32::from_le_bytes(data.try_into().unwrap()), offset);
+                }*/
+                // 1. handle unaligned start offsets
+                let mut written: usize = 0;
+                if ((kcache.start + offset as u64 + written as u64) % VPAGE_SIZE as u64) != 0 {
+                    let start_vpage_addr = ((kcache.start + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
+                    let pp = v2p_map.get(&VirtAddr::new(start_vpage_addr).unwrap()).expect("large key data allocation missing");
+                    assert!(pp.valid(), "v2p returned an invalid page");
+                    let mut pt_data = match hw.data_decrypt_page(&cipher, &self.aad, pp) {
+                        Some(data) => data,
+                        None => {
+                            // this case is triggered by the following circumstance:
+                            //  - we reserved data that includes this current page
+                            //  - up until now, we've only written data into the previous page (so this page is not initialized -- it's garbage)
+                            //  - we just issued an update that causes the data to touch this page for the first time
+                            // in response to this, we allocate a fresh page of 0's.
+                            log::debug!("Reserved and uninitialized page encountered updating large block: {} {:x}..{}->{}; update @{}..{}",
+                                name, kcache.start, kcache.len, kcache.reserved, offset, data.len());
+                            let mut d = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+                            for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(d[..size_of::<JournalType>()].iter_mut()) {
+                                *dst = src;
                             }
-                        };
-                        if offset > 0 {
-                            log::trace!("patching offset {}, total length {}, data length {}", offset % VPAGE_SIZE, kcache.len, data.len());
+                            d
+                        }
+                    };
+                    if offset > 0 {
+                        log::trace!("patching offset {}, total length {}, data length {}", offset % VPAGE_SIZE, kcache.len, data.len());
+                    }
+                    for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>() + (offset % VPAGE_SIZE)..].iter_mut()) {
+                        *dst = src;
+                        written += 1;
+                    }
+                    if written < data.len() {
+                        assert!((kcache.start + offset as u64 + written as u64) % VPAGE_SIZE as u64 == 0, "alignment algorithm failed");
+                    }
+                    hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, &pp);
+                }
+                // 2. do the rest
+                while written < data.len() {
+                    let vpage_addr = ((kcache.start + written as u64 + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
+                    let pp = v2p_map.get(&VirtAddr::new(vpage_addr).unwrap()).expect("large key data allocation missing");
+                    assert!(pp.valid(), "v2p returned an invalid page");
+                    if data.len() - written >= VPAGE_SIZE {
+                        // overwrite whole pages without decryption
+                        let mut block = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+                        for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(block[..size_of::<JournalType>()].iter_mut()) {
+                            *dst = src;
                         }
-                        for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>() + (offset % VPAGE_SIZE)..].iter_mut()) {
+                        for (&src, dst) in data[written..].iter().zip(block[size_of::<JournalType>()..].iter_mut()) {
                             *dst = src;
                             written += 1;
                         }
-                        if written < data.len() {
-                            assert!((kcache.start + offset as u64 + written as u64) % VPAGE_SIZE as u64 == 0, "alignment algorithm failed");
-                        }
-                        hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, &pp);
-                    }
-                    // 2. do the rest
-                    while written < data.len() {
-                        let vpage_addr = ((kcache.start + written as u64 + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                        let pp = v2p_map.get(&VirtAddr::new(vpage_addr).unwrap()).expect("large key data allocation missing");
-                        assert!(pp.valid(), "v2p returned an invalid page");
-                        if data.len() - written >= VPAGE_SIZE {
-                            // overwrite whole pages without decryption
-                            let mut block = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                            for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(block[..size_of::<JournalType>()].iter_mut()) {
-                                *dst = src;
-                            }
-                            for (&src, dst) in data[written..].iter().zip(block[size_of::<JournalType>()..].iter_mut()) {
+                        hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut block, pp);
+                    } else {
+                        // handle partial trailing pages
+                        if let Some(pt_data) = hw.data_decrypt_page(&cipher, &self.aad, pp).as_mut() {
+                            for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
                                 *dst = src;
                                 written += 1;
                             }
-                            hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut block, pp);
+                            hw.data_encrypt_and_patch_page(cipher, &self.aad, pt_data, pp);
                         } else {
-                            // handle partial trailing pages
-                            if let Some(pt_data) = hw.data_decrypt_page(&cipher, &self.aad, pp).as_mut() {
-                                for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
-                                    *dst = src;
-                                    written += 1;
-                                }
-                                hw.data_encrypt_and_patch_page(cipher, &self.aad, pt_data, pp);
-                            } else {
-                                // page didn't exist, initialize it with 0's and merge the tail end.
-                                let mut pt_data = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                                for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(pt_data[..size_of::<JournalType>()].iter_mut()) {
-                                    *dst = src;
-                                }
-                                for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
-                                    *dst = src;
-                                    written += 1;
-                                }
-                                hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, pp);
+                            // page didn't exist, initialize it with 0's and merge the tail end.
+                            let mut pt_data = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+                            for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(pt_data[..size_of::<JournalType>()].iter_mut()) {
+                                *dst = src;
+                            }
+                            for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
+                                *dst = src;
+                                written += 1;
                             }
+                            hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, pp);
                         }
                     }
-                    log::trace!("data written: {}, data requested to write: {}", written, data.len());
-                    assert!(written == data.len(), "algorithm problem -- didn't write all the data we thought we would");
-                    // 3. truncate or extend
-                    // check if we grew the length; extend the length by exactly enough if so.
-                    if kcache.len < (data.len() + offset) as u64 {
-                        kcache.len = (data.len() + offset) as u64;
-                    } else if truncate {
-                        // discard all whole pages after written+offset, and reset the reserved field to the smaller size.
-                        log::trace!("PageAligned VA components: {}, {}", written, offset);
-                        let vpage_end_offset = PageAlignedVa::from((written + offset) as u64);
-                        if (vpage_end_offset.as_u64() - kcache.start) > kcache.reserved {
-                            for vpage in (vpage_end_offset.as_u64()..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
-                                if let Some(pp) = v2p_map.get_mut(&VirtAddr::new(vpage).unwrap()) {
-                                    assert!(pp.valid(), "v2p returned an invalid page");
-                                    log::trace!("fast_space_free key_update {} before", pp.journal());
-                                    hw.fast_space_free(pp);
-                                    assert!(pp.valid() == false, "pp is still marked as valid!");
-                                }
+                }
+                log::trace!("data written: {}, data requested to write: {}", written, data.len());
+                assert!(written == data.len(), "algorithm problem -- didn't write all the data we thought we would");
+                // 3. truncate or extend
+                // check if we grew the length; extend the length by exactly enough if so.
+                if kcache.len < (data.len() + offset) as u64 {
+                    kcache.len = (data.len() + offset) as u64;
+                } else if truncate {
+                    // discard all whole pages after written+offset, and reset the reserved field to the smaller size.
+                    log::trace!("PageAligned VA components: {}, {}", written, offset);
+                    let vpage_end_offset = PageAlignedVa::from((written + offset) as u64);
+                    if (vpage_end_offset.as_u64() - kcache.start) > kcache.reserved {
+                        for vpage in (vpage_end_offset.as_u64()..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
+                            if let Some(pp) = v2p_map.get_mut(&VirtAddr::new(vpage).unwrap()) {
+                                assert!(pp.valid(), "v2p returned an invalid page");
+                                log::trace!("fast_space_free key_update {} before", pp.journal());
+                                hw.fast_space_free(pp);
+                                assert!(pp.valid() == false, "pp is still marked as valid!");
                             }
-                            kcache.reserved = vpage_end_offset.as_u64() - kcache.start;
-                            kcache.clean = false;
-                            kcache.len = (data.len() + offset) as u64;
                         }
+                        kcache.reserved = vpage_end_offset.as_u64() - kcache.start;
+                        kcache.clean = false;
+                        kcache.len = (data.len() + offset) as u64;
                     }
                 }
             }
@@ -668,7 +690,7 @@ impl DictCacheEntry {
                     age: 0,
                     descriptor_index,
                     clean: false,
-                    data: None, // no caching implemented yet for large keys
+                    data: None, // large key pages are cached as they are read
                     atime: self.created.elapsed().as_millis() as u64,
                 };
                 self.keys.insert(name.to_string(), kcache);
@@ -700,14 +722,13 @@ impl DictCacheEntry {
         }
     }
     /// Used to remove a key from the dictionary. If you call it with a non-existent key,
-    /// the routine has no effect, and does not report an error. Small keys are not immediately
-    /// overwritten in paranoid mode, but large keys are.
+    /// the routine has no effect, and does not report an error. Large keys always have their pages
+    /// overwritten with noise as they are freed. In paranoid mode, the small pool holding the key is
+    /// also re-written immediately with noise in the freed slot, the key's descriptor is zeroed on disk,
+    /// and the cached plaintext is scrubbed. The small pool re-write dirties the descriptors of the keys
+    /// that shared the pool, so the caller must follow up with a `dict_sync` and a `pt_sync`.
     pub fn key_remove(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>,
         name_str: &str, paranoid: bool) {
-        if paranoid {
-            // large records are paranoid-erased, by default, because of the pool-reuse problem.
-            unimplemented!("Paranoid erase for small records not yet implemented. Calling sync after an update, however, effectively does a paranoid erase.");
-        }
         // this call will check the disk to see if there's key data that's not in cache.
         if self.ensure_key_entry(hw, v2p_map, cipher, name_str) {
             let name = String::from(name_str);
@@ -734,12 +755,14 @@ impl DictCacheEntry {
                     ksp.avail += kcache.reserved as u16;
                     assert!(ksp.avail <= SMALL_CAPACITY as u16, "bookkeeping error in small pool capacity");
                     ksp.clean = false; // this will also effectively cause the record to be deleted on disk once the small pool data is synchronized
+                    ksp.scrub |= paranoid;
                     need_rebuild = true;
 
                 } else {
                     // handle the large pool case
                     // mark the entry as invalid and dirty; virtual space is one huge memory leak...
                     // ...but we remove the virtual pages from the page pool, effectively reclaiming the physical space.
+                    kcache.data.take(); // scrubs any cached pages
                     for vpage in kcache.large_pool_vpages() {
                         if let Some(pp) = v2p_map.get_mut(&vpage) {
                             assert!(pp.valid(), "v2p returned an invalid page");
@@ -755,6 +778,9 @@ impl DictCacheEntry {
                         }
                     }
                 }
+                if paranoid {
+                    kcache.scrub_data();
+                }
                 need_free_key = Some(kcache.descriptor_index.get());
             }
             // free up the key index in the dictionary, if necessary
@@ -767,6 +793,20 @@ impl DictCacheEntry {
                 // no stable "retain" api, so we have to clear the heap and rebuild it https://github.com/rust-lang/rust/issues/71503
                 self.rebuild_free_pool();
             }
+            if paranoid {
+                if need_rebuild {
+                    // sync_small_pool() needs the data of every key that shares the pool
+                    match self.fill_small_data(hw, v2p_map, cipher) {
+                        Ok(_) => if !self.sync_small_pool(hw, v2p_map, cipher) {
+                            log::warn!("Out of FastSpace; the slot of {} will be scrubbed on the next sync", name_str);
+                        },
+                        Err(e) => log::error!("Couldn't scrub the small pool of {}: {:?}", name_str, e),
+                    }
+                }
+                if let Some(key_index) = need_free_key {
+                    self.scrub_descriptor(hw, v2p_map, cipher, key_index);
+                }
+            }
 
             // we don't remove the cache entry, because it hasn't been synchronized to disk.
             // at this point:
@@ -778,12 +818,20 @@ impl DictCacheEntry {
         }
         // if there's no key....we're done!
     }
-    /// used to remove a key from the dictionary, syncing 0's to the disk in the key's place
-    /// sort of less relevant now that the large keys have a paranoid mode; probably this routine should actually
-    /// be a higher-level function that catches the paranoid request and does an "update" of 0's to the key
-    /// then does a disk sync and then calls remove
-    pub fn key_erase(&mut self, _name: &str) {
-        unimplemented!();
+    /// Zeroes the on-disk descriptor of a removed key. The page is re-encrypted in place, so none of the
+    /// old ciphertext survives; the zeroed slot reads back the same as one that was never allocated.
+    fn scrub_descriptor(&self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>, key_index: u32) {
+        let dk_vaddr = VirtAddr::new(dict_indices_to_vaddr(self.index, key_index as usize)).unwrap();
+        if let Some(pp) = v2p_map.get(&dk_vaddr) {
+            if let Some(mut page) = hw.data_decrypt_page(cipher, &self.aad, pp) {
+                let start = size_of::<JournalType>() + (key_index as usize % DK_PER_VPAGE) * DK_STRIDE;
+                for b in page[start..start + DK_STRIDE].iter_mut() {
+                    *b = 0;
+                }
+                hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut page, pp);
+            }
+        }
+        // if the page isn't mapped or doesn't decrypt, the descriptor was never committed to disk
     }
     /// estimates the amount of space needed to sync the dict cache. Pass this to ensure_fast_space_alloc() before calling a sync.
     /// estimate can be inaccurate under pathological allocation conditions.
@@ -846,6 +894,11 @@ impl DictCacheEntry {
                 for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(page[..size_of::<JournalType>()].iter_mut()) {
                     *dst = src;
                 }
+                if entry.scrub {
+                    // a paranoid remove freed up a slot in this pool: back the free space with noise
+                    hw.trng_slice(&mut page[size_of::<JournalType>()..]);
+                    entry.scrub = false;
+                }
                 let mut pool_offset = 0;
                 // visit the entries in arbitrary order, but back them in optimally tightly packed
                 for key_name in &entry.contents {
@@ -976,6 +1029,143 @@ impl DictCacheEntry {
         }
     }
 
+    /// Loads the data of every valid small key that has had its data evicted from the cache. Unlike
+    /// `try_fill_small_key()`, this does not skip pools that are marked as clean, so it can be used to
+    /// bring the whole small pool into RAM prior to re-writing it.
+    pub(crate) fn fill_small_data(&mut self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>) -> Result<()> {
+        let mut data_cache = PlaintextCache { data: None, tag: None };
+        for (name, kcache) in self.keys.iter_mut() {
+            if !kcache.flags.valid() || kcache.data.is_some() {
+                continue;
+            }
+            if let Some(pool_index) = small_storage_index_from_key(kcache, self.index) {
+                let data_vaddr = small_storage_base_vaddr_from_indices(self.index, pool_index);
+                data_cache.fill(hw, v2p_map, cipher, &self.aad, VirtAddr::new(data_vaddr).unwrap());
+                if let Some(page) = data_cache.data.as_ref() {
+                    let start_offset = size_of::<JournalType>() + (kcache.start % VPAGE_SIZE as u64) as usize;
+                    let mut data = page[start_offset..start_offset + kcache.len as usize].to_vec();
+                    data.reserve_exact((kcache.reserved - kcache.len) as usize);
+                    kcache.data = Some(KeyCacheData::Small(KeySmallData { clean: true, data }));
+                } else {
+                    log::error!("Key {}'s data region at va: {:x} is unreadable", name, kcache.start);
+                    return Err(Error::new(ErrorKind::InvalidData, "small key data is unreadable"));
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Computes the target layout of a compacted dictionary. Key descriptors keep their relative
+    /// order, which guarantees that every descriptor moves to an index less than or equal to its current one.
+    /// Small keys are packed into pools with a first-fit-decreasing strategy. Requires every key to be in the cache.
+    pub(crate) fn compaction_plan(&self) -> CompactionPlan {
+        let mut order: Vec<(u32, String)> = self.keys.iter()
+            .filter(|(_, k)| k.flags.valid())
+            .map(|(name, k)| (k.descriptor_index.get(), name.to_string()))
+            .collect();
+        order.sort();
+
+        let mut small: Vec<(u64, String)> = self.keys.iter()
+            .filter(|(_, k)| k.flags.valid() && small_storage_index_from_key(k, self.index).is_some())
+            .map(|(name, k)| (k.reserved, name.to_string()))
+            .collect();
+        small.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        let mut pools = Vec::<Vec<String>>::new();
+        let mut avail = Vec::<u64>::new();
+        for (reserved, name) in small {
+            if let Some(bin) = avail.iter().position(|&a| a >= reserved) {
+                avail[bin] -= reserved;
+                pools[bin].push(name);
+            } else {
+                avail.push(SMALL_CAPACITY as u64 - reserved);
+                pools.push(vec![name]);
+            }
+        }
+        CompactionPlan {
+            order: order.into_iter().map(|(_, name)| name).collect(),
+            pools,
+        }
+    }
+    /// Returns the first small pool index at which `count` pools can be written without overwriting
+    /// data referenced by a valid key. 0 means the packed pools can be written in place; any other
+    /// value is a staging area that the data has to visit first. None if the pool space is exhausted.
+    pub(crate) fn pool_staging_base(&self, count: usize) -> Option<usize> {
+        let mut used = HashSet::<usize>::new();
+        for kcache in self.keys.values() {
+            if kcache.flags.valid() {
+                if let Some(index) = small_storage_index_from_key(kcache, self.index) {
+                    used.insert(index);
+                }
+            }
+        }
+        if (0..count).all(|i| !used.contains(&i)) {
+            return Some(0);
+        }
+        let max_pools = (SMALL_POOL_STRIDE / SMALL_CAPACITY as u64) as usize;
+        let mut base = count;
+        while base + count <= max_pools {
+            if let Some(clash) = (base..base + count).filter(|i| used.contains(i)).max() {
+                base = clash + 1;
+            } else {
+                return Some(base);
+            }
+        }
+        None
+    }
+    /// Returns the number of pages the dictionary occupies now, and the number it would occupy once compacted.
+    pub(crate) fn compaction_extent(&self, v2p_map: &HashMap::<VirtAddr, PhysPage>, plan: &CompactionPlan) -> (usize, usize) {
+        let mut now = 0;
+        for vpage in 0..=(self.last_disk_key_index as usize / DK_PER_VPAGE) {
+            let vaddr = self.index.get() as u64 * DICT_VSIZE + (vpage * VPAGE_SIZE) as u64;
+            if v2p_map.contains_key(&VirtAddr::new(vaddr).unwrap()) {
+                now += 1;
+            }
+        }
+        for pool in 0..self.small_pool.len() {
+            if v2p_map.contains_key(&VirtAddr::new(small_storage_base_vaddr_from_indices(self.index, pool)).unwrap()) {
+                now += 1;
+            }
+        }
+        (now, plan.order.len() / DK_PER_VPAGE + 1 + plan.pools.len())
+    }
+    /// Decides if the dictionary is fragmented enough to be worth compacting. Interrupted compactions
+    /// always need to be finished; otherwise we only consider fully cached dictionaries, so that this
+    /// check never has to go to disk.
+    pub(crate) fn compaction_needed(&self, v2p_map: &HashMap::<VirtAddr, PhysPage>) -> bool {
+        if self.flags.compacting() {
+            return true;
+        }
+        let valid_keys = self.keys.values().filter(|k| k.flags.valid()).count();
+        if !self.flags.valid() || valid_keys != self.key_count as usize {
+            return false;
+        }
+        let (now, after) = self.compaction_extent(v2p_map, &self.compaction_plan());
+        let reclaim = now.saturating_sub(after);
+        reclaim >= COMPACT_MIN_PAGES && reclaim * 100 >= now * COMPACT_THRESHOLD_PERCENT
+    }
+    /// Brings the bookkeeping in line with a compacted layout, once the keys have been re-written
+    /// to their new locations on disk.
+    pub(crate) fn compaction_commit(&mut self, plan: &CompactionPlan) {
+        self.keys.retain(|_, k| k.flags.valid());
+        let mut small_pool = Vec::<KeySmallPool>::new();
+        for names in plan.pools.iter() {
+            let used: u64 = names.iter().map(|n| self.keys.get(n).expect("planned key is missing").reserved).sum();
+            small_pool.push(KeySmallPool {
+                contents: names.clone(),
+                avail: (SMALL_CAPACITY as u64 - used) as u16,
+                clean: true,
+                scrub: false,
+            });
+        }
+        self.small_pool = small_pool;
+        self.rebuild_free_pool();
+        let next_free = plan.order.len() as u32 + 1;
+        self.free_keys.clear();
+        self.free_keys.push(Reverse(FreeKeyRange{start: next_free, run: KEY_MAXCOUNT as u32 - 1 - next_free}));
+        self.last_disk_key_index = next_free;
+        self.key_count = plan.order.len() as u32;
+        self.clean = true;
+    }
+
     /// a cache entry doesn't actually know it's own name -- it's the key associated with the cache entry
     /// so you must provide it to create the full record. It also doesn't know the name of its containing basis.
     pub(crate) fn to_dict_attributes(&self, name: &str, basis_name: &str) -> DictAttributes {
@@ -1040,81 +1230,6 @@ pub(crate) fn small_storage_base_vaddr_from_indices(dict_index: NonZeroU32, base
     SMALL_POOL_START + (dict_index.get()-1) as u64 * DICT_VSIZE + base_index as u64 * SMALL_CAPACITY as u64
 }
 
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
-#[repr(C, align(8))]
-pub struct DictName {
-    pub len: u8,
-    pub data: [u8; DICT_NAME_LEN - 1],
-}
-impl DictName {
-    pub fn try_from_str(name: &str) -> Result<DictName> {
-        let mut alloc = [0u8; DICT_NAME_LEN - 1];
-        let bytes = name.as_bytes();
-        if bytes.len() > (DICT_NAME_LEN - 1) {
-            Err(Error::new(ErrorKind::InvalidInput, "dict name is too long"))
-        } else {
-            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
-                *dst = src;
-            }
-            Ok(DictName {
-                len: bytes.len() as u8, // this as checked above to be short enough
-                data: alloc,
-            })
-        }
-    }
-}
-impl Default for DictName {
-    fn default() -> DictName {
-        DictName {
-            len: 0,
-            data: [0; DICT_NAME_LEN - 1]
-        }
-    }
-}
-
-#[derive(Debug)]
-/// On-disk representation of the dictionary header. This structure is mainly for archival/unarchival
-/// purposes. To "functionalize" a stored disk entry, it needs to be deserialized into a DictionaryCacheEntry.
-#[repr(C, align(8))]
-pub(crate) struct Dictionary {
-    /// Reserved for flags on the record entry
-    pub(crate) flags: DictFlags,
-    /// Access count to the dicitionary
-    pub(crate) age: u32,
-    /// Number of keys in the dictionary
-    pub(crate) num_keys: u32,
-    /// Free index starting space. While this is a derived parameter, its value is recorded to avoid
-    /// an expensive, long search operation during the creation of a dictionary cache record. 0 is an invalid index,
-    /// as this is where the header goes. Maybe this should be a NonZeroU32.
-    pub(crate) free_key_index: u32,
-    /// Name. Length should pad out the record to exactly 127 bytes.
-    pub(crate) name: DictName,
-}
-impl Default for Dictionary {
-    fn default() -> Dictionary {
-        let mut flags = DictFlags(0);
-        flags.set_valid(true);
-        Dictionary { flags, age: 0, num_keys: 0, free_key_index: 1, name: DictName::default() }
-    }
-}
-impl Deref for Dictionary {
-    type Target = [u8];
-    fn deref(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(self as *const Dictionary as *const u8, core::mem::size_of::<Dictionary>())
-                as &[u8]
-        }
-    }
-}
-impl DerefMut for Dictionary {
-    fn deref_mut(&mut self) -> &mut [u8] {
-        unsafe {
-            core::slice::from_raw_parts_mut(self as *mut Dictionary as *mut u8, core::mem::size_of::<Dictionary>())
-                as &mut [u8]
-        }
-    }
-}
-
 #[allow(dead_code)]
 #[derive(Debug)]
 pub(crate) struct DictAttributes {
@@ -1203,6 +1318,14 @@ impl PartialEq for FreeKeyRange {
     }
 }
 
+/// The target layout of a dictionary compaction.
+pub(crate) struct CompactionPlan {
+    /// valid keys, in order of their current descriptor index. A key's new index is its position in this list, plus one.
+    pub(crate) order: Vec<String>,
+    /// small keys, grouped by the pool they are packed into. The pool's index is its position in this list.
+    pub(crate) pools: Vec<Vec<String>>,
+}
+
 /// stashed copy of a decrypted page. The copy here must always match
 /// what's actually on disk; do not mutate it and expect it to sync with the disk.
 /// Remember to invalidate this if the data are
@@ -1383,4 +1506,48 @@ mod tests {
         assert!(f[0].0.start == 8);
         assert!(f[0].0.run == init_max - 7);
 }
+    #[test]
+    fn test_compaction_plan() {
+        let mut d = DictCacheEntry::new(
+            Dictionary::default(),
+            1,
+            &Vec::<u8>::new(),
+        );
+        let mut add_key = |name: &str, index: u32, start: u64, reserved: u64, valid: bool| {
+            let mut flags = KeyFlags(0);
+            flags.set_valid(valid);
+            d.keys.insert(name.to_string(), KeyCacheEntry {
+                start,
+                len: reserved,
+                reserved,
+                flags,
+                age: 0,
+                atime: 0,
+                descriptor_index: NonZeroU32::new(index).unwrap(),
+                clean: true,
+                data: None,
+            });
+        };
+        let pool = |index: usize| small_storage_base_vaddr_from_indices(NonZeroU32::new(1).unwrap(), index);
+        add_key("a", 5, pool(3), 3000, true);
+        add_key("b", 2, pool(7), 2000, true);
+        add_key("c", 40, pool(1), 1000, true);
+        add_key("d", 9, LARGE_POOL_START, 8192, true);
+        add_key("e", 3, pool(0), 100, false);
+
+        let plan = d.compaction_plan();
+        assert!(plan.order == vec!["b", "a", "d", "c"]);
+        assert!(plan.pools == vec![vec!["a", "c"], vec!["b"]]);
+        // pool 1 is still in use, so the packed pools have to be staged above the highest clash
+        assert!(d.pool_staging_base(plan.pools.len()) == Some(4));
+        assert!(d.pool_staging_base(1) == Some(0));
+
+        d.compaction_commit(&plan);
+        assert!(d.keys.len() == 4);
+        assert!(d.key_count == 4);
+        assert!(d.last_disk_key_index == 5);
+        assert!(d.small_pool.len() == 2);
+        assert!(d.small_pool[0].avail == (SMALL_CAPACITY - 4000) as u16);
+        assert!(d.get_free_key_index().unwrap().get() == 5);
+    }
 }
@@ -0,0 +1,437 @@
+//! A consistency checker for the PDDB.
+//!
+//! The checker only trusts what is on disk: it walks the raw page table of every open Basis, authenticates
+//! every page the Basis maps, and then re-derives the set of pages that *should* be mapped by reading the
+//! dictionary headers and key descriptors directly, without going through the dictionary caches (which
+//! stop scanning as soon as the header counts are satisfied, and thus can't see past a corrupted count).
+//! Locked Basis are invisible to it, so it can't tell if a page claimed by a locked Basis is also on the free
+//! space list; unlock all the Basis before running a check if that matters.
+//!
+//! Problems are either only reported (`FsckMode::Check`), or fixed. Fixing follows a single rule: anything that
+//! can't be reached from a healthy structure is released (`Repair`) or set aside into the quarantine region
+//! (`Quarantine`). Damaged keys are dropped by blanking their descriptors, which in turn orphans their pages.
+
+use crate::api::*;
+use super::*;
+
+use core::num::NonZeroU32;
+use core::ops::{Deref, DerefMut};
+use core::mem::size_of;
+use std::collections::{HashMap, HashSet};
+
+fn in_quarantine(vaddr: VirtAddr) -> bool {
+    vaddr.get() >= QUARANTINE_START && vaddr.get() < QUARANTINE_END
+}
+
+/// A key descriptor as found on disk.
+struct DiskKey {
+    index: u32,
+    /// None if the name doesn't decode
+    name: Option<String>,
+    start: u64,
+    len: u64,
+    reserved: u64,
+    age: u32,
+}
+
+/// Decodes a length-prefixed name field, returning None if the length or the contents are bogus.
+fn decode_name(data: &[u8], len: u8) -> Option<String> {
+    if len as usize > data.len() {
+        return None;
+    }
+    std::str::from_utf8(&data[..len as usize]).ok().map(|s| s.to_string())
+}
+
+/// Checks a single basis. `owners` tracks which basis maps every physical page seen so far, so that pages
+/// claimed by more than one basis are caught. The caller must sync the basis before, and drop its dictionary
+/// cache after a repair, as the repairs are done directly on disk.
+pub(crate) fn fsck_basis(hw: &mut PddbOs, basis: &mut BasisCacheEntry, mode: FsckMode, report: &mut FsckReport,
+    owners: &mut HashMap::<PhysAddr, String>) {
+    let repair = mode != FsckMode::Check;
+    report.bases += 1;
+    report.pages += basis.v2p_map.len() as u32;
+
+    // 1. page table. If a virtual page has several entries, the mount resolved it to one of them; the others
+    // are left over from an interrupted update and would otherwise never be reclaimed.
+    let mut pt_key = [0u8; AES_KEYSIZE];
+    pt_key.copy_from_slice(basis.pt_key.as_slice());
+    let mut stale = Vec::<(VirtAddr, PhysAddr)>::new();
+    for (vaddr, page) in hw.pt_scan_raw(&pt_key) {
+        if basis.v2p_map.get(&vaddr).map_or(false, |pp| pp.page_number() == page) {
+            if let Some(other) = owners.insert(page, basis.name.to_string()) {
+                log::warn!("fsck: page {} is mapped by both {} and {}", page, other, basis.name);
+                report.double_mapped += 1;
+            }
+        } else {
+            log::warn!("fsck: {}: stale PTE maps va {:x} to page {}", basis.name, vaddr, page);
+            report.double_mapped += 1;
+            stale.push((vaddr, page));
+        }
+    }
+
+    // 2. authenticate every mapped page
+    let mut unreadable = HashSet::<VirtAddr>::new();
+    for (&vaddr, pp) in basis.v2p_map.iter() {
+        if in_quarantine(vaddr) {
+            report.quarantined += 1;
+            continue;
+        }
+        let readable = if vaddr.get() == VPAGE_SIZE as u64 {
+            // the basis root is encrypted with a key commitment
+            hw.data_decrypt_page_with_commit(basis.key.as_slice(), &basis.aad, pp).is_some()
+        } else {
+            hw.data_decrypt_page(&basis.cipher, &basis.aad, pp).is_some()
+        };
+        if !readable {
+            log::warn!("fsck: {}: va {:x} (page {}) does not authenticate", basis.name, vaddr, pp.page_number());
+            report.bad_pages += 1;
+            unreadable.insert(vaddr);
+        }
+    }
+
+    // 3. walk the dictionaries, noting every page that is reachable from a healthy structure
+    let mut reachable = HashSet::<VirtAddr>::new();
+    reachable.insert(VirtAddr::new(VPAGE_SIZE as u64).unwrap());
+    let mut dict_names = HashSet::<String>::new();
+    let mut dict_count = 0;
+    for dict_index in 1..=DICT_MAXCOUNT as u32 {
+        let dict_vaddr = VirtAddr::new(dict_index as u64 * DICT_VSIZE).unwrap();
+        let pp = match basis.v2p_map.get(&dict_vaddr) {
+            Some(pp) if !unreadable.contains(&dict_vaddr) => pp,
+            _ => continue,
+        };
+        let page = match hw.data_decrypt_page(&basis.cipher, &basis.aad, pp) {
+            Some(page) => page,
+            None => continue,
+        };
+        let mut dict = Dictionary::default();
+        for (&src, dst) in page[size_of::<JournalType>()..].iter().zip(dict.deref_mut().iter_mut()) {
+            *dst = src;
+        }
+        if !dict.flags.valid() {
+            // the header of a deleted dictionary; its pages are orphans
+            continue;
+        }
+        match decode_name(&dict.name.data, dict.name.len) {
+            Some(name) if dict_names.insert(name.to_string()) => {
+                dict_count += 1;
+                fsck_dict(hw, basis, mode, report, &unreadable, &mut reachable, dict_index, dict, &name);
+            }
+            Some(name) => {
+                log::warn!("fsck: {}: dictionary {} appears twice; ignoring the copy at index {}", basis.name, name, dict_index);
+                report.bad_dicts += 1;
+            }
+            None => {
+                log::warn!("fsck: {}: dictionary at index {} has a bogus name", basis.name, dict_index);
+                report.bad_dicts += 1;
+            }
+        }
+    }
+    if dict_count != basis.num_dicts {
+        log::warn!("fsck: {}: basis root records {} dictionaries, found {}", basis.name, basis.num_dicts, dict_count);
+        report.bad_dicts += 1;
+        if repair {
+            basis.num_dicts = dict_count;
+            basis.clean = false;
+            report.repaired += 1;
+        }
+    }
+
+    // 4. everything that is mapped but not reachable is an orphan. A transaction journal is only left behind
+    // if its replay failed on mount; it is kept so the replay can be tried again.
+    let mut orphans = Vec::<VirtAddr>::new();
+    for &vaddr in basis.v2p_map.keys() {
+        if !reachable.contains(&vaddr) && !in_quarantine(vaddr) && !in_txn_journal(vaddr) {
+            log::warn!("fsck: {}: orphaned page at va {:x}", basis.name, vaddr);
+            report.orphaned += 1;
+            orphans.push(vaddr);
+        }
+    }
+    if !repair {
+        return;
+    }
+
+    // 5. release or quarantine the stale and orphaned pages
+    let mut next_quarantine = QUARANTINE_START;
+    for (_, page) in stale {
+        let mut pp = PhysPage(0);
+        pp.set_page_number(page);
+        pp.set_valid(true);
+        pp.set_space_state(SpaceState::Used);
+        if mode == FsckMode::Quarantine {
+            // only the PTE has to change: the data isn't bound to its virtual address
+            pp.set_clean(false);
+            next_quarantine = quarantine_insert(basis, next_quarantine, pp);
+            report.quarantined += 1;
+        } else {
+            scrub_page(hw, &mut pp);
+        }
+        report.repaired += 1;
+    }
+    for vaddr in orphans {
+        if mode == FsckMode::Quarantine {
+            let mut pp = basis.v2p_map.remove(&vaddr).expect("orphan was mapped");
+            pp.set_clean(false);
+            next_quarantine = quarantine_insert(basis, next_quarantine, pp);
+            report.quarantined += 1;
+        } else {
+            retire_page(hw, &mut basis.v2p_map, vaddr);
+        }
+        report.repaired += 1;
+    }
+    basis.basis_sync(hw);
+    basis.pt_sync(hw);
+}
+
+/// Maps `pp` to the first free page of the quarantine region at or above `from`, returning the address
+/// to continue the search from. The mapping is committed on the next `pt_sync()`.
+fn quarantine_insert(basis: &mut BasisCacheEntry, from: u64, pp: PhysPage) -> u64 {
+    let mut vaddr = from;
+    while basis.v2p_map.contains_key(&VirtAddr::new(vaddr).unwrap()) {
+        vaddr += VPAGE_SIZE as u64;
+    }
+    log::info!("fsck: {}: quarantining page {} at va {:x}", basis.name, pp.page_number(), vaddr);
+    basis.v2p_map.insert(VirtAddr::new(vaddr).unwrap(), pp);
+    vaddr + VPAGE_SIZE as u64
+}
+
+/// Checks one dictionary: its descriptor pages, the consistency of each key descriptor, and the
+/// header counts. In the repair modes, damaged keys are blanked and the header is corrected.
+fn fsck_dict(hw: &mut PddbOs, basis: &BasisCacheEntry, mode: FsckMode, report: &mut FsckReport,
+    unreadable: &HashSet::<VirtAddr>, reachable: &mut HashSet::<VirtAddr>, dict_index: u32, mut dict: Dictionary, name: &str) {
+    let repair = mode != FsckMode::Check;
+    let index = NonZeroU32::new(dict_index).unwrap();
+    let dict_base = dict_index as u64 * DICT_VSIZE;
+    let small_base = small_storage_base_vaddr_from_indices(index, 0);
+    let small_end = small_base + SMALL_POOL_STRIDE;
+    let compacting = dict.flags.compacting();
+    if compacting {
+        // the compaction will resolve duplicates, and staged pools are deliberately unreferenced: claim the whole
+        // region, and don't touch anything until the compaction has been completed.
+        log::warn!("fsck: {}: {} has an unfinished compaction; run a compaction, then check it again", basis.name, name);
+        for &vaddr in basis.v2p_map.keys() {
+            if (vaddr.get() >= dict_base && vaddr.get() < dict_base + DICT_VSIZE) || (vaddr.get() >= small_base && vaddr.get() < small_end) {
+                reachable.insert(vaddr);
+            }
+        }
+    }
+    let readable = |vaddr: &VirtAddr| basis.v2p_map.contains_key(vaddr) && !unreadable.contains(vaddr);
+
+    // 1. read in every descriptor
+    let mut keys = Vec::<DiskKey>::new();
+    for vpage in 0..(DICT_VSIZE / VPAGE_SIZE as u64) as usize {
+        let vaddr = VirtAddr::new(dict_base + (vpage * VPAGE_SIZE) as u64).unwrap();
+        if !basis.v2p_map.contains_key(&vaddr) {
+            continue;
+        }
+        if !repair || readable(&vaddr) {
+            reachable.insert(vaddr);
+        }
+        let page = match basis.v2p_map.get(&vaddr).and_then(|pp| hw.data_decrypt_page(&basis.cipher, &basis.aad, pp)) {
+            Some(page) => page,
+            None => continue,
+        };
+        for slot in 0..DK_PER_VPAGE {
+            let key_index = vpage * DK_PER_VPAGE + slot;
+            if key_index == 0 {
+                continue; // the dictionary header
+            }
+            let mut kd = KeyDescriptor::default();
+            let start = size_of::<JournalType>() + slot * DK_STRIDE;
+            for (&src, dst) in page[start..start + DK_STRIDE].iter().zip(kd.deref_mut().iter_mut()) {
+                *dst = src;
+            }
+            if kd.flags.valid() {
+                keys.push(DiskKey {
+                    index: key_index as u32,
+                    name: decode_name(&kd.name.data, kd.name.len),
+                    start: kd.start,
+                    len: kd.len,
+                    reserved: kd.reserved,
+                    age: kd.age,
+                });
+            }
+        }
+    }
+
+    // 2. check each descriptor on its own, and against the others
+    let mut bad = HashSet::<u32>::new();
+    let mut by_name = HashMap::<String, (u32, u32)>::new();
+    let mut pools = HashMap::<u64, Vec<(u64, u64, u32)>>::new();
+    let mut large_claims = HashMap::<u64, u32>::new();
+    for key in keys.iter() {
+        let kname = match &key.name {
+            Some(n) => n.to_string(),
+            None => {
+                log::warn!("fsck: {}: {} has a descriptor with a bogus name at {}", basis.name, name, key.index);
+                bad.insert(key.index);
+                continue;
+            }
+        };
+        if !compacting {
+            if let Some(&(other_index, other_age)) = by_name.get(&kname) {
+                // keep the more recently updated copy
+                log::warn!("fsck: {}: {}:{} has two descriptors, at {} and {}", basis.name, name, kname, other_index, key.index);
+                if key.age > other_age {
+                    bad.insert(other_index);
+                } else {
+                    bad.insert(key.index);
+                    continue;
+                }
+            }
+            by_name.insert(kname.to_string(), (key.index, key.age));
+        }
+        if key.len > key.reserved {
+            log::warn!("fsck: {}: {}:{} is longer than its reservation", basis.name, name, kname);
+            bad.insert(key.index);
+        } else if key.start >= LARGE_POOL_START {
+            if key.reserved > LARGE_FILE_MAX_SIZE {
+                log::warn!("fsck: {}: {}:{} has an impossible reservation", basis.name, name, kname);
+                bad.insert(key.index);
+                continue;
+            }
+            if key.len > 0 {
+                for vpage in key.start / VPAGE_SIZE as u64..=(key.start + key.len - 1) / VPAGE_SIZE as u64 {
+                    if !readable(&VirtAddr::new(vpage * VPAGE_SIZE as u64).unwrap()) {
+                        log::warn!("fsck: {}: {}:{} has lost data at va {:x}", basis.name, name, kname, vpage * VPAGE_SIZE as u64);
+                        bad.insert(key.index);
+                    }
+                }
+            }
+            if key.reserved > 0 {
+                for vpage in key.start / VPAGE_SIZE as u64..=(key.start + key.reserved - 1) / VPAGE_SIZE as u64 {
+                    if let Some(other) = large_claims.insert(vpage, key.index) {
+                        if other != key.index {
+                            log::warn!("fsck: {}: {}: keys at {} and {} share va {:x}", basis.name, name, other, key.index, vpage * VPAGE_SIZE as u64);
+                            bad.insert(other);
+                            bad.insert(key.index);
+                        }
+                    }
+                }
+            }
+        } else if key.start >= small_base && key.start + key.reserved <= small_end {
+            let pool_vaddr = small_base + (key.start - small_base) / SMALL_CAPACITY as u64 * SMALL_CAPACITY as u64;
+            if key.start - pool_vaddr + key.reserved > SMALL_CAPACITY as u64 {
+                log::warn!("fsck: {}: {}:{} straddles two small pools", basis.name, name, kname);
+                bad.insert(key.index);
+            } else if !readable(&VirtAddr::new(pool_vaddr).unwrap()) {
+                log::warn!("fsck: {}: {}:{} has lost its small pool at va {:x}", basis.name, name, kname, pool_vaddr);
+                bad.insert(key.index);
+            } else {
+                pools.entry(pool_vaddr).or_insert_with(Vec::new).push((key.start, key.start + key.reserved, key.index));
+            }
+        } else {
+            log::warn!("fsck: {}: {}:{} points outside of its dictionary's storage: {:x}", basis.name, name, kname, key.start);
+            bad.insert(key.index);
+        }
+    }
+    for extents in pools.values_mut() {
+        extents.sort();
+        for pair in extents.windows(2) {
+            if pair[1].0 < pair[0].1 {
+                log::warn!("fsck: {}: {}: keys at {} and {} overlap in the small pool", basis.name, name, pair[0].2, pair[1].2);
+                bad.insert(pair[0].2);
+                bad.insert(pair[1].2);
+            }
+        }
+    }
+    if compacting {
+        // report, but don't act: the compaction may legitimately have left the data in any of these states
+        report.bad_keys += bad.len() as u32;
+        bad.clear();
+    }
+
+    // 3. note the data pages that remain in use. Small pools stay mapped once they are emptied, so any pool
+    // page that is intact belongs to the dictionary, whether or not a key points into it.
+    for &vaddr in basis.v2p_map.keys() {
+        if vaddr.get() >= small_base && vaddr.get() < small_end && readable(&vaddr) {
+            reachable.insert(vaddr);
+        }
+    }
+    for key in keys.iter() {
+        if repair && bad.contains(&key.index) {
+            continue;
+        }
+        if key.start >= LARGE_POOL_START {
+            if key.reserved > 0 && key.reserved <= LARGE_FILE_MAX_SIZE {
+                for vpage in key.start / VPAGE_SIZE as u64..=(key.start + key.reserved - 1) / VPAGE_SIZE as u64 {
+                    let vaddr = VirtAddr::new(vpage * VPAGE_SIZE as u64).unwrap();
+                    if !repair || readable(&vaddr) {
+                        reachable.insert(vaddr);
+                    }
+                }
+            }
+        } else if key.start >= small_base && key.start < small_end {
+            let pool_vaddr = small_base + (key.start - small_base) / SMALL_CAPACITY as u64 * SMALL_CAPACITY as u64;
+            reachable.insert(VirtAddr::new(pool_vaddr).unwrap());
+        }
+    }
+
+    // 4. header consistency
+    report.bad_keys += bad.len() as u32;
+    let mut header_ok = true;
+    if dict.num_keys as usize != keys.len() && !compacting {
+        log::warn!("fsck: {}: {} records {} keys, found {}", basis.name, name, dict.num_keys, keys.len());
+        header_ok = false;
+    }
+    let max_index = keys.iter().map(|k| k.index).max().unwrap_or(0);
+    if dict.free_key_index <= max_index {
+        log::warn!("fsck: {}: {} has its free key index {} below key {}", basis.name, name, dict.free_key_index, max_index);
+        header_ok = false;
+    }
+    if !header_ok {
+        report.bad_dicts += 1;
+    }
+    if !repair || (header_ok && bad.is_empty()) {
+        return;
+    }
+
+    // 5. blank the damaged descriptors, then fix up the header
+    for &key_index in bad.iter() {
+        let vaddr = VirtAddr::new(dict_base + (key_index as usize / DK_PER_VPAGE * VPAGE_SIZE) as u64).unwrap();
+        let slot = key_index as usize % DK_PER_VPAGE;
+        patch_page(hw, basis, vaddr, |data| {
+            for b in data[slot * DK_STRIDE..(slot + 1) * DK_STRIDE].iter_mut() {
+                *b = 0;
+            }
+        });
+        report.repaired += 1;
+    }
+    dict.num_keys = (keys.len() - bad.len()) as u32;
+    dict.free_key_index = dict.free_key_index.max(max_index + 1);
+    dict.age = dict.age.saturating_add(1);
+    if patch_page(hw, basis, VirtAddr::new(dict_base).unwrap(), |data| {
+        for (&src, dst) in dict.deref().iter().zip(data.iter_mut()) {
+            *dst = src;
+        }
+    }) {
+        report.repaired += 1;
+    }
+}
+
+/// Decrypts the page at `vaddr`, hands the data (below the journal) to `patch`, and writes it back in place,
+/// the same way `dict_sync()` updates its pages. Returns false if the page isn't mapped or doesn't decrypt.
+fn patch_page<F: FnOnce(&mut [u8])>(hw: &mut PddbOs, basis: &BasisCacheEntry, vaddr: VirtAddr, patch: F) -> bool {
+    if let Some(pp) = basis.v2p_map.get(&vaddr) {
+        if let Some(mut page) = hw.data_decrypt_page(&basis.cipher, &basis.aad, pp) {
+            patch(&mut page[size_of::<JournalType>()..]);
+            hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut page, pp);
+            return true;
+        }
+    }
+    false
+}
+
+/// Checks the FastSpace free list against the pages mapped by the open Basis. A page that is both mapped and
+/// free would be handed out again and overwritten. Returns true if the free list has to be regenerated.
+pub(crate) fn fsck_fast_space(hw: &PddbOs, owners: &HashMap::<PhysAddr, String>, report: &mut FsckReport) -> bool {
+    let mut conflicts = 0;
+    for page in hw.fast_space_free_pages() {
+        if let Some(owner) = owners.get(&page) {
+            log::warn!("fsck: page {} is on the free space list, but is mapped by {}", page, owner);
+            conflicts += 1;
+        }
+    }
+    report.double_mapped += conflicts;
+    conflicts > 0
+}
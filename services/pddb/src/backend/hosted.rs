@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 use crate::api::*;
+use spinor::SPINOR_ERASE_SIZE;
 
 use std::sync::Once;
 use std::mem::MaybeUninit;
@@ -19,6 +20,50 @@ use std::io::SeekFrom;
 struct FlashSingleton {
     memory: Vec::<u8>,
     disk: File,
+    /// number of patch/erase operations since the power loss emulation was last armed
+    writes: usize,
+    /// length of each of those operations, in order
+    write_sizes: Vec::<usize>,
+    /// if set, the contents of the flash are captured as this power loss would have left them
+    power_loss_at: Option<PowerLoss>,
+    power_loss_image: Option<Vec::<u8>>,
+}
+impl FlashSingleton {
+    /// Called before every write of `data` at `offset` into `memory`, to emulate a power loss that stops
+    /// the flash before, or part way through it.
+    fn count_write(&mut self, offset: usize, data: &[u8]) {
+        match self.power_loss_at {
+            Some(fault) if fault.at == self.writes => {
+                let mut image = self.memory.clone();
+                if let Some(landed) = fault.torn {
+                    // the spinor erases every sector under a patch, then programs them back in order, with the
+                    // original bytes around the patch. So the sectors read back as erased from where the
+                    // programming stopped to their end, even past the end of the patch.
+                    let landed = landed.min(data.len());
+                    let sector = SPINOR_ERASE_SIZE as usize;
+                    let end = ((offset + data.len() + sector - 1) / sector * sector).min(image.len());
+                    image[offset..offset + landed].copy_from_slice(&data[..landed]);
+                    for b in image[offset + landed..end].iter_mut() {
+                        *b = 0xFF;
+                    }
+                }
+                self.power_loss_image = Some(image);
+            }
+            _ => {}
+        }
+        self.writes += 1;
+        self.write_sizes.push(data.len());
+    }
+}
+
+/// Where an emulated power loss strikes, counted in flash writes (patches and erases).
+#[derive(Copy, Clone, Debug)]
+pub struct PowerLoss {
+    /// index of the write that is interrupted, counting from when the power loss was armed
+    pub at: usize,
+    /// None if the power goes just before the write starts; otherwise, the number of bytes of the write that
+    /// made it to the flash before the power went. The rest of the sectors the write touches read back as erased.
+    pub torn: Option<usize>,
 }
 
 fn flashmem() -> &'static mut FlashSingleton {
@@ -27,11 +72,14 @@ fn flashmem() -> &'static mut FlashSingleton {
 
     unsafe {
         ONCE.call_once(|| {
+            // XOUS_PDDB_IMAGE selects another image, e.g. a `dbg_dump()` snapshot to be checked with fsck.
+            // Note that the image is modified in place.
+            let image = std::env::var("XOUS_PDDB_IMAGE").unwrap_or("../tools/pddb-images/hosted.bin".to_string());
             let mut disk = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open("../tools/pddb-images/hosted.bin")
+            .open(&image)
             .expect("Can't open a PDDB image file for writing");
 
             let mut memory = Vec::<u8>::with_capacity(PDDB_A_LEN);
@@ -56,6 +104,10 @@ fn flashmem() -> &'static mut FlashSingleton {
             let flashmem = FlashSingleton {
                 memory,
                 disk,
+                writes: 0,
+                write_sizes: Vec::new(),
+                power_loss_at: None,
+                power_loss_image: None,
             };
             SINGLETON.write(flashmem);
         });
@@ -95,6 +147,42 @@ impl EmuStorage {
             *b = 0xFF;
         }
     }
+    /// A copy of the whole flash, to be put back with `restore()`.
+    pub fn snapshot(&self) -> Vec::<u8> {
+        flashmem().memory.clone()
+    }
+    /// Replaces the contents of the flash with `image`. Anything cached from the flash, e.g. the FastSpace
+    /// state and the basis caches, has to be reloaded with a fresh mount afterwards.
+    pub fn restore(&mut self, image: &[u8]) {
+        flashmem().memory.copy_from_slice(image);
+        flashmem().disk.seek(SeekFrom::Start(0)).expect("couldn't seek PDDB");
+        flashmem().disk.write(image).expect("couldn't write PDDB");
+    }
+    /// Starts counting writes from zero. If `writes` is Some, the contents of the flash are captured just
+    /// before write number `writes` is done, as a power loss at that point would have left them; the
+    /// capture is retrieved with `power_loss_image()`. Writes carry on as usual, so the code under test
+    /// runs to completion.
+    pub fn power_loss_after(&mut self, writes: Option<usize>) {
+        self.inject_power_loss(writes.map(|at| PowerLoss { at, torn: None }));
+    }
+    /// Like `power_loss_after()`, but the power loss may also tear the write it strikes.
+    pub fn inject_power_loss(&mut self, fault: Option<PowerLoss>) {
+        flashmem().writes = 0;
+        flashmem().write_sizes.clear();
+        flashmem().power_loss_at = fault;
+        flashmem().power_loss_image = None;
+    }
+    /// Number of writes done since the power loss emulation was last armed.
+    pub fn write_count(&self) -> usize {
+        flashmem().writes
+    }
+    /// Length of each of the writes done since the power loss emulation was last armed.
+    pub fn write_sizes(&self) -> Vec::<usize> {
+        flashmem().write_sizes.clone()
+    }
+    pub fn power_loss_image(&mut self) -> Option<Vec::<u8>> {
+        flashmem().power_loss_image.take()
+    }
     pub fn dump_fs(&self, name: &Option<String>) {
         let defaultname = String::from("pddb");
         let rootname = name.as_ref().unwrap_or(&defaultname);
@@ -125,6 +213,7 @@ impl HostedSpinor {
     }
     pub fn patch(&self, _region: &[u8], _region_base: u32, data: &[u8], offset: u32) -> Result<(), xous::Error> {
         // println!("patch at {:x}+{}", offset, data.len());
+        flashmem().count_write(offset as usize, data);
         for (&src, dst) in data.iter().zip(
             flashmem().memory.as_mut_slice()[offset as usize..offset as usize + data.len()].iter_mut()
         ) {
@@ -135,14 +224,15 @@ impl HostedSpinor {
         Ok(())
     }
     pub fn bulk_erase(&self, start: u32, len: u32) -> Result<(), xous::Error> {
-        for b in flashmem().memory.as_mut_slice()[(start - xous::PDDB_LOC) as usize .. (start - xous::PDDB_LOC + len) as usize].iter_mut() {
-            *b = 0xFF;
-        }
-        flashmem().disk.seek(SeekFrom::Start(start as u64)).expect("couldn't seek PDDB");
         let mut blank = Vec::<u8>::with_capacity(len as usize);
         for _ in 0..len {
             blank.push(0xFF);
         }
+        flashmem().count_write((start - xous::PDDB_LOC) as usize, &blank);
+        for b in flashmem().memory.as_mut_slice()[(start - xous::PDDB_LOC) as usize .. (start - xous::PDDB_LOC + len) as usize].iter_mut() {
+            *b = 0xFF;
+        }
+        flashmem().disk.seek(SeekFrom::Start(start as u64)).expect("couldn't seek PDDB");
         flashmem().disk.write(&blank).expect("couldn't write PDDB");
         Ok(())
     }
@@ -30,21 +30,18 @@ use zeroize::Zeroize;
 #[cfg(feature="migration1")]
 use crate::backend::migration1to2::*;
 
-/// Implementation-specific PDDB structures: for Precursor/Xous OS pair
-pub(crate) const MBBB_PAGES: usize = 10;
-pub(crate) const FSCB_PAGES: usize = 16;
-
-/// size of a physical page
-pub const PAGE_SIZE: usize = spinor::SPINOR_ERASE_SIZE as usize;
-/// size of a virtual page -- after the AES encryption and journaling overhead is subtracted
-pub const VPAGE_SIZE: usize = PAGE_SIZE - size_of::<Nonce>() - size_of::<Tag>() - size_of::<JournalType>();
-
-/// length of the ciphertext in an AES-GCM-SIV page with key commitments
-/// equal to the total plaintext to be encrypted, including the journal number
-/// does not include the MAC overhead
-pub const KCOM_CT_LEN: usize = 4004;
+pub const PDDB_SIZE_PAGES: usize = PDDB_A_LEN as usize / PAGE_SIZE;
+/// This structure is mapped into the top of FLASH memory, starting at
+/// xous::PDDB_LOC. This actually slightly over-sizes the page table,
+/// because the page table does not map the locations for the page table
+/// itself, the MBBB, or the FSCB. However, the 0th entry of the page table
+/// always corresponds to the base of data in FLASH, which means the excess
+/// pages are going to be toward the high end of the page table range.
+#[repr(C, packed)]
+pub(crate) struct PageTableInFlash {
+    table: [Pte; PDDB_SIZE_PAGES],
+}
 
-pub(crate) const WRAPPED_AES_KEYSIZE: usize = AES_KEYSIZE + 8;
 const SCD_VERSION: u32 = 2;
 
 #[cfg(all(feature="pddbtest", feature="autobasis"))]
@@ -158,6 +155,8 @@ pub(crate) struct PddbOs {
 
 impl PddbOs {
     pub fn new(trngpool: Rc<RefCell<TrngPool>>, pw_cid: xous::CID) -> PddbOs {
+        // the on-disk format fixes the size of a page; it has to match what the flash erases at once
+        assert!(PAGE_SIZE == spinor::SPINOR_ERASE_SIZE as usize, "PAGE_SIZE does not match the flash erase size");
         let xns = xous_names::XousNames::new().unwrap();
         #[cfg(any(target_os = "none", target_os = "xous"))]
         let pddb = xous::syscall::map_memory(
@@ -410,7 +409,7 @@ impl PddbOs {
     /// a *page number* (so a physical address divided by the page size). It's a slightly awkward units, but
     /// it saves a bit of math going back and forth between the native storage formats of the records.
     pub(crate) fn pt_patch_mapping(&self, va: VirtAddr, phys_page_num: u32, cipher: &Aes256) {
-        let mut pte = Pte::new(va, PtFlags::CLEAN, Rc::clone(&self.entropy));
+        let mut pte = Pte::new(va, PtFlags::CLEAN, self.entropy.borrow_mut().get_u32());
         let mut block = Block::from_mut_slice(pte.deref_mut());
         //log::info!("pte pt: {:x?}", block);
         cipher.encrypt_block(&mut block);
@@ -531,6 +530,30 @@ impl PddbOs {
             None
         }
     }
+    /// Returns every page table entry that decrypts under `pt_key`, as (virtual address, physical page number) pairs.
+    /// Unlike `pt_scan_key()`, no attempt is made to resolve entries that map the same virtual address: those are
+    /// exactly what the consistency checker is looking for.
+    pub(crate) fn pt_scan_raw(&self, pt_key: &[u8; AES_KEYSIZE]) -> Vec<(VirtAddr, PhysAddr)> {
+        let cipher = Aes256::new(&GenericArray::from_slice(pt_key));
+        let pt = self.pt_as_slice();
+        let mut entries = Vec::<(VirtAddr, PhysAddr)>::new();
+        let blank = [0xffu8; aes::BLOCK_SIZE];
+        for (page_index, pt_page) in pt.chunks(PAGE_SIZE).enumerate() {
+            let clean_page = if pt_page[..aes::BLOCK_SIZE] == blank {
+                self.mbbb_retrieve().unwrap_or(pt_page)
+            } else {
+                pt_page
+            };
+            for (index, candidate) in clean_page.chunks(aes::BLOCK_SIZE).enumerate() {
+                let mut block = Block::clone_from_slice(candidate);
+                cipher.decrypt_block(&mut block);
+                if let Some(pte) = Pte::try_from_slice(block.as_slice()) {
+                    entries.push((pte.vaddr(), ((page_index * PAGE_SIZE / aes::BLOCK_SIZE) + index) as PhysAddr));
+                }
+            }
+        }
+        entries
+    }
     /// Pages drawn from disk might already have come from the FSCB. We need to make the journal number
     /// of these consistent with those in the FSCB so later on when they are retired we don't have journal conflicts.
     fn resolve_pp_journal(&self, pp: &mut PhysPage) {
@@ -991,6 +1014,13 @@ impl PddbOs {
     pub fn fast_space_len(&self) -> usize {
         self.fspace_cache.len()
     }
+    /// returns the page numbers that the fspace cache considers available for allocation
+    pub(crate) fn fast_space_free_pages(&self) -> Vec::<PhysAddr> {
+        self.fspace_cache.iter()
+            .filter(|pp| pp.space_state() == SpaceState::Free || pp.space_state() == SpaceState::Dirty)
+            .map(|pp| pp.page_number())
+            .collect()
+    }
     /// Normally, the fspace_log_next_addr is just incremented, but when it hits the end of the
     /// page, it's set to None. This function will do a modestly expensive scan of the FSCB area
     /// to try and either find another partially filled page, or a completely empty page.
@@ -1176,37 +1206,8 @@ impl PddbOs {
             if !has_pages {
                 log::warn!("FastSpace alloc forced by lack of free space");
                 // if we're really out of space, do an expensive full-space sweep
-                if let Some(used_pages) = self.pddb_generate_used_map(cache) {
-                    let free_pool = self.fast_space_generate(used_pages);
-                    if free_pool.len() == 0 {
-                        // we're out of free space
-                        false
-                    } else {
-                        let mut fast_space = FastSpace {
-                            free_pool: [PhysPage(0); FASTSPACE_FREE_POOL_LEN],
-                        };
-                        for pp in fast_space.free_pool.iter_mut() {
-                            pp.set_journal(self.trng_u8() % FSCB_JOURNAL_RAND_RANGE)
-                        }
-                        for (&src, dst) in free_pool.iter().zip(fast_space.free_pool.iter_mut()) {
-                            *dst = src;
-                        }
-                        // write just commits a new record to disk, but doesn't update our internal data cache
-                        // this also clears the fast space log.
-                        self.fast_space_write(&fast_space);
-                        // this will ensure the data cache is fully in sync
-                        self.fast_space_read();
-
-                        // check that we have enough space now -- if not, we're just out of disk space!
-                        if self.fast_space_len() > pages {
-                            true
-                        } else {
-                            false
-                        }
-                    }
-                } else {
-                    false
-                }
+                // check that we have enough space now -- if not, we're just out of disk space!
+                self.fast_space_rebuild(cache) && self.fast_space_len() > pages
             } else {
                 // log regenration is faster & less intrusive than fastspace regeneration, and we would have
                 // to do this more often. So we have a separate path for this outcome.
@@ -1233,6 +1234,36 @@ impl PddbOs {
         }
     }
 
+    /// Does an expensive full-space sweep of all the Basis to regenerate the FastSpace record from scratch.
+    /// Returns false if the sweep couldn't be done, or if there is no free space left at all.
+    pub(crate) fn fast_space_rebuild(&mut self, cache: &Vec::<BasisCacheEntry>) -> bool {
+        if let Some(used_pages) = self.pddb_generate_used_map(cache) {
+            let free_pool = self.fast_space_generate(used_pages);
+            if free_pool.len() == 0 {
+                // we're out of free space
+                false
+            } else {
+                let mut fast_space = FastSpace {
+                    free_pool: [PhysPage(0); FASTSPACE_FREE_POOL_LEN],
+                };
+                for pp in fast_space.free_pool.iter_mut() {
+                    pp.set_journal(self.trng_u8() % FSCB_JOURNAL_RAND_RANGE)
+                }
+                for (&src, dst) in free_pool.iter().zip(fast_space.free_pool.iter_mut()) {
+                    *dst = src;
+                }
+                // write just commits a new record to disk, but doesn't update our internal data cache
+                // this also clears the fast space log.
+                self.fast_space_write(&fast_space);
+                // this will ensure the data cache is fully in sync
+                self.fast_space_read();
+                true
+            }
+        } else {
+            false
+        }
+    }
+
     pub(crate) fn data_aad(&self, name: &str) -> Vec::<u8> {
         let mut aad = Vec::<u8>::new();
         aad.extend_from_slice(&name.as_bytes());
@@ -1732,6 +1763,7 @@ impl PddbOs {
             name: BasisRootName::try_from_str(PDDB_DEFAULT_SYSTEM_BASIS).unwrap(),
             age: 0,
             num_dictionaries: 0,
+            kdf: KdfRecord::default(),
         };
 
         // step 7. Create a hashmap for our reverse PTE, allocate sectors, and add it to the Pddb's cache
@@ -1963,7 +1995,7 @@ impl PddbOs {
                         let mut block = Block::clone_from_slice(enc_pte);
                         ciphers.pt_ecb.decrypt_block(&mut block);
                         if let Some(mut pte) = Pte::try_from_slice(block.as_slice()) {
-                            pte.re_nonce(Rc::clone(&self.entropy));
+                            pte.re_nonce(self.entropy.borrow_mut().get_u32());
                             let mut enc_entry = Block::from_mut_slice(pte.deref_mut());
                             ciphers.pt_ecb.encrypt_block(&mut enc_entry);
                             new_pte.copy_from_slice(enc_entry.as_slice());
@@ -2140,37 +2172,13 @@ impl PddbOs {
             {
                 Ok(bname) => {
                     let name = bname.first().as_str().to_string();
-                    let request = BasisRequestPassword {
-                        db_name: xous_ipc::String::from_str(name.to_string()),
-                        plaintext_pw: None,
-                    };
-                    let mut buf = Buffer::into_buf(request).unwrap();
-                    buf.lend_mut(self.pw_cid, PwManagerOpcode::RequestPassword.to_u32().unwrap()).unwrap();
-                    let retpass = buf.to_original::<BasisRequestPassword, _>().unwrap();
-                    // 2. validate the name/password combo
-                    let basis_key = self.basis_derive_key(
+                    let retpass = self.request_password(&name);
+                    // 2. validate the name/password combo, by finding the root block of the basis.
+                    // if the root page decrypts, we accept the password; no further checking is done.
+                    let maybe_entry = self.basis_probe_keys(
                         &name,
-                        retpass.plaintext_pw.unwrap().as_str().unwrap()
-                    );
-                    // validate the password by finding the root block of the basis. We rely entirely
-                    // upon the AEAD with key commit to ensure the password is correct.
-                    let maybe_entry = if let Some(basis_map) =
-                    self.pt_scan_key(&basis_key.pt, &basis_key.data, &name) {
-                        let aad = self.data_aad(&name);
-                        if let Some(root_page) = basis_map.get(&VirtAddr::new(VPAGE_SIZE as u64).unwrap()) {
-                            match self.data_decrypt_page_with_commit(&basis_key.data, &aad, root_page) {
-                                Some(_data) => {
-                                    // if the root page decrypts, we accept the password; no further checking is done.
-                                    Some((basis_key, name.to_string()))
-                                },
-                                None => None,
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
+                        retpass.unwrap().as_str().unwrap()
+                    ).map(|(basis_key, _kdf, _map)| (basis_key, name.to_string()));
                     // 3. add to the Aes256 return vec
                     if let Some((basis_key, name)) = maybe_entry {
                         ret.push((basis_key, name));
@@ -2224,12 +2232,11 @@ impl PddbOs {
         }
     }
 
-    /// Derives a 256-bit AES encryption key for a basis given a basis name and its password.
-    /// You will also need to derive the AAD for the basis using the basis_name.
-    pub(crate) fn basis_derive_key(&self, basis_name: &str, password: &str) -> BasisKeys {
+    /// Derives a 256-bit AES encryption key for a basis given a basis name, its password and the KDF it is
+    /// keyed with. You will also need to derive the AAD for the basis using the basis_name.
+    pub(crate) fn basis_derive_key(&self, basis_name: &str, password: &str, kdf: BasisKdf) -> BasisKeys {
         use sha2::{FallbackStrategy, Sha512Trunc256};
         use digest::Digest;
-        use backend::bcrypt::*;
 
         // 1. derive the salt from the "key" region. First step is to create the salt lookup
         // table, which is done by hashing the name and password together with SHA-512
@@ -2246,7 +2253,7 @@ impl PddbOs {
 
         log::info!("creating salt");
         // uses Sha512Trunc256 on the salt array to generate a compressed version of
-        // the basis name and plaintext password, which forms the Salt that is fed into bcrypt
+        // the basis name and plaintext password, which forms the Salt that is fed into the KDF
         // our salt is probably way too big but what else are we going to use all that page's data for?
         let scd = self.static_crypto_data_get();
         let mut salt = [0u8; 16];
@@ -2261,20 +2268,20 @@ impl PddbOs {
         #[cfg(feature="hazardous-debug")]
         log::info!("derived salt: {:x?}", salt);
 
-        // 3. use the salt + password and run bcrypt on it to derive a key.
-        let mut hashed_password: [u8; 24] = [0; 24];
+        // 3. use the salt + password and run the KDF on it to derive a key.
+        let mut hashed_password = [0u8; KDF_OUTPUT_LEN];
         let start_time = self.timestamp_now();
-        bcrypt(BCRYPT_COST, &salt, password, &mut hashed_password); // note: this internally makes a copy of the password, and destroys it
+        let hash_len = kdf.hash_password(&salt, password, &mut hashed_password);
         let elapsed = self.timestamp_now() - start_time;
-        log::info!("derived bcrypt password in {}ms", elapsed);
+        log::info!("derived {:?} password in {}ms", kdf, elapsed);
 
-        // 4. take the resulting 24-byte password and expand it to 2x 32 byte keys using HKDF.
+        // 4. take the resulting password hash and expand it to 2x 32 byte keys using HKDF.
         // one key is for the AES-256 ECB-encoded page tables, one key is for the AES-GCM-SIV data pages
-        let hkpt = hkdf::Hkdf::<sha2::Sha256>::new(Some(&scd.salt_base[..32]), &hashed_password);
+        let hkpt = hkdf::Hkdf::<sha2::Sha256>::new(Some(&scd.salt_base[..32]), &hashed_password[..hash_len]);
         let mut okm_pt = [0u8; 32];
         hkpt.expand(b"pddb page table key", &mut okm_pt).expect("invalid length specified for HKDF");
 
-        let hkdt = hkdf::Hkdf::<sha2::Sha256>::new(Some(&scd.salt_base[..32]), &hashed_password);
+        let hkdt = hkdf::Hkdf::<sha2::Sha256>::new(Some(&scd.salt_base[..32]), &hashed_password[..hash_len]);
         let mut okm_data = [0u8; 32];
         hkdt.expand(b"pddb data key", &mut okm_data).expect("invalid length specified for HKDF");
 
@@ -2288,6 +2295,10 @@ impl PddbOs {
         for i in 0..plaintext_pw.len() {
             unsafe{pt_ptr.add(i).write_volatile(core::mem::zeroed());}
         }
+        let hp_ptr = hashed_password.as_mut_ptr();
+        for i in 0..hashed_password.len() {
+            unsafe{hp_ptr.add(i).write_volatile(core::mem::zeroed());}
+        }
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
 
         #[cfg(feature="hazardous-debug")]
@@ -2302,6 +2313,95 @@ impl PddbOs {
         }
     }
 
+    /// Finds the KDF a basis is keyed with, by deriving its keys with each of the KDFs in `KDF_PROBE_ORDER`
+    /// in turn, until they map a root page that authenticates. Returns the keys, the KDF, and the page
+    /// table of the basis. Note that a bad password costs a run of every KDF before it is turned down.
+    pub(crate) fn basis_probe_keys(&self, basis_name: &str, password: &str) -> Option<(BasisKeys, BasisKdf, HashMap::<VirtAddr, PhysPage>)> {
+        let aad = self.data_aad(basis_name);
+        for &kdf in KDF_PROBE_ORDER.iter() {
+            let basis_key = self.basis_derive_key(basis_name, password, kdf);
+            if let Some(basis_map) = self.pt_scan_key(&basis_key.pt, &basis_key.data, basis_name) {
+                // We rely entirely upon the AEAD with key commit to ensure the password is correct.
+                if let Some(root_page) = basis_map.get(&VirtAddr::new(VPAGE_SIZE as u64).unwrap()) {
+                    if self.data_decrypt_page_with_commit(&basis_key.data, &aad, root_page).is_some() {
+                        return Some((basis_key, kdf, basis_map));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn request_password(&self, basis_name: &str) -> Option<xous_ipc::String::<PASSWORD_LEN>> {
+        let request = BasisRequestPassword {
+            db_name: xous_ipc::String::from_str(basis_name),
+            plaintext_pw: None,
+        };
+        let mut buf = Buffer::into_buf(request).unwrap();
+        buf.lend_mut(self.pw_cid, PwManagerOpcode::RequestPassword.to_u32().unwrap()).unwrap();
+        buf.to_original::<BasisRequestPassword, _>().unwrap().plaintext_pw
+    }
+
+    /// Changes the password of the basis that is open in `entry`, and re-keys it with `BasisKdf::DEFAULT`.
+    /// This is also how a basis that is keyed with an older KDF is moved over to the current one. The user
+    /// is asked for the current password, which has to match the keys the basis is open with, and then
+    /// for the new one. Returns the new keys, which the basis has to be re-mounted with.
+    ///
+    /// Every page of the basis is re-encrypted in place, and its page table entries are re-written under the
+    /// new key, so the basis has to be synced beforehand, and a power loss part way through loses the pages
+    /// that were caught in the middle. Someone with images of the PDDB from before and after the change can
+    /// tell which pages belong to the basis, and so how big it is; a `Churn` afterwards covers that up.
+    pub(crate) fn basis_change_pass(&mut self, entry: &BasisCacheEntry) -> std::result::Result<BasisKeys, PddbRekeyOp> {
+        let xns = xous_names::XousNames::new().unwrap();
+        let modals = modals::Modals::new(&xns).unwrap();
+
+        modals.show_notification(t!("pddb.changepass.current", xous::LANG), None).ok();
+        let current = self.request_password(&entry.name).ok_or(PddbRekeyOp::UserAbort)?;
+        match self.basis_probe_keys(&entry.name, current.as_str().unwrap_or("UTF8 error")) {
+            Some((keys, _kdf, _map))
+            if bool::from(keys.pt[..].ct_eq(&entry.pt_key[..]) & keys.data[..].ct_eq(&entry.key[..])) => (),
+            _ => {
+                modals.show_notification(t!("pddb.freespace.badpass", xous::LANG), None).ok();
+                return Err(PddbRekeyOp::AuthFail);
+            }
+        }
+        modals.show_notification(t!("pddb.changepass.new", xous::LANG), None).ok();
+        let new_pw = self.request_password(&entry.name).ok_or(PddbRekeyOp::UserAbort)?;
+        let new_keys = self.basis_derive_key(&entry.name, new_pw.as_str().unwrap_or("UTF8 error"), BasisKdf::DEFAULT);
+        let new_pt = Aes256::new(GenericArray::from_slice(&new_keys.pt));
+        let new_data = AesGcmSiv::<Aes256>::new(Key::from_slice(&new_keys.data));
+
+        let root_va = VirtAddr::new(VPAGE_SIZE as u64).unwrap();
+        modals.start_progress(t!("pddb.changepass.running", xous::LANG), 0, entry.v2p_map.len() as u32, 0).ok();
+        for (index, (&va, pp)) in entry.v2p_map.iter().enumerate() {
+            if va == root_va {
+                // the root record is key-committed, and it's where the new KDF is recorded
+                if let Some(mut data) = self.data_decrypt_page_with_commit(&entry.key, &entry.aad, pp) {
+                    let mut basis_root = BasisRoot::default();
+                    for (&src, dst) in data[size_of::<JournalType>()..].iter().zip(basis_root.deref_mut().iter_mut()) {
+                        *dst = src;
+                    }
+                    basis_root.kdf = BasisKdf::DEFAULT.to_record();
+                    for (&src, dst) in basis_root.deref().iter().zip(data[size_of::<JournalType>()..].iter_mut()) {
+                        *dst = src;
+                    }
+                    self.data_encrypt_and_patch_page_with_commit(&new_keys.data, &entry.aad, &mut data, pp);
+                } else {
+                    log::error!("root page of basis {} failed to decrypt. The basis is now lost.", entry.name);
+                }
+            } else if let Some(mut data) = self.data_decrypt_page(&entry.cipher, &entry.aad, pp) {
+                self.data_encrypt_and_patch_page(&new_data, &entry.aad, &mut data, pp);
+            } else {
+                log::warn!("Page number {} failed to decrypt. This data is now lost.", pp.page_number());
+            }
+            self.pt_patch_mapping(va, pp.page_number(), &new_pt);
+            modals.update_progress(index as u32 + 1).ok();
+        }
+        modals.finish_progress().ok();
+        log::info!("basis {} re-keyed with {:?}", entry.name, BasisKdf::DEFAULT);
+        Ok(new_keys)
+    }
+
     pub(crate) fn reset_dont_ask_init(&self) {
         self.rootkeys.do_reset_dont_ask_init();
     }
@@ -2335,12 +2435,12 @@ impl PddbOs {
                         self, &name, &name, BasisRetentionPolicy::Persist);
 
                     if maybe_basis.is_some() {
-                        cache.basis_add(maybe_basis.unwrap());
+                        cache.basis_add(self, maybe_basis.unwrap());
                     } else {
                         cache.basis_create(self, &name, &name).expect("couldn't create basis");
                         let basis = cache.basis_unlock(self, &name, &name, BasisRetentionPolicy::Persist)
                             .expect("couldn't open just created basis");
-                        cache.basis_add(basis);
+                        cache.basis_add(self, basis);
                     }
                 } else {
                     let blist = cache.basis_list();
@@ -2379,7 +2479,8 @@ impl PddbOs {
             if !exists {
                 let basis_key = self.basis_derive_key(
                     name,
-                    name
+                    name,
+                    BasisKdf::DEFAULT
                 );
                 ret.push((basis_key, name.to_string()));
             }
@@ -2396,7 +2497,8 @@ impl PddbOs {
         for name in self.testnames.iter() {
             let basis_key = self.basis_derive_key(
                 name,
-                name
+                name,
+                BasisKdf::DEFAULT
             );
             let mut basis_name = [0 as u8; 64];
             for (&src, dst) in name.as_bytes().iter().zip(basis_name.iter_mut()) {
@@ -2422,7 +2524,6 @@ impl PddbOs {
     pub(crate) fn basis_derive_key_v00_00_01_01(&self, basis_name: &str, password: &str, scd: &StaticCryptoDataV1) -> [u8; AES_KEYSIZE] {
         use sha2::{FallbackStrategy, Sha512Trunc256};
         use digest::Digest;
-        use backend::bcrypt::*;
 
         // 1. derive the salt from the "key" region. First step is to create the salt lookup
         // table, which is done by hashing the name and password together with SHA-512
@@ -2585,7 +2686,7 @@ impl PddbOs {
 
                     // *** 3. re-encrypt the PTE and the target page to the v2 keys and corrected addressing scheme
                     // a deconstructed pt_patch_mapping() call -- because the normal call would insert a MBBB block, which is not what we want in this case.
-                    let mut pte = Pte::new(pte.vaddr_v1(), PtFlags::CLEAN, Rc::clone(&self.entropy));
+                    let mut pte = Pte::new(pte.vaddr_v1(), PtFlags::CLEAN, self.entropy.borrow_mut().get_u32());
                     let mut pt_block = Block::from_mut_slice(pte.deref_mut());
                     cipher_pt_v2.encrypt_block(&mut pt_block);
                     self.patch_pagetable_raw(&pt_block, pp.page_number() * aes::BLOCK_SIZE as u32);
@@ -2743,7 +2844,8 @@ impl PddbOs {
                                             let basis_aad_v2 = self.data_aad(bname.first().as_str());
                                             let basis_pt_cipher_v1 = Aes256::new(GenericArray::from_slice(&basis_key_v1));
                                             let basis_data_cipher_v1 = AesGcmSiv::<Aes256>::new(Key::from_slice(&basis_key_v1));
-                                            let basis_keys = self.basis_derive_key(bname.first().as_str(), pw.as_str().unwrap_or("UTF8 error"));
+                                            // migrated bases keep the KDF they were made with
+                                            let basis_keys = self.basis_derive_key(bname.first().as_str(), pw.as_str().unwrap_or("UTF8 error"), BasisKdf::Bcrypt);
                                             let basis_pt_cipher_2 = Aes256::new(GenericArray::from_slice(&basis_keys.pt));
                                             let basis_data_cipher_2 = AesGcmSiv::<Aes256>::new(Key::from_slice(&basis_keys.data));
                                             // perform the migration
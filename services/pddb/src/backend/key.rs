@@ -2,89 +2,9 @@ use crate::api::*;
 use super::*;
 
 use std::num::NonZeroU32;
-use core::ops::{Deref, DerefMut};
 use std::cmp::Ordering;
-use std::io::{Result, Error, ErrorKind};
-
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
-#[repr(C, align(8))]
-pub struct KeyName {
-    pub len: u8,
-    pub data: [u8; KEY_NAME_LEN - 1],
-}
-impl KeyName {
-    pub fn try_from_str(name: &str) -> Result<KeyName> {
-        let mut alloc = [0u8; KEY_NAME_LEN - 1];
-        let bytes = name.as_bytes();
-        if bytes.len() > (KEY_NAME_LEN - 1) {
-            Err(Error::new(ErrorKind::InvalidInput, "key name is too long"))
-        } else {
-            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
-                *dst = src;
-            }
-            Ok(KeyName {
-                len: bytes.len() as u8, // this as checked above to be short enough
-                data: alloc,
-            })
-        }
-    }
-}
-impl Default for KeyName {
-    fn default() -> KeyName {
-        KeyName {
-            len: 0,
-            data: [0; KEY_NAME_LEN - 1]
-        }
-    }
-}
-
-/// On-disk representation of the Key. Note that the storage on disk is mis-aligned relative
-/// to Rust's expecatation of in-RAM format, so any deserialization must essentially come with
-/// a copy step to re-align the record to meet Rust's placement rules.
-#[repr(C, align(8))]
-pub(crate) struct KeyDescriptor {
-    /// virtual address of the key's start
-    pub(crate) start: u64,
-    /// length of the key's stored data
-    pub(crate) len: u64,
-    /// amount of space reserved for the key. Must be >= len.
-    pub(crate) reserved: u64,
-    /// Reserved for flags on the record entry
-    pub(crate) flags: KeyFlags,
-    /// Access count to the key
-    pub(crate) age: u32,
-    /// Name. Length should pad out the record to exactly 127 bytes.
-    pub(crate) name: KeyName,
-}
-impl Default for KeyDescriptor {
-    fn default() -> Self {
-        KeyDescriptor {
-            start: 0,
-            len: 0,
-            reserved: 0,
-            flags: KeyFlags(0),
-            age: 0,
-            name: KeyName::default(),
-        }
-    }
-}
-impl Deref for KeyDescriptor {
-    type Target = [u8];
-    fn deref(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(self as *const KeyDescriptor as *const u8, core::mem::size_of::<KeyDescriptor>())
-                as &[u8]
-        }
-    }
-}
-impl DerefMut for KeyDescriptor {
-    fn deref_mut(&mut self) -> &mut [u8] {
-        unsafe {
-            core::slice::from_raw_parts_mut(self as *mut KeyDescriptor as *mut u8, core::mem::size_of::<KeyDescriptor>())
-                as &mut [u8]
-        }
-    }
-}
+use std::collections::HashMap;
+use zeroize::Zeroize;
 
 /// In-RAM representation of a key. This file defines the storage for the KeyCacheEntry; most of the structure
 /// manipulations happen inside `dictionary.rs`, in part because to locate a Key in absolute memory space you need
@@ -112,11 +32,14 @@ pub(crate) struct KeyCacheEntry {
 }
 impl KeyCacheEntry {
     /// Given a base offset of the dictionary containing the key, compute the starting VirtAddr of the key itself.
+    /// Descriptors don't straddle vpages: there are DK_PER_VPAGE of them per vpage, and the tail of each vpage is unused.
     pub(crate) fn descriptor_vaddr(&self, dict_offset: VirtAddr) -> VirtAddr {
-        VirtAddr::new(dict_offset.get() + ((self.descriptor_index.get() as u64) * DK_STRIDE as u64)).unwrap()
+        VirtAddr::new(dict_offset.get()
+            + (self.descriptor_vpage_num() * VPAGE_SIZE) as u64
+            + (self.descriptor_modulus() * DK_STRIDE) as u64
+        ).unwrap()
     }
     /// Computes the modular position of the KeyDescriptor within a vpage.
-    #[allow(dead_code)] // I feel like we should have been calling this /somewhere/ at some point in time, but I probably just re-wrote the math long-hand.
     pub(crate) fn descriptor_modulus(&self) -> usize {
         (self.descriptor_index.get() as usize) % (VPAGE_SIZE / DK_STRIDE)
     }
@@ -142,19 +65,26 @@ impl KeyCacheEntry {
             None => 0,
             Some(kcd) => match kcd {
                 KeyCacheData::Small(ksd) => ksd.data.len(),
-                KeyCacheData::Large(kld) => kld.data.len(),
+                KeyCacheData::Large(kld) => kld.size(),
             }
         };
         core::mem::size_of::<KeyCacheEntry>() + data_size
     }
     pub(crate) fn atime(&self) -> u64 { self.atime }
+    /// Drops any cached copy of the key's data, overwriting the plaintext before the memory is released.
+    pub(crate) fn scrub_data(&mut self) {
+        if let Some(KeyCacheData::Small(ksd)) = self.data.as_mut() {
+            ksd.data.zeroize();
+        }
+        // large cache pages zeroize themselves when dropped
+        self.data = None;
+    }
     pub(crate) fn set_atime(&mut self, atime: u64) { self.atime = atime; }
 }
 
 pub (crate) enum KeyCacheData {
     Small(KeySmallData),
     // the "Medium" type has a region reserved for it, but we haven't coded a handler for it.
-    #[allow(dead_code)] // Large data caching isn't implemented, so of course, we don't ever create this type
     Large(KeyLargeData),
 }
 /// Small data is optimized for low overhead, and always represent a complete copy of the data.
@@ -162,14 +92,59 @@ pub(crate) struct KeySmallData {
     pub clean: bool,
     pub(crate) data: Vec::<u8>,
 }
-/// This can hold just a portion of a large key's data. For now, we now essentially manually
-/// encode a sub-slice in parts, but, later on we could get more clever and start to cache
-/// multiple disjoint portions of a large key's data...
-#[allow(dead_code)] // large key caching is not yet implemented
+/// Holds decrypted copies of some of the vpages of a large key, keyed by their virtual address. Writes to large
+/// keys go straight through to disk, so this is a pure read cache: it's always clean, and a write simply
+/// invalidates the pages it touches. At most LARGE_CACHE_PAGES are held per key, and the least recently used
+/// page is dropped to make room for a new one; across keys, the budget is enforced by `cache_prune()`.
 pub(crate) struct KeyLargeData {
     pub clean: bool,
-    pub(crate) start: u64,
-    pub(crate) data: Vec::<u8>,
+    pages: HashMap::<u64, LargeCachePage>,
+    /// monotonic counter used to order page accesses
+    tick: u64,
+}
+impl KeyLargeData {
+    pub(crate) fn new() -> KeyLargeData {
+        KeyLargeData {
+            clean: true,
+            pages: HashMap::new(),
+            tick: 0,
+        }
+    }
+    /// Returns the cached page at `vaddr` (including the journal on top), and marks it as most recently used.
+    pub(crate) fn get(&mut self, vaddr: u64) -> Option<&Vec::<u8>> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.pages.get_mut(&vaddr).map(|page| {
+            page.atime = tick;
+            &page.data
+        })
+    }
+    pub(crate) fn insert(&mut self, vaddr: u64, data: Vec::<u8>) {
+        while self.pages.len() >= LARGE_CACHE_PAGES {
+            let lru = *self.pages.iter().min_by_key(|(_, page)| page.atime).expect("cache is not empty").0;
+            self.pages.remove(&lru);
+        }
+        self.tick += 1;
+        self.pages.insert(vaddr, LargeCachePage { data, atime: self.tick });
+    }
+    /// Drops every cached page that overlaps the virtual address range `start..end`.
+    pub(crate) fn invalidate(&mut self, start: u64, end: u64) {
+        self.pages.retain(|&vaddr, _| vaddr + VPAGE_SIZE as u64 <= start || vaddr >= end);
+    }
+    pub(crate) fn size(&self) -> usize {
+        self.pages.values().map(|page| page.data.len()).sum()
+    }
+}
+/// Plaintext is scrubbed from the heap as soon as it leaves the cache, whether by eviction, invalidation,
+/// pruning or unmounting of the basis.
+struct LargeCachePage {
+    data: Vec::<u8>,
+    atime: u64,
+}
+impl Drop for LargeCachePage {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
 }
 
 /// A storage pool for data that is strictly smaller than one VPAGE. These element are serialized
@@ -182,6 +157,9 @@ pub(crate) struct KeySmallPool {
     /// keeps track of the available space within the pool, avoiding an expensive lookup every time we want to query the available space
     pub(crate) avail: u16,
     pub(crate) clean: bool,
+    /// when set, the next sync fills the unused space of the pool with noise instead of zeroes. Set by a
+    /// paranoid key removal, so that the slot freed by the key does not retain a copy of its data.
+    pub(crate) scrub: bool,
 }
 impl KeySmallPool {
     pub(crate) fn new() -> KeySmallPool {
@@ -189,6 +167,7 @@ impl KeySmallPool {
             contents: Vec::<String>::new(),
             avail: SMALL_CAPACITY as u16,
             clean: false,
+            scrub: false,
         }
     }
 }
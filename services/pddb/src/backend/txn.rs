@@ -0,0 +1,266 @@
+//! Journaled multi-key transactions.
+//!
+//! A transaction is staged in RAM by the server, and only touches the disk when it is committed. The commit
+//! first writes every operation, with its data, into a journal in the basis' own virtual memory space, and
+//! then applies the operations through the regular key update path. The journal consists of body pages,
+//! which hold the serialized operations, and a header page that describes them. The header is written
+//! last: once its PTE is on disk the transaction is committed, and the operations are replayed on the next
+//! mount if power is lost before they have all been applied. Body pages without a header are the remains of
+//! a commit that didn't complete, and are discarded.
+//!
+//! Replaying is idempotent, because the journal only records whole-key writes and removals: a key that was
+//! already updated when power was lost is simply written again.
+
+use crate::api::*;
+use super::*;
+
+use core::convert::TryInto;
+use core::mem::size_of;
+use std::io::{Error, ErrorKind, Result};
+
+const TXN_JOURNAL_END: u64 = QUARANTINE_START;
+const TXN_MAGIC: [u8; 4] = *b"TxnJ";
+/// bytes of the operation stream carried by each body page, after its serial number and index
+const TXN_BODY_LEN: usize = VPAGE_SIZE - size_of::<u64>() - size_of::<u32>();
+/// Upper bound on the data staged by one transaction. Transactions are held in the server's RAM until
+/// they are committed, so they are meant for small, related records and not for bulk data.
+pub(crate) const TXN_MAX_STAGED: usize = 256 * 1024;
+
+const TXN_OP_WRITE: u8 = 1;
+const TXN_OP_DELETE: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TxnOp {
+    /// replaces the contents of the key, creating the key and its dictionary if necessary
+    Write { dict: String, key: String, data: Vec<u8> },
+    /// removes the key; it is not an error if it doesn't exist
+    Delete { dict: String, key: String },
+}
+
+/// A transaction that has been started by a client, but not yet committed or aborted.
+pub(crate) struct Transaction {
+    pub(crate) basis: Option<String>,
+    pub(crate) ops: Vec<TxnOp>,
+    staged: usize,
+}
+impl Transaction {
+    pub(crate) fn new(basis: Option<String>) -> Self {
+        Transaction { basis, ops: Vec::new(), staged: 0 }
+    }
+    /// Stages a write. Data that doesn't fit in a single message is sent in chunks: a chunk with a non-zero
+    /// `offset` extends the previous write, which has to be to the same key.
+    pub(crate) fn write(&mut self, dict: &str, key: &str, offset: usize, data: &[u8]) -> Result<()> {
+        if self.staged + data.len() > TXN_MAX_STAGED {
+            return Err(Error::new(ErrorKind::OutOfMemory, "transaction is too large"));
+        }
+        if offset == 0 {
+            self.ops.push(TxnOp::Write { dict: dict.to_string(), key: key.to_string(), data: data.to_vec() });
+        } else {
+            match self.ops.last_mut() {
+                Some(TxnOp::Write { dict: d, key: k, data: staged }) if d == dict && k == key && staged.len() == offset => {
+                    staged.extend_from_slice(data);
+                }
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "write chunk does not continue the previous write")),
+            }
+        }
+        self.staged += data.len();
+        Ok(())
+    }
+    pub(crate) fn delete(&mut self, dict: &str, key: &str) {
+        self.ops.push(TxnOp::Delete { dict: dict.to_string(), key: key.to_string() });
+    }
+}
+
+/// Number of pages taken up by the journal of a `len` byte operation stream, including the header.
+pub(crate) fn txn_journal_pages(len: usize) -> usize {
+    1 + (len + TXN_BODY_LEN - 1) / TXN_BODY_LEN
+}
+
+fn txn_vaddr(page: usize) -> VirtAddr {
+    VirtAddr::new(TXN_JOURNAL_START + (page * VPAGE_SIZE) as u64).unwrap()
+}
+pub(crate) fn in_txn_journal(vaddr: VirtAddr) -> bool {
+    vaddr.get() >= TXN_JOURNAL_START && vaddr.get() < TXN_JOURNAL_END
+}
+
+/// The operation stream: for each operation, its type, the length-prefixed dict and key names, and for
+/// writes, the length-prefixed data.
+pub(crate) fn txn_serialize(ops: &[TxnOp]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for op in ops {
+        let (code, dict, key) = match op {
+            TxnOp::Write { dict, key, .. } => (TXN_OP_WRITE, dict, key),
+            TxnOp::Delete { dict, key } => (TXN_OP_DELETE, dict, key),
+        };
+        stream.push(code);
+        stream.push(dict.len() as u8);
+        stream.extend_from_slice(dict.as_bytes());
+        stream.push(key.len() as u8);
+        stream.extend_from_slice(key.as_bytes());
+        if let TxnOp::Write { data, .. } = op {
+            stream.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            stream.extend_from_slice(data);
+        }
+    }
+    stream
+}
+
+fn txn_deserialize(stream: &[u8]) -> Option<Vec<TxnOp>> {
+    fn take<'a>(stream: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+        let field = stream.get(*pos..*pos + len)?;
+        *pos += len;
+        Some(field)
+    }
+    fn take_name(stream: &[u8], pos: &mut usize) -> Option<String> {
+        let len = take(stream, pos, 1)?[0] as usize;
+        std::str::from_utf8(take(stream, pos, len)?).ok().map(|s| s.to_string())
+    }
+    let mut ops = Vec::new();
+    let mut pos = 0;
+    while pos < stream.len() {
+        let code = take(stream, &mut pos, 1)?[0];
+        let dict = take_name(stream, &mut pos)?;
+        let key = take_name(stream, &mut pos)?;
+        match code {
+            TXN_OP_WRITE => {
+                let len = u32::from_le_bytes(take(stream, &mut pos, size_of::<u32>())?.try_into().unwrap()) as usize;
+                let data = take(stream, &mut pos, len)?.to_vec();
+                ops.push(TxnOp::Write { dict, key, data });
+            }
+            TXN_OP_DELETE => ops.push(TxnOp::Delete { dict, key }),
+            _ => return None,
+        }
+    }
+    Some(ops)
+}
+
+/// Header of a journal, stored at `TXN_JOURNAL_START`.
+struct TxnHeader {
+    /// ties the body pages to this header, so pages left over from another commit can't be mixed in
+    serial: u64,
+    num_ops: u32,
+    len: u32,
+    pages: u32,
+}
+impl TxnHeader {
+    fn encode(&self, page: &mut [u8]) {
+        let mut pos = size_of::<JournalType>();
+        for field in [&TXN_MAGIC[..], &self.serial.to_le_bytes(), &self.num_ops.to_le_bytes(),
+            &self.len.to_le_bytes(), &self.pages.to_le_bytes()].iter() {
+            page[pos..pos + field.len()].copy_from_slice(field);
+            pos += field.len();
+        }
+    }
+    fn decode(page: &[u8]) -> Option<TxnHeader> {
+        let p = &page[size_of::<JournalType>()..];
+        if p[..4] != TXN_MAGIC {
+            return None;
+        }
+        Some(TxnHeader {
+            serial: u64::from_le_bytes(p[4..12].try_into().unwrap()),
+            num_ops: u32::from_le_bytes(p[12..16].try_into().unwrap()),
+            len: u32::from_le_bytes(p[16..20].try_into().unwrap()),
+            pages: u32::from_le_bytes(p[20..24].try_into().unwrap()),
+        })
+    }
+}
+
+/// True if any part of a journal is mapped, i.e. a commit was interrupted.
+pub(crate) fn txn_journal_present(basis: &BasisCacheEntry) -> bool {
+    basis.v2p_map.keys().any(|&vaddr| in_txn_journal(vaddr))
+}
+
+/// Encrypts `page` into a freshly allocated page, and maps it at `vaddr`. The PTE is only written by
+/// the next `pt_sync()`.
+fn txn_write_page(hw: &mut PddbOs, basis: &mut BasisCacheEntry, vaddr: VirtAddr, page: &mut [u8]) -> Result<()> {
+    for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(page[..size_of::<JournalType>()].iter_mut()) {
+        *dst = src;
+    }
+    let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space for the transaction journal"))?;
+    pp.set_valid(true);
+    hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, page, &pp);
+    if let Some(mut old_pp) = basis.v2p_map.insert(vaddr, pp) {
+        scrub_page(hw, &mut old_pp);
+    }
+    Ok(())
+}
+
+/// Writes the journal for `stream`, which holds `num_ops` operations. When this returns successfully, the
+/// transaction is committed. The caller has to make sure enough free space is available, and that any
+/// journal already there has been replayed or discarded.
+pub(crate) fn txn_journal_write(hw: &mut PddbOs, basis: &mut BasisCacheEntry, stream: &[u8], num_ops: usize) -> Result<()> {
+    // that journal may hold a committed transaction, so it's never simply overwritten
+    if txn_journal_present(basis) {
+        return Err(Error::new(ErrorKind::Other, "An earlier transaction has yet to be replayed"));
+    }
+    let serial = (hw.trng_u32() as u64) << 32 | hw.trng_u32() as u64;
+    let mut pages = 0;
+    for (index, chunk) in stream.chunks(TXN_BODY_LEN).enumerate() {
+        let mut page = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+        let body = &mut page[size_of::<JournalType>()..];
+        body[..8].copy_from_slice(&serial.to_le_bytes());
+        body[8..12].copy_from_slice(&(index as u32).to_le_bytes());
+        body[12..12 + chunk.len()].copy_from_slice(chunk);
+        txn_write_page(hw, basis, txn_vaddr(index + 1), &mut page)?;
+        pages += 1;
+    }
+    // the body has to be durable before the header can refer to it
+    basis.pt_sync(hw);
+    let mut page = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+    TxnHeader { serial, num_ops: num_ops as u32, len: stream.len() as u32, pages }.encode(&mut page);
+    txn_write_page(hw, basis, txn_vaddr(0), &mut page)?;
+    // this is the commit point
+    basis.pt_sync(hw);
+    log::debug!("txn: {}: journaled {} ops in {} pages", basis.name, num_ops, pages + 1);
+    Ok(())
+}
+
+/// Reads back a committed journal. Returns None if there is no header, or if the journal is damaged.
+pub(crate) fn txn_journal_read(hw: &mut PddbOs, basis: &BasisCacheEntry) -> Option<Vec<TxnOp>> {
+    let header = basis.v2p_map.get(&txn_vaddr(0))
+        .and_then(|pp| hw.data_decrypt_page(&basis.cipher, &basis.aad, pp))
+        .and_then(|page| TxnHeader::decode(&page))?;
+    let mut stream = Vec::<u8>::new();
+    for index in 0..header.pages as usize {
+        let page = match basis.v2p_map.get(&txn_vaddr(index + 1)).and_then(|pp| hw.data_decrypt_page(&basis.cipher, &basis.aad, pp)) {
+            Some(page) => page,
+            None => {
+                log::error!("txn: {}: journal page {} is missing", basis.name, index);
+                return None;
+            }
+        };
+        let body = &page[size_of::<JournalType>()..];
+        if u64::from_le_bytes(body[..8].try_into().unwrap()) != header.serial
+        || u32::from_le_bytes(body[8..12].try_into().unwrap()) != index as u32 {
+            log::error!("txn: {}: journal page {} belongs to another transaction", basis.name, index);
+            return None;
+        }
+        stream.extend_from_slice(&body[12..]);
+    }
+    if stream.len() < header.len as usize {
+        log::error!("txn: {}: journal is shorter than its header records", basis.name);
+        return None;
+    }
+    stream.truncate(header.len as usize);
+    match txn_deserialize(&stream) {
+        Some(ops) if ops.len() == header.num_ops as usize => Some(ops),
+        _ => {
+            log::error!("txn: {}: journal does not decode", basis.name);
+            None
+        }
+    }
+}
+
+/// Releases the journal pages. The header goes first, so an interruption can only ever leave body pages
+/// behind, which are ignored.
+pub(crate) fn txn_journal_retire(hw: &mut PddbOs, basis: &mut BasisCacheEntry) {
+    if !txn_journal_present(basis) {
+        return;
+    }
+    retire_page(hw, &mut basis.v2p_map, txn_vaddr(0));
+    let mut body: Vec<VirtAddr> = basis.v2p_map.keys().filter(|&&vaddr| in_txn_journal(vaddr)).cloned().collect();
+    body.sort();
+    for vaddr in body {
+        retire_page(hw, &mut basis.v2p_map, vaddr);
+    }
+}
@@ -0,0 +1,576 @@
+use crate::*;
+use aes_gcm_siv::{AesGcmSiv, Nonce, Key};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use aes::Aes256;
+use zeroize::Zeroize;
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::io::{Result, Error, ErrorKind};
+
+// Layout of an archive:
+//
+//   header (plaintext, 48 bytes):
+//     magic [8] | version u32 | Argon2id m_cost u32, t_cost u32, p_cost u32 | salt [16] | nonce prefix [7] | 0u8
+//   chunks, until one is marked as the last:
+//     frame u32 (bit 31: last chunk; bits 0-30: length of the sealed chunk) | sealed chunk
+//
+// The chunks are a STREAM construction over AES-GCM-SIV: the nonce of each chunk is the nonce prefix,
+// the chunk's index (u32, BE) and the "last" flag, and every chunk carries the header as associated data.
+// Reordering, dropping or truncating chunks, or altering the header, makes a chunk fail to open.
+//
+// The plaintext of the chunks is a series of records:
+//   RECORD_KEY | basis, dict, key (each a u8 length and UTF-8 bytes) | reserved u64 | len u64 | data [len]
+//   RECORD_END
+// and RECORD_END has to be at the very end of the last chunk.
+
+const ARCHIVE_MAGIC: [u8; 8] = *b"PDDBARC\0";
+const ARCHIVE_VERSION: u32 = 1;
+const HEADER_LEN: usize = 48;
+const NONCE_PREFIX_LEN: usize = 7;
+/// plaintext bytes in every chunk but the last
+const CHUNK_LEN: usize = 4096;
+const TAG_LEN: usize = 16;
+const FRAME_LAST: u32 = 0x8000_0000;
+
+/// Parameters in an archive header are only trusted up to this memory cost (KiB), so a crafted archive
+/// can't make the importer allocate more memory than the device has.
+const ARCHIVE_MAX_M_COST: u32 = 8 * 1024;
+/// Argon2id parameters for new archives. An archive lives off the device, where nothing slows down guessing
+/// its passphrase, so it is keyed at a higher cost than a secret basis (see `ARGON2_M_COST` in
+/// pddb-format's kdf.rs): all the memory an importer trusts, and twice the passes.
+const ARCHIVE_M_COST: u32 = ARCHIVE_MAX_M_COST;
+const ARCHIVE_T_COST: u32 = 10;
+const ARCHIVE_P_COST: u32 = 1;
+
+const RECORD_END: u8 = 0;
+const RECORD_KEY: u8 = 1;
+
+/// What to do when an imported key already exists in its dictionary.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// keep the existing key, and drop the one in the archive
+    Skip,
+    /// replace the existing key with the one in the archive
+    Overwrite,
+    /// keep both: the archived key is stored as `<key>.<n>`, with the first `n` that is free
+    Rename,
+}
+
+/// What an export or an import did.
+#[derive(Debug, Default, Clone)]
+pub struct ArchiveReport {
+    /// keys written to the archive, or to the PDDB
+    pub keys: u32,
+    /// bytes of key data in those keys
+    pub bytes: u64,
+    /// keys dropped on import, because of the conflict policy or because their basis isn't open
+    pub skipped: u32,
+    /// keys stored under a new name on import
+    pub renamed: u32,
+    /// dictionaries or keys left out because the access control list denied this app access to them
+    pub denied: u32,
+}
+
+struct ArchiveHeader {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: [u8; 16],
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+impl ArchiveHeader {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..8].copy_from_slice(&ARCHIVE_MAGIC);
+        header[8..12].copy_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&self.m_cost.to_le_bytes());
+        header[16..20].copy_from_slice(&self.t_cost.to_le_bytes());
+        header[20..24].copy_from_slice(&self.p_cost.to_le_bytes());
+        header[24..40].copy_from_slice(&self.salt);
+        header[40..47].copy_from_slice(&self.nonce_prefix);
+        header
+    }
+    fn from_bytes(header: &[u8; HEADER_LEN]) -> Result<ArchiveHeader> {
+        let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        if header[..8] != ARCHIVE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a PDDB archive"));
+        }
+        if word(8) != ARCHIVE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported PDDB archive version"));
+        }
+        let h = ArchiveHeader {
+            m_cost: word(12),
+            t_cost: word(16),
+            p_cost: word(20),
+            salt: header[24..40].try_into().unwrap(),
+            nonce_prefix: header[40..47].try_into().unwrap(),
+        };
+        if h.m_cost == 0 || h.m_cost > ARCHIVE_MAX_M_COST || h.t_cost == 0 || h.p_cost == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported archive key derivation parameters"));
+        }
+        Ok(h)
+    }
+    fn cipher(&self, passphrase: &str) -> Result<AesGcmSiv<Aes256>> {
+        let mut key = [0u8; 32];
+        let params = argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, Some(key.len()))
+            .or(Err(Error::new(ErrorKind::InvalidData, "invalid archive key derivation parameters")))?;
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+            .or(Err(Error::new(ErrorKind::Other, "archive key derivation failed")))?;
+        let cipher = AesGcmSiv::<Aes256>::new(Key::from_slice(&key));
+        key.zeroize();
+        Ok(cipher)
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], index: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = if last { 1 } else { 0 };
+    nonce
+}
+
+/// Seals whatever is written to it into archive chunks.
+struct SealingWriter<W: Write> {
+    out: W,
+    cipher: AesGcmSiv<Aes256>,
+    header: [u8; HEADER_LEN],
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    index: u32,
+    plain: Vec<u8>,
+}
+impl<W: Write> SealingWriter<W> {
+    fn new(mut out: W, header: &ArchiveHeader, passphrase: &str) -> Result<Self> {
+        let header_bytes = header.to_bytes();
+        out.write_all(&header_bytes)?;
+        Ok(SealingWriter {
+            out,
+            cipher: header.cipher(passphrase)?,
+            header: header_bytes,
+            nonce_prefix: header.nonce_prefix,
+            index: 0,
+            plain: Vec::with_capacity(CHUNK_LEN),
+        })
+    }
+    fn seal(&mut self, len: usize, last: bool) -> Result<()> {
+        let nonce = chunk_nonce(&self.nonce_prefix, self.index, last);
+        let sealed = self.cipher.encrypt(
+            Nonce::from_slice(&nonce),
+            Payload { msg: &self.plain[..len], aad: &self.header }
+        ).or(Err(Error::new(ErrorKind::Other, "couldn't seal archive chunk")))?;
+        self.plain.drain(..len);
+        let frame = sealed.len() as u32 | if last { FRAME_LAST } else { 0 };
+        self.out.write_all(&frame.to_le_bytes())?;
+        self.out.write_all(&sealed)?;
+        self.index = self.index.checked_add(1)
+            .ok_or(Error::new(ErrorKind::Other, "archive too large"))?;
+        Ok(())
+    }
+    /// Ends the record stream and seals the last chunk.
+    fn finish(mut self) -> Result<W> {
+        self.plain.push(RECORD_END);
+        // everything up to the last full chunk was sealed as it came in, so at most one chunk is left
+        while self.plain.len() > CHUNK_LEN {
+            self.seal(CHUNK_LEN, false)?;
+        }
+        self.seal(self.plain.len(), true)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+impl<W: Write> Write for SealingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.plain.extend_from_slice(buf);
+        // a full chunk is only sealed once there is more data after it, so the last chunk is never empty
+        while self.plain.len() > CHUNK_LEN {
+            self.seal(CHUNK_LEN, false)?;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Opens archive chunks, and hands out their plaintext.
+struct OpeningReader<R: Read> {
+    input: R,
+    cipher: AesGcmSiv<Aes256>,
+    header: [u8; HEADER_LEN],
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    index: u32,
+    plain: Vec<u8>,
+    pos: usize,
+    last_seen: bool,
+}
+impl<R: Read> OpeningReader<R> {
+    fn new(mut input: R, passphrase: &str) -> Result<Self> {
+        let mut header_bytes = [0u8; HEADER_LEN];
+        input.read_exact(&mut header_bytes)?;
+        let header = ArchiveHeader::from_bytes(&header_bytes)?;
+        Ok(OpeningReader {
+            input,
+            cipher: header.cipher(passphrase)?,
+            header: header_bytes,
+            nonce_prefix: header.nonce_prefix,
+            index: 0,
+            plain: Vec::new(),
+            pos: 0,
+            last_seen: false,
+        })
+    }
+    /// Returns false once the last chunk has been opened.
+    fn next_chunk(&mut self) -> Result<bool> {
+        if self.last_seen {
+            return Ok(false);
+        }
+        let mut frame = [0u8; 4];
+        self.input.read_exact(&mut frame)?;
+        let frame = u32::from_le_bytes(frame);
+        let last = frame & FRAME_LAST != 0;
+        let len = (frame & !FRAME_LAST) as usize;
+        if len <= TAG_LEN || len > CHUNK_LEN + TAG_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "archive chunk has a bad length"));
+        }
+        let mut sealed = vec![0u8; len];
+        self.input.read_exact(&mut sealed)?;
+        let nonce = chunk_nonce(&self.nonce_prefix, self.index, last);
+        self.plain.zeroize();
+        self.plain = match self.cipher.decrypt(Nonce::from_slice(&nonce), Payload { msg: &sealed, aad: &self.header }) {
+            Ok(plain) => plain,
+            // the first chunk can't tell a bad passphrase from a damaged archive; later ones can
+            Err(_) if self.index == 0 => return Err(Error::new(ErrorKind::PermissionDenied, "wrong passphrase, or the archive is damaged")),
+            Err(_) => return Err(Error::new(ErrorKind::InvalidData, "archive is damaged or was tampered with")),
+        };
+        self.pos = 0;
+        self.last_seen = last;
+        self.index = self.index.checked_add(1)
+            .ok_or(Error::new(ErrorKind::InvalidData, "archive too large"))?;
+        Ok(true)
+    }
+    /// Checks that the end record that was just read is the end of the last chunk.
+    fn finish(mut self) -> Result<()> {
+        let at_end = self.last_seen && self.pos == self.plain.len();
+        self.plain.zeroize();
+        if at_end {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::InvalidData, "archive has data after its end record"))
+        }
+    }
+}
+impl<R: Read> Read for OpeningReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.pos == self.plain.len() {
+            if !self.next_chunk()? {
+                // the record stream ends with RECORD_END, so running out of chunks first means it was cut short
+                return Err(Error::new(ErrorKind::UnexpectedEof, "archive ends in the middle of a record"));
+            }
+        }
+        let len = buf.len().min(self.plain.len() - self.pos);
+        buf[..len].copy_from_slice(&self.plain[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+fn write_name(out: &mut impl Write, name: &str) -> Result<()> {
+    out.write_all(&[name.len() as u8])?;
+    out.write_all(name.as_bytes())
+}
+fn read_name(input: &mut impl Read, max_len: usize) -> Result<String> {
+    let mut len = [0u8];
+    input.read_exact(&mut len)?;
+    if len[0] as usize > max_len - 1 {
+        return Err(Error::new(ErrorKind::InvalidData, "name in archive is too long"));
+    }
+    let mut name = vec![0u8; len[0] as usize];
+    input.read_exact(&mut name)?;
+    String::from_utf8(name).or(Err(Error::new(ErrorKind::InvalidData, "name in archive is not UTF-8")))
+}
+fn read_u64(input: &mut impl Read) -> Result<u64> {
+    let mut word = [0u8; 8];
+    input.read_exact(&mut word)?;
+    Ok(u64::from_le_bytes(word))
+}
+/// Copies exactly `len` bytes from `input` to `out`, through a buffer that is cleared afterwards.
+fn copy_exact(input: &mut impl Read, out: &mut impl Write, len: u64) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(buf.len() as u64) as usize;
+        let read = input.read(&mut buf[..chunk])?;
+        if read == 0 {
+            buf.zeroize();
+            return Err(Error::new(ErrorKind::UnexpectedEof, "key data ended early"));
+        }
+        out.write_all(&buf[..read])?;
+        remaining -= read as u64;
+    }
+    buf.zeroize();
+    Ok(())
+}
+
+impl Pddb {
+    /// Writes the keys of the open bases named in `bases` (all of the open bases if None) to `out`, as an
+    /// archive encrypted with `passphrase`. If `dicts` is given, only those dictionaries are written.
+    ///
+    /// `out` can be anything that takes a stream of bytes, e.g. a file in hosted mode. The archive is written
+    /// as the keys are read, so it never has to fit in memory.
+    ///
+    /// Keys are read with the access rights of the calling app, so dictionaries it isn't allowed to read
+    /// are left out (and counted in `ArchiveReport::denied`) rather than failing the export.
+    pub fn export_archive<W: Write>(&self, out: W, passphrase: &str, bases: Option<&[&str]>, dicts: Option<&[&str]>) -> Result<ArchiveReport> {
+        let basis_list = match bases {
+            Some(bases) => bases.iter().map(|b| b.to_string()).collect(),
+            None => self.list_basis(),
+        };
+        let mut entropy = [0u8; 16 + 8];
+        for word in entropy.chunks_mut(4) {
+            word.copy_from_slice(&self.trng.get_u32().or(Err(Error::new(ErrorKind::Other, "TRNG error")))?.to_le_bytes());
+        }
+        let header = ArchiveHeader {
+            m_cost: ARCHIVE_M_COST,
+            t_cost: ARCHIVE_T_COST,
+            p_cost: ARCHIVE_P_COST,
+            salt: entropy[..16].try_into().unwrap(),
+            nonce_prefix: entropy[16..16 + NONCE_PREFIX_LEN].try_into().unwrap(),
+        };
+        let mut archive = SealingWriter::new(out, &header, passphrase)?;
+        let mut report = ArchiveReport::default();
+        for basis in basis_list.iter() {
+            for dict in self.list_dict(Some(basis))? {
+                if dicts.map_or(false, |d| !d.contains(&dict.as_str())) {
+                    continue;
+                }
+                let keys = match self.list_keys(&dict, Some(basis)) {
+                    Ok(keys) => keys,
+                    Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                        log::warn!("leaving {}:{} out of the archive: access denied", basis, dict);
+                        report.denied += 1;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                for key in keys {
+                    let mut pk = match self.get(&dict, &key, Some(basis), false, false, None, None::<fn()>) {
+                        Ok(pk) => pk,
+                        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                            log::warn!("leaving {}:{}:{} out of the archive: access denied", basis, dict, key);
+                            report.denied += 1;
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let attr = pk.attributes()?;
+                    archive.write_all(&[RECORD_KEY])?;
+                    write_name(&mut archive, basis)?;
+                    write_name(&mut archive, &dict)?;
+                    write_name(&mut archive, &key)?;
+                    archive.write_all(&(attr.reserved as u64).to_le_bytes())?;
+                    archive.write_all(&(attr.len as u64).to_le_bytes())?;
+                    copy_exact(&mut pk, &mut archive, attr.len as u64)?;
+                    report.keys += 1;
+                    report.bytes += attr.len as u64;
+                }
+            }
+        }
+        archive.finish()?;
+        log::info!("exported {:?}", report);
+        Ok(report)
+    }
+
+    /// Reads an archive made by `export_archive()` from `input`, and merges its keys into the PDDB.
+    ///
+    /// Keys go back into the basis they were exported from, which has to be open, or into `into_basis` if
+    /// it is given. Keys that already exist are handled according to `policy`. Keys are written with the
+    /// access rights of the calling app; those it may not write are counted in `ArchiveReport::denied`.
+    ///
+    /// Each chunk of the archive is authenticated before any of it is used, but the archive is read as a
+    /// stream, so if it turns out to be damaged part way through, the keys before the damage have already
+    /// been imported, and an error is returned.
+    pub fn import_archive<R: Read>(&self, input: R, passphrase: &str, into_basis: Option<&str>, policy: ConflictPolicy) -> Result<ArchiveReport> {
+        let mut archive = OpeningReader::new(input, passphrase)?;
+        let open_bases = self.list_basis();
+        let mut report = ArchiveReport::default();
+        loop {
+            let mut tag = [0u8];
+            archive.read_exact(&mut tag)?;
+            match tag[0] {
+                RECORD_END => break,
+                RECORD_KEY => {}
+                _ => return Err(Error::new(ErrorKind::InvalidData, "unknown record in archive")),
+            }
+            let record_basis = read_name(&mut archive, BASIS_NAME_LEN)?;
+            let dict = read_name(&mut archive, DICT_NAME_LEN)?;
+            let mut key = read_name(&mut archive, KEY_NAME_LEN)?;
+            let reserved = read_u64(&mut archive)?;
+            let len = read_u64(&mut archive)?;
+            let basis = into_basis.unwrap_or(&record_basis);
+            if !open_bases.iter().any(|b| b == basis) {
+                log::warn!("skipping {}:{}:{}: basis is not open", basis, dict, key);
+                copy_exact(&mut archive, &mut std::io::sink(), len)?;
+                report.skipped += 1;
+                continue;
+            }
+            let exists = match self.get(&dict, &key, Some(basis), false, false, None, None::<fn()>) {
+                Ok(_) => true,
+                Err(e) if e.kind() == ErrorKind::NotFound => false,
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                    log::warn!("skipping {}:{}:{}: access denied", basis, dict, key);
+                    copy_exact(&mut archive, &mut std::io::sink(), len)?;
+                    report.denied += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if exists {
+                match policy {
+                    ConflictPolicy::Skip => {
+                        copy_exact(&mut archive, &mut std::io::sink(), len)?;
+                        report.skipped += 1;
+                        continue;
+                    }
+                    // deleted rather than written over, so a shorter key doesn't keep the tail of the old one
+                    ConflictPolicy::Overwrite => self.delete_key(&dict, &key, Some(basis))?,
+                    ConflictPolicy::Rename => {
+                        key = self.free_key_name(&dict, &key, basis)?;
+                        report.renamed += 1;
+                    }
+                }
+            }
+            let alloc_hint = reserved.max(len) as usize;
+            let mut pk = match self.get(&dict, &key, Some(basis), true, true, Some(alloc_hint), None::<fn()>) {
+                Ok(pk) => pk,
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                    log::warn!("skipping {}:{}:{}: access denied", basis, dict, key);
+                    copy_exact(&mut archive, &mut std::io::sink(), len)?;
+                    report.denied += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            copy_exact(&mut archive, &mut pk, len)?;
+            report.keys += 1;
+            report.bytes += len;
+        }
+        archive.finish()?;
+        self.sync()?;
+        log::info!("imported {:?}", report);
+        Ok(report)
+    }
+
+    /// Finds a name of the form `<key>.<n>` that isn't taken in `dict`, for `ConflictPolicy::Rename`.
+    fn free_key_name(&self, dict: &str, key: &str, basis: &str) -> Result<String> {
+        for n in 1..u16::MAX {
+            let suffix = format!(".{}", n);
+            let mut stem = key.to_string();
+            while stem.len() + suffix.len() > KEY_NAME_LEN - 1 {
+                stem.pop();
+            }
+            let candidate = stem + &suffix;
+            match self.get(dict, &candidate, Some(basis), false, false, None, None::<fn()>) {
+                Ok(_) => continue,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(candidate),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::new(ErrorKind::AlreadyExists, "no free name to import the key under"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    /// Seals `data` (and the end record) into an archive, at a cost low enough for tests.
+    fn seal(data: &[u8]) -> Vec<u8> {
+        let header = ArchiveHeader { m_cost: 8, t_cost: 1, p_cost: 1, salt: [7; 16], nonce_prefix: [3; NONCE_PREFIX_LEN] };
+        let mut archive = SealingWriter::new(Vec::new(), &header, PASSPHRASE).unwrap();
+        archive.write_all(data).unwrap();
+        archive.finish().unwrap()
+    }
+    /// Opens `sealed`, and returns the plaintext before the end record.
+    fn open(sealed: &[u8], passphrase: &str, len: usize) -> Result<Vec<u8>> {
+        let mut archive = OpeningReader::new(sealed, passphrase)?;
+        let mut data = vec![0u8; len];
+        archive.read_exact(&mut data)?;
+        let mut tag = [0u8];
+        archive.read_exact(&mut tag)?;
+        assert_eq!(tag[0], RECORD_END);
+        archive.finish()?;
+        Ok(data)
+    }
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 + i / 256) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        // an empty archive, one that ends right on a chunk boundary, and ones of several chunks
+        for &len in [0, 1, CHUNK_LEN - 1, CHUNK_LEN, CHUNK_LEN + 1, 3 * CHUNK_LEN + 100].iter() {
+            let plain = data(len);
+            let sealed = seal(&plain);
+            assert_eq!(&sealed[..8], &ARCHIVE_MAGIC);
+            if len >= 64 {
+                assert!(!sealed.windows(64).any(|w| w == &plain[..64]), "plaintext shows in the archive");
+            }
+            assert_eq!(open(&sealed, PASSPHRASE, len).unwrap(), plain, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let sealed = seal(&data(100));
+        let err = open(&sealed, "correct horse battery stapler", 100).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn tampering() {
+        let plain = data(2 * CHUNK_LEN + 10);
+        let sealed = seal(&plain);
+        let second_chunk = HEADER_LEN + 4 + CHUNK_LEN + TAG_LEN;
+        for &(at, kind) in [
+            // the salt: the key is wrong, and the first chunk can't tell that from a bad passphrase
+            (30, ErrorKind::PermissionDenied),
+            // the first and the second chunk's ciphertext
+            (HEADER_LEN + 4 + 10, ErrorKind::PermissionDenied),
+            (second_chunk + 4 + 10, ErrorKind::InvalidData),
+            // the last chunk's tag
+            (sealed.len() - 1, ErrorKind::InvalidData),
+        ].iter() {
+            let mut damaged = sealed.clone();
+            damaged[at] ^= 0x01;
+            let err = open(&damaged, PASSPHRASE, plain.len()).unwrap_err();
+            assert_eq!(err.kind(), kind, "flipped byte {}", at);
+        }
+        // a frame length that is out of bounds is refused before anything is read
+        let mut damaged = sealed.clone();
+        damaged[HEADER_LEN + 1] = 0xFF;
+        assert_eq!(open(&damaged, PASSPHRASE, plain.len()).unwrap_err().kind(), ErrorKind::InvalidData);
+        // marking a chunk as the last one changes its nonce, so it won't open
+        let mut damaged = sealed.clone();
+        damaged[second_chunk + 3] |= 0x80;
+        assert_eq!(open(&damaged, PASSPHRASE, plain.len()).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncation() {
+        let plain = data(2 * CHUNK_LEN + 10);
+        let sealed = seal(&plain);
+        let chunk = 4 + CHUNK_LEN + TAG_LEN;
+        // inside the header, inside a frame, inside a chunk, and on each chunk boundary
+        for &len in [HEADER_LEN - 1, HEADER_LEN, HEADER_LEN + 2, HEADER_LEN + 100, HEADER_LEN + chunk,
+            HEADER_LEN + 2 * chunk, sealed.len() - 1].iter() {
+            assert!(open(&sealed[..len], PASSPHRASE, plain.len()).is_err(), "cut to {} bytes", len);
+        }
+        // losing whole chunks off the end reads as the stream stopping before its end record
+        let err = open(&sealed[..HEADER_LEN + 2 * chunk], PASSPHRASE, plain.len()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
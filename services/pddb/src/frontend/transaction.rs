@@ -0,0 +1,91 @@
+use crate::*;
+use xous::CID;
+use xous_ipc::Buffer;
+
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+
+/// A set of key updates that are applied all together, or not at all, even if power is lost part way
+/// through. Updates are staged by the PDDB server and are not visible to anyone, including the owner of the
+/// transaction, until `commit()` is called. Dropping a transaction without committing it aborts it.
+///
+/// All the updates of a transaction go to the same basis. Transactions are held in the server's memory until
+/// they are committed, so they are meant for small, related records (e.g. a record, its index and a counter),
+/// and not for bulk data.
+pub struct PddbTransaction {
+    pub(crate) id: u32,
+    pub(crate) conn: CID,
+    pub(crate) done: bool,
+}
+/// PddbTransactions are created by Pddb
+impl PddbTransaction {
+    /// Stages replacing the contents of `key` in `dict` with `data`. The key, and the dictionary, are created
+    /// if they don't exist.
+    pub fn write(&mut self, dict: &str, key: &str, data: &[u8]) -> Result<()> {
+        let mut offset = 0;
+        // an empty write still has to be sent, so the key gets created
+        loop {
+            let chunk = &data[offset..data.len().min(offset + TXN_CHUNK_LEN)];
+            let mut request = self.request(dict, key)?;
+            request.offset = offset as u64;
+            request.len = chunk.len() as u16;
+            request.data[..chunk.len()].copy_from_slice(chunk);
+            self.send(request, Opcode::TxnWrite)?;
+            offset += chunk.len();
+            if offset >= data.len() {
+                return Ok(());
+            }
+        }
+    }
+    /// Stages the removal of `key` from `dict`. Committing is not affected if the key doesn't exist.
+    pub fn delete(&mut self, dict: &str, key: &str) -> Result<()> {
+        let request = self.request(dict, key)?;
+        self.send(request, Opcode::TxnDelete)
+    }
+    /// Applies all the staged updates. Once this returns successfully, they are all on disk; if power is lost
+    /// before that, either none or all of them are visible on the next boot.
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        self.send(PddbTxnRequest::new(self.id), Opcode::TxnCommit)
+    }
+    /// Drops all the staged updates.
+    pub fn abort(mut self) -> Result<()> {
+        self.done = true;
+        self.send(PddbTxnRequest::new(self.id), Opcode::TxnAbort)
+    }
+
+    fn request(&self, dict: &str, key: &str) -> Result<PddbTxnRequest> {
+        if dict.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if key.len() > (KEY_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        let mut request = PddbTxnRequest::new(self.id);
+        request.dict = xous_ipc::String::<DICT_NAME_LEN>::from_str(dict);
+        request.key = xous_ipc::String::<KEY_NAME_LEN>::from_str(key);
+        Ok(request)
+    }
+    fn send(&self, request: PddbTxnRequest, opcode: Opcode) -> Result<()> {
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, opcode.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbTxnRequest, _>().unwrap();
+        match response.result {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Transaction or basis not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Transaction too large, or out of disk space")),
+            PddbRequestCode::InvalidRequest => Err(Error::new(ErrorKind::InvalidInput, "Malformed transaction request")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+}
+
+impl Drop for PddbTransaction {
+    fn drop(&mut self) {
+        if !self.done {
+            self.send(PddbTxnRequest::new(self.id), Opcode::TxnAbort).ok();
+        }
+    }
+}
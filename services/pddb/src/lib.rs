@@ -23,7 +23,9 @@ use std::cell::RefCell;
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub enum CbOp {
     Change,
-    Quit
+    Quit,
+    /// memory message carrying a `PddbWatchEvent`
+    Watch,
 }
 
 pub struct PddbMountPoller {
@@ -87,6 +89,9 @@ pub struct Pddb {
     /// in the case of a basis change. Basis changes are thought to be rare; so, big changes
     /// like this are probably OK.
     keys: Arc<Mutex<HashMap<ApiToken, Box<dyn Fn() + 'static + Send> >>>,
+    /// Callbacks of the watches made with `watch()`, by watch id. These run on the same thread as the
+    /// key change callbacks, so the same restrictions apply.
+    watches: Arc<Mutex<HashMap<u32, Box<dyn Fn(PddbEvent) + 'static + Send> >>>,
     trng: trng::Trng,
 }
 impl Pddb {
@@ -100,6 +105,7 @@ impl Pddb {
             cb: RefCell::new(None),
             cb_handle: RefCell::new(None),
             keys,
+            watches: Arc::new(Mutex::new(HashMap::new())),
             trng: trng::Trng::new(&xns).unwrap(),
         }
     }
@@ -108,6 +114,7 @@ impl Pddb {
             let sid = xous::create_server().unwrap();
             let handle = thread::spawn({
                 let keys = Arc::clone(&self.keys);
+                let watches = Arc::clone(&self.watches);
                 let sid = sid.clone();
                 move || {
                     loop {
@@ -121,6 +128,16 @@ impl Pddb {
                                     log::warn!("Key changed but no callback was hooked to receive it");
                                 }
                             }),
+                            Some(CbOp::Watch) => {
+                                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                                let event = buffer.to_original::<PddbWatchEvent, _>().unwrap();
+                                if let Some(cb) = watches.lock().unwrap().get(&event.id) {
+                                    cb(event.to_event());
+                                } else {
+                                    // can happen if an event was already in flight when the watch was cancelled
+                                    log::debug!("Got an event for watch {:x}, which is no longer registered", event.id);
+                                }
+                            },
                             Some(CbOp::Quit) => { // blocking scalar
                                 xous::return_scalar(msg.sender, 0).unwrap();
                                 break;
@@ -384,6 +401,14 @@ impl Pddb {
 
     /// deletes a key within the dictionary
     pub fn delete_key(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_key_inner(dict_name, key_name, basis_name, Opcode::DeleteKey)
+    }
+    /// deletes a key within the dictionary, and immediately overwrites the storage it occupied with
+    /// noise. Slower than `delete_key()`, as it re-writes the key's small pool and descriptor page on the spot.
+    pub fn secure_delete_key(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_key_inner(dict_name, key_name, basis_name, Opcode::DeleteKeySecure)
+    }
+    fn delete_key_inner(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>, opcode: Opcode) -> Result<()> {
         if key_name.len() > (KEY_NAME_LEN - 1) {
             return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
         }
@@ -421,18 +446,27 @@ impl Pddb {
         };
         let mut buf = Buffer::into_buf(request)
             .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
-        buf.lend_mut(self.conn, Opcode::DeleteKey.to_u32().unwrap())
+        buf.lend_mut(self.conn, opcode.to_u32().unwrap())
             .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
 
         let response = buf.to_original::<PddbKeyRequest, _>().unwrap();
         match response.result {
             PddbRequestCode::NoErr => Ok(()),
             PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dictionary or key was not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No more space on disk")),
             _ => Err(Error::new(ErrorKind::Other, "Internal error"))
         }
     }
     /// deletes the entire dictionary
     pub fn delete_dict(&self, dict_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_dict_inner(dict_name, basis_name, Opcode::DeleteDict)
+    }
+    /// deletes the entire dictionary, and also scrubs the plaintext of its keys from the PDDB's cache.
+    /// The pages of a deleted dictionary are always overwritten with noise on disk.
+    pub fn secure_delete_dict(&self, dict_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_dict_inner(dict_name, basis_name, Opcode::DeleteDictSecure)
+    }
+    fn delete_dict_inner(&self, dict_name: &str, basis_name: Option<&str>, opcode: Opcode) -> Result<()> {
         if dict_name.len() > (DICT_NAME_LEN - 1) {
             return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
         }
@@ -467,7 +501,7 @@ impl Pddb {
         };
         let mut buf = Buffer::into_buf(request)
             .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
-        buf.lend_mut(self.conn, Opcode::DeleteDict.to_u32().unwrap())
+        buf.lend_mut(self.conn, opcode.to_u32().unwrap())
             .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
 
         let response = buf.to_original::<PddbKeyRequest, _>().unwrap();
@@ -478,6 +512,231 @@ impl Pddb {
         }
     }
 
+    /// Re-packs a dictionary's key descriptors and small-key storage to reclaim fragmented space. If
+    /// `dict_name` is None, all the dictionaries are compacted; if `basis_name` is None, all open bases are visited.
+    pub fn compact_dict(&self, dict_name: Option<&str>, basis_name: Option<&str>) -> Result<()> {
+        let dname = dict_name.unwrap_or("");
+        if dname.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        let bname = if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname)
+        } else {
+            xous_ipc::String::<BASIS_NAME_LEN>::new()
+        };
+
+        let request = PddbKeyRequest {
+            basis_specified: basis_name.is_some(),
+            basis: bname,
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dname),
+            key: xous_ipc::String::<KEY_NAME_LEN>::new(),
+            create_dict: false,
+            create_key: false,
+            token: None,
+            result: PddbRequestCode::Uninit,
+            cb_sid: None,
+            alloc_hint: None,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::CompactDict.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbKeyRequest, _>().unwrap();
+        match response.result {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dictionary was not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Not enough free space to compact")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error"))
+        }
+    }
+
+    /// Starts a transaction on the named basis, or on the most recently opened basis if `basis_name` is None.
+    /// See `PddbTransaction` for how the updates it stages are applied.
+    pub fn begin_transaction(&self, basis_name: Option<&str>) -> Result<PddbTransaction> {
+        let mut request = PddbTxnRequest::new(0);
+        if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            request.basis_specified = true;
+            request.basis = xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname);
+        }
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::TxnBegin.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbTxnRequest, _>().unwrap();
+        match response.result {
+            PddbRequestCode::NoErr => Ok(PddbTransaction { id: response.id, conn: self.conn, done: false }),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::NotFound, "PDDB not mounted")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error"))
+        }
+    }
+
+    /// Subscribes to changes to `dict`, or to just `key` in it if one is given, in the named basis or in any
+    /// basis if `basis_name` is None. `cb` is called when a matching key is created, updated or deleted, when
+    /// the dictionary is deleted, and when a basis is unlocked or locked (keys may appear or vanish as a result).
+    /// The dictionary doesn't have to exist yet, and the PDDB doesn't have to be mounted.
+    ///
+    /// Returns an id to pass to `unwatch()`. If this `Pddb` object goes away, the server drops its watches the
+    /// next time it has an event for them, but it's polite to cancel them explicitly.
+    pub fn watch<F>(&self, dict_name: &str, key_name: Option<&str>, basis_name: Option<&str>, cb: F) -> Result<u32>
+    where F: Fn(PddbEvent) + 'static + Send {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if key_name.map_or(false, |k| k.len() > (KEY_NAME_LEN - 1)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        if basis_name.map_or(false, |b| b.len() > (BASIS_NAME_LEN - 1)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        self.ensure_async_responder();
+        let maybe_cb = self.cb.take();
+        let cb_sid = maybe_cb.expect("async responder was not started").to_array();
+        self.cb.replace(maybe_cb);
+
+        // the callback has to be in place before the server knows about the watch
+        let mut id = self.trng.get_u32().unwrap();
+        while id == 0 || self.watches.lock().unwrap().contains_key(&id) {
+            id = self.trng.get_u32().unwrap();
+        }
+        self.watches.lock().unwrap().insert(id, Box::new(cb));
+
+        let request = PddbWatchRequest {
+            id,
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            key_specified: key_name.is_some(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name.unwrap_or("")),
+            cb_sid,
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let sent = buf.lend_mut(self.conn, Opcode::Watch.to_u32().unwrap());
+        let result = match sent {
+            Ok(_) => buf.to_original::<PddbWatchRequest, _>().unwrap().result,
+            Err(_) => PddbRequestCode::InternalError,
+        };
+        match result {
+            PddbRequestCode::NoErr => Ok(id),
+            _ => {
+                self.watches.lock().unwrap().remove(&id);
+                Err(Error::new(ErrorKind::Other, "Couldn't register the watch"))
+            }
+        }
+    }
+    /// Cancels a watch made with `watch()`.
+    pub fn unwatch(&self, id: u32) -> Result<()> {
+        self.watches.lock().unwrap().remove(&id);
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Unwatch.to_usize().unwrap(), id as usize, 0, 0, 0)
+        ) {
+            Ok(xous::Result::Scalar1(rcode)) => {
+                match FromPrimitive::from_u8(rcode as u8) {
+                    Some(PddbRetcode::Ok) => Ok(()),
+                    Some(PddbRetcode::NotFound) => Err(Error::new(ErrorKind::NotFound, "No such watch")),
+                    _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+                }
+            }
+            _ => Err(Error::new(ErrorKind::Other, "Xous internal error")),
+        }
+    }
+
+    /// Ties this process to the app that `gam_token` was issued to, so that it can claim dictionaries, and so
+    /// that the owners of other dictionaries (and the user) can tell who is asking for access. A process can
+    /// only be one app, and stays tied to it until it drops its last `Pddb`.
+    pub fn identify(&self, gam_token: [u32; 4]) -> Result<()> {
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Identify.to_usize().unwrap(),
+                gam_token[0] as usize, gam_token[1] as usize, gam_token[2] as usize, gam_token[3] as usize)
+        ) {
+            Ok(xous::Result::Scalar1(rcode)) => {
+                match FromPrimitive::from_u8(rcode as u8) {
+                    Some(PddbRetcode::Ok) => Ok(()),
+                    Some(PddbRetcode::AccessDenied) => Err(Error::new(ErrorKind::PermissionDenied, "Token not recognized, or process already identified")),
+                    _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+                }
+            }
+            _ => Err(Error::new(ErrorKind::Other, "Xous internal error")),
+        }
+    }
+    /// Makes the app this process identified as the owner of `dict`, which doesn't have to exist yet.
+    /// From then on, other apps need a grant from the owner, or from the user, to use it. Claiming a dictionary
+    /// that already has keys asks the user to confirm, as the data might belong to another app. Claiming a
+    /// dictionary the app already owns is a no-op.
+    pub fn claim_dict(&self, dict_name: &str, basis_name: Option<&str>) -> Result<()> {
+        let request = self.acl_request(dict_name, basis_name, "", PddbAccess::empty())?;
+        self.acl_send(request, Opcode::ClaimDict)
+    }
+    /// Sets what `app` (a GAM context name) may do with `dict`; an empty `access` revokes everything. Only the
+    /// owner of the dictionary may call this.
+    pub fn set_dict_access(&self, dict_name: &str, basis_name: Option<&str>, app: &str, access: PddbAccess) -> Result<()> {
+        let request = self.acl_request(dict_name, basis_name, app, access)?;
+        self.acl_send(request, Opcode::SetDictAccess)
+    }
+    fn acl_request(&self, dict_name: &str, basis_name: Option<&str>, app: &str, access: PddbAccess) -> Result<PddbAclRequest> {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if basis_name.map_or(false, |b| b.len() > (BASIS_NAME_LEN - 1)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        if app.len() > (APP_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "app name too long"));
+        }
+        Ok(PddbAclRequest {
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            app: xous_ipc::String::<APP_NAME_LEN>::from_str(app),
+            access: access.bits(),
+            result: PddbRequestCode::Uninit,
+        })
+    }
+    fn acl_send(&self, request: PddbAclRequest, opcode: Opcode) -> Result<()> {
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, opcode.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        match buf.to_original::<PddbAclRequest, _>().unwrap().result {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Not the owner, or not identified")),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dictionary has no owner")),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::NotFound, "PDDB not mounted")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Out of disk space")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+
+    /// Checks the page table, free space, dictionaries and keys of every open basis for consistency. Depending
+    /// on `mode`, problems are only reported, or fixed. Locked bases are not checked, so unlock everything first.
+    pub fn fsck(&self, mode: FsckMode) -> Result<FsckReport> {
+        let request = PddbFsckRequest {
+            mode,
+            report: FsckReport::default(),
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Fsck.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbFsckRequest, _>().unwrap();
+        match response.result {
+            PddbRequestCode::NoErr => Ok(response.report),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::NotFound, "PDDB not mounted")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error"))
+        }
+    }
+
     pub fn sync(&self) -> Result<()> {
         let response = send_message(
             self.conn,
@@ -527,6 +786,7 @@ impl Pddb {
         let count = match response.code {
             PddbRequestCode::NoErr => response.index,
             PddbRequestCode::NotFound => return Err(Error::new(ErrorKind::NotFound, "dictionary not found")),
+            PddbRequestCode::AccessDenied => return Err(Error::new(ErrorKind::PermissionDenied, "dictionary access denied")),
             _ => return Err(Error::new(ErrorKind::Other, "Internal error")),
         };
         // very non-optimal, slow way of doing this, but let's just get it working first and optimize later.
@@ -706,6 +966,7 @@ impl Drop for Pddb {
         }
 
         if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            send_message(self.conn, Message::new_blocking_scalar(Opcode::Disconnect.to_usize().unwrap(), 0, 0, 0, 0)).ok();
             unsafe{xous::disconnect(self.conn).unwrap();}
         }
     }
@@ -1,9 +1,13 @@
 mod senres;
 mod utils;
 
+use crate::acl::AccessControl;
 use crate::backend::BasisCache;
 use crate::backend::PddbOs;
+use crate::watch::Watchers;
 use crate::FileHandle;
+use crate::PddbAccess;
+use crate::PddbEventKind;
 
 use senres::{Senres, SenresMut};
 
@@ -32,6 +36,8 @@ pub(crate) fn stat_path(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    acl: &mut AccessControl,
+    pid: Option<xous::PID>,
 ) -> Result<(), crate::PddbRetcode> {
     // Convert the memory to a Senres buffer
     let mut backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
@@ -104,6 +110,10 @@ pub(crate) fn stat_path(
     // Find all keys that are in this dict. Ignore errors, since sometimes
     // the dict doesn't exist, which is fine.
     if let Some((dict_path, key_path)) = stripped_path.rsplit_once(std::path::MAIN_SEPARATOR) {
+        // whether a key exists is as telling as the list of keys
+        if !acl.check(pddb_os, basis_cache, pid, basis.as_deref(), dict_path, PddbAccess::LIST) {
+            return Err(crate::PddbRetcode::AccessDenied);
+        }
         if let Some(key_list) = basis_cache
             .key_list(pddb_os, dict_path, basis.as_deref())
             .map_err(|e| {
@@ -139,6 +149,8 @@ pub(crate) fn list_path(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    acl: &mut AccessControl,
+    pid: Option<xous::PID>,
 ) -> Result<(), crate::PddbRetcode> {
     // Convert the memory to a Senres buffer
     let mut backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
@@ -181,6 +193,11 @@ pub(crate) fn list_path(
     }
 
     let dict = dict.as_deref().unwrap_or("");
+    if !dict.is_empty()
+        && !acl.check(pddb_os, basis_cache, pid, basis.as_deref(), dict, PddbAccess::LIST)
+    {
+        return Err(crate::PddbRetcode::AccessDenied);
+    }
 
     // Keep a space at the start of the list for us to log the number of elements.
     let entry_len_pos = writer.delayed_append();
@@ -265,6 +282,9 @@ pub(crate) fn open_key(
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
     fds: &mut Vec<Option<crate::FileHandle>>,
+    watchers: &mut Watchers,
+    acl: &mut AccessControl,
+    pid: Option<xous::PID>,
 ) -> Result<(), crate::PddbRetcode> {
     // Convert the memory to a Senres buffer
     let mut backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
@@ -347,6 +367,9 @@ pub(crate) fn open_key(
             continue;
         }
         let bname = Some(basis.as_str());
+        if !acl.check(pddb_os, basis_cache, pid, bname, requested_dict, PddbAccess::READ) {
+            return Err(crate::PddbRetcode::AccessDenied);
+        }
 
         // If the dictionary wasn't found, either add it or move on
         if basis_cache
@@ -381,6 +404,9 @@ pub(crate) fn open_key(
             if !create_file {
                 continue;
             }
+            if !acl.check(pddb_os, basis_cache, pid, bname, requested_dict, PddbAccess::WRITE) {
+                return Err(crate::PddbRetcode::AccessDenied);
+            }
             // create an empty key placeholder
             basis_cache
                 .key_update(
@@ -406,6 +432,7 @@ pub(crate) fn open_key(
                     );
                     crate::PddbRetcode::InternalError
                 })?;
+            watchers.key_changed(PddbEventKind::Created, basis, requested_dict, requested_key);
             len = 0;
         } else if create_new {
             log::error!(
@@ -414,6 +441,9 @@ pub(crate) fn open_key(
             );
             return Err(crate::PddbRetcode::DiskFull);
         } else if truncate {
+            if !acl.check(pddb_os, basis_cache, pid, bname, requested_dict, PddbAccess::WRITE) {
+                return Err(crate::PddbRetcode::AccessDenied);
+            }
             // Truncate the file, which we know exists
             basis_cache
                 .key_update(
@@ -439,6 +469,7 @@ pub(crate) fn open_key(
                     );
                     crate::PddbRetcode::InternalError
                 })?;
+            watchers.key_changed(PddbEventKind::Updated, basis, requested_dict, requested_key);
         }
 
         // The basis exists for sure.
@@ -542,6 +573,10 @@ pub(crate) fn delete_key(
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
     all_fds: &mut std::collections::HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>,
+    watchers: &mut Watchers,
+    acl: &mut AccessControl,
+    pid: Option<xous::PID>,
+    paranoid: bool,
 ) -> Result<(), crate::PddbRetcode> {
     // Convert the memory to a Senres buffer
     let backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
@@ -565,9 +600,13 @@ pub(crate) fn delete_key(
         .rsplit_once(std::path::MAIN_SEPARATOR)
         .ok_or(crate::PddbRetcode::AccessDenied)?;
 
+    if !acl.check(pddb_os, basis_cache, pid, bname, dict, PddbAccess::WRITE) {
+        return Err(crate::PddbRetcode::AccessDenied);
+    }
+
     // Perform the actual removal
     basis_cache
-        .key_remove(pddb_os, dict, key, bname, false)
+        .key_remove(pddb_os, dict, key, bname, paranoid)
         .or_else(|e| {
             log::error!(
                 "unable to delete key {} in dict {} (basis {:?}): {:?}",
@@ -578,6 +617,12 @@ pub(crate) fn delete_key(
             );
             Err(crate::PddbRetcode::UnexpectedEof)
         })?;
+    if let Some(b) = bname
+        .map(|b| b.to_owned())
+        .or_else(|| basis_cache.basis_latest().map(|b| b.to_owned()))
+    {
+        watchers.key_changed(PddbEventKind::Deleted, &b, dict, key);
+    }
 
     // Mark the entry as deleted in all remaining file handles in the entire system
     for fds in all_fds.values_mut() {
@@ -601,8 +646,14 @@ pub(crate) fn write_key(
     basis_cache: &mut BasisCache,
     fds: &mut Vec<Option<crate::FileHandle>>,
     fd: usize,
+    watchers: &mut Watchers,
+    acl: &mut AccessControl,
+    pid: Option<xous::PID>,
 ) -> Result<(), crate::PddbRetcode> {
     let file = get_fd(fds, fd)?;
+    if !acl.check(pddb_os, basis_cache, pid, file.basis.as_deref(), &file.dict, PddbAccess::WRITE) {
+        return Err(crate::PddbRetcode::AccessDenied);
+    }
     let mut retcode = crate::PddbRetcode::InternalError;
 
     for basis in basis_cache.access_list().iter() {
@@ -639,6 +690,12 @@ pub(crate) fn write_key(
         {
             file.offset += length_to_write as u64;
             mem.valid = xous::MemorySize::new(length_to_write);
+            watchers.key_changed(
+                PddbEventKind::Updated,
+                file.basis.as_ref().unwrap_or(basis),
+                &file.dict,
+                &file.key,
+            );
             return Ok(());
         }
     }
@@ -780,6 +837,8 @@ pub(crate) fn list_key(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    acl: &mut AccessControl,
+    pid: Option<xous::PID>,
 ) -> Result<(), crate::PddbRetcode> {
     let mut backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
         .or(Err(crate::PddbRetcode::InternalError))?;
@@ -798,6 +857,9 @@ pub(crate) fn list_key(
             .to_owned();
     }
 
+    if !acl.check(pddb_os, basis_cache, pid, bname.as_deref(), &key, PddbAccess::LIST) {
+        return Err(crate::PddbRetcode::AccessDenied);
+    }
     let mut writer = backing
         .writer(*b"LiKR")
         .ok_or(crate::PddbRetcode::InternalError)?;
@@ -826,6 +888,9 @@ pub(crate) fn delete_dict(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    watchers: &mut Watchers,
+    acl: &mut AccessControl,
+    pid: Option<xous::PID>,
 ) -> Result<(), crate::PddbRetcode> {
     let backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
         .or(Err(crate::PddbRetcode::InternalError))?;
@@ -840,6 +905,9 @@ pub(crate) fn delete_dict(
         utils::split_basis_and_dict(path, || basis_cache.basis_latest().map(|m| m.to_owned()))
             .or(Err(crate::PddbRetcode::InternalError))?;
     let dict = dict.ok_or(crate::PddbRetcode::InternalError)?;
+    if !acl.check(pddb_os, basis_cache, pid, bname.as_deref(), &dict, PddbAccess::WRITE) {
+        return Err(crate::PddbRetcode::AccessDenied);
+    }
 
     if let Some(key_list) = basis_cache
         .key_list(pddb_os, &dict, bname.as_deref())
@@ -862,6 +930,10 @@ pub(crate) fn delete_dict(
         log::error!("error removing dict {} in basis {:?}", dict, bname);
         return Err(crate::PddbRetcode::InternalError);
     }
+    if let Some(b) = bname.or_else(|| basis_cache.basis_latest().map(|b| b.to_owned())) {
+        watchers.dict_deleted(&b, &dict);
+        acl.dict_removed(pddb_os, basis_cache, &b, &dict);
+    }
 
     Ok(())
 }
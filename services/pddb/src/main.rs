@@ -116,13 +116,14 @@
 ///    it just prevents a naive rainbow attack from re-using its table.
 ///  - The bcrypt() implementation is vendored in from a Rust bcrypt crate. It hasn't been audited.
 ///  - The COST of 7 for bcrypt is relatively low by today's standards (should be 10). However,
-///    we can't raise the cost to 10 because our CPU is slower than most modern x86 devices. There is
-///    an open issue to try to improve this with hardware acceleration. The mitigation is to use
-///    a longer passphrase instead of a 12 or 14-character password.
+///    we can't raise the cost to 10 because our CPU is slower than most modern x86 devices. Bases
+///    are now created with Argon2id instead, which is memory-hard; bases keyed with bcrypt are moved
+///    over to it when their password is changed (`PddbRekeyOp::ChangePass`). Until then, the mitigation
+///    is to use a longer passphrase instead of a 12 or 14-character password.
 ///  - The RootKey is used to decrypt a locally stored System Basis key. The key is encrypted using
 ///    straight AES-256 with no authentication.
 ///  - Secret basis keys are not stored anywhere on the device. They are all derived from a password
-///    using bcrypt. The salt for the password is drawn from a "salt pool", whose index is derived from
+///    using Argon2id, or bcrypt for older bases. The salt for the password is drawn from a "salt pool", whose index is derived from
 ///    a weak hash of the password itself. This means there is a chance that a salt gets re-used. However,
 ///    we do not store per-password salts because the existence of the salt would betray the existence of
 ///    a password.
@@ -362,7 +363,6 @@
 // historical note: 389 hours, 11 mins elapsed since the start of the PDDB coding, and the first attempt at a hardware test -- as evidenced by the uptime of the hardware validation unit.
 
 extern crate bitflags;
-extern crate bitfield;
 
 mod api;
 use api::*;
@@ -374,6 +374,10 @@ mod menu;
 use menu::*;
 
 mod libstd;
+mod watch;
+use watch::*;
+mod acl;
+use acl::*;
 
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 mod tests;
@@ -478,6 +482,17 @@ fn wrapped_main() -> ! {
     // Process-indexed map of file descriptors to token records
     let mut fd_mapping = HashMap::<Option<xous::PID>, Vec<Option<FileHandle>>>::new();
 
+    // transactions that have been started but not yet committed or aborted, by owning process and id
+    let mut transactions = HashMap::<(Option<xous::PID>, u32), Transaction>::new();
+
+    // subscriptions to changes to keys, dictionaries and bases
+    let mut watchers = Watchers::new();
+
+    // which app each process is, and who may use the dictionaries apps have claimed
+    let mut acl = AccessControl::new(&xns);
+    // connected on the first Identify, as the GAM may not be up when the PDDB starts
+    let mut gam: Option<gam::Gam> = None;
+
     // mount poller thread
     let is_mounted = Arc::new(AtomicBool::new(false));
     let _ = thread::spawn({
@@ -619,6 +634,7 @@ fn wrapped_main() -> ! {
                             PasswordState::Correct => {
                                 if try_mount_or_format(&modals, &mut pddb_os, &mut basis_cache, PasswordState::Correct, time_resetter) {
                                     is_mounted.store(true, Ordering::SeqCst);
+                                    watchers.basis_changed(PddbEventKind::BasisUnlocked, PDDB_DEFAULT_SYSTEM_BASIS);
                                     for requester in mount_notifications.drain(..) {
                                         xous::return_scalar(requester, 1).expect("couldn't return scalar");
                                     }
@@ -632,6 +648,7 @@ fn wrapped_main() -> ! {
                             PasswordState::Uninit => {
                                 if try_mount_or_format(&modals, &mut pddb_os, &mut basis_cache, PasswordState::Uninit, time_resetter) {
                                     is_mounted.store(true, Ordering::SeqCst);
+                                    watchers.basis_changed(PddbEventKind::BasisUnlocked, PDDB_DEFAULT_SYSTEM_BASIS);
                                     for requester in mount_notifications.drain(..) {
                                         xous::return_scalar(requester, 1).expect("couldn't return scalar");
                                     }
@@ -671,6 +688,10 @@ fn wrapped_main() -> ! {
                     latest_heap = heap_usage();
                     log::info!("{} pruned, now: {} heap, {} cache", pruned, latest_heap, basis_cache.cache_size())
                 }
+                let compacted = basis_cache.dict_autocompact(&mut pddb_os);
+                if compacted > 0 {
+                    log::info!("PDDB compacted {} dictionaries", compacted);
+                }
             }
             Opcode::ListBasis => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
@@ -694,7 +715,7 @@ fn wrapped_main() -> ! {
             Opcode::ListPathStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(e) = libstd::list_path(mem, &mut pddb_os, &mut basis_cache) {
+                    if let Err(e) = libstd::list_path(mem, &mut pddb_os, &mut basis_cache, &mut acl, msg.sender.pid()) {
                         mem.offset = xous::MemoryAddress::new(e as usize);
                     }
                 }
@@ -702,7 +723,7 @@ fn wrapped_main() -> ! {
             Opcode::StatPathStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(e) = libstd::stat_path(mem, &mut pddb_os, &mut basis_cache) {
+                    if let Err(e) = libstd::stat_path(mem, &mut pddb_os, &mut basis_cache, &mut acl, msg.sender.pid()) {
                         mem.offset = xous::MemoryAddress::new(e as usize);
                     }
                 }
@@ -779,7 +800,8 @@ fn wrapped_main() -> ! {
                                     &mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8"), pw.as_str().expect("password was not valid utf-8"),
                                     mgmt.policy.unwrap_or(BasisRetentionPolicy::Persist)
                                 ) {
-                                    basis_cache.basis_add(basis);
+                                    basis_cache.basis_add(&mut pddb_os, basis);
+                                    watchers.basis_changed(PddbEventKind::BasisUnlocked, mgmt.name.as_str().unwrap());
                                     finished = true;
                                     mgmt.code = PddbRequestCode::NoErr;
                                 } else {
@@ -820,7 +842,11 @@ fn wrapped_main() -> ! {
                 match mgmt.code {
                     PddbRequestCode::Close => {
                         match basis_cache.basis_unmount(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8")) {
-                            Ok(_) => mgmt.code = PddbRequestCode::NoErr,
+                            Ok(_) => {
+                                watchers.basis_changed(PddbEventKind::BasisLocked, mgmt.name.as_str().unwrap());
+                                acl.basis_locked(mgmt.name.as_str().unwrap());
+                                mgmt.code = PddbRequestCode::NoErr;
+                            }
                             Err(e) => match e.kind() {
                                 ErrorKind::NotFound => mgmt.code = PddbRequestCode::NotFound,
                                 _ => mgmt.code = PddbRequestCode::InternalError,
@@ -840,7 +866,11 @@ fn wrapped_main() -> ! {
                 match mgmt.code {
                     PddbRequestCode::Delete => {
                         match basis_cache.basis_delete(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8")) {
-                            Ok(_) => mgmt.code = PddbRequestCode::NoErr,
+                            Ok(_) => {
+                                watchers.basis_changed(PddbEventKind::BasisLocked, mgmt.name.as_str().unwrap());
+                                acl.basis_locked(mgmt.name.as_str().unwrap());
+                                mgmt.code = PddbRequestCode::NoErr;
+                            }
                             Err(e) => match e.kind() {
                                 ErrorKind::NotFound => mgmt.code = PddbRequestCode::NotFound,
                                 _ => mgmt.code = PddbRequestCode::InternalError,
@@ -865,6 +895,11 @@ fn wrapped_main() -> ! {
                     let dict = req.dict.as_str().expect("dict utf-8 decode error");
                     let key = req.key.as_str().expect("key utf-8 decode error");
                     log::debug!("get: {:?} {}", bname, key);
+                    if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname, dict, PddbAccess::READ) {
+                        req.result = PddbRequestCode::AccessDenied;
+                        buffer.replace(req).unwrap();
+                        break;
+                    }
                     if basis_cache.dict_attributes(&mut pddb_os, dict, bname).is_err() {
                         if req.create_dict {
                             match basis_cache.dict_add(&mut pddb_os, dict, bname) {
@@ -887,6 +922,10 @@ fn wrapped_main() -> ! {
                         if !req.create_key {
                             req.result = PddbRequestCode::NotFound;
                             buffer.replace(req).unwrap(); continue
+                        } else if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname, dict, PddbAccess::WRITE) {
+                            req.result = PddbRequestCode::AccessDenied;
+                            buffer.replace(req).unwrap();
+                            break;
                         } else {
                             // create an empty key placeholder
                             let empty: [u8; 0] = [];
@@ -895,7 +934,7 @@ fn wrapped_main() -> ! {
                                 // don't truncate if we've been given an explicit size hint.
                                 alloc_hint.is_none()
                             ) {
-                                Ok(_) => {},
+                                Ok(_) => watchers.key_changed(PddbEventKind::Created, bname.unwrap(), dict, key),
                                 Err(e) => {
                                     log::error!("Couldn't allocate key: {:?}", e);
                                     match e.kind() {
@@ -932,7 +971,7 @@ fn wrapped_main() -> ! {
             Opcode::OpenKeyStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(e) = libstd::open_key(mem, &mut pddb_os, &mut basis_cache, fd_mapping.entry(msg.sender.pid()).or_default()) {
+                    if let Err(e) = libstd::open_key(mem, &mut pddb_os, &mut basis_cache, fd_mapping.entry(msg.sender.pid()).or_default(), &mut watchers, &mut acl, msg.sender.pid()) {
                         mem.offset = xous::MemoryAddress::new(e as usize);
                     }
                 }
@@ -944,7 +983,8 @@ fn wrapped_main() -> ! {
                     // now check if we can safely disconnect and recycle our connection number.
                     // This is important because we can only have 32 outgoing connections...
                     if let Some(conn_to_remove) = rec.conn {
-                        let mut still_needs_cid = false;
+                        // watches share the callback connection of the tokens
+                        let mut still_needs_cid = watchers.uses_conn(conn_to_remove);
                         for r in token_dict.values() {
                             // check through the remaining dictionary values to see if they have a connection that is the same as our number
                             if let Some(existing_conn) = r.conn {
@@ -981,7 +1021,8 @@ fn wrapped_main() -> ! {
                 }
             }
 
-            Opcode::DeleteKey => {
+            Opcode::DeleteKey | Opcode::DeleteKeySecure => {
+                let paranoid = (msg.body.id() & 0xffff) == Opcode::DeleteKeySecure as usize;
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req: PddbKeyRequest = buffer.to_original::<PddbKeyRequest, _>().unwrap();
                 let bname = if req.basis_specified {
@@ -991,8 +1032,18 @@ fn wrapped_main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 let key = req.key.as_str().expect("key utf-8 decode error");
-                match basis_cache.key_remove(&mut pddb_os, dict, key, bname, false) {
+                if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname, dict, PddbAccess::WRITE) {
+                    req.result = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                // resolve the basis before the removal, as it's the one the removal applies to
+                let event_basis = bname.or(basis_cache.basis_latest()).map(|b| b.to_string());
+                match basis_cache.key_remove(&mut pddb_os, dict, key, bname, paranoid) {
                     Ok(_) => {
+                        if let Some(b) = event_basis {
+                            watchers.key_changed(PddbEventKind::Deleted, &b, dict, key);
+                        }
                         let mut evict_list = Vec::<ApiToken>::new();
                         // check to see if we need to eliminate any ApiTokens as a result of this.
                         for (token, rec) in token_dict.iter() {
@@ -1025,21 +1076,24 @@ fn wrapped_main() -> ! {
                     Err(e) => {
                         match e.kind() {
                             std::io::ErrorKind::NotFound => req.result = PddbRequestCode::NotFound,
+                            std::io::ErrorKind::OutOfMemory => req.result = PddbRequestCode::NoFreeSpace,
                             _ => req.result = PddbRequestCode::InternalError,
                         }
                     }
                 }
                 buffer.replace(req).unwrap();
             }
-            Opcode::DeleteKeyStd => {
+            Opcode::DeleteKeyStd | Opcode::DeleteKeySecureStd => {
+                let paranoid = (msg.body.id() & 0xffff) == Opcode::DeleteKeySecureStd as usize;
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(err) = libstd::delete_key(mem, &mut pddb_os, &mut basis_cache, &mut fd_mapping) {
+                    if let Err(err) = libstd::delete_key(mem, &mut pddb_os, &mut basis_cache, &mut fd_mapping, &mut watchers, &mut acl, msg.sender.pid(), paranoid) {
                         mem.offset = xous::MemoryAddress::new(err as usize);
                     }
                 }
             }
-            Opcode::DeleteDict => {
+            Opcode::DeleteDict | Opcode::DeleteDictSecure => {
+                let paranoid = (msg.body.id() & 0xffff) == Opcode::DeleteDictSecure as usize;
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req: PddbKeyRequest = buffer.to_original::<PddbKeyRequest, _>().unwrap();
                 let bname = if req.basis_specified {
@@ -1049,8 +1103,18 @@ fn wrapped_main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 log::debug!("attempting to remove dict {} basis {:?}", dict, bname);
-                match basis_cache.dict_remove(&mut pddb_os, dict, bname, false) {
+                if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname, dict, PddbAccess::WRITE) {
+                    req.result = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                let event_basis = bname.or(basis_cache.basis_latest()).map(|b| b.to_string());
+                match basis_cache.dict_remove(&mut pddb_os, dict, bname, paranoid) {
                     Ok(_) => {
+                        if let Some(b) = event_basis {
+                            watchers.dict_deleted(&b, dict);
+                            acl.dict_removed(&mut pddb_os, &mut basis_cache, &b, dict);
+                        }
                         let mut evict_list = Vec::<ApiToken>::new();
                         // check to see if we need to eliminate any ApiTokens as a result of this.
                         for (token, rec) in token_dict.iter() {
@@ -1089,10 +1153,228 @@ fn wrapped_main() -> ! {
                 }
                 buffer.replace(req).unwrap();
             }
+            Opcode::CompactDict => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbKeyRequest = buffer.to_original::<PddbKeyRequest, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap())
+                } else {
+                    None
+                };
+                let dict = req.dict.as_str().expect("dict utf-8 decode error");
+                let dname = if dict.len() > 0 { Some(dict) } else { None };
+                log::debug!("attempting to compact dict {:?} basis {:?}", dname, bname);
+                match basis_cache.dict_compact(&mut pddb_os, dname, bname) {
+                    Ok(count) => {
+                        log::info!("compacted {} dictionaries", count);
+                        req.result = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => {
+                        match e.kind() {
+                            std::io::ErrorKind::NotFound => req.result = PddbRequestCode::NotFound,
+                            std::io::ErrorKind::OutOfMemory => req.result = PddbRequestCode::NoFreeSpace,
+                            _ => req.result = PddbRequestCode::InternalError,
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::Fsck => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbFsckRequest = buffer.to_original::<PddbFsckRequest, _>().unwrap();
+                if basis_cache.basis_count() == 0 {
+                    req.result = PddbRequestCode::NotMounted;
+                } else {
+                    match basis_cache.fsck(&mut pddb_os, req.mode) {
+                        Ok(report) => {
+                            req.report = report;
+                            req.result = PddbRequestCode::NoErr;
+                        }
+                        Err(e) => {
+                            log::error!("fsck failed: {:?}", e);
+                            req.result = PddbRequestCode::InternalError;
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnBegin => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnRequest = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                if basis_cache.basis_count() == 0 {
+                    req.result = PddbRequestCode::NotMounted;
+                } else {
+                    // ids are random, so a process can't guess its way into another one's transaction
+                    let mut id = pddb_os.trng_u32();
+                    while id == 0 || transactions.contains_key(&(msg.sender.pid(), id)) {
+                        id = pddb_os.trng_u32();
+                    }
+                    let basis = if req.basis_specified {
+                        Some(req.basis.as_str().unwrap().to_string())
+                    } else {
+                        None
+                    };
+                    transactions.insert((msg.sender.pid(), id), Transaction::new(basis));
+                    req.id = id;
+                    req.result = PddbRequestCode::NoErr;
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnWrite => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnRequest = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                req.result = if req.len as usize > TXN_CHUNK_LEN {
+                    PddbRequestCode::InvalidRequest
+                } else if let Some(txn) = transactions.get_mut(&(msg.sender.pid(), req.id)) {
+                    let dict = req.dict.as_str().expect("dict utf-8 decode error");
+                    let key = req.key.as_str().expect("key utf-8 decode error");
+                    if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), txn.basis.as_deref(), dict, PddbAccess::WRITE) {
+                        PddbRequestCode::AccessDenied
+                    } else {
+                        match txn.write(dict, key, req.offset as usize, &req.data[..req.len as usize]) {
+                            Ok(_) => PddbRequestCode::NoErr,
+                            Err(e) if e.kind() == ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+                            Err(_) => PddbRequestCode::InternalError,
+                        }
+                    }
+                } else {
+                    PddbRequestCode::NotFound
+                };
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnDelete => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnRequest = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                req.result = if let Some(txn) = transactions.get_mut(&(msg.sender.pid(), req.id)) {
+                    let dict = req.dict.as_str().expect("dict utf-8 decode error");
+                    if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), txn.basis.as_deref(), dict, PddbAccess::WRITE) {
+                        PddbRequestCode::AccessDenied
+                    } else {
+                        txn.delete(dict, req.key.as_str().expect("key utf-8 decode error"));
+                        PddbRequestCode::NoErr
+                    }
+                } else {
+                    PddbRequestCode::NotFound
+                };
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnCommit => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnRequest = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                req.result = if let Some(txn) = transactions.remove(&(msg.sender.pid(), req.id)) {
+                    // work out what each op means to a watcher before the commit changes the answer
+                    let event_basis = txn.basis.as_deref().or(basis_cache.basis_latest()).map(|b| b.to_string());
+                    let events: Vec<(PddbEventKind, &str, &str)> = txn.ops.iter().filter_map(|op| {
+                        let (dict, key) = match op {
+                            TxnOp::Write { dict, key, .. } | TxnOp::Delete { dict, key } => (dict, key),
+                        };
+                        let exists = basis_cache.key_attributes(&mut pddb_os, dict, key, txn.basis.as_deref()).is_ok();
+                        match (op, exists) {
+                            (TxnOp::Write { .. }, true) => Some((PddbEventKind::Updated, dict.as_str(), key.as_str())),
+                            (TxnOp::Write { .. }, false) => Some((PddbEventKind::Created, dict.as_str(), key.as_str())),
+                            (TxnOp::Delete { .. }, true) => Some((PddbEventKind::Deleted, dict.as_str(), key.as_str())),
+                            // deleting a key that isn't there is not a change
+                            (TxnOp::Delete { .. }, false) => None,
+                        }
+                    }).collect();
+                    match basis_cache.txn_commit(&mut pddb_os, &txn.ops, txn.basis.as_deref()) {
+                        Ok(_) => {
+                            if let Some(b) = event_basis {
+                                for (kind, dict, key) in events {
+                                    watchers.key_changed(kind, &b, dict, key);
+                                }
+                            }
+                            PddbRequestCode::NoErr
+                        }
+                        Err(e) => {
+                            log::error!("couldn't commit transaction: {:?}", e);
+                            match e.kind() {
+                                ErrorKind::NotFound => PddbRequestCode::NotFound,
+                                ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+                                _ => PddbRequestCode::InternalError,
+                            }
+                        }
+                    }
+                } else {
+                    PddbRequestCode::NotFound
+                };
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnAbort => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnRequest = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                req.result = if transactions.remove(&(msg.sender.pid(), req.id)).is_some() {
+                    PddbRequestCode::NoErr
+                } else {
+                    PddbRequestCode::NotFound
+                };
+                buffer.replace(req).unwrap();
+            }
+            Opcode::Watch => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbWatchRequest = buffer.to_original::<PddbWatchRequest, _>().unwrap();
+                // watches don't need a mounted PDDB: a client may want to hear about the mount itself
+                let bname = if req.basis_specified { Some(req.basis.as_str().unwrap()) } else { None };
+                req.result = if acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname, req.dict.as_str().unwrap(), PddbAccess::LIST) {
+                    watchers.add(msg.sender.pid(), &req)
+                } else {
+                    PddbRequestCode::AccessDenied
+                };
+                buffer.replace(req).unwrap();
+            }
+            Opcode::Unwatch => msg_blocking_scalar_unpack!(msg, id, _, _, _, {
+                if let Some(conn) = watchers.remove(msg.sender.pid(), id as u32) {
+                    if !watchers.uses_conn(conn) && !token_dict.values().any(|r| r.conn == Some(conn)) {
+                        unsafe{xous::disconnect(conn).expect("couldn't disconnect from callback server")};
+                    }
+                    xous::return_scalar(msg.sender, PddbRetcode::Ok as usize).expect("couldn't ack Unwatch");
+                } else {
+                    xous::return_scalar(msg.sender, PddbRetcode::NotFound as usize).expect("couldn't ack Unwatch");
+                }
+            }),
+            Opcode::Disconnect => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                // the process is done with the PDDB; whatever it left staged can never be committed
+                let pid = msg.sender.pid();
+                transactions.retain(|&(owner, _), _| owner != pid);
+                acl.process_gone(pid);
+                xous::return_scalar(msg.sender, PddbRetcode::Ok as usize).expect("couldn't ack Disconnect");
+            }),
+            Opcode::Identify => msg_blocking_scalar_unpack!(msg, t0, t1, t2, t3, {
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                // only the GAM can vouch for a token, and it never hands them out, so a process can't pose as another
+                if gam.is_none() {
+                    gam = gam::Gam::new(&xns).ok();
+                }
+                let app = gam.as_ref().and_then(|gam| gam.token_to_name(token).ok().flatten());
+                let rcode = match app {
+                    Some(app) if acl.identify(msg.sender.pid(), &app) => PddbRetcode::Ok,
+                    _ => PddbRetcode::AccessDenied,
+                };
+                xous::return_scalar(msg.sender, rcode as usize).expect("couldn't ack Identify");
+            }),
+            Opcode::ClaimDict => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbAclRequest = buffer.to_original::<PddbAclRequest, _>().unwrap();
+                let bname = if req.basis_specified { Some(req.basis.as_str().unwrap()) } else { None };
+                req.result = acl.claim(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname,
+                    req.dict.as_str().expect("dict utf-8 decode error"));
+                buffer.replace(req).unwrap();
+            }
+            Opcode::SetDictAccess => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbAclRequest = buffer.to_original::<PddbAclRequest, _>().unwrap();
+                let bname = if req.basis_specified { Some(req.basis.as_str().unwrap()) } else { None };
+                req.result = acl.set_access(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname,
+                    req.dict.as_str().expect("dict utf-8 decode error"),
+                    req.app.as_str().expect("app utf-8 decode error"),
+                    PddbAccess::from_bits_truncate(req.access),
+                );
+                buffer.replace(req).unwrap();
+            }
             Opcode::DeleteDictStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(err) = libstd::delete_dict(mem, &mut pddb_os, &mut basis_cache) {
+                    if let Err(err) = libstd::delete_dict(mem, &mut pddb_os, &mut basis_cache, &mut watchers, &mut acl, msg.sender.pid()) {
                         mem.offset = xous::MemoryAddress::new(err as usize);
                     }
                 }
@@ -1114,6 +1396,11 @@ fn wrapped_main() -> ! {
                     } else {
                         None
                     };
+                    if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname, &token_record.dict, PddbAccess::LIST) {
+                        req.code = PddbRequestCode::AccessDenied;
+                        buffer.replace(req).unwrap();
+                        continue;
+                    }
                     match basis_cache.key_attributes(&mut pddb_os, &token_record.dict, &token_record.key, bname) {
                         Ok(attr) => {
                             buffer.replace(PddbKeyAttrIpc::from_attributes(attr, req.token)).unwrap();
@@ -1143,6 +1430,12 @@ fn wrapped_main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 log::debug!("counting keys in dict {} basis {:?}", dict, bname);
+                if !acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), bname, dict, PddbAccess::LIST) {
+                    key_token = None;
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
                 match basis_cache.key_list(&mut pddb_os, dict, bname) {
                     Ok(list) => {
                         log::debug!("count: {}", list.len());
@@ -1201,7 +1494,7 @@ fn wrapped_main() -> ! {
             Opcode::ListKeyStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(e) = libstd::list_key(mem, &mut pddb_os, &mut basis_cache) {
+                    if let Err(e) = libstd::list_key(mem, &mut pddb_os, &mut basis_cache, &mut acl, msg.sender.pid()) {
                         mem.offset = xous::MemoryAddress::new(e as usize);
                     }
                 }
@@ -1329,7 +1622,9 @@ fn wrapped_main() -> ! {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let pbuf = PddbBuf::from_slice_mut(buffer.as_mut()); // direct translation, no serialization necessary for performance
                 let token = pbuf.token;
-                if let Some(rec) = token_dict.get(&token) {
+                if let Some(rec) = token_dict.get(&token).filter(|rec|
+                    acl.check(&mut pddb_os, &mut basis_cache, msg.sender.pid(), rec.basis.as_deref(), &rec.dict, PddbAccess::WRITE)
+                ) {
                     for basis in basis_cache.access_list().iter() {
                         let temp = if let Some (name) = &rec.basis {Some(name)} else {Some(basis)};
                         log::debug!("write (spec: {:?}){:?} {}", rec.basis, temp, rec.key);
@@ -1340,6 +1635,7 @@ fn wrapped_main() -> ! {
                             false
                         ) {
                             Ok(_) => {
+                                watchers.key_changed(PddbEventKind::Updated, temp.unwrap(), &rec.dict, &rec.key);
                                 pbuf.retcode = PddbRetcode::Ok;
                                 break;
                             }
@@ -1351,6 +1647,9 @@ fn wrapped_main() -> ! {
                             }
                         }
                     }
+                } else if token_dict.contains_key(&token) {
+                    // the key is there, but its owner doesn't let this app write to it
+                    pbuf.retcode = PddbRetcode::AccessDenied;
                 } else {
                     pbuf.retcode = PddbRetcode::BasisLost;
                 }
@@ -1364,7 +1663,7 @@ fn wrapped_main() -> ! {
                 let fd = (msg.body.id() >> 16) & 0xffff;
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(e) = libstd::write_key(mem, &mut pddb_os, &mut basis_cache, fd_mapping.entry(msg.sender.pid()).or_default(), fd) {
+                    if let Err(e) = libstd::write_key(mem, &mut pddb_os, &mut basis_cache, fd_mapping.entry(msg.sender.pid()).or_default(), fd, &mut watchers, &mut acl, msg.sender.pid()) {
                         mem.offset = xous::MemoryAddress::new(e as usize);
                     }
                 }
@@ -1390,6 +1689,17 @@ fn wrapped_main() -> ! {
                 }
                 modals.show_notification(&note, None).expect("couldn't show basis list");
             },
+            Opcode::MenuCompact => {
+                let mut note = String::from(t!("pddb.menu.compact_done", xous::LANG));
+                match basis_cache.dict_compact(&mut pddb_os, None, None) {
+                    Ok(count) => note.push_str(&count.to_string()),
+                    Err(e) => {
+                        log::warn!("compaction failed: {:?}", e);
+                        note.push_str(&format!("{:?}", e.kind()));
+                    }
+                }
+                modals.show_notification(&note, None).expect("couldn't show compaction result");
+            },
             Opcode::RekeyPddb => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let rekey_op = buffer.to_original::<PddbRekeyOp, _>().unwrap();
@@ -1420,7 +1730,7 @@ fn wrapped_main() -> ! {
                         basis_cache = BasisCache::new(); // this effectively erases the PDDB from memory
                         if let Some(sys_basis) = pddb_os.pddb_mount() {
                             log::info!("remount successful");
-                            basis_cache.basis_add(sys_basis);
+                            basis_cache.basis_add(&mut pddb_os, sys_basis);
                         } else {
                             log::info!("remount failed");
                         }
@@ -1511,7 +1821,7 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
     if pw_state == PasswordState::Correct {
         if let Some(sys_basis) = pddb_os.pddb_mount() {
             log::info!("PDDB mount operation finished successfully");
-            basis_cache.basis_add(sys_basis);
+            basis_cache.basis_add(pddb_os, sys_basis);
             return true
         }
     }
@@ -1573,7 +1883,7 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
 
                 if let Some(sys_basis) = pddb_os.pddb_mount() {
                     log::info!("PDDB mount operation finished successfully");
-                    basis_cache.basis_add(sys_basis);
+                    basis_cache.basis_add(pddb_os, sys_basis);
                     true
                 } else {
                     log::error!("Despite formatting, no PDDB was found!");
@@ -1598,7 +1908,7 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
             pddb_os.dbg_dump(Some("full".to_string()), None);
             if let Some(sys_basis) = pddb_os.pddb_mount() {
                 log::info!("PDDB mount operation finished successfully");
-                basis_cache.basis_add(sys_basis);
+                basis_cache.basis_add(pddb_os, sys_basis);
                 true
             } else {
                 log::error!("Despite formatting, no PDDB was found!");
@@ -1627,7 +1937,7 @@ pub(crate) fn manual_testcase(hw: &mut PddbOs) {
     log::info!("Attempting to mount the PDDB");
     if let Some(sys_basis) = hw.pddb_mount() {
         log::info!("PDDB mount operation finished successfully");
-        basis_cache.basis_add(sys_basis);
+        basis_cache.basis_add(hw, sys_basis);
     } else {
         log::info!("PDDB did not mount; did you remember to format the PDDB region?");
     }
@@ -1685,7 +1995,7 @@ pub(crate) fn hw_testcase(pddb_os: &mut PddbOs) {
     let mut basis_cache = BasisCache::new();
     if let Some(sys_basis) = pddb_os.pddb_mount() {
         log::info!("PDDB mount operation finished successfully");
-        basis_cache.basis_add(sys_basis);
+        basis_cache.basis_add(pddb_os, sys_basis);
     } else {
         log::info!("PDDB did not mount; did you remember to format the PDDB region?");
     }
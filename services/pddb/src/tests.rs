@@ -3,7 +3,8 @@ use rand_chacha::rand_core::RngCore;
 use rand_chacha::rand_core::SeedableRng;
 use crate::*;
 use core::sync::atomic::{AtomicU64, Ordering};
-use std::collections::{BTreeSet, HashSet};
+use core::mem::size_of;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::io::Result;
 
 const UPPER_BOUND: usize = 9000;
@@ -51,7 +52,7 @@ pub(crate) fn create_basis_testcase(hw: &mut PddbOs, basis_cache: &mut BasisCach
 
     hw.pddb_format(false, None).unwrap();
     let sys_basis = hw.pddb_mount().expect("couldn't mount system basis");
-        basis_cache.basis_add(sys_basis);
+        basis_cache.basis_add(hw, sys_basis);
 
     let num_dicts = maybe_num_dicts.unwrap_or(4);
     let num_keys = maybe_num_keys.unwrap_or(34);
@@ -342,6 +343,422 @@ pub(crate) fn delete_pattern(hw: &mut PddbOs, basis_cache: &mut BasisCache,
     Ok(())
 }
 
+/// Reads a multi-page key so its pages land in the large key cache, then patches it across a
+/// page boundary, checking that every read reflects what was written.
+pub(crate) fn large_cache_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const DICT: &'static str = "largecache";
+    const KEY: &'static str = "pages";
+    let mut expected: Vec<u8> = (0..VPAGE_SIZE * 3 + 100).map(|i| (i % 251) as u8).collect();
+    basis_cache.key_update(hw, DICT, KEY, &expected, None, None, None, false)?;
+    let mut readback = vec![0u8; expected.len()];
+    for _ in 0..2 {
+        assert!(basis_cache.key_read(hw, DICT, KEY, &mut readback, Some(0), None)? == expected.len());
+        assert!(readback == expected, "large key read back incorrectly");
+    }
+
+    let patch = [0xA5u8; 64];
+    let patch_offset = VPAGE_SIZE * 2 - 32;
+    basis_cache.key_update(hw, DICT, KEY, &patch, Some(patch_offset), None, None, false)?;
+    expected[patch_offset..patch_offset + patch.len()].copy_from_slice(&patch);
+    assert!(basis_cache.key_read(hw, DICT, KEY, &mut readback, Some(0), None)? == expected.len());
+    assert!(readback == expected, "large key cache was not invalidated by a patch");
+
+    basis_cache.dict_remove(hw, DICT, None, false)?;
+    basis_cache.sync(hw, None)
+}
+
+/// Paranoid-removes a small key that shares its pool with other keys, and checks that its neighbours
+/// survive the re-write of the pool, and that the key is gone on disk without any further sync.
+pub(crate) fn secure_erase_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const DICT: &'static str = "secure";
+    let mut expected = HashMap::<String, Vec::<u8>>::new();
+    for i in 0..4 {
+        let name = format!("key{}", i);
+        let data: Vec<u8> = (0..300 + i * 50).map(|j| (j + i) as u8).collect();
+        basis_cache.key_update(hw, DICT, &name, &data, None, None, None, false)?;
+        expected.insert(name, data);
+    }
+    let large: Vec<u8> = (0..VPAGE_SIZE * 2).map(|j| (j % 13) as u8).collect();
+    basis_cache.key_update(hw, DICT, "large", &large, None, None, None, false)?;
+    basis_cache.sync(hw, None)?;
+
+    basis_cache.key_remove(hw, DICT, "key1", None, true)?;
+    basis_cache.key_remove(hw, DICT, "large", None, true)?;
+    expected.remove("key1");
+    for (name, data) in expected.iter() {
+        let mut readback = vec![0u8; data.len()];
+        assert!(basis_cache.key_read(hw, DICT, name, &mut readback, Some(0), None)? == data.len());
+        assert!(readback == *data, "{} was damaged by the secure erase of a neighbour", name);
+    }
+
+    let mut remount_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    remount_cache.basis_add(hw, basis);
+    let keys = remount_cache.key_list(hw, DICT, None)?;
+    assert!(keys.len() == expected.len(), "erased keys are still on disk: {:?}", keys);
+    for (name, data) in expected.iter() {
+        let mut readback = vec![0u8; data.len()];
+        assert!(remount_cache.key_read(hw, DICT, name, &mut readback, Some(0), None)? == data.len());
+        assert!(readback == *data, "{} did not survive a remount after the secure erase", name);
+    }
+
+    basis_cache.dict_remove(hw, DICT, None, true)?;
+    basis_cache.sync(hw, None)
+}
+
+fn snapshot_basis(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis_name: Option<&str>) -> Result<HashMap::<String, Vec::<u8>>> {
+    let mut snapshot = HashMap::<String, Vec::<u8>>::new();
+    for dict in basis_cache.dict_list(hw, basis_name).iter() {
+        for key in basis_cache.key_list(hw, dict, basis_name)?.iter() {
+            let attrs = basis_cache.key_attributes(hw, dict, key, basis_name)?;
+            let mut data = vec![0u8; attrs.len];
+            let readlen = basis_cache.key_read(hw, dict, key, &mut data, Some(0), basis_name)?;
+            assert!(readlen == attrs.len, "short read on {}:{}", dict, key);
+            snapshot.insert(format!("{}:{}", dict, key), data);
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Compacts every dictionary in the system basis, and checks that every key reads back
+/// identically, both out of the cache and after a fresh mount of the basis.
+pub(crate) fn compact_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    let basis_name = Some(PDDB_DEFAULT_SYSTEM_BASIS);
+    let before = snapshot_basis(hw, basis_cache, basis_name)?;
+    let compacted = basis_cache.dict_compact(hw, None, basis_name)?;
+    log::info!("compacted {} dictionaries holding {} keys", compacted, before.len());
+    assert!(compacted > 0, "no dictionaries were compacted");
+
+    let after = snapshot_basis(hw, basis_cache, basis_name)?;
+    assert!(before == after, "key contents changed across compaction");
+
+    let mut remount_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    remount_cache.basis_add(hw, basis);
+    let remounted = snapshot_basis(hw, &mut remount_cache, basis_name)?;
+    assert!(before == remounted, "key contents changed across compaction and remount");
+    Ok(())
+}
+
+/// Maps a page of noise into the system basis without any structure referencing it, which is what
+/// an update interrupted between allocating its data page and writing the descriptor leaves behind.
+fn inject_orphan(hw: &mut PddbOs, vaddr: u64) {
+    let mut basis = hw.pddb_mount().expect("couldn't mount system basis");
+    let mut pp = hw.try_fast_space_alloc().expect("no free space to inject an orphan");
+    pp.set_valid(true);
+    let mut page = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+    hw.trng_slice(&mut page);
+    hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut page, &pp);
+    pp.set_clean(false);
+    basis.v2p_map.insert(VirtAddr::new(vaddr).unwrap(), pp);
+    basis.pt_sync(hw);
+}
+
+/// Checks that a healthy system basis passes fsck, then plants orphaned pages and checks that they are
+/// found, quarantined or released, and that the keys read back identically afterwards.
+pub(crate) fn fsck_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    let basis_name = Some(PDDB_DEFAULT_SYSTEM_BASIS);
+    let before = snapshot_basis(hw, basis_cache, basis_name)?;
+    let mut check_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    check_cache.basis_add(hw, basis);
+    let report = check_cache.fsck(hw, FsckMode::Check)?;
+    assert!(report.is_clean(), "fsck flagged a healthy basis: {:?}", report);
+
+    // an orphan in the large pool, far beyond anything allocated, is set aside
+    inject_orphan(hw, LARGE_POOL_START + 1024 * LARGE_FILE_MAX_SIZE);
+    let mut check_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    check_cache.basis_add(hw, basis);
+    let report = check_cache.fsck(hw, FsckMode::Check)?;
+    assert!(report.orphaned == 1 && report.repaired == 0, "orphan not found: {:?}", report);
+    let report = check_cache.fsck(hw, FsckMode::Quarantine)?;
+    assert!(report.orphaned == 1 && report.quarantined == 1, "orphan not quarantined: {:?}", report);
+    let report = check_cache.fsck(hw, FsckMode::Check)?;
+    assert!(report.is_clean() && report.quarantined == 1, "quarantine didn't stick: {:?}", report);
+
+    // an orphan in the descriptor area of an unallocated dictionary is released
+    inject_orphan(hw, DICT_MAXCOUNT as u64 * DICT_VSIZE + VPAGE_SIZE as u64);
+    let mut check_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    check_cache.basis_add(hw, basis);
+    let report = check_cache.fsck(hw, FsckMode::Repair)?;
+    assert!(report.orphaned == 1 && report.repaired == 1, "orphan not repaired: {:?}", report);
+    let mut check_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    check_cache.basis_add(hw, basis);
+    let report = check_cache.fsck(hw, FsckMode::Check)?;
+    assert!(report.is_clean(), "repair didn't stick: {:?}", report);
+
+    let after = snapshot_basis(hw, &mut check_cache, basis_name)?;
+    assert!(before == after, "key contents changed across fsck");
+    Ok(())
+}
+
+/// Commits a transaction that rewrites a record, its index and a counter, creates a key and a dictionary,
+/// and removes keys, with a power loss emulated before each of the flash writes the commit does in turn.
+/// After every one, a fresh mount has to show either none or all of the updates, and once a power loss
+/// shows all of them, so must every later one.
+pub(crate) fn txn_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    let basis_name = Some(PDDB_DEFAULT_SYSTEM_BASIS);
+    let blob_old: Vec<u8> = (0..VPAGE_SIZE + 100).map(|j| (j % 7) as u8).collect();
+    basis_cache.key_update(hw, "txn.records", "record1", b"old record", None, None, None, true)?;
+    basis_cache.key_update(hw, "txn.records", "blob", &blob_old, None, None, None, true)?;
+    basis_cache.key_update(hw, "txn.index", "index", b"old index", None, None, None, true)?;
+    basis_cache.key_update(hw, "txn.index", "counter", &1u32.to_le_bytes(), None, None, None, true)?;
+    basis_cache.key_update(hw, "txn.index", "stale", b"to be removed", None, None, None, true)?;
+    basis_cache.sync(hw, None)?;
+    let mut storage = EmuStorage::new();
+    let image = storage.snapshot();
+    let old = snapshot_basis(hw, basis_cache, basis_name)?;
+
+    let blob_new: Vec<u8> = (0..VPAGE_SIZE * 2 + 300).map(|j| (j % 11) as u8).collect();
+    let ops = vec![
+        TxnOp::Write { dict: "txn.records".to_string(), key: "record1".to_string(), data: b"new record, longer than the old one".to_vec() },
+        TxnOp::Write { dict: "txn.records".to_string(), key: "blob".to_string(), data: blob_new },
+        TxnOp::Write { dict: "txn.records".to_string(), key: "record2".to_string(), data: b"a new record".to_vec() },
+        TxnOp::Write { dict: "txn.created".to_string(), key: "marker".to_string(), data: b"new dictionary".to_vec() },
+        TxnOp::Write { dict: "txn.index".to_string(), key: "index".to_string(), data: b"new index".to_vec() },
+        TxnOp::Write { dict: "txn.index".to_string(), key: "counter".to_string(), data: 2u32.to_le_bytes().to_vec() },
+        TxnOp::Delete { dict: "txn.index".to_string(), key: "stale".to_string() },
+        TxnOp::Delete { dict: "txn.index".to_string(), key: "never existed".to_string() },
+    ];
+    let mut new = old.clone();
+    for op in ops.iter() {
+        match op {
+            TxnOp::Write { dict, key, data } => { new.insert(format!("{}:{}", dict, key), data.to_vec()); }
+            TxnOp::Delete { dict, key } => { new.remove(&format!("{}:{}", dict, key)); }
+        }
+    }
+
+    // an uninterrupted commit, to count the writes it does
+    let mut run_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    run_cache.basis_add(hw, basis);
+    storage.power_loss_after(None);
+    run_cache.txn_commit(hw, &ops, basis_name)?;
+    let writes = storage.write_count();
+    log::info!("a commit of {} ops takes {} writes", ops.len(), writes);
+    assert!(snapshot_basis(hw, &mut run_cache, basis_name)? == new, "transaction was not applied");
+
+    let mut committed_at = None;
+    for n in 0..writes {
+        storage.restore(&image);
+        let mut run_cache = BasisCache::new();
+        let basis = hw.pddb_mount().expect("couldn't remount system basis");
+        run_cache.basis_add(hw, basis);
+        storage.power_loss_after(Some(n));
+        run_cache.txn_commit(hw, &ops, basis_name)?;
+        let crashed = storage.power_loss_image().expect("the commit did fewer writes than before");
+        storage.power_loss_after(None);
+        storage.restore(&crashed);
+
+        // the replay happens as the basis joins the cache, and retires the journal
+        let basis = hw.pddb_mount().expect("couldn't mount the system basis after a power loss");
+        let mut check_cache = BasisCache::new();
+        check_cache.basis_add(hw, basis);
+        let remounted = hw.pddb_mount().expect("couldn't remount the system basis after the replay");
+        assert!(!txn_journal_present(&remounted), "power loss at write {}: the journal was left behind", n);
+        let state = snapshot_basis(hw, &mut check_cache, basis_name)?;
+        if state == new {
+            committed_at.get_or_insert(n);
+        } else {
+            assert!(state == old, "power loss at write {} of {}: the transaction was partially applied", n, writes);
+            assert!(committed_at.is_none(), "power loss at write {}: a transaction committed at write {:?} was lost", n, committed_at);
+        }
+    }
+    log::info!("the transaction is committed from write {:?} of {} on", committed_at, writes);
+
+    // put back the state the caller's cache knows about, and leave it the way the test found it
+    storage.restore(&image);
+    hw.pddb_mount().expect("couldn't remount system basis");
+    basis_cache.txn_commit(hw, &ops, basis_name)?;
+    assert!(snapshot_basis(hw, basis_cache, basis_name)? == new, "transaction was not applied");
+    for dict in ["txn.records", "txn.index", "txn.created"].iter() {
+        basis_cache.dict_remove(hw, dict, None, false)?;
+    }
+    basis_cache.sync(hw, None)
+}
+
+/// Where the power loss sweeps pick their fault points from. The seed of the run is used unless
+/// `XOUS_PDDB_FAULT_SEED` is set, so a failure reported by CI can be replayed on its own.
+fn power_loss_seed() -> u64 {
+    match std::env::var("XOUS_PDDB_FAULT_SEED").ok().and_then(|s| s.parse::<u64>().ok()) {
+        Some(seed) => seed,
+        None => xous::TESTING_RNG_SEED.load(Ordering::SeqCst),
+    }
+}
+
+/// Runs `op` against the system basis with a power loss emulated at up to `runs` of the flash writes it
+/// does, picked by `rng`; about half of the power losses also tear the write they strike part way. After
+/// every one, the basis has to mount, repair to a clean fsck, and show the keys that don't start with one
+/// of the `volatile` prefixes all as they were before `op`, or all as `op` left them: a mix of the two
+/// fails. Keys matching `volatile` are the ones `op` updates without a transaction, so they may be lost,
+/// but not in a way that fsck can't clean up.
+///
+/// The caller's cache has to be in sync with the flash. `op` is run on it once more at the end, so the
+/// PDDB is left as if `op` had just been run.
+fn power_loss_sweep<F>(hw: &mut PddbOs, basis_cache: &mut BasisCache, rng: &mut ChaCha8Rng, seed: u64,
+    name: &str, runs: usize, volatile: &[&str], mut op: F) -> Result<()>
+where F: FnMut(&mut PddbOs, &mut BasisCache) -> Result<()> {
+    let basis_name = Some(PDDB_DEFAULT_SYSTEM_BASIS);
+    let mut storage = EmuStorage::new();
+    let image = storage.snapshot();
+    let old = snapshot_basis(hw, basis_cache, basis_name)?;
+
+    // an uninterrupted run, to count the writes it does
+    let mut run_cache = BasisCache::new();
+    let basis = hw.pddb_mount().expect("couldn't remount system basis");
+    run_cache.basis_add(hw, basis);
+    storage.inject_power_loss(None);
+    op(hw, &mut run_cache)?;
+    let writes = storage.write_sizes();
+    let new = snapshot_basis(hw, &mut run_cache, basis_name)?;
+
+    let mut faults = Vec::<PowerLoss>::new();
+    for n in 0..writes.len() {
+        if writes.len() <= runs || (rng.next_u32() as usize % writes.len()) < runs {
+            let torn = if writes[n] > 1 && rng.next_u32() & 1 == 1 {
+                Some(1 + rng.next_u32() as usize % (writes[n] - 1))
+            } else {
+                None
+            };
+            faults.push(PowerLoss { at: n, torn });
+        }
+    }
+    log::info!("{}: {} writes, trying a power loss at {} of them", name, writes.len(), faults.len());
+
+    let stable = |snapshot: &HashMap<String, Vec<u8>>| -> HashMap<String, Vec<u8>> {
+        snapshot.iter()
+            .filter(|(k, _)| !volatile.iter().any(|v| k.starts_with(v)))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    };
+    let (stable_old, stable_new) = (stable(&old), stable(&new));
+    for fault in faults {
+        storage.restore(&image);
+        let mut run_cache = BasisCache::new();
+        let basis = hw.pddb_mount().expect("couldn't remount system basis");
+        run_cache.basis_add(hw, basis);
+        storage.inject_power_loss(Some(fault));
+        op(hw, &mut run_cache)?;
+        let crashed = storage.power_loss_image().expect("the operation did fewer writes than before");
+        storage.inject_power_loss(None);
+        storage.restore(&crashed);
+
+        let basis = match hw.pddb_mount() {
+            Some(basis) => basis,
+            None => panic!("{}: {:?} of {} (seed {}): the system basis doesn't mount", name, fault, writes.len(), seed),
+        };
+        let mut check_cache = BasisCache::new();
+        check_cache.basis_add(hw, basis);
+        let report = check_cache.fsck(hw, FsckMode::Check)?;
+        log::debug!("{}: {:?}: {:?}", name, fault, report);
+        if !report.is_clean() {
+            check_cache.fsck(hw, FsckMode::Repair)?;
+            let mut check_cache = BasisCache::new();
+            let basis = hw.pddb_mount().expect("couldn't remount system basis after a repair");
+            check_cache.basis_add(hw, basis);
+            let report = check_cache.fsck(hw, FsckMode::Check)?;
+            assert!(report.is_clean(), "{}: {:?} of {} (seed {}): repair didn't stick: {:?}",
+                name, fault, writes.len(), seed, report);
+        }
+        let mut check_cache = BasisCache::new();
+        let basis = hw.pddb_mount().expect("couldn't remount system basis");
+        check_cache.basis_add(hw, basis);
+        let state = stable(&snapshot_basis(hw, &mut check_cache, basis_name)?);
+        if state != stable_old && state != stable_new {
+            let mut mixed: Vec<&String> = stable_old.keys().chain(stable_new.keys()).chain(state.keys())
+                .filter(|k| state.get(*k) != stable_old.get(*k))
+                .collect();
+            mixed.sort();
+            mixed.dedup();
+            panic!("{}: {:?} of {} (seed {}): neither the old state nor the new one, these differ from the old: {:?}",
+                name, fault, writes.len(), seed, mixed);
+        }
+    }
+
+    // put back the state the caller's cache knows about, and move it along
+    storage.restore(&image);
+    hw.pddb_mount().expect("couldn't remount system basis");
+    op(hw, basis_cache)?;
+    assert!(snapshot_basis(hw, basis_cache, basis_name)? == new, "{}: the operation didn't repeat", name);
+    Ok(())
+}
+
+/// Sweeps power losses, some of them tearing a page write, across the basic updates of a key and a
+/// dictionary, and across a compaction and a transaction commit, which have to come out all or nothing.
+pub(crate) fn power_loss_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const RUNS: usize = 24;
+    let seed = power_loss_seed();
+    log::info!("power loss seed: {} (set XOUS_PDDB_FAULT_SEED to replay it)", seed);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let large: Vec<u8> = (0..VPAGE_SIZE * 3 + 500).map(|j| (j % 13) as u8).collect();
+
+    basis_cache.key_update(hw, "pl.keep", "small", b"a bystander", None, None, None, true)?;
+    basis_cache.key_update(hw, "pl.keep", "large", &large, None, None, None, true)?;
+    basis_cache.key_update(hw, "pl.work", "existing", b"old contents", None, None, None, true)?;
+    basis_cache.sync(hw, None)?;
+
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "small key create", RUNS, &["pl.work:small"], |hw, cache| {
+        cache.key_update(hw, "pl.work", "small", b"new small key", None, None, None, true)?;
+        cache.sync(hw, None)
+    })?;
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "key extend", RUNS, &["pl.work:existing"], |hw, cache| {
+        cache.key_update(hw, "pl.work", "existing", &[0x5a; 2000], None, None, None, true)?;
+        cache.sync(hw, None)
+    })?;
+    let big: Vec<u8> = (0..VPAGE_SIZE * 8 + 123).map(|j| (j % 17) as u8).collect();
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "large key create", RUNS, &["pl.work:big"], |hw, cache| {
+        cache.key_update(hw, "pl.work", "big", &big, None, None, None, true)?;
+        cache.sync(hw, None)
+    })?;
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "key remove", RUNS, &["pl.work:small"], |hw, cache| {
+        cache.key_remove(hw, "pl.work", "small", None, false)?;
+        cache.sync(hw, None)
+    })?;
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "dict add", RUNS, &["pl.added:"], |hw, cache| {
+        for j in 0..4 {
+            cache.key_update(hw, "pl.added", &format!("key{}", j), &[j as u8; 700], None, None, None, true)?;
+        }
+        cache.sync(hw, None)
+    })?;
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "dict remove", RUNS, &["pl.added:"], |hw, cache| {
+        cache.dict_remove(hw, "pl.added", None, false)?;
+        cache.sync(hw, None)
+    })?;
+
+    // a compaction only moves keys around, so every key has to come through it; the dictionary is
+    // fragmented first, with keys of all sizes and holes where some of them were removed
+    for j in 0..48 {
+        let data: Vec<u8> = (0..(37 * j + 11) % 1900 + 1).map(|i| (i + j) as u8).collect();
+        basis_cache.key_update(hw, "pl.frag", &format!("key{}", j), &data, None, None, None, true)?;
+    }
+    basis_cache.key_update(hw, "pl.frag", "large", &large, None, None, None, true)?;
+    for j in (0..48).step_by(3) {
+        basis_cache.key_remove(hw, "pl.frag", &format!("key{}", j), None, false)?;
+    }
+    basis_cache.sync(hw, None)?;
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "dict compact", RUNS, &[], |hw, cache| {
+        cache.dict_compact(hw, Some("pl.frag"), Some(PDDB_DEFAULT_SYSTEM_BASIS)).map(|_| ())
+    })?;
+
+    // a commit may not lose its atomicity either, so nothing it touches is volatile
+    let ops = vec![
+        TxnOp::Write { dict: "pl.work".to_string(), key: "existing".to_string(), data: b"committed".to_vec() },
+        TxnOp::Write { dict: "pl.txn".to_string(), key: "counter".to_string(), data: 7u32.to_le_bytes().to_vec() },
+        TxnOp::Delete { dict: "pl.work".to_string(), key: "big".to_string() },
+    ];
+    power_loss_sweep(hw, basis_cache, &mut rng, seed, "txn commit", RUNS, &[], |hw, cache| {
+        cache.txn_commit(hw, &ops, Some(PDDB_DEFAULT_SYSTEM_BASIS))
+    })?;
+
+    for dict in ["pl.keep", "pl.work", "pl.frag", "pl.txn"].iter() {
+        basis_cache.dict_remove(hw, dict, None, false)?;
+    }
+    basis_cache.sync(hw, None)
+}
+
 pub(crate) fn list_all(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
     #[cfg(feature = "deterministic")]
     let mut dict_list = BTreeSet::<String>::new();
@@ -388,6 +805,13 @@ pub(crate) fn list_all(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
         note: for faster stress-testing, we dialed the FSCB_PAGES to 4 and the FASTSPACE_PAGES to 1.
     - [done] basis search: create basis A, populate with general integrity. create basis B, add test entries.
         hide basis B, confirm original A; mount basis B, confirm B overlay.
+    - [done] large key cache: read a large key, patch it, confirm reads see the patch.
+    - [done] secure erase: paranoid-remove keys, confirm their neighbours survive and the keys are gone after a remount.
+    - [done] compaction: compact a fragmented basis, confirm all keys read back identically, before and after a remount.
+    - [done] fsck: a healthy basis checks clean; planted orphans are found, quarantined or released, and the keys survive.
+    - [done] transactions: a power loss before any flash write of a commit leaves none or all of its updates visible.
+    - [done] power loss: seeded power losses, before or tearing a flash write, across key, dictionary and commit
+        updates; the basis mounts, repairs to a clean fsck, and keys outside the update are intact.
 */
 
 #[allow(dead_code)]
@@ -407,7 +831,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
             None, None, Some(32))?;
         log::info!("Saving `basecase1e` to local host");
         pddb_os.dbg_dump(Some("basecase1e".to_string()), None);
-        let extra_basis_key = pddb_os.basis_derive_key(EXTRA_BASIS, EXTRA_BASIS_PW);
+        let extra_basis_key = pddb_os.basis_derive_key(EXTRA_BASIS, EXTRA_BASIS_PW, BasisKdf::DEFAULT);
         let mut name = [0 as u8; 64];
         for (&src, dst) in EXTRA_BASIS.as_bytes().iter().zip(name.iter_mut()) {
             *dst = src;
@@ -437,6 +861,12 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         patch_test(pddb_os, &mut basis_cache, None, None, true)?;
         pddb_os.dbg_dump(Some("patche".to_string()), None);
 
+        log::info!("Doing large key cache test");
+        large_cache_test(pddb_os, &mut basis_cache)?;
+
+        log::info!("Doing secure erase test");
+        secure_erase_test(pddb_os, &mut basis_cache)?;
+
         log::info!("Doing delete pattern test");
         delete_pattern(pddb_os, &mut basis_cache, None, None, None, None)?;
         pddb_os.dbg_dump(Some("patterne".to_string()), None);
@@ -452,6 +882,25 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         delete_pattern(pddb_os, &mut basis_cache, None, None, None, None)?;
         pddb_os.dbg_dump(Some("patterne2".to_string()), None);
 
+        log::info!("Doing compaction test");
+        compact_test(pddb_os, &mut basis_cache)?;
+        pddb_os.dbg_dump(Some("compacte".to_string()), None);
+
+        log::info!("Doing fsck test");
+        fsck_test(pddb_os, &mut basis_cache)?;
+        pddb_os.dbg_dump(Some("fscke".to_string()), None);
+
+        log::info!("Doing transaction power loss test");
+        txn_test(pddb_os, &mut basis_cache)?;
+        pddb_os.dbg_dump(Some("txne".to_string()), None);
+
+        #[cfg(feature = "powerloss")]
+        {
+            log::info!("Doing power loss injection test");
+            power_loss_test(pddb_os, &mut basis_cache)?;
+            pddb_os.dbg_dump(Some("powerlosse".to_string()), None);
+        }
+
         log::info!("Doing delete/add consistency with data extension 2");
         delete_add_dict_consistency(pddb_os, &mut basis_cache, Some(3),
             Some(50), None, None, None)?;
@@ -483,7 +932,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Doing remount disk test");
         let mut basis_cache = BasisCache::new();
         if let Some(sys_basis) = pddb_os.pddb_mount() {
-            basis_cache.basis_add(sys_basis);
+            basis_cache.basis_add(pddb_os, sys_basis);
             list_all(pddb_os, &mut basis_cache);
             pddb_os.dbg_dump(Some("remounte".to_string()), Some(&export));
         }
@@ -491,7 +940,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Mounting the second basis");
         if let Some(basis2) = basis_cache.basis_unlock(pddb_os,
             EXTRA_BASIS, EXTRA_BASIS_PW, BasisRetentionPolicy::Persist) {
-            basis_cache.basis_add(basis2);
+            basis_cache.basis_add(pddb_os, basis2);
         }
         log::set_max_level(log::LevelFilter::Info);
         log::info!("Adding keys to Basis2");
@@ -529,7 +978,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Doing remount disk test part 2");
         let mut basis_cache = BasisCache::new();
         if let Some(sys_basis) = pddb_os.pddb_mount() {
-            basis_cache.basis_add(sys_basis);
+            basis_cache.basis_add(pddb_os, sys_basis);
         }
         let mut remount_list = HashSet::<String>::new();
         for dict in basis_cache.dict_list(pddb_os, None).iter() {
@@ -542,7 +991,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Mounting the second basis again");
         if let Some(basis2) = basis_cache.basis_unlock(pddb_os,
             EXTRA_BASIS, EXTRA_BASIS_PW, BasisRetentionPolicy::Persist) {
-            basis_cache.basis_add(basis2);
+            basis_cache.basis_add(pddb_os, basis2);
         }
         let mut merge2_list = HashSet::<String>::new();
         for dict in basis_cache.dict_list(pddb_os, None).iter() {
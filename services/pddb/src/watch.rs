@@ -0,0 +1,177 @@
+//! Change notifications. A client registers a watch on a dictionary, or on a single key in it, and the server
+//! sends a `PddbWatchEvent` to the client's callback server whenever a matching key is created, updated or
+//! deleted, and whenever a basis is unlocked or locked. The callback server is the same one that receives
+//! the key change callbacks, so watches don't cost the client an extra connection.
+use crate::api::*;
+
+use num_traits::*;
+use std::collections::HashMap;
+use xous_ipc::Buffer;
+
+struct Watch {
+    /// only events in this basis are reported; None follows all of them
+    basis: Option<String>,
+    dict: String,
+    /// None watches every key in `dict`
+    key: Option<String>,
+    conn: xous::CID,
+}
+impl Watch {
+    fn wants_basis(&self, basis: &str) -> bool {
+        self.basis.as_ref().map_or(true, |b| b == basis)
+    }
+    fn wants_key(&self, basis: &str, dict: &str, key: &str) -> bool {
+        self.wants_basis(basis) && self.dict == dict && self.key.as_ref().map_or(true, |k| k == key)
+    }
+    fn wants_dict(&self, basis: &str, dict: &str) -> bool {
+        self.wants_basis(basis) && self.dict == dict
+    }
+}
+
+/// Watch ids are picked by the client, so they are only unique within the process that made them.
+type WatchId = (Option<xous::PID>, u32);
+
+pub(crate) struct Watchers {
+    watches: HashMap<WatchId, Watch>,
+}
+impl Watchers {
+    pub(crate) fn new() -> Self {
+        Watchers { watches: HashMap::new() }
+    }
+    pub(crate) fn add(&mut self, owner: Option<xous::PID>, req: &PddbWatchRequest) -> PddbRequestCode {
+        if self.watches.contains_key(&(owner, req.id)) {
+            return PddbRequestCode::AccessDenied;
+        }
+        let conn = match xous::connect(xous::SID::from_array(req.cb_sid)) {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::error!("couldn't connect to the callback server of a watch: {:?}", e);
+                return PddbRequestCode::InternalError;
+            }
+        };
+        let watch = Watch {
+            basis: if req.basis_specified { Some(req.basis.as_str().unwrap().to_string()) } else { None },
+            dict: req.dict.as_str().expect("dict utf-8 decode error").to_string(),
+            key: if req.key_specified { Some(req.key.as_str().expect("key utf-8 decode error").to_string()) } else { None },
+            conn,
+        };
+        log::debug!("watching {:?}:{}:{:?}", watch.basis, watch.dict, watch.key);
+        self.watches.insert((owner, req.id), watch);
+        PddbRequestCode::NoErr
+    }
+    /// Returns the callback connection of the watch, so the caller can recycle it if nothing else uses it.
+    /// Only the process that made a watch can cancel it.
+    pub(crate) fn remove(&mut self, owner: Option<xous::PID>, id: u32) -> Option<xous::CID> {
+        self.watches.remove(&(owner, id)).map(|w| w.conn)
+    }
+    pub(crate) fn uses_conn(&self, conn: xous::CID) -> bool {
+        self.watches.values().any(|w| w.conn == conn)
+    }
+
+    pub(crate) fn key_changed(&mut self, kind: PddbEventKind, basis: &str, dict: &str, key: &str) {
+        self.notify(|w| w.wants_key(basis, dict, key), kind, basis, dict, key);
+    }
+    /// Everyone watching the dictionary, or a key in it, hears about its removal.
+    pub(crate) fn dict_deleted(&mut self, basis: &str, dict: &str) {
+        self.notify(|w| w.wants_dict(basis, dict), PddbEventKind::Deleted, basis, dict, "");
+    }
+    /// Any watched dictionary may appear or disappear with a basis, so every watch hears about it.
+    pub(crate) fn basis_changed(&mut self, kind: PddbEventKind, basis: &str) {
+        self.notify(|w| w.wants_basis(basis), kind, basis, "", "");
+    }
+
+    /// The watches an event is delivered to, and the connections to deliver it on.
+    fn targets<F>(&self, wants: F) -> Vec<(WatchId, xous::CID)>
+    where F: Fn(&Watch) -> bool {
+        self.watches.iter().filter(|(_, w)| wants(w)).map(|(&id, w)| (id, w.conn)).collect()
+    }
+
+    fn notify<F>(&mut self, wants: F, kind: PddbEventKind, basis: &str, dict: &str, key: &str)
+    where F: Fn(&Watch) -> bool {
+        let mut gone = Vec::<WatchId>::new();
+        for ((owner, id), conn) in self.targets(wants) {
+            let event = PddbWatchEvent {
+                id,
+                kind,
+                basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis),
+                dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict),
+                key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key),
+            };
+            let buf = Buffer::into_buf(event).expect("couldn't convert watch event");
+            match buf.send(conn, pddb::CbOp::Watch.to_u32().unwrap()) {
+                Ok(_) => {}
+                Err(xous::Error::ServerNotFound) => {
+                    // the client went away without cancelling its watches
+                    gone.push((owner, id));
+                }
+                Err(e) => log::warn!("couldn't deliver {:?} on {}:{} to watch {:x}: {:?}", kind, dict, key, id, e),
+            }
+        }
+        for (owner, id) in gone {
+            log::info!("dropping watch {:x} of {:?}: its callback server is gone", id, owner);
+            self.watches.remove(&(owner, id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch(basis: Option<&str>, dict: &str, key: Option<&str>, conn: xous::CID) -> Watch {
+        Watch { basis: basis.map(|b| b.to_string()), dict: dict.to_string(), key: key.map(|k| k.to_string()), conn }
+    }
+    fn ids(mut targets: Vec<(WatchId, xous::CID)>) -> Vec<u32> {
+        targets.sort_by_key(|&((_, id), _)| id);
+        targets.iter().map(|&((_, id), _)| id).collect()
+    }
+
+    #[test]
+    fn test_register() {
+        let app = xous::PID::new(5);
+        let mut watchers = Watchers::new();
+        watchers.watches.insert((app, 1), watch(None, "wlan.networks", None, 10));
+        watchers.watches.insert((app, 2), watch(Some(".System"), "vault.passwords", Some("github"), 11));
+        assert!(watchers.uses_conn(10));
+        assert!(watchers.uses_conn(11));
+        assert!(!watchers.uses_conn(12));
+    }
+
+    #[test]
+    fn test_fire() {
+        let app = xous::PID::new(5);
+        let mut watchers = Watchers::new();
+        watchers.watches.insert((app, 1), watch(None, "wlan.networks", None, 10));
+        watchers.watches.insert((app, 2), watch(Some(".System"), "wlan.networks", Some("home"), 10));
+        watchers.watches.insert((app, 3), watch(Some("Basis2"), "wlan.networks", None, 10));
+        watchers.watches.insert((app, 4), watch(None, "vault.passwords", None, 11));
+
+        // a key watch only fires for its key; a basis filter only passes its basis
+        assert_eq!(ids(watchers.targets(|w| w.wants_key(".System", "wlan.networks", "home"))), vec![1, 2]);
+        assert_eq!(ids(watchers.targets(|w| w.wants_key(".System", "wlan.networks", "work"))), vec![1]);
+        assert_eq!(ids(watchers.targets(|w| w.wants_key("Basis2", "wlan.networks", "home"))), vec![1, 3]);
+        // removing the dictionary reaches the key watches in it too
+        assert_eq!(ids(watchers.targets(|w| w.wants_dict(".System", "wlan.networks"))), vec![1, 2]);
+        // every watch hears about its basis coming and going
+        assert_eq!(ids(watchers.targets(|w| w.wants_basis("Basis2"))), vec![1, 3, 4]);
+        assert!(watchers.targets(|w| w.wants_key(".System", "other", "home")).is_empty());
+    }
+
+    #[test]
+    fn test_unwatch() {
+        let app = xous::PID::new(5);
+        let other = xous::PID::new(6);
+        let mut watchers = Watchers::new();
+        watchers.watches.insert((app, 1), watch(None, "wlan.networks", None, 10));
+        watchers.watches.insert((other, 1), watch(None, "vault.passwords", None, 11));
+
+        // the same id in another process is a different watch
+        assert_eq!(watchers.remove(other, 1), Some(11));
+        assert_eq!(watchers.remove(other, 1), None);
+        assert!(!watchers.uses_conn(11));
+        assert_eq!(ids(watchers.targets(|w| w.wants_dict(".System", "wlan.networks"))), vec![1]);
+        assert_eq!(watchers.remove(xous::PID::new(7), 1), None);
+        assert_eq!(watchers.remove(app, 1), Some(10));
+        assert!(watchers.targets(|w| w.wants_dict(".System", "wlan.networks")).is_empty());
+    }
+}
@@ -103,6 +103,8 @@ mod jtag_cmd; use jtag_cmd::*;
 mod net_cmd;  use net_cmd::*;
 mod pddb_cmd; use pddb_cmd::*;
 mod usb; use usb::*;
+mod log_cmd;  use log_cmd::*;
+mod crash_cmd; use crash_cmd::*;
 
 #[cfg(feature="tts")]
 mod tts;
@@ -146,6 +148,8 @@ pub struct CmdEnv {
     pddb_cmd: PddbCmd,
     wlan_cmd: Wlan,
     usb_cmd: Usb,
+    log_cmd: LogCmd,
+    crash_cmd: CrashCmd,
 
     #[cfg(feature="tts")]
     tts_cmd: Tts,
@@ -216,6 +220,8 @@ impl CmdEnv {
             pddb_cmd: PddbCmd::new(&xns),
             wlan_cmd: Wlan::new(),
             usb_cmd: Usb::new(),
+            log_cmd: LogCmd::new(),
+            crash_cmd: CrashCmd::new(),
 
             #[cfg(feature="tts")]
             tts_cmd: Tts::new(&xns),
@@ -263,6 +269,8 @@ impl CmdEnv {
             &mut self.net_cmd,
             &mut self.pddb_cmd,
             &mut self.usb_cmd,
+            &mut self.log_cmd,
+            &mut self.crash_cmd,
 
             #[cfg(feature="tts")]
             &mut self.tts_cmd,
@@ -0,0 +1,141 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+use std::io::Read;
+use log_server::api::{CRASH_PERSIST_DICT, CRASH_PERSIST_NEXT_KEY, CRASH_PERSIST_SLOTS};
+
+pub struct CrashCmd {
+    pddb: pddb::Pddb,
+}
+impl CrashCmd {
+    pub fn new() -> CrashCmd {
+        CrashCmd {
+            pddb: pddb::Pddb::new(),
+        }
+    }
+    fn read_key(&self, name: &str) -> Option<std::string::String> {
+        let mut key = self.pddb.get(
+            CRASH_PERSIST_DICT, name,
+            Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
+            None, None::<fn()>
+        ).ok()?;
+        let mut text = std::string::String::new();
+        key.read_to_string(&mut text).ok()?;
+        Some(text)
+    }
+    /// Slot numbers of the saved reports, oldest first
+    fn slots(&self) -> Vec<u32> {
+        let next = match self.pddb.get(
+            CRASH_PERSIST_DICT, CRASH_PERSIST_NEXT_KEY,
+            Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
+            None, None::<fn()>
+        ) {
+            Ok(mut key) => {
+                let mut buf = [0u8; 4];
+                if key.read(&mut buf).unwrap_or(0) == 4 {
+                    u32::from_le_bytes(buf)
+                } else {
+                    return Vec::new();
+                }
+            }
+            Err(_) => return Vec::new(),
+        };
+        let mut slots = Vec::new();
+        for n in next.saturating_sub(CRASH_PERSIST_SLOTS)..next {
+            slots.push(n % CRASH_PERSIST_SLOTS);
+        }
+        slots
+    }
+}
+
+/// Pulls the value of a `field: value` line out of a saved report
+fn field<'a>(report: &'a str, name: &str) -> &'a str {
+    for line in report.lines() {
+        if let Some(value) = line.strip_prefix(name).and_then(|l| l.strip_prefix(": ")) {
+            return value;
+        }
+    }
+    "?"
+}
+
+/// Everything after the `message:` line
+fn message(report: &str) -> &str {
+    match report.find("\nmessage:\n") {
+        Some(start) => report[start + "\nmessage:\n".len()..].trim_end(),
+        None => "",
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for CrashCmd {
+    cmd_api!(crash); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "crash [list] [show <n>] [export <n>] [clear]";
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+
+        if let Some(sub_cmd) = tokens.next() {
+            match sub_cmd {
+                "list" => {
+                    let mut found = false;
+                    for slot in self.slots() {
+                        if let Some(report) = self.read_key(&format!("report.{}", slot)) {
+                            let first = message(&report).lines().next().unwrap_or("");
+                            let mut end = first.len().min(48);
+                            while !first.is_char_boundary(end) {
+                                end -= 1;
+                            }
+                            // output past the end of the return buffer is silently truncated
+                            writeln!(ret, "{}: {} (PID {}) at {}\n  {}",
+                                slot, field(&report, "name"), field(&report, "pid"), field(&report, "time"), &first[..end]
+                            ).unwrap();
+                            found = true;
+                        }
+                    }
+                    if !found {
+                        write!(ret, "No saved crash reports").unwrap();
+                    }
+                }
+                "show" | "export" => {
+                    let report = tokens.next()
+                        .and_then(|n| n.parse::<u32>().ok())
+                        .and_then(|n| self.read_key(&format!("report.{}", n)));
+                    match report {
+                        Some(report) if sub_cmd == "show" => {
+                            write!(ret, "{} (PID {}) at {}\npc {} ra {}\n",
+                                field(&report, "name"), field(&report, "pid"), field(&report, "time"),
+                                field(&report, "pc"), field(&report, "ra")
+                            ).unwrap();
+                            for line in report.lines().filter(|l| l.starts_with("frame: ")).take(4) {
+                                writeln!(ret, "{}", line).unwrap();
+                            }
+                            write!(ret, "{}", message(&report)).unwrap();
+                        }
+                        Some(report) => {
+                            // the prefix lets tools/src/bin/symbolize-crash.rs pick the report out of a console capture
+                            for line in report.lines() {
+                                log::info!("CRASH| {}", line);
+                            }
+                            write!(ret, "Report written to the console log").unwrap();
+                        }
+                        None => write!(ret, "No such report. {}", helpstring).unwrap(),
+                    }
+                }
+                "clear" => {
+                    match self.pddb.delete_dict(CRASH_PERSIST_DICT, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS)) {
+                        Ok(_) => write!(ret, "Crash reports cleared").unwrap(),
+                        Err(e) => write!(ret, "Couldn't clear crash reports: {:?}", e).unwrap(),
+                    }
+                    self.pddb.sync().ok();
+                }
+                _ => {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+        } else {
+            write!(ret, "{}", helpstring).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+use std::io::{Write, Read};
+use log_server::api::{level_name, LOG_PERSIST_DICT, LOG_PERSIST_LEVEL_KEY, LOG_PERSIST_HEAD_KEY, LOG_PERSIST_FILES};
+
+pub struct LogCmd {
+    pddb: pddb::Pddb,
+}
+impl LogCmd {
+    pub fn new() -> LogCmd {
+        LogCmd {
+            pddb: pddb::Pddb::new(),
+        }
+    }
+}
+
+fn parse_level(s: &str) -> Option<log::LevelFilter> {
+    match s {
+        "off" => Some(log::LevelFilter::Off),
+        "error" => Some(log::LevelFilter::Error),
+        "warn" => Some(log::LevelFilter::Warn),
+        "info" => Some(log::LevelFilter::Info),
+        "debug" => Some(log::LevelFilter::Debug),
+        "trace" => Some(log::LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for LogCmd {
+    cmd_api!(log); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write as FmtWrite;
+        let mut ret = String::<1024>::new();
+        let helpstring = "log [level <pid|*> <module|*> <off|error|warn|info|debug|trace>] [clear]\n[recent [n]] [persist <off|error|warn>] [saved]";
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+
+        if let Some(sub_cmd) = tokens.next() {
+            match sub_cmd {
+                "level" => {
+                    let pid = tokens.next();
+                    let module = tokens.next();
+                    let level = tokens.next().and_then(parse_level);
+                    match (pid, module, level) {
+                        (Some(pid), Some(module), Some(level)) => {
+                            let pid = if pid == "*" {
+                                None
+                            } else {
+                                match pid.parse::<u8>().ok().and_then(|p| xous::PID::new(p)) {
+                                    Some(p) => Some(p),
+                                    None => {
+                                        write!(ret, "Invalid PID: {}", pid).unwrap();
+                                        return Ok(Some(ret));
+                                    }
+                                }
+                            };
+                            let module = if module == "*" { "" } else { module };
+                            match log_server::set_filter(pid, module, level) {
+                                Ok(_) => write!(ret, "Filter set: {:?} {} {:?}", pid, module, level).unwrap(),
+                                Err(e) => write!(ret, "Couldn't set filter: {:?}", e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "{}", helpstring).unwrap(),
+                    }
+                }
+                "clear" => {
+                    match log_server::clear_filters() {
+                        Ok(_) => write!(ret, "All log filters cleared").unwrap(),
+                        Err(e) => write!(ret, "Couldn't clear the log filters: {:?}", e).unwrap(),
+                    }
+                }
+                "recent" => {
+                    let count = tokens.next().and_then(|n| n.parse::<usize>().ok()).unwrap_or(8);
+                    let mut lines: Vec<std::string::String> = Vec::new();
+                    if let Err(e) = log_server::for_each_record(0, log::Level::Trace, |r| {
+                        lines.push(format!("{} {} {}: {}", r.pid, level_name(r.level), r.module, r.args));
+                    }) {
+                        write!(ret, "Couldn't read the log: {:?}", e).unwrap();
+                        return Ok(Some(ret));
+                    }
+                    // output past the end of the return buffer is silently truncated
+                    for line in lines.iter().skip(lines.len().saturating_sub(count)) {
+                        writeln!(ret, "{}", line).unwrap();
+                    }
+                }
+                "persist" => {
+                    match tokens.next() {
+                        Some("off") => {
+                            self.pddb.delete_key(LOG_PERSIST_DICT, LOG_PERSIST_LEVEL_KEY, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS)).ok();
+                            write!(ret, "Log persistence disabled").unwrap();
+                        }
+                        Some(level) if level == "error" || level == "warn" => {
+                            self.pddb.delete_key(LOG_PERSIST_DICT, LOG_PERSIST_LEVEL_KEY, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS)).ok();
+                            match self.pddb.get(
+                                LOG_PERSIST_DICT, LOG_PERSIST_LEVEL_KEY,
+                                Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), true, true,
+                                Some(8), None::<fn()>
+                            ) {
+                                Ok(mut key) => {
+                                    key.write_all(level.as_bytes()).map_err(|_| xous::Error::InternalError)?;
+                                    write!(ret, "Persisting log records at {} and above", level).unwrap();
+                                }
+                                Err(e) => write!(ret, "Couldn't save setting: {:?}", e).unwrap(),
+                            }
+                            self.pddb.sync().ok();
+                        }
+                        _ => write!(ret, "{}", helpstring).unwrap(),
+                    }
+                }
+                "saved" => {
+                    let mut head = 0u32;
+                    if let Ok(mut key) = self.pddb.get(
+                        LOG_PERSIST_DICT, LOG_PERSIST_HEAD_KEY,
+                        Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
+                        None, None::<fn()>
+                    ) {
+                        let mut buf = [0u8; 4];
+                        if key.read(&mut buf).unwrap_or(0) == 4 {
+                            head = u32::from_le_bytes(buf);
+                        }
+                    }
+                    // oldest first
+                    let mut saved = std::string::String::new();
+                    for i in (0..LOG_PERSIST_FILES).rev() {
+                        let name = format!("saved.{}", head.wrapping_sub(i) % LOG_PERSIST_FILES);
+                        if let Ok(mut key) = self.pddb.get(
+                            LOG_PERSIST_DICT, &name,
+                            Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
+                            None, None::<fn()>
+                        ) {
+                            key.read_to_string(&mut saved).ok();
+                        }
+                    }
+                    if saved.len() == 0 {
+                        write!(ret, "No saved log records").unwrap();
+                    } else {
+                        // the whole thing goes to the console; the screen only has room for the tail
+                        for line in saved.lines() {
+                            log::info!("saved: {}", line);
+                        }
+                        let mut start = saved.len().saturating_sub(900);
+                        while !saved.is_char_boundary(start) {
+                            start += 1;
+                        }
+                        write!(ret, "{}", &saved[start..]).ok();
+                    }
+                }
+                _ => {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+        } else {
+            write!(ret, "{}", helpstring).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
@@ -6,7 +6,7 @@ use net::XousServerId;
 use net::NetPingCallback;
 use xous::MessageEnvelope;
 use num_traits::*;
-use std::net::{IpAddr, TcpStream, TcpListener};
+use std::net::{IpAddr, Ipv6Addr, TcpStream, TcpListener};
 use std::io::Write;
 use std::io::Read;
 use std::sync::{Arc, Mutex};
@@ -25,6 +25,8 @@ pub struct NetCmd {
     dns: Dns,
     #[cfg(any(target_os = "none", target_os = "xous"))]
     ping: Option<net::Ping>,
+    /// the last IPv6 address pinged; ping callbacks only carry the bottom of the address
+    ping_v6: Option<Ipv6Addr>,
 }
 impl NetCmd {
     pub fn new(xns: &xous_names::XousNames) -> Self {
@@ -34,6 +36,7 @@ impl NetCmd {
             dns: dns::Dns::new(&xns).unwrap(),
             #[cfg(any(target_os = "none", target_os = "xous"))]
             ping: None,
+            ping_v6: None,
         }
     }
 }
@@ -57,10 +60,10 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
         #[cfg(any(target_os = "none", target_os = "xous"))]
-        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path]";
+        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path] [tls] [ipv6] [securedns] [wg] [pcap]";
         // no ping in hosted mode -- why would you need it? we're using the host's network connection.
         #[cfg(not(any(target_os = "none", target_os = "xous")))]
-        let helpstring = "net [udp [port]] [count]] [tcpget host/path]";
+        let helpstring = "net [udp [port]] [count]] [tcpget host/path] [tls] [ipv6] [securedns] [wg] [pcap]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -73,6 +76,21 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                         Err(e) => write!(ret, "wifi unsub error: {:?}", e),
                     }.ok();
                 }
+                "ipv6" => {
+                    let config = env.netmgr.get_ipv6_config();
+                    write!(ret, "link-local: {}", Ipv6Addr::from(config.link_local)).unwrap();
+                    match config.global {
+                        Some(global) => write!(ret, "\naddress: {}/{}, valid {}s",
+                            Ipv6Addr::from(global), config.prefix_len, config.valid_secs).unwrap(),
+                        None => write!(ret, "\nno address from a router yet").unwrap(),
+                    }
+                    if let Some(gateway) = config.gateway {
+                        write!(ret, "\ngateway: {}", Ipv6Addr::from(gateway)).unwrap();
+                    }
+                    if let Some(dns) = config.dns {
+                        write!(ret, "\ndns: {}", Ipv6Addr::from(dns)).unwrap();
+                    }
+                }
                 "tcpget" => {
                     // note: to keep shellchat lightweight, we do a very minimal parsing of the URL. We assume it always has
                     // a form such as:
@@ -287,6 +305,163 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                         }
                     }
                 }
+                "securedns" => {
+                    use dns::api::{FallbackPolicy, SecureResolver, SecureTransport};
+                    let usage = "Usage: net securedns [off] [tls addr name] [https addr name/path] [plaintext]";
+                    let sub = tokens.next();
+                    let addr = tokens.next().map(|a| a.parse::<IpAddr>());
+                    let resolver = match (sub, addr, tokens.next()) {
+                        (Some("tls"), Some(Ok(addr)), Some(name)) => Some(SecureResolver::tls(addr, name)),
+                        (Some("https"), Some(Ok(addr)), Some(url)) => match url.split_once('/') {
+                            Some((name, path)) => Some(SecureResolver::https(addr, name, &format!("/{}", path))),
+                            None => Some(SecureResolver::https(addr, url, "/dns-query")),
+                        },
+                        _ => None,
+                    };
+                    match (sub, resolver) {
+                        (None, _) => match self.dns.secure_resolver() {
+                            Ok(Some(r)) => write!(ret, "Encrypted DNS over {:?} to {:?} ({}{}), fallback {:?}",
+                                r.transport, r.addr, r.server_name, r.path, r.fallback).unwrap(),
+                            Ok(None) => write!(ret, "Encrypted DNS is off").unwrap(),
+                            Err(e) => write!(ret, "Couldn't get the encrypted DNS setting: {:?}", e).unwrap(),
+                        },
+                        (Some("off"), _) => match self.dns.set_secure_resolver(None) {
+                            Ok(_) => write!(ret, "Encrypted DNS off").unwrap(),
+                            Err(e) => write!(ret, "Couldn't turn encrypted DNS off: {:?}", e).unwrap(),
+                        },
+                        (_, Some(mut resolver)) => {
+                            if tokens.next() == Some("plaintext") {
+                                resolver.fallback = FallbackPolicy::Plaintext;
+                            }
+                            match self.dns.set_secure_resolver(Some(resolver)) {
+                                Ok(_) => write!(ret, "Looking names up over {}",
+                                    if resolver.transport == SecureTransport::Tls { "TLS" } else { "HTTPS" }).unwrap(),
+                                Err(e) => write!(ret, "Couldn't set the encrypted resolver: {:?}", e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "{}", usage).unwrap(),
+                    }
+                }
+                "wg" => {
+                    use net::WireguardState;
+                    let usage = "Usage: net wg [key] [up] [down] [setup addr peer-key endpoint:port [allowed-ips] [dns]]";
+                    match tokens.next() {
+                        None => {
+                            let status = env.netmgr.wireguard_status();
+                            write!(ret, "WireGuard: {:?}", status.state).unwrap();
+                            if status.state != WireguardState::Disabled && status.state != WireguardState::Unconfigured {
+                                if let Some(address) = status.address {
+                                    write!(ret, "
+address: {}", std::net::Ipv4Addr::from(address)).unwrap();
+                                }
+                                if let Some(endpoint) = status.endpoint {
+                                    write!(ret, "
+peer: {}:{}", std::net::Ipv4Addr::from(endpoint), status.endpoint_port).unwrap();
+                                }
+                                match status.last_handshake_secs {
+                                    Some(secs) => write!(ret, "
+last handshake {}s ago", secs).unwrap(),
+                                    None => write!(ret, "
+no handshake yet").unwrap(),
+                                }
+                                write!(ret, "
+rx {} bytes, tx {} bytes", status.rx_bytes, status.tx_bytes).unwrap();
+                            }
+                        }
+                        Some("key") => match env.netmgr.wireguard_public_key() {
+                            Some(key) => write!(ret, "{}", key).unwrap(),
+                            None => write!(ret, "No key until the PDDB is mounted").unwrap(),
+                        },
+                        Some(sub) if sub == "up" || sub == "down" => match env.netmgr.wireguard_enable(sub == "up") {
+                            Ok(_) => write!(ret, "WireGuard {}", sub).unwrap(),
+                            Err(e) => write!(ret, "Couldn't bring WireGuard {}: {:?}", sub, e).unwrap(),
+                        },
+                        Some("setup") => match (tokens.next(), tokens.next(), tokens.next()) {
+                            (Some(addr), Some(peer_key), Some(endpoint)) => {
+                                // everything goes through the tunnel unless told otherwise
+                                let allowed_ips = tokens.next().unwrap_or("0.0.0.0/0,::/0");
+                                let mut config = format!("[Interface]\nAddress = {}\n", addr);
+                                if let Some(dns) = tokens.next() {
+                                    config.push_str(&format!("DNS = {}\n", dns));
+                                }
+                                config.push_str(&format!(
+                                    "[Peer]\nPublicKey = {}\nEndpoint = {}\nAllowedIPs = {}\nPersistentKeepalive = 25\n",
+                                    peer_key, endpoint, allowed_ips
+                                ));
+                                match env.netmgr.wireguard_set_config(&config) {
+                                    Ok(_) => write!(ret, "WireGuard configured; `net wg up` to use it").unwrap(),
+                                    Err(e) => write!(ret, "Couldn't configure WireGuard: {:?}", e).unwrap(),
+                                }
+                            }
+                            _ => write!(ret, "{}", usage).unwrap(),
+                        },
+                        _ => write!(ret, "{}", usage).unwrap(),
+                    }
+                }
+                "pcap" => {
+                    let usage = "Usage: net pcap [start [KiB] [filter]] [stop] [save name] [dump]\nfilter terms: in out arp ip ip6 icmp tcp udp port <n> host <addr>";
+                    match tokens.next() {
+                        None => {
+                            let status = env.netmgr.capture_status();
+                            if status.ring_size == 0 {
+                                write!(ret, "No capture").unwrap();
+                            } else {
+                                write!(ret, "Capture {}, filter '{}'\n{} frames, {} of {} bytes\n{} seen, {} dropped",
+                                    if status.running { "running" } else { "stopped" },
+                                    status.filter.as_str().unwrap_or(""),
+                                    status.frames, status.bytes, status.ring_size,
+                                    status.seen, status.dropped,
+                                ).unwrap();
+                            }
+                        }
+                        Some("start") => {
+                            let mut terms: Vec<&str> = tokens.collect();
+                            let ring_size = match terms.first().and_then(|size| size.parse::<usize>().ok()) {
+                                Some(kib) => {
+                                    terms.remove(0);
+                                    kib * 1024
+                                }
+                                None => net::CAPTURE_DEFAULT_RING_SIZE,
+                            };
+                            let filter = terms.join(" ");
+                            if ring_size > net::CAPTURE_MAX_RING_SIZE {
+                                write!(ret, "The most a capture can hold is {} KiB", net::CAPTURE_MAX_RING_SIZE / 1024).unwrap();
+                            } else {
+                                match env.netmgr.capture_start(&filter, ring_size) {
+                                    Ok(_) => write!(ret, "Capturing into {} KiB", ring_size / 1024).unwrap(),
+                                    Err(_) => write!(ret, "Bad filter '{}'\n{}", filter, usage).unwrap(),
+                                }
+                            }
+                        }
+                        Some("stop") => match env.netmgr.capture_stop() {
+                            Ok(frames) => write!(ret, "Capture stopped with {} frames", frames).unwrap(),
+                            Err(e) => write!(ret, "Couldn't stop the capture: {:?}", e).unwrap(),
+                        },
+                        Some(sub) if sub == "save" || sub == "dump" => {
+                            let name = if sub == "save" {
+                                match tokens.next() {
+                                    Some(name) if name.len() > 0 => Some(name),
+                                    _ => {
+                                        write!(ret, "{}", usage).unwrap();
+                                        return Ok(Some(ret));
+                                    }
+                                }
+                            } else {
+                                None
+                            };
+                            match env.netmgr.capture_export(name) {
+                                Ok(_) => match name {
+                                    Some(name) => write!(ret, "Saved as {}:{}", net::CAPTURE_DICT_NAME, name).unwrap(),
+                                    None => write!(ret, "Capture written to the log").unwrap(),
+                                },
+                                Err(xous::Error::UseBeforeInit) => write!(ret, "Nothing has been captured").unwrap(),
+                                Err(xous::Error::AccessDenied) => write!(ret, "The PDDB isn't mounted").unwrap(),
+                                Err(e) => write!(ret, "Couldn't export the capture: {:?}", e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "{}", usage).unwrap(),
+                    }
+                }
                 #[cfg(feature="ditherpunk")]
                 "image" => {
                     let new_limit = 2048 * 1024;
@@ -453,14 +628,69 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                     AllocationRegistry::disable_tracking();
                 }
                 "tls" => {
-                    write!(ret, "Work in progress. Please check back later!").unwrap();
+                    let tls = tls::Tls::new();
+                    match tokens.next() {
+                        Some("list") => {
+                            let cas = tls.trusted();
+                            write!(ret, "{} trusted CAs\n", cas.len()).unwrap();
+                            for (key, ca) in cas {
+                                if write!(ret, "{} {}\n", key, ca.name).is_err() {
+                                    break; // overflowed return buffer
+                                }
+                            }
+                        }
+                        Some("mozilla") => match tls.trust_mozilla_roots() {
+                            Ok(count) => write!(ret, "trusting the {} root CAs of the Mozilla root program", count).unwrap(),
+                            Err(e) => write!(ret, "couldn't add the Mozilla root CAs: {:?}", e).unwrap(),
+                        },
+                        Some("untrust") => match tokens.next() {
+                            Some(key) => match tls.untrust(key) {
+                                Ok(_) => write!(ret, "no longer trusting {}", key).unwrap(),
+                                Err(e) => write!(ret, "couldn't remove {}: {:?}", key, e).unwrap(),
+                            },
+                            None => write!(ret, "Usage: net tls untrust [key]").unwrap(),
+                        },
+                        Some("get") => match tokens.next().and_then(|url| url.split_once('/')) {
+                            // same minimal URL parsing as tcpget
+                            Some((host, path)) => match tls.connect(host, 443) {
+                                Ok(mut stream) => {
+                                    stream.sock.set_read_timeout(Some(Duration::from_millis(10_000))).unwrap();
+                                    stream.sock.set_write_timeout(Some(Duration::from_millis(10_000))).unwrap();
+                                    let request = format!("GET /{} HTTP/1.1\r\nHost: {}\r\nAccept: */*\r\nUser-Agent: Precursor/0.9.6\r\nConnection: close\r\n\r\n",
+                                        path, host);
+                                    let mut buf = [0u8; 512];
+                                    match stream.write_all(request.as_bytes()).and_then(|_| stream.read(&mut buf)) {
+                                        Ok(len) => {
+                                            log::info!("{}NET.TLSGET,{},{}",
+                                                xous::BOOKEND_START,
+                                                std::string::String::from_utf8_lossy(&buf[..len]),
+                                                xous::BOOKEND_END);
+                                            write!(ret, "{}", std::string::String::from_utf8_lossy(&buf[..len])).ok(); // let it run off the end
+                                        }
+                                        Err(e) => write!(ret, "TLS session with {} failed: {:?}", host, e).unwrap(),
+                                    }
+                                }
+                                Err(e) => write!(ret, "Couldn't connect to {}:443: {:?}", host, e).unwrap(),
+                            },
+                            None => write!(ret, "Usage: net tls get bunniefoo.com/bunnie/test.txt").unwrap(),
+                        },
+                        _ => write!(ret, "Usage: net tls [list] [mozilla] [untrust key] [get host/path]").unwrap(),
+                    }
                 }
                 #[cfg(any(target_os = "none", target_os = "xous"))]
                 "ping" => {
                     if let Some(name) = tokens.next() {
-                        match self.dns.lookup(name) {
+                        // literal addresses don't need a lookup; that's also how to ping an IPv6 host for now
+                        let target = match name.parse::<IpAddr>() {
+                            Ok(ipaddr) => Ok(ipaddr),
+                            Err(_) => self.dns.lookup(name).map(IpAddr::from),
+                        };
+                        match target {
                             Ok(ipaddr) => {
                                 log::debug!("sending ping to {:?}", ipaddr);
+                                if let IpAddr::V6(v6) = ipaddr {
+                                    self.ping_v6 = Some(v6);
+                                }
                                 if self.ping.is_none() {
                                     self.ping = Some(net::Ping::non_blocking_handle(
                                         XousServerId::ServerName(xous_ipc::String::from_str(crate::SERVER_NAME_SHELLCHAT)),
@@ -530,7 +760,15 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                     None => {
                         // rebind the scalar args to the Ping convention
                         let op = arg1;
-                        let addr = IpAddr::from((*arg2 as u32).to_be_bytes());
+                        let addr = if op & net::NET_PING_IPV6 != 0 {
+                            let bottom = (*arg2 as u32).to_be_bytes();
+                            match self.ping_v6 {
+                                Some(v6) if v6.octets()[12..] == bottom => IpAddr::V6(v6),
+                                _ => IpAddr::V6(Ipv6Addr::from(*arg2 as u32 as u128)),
+                            }
+                        } else {
+                            IpAddr::from((*arg2 as u32).to_be_bytes())
+                        };
                         let seq_or_addr = *arg3;
                         let timestamp = *arg4;
                         match FromPrimitive::from_usize(op & 0xFF) {
@@ -555,7 +793,10 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                                         );
                                     },
                                     IpAddr::V6(_) => {
-                                        write!(ret, "Ipv6 pong received: {} ms", timestamp).unwrap();
+                                        write!(ret, "Pong from {:?} seq {} received: {} ms",
+                                        addr,
+                                        seq_or_addr,
+                                        timestamp).unwrap();
                                     },
                                 }
                             }
@@ -563,8 +804,13 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                                 write!(ret, "Ping to {:?} timed out", addr).unwrap();
                             }
                             Some(NetPingCallback::Unreachable) => {
-                                let code = net::Icmpv4DstUnreachable::from((op >> 24) as u8);
-                                write!(ret, "Ping to {:?} unreachable: {:?}", addr, code).unwrap();
+                                if op & net::NET_PING_IPV6 != 0 {
+                                    let code = net::Icmpv6DstUnreachable::from((op >> 24) as u8);
+                                    write!(ret, "Ping to {:?} unreachable: {:?}", addr, code).unwrap();
+                                } else {
+                                    let code = net::Icmpv4DstUnreachable::from((op >> 24) as u8);
+                                    write!(ret, "Ping to {:?} unreachable: {:?}", addr, code).unwrap();
+                                }
                             }
                             None => {
                                 log::error!("Unknown opcode received in NetCmd callback: {:?}", op);
@@ -22,10 +22,12 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
     fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
-        #[cfg(not(feature="pddbtest"))]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [churn]";
+        #[cfg(all(not(feature="pddbtest"), not(target_os = "xous")))]
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [compact] [fsck] [churn] [changepass]\n[export] [import]";
+        #[cfg(all(not(feature="pddbtest"), target_os = "xous"))]
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [compact] [fsck] [churn] [changepass]";
         #[cfg(feature="pddbtest")]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete]\n[test]";
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [compact] [fsck]\n[test]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
         if let Some(sub_cmd) = tokens.next() {
@@ -164,6 +166,38 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                         write!(ret, "Missing dictionary name").unwrap();
                     }
                 }
+                "compact" => {
+                    let dict = tokens.next();
+                    match self.pddb.compact_dict(dict, None) {
+                        Ok(_) => write!(ret, "Compacted {}", dict.unwrap_or("all dictionaries")).unwrap(),
+                        Err(e) => write!(ret, "Compaction failed: {:?}", e).unwrap(),
+                    }
+                }
+                "fsck" => {
+                    let mode = match tokens.next() {
+                        None => Some(pddb::FsckMode::Check),
+                        Some("repair") => Some(pddb::FsckMode::Repair),
+                        Some("quarantine") => Some(pddb::FsckMode::Quarantine),
+                        Some(_) => None,
+                    };
+                    if let Some(mode) = mode {
+                        match self.pddb.fsck(mode) {
+                            Ok(r) => {
+                                write!(ret, "{} basis, {} pages: {}
+", r.bases, r.pages,
+                                    if r.is_clean() { "clean" } else { "PROBLEMS FOUND" }).unwrap();
+                                write!(ret, "orphaned {}, double-mapped {}, bad pages {}
+", r.orphaned, r.double_mapped, r.bad_pages).unwrap();
+                                write!(ret, "bad dicts {}, bad keys {}
+", r.bad_dicts, r.bad_keys).unwrap();
+                                write!(ret, "repaired {}, quarantined {}", r.repaired, r.quarantined).unwrap();
+                            }
+                            Err(e) => write!(ret, "fsck failed: {:?}", e).unwrap(),
+                        }
+                    } else {
+                        write!(ret, "usage: pddb fsck [repair|quarantine]").unwrap();
+                    }
+                }
                 "keylist" => {
                     if let Some(dict) = tokens.next() {
                         match self.pddb.list_keys(dict, None) {
@@ -219,6 +253,61 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                 "churn" => {
                     write!(ret, "Churn result code: {:?}", self.pddb.rekey_pddb(pddb::PddbRekeyOp::Churn)).ok();
                 }
+                "changepass" => {
+                    if let Some(bname) = tokens.next() {
+                        match self.pddb.rekey_pddb(pddb::PddbRekeyOp::ChangePass(String::from_str(bname))) {
+                            Ok(_) => write!(ret, "password of basis {} changed; consider a `pddb churn`", bname).unwrap(),
+                            Err(e) => write!(ret, "password of basis {} could not be changed: {:?}", bname, e).unwrap(),
+                        }
+                    } else {
+                        write!(ret, "usage: pddb changepass [basis name]").unwrap()
+                    }
+                }
+                // archives are saved to, and loaded from, the host's filesystem. The device has no channel yet to
+                // stream an archive off it or onto it, so there these commands don't exist.
+                #[cfg(not(target_os = "xous"))]
+                "export" => {
+                    if let Some(path) = tokens.next() {
+                        let bases: Vec<&str> = tokens.collect();
+                        let passphrase = archive_passphrase(&_env.xns);
+                        match std::fs::File::create(path) {
+                            Ok(file) => match self.pddb.export_archive(std::io::BufWriter::new(file), &passphrase,
+                                if bases.len() > 0 { Some(&bases[..]) } else { None }, None) {
+                                Ok(r) => write!(ret, "exported {} keys ({} bytes) to {}; {} denied", r.keys, r.bytes, path, r.denied).unwrap(),
+                                Err(e) => write!(ret, "export failed: {:?}", e).unwrap(),
+                            },
+                            Err(e) => write!(ret, "couldn't create {}: {:?}", path, e).unwrap(),
+                        }
+                    } else {
+                        write!(ret, "usage: pddb export [file] [basis names, or all open bases]").unwrap()
+                    }
+                }
+                #[cfg(not(target_os = "xous"))]
+                "import" => {
+                    if let Some(path) = tokens.next() {
+                        let policy = match tokens.next() {
+                            None | Some("skip") => Some(pddb::ConflictPolicy::Skip),
+                            Some("overwrite") => Some(pddb::ConflictPolicy::Overwrite),
+                            Some("rename") => Some(pddb::ConflictPolicy::Rename),
+                            Some(_) => None,
+                        };
+                        if let Some(policy) = policy {
+                            let passphrase = archive_passphrase(&_env.xns);
+                            match std::fs::File::open(path) {
+                                Ok(file) => match self.pddb.import_archive(std::io::BufReader::new(file), &passphrase, tokens.next(), policy) {
+                                    Ok(r) => write!(ret, "imported {} keys ({} bytes); {} skipped, {} renamed, {} denied",
+                                        r.keys, r.bytes, r.skipped, r.renamed, r.denied).unwrap(),
+                                    Err(e) => write!(ret, "import failed: {:?}", e).unwrap(),
+                                },
+                                Err(e) => write!(ret, "couldn't open {}: {:?}", path, e).unwrap(),
+                            }
+                        } else {
+                            write!(ret, "usage: pddb import [file] [skip|overwrite|rename] [into basis]").unwrap()
+                        }
+                    } else {
+                        write!(ret, "usage: pddb import [file] [skip|overwrite|rename] [into basis]").unwrap()
+                    }
+                }
                 #[cfg(feature="test-rekey")]
                 "rekey" => {
                     let old_dna = if let Some(dna_str) = tokens.next() {
@@ -841,6 +930,20 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
     }
 }
 
+/// Asks for the passphrase of an archive. Note that the text entry modal doesn't hide what is typed.
+#[cfg(not(target_os = "xous"))]
+fn archive_passphrase(xns: &xous_names::XousNames) -> std::string::String {
+    let modals = modals::Modals::new(xns).expect("couldn't connect to modals");
+    let mut entry = modals.alert_builder("Archive passphrase")
+        .field(None, None)
+        .build()
+        .expect("couldn't get archive passphrase")
+        .first();
+    let passphrase = entry.as_str().to_string();
+    entry.volatile_clear();
+    passphrase
+}
+
 #[cfg(all(feature="pddbtest", feature="autobasis"))]
 enum VectorType {
     Small(usize),
@@ -2,6 +2,7 @@ use crate::{CommonEnv, ShellCmdApi};
 use core::fmt::Write;
 use std::io::Write as PddbWrite;
 use xous_ipc::String;
+use net::profiles;
 
 #[derive(Debug)]
 pub struct Wlan {
@@ -24,6 +25,15 @@ wlan shell command:
         and password, otherwise NOP
 - leave: if joined, disconnect from AP
 - status: get wlan radio status (power state? connected? AP info?)
+- known: list the saved networks and their settings
+- prio N ...: networks with a higher priority (0-255) are joined first
+- autojoin on|off ...: whether the connection manager joins the network on its own
+- hidden on|off ...: hidden networks are tried even when a scan doesn't turn them up
+- static addr/len gateway ...: use an address of our own instead of DHCP
+- dhcp ...: go back to DHCP
+- dns addr|auto ...: use a DNS server of our choosing, or the network's
+- forget ...: remove the network, password and all
+The trailing `...` is the SSID of a saved network.
 */
 impl<'a> ShellCmdApi<'a> for Wlan {
     cmd_api!(wlan); // inserts boilerplate for command API
@@ -34,7 +44,7 @@ impl<'a> ShellCmdApi<'a> for Wlan {
         env: &mut CommonEnv,
     ) -> Result<Option<String<1024>>, xous::Error> {
         let mut ret = String::<1024>::new();
-        let helpstring = "wlan [on] [off] [setssid ...] [setpass ...] [join] [leave] [status] [save] [known]";
+        let helpstring = "wlan [on] [off] [setssid ...] [setpass ...] [join] [leave] [status] [save] [known]\n[prio N ...] [autojoin on|off ...] [hidden on|off ...] [static addr/len gw ...] [dhcp ...] [dns addr|auto ...] [forget ...]";
         let mut show_help = false;
 
         let mut tokens = args.as_str().unwrap().split(' ');
@@ -122,11 +132,21 @@ impl<'a> ShellCmdApi<'a> for Wlan {
                 }
                 "known" => {
                     let pddb = pddb::Pddb::new();
-                    match pddb.list_keys(net::AP_DICT_NAME, None) {
-                        Ok(list) => {
-                            write!(ret, "Saved network configs:\n").unwrap();
-                            for item in list.iter() {
-                                write!(ret, "- {}", item).ok(); // whatever, maybe we have too many?
+                    match profiles::list(&pddb) {
+                        Ok(mut list) => {
+                            list.sort_by_key(|p| std::cmp::Reverse(p.priority));
+                            write!(ret, "Saved network configs:").unwrap();
+                            for p in list.iter() {
+                                // whatever, maybe we have too many?
+                                write!(ret, "\n- {} (prio {}{}{})", p.ssid, p.priority,
+                                    if p.auto_join { "" } else { ", manual" },
+                                    if p.hidden { ", hidden" } else { "" }).ok();
+                                if let Some(ipv4) = p.static_ipv4 {
+                                    write!(ret, " {}/{} via {}", ipv4.addr, ipv4.prefix_len, ipv4.gateway).ok();
+                                }
+                                if let Some(dns) = p.dns {
+                                    write!(ret, " dns {}", dns).ok();
+                                }
                             }
                         }
                         Err(e) => {
@@ -134,6 +154,66 @@ impl<'a> ShellCmdApi<'a> for Wlan {
                         }
                     }
                 }
+                "prio" | "autojoin" | "hidden" | "static" | "dhcp" | "dns" | "forget" => {
+                    let mut static_ipv4 = None;
+                    let arg = match sub_cmd {
+                        "prio" | "autojoin" | "hidden" | "dns" => tokens.next(),
+                        "static" => {
+                            static_ipv4 = tokens.next().zip(tokens.next())
+                                .and_then(|(cidr, gateway)| profiles::StaticIpv4::parse(cidr, gateway));
+                            static_ipv4.map(|_| "")
+                        }
+                        _ => Some(""),
+                    };
+                    let mut ssid = String::<1024>::new();
+                    join_tokens(&mut ssid, &mut tokens);
+                    let ssid = ssid.to_str();
+                    let pddb = pddb::Pddb::new();
+                    let saved = pddb.list_keys(net::AP_DICT_NAME, None).unwrap_or_default();
+                    if !saved.iter().any(|s| s == ssid) {
+                        write!(ret, "No saved network called `{}`", ssid).unwrap();
+                    } else if sub_cmd == "forget" {
+                        match profiles::forget(&pddb, ssid) {
+                            Ok(_) => write!(ret, "Forgot {}", ssid).unwrap(),
+                            Err(e) => write!(ret, "PDDB error removing network: {:?}", e).unwrap(),
+                        }
+                    } else {
+                        let mut profile = profiles::load(&pddb, ssid);
+                        let ok = match (sub_cmd, arg) {
+                            ("prio", Some(n)) => n.parse().map(|n| profile.priority = n).is_ok(),
+                            ("autojoin", Some("on")) | ("autojoin", Some("off")) => {
+                                profile.auto_join = arg == Some("on");
+                                true
+                            }
+                            ("hidden", Some("on")) | ("hidden", Some("off")) => {
+                                profile.hidden = arg == Some("on");
+                                true
+                            }
+                            ("static", Some(_)) => {
+                                profile.static_ipv4 = static_ipv4;
+                                true
+                            }
+                            ("dhcp", _) => {
+                                profile.static_ipv4 = None;
+                                true
+                            }
+                            ("dns", Some("auto")) => {
+                                profile.dns = None;
+                                true
+                            }
+                            ("dns", Some(addr)) => addr.parse().map(|addr| profile.dns = Some(addr)).is_ok(),
+                            _ => false,
+                        };
+                        if !ok {
+                            show_help = true;
+                        } else {
+                            match profiles::store(&pddb, &profile) {
+                                Ok(_) => write!(ret, "{} updated; it takes effect the next time it's joined", ssid).unwrap(),
+                                Err(e) => write!(ret, "PDDB error storing network settings: {:?}", e).unwrap(),
+                            }
+                        }
+                    }
+                }
                 "join" => {
                     let _ = match env.com.wlan_join() {
                         Ok(_) => {
@@ -0,0 +1,165 @@
+//! Persists warnings and errors from the log server's ring buffer into the PDDB, so they can be
+//! retrieved after a field failure.
+//!
+//! The log server can't do this itself, because the PDDB depends on the log server. Instead, this
+//! thread periodically drains new records out of the ring, and appends them to a rotating set of
+//! keys in `log_server::api::LOG_PERSIST_DICT`. Persistence is opt-in: it only runs while the
+//! `persist` key holds a level, which is set with the shellchat `log persist` command.
+//!
+//! The same thread also drains crash reports held by the log server into
+//! `log_server::api::CRASH_PERSIST_DICT`. That is always on, as a crash report is only useful if it was
+//! saved before anybody knew they needed it.
+use std::fmt::Write as FmtWrite;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::thread;
+use log_server::api::{level_name, LOG_PERSIST_DICT, LOG_PERSIST_LEVEL_KEY, LOG_PERSIST_HEAD_KEY, LOG_PERSIST_FILES};
+use log_server::api::{CrashRecord, CRASH_PERSIST_DICT, CRASH_PERSIST_NEXT_KEY, CRASH_PERSIST_SLOTS};
+use pddb::{Pddb, PddbMountPoller};
+
+/// How often the ring buffer is checked for new records
+const PERSIST_INTERVAL_MS: usize = 10_000;
+/// Rotate to the next key once the current one grows past this
+const PERSIST_FILE_MAX: u64 = 16 * 1024;
+
+pub(crate) fn start_log_persist() {
+    thread::spawn({
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            let pddb_poller = PddbMountPoller::new();
+            while !pddb_poller.is_mounted_nonblocking() {
+                tt.sleep_ms(2000).unwrap();
+            }
+            let pddb = Pddb::new();
+            let localtime = llio::LocalTime::new();
+            let mut since = 0u64;
+            let mut boot_marked = false;
+            let mut crash = Box::new(CrashRecord::default());
+            loop {
+                tt.sleep_ms(PERSIST_INTERVAL_MS).unwrap();
+                while log_server::take_crash_report(&mut crash).unwrap_or(false) {
+                    let time = localtime.get_utc_time_ms().map(|ms| ms / 1000);
+                    match save_crash(&pddb, &crash, time) {
+                        Ok(slot) => log::info!("saved crash report for PID {} ({}) as report.{}", crash.pid, crash.name(), slot),
+                        Err(e) => log::error!("couldn't save crash report: {:?}", e),
+                    }
+                }
+                let level = match persist_level(&pddb) {
+                    Some(level) => level,
+                    None => continue,
+                };
+                // records don't carry a timestamp, so we stamp them with the time they were persisted
+                let stamp = match localtime.get_utc_time_ms() {
+                    Some(ms) => format!("{}", ms / 1000),
+                    None => String::from("-"),
+                };
+                let mut text = String::new();
+                since = match log_server::for_each_record(since, level, |r| {
+                    writeln!(text, "{} {} {}:{}: {}", stamp, r.pid, level_name(r.level), r.module, r.args).ok();
+                }) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        log::error!("couldn't read log ring: {:?}", e);
+                        continue;
+                    }
+                };
+                if text.len() == 0 {
+                    continue;
+                }
+                if !boot_marked {
+                    text.insert_str(0, &format!("--- boot @ {} ---\n", stamp));
+                    boot_marked = true;
+                }
+                if let Err(e) = append(&pddb, &text) {
+                    // don't log this at warn or above, or we'd try to persist our own failure forever
+                    log::info!("couldn't persist log records: {:?}", e);
+                }
+            }
+        }
+    });
+}
+
+fn persist_level(pddb: &Pddb) -> Option<log::Level> {
+    let mut key = pddb.get(
+        LOG_PERSIST_DICT, LOG_PERSIST_LEVEL_KEY,
+        Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
+        None, None::<fn()>
+    ).ok()?;
+    let mut setting = String::new();
+    key.read_to_string(&mut setting).ok()?;
+    match setting.as_str() {
+        "error" => Some(log::Level::Error),
+        "warn" => Some(log::Level::Warn),
+        _ => None,
+    }
+}
+
+fn append(pddb: &Pddb, text: &str) -> std::io::Result<()> {
+    let mut head = 0u32;
+    let mut head_key = pddb.get(
+        LOG_PERSIST_DICT, LOG_PERSIST_HEAD_KEY,
+        Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), true, true,
+        Some(4), None::<fn()>
+    )?;
+    let mut head_buf = [0u8; 4];
+    if head_key.read(&mut head_buf).unwrap_or(0) == 4 {
+        head = u32::from_le_bytes(head_buf);
+    }
+    let mut file = open_file(pddb, head)?;
+    let len = file.seek(SeekFrom::End(0))?;
+    if len + text.len() as u64 > PERSIST_FILE_MAX && len != 0 {
+        // rotate: the next slot holds the oldest records, so start it over
+        head = head.wrapping_add(1);
+        let name = file_name(head);
+        pddb.delete_key(LOG_PERSIST_DICT, &name, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS)).ok();
+        file = open_file(pddb, head)?;
+        head_key.seek(SeekFrom::Start(0))?;
+        head_key.write_all(&head.to_le_bytes())?;
+    }
+    file.write_all(text.as_bytes())?;
+    pddb.sync().ok();
+    Ok(())
+}
+
+/// Saves the text form of `crash` to the next slot, returning the slot number.
+fn save_crash(pddb: &Pddb, crash: &CrashRecord, time: Option<u64>) -> std::io::Result<u32> {
+    let mut text = String::new();
+    crash.write_report(&mut text, time).ok();
+
+    let mut next_key = pddb.get(
+        CRASH_PERSIST_DICT, CRASH_PERSIST_NEXT_KEY,
+        Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), true, true,
+        Some(4), None::<fn()>
+    )?;
+    let mut next_buf = [0u8; 4];
+    let next = if next_key.read(&mut next_buf).unwrap_or(0) == 4 {
+        u32::from_le_bytes(next_buf)
+    } else {
+        0
+    };
+    let slot = next % CRASH_PERSIST_SLOTS;
+    let name = format!("report.{}", slot);
+    // keys can't be truncated, so the oldest report is removed before its slot is reused
+    pddb.delete_key(CRASH_PERSIST_DICT, &name, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS)).ok();
+    let mut report = pddb.get(
+        CRASH_PERSIST_DICT, &name,
+        Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), true, true,
+        Some(text.len()), None::<fn()>
+    )?;
+    report.write_all(text.as_bytes())?;
+    next_key.seek(SeekFrom::Start(0))?;
+    next_key.write_all(&next.wrapping_add(1).to_le_bytes())?;
+    pddb.sync().ok();
+    Ok(slot)
+}
+
+fn file_name(head: u32) -> String {
+    format!("saved.{}", head % LOG_PERSIST_FILES)
+}
+
+fn open_file<'a>(pddb: &'a Pddb, head: u32) -> std::io::Result<pddb::PddbKey<'a>> {
+    pddb.get(
+        LOG_PERSIST_DICT, &file_name(head),
+        Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), true, true,
+        Some(PERSIST_FILE_MAX as usize), None::<fn()>
+    )
+}
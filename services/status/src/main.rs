@@ -10,7 +10,9 @@ mod kbdmenu;
 use kbdmenu::*;
 mod app_autogen;
 mod time;
+mod wifi;
 mod ecup;
+mod logpersist;
 
 use com::api::*;
 use root_keys::api::{BackupOp, BackupKeyboardLayout};
@@ -146,6 +148,8 @@ fn wrapped_main() -> ! {
     // this kicks off the thread that services the `libstd` calls for time-related things.
     // we want this started really early, because it sanity checks the RTC and a bunch of other stuff.
     time::start_time_server();
+    // drains warnings and errors from the log server into the PDDB, if the user has asked for it
+    logpersist::start_log_persist();
 
     // ------------------ acquire the status canvas GID
     let xns = xous_names::XousNames::new().unwrap();
@@ -318,6 +322,9 @@ fn wrapped_main() -> ! {
     let time_sid = xous::create_server().unwrap();
     let time_cid = xous::connect(time_sid).unwrap();
     time::start_time_ux(time_sid);
+    let wifi_sid = xous::create_server().unwrap();
+    let wifi_cid = xous::connect(wifi_sid).unwrap();
+    wifi::start_wifi_ux(wifi_sid);
     // this is used by the main loop to get the localtime to show on the status bar
     let mut localtime = llio::LocalTime::new();
 
@@ -335,7 +342,7 @@ fn wrapped_main() -> ! {
     log::debug!("starting main menu thread");
     let main_menu_sid = xous::create_server().unwrap();
     let status_cid = xous::connect(status_sid).unwrap();
-    let menu_manager = create_main_menu(keys.clone(), main_menu_sid, status_cid, &com, time_cid);
+    let menu_manager = create_main_menu(keys.clone(), main_menu_sid, status_cid, &com, time_cid, wifi_cid);
     create_app_menu(xous::connect(status_sid).unwrap());
     let kbd_mgr = xous::create_server().unwrap();
     let kbd_menumatic = create_kbd_menu(xous::connect(status_sid).unwrap(), kbd_mgr);
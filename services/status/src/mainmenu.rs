@@ -8,7 +8,7 @@ use num_traits::*;
 use crate::StatusOpcode;
 
 #[allow(unused_variables)] // quiets a warning about unused com that is emitted in tts config. Would be nice to make this more targeted...
-pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, menu_management_sid: xous::SID, status_conn: xous::CID, com: &com::Com, time_ux_conn: xous::CID) -> MenuMatic {
+pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, menu_management_sid: xous::SID, status_conn: xous::CID, com: &com::Com, time_ux_conn: xous::CID, wifi_ux_conn: xous::CID) -> MenuMatic {
     let key_conn = keys.lock().unwrap().conn();
 
     let mut menuitems = Vec::<MenuItem>::new();
@@ -117,6 +117,14 @@ pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, menu_management_sid: xous::S
         });
     }
 
+    menuitems.push(MenuItem {
+        name: String::from_str(t!("wlan.menu", xous::LANG)),
+        action_conn: Some(wifi_ux_conn),
+        action_opcode: crate::wifi::WifiUxOp::ManageNetworks.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+
     menuitems.push(MenuItem {
         name: String::from_str(t!("mainmenu.reboot", xous::LANG)),
         action_conn: Some(status_conn),
@@ -21,6 +21,10 @@
 /// The second representation is an optimization to avoid hitting the I2C module constantly to
 /// read RTC, plus you get milliseconds resolution. Time "T" can be updated at any time by just
 /// reading the RTC and noting the ticktimer offset at the point of reading.
+///
+/// "offset to current TZ" is either a fixed offset entered by the user, or an IANA time zone
+/// selected from the compact database in `llio::tz`, in which case DST is applied automatically.
+/// Clients should use `llio::LocalTime` rather than talking to this server directly.
 use std::thread;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -48,6 +52,8 @@ pub(crate) const TIME_SERVER_DICT: &'static str = "sys.rtc";
 const TIME_SERVER_UTC_OFFSET: &'static str = "utc_offset";
 /// This is the offset from UTC to the display time zone. This can vary when the user changes time zones.
 pub(crate) const TIME_SERVER_TZ_OFFSET: &'static str = "tz_offset";
+/// IANA name of the display time zone. If present, this takes precedence over `tz_offset`.
+pub(crate) const TIME_SERVER_TZ_NAME: &'static str = "tz_name";
 
 #[allow(dead_code)]
 const CTL3: usize = 0;
@@ -84,6 +90,12 @@ pub(crate) enum TimeOp {
     WallClockTimeInit = 6,
     /// Self-poll for PDDB mount
     PddbMountPoll = 7,
+    /// Sets the time zone by index into `llio::tz::ZONES`
+    SetTzIndex = 8,
+    /// Get the time zone index, biased by 1 (0 means a fixed offset is in use)
+    GetTzIndex = 9,
+    /// Query to see if time relative to UTC has been set (does not require a timezone)
+    UtcTimeInit = 10,
 }
 
 /// Do not modify the discriminants in this structure. They are used in `libstd` directly.
@@ -120,6 +132,55 @@ impl NtpTimestampGenerator for StdTimestampGen {
 }
 
 
+/// Maps the monotonic ticktimer onto the RTC seconds count.
+///
+/// The RTC only has 1-second resolution, so a re-anchor can land up to a second behind the previous
+/// extrapolation; `last_ms` keeps the reported time from running backwards when that happens.
+/// The ticktimer does not count time spent in suspend, so on resume the mapping is marked `stale`
+/// and gets re-anchored before it is used again.
+struct HwClock {
+    rtc_secs: u64,
+    tt_ms: u64,
+    last_ms: i64,
+    stale: bool,
+}
+impl HwClock {
+    fn new(rtc_secs: u64, tt_ms: u64) -> Self {
+        HwClock { rtc_secs, tt_ms, last_ms: 0, stale: false }
+    }
+    fn anchor(&mut self, rtc_secs: u64, tt_ms: u64) {
+        // a jump backwards by more than the RTC resolution is a real change (e.g. RTC reset), not jitter
+        if (rtc_secs as i64) * 1000 + 2000 < self.last_ms {
+            log::warn!("RTC went backwards by {}ms; resetting monotonic clamp", self.last_ms - (rtc_secs as i64) * 1000);
+            self.last_ms = 0;
+        }
+        self.rtc_secs = rtc_secs;
+        self.tt_ms = tt_ms;
+        self.stale = false;
+    }
+    /// Milliseconds counted by the RTC, extrapolated with the ticktimer
+    fn now_ms(&mut self, tt_ms: u64) -> i64 {
+        let t = self.rtc_secs as i64 * 1000 + (tt_ms - self.tt_ms) as i64;
+        if t > self.last_ms {
+            self.last_ms = t;
+        }
+        self.last_ms
+    }
+    /// Re-anchors the clock against the RTC. Returns `false` if the RTC could not be read.
+    fn sync(&mut self, llio: &llio::Llio, tt: &ticktimer_server::Ticktimer) -> bool {
+        match llio.get_rtc_secs() {
+            Ok(secs) => {
+                self.anchor(secs, tt.elapsed_ms());
+                true
+            }
+            Err(e) => {
+                log::warn!("RTC read yielded error: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct UdpSocketWrapper(UdpSocket);
 
@@ -163,10 +224,12 @@ pub fn start_time_server() {
             while !rtc_checked.load(Ordering::SeqCst) {
                 tt.sleep_ms(42).unwrap();
             }
-            let mut start_rtc_secs = llio.get_rtc_secs().expect("couldn't read RTC offset value");
-            let mut start_tt_ms = tt.elapsed_ms();
-            log::trace!("start_rtc_secs: {}", start_rtc_secs);
-            log::trace!("start_tt_ms: {}", start_tt_ms);
+            let mut clock = HwClock::new(
+                llio.get_rtc_secs().expect("couldn't read RTC offset value"),
+                tt.elapsed_ms()
+            );
+            log::trace!("start_rtc_secs: {}", clock.rtc_secs);
+            log::trace!("start_tt_ms: {}", clock.tt_ms);
 
             // register a suspend/resume listener
             let sr_cid = xous::connect(pub_sid).expect("couldn't create suspend callback connection");
@@ -207,33 +270,20 @@ pub fn start_time_server() {
                         susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
                         // resync time on resume
                         let mut count = 0;
-                        loop {
-                            match llio.get_rtc_secs() {
-                                Ok(secs) => {
-                                    start_rtc_secs = secs;
-                                    start_tt_ms = tt.elapsed_ms();
-                                    break;
-                                }
-                                Err(e) => {
-                                    log::warn!("RTC read yielded error; retrying: {:?}", e);
-                                    tt.sleep_ms(850).unwrap();
-                                    count += 1;
-                                    if count > 5 {
-                                        // this should be a panic, I think, not an abort.
-                                        panic!("Couldn't sync time on resume. Something went wrong with the RTC hardware.");
-                                    }
-                                }
+                        while !clock.sync(&llio, &tt) {
+                            tt.sleep_ms(850).unwrap();
+                            count += 1;
+                            if count > 5 {
+                                // this should be a panic, I think, not an abort.
+                                panic!("Couldn't sync time on resume. Something went wrong with the RTC hardware.");
                             }
                         }
                     }),
                     Some(TimeOp::HwSync) => {
-                        start_rtc_secs = llio.get_rtc_secs().expect("couldn't read RTC offset value");
-                        start_tt_ms = tt.elapsed_ms();
+                        clock.anchor(llio.get_rtc_secs().expect("couldn't read RTC offset value"), tt.elapsed_ms());
                     },
                     Some(TimeOp::GetUtcTimeMs) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                        let t =
-                            start_rtc_secs as i64 * 1000i64
-                            + (tt.elapsed_ms() - start_tt_ms) as i64;
+                        let t = clock.now_ms(tt.elapsed_ms());
                         log::debug!("hw only UTC ms {}", t);
                         xous::return_scalar2(msg.sender,
                             (((t as u64) >> 32) & 0xFFFF_FFFF) as usize,
@@ -241,9 +291,7 @@ pub fn start_time_server() {
                         ).expect("couldn't respond to GetUtcTimeMs");
                     }),
                     Some(TimeOp::GetLocalTimeMs) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                        let t =
-                            start_rtc_secs as i64 * 1000i64
-                            + (tt.elapsed_ms() - start_tt_ms) as i64;
+                        let t = clock.now_ms(tt.elapsed_ms());
                         assert!(t > 0, "time result is negative, this is an error");
                         log::debug!("hw only local ms {}", t);
                         xous::return_scalar2(msg.sender,
@@ -251,7 +299,8 @@ pub fn start_time_server() {
                             (t as u64 & 0xFFFF_FFFF) as usize,
                         ).expect("couldn't respond to GetLocalTimeMs");
                     }),
-                    Some(TimeOp::WallClockTimeInit) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                    Some(TimeOp::WallClockTimeInit) | Some(TimeOp::UtcTimeInit) | Some(TimeOp::GetTzIndex) =>
+                        xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                         // definitely not initialized
                         xous::return_scalar(msg.sender, 0).unwrap();
                     }),
@@ -284,10 +333,12 @@ pub fn start_time_server() {
             if tz_key.read(&mut tz_buf).unwrap_or(0) == 8 {
                 tz_offset_ms = i64::from_le_bytes(tz_buf);
             }
+            let mut tz_zone = load_tz_name(&tz_handle);
             log::debug!("offset_key: {}", utc_offset_ms / 1000);
             log::debug!("tz_key: {}", tz_offset_ms / 1000);
-            log::debug!("start_rtc_secs: {}", start_rtc_secs);
-            log::debug!("start_tt_ms: {}", start_tt_ms);
+            log::debug!("tz_name: {:?}", tz_zone.map(|i| llio::tz::ZONES[i].name));
+            log::debug!("start_rtc_secs: {}", clock.rtc_secs);
+            log::debug!("start_tt_ms: {}", clock.tt_ms);
             loop {
                 let msg = xous::receive_message(pub_sid).unwrap();
                 let opcode: Option<TimeOp> = FromPrimitive::from_usize(msg.body.id());
@@ -299,6 +350,9 @@ pub fn start_time_server() {
                     },
                     Some(TimeOp::SusRes) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
                         susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
+                        // the ticktimer didn't count the time we spent suspended; any query that arrives before the
+                        // deferred sync below will re-anchor on demand.
+                        clock.stale = true;
                         // resync time on resume, but give a little time for other processes to clear as this is not urgent
                         tt.sleep_ms(180).unwrap();
                         send_message(self_cid,
@@ -306,25 +360,19 @@ pub fn start_time_server() {
                         ).expect("couldn't queue sync request");
                     }),
                     Some(TimeOp::HwSync) => {
-                        match llio.get_rtc_secs() {
-                            Ok(val) => {
-                                start_rtc_secs = val;
-                                start_tt_ms = tt.elapsed_ms();
-                            }
-                            Err(e) => {
-                                log::warn!("Error syncing time: {:?}; retrying!", e);
-                                tt.sleep_ms(82).unwrap();
-                                send_message(self_cid,
-                                    Message::new_scalar(TimeOp::HwSync.to_usize().unwrap(), 0, 0, 0, 0)
-                                ).expect("couldn't queue sync request");
-                            }
+                        if !clock.sync(&llio, &tt) {
+                            log::warn!("Error syncing time; retrying!");
+                            tt.sleep_ms(82).unwrap();
+                            send_message(self_cid,
+                                Message::new_scalar(TimeOp::HwSync.to_usize().unwrap(), 0, 0, 0, 0)
+                            ).expect("couldn't queue sync request");
                         }
                     },
                     Some(TimeOp::GetUtcTimeMs) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                        let t =
-                            start_rtc_secs as i64 * 1000i64
-                            + (tt.elapsed_ms() - start_tt_ms) as i64
-                            + utc_offset_ms;
+                        if clock.stale {
+                            clock.sync(&llio, &tt);
+                        }
+                        let t = clock.now_ms(tt.elapsed_ms()) + utc_offset_ms;
                         assert!(t > 0, "time result is negative, this is an error");
                         log::trace!("utc ms {}", t);
                         xous::return_scalar2(msg.sender,
@@ -333,12 +381,15 @@ pub fn start_time_server() {
                         ).expect("couldn't respond to GetUtcTimeMs");
                     }),
                     Some(TimeOp::GetLocalTimeMs) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                        log::trace!("current offset {}", (start_rtc_secs as i64 * 1000i64 + (tt.elapsed_ms() - start_tt_ms) as i64) / 1000);
-                        let t =
-                            start_rtc_secs as i64 * 1000i64
-                            + (tt.elapsed_ms() - start_tt_ms) as i64
-                            + utc_offset_ms
-                            + tz_offset_ms;
+                        if clock.stale {
+                            clock.sync(&llio, &tt);
+                        }
+                        let utc = clock.now_ms(tt.elapsed_ms()) + utc_offset_ms;
+                        log::trace!("current offset {}", (utc - utc_offset_ms) / 1000);
+                        let t = utc + match tz_zone {
+                            Some(index) => llio::tz::ZONES[index].offset_ms_at(utc),
+                            None => tz_offset_ms,
+                        };
                         assert!(t > 0, "time result is negative, this is an error");
                         log::trace!("local since epoch {}", t / 1000);
                         xous::return_scalar2(msg.sender,
@@ -348,14 +399,13 @@ pub fn start_time_server() {
                     }),
                     Some(TimeOp::SetUtcTimeMs) => xous::msg_scalar_unpack!(msg, utc_hi_ms, utc_lo_ms, _, _, {
                         let utc_time_ms = (utc_hi_ms as i64) << 32 | (utc_lo_ms as i64);
-                        start_rtc_secs = llio.get_rtc_secs().expect("couldn't read RTC offset value");
-                        start_tt_ms = tt.elapsed_ms();
+                        clock.anchor(llio.get_rtc_secs().expect("couldn't read RTC offset value"), tt.elapsed_ms());
                         log::info!("utc_time: {}", utc_time_ms / 1000);
-                        log::info!("rtc_secs: {}", start_rtc_secs);
-                        log::info!("start_tt_ms: {}", start_tt_ms);
+                        log::info!("rtc_secs: {}", clock.rtc_secs);
+                        log::info!("start_tt_ms: {}", clock.tt_ms);
                         let offset =
                             utc_time_ms -
-                            (start_rtc_secs as i64) * 1000;
+                            (clock.rtc_secs as i64) * 1000;
                         utc_offset_ms = offset;
                         offset_key.seek(SeekFrom::Start(0)).expect("couldn't seek");
                         log::info!("setting offset to {} secs", offset / 1000);
@@ -376,15 +426,33 @@ pub fn start_time_server() {
                             log::info!("setting tz offset to {} secs", tz_ms / 1000);
                             assert_eq!(tz_key.write(&tz_ms.to_le_bytes()).unwrap_or(0), 8, "couldn't commit TZ time offset to PDDB");
                             tz_key.flush().expect("couldn't flush PDDB");
+                            // an explicit offset overrides any previously selected zone
+                            tz_zone = None;
+                            store_tz_name(&tz_handle, None);
                         }
                     }),
+                    Some(TimeOp::SetTzIndex) => xous::msg_scalar_unpack!(msg, index, _, _, _, {
+                        if let Some(zone) = llio::tz::ZONES.get(index) {
+                            log::info!("setting time zone to {}", zone.name);
+                            tz_zone = Some(index);
+                            store_tz_name(&tz_handle, Some(zone.name));
+                        } else {
+                            log::warn!("Requested time zone index {} is out of bounds, ignoring!", index);
+                        }
+                    }),
+                    Some(TimeOp::GetTzIndex) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                        xous::return_scalar(msg.sender, tz_zone.map(|i| i + 1).unwrap_or(0)).unwrap();
+                    }),
                     Some(TimeOp::WallClockTimeInit) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                        if utc_offset_ms == 0 || tz_offset_ms == 0 {
+                        if utc_offset_ms == 0 || (tz_offset_ms == 0 && tz_zone.is_none()) {
                             xous::return_scalar(msg.sender, 0).unwrap();
                         } else {
                             xous::return_scalar(msg.sender, 1).unwrap();
                         }
                     }),
+                    Some(TimeOp::UtcTimeInit) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                        xous::return_scalar(msg.sender, if utc_offset_ms == 0 { 0 } else { 1 }).unwrap();
+                    }),
                     None => log::error!("Time server public thread received unknown opcode: {:?}", msg),
                 }
             }
@@ -482,6 +550,45 @@ pub fn start_time_server() {
     });
 }
 
+/// Reads the persisted time zone selection, returning its index into `llio::tz::ZONES`.
+fn load_tz_name(pddb: &Pddb) -> Option<usize> {
+    let mut key = pddb.get(
+        TIME_SERVER_DICT,
+        TIME_SERVER_TZ_NAME,
+        Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
+        None,
+        None::<fn()>
+    ).ok()?;
+    let mut name = String::new();
+    key.read_to_string(&mut name).ok()?;
+    let index = llio::tz::find(&name);
+    if index.is_none() && name.len() > 0 {
+        log::warn!("Stored time zone {} is not in the time zone database, falling back to fixed offset", name);
+    }
+    index
+}
+
+/// Persists the time zone selection. `None` removes it, so that the fixed `tz_offset` applies.
+fn store_tz_name(pddb: &Pddb, name: Option<&str>) {
+    // keys can't be truncated, so always start from a fresh key
+    pddb.delete_key(TIME_SERVER_DICT, TIME_SERVER_TZ_NAME, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS)).ok();
+    if let Some(name) = name {
+        match pddb.get(
+            TIME_SERVER_DICT,
+            TIME_SERVER_TZ_NAME,
+            Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), true, true,
+            Some(64),
+            None::<fn()>
+        ) {
+            Ok(mut key) => {
+                assert_eq!(key.write(name.as_bytes()).unwrap_or(0), name.len(), "couldn't commit time zone to PDDB");
+            }
+            Err(e) => log::error!("couldn't open time zone key: {:?}", e),
+        }
+    }
+    pddb.sync().ok();
+}
+
 #[allow(dead_code)]
 fn is_rtc_invalid(settings: &[u8]) -> bool {
     ((settings[CTL3] & 0xE0) != (Control3::BATT_STD_BL_EN).bits()) // power switchover setting should be initialized
@@ -551,6 +658,7 @@ pub fn start_time_ux(sid: xous::SID) {
             let timeserver_cid = xous::connect(xous::SID::from_bytes(crate::time::TIME_SERVER_PUBLIC).unwrap()).unwrap();
             let pddb_poller = pddb::PddbMountPoller::new();
             let trng = trng::Trng::new(&xns).unwrap();
+            let localtime = llio::LocalTime::new();
 
             loop {
                 let msg = xous::receive_message(sid).unwrap();
@@ -560,44 +668,34 @@ pub fn start_time_ux(sid: xous::SID) {
                             modals.show_notification(t!("stats.please_mount", xous::LANG), None).expect("couldn't show notification");
                             continue;
                         }
-                        let tz_set_handle = pddb::Pddb::new();
-                        let mut tz_set = false;
-                        let mut tz_offset_ms = 0i64;
-                        let maybe_tz_set_key = tz_set_handle.get(
-                            TIME_SERVER_DICT,
-                            TIME_SERVER_TZ_OFFSET,
-                            Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
-                            None,
-                            None::<fn()>
-                        ).ok();
-                        if let Some(mut tz_set_key) = maybe_tz_set_key {
-                            let mut tz_buf = [0u8; 8];
-                            if tz_set_key.read(&mut tz_buf).unwrap_or(0) == 8 {
-                                tz_offset_ms = i64::from_le_bytes(tz_buf);
-                                tz_set = true;
+                        let mut tz_selection = localtime.get_timezone()
+                            .and_then(|zone| llio::tz::find(zone.name))
+                            .map(|index| TzSelection::Zone(index));
+                        if tz_selection.is_none() {
+                            let tz_set_handle = pddb::Pddb::new();
+                            let maybe_tz_set_key = tz_set_handle.get(
+                                TIME_SERVER_DICT,
+                                TIME_SERVER_TZ_OFFSET,
+                                Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS), false, false,
+                                None,
+                                None::<fn()>
+                            ).ok();
+                            if let Some(mut tz_set_key) = maybe_tz_set_key {
+                                let mut tz_buf = [0u8; 8];
+                                if tz_set_key.read(&mut tz_buf).unwrap_or(0) == 8 {
+                                    tz_selection = Some(TzSelection::Fixed(i64::from_le_bytes(tz_buf)));
+                                }
                             }
                         }
                         // note that we don't do an "else" here because we also want to catch the case of
                         // a key exists, but nothing was written to it (length of key was 0 or inappropriate)
-                        if !tz_set {
-                            log::info!("{}RTC.TZ,{}", xous::BOOKEND_START, xous::BOOKEND_END);
-                            let tz_str = modals.alert_builder(t!("rtc.timezone", xous::LANG))
-                                .field(None, Some(tz_ux_validator))
-                                .build()
-                                .expect("couldn't get timezone")
-                                .first();
-                            let tz = simple_kilofloat_parse(tz_str.as_str()).expect("pre-validated input failed to re-parse!");
-                            log::info!("got tz offset {}", tz);
-                            tz_offset_ms = (tz * 3600) as i64;
-                            xous::send_message(timeserver_cid,
-                                Message::new_scalar(
-                                    crate::time::TimeOp::SetTzOffsetMs.to_usize().unwrap(),
-                                    (tz_offset_ms >> 32) as usize,
-                                    (tz_offset_ms & 0xFFFF_FFFF) as usize,
-                                    0, 0,
-                                )
-                            ).expect("couldn't set timezone");
-                        }
+                        let tz_selection = match tz_selection {
+                            Some(sel) => sel,
+                            None => {
+                                log::info!("{}RTC.TZ,{}", xous::BOOKEND_START, xous::BOOKEND_END);
+                                tz_ux_select(&modals, timeserver_cid)
+                            }
+                        };
 
                         // see if we want to try to use NTP or not
                         log::info!("{}RTC.NTP,{}", xous::BOOKEND_START, xous::BOOKEND_END);
@@ -678,13 +776,14 @@ pub fn start_time_ux(sid: xous::SID) {
                         }
 
                         log::info!("Setting time: {}/{}/{} {}:{}:{}", months, days, years, hours, mins, secs);
-                        let new_dt = chrono::FixedOffset::east((tz_offset_ms / 1000) as i32).ymd(years as i32 + 2000, months as u32, days as u32)
+                        let local_dt = chrono::NaiveDate::from_ymd(years as i32 + 2000, months as u32, days as u32)
                         .and_hms(hours as u32, mins as u32, secs as u32);
+                        let utc_ms = local_dt.timestamp_millis() - tz_selection.offset_ms_for_local(local_dt.timestamp_millis());
                         xous::send_message(timeserver_cid,
                             Message::new_scalar(
                                 crate::time::TimeOp::SetUtcTimeMs.to_usize().unwrap(),
-                                ((new_dt.timestamp_millis() as u64) >> 32) as usize,
-                                (new_dt.timestamp_millis() as u64 & 0xFFFF_FFFF) as usize,
+                                ((utc_ms as u64) >> 32) as usize,
+                                (utc_ms as u64 & 0xFFFF_FFFF) as usize,
                                 0, 0,
                             )
                         ).expect("couldn't set time");
@@ -695,23 +794,7 @@ pub fn start_time_ux(sid: xous::SID) {
                             continue;
                         }
 
-                        let tz_str = modals.alert_builder(t!("rtc.timezone", xous::LANG))
-                            .field(None, Some(tz_ux_validator))
-                            .build()
-                            .expect("couldn't get timezone")
-                            .first();
-                        let tz = simple_kilofloat_parse(tz_str.as_str())
-                            .expect("pre-validated input failed to re-parse!");
-                        log::info!("got tz offset {}", tz);
-                        let tzoff_ms = (tz * 3600) as i64;
-                        xous::send_message(timeserver_cid,
-                            Message::new_scalar(
-                                crate::time::TimeOp::SetTzOffsetMs.to_usize().unwrap(),
-                                (tzoff_ms >> 32) as usize,
-                                (tzoff_ms & 0xFFFF_FFFF) as usize,
-                                0, 0,
-                            )
-                        ).expect("couldn't set timezone");
+                        tz_ux_select(&modals, timeserver_cid);
                     }),
                     Some(TimeUxOp::Quit) => {
                         xous::return_scalar(msg.sender, 0).unwrap();
@@ -727,6 +810,80 @@ pub fn start_time_ux(sid: xous::SID) {
     });
 }
 
+/// The result of asking the user for their time zone
+enum TzSelection {
+    /// index into `llio::tz::ZONES`
+    Zone(usize),
+    /// fixed offset from UTC in ms, for users whose zone isn't in the database
+    Fixed(i64),
+}
+impl TzSelection {
+    /// The offset to apply to a wall-clock time the user entered, given as ms since EPOCH in local time.
+    fn offset_ms_for_local(&self, local_ms: i64) -> i64 {
+        match self {
+            TzSelection::Zone(index) => {
+                let zone = &llio::tz::ZONES[*index];
+                // DST is decided on the UTC time, so go through the standard offset to get there; this can
+                // only be wrong inside the hour of ambiguity at a transition.
+                zone.offset_ms_at(local_ms - zone.std_offset as i64 * 60_000)
+            }
+            TzSelection::Fixed(ms) => *ms,
+        }
+    }
+}
+
+/// Asks the user for a region and then a city, and commits the choice to the time server. The last
+/// region entry allows a fixed UTC offset to be entered instead.
+fn tz_ux_select(modals: &modals::Modals, timeserver_cid: xous::CID) -> TzSelection {
+    for region in llio::tz::regions() {
+        modals.add_list_item(region).expect("couldn't build radio item list");
+    }
+    modals.add_list_item(t!("rtc.tz_other", xous::LANG)).expect("couldn't build radio item list");
+    let region = modals.get_radiobutton(t!("rtc.tz_region", xous::LANG)).expect("couldn't get time zone region");
+
+    let zones: Vec<usize> = llio::tz::zones_in(&region).collect();
+    let index = match zones.len() {
+        0 => None,
+        1 => Some(zones[0]),
+        _ => {
+            for &index in zones.iter() {
+                modals.add_list_item(llio::tz::ZONES[index].city()).expect("couldn't build radio item list");
+            }
+            let city = modals.get_radiobutton(t!("rtc.tz_city", xous::LANG)).expect("couldn't get time zone city");
+            zones.into_iter().find(|&index| llio::tz::ZONES[index].city() == city)
+        }
+    };
+    if let Some(index) = index {
+        log::info!("got time zone {}", llio::tz::ZONES[index].name);
+        xous::send_message(timeserver_cid,
+            Message::new_scalar(
+                crate::time::TimeOp::SetTzIndex.to_usize().unwrap(),
+                index, 0, 0, 0,
+            )
+        ).expect("couldn't set timezone");
+        TzSelection::Zone(index)
+    } else {
+        let tz_str = modals.alert_builder(t!("rtc.timezone", xous::LANG))
+            .field(None, Some(tz_ux_validator))
+            .build()
+            .expect("couldn't get timezone")
+            .first();
+        let tz = simple_kilofloat_parse(tz_str.as_str())
+            .expect("pre-validated input failed to re-parse!");
+        log::info!("got tz offset {}", tz);
+        let tzoff_ms = (tz * 3600) as i64;
+        xous::send_message(timeserver_cid,
+            Message::new_scalar(
+                crate::time::TimeOp::SetTzOffsetMs.to_usize().unwrap(),
+                (tzoff_ms >> 32) as usize,
+                (tzoff_ms & 0xFFFF_FFFF) as usize,
+                0, 0,
+            )
+        ).expect("couldn't set timezone");
+        TzSelection::Fixed(tzoff_ms)
+    }
+}
+
 // RTC Ux helper functions
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub(crate) enum ValidatorOp {
@@ -0,0 +1,183 @@
+//! The modals for managing saved wifi networks. The shell's `wlan` command can do the same; see
+//! `net::profiles` for what's kept about each network.
+use std::thread;
+use locales::t;
+use gam::modal::*;
+use net::profiles::{self, StaticIpv4};
+use num_traits::*;
+
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum WifiUxOp {
+    ManageNetworks,
+    Quit,
+}
+
+pub fn start_wifi_ux(sid: xous::SID) {
+    thread::spawn({
+        move || {
+            let xns = xous_names::XousNames::new().unwrap();
+            let modals = modals::Modals::new(&xns).unwrap();
+            let pddb_poller = pddb::PddbMountPoller::new();
+            loop {
+                let msg = xous::receive_message(sid).unwrap();
+                match FromPrimitive::from_usize(msg.body.id()) {
+                    Some(WifiUxOp::ManageNetworks) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
+                        if !pddb_poller.is_mounted_nonblocking() {
+                            modals.show_notification(t!("stats.please_mount", xous::LANG), None).expect("couldn't show notification");
+                            continue;
+                        }
+                        let pddb = pddb::Pddb::new();
+                        manage_networks(&modals, &pddb);
+                    }),
+                    Some(WifiUxOp::Quit) => {
+                        log::warn!("quit received");
+                        break;
+                    }
+                    None => log::error!("unknown opcode: {:?}", msg),
+                }
+            }
+            xous::destroy_server(sid).unwrap();
+        }
+    });
+}
+
+/// Lets the user pick a saved network, then change its settings one at a time until they're done
+fn manage_networks(modals: &modals::Modals, pddb: &pddb::Pddb) {
+    let mut saved = match profiles::list(pddb) {
+        Ok(saved) => saved,
+        Err(e) => {
+            log::error!("couldn't list the saved networks: {:?}", e);
+            return;
+        }
+    };
+    if saved.is_empty() {
+        modals.show_notification(t!("wlan.no_networks", xous::LANG), None).expect("couldn't show notification");
+        return;
+    }
+    saved.sort_by_key(|p| std::cmp::Reverse(p.priority));
+    for profile in saved.iter() {
+        modals.add_list_item(&profile.ssid).expect("couldn't build radio item list");
+    }
+    modals.add_list_item(t!("wlan.cancel", xous::LANG)).expect("couldn't build radio item list");
+    let choice = match modals.get_radiobutton(t!("wlan.pick_network", xous::LANG)) {
+        Ok(choice) => choice,
+        _ => {
+            log::error!("get_radiobutton failed");
+            return;
+        }
+    };
+    let mut profile = match saved.into_iter().find(|p| p.ssid == choice) {
+        Some(profile) => profile,
+        None => return,
+    };
+
+    loop {
+        let yes_no = |flag: bool| if flag { t!("wlan.yes", xous::LANG) } else { t!("wlan.no", xous::LANG) };
+        let priority = format!("{} {}", t!("wlan.priority", xous::LANG), profile.priority);
+        let auto_join = format!("{} {}", t!("wlan.auto_join", xous::LANG), yes_no(profile.auto_join));
+        let hidden = format!("{} {}", t!("wlan.hidden", xous::LANG), yes_no(profile.hidden));
+        let ipv4 = match profile.static_ipv4 {
+            Some(ipv4) => format!("{} {}/{}", t!("wlan.ipv4", xous::LANG), ipv4.addr, ipv4.prefix_len),
+            None => format!("{} DHCP", t!("wlan.ipv4", xous::LANG)),
+        };
+        let dns = match profile.dns {
+            Some(dns) => format!("{} {}", t!("wlan.dns", xous::LANG), dns),
+            None => format!("{} {}", t!("wlan.dns", xous::LANG), t!("wlan.automatic", xous::LANG)),
+        };
+        for item in [&priority, &auto_join, &hidden, &ipv4, &dns] {
+            modals.add_list_item(item).expect("couldn't build radio item list");
+        }
+        modals.add_list_item(t!("wlan.forget", xous::LANG)).expect("couldn't build radio item list");
+        modals.add_list_item(t!("wlan.done", xous::LANG)).expect("couldn't build radio item list");
+        let choice = match modals.get_radiobutton(&profile.ssid) {
+            Ok(choice) => choice,
+            _ => {
+                log::error!("get_radiobutton failed");
+                return;
+            }
+        };
+
+        if choice == priority {
+            let entry = modals.alert_builder(t!("wlan.priority_prompt", xous::LANG))
+                .field(Some(profile.priority.to_string()), Some(priority_validator))
+                .build()
+                .expect("couldn't get priority")
+                .first();
+            profile.priority = entry.as_str().parse().expect("pre-validated input failed to re-parse!");
+        } else if choice == auto_join {
+            profile.auto_join = !profile.auto_join;
+        } else if choice == hidden {
+            profile.hidden = !profile.hidden;
+        } else if choice == ipv4 {
+            // an untouched field keeps what's shown in it, so only the current settings are shown
+            let entries = modals.alert_builder(t!("wlan.ipv4_prompt", xous::LANG))
+                .field(profile.static_ipv4.map(|ipv4| format!("{}/{}", ipv4.addr, ipv4.prefix_len)), Some(cidr_validator))
+                .field(profile.static_ipv4.map(|ipv4| ipv4.gateway.to_string()), Some(address_validator))
+                .build()
+                .expect("couldn't get IPv4 settings");
+            let content = entries.content();
+            let cidr = content[0].as_str();
+            profile.static_ipv4 = if cidr.is_empty() {
+                None
+            } else {
+                match StaticIpv4::parse(cidr, content[1].as_str()) {
+                    Some(ipv4) => Some(ipv4),
+                    None => {
+                        // the address was good, so it's the gateway that was left out
+                        modals.show_notification(t!("wlan.bad_address", xous::LANG), None).expect("couldn't show notification");
+                        continue;
+                    }
+                }
+            };
+        } else if choice == dns {
+            let entry = modals.alert_builder(t!("wlan.dns_prompt", xous::LANG))
+                .field(profile.dns.map(|dns| dns.to_string()), Some(address_validator))
+                .build()
+                .expect("couldn't get DNS server")
+                .first();
+            profile.dns = entry.as_str().parse().ok();
+        } else if choice == t!("wlan.forget", xous::LANG) {
+            modals.add_list_item(t!("wlan.yes", xous::LANG)).expect("couldn't build radio item list");
+            modals.add_list_item(t!("wlan.no", xous::LANG)).expect("couldn't build radio item list");
+            if modals.get_radiobutton(t!("wlan.forget_confirm", xous::LANG)).ok().as_deref() == Some(t!("wlan.yes", xous::LANG)) {
+                if let Err(e) = profiles::forget(pddb, &profile.ssid) {
+                    modals.show_notification(&format!("{}{:?}", t!("wlan.save_fail", xous::LANG), e), None).ok();
+                }
+                return;
+            }
+            continue;
+        } else {
+            return;
+        }
+        if let Err(e) = profiles::store(pddb, &profile) {
+            modals.show_notification(&format!("{}{:?}", t!("wlan.save_fail", xous::LANG), e), None).ok();
+            return;
+        }
+    }
+}
+
+fn priority_validator(input: TextEntryPayload) -> Option<ValidatorErr> {
+    match input.as_str().parse::<u32>() {
+        Ok(priority) if priority > u8::MAX as u32 => Some(ValidatorErr::from_str(t!("rtc.range_err", xous::LANG))),
+        Ok(_) => None,
+        _ => Some(ValidatorErr::from_str(t!("rtc.integer_err", xous::LANG))),
+    }
+}
+
+/// `a.b.c.d/n`, or nothing
+fn cidr_validator(input: TextEntryPayload) -> Option<ValidatorErr> {
+    if input.as_str().is_empty() || StaticIpv4::parse(input.as_str(), "0.0.0.0").is_some() {
+        None
+    } else {
+        Some(ValidatorErr::from_str(t!("wlan.bad_address", xous::LANG)))
+    }
+}
+
+/// `a.b.c.d`, or nothing
+fn address_validator(input: TextEntryPayload) -> Option<ValidatorErr> {
+    if input.as_str().is_empty() || input.as_str().parse::<std::net::Ipv4Addr>().is_ok() {
+        None
+    } else {
+        Some(ValidatorErr::from_str(t!("wlan.bad_address", xous::LANG)))
+    }
+}
@@ -0,0 +1,50 @@
+// Just enough of a DER reader to pull a readable name out of a certificate subject.
+
+const OID_COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+const OID_ORGANIZATION: [u8; 3] = [0x55, 0x04, 0x0a];
+
+/// Splits the first TLV off `data`, and returns its tag, its value, and what follows it.
+fn tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, mut data) = data.split_first()?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        // long form; nothing in a certificate name needs more than two length bytes
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 2 || data.len() < count {
+            return None;
+        }
+        let len = data[..count].iter().fold(0usize, |len, &b| (len << 8) | b as usize);
+        data = &data[count..];
+        len
+    };
+    if data.len() < len {
+        return None;
+    }
+    Some((tag, &data[..len], &data[len..]))
+}
+
+/// Returns the common name in `subject` (the contents of a Name), or its organization if it has no
+/// common name.
+pub(crate) fn subject_name(subject: &[u8]) -> Option<String> {
+    let mut organization = None;
+    let mut rdns = subject;
+    while !rdns.is_empty() {
+        let (_, rdn, rest) = tlv(rdns)?;
+        rdns = rest;
+        let mut attributes = rdn;
+        while !attributes.is_empty() {
+            let (_, attribute, rest) = tlv(attributes)?;
+            attributes = rest;
+            let (_, oid, value) = tlv(attribute)?;
+            let (_, value, _) = tlv(value)?;
+            if oid == OID_COMMON_NAME {
+                return Some(String::from_utf8_lossy(value).to_string());
+            } else if oid == OID_ORGANIZATION {
+                organization = Some(String::from_utf8_lossy(value).to_string());
+            }
+        }
+    }
+    organization
+}
@@ -0,0 +1,239 @@
+//! A TLS 1.3 client for Xous apps.
+//!
+//! Connections run over the `std::net::TcpStream`s that the net service provides, so anything that can
+//! open a socket can use this to talk to an HTTPS endpoint:
+//!
+//! ```no_run
+//! use std::io::{Read, Write};
+//! let tls = tls::Tls::new();
+//! let mut stream = tls.connect("betrusted.io", 443).unwrap();
+//! stream.write_all(b"GET / HTTP/1.1\r\nHost: betrusted.io\r\nConnection: close\r\n\r\n").unwrap();
+//! let mut page = Vec::new();
+//! stream.read_to_end(&mut page).ok();
+//! ```
+//!
+//! Servers are authenticated against the root CAs in the `tls.trusted` dictionary of the PDDB, so the set of
+//! CAs a device trusts is the user's to edit: certificates can be added with `trust_cert()` and removed with
+//! `untrust()`, and the Mozilla root program (as shipped by `webpki-roots`) can be copied in with
+//! `trust_mozilla_roots()`. Nothing is trusted until one of those is done. The store can be kept in a secret
+//! basis, in which case it is only in effect while that basis is open.
+//!
+//! The protocol is rustls', and the crypto under it is a provider of our own (see `provider.rs`), so it builds
+//! for the device as well as in hosted mode: randomness comes from the TRNG, AES and X25519 run on the
+//! hardware engines (through the workspace's patches of `aes` and `curve25519-dalek`), and the hashes come
+//! from `engine-sha512`. CA fingerprints are taken with the SHA-512 engine.
+
+mod der;
+mod provider;
+
+use digest::Digest;
+use rustls::pki_types::{CertificateDer, Der, ServerName, TrustAnchor};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use sha2::Sha512Trunc256;
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::io::{Result, Error, ErrorKind};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+
+/// PDDB dictionary that holds the trusted root CAs, one per key. Keys are named after the fingerprint of the
+/// CA (see `TrustedCa::fingerprint()`).
+pub const TLS_TRUSTED_DICT: &str = "tls.trusted";
+/// version of the records in `TLS_TRUSTED_DICT`
+const CA_RECORD_VERSION: u8 = 1;
+
+/// A TLS session over a TCP connection, as returned by `Tls::connect()`
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// A root CA, as it is kept in the PDDB. Only the parts of the certificate that are needed to check a chain
+/// against it are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustedCa {
+    /// a readable name, taken from the CN (or failing that, the O) of the subject
+    pub name: String,
+    /// DER of the subject name, without its outer SEQUENCE
+    pub subject: Vec<u8>,
+    /// DER of the subject public key info, without its outer SEQUENCE
+    pub spki: Vec<u8>,
+    /// DER of the name constraints extension, if the CA has one
+    pub name_constraints: Option<Vec<u8>>,
+}
+impl TrustedCa {
+    pub fn from_cert_der(cert: &[u8]) -> Result<TrustedCa> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(cert.to_vec()))
+            .or(Err(Error::new(ErrorKind::InvalidData, "not a usable X.509 certificate")))?;
+        Ok(TrustedCa::from_anchor(&roots.roots[0]))
+    }
+    fn from_anchor(anchor: &TrustAnchor) -> TrustedCa {
+        TrustedCa {
+            name: der::subject_name(&anchor.subject).unwrap_or_else(|| String::from("(unnamed)")),
+            subject: anchor.subject.to_vec(),
+            spki: anchor.subject_public_key_info.to_vec(),
+            name_constraints: anchor.name_constraints.as_ref().map(|nc| nc.to_vec()),
+        }
+    }
+    fn into_anchor(self) -> TrustAnchor<'static> {
+        TrustAnchor {
+            subject: Der::from(self.subject),
+            subject_public_key_info: Der::from(self.spki),
+            name_constraints: self.name_constraints.map(Der::from),
+        }
+    }
+    /// The name of the CA's key in the PDDB: the first 128 bits of the SHA-512/256 hash of its subject and
+    /// public key, in hex.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha512Trunc256::new();
+        hasher.update(&self.subject);
+        hasher.update(&self.spki);
+        hasher.finalize()[..16].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+    fn to_record(&self) -> Vec<u8> {
+        let mut record = vec![CA_RECORD_VERSION];
+        let name = &self.name.as_bytes()[..self.name.len().min(u8::MAX as usize)];
+        record.push(name.len() as u8);
+        record.extend_from_slice(name);
+        for field in [&self.subject[..], &self.spki[..], self.name_constraints.as_deref().unwrap_or(&[])].iter() {
+            record.extend_from_slice(&(field.len() as u16).to_le_bytes());
+            record.extend_from_slice(field);
+        }
+        record
+    }
+    fn from_record(record: &[u8]) -> Option<TrustedCa> {
+        let (&version, mut rest) = record.split_first()?;
+        if version != CA_RECORD_VERSION {
+            return None;
+        }
+        let (&name_len, tail) = rest.split_first()?;
+        let name = String::from_utf8_lossy(tail.get(..name_len as usize)?).to_string();
+        rest = &tail[name_len as usize..];
+        let mut fields = Vec::new();
+        for _ in 0..3 {
+            let len = u16::from_le_bytes([*rest.get(0)?, *rest.get(1)?]) as usize;
+            fields.push(rest.get(2..2 + len)?.to_vec());
+            rest = &rest[2 + len..];
+        }
+        let name_constraints = fields.pop().filter(|nc| !nc.is_empty());
+        let spki = fields.pop()?;
+        let subject = fields.pop()?;
+        Some(TrustedCa { name, subject, spki, name_constraints })
+    }
+}
+
+pub struct Tls {
+    pddb: pddb::Pddb,
+    /// built from the store on first use, and rebuilt after the store is changed through this object.
+    /// Changes made by other processes are seen by `Tls` objects made after them.
+    config: RefCell<Option<Arc<ClientConfig>>>,
+}
+impl Tls {
+    pub fn new() -> Tls {
+        Tls { pddb: pddb::Pddb::new(), config: RefCell::new(None) }
+    }
+
+    /// The root CAs in the store, with the names of their keys. Records that can't be decoded are logged
+    /// and left out.
+    pub fn trusted(&self) -> Vec<(String, TrustedCa)> {
+        let mut cas = Vec::new();
+        let keys = match self.pddb.list_keys(TLS_TRUSTED_DICT, None) {
+            Ok(keys) => keys,
+            Err(e) => {
+                if e.kind() != ErrorKind::NotFound {
+                    log::warn!("couldn't list the trusted CAs: {:?}", e);
+                }
+                return cas;
+            }
+        };
+        for key in keys {
+            let mut record = Vec::new();
+            match self.pddb.get(TLS_TRUSTED_DICT, &key, None, false, false, None, None::<fn()>)
+                .and_then(|mut k| k.read_to_end(&mut record)) {
+                Ok(_) => match TrustedCa::from_record(&record) {
+                    Some(ca) => cas.push((key, ca)),
+                    None => log::warn!("trusted CA {} has a record that can't be decoded", key),
+                },
+                Err(e) => log::warn!("couldn't read trusted CA {}: {:?}", key, e),
+            }
+        }
+        cas
+    }
+    /// Adds `ca` to the store, and returns the name of its key. Adding a CA that is already there is not an
+    /// error.
+    pub fn trust(&self, ca: &TrustedCa) -> Result<String> {
+        let key = ca.fingerprint();
+        let record = ca.to_record();
+        let mut k = self.pddb.get(TLS_TRUSTED_DICT, &key, None, true, true, Some(record.len()), None::<fn()>)?;
+        k.write_all(&record)?;
+        self.config.replace(None);
+        log::info!("trusting {} as {}", ca.name, key);
+        Ok(key)
+    }
+    /// Adds the root CA in the DER certificate `cert` to the store, and returns the name of its key.
+    pub fn trust_cert(&self, cert: &[u8]) -> Result<String> {
+        let key = self.trust(&TrustedCa::from_cert_der(cert)?)?;
+        self.pddb.sync()?;
+        Ok(key)
+    }
+    /// Adds all of the root CAs of the Mozilla root program to the store, and returns how many there are.
+    pub fn trust_mozilla_roots(&self) -> Result<usize> {
+        for anchor in webpki_roots::TLS_SERVER_ROOTS.iter() {
+            self.trust(&TrustedCa::from_anchor(anchor))?;
+        }
+        self.pddb.sync()?;
+        Ok(webpki_roots::TLS_SERVER_ROOTS.len())
+    }
+    /// Removes the CA stored under `key` from the store.
+    pub fn untrust(&self, key: &str) -> Result<()> {
+        self.pddb.delete_key(TLS_TRUSTED_DICT, key, None)?;
+        self.config.replace(None);
+        self.pddb.sync()
+    }
+
+    /// A client configuration that only speaks TLS 1.3, and trusts the CAs in the store. Fails with
+    /// `NotFound` if there are none, as no server could be authenticated.
+    pub fn client_config(&self) -> Result<Arc<ClientConfig>> {
+        if let Some(config) = self.config.borrow().as_ref() {
+            return Ok(Arc::clone(config));
+        }
+        let mut roots = RootCertStore::empty();
+        roots.extend(self.trusted().into_iter().map(|(_, ca)| ca.into_anchor()));
+        if roots.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "no trusted root CAs; see Tls::trust_mozilla_roots()"));
+        }
+        let config = ClientConfig::builder_with_provider(Arc::new(provider::provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .or(Err(Error::new(ErrorKind::Other, "TLS 1.3 is not available")))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let config = Arc::new(config);
+        self.config.replace(Some(Arc::clone(&config)));
+        Ok(config)
+    }
+
+    /// Opens a TCP connection to `host`:`port`, and starts a TLS session with it. The handshake happens on
+    /// the first read or write of the stream; an untrusted or mismatched certificate shows up as an
+    /// `InvalidData` error there.
+    pub fn connect(&self, host: &str, port: u16) -> Result<TlsStream> {
+        let conn = self.session(host)?;
+        let sock = TcpStream::connect((host, port))?;
+        Ok(StreamOwned::new(conn, sock))
+    }
+
+    /// Like `connect()`, but to a server whose address is already known, so no name lookup is done.
+    /// The certificate still has to be valid for `host`.
+    pub fn connect_to(&self, addr: SocketAddr, host: &str) -> Result<TlsStream> {
+        let conn = self.session(host)?;
+        let sock = TcpStream::connect(addr)?;
+        Ok(StreamOwned::new(conn, sock))
+    }
+
+    fn session(&self, host: &str) -> Result<ClientConnection> {
+        let config = self.client_config()?;
+        let server_name = ServerName::try_from(host.to_owned())
+            .or(Err(Error::new(ErrorKind::InvalidInput, "not a valid server name")))?;
+        ClientConnection::new(config, server_name)
+            .or_else(|e| Err(Error::new(ErrorKind::Other, format!("couldn't start TLS session: {:?}", e))))
+    }
+}
@@ -0,0 +1,444 @@
+// The crypto under rustls, built from crates that compile for Xous: randomness comes from the TRNG
+// (through getrandom, see imports/getrandom), SHA-256 and SHA-384 from the sha2 crate, which the workspace
+// patches to engine-sha512, AES from the aes crate, which is patched to the Xous AES library, and X25519
+// from curve25519-dalek, which is patched to use engine-25519. Everything else is plain RustCrypto.
+//
+// Only what a TLS 1.3 client needs is here: there are no private keys, so no client auth and no servers.
+
+use chacha20poly1305::aead::{generic_array::GenericArray, AeadInPlace, KeyInit};
+use digest::Digest;
+use hmac::{Mac, NewMac};
+use rustls::crypto::cipher::{
+    make_tls13_aad, AeadKey, InboundOpaqueMessage, InboundPlainMessage, Iv, MessageDecrypter,
+    MessageEncrypter, Nonce, OutboundOpaqueMessage, OutboundPlainMessage, PrefixedPayload,
+    Tls13AeadAlgorithm, UnsupportedOperationError,
+};
+use rustls::crypto::{
+    hash, hmac as tls_hmac, tls13::HkdfUsingHmac, ActiveKeyExchange, CipherSuiteCommon, CryptoProvider,
+    GetRandomFailed, KeyProvider, SecureRandom, SharedSecret, SupportedKxGroup, WebPkiSupportedAlgorithms,
+};
+use rustls::pki_types::{alg_id, AlgorithmIdentifier, InvalidSignature, PrivateKeyDer, SignatureVerificationAlgorithm};
+use rustls::sign::SigningKey;
+use rustls::{
+    CipherSuite, ContentType, Error, NamedGroup, ProtocolVersion, SignatureScheme, SupportedCipherSuite,
+    Tls13CipherSuite,
+};
+
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The provider `Tls` builds its client configurations with
+pub fn provider() -> CryptoProvider {
+    CryptoProvider {
+        cipher_suites: vec![TLS13_AES_128_GCM_SHA256, TLS13_AES_256_GCM_SHA384, TLS13_CHACHA20_POLY1305_SHA256],
+        kx_groups: vec![&X25519, &SECP256R1],
+        signature_verification_algorithms: SIGNATURE_ALGORITHMS,
+        secure_random: &Trng,
+        key_provider: &NoKeys,
+    }
+}
+
+// Randomness
+
+#[derive(Debug)]
+struct Trng;
+impl SecureRandom for Trng {
+    fn fill(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+        getrandom::getrandom(buf).or(Err(GetRandomFailed))
+    }
+}
+
+#[derive(Debug)]
+struct NoKeys;
+impl KeyProvider for NoKeys {
+    fn load_private_key(&self, _key_der: PrivateKeyDer<'static>) -> Result<Arc<dyn SigningKey>, Error> {
+        Err(Error::General("private keys are not supported".into()))
+    }
+}
+
+// Hashes, and the HMAC that rustls builds HKDF from
+
+struct Sha2<D> {
+    algorithm: hash::HashAlgorithm,
+    _digest: PhantomData<fn() -> D>,
+}
+type Sha256 = Sha2<sha2::Sha256>;
+type Sha384 = Sha2<sha2::Sha384>;
+static SHA256: Sha256 = Sha2 { algorithm: hash::HashAlgorithm::SHA256, _digest: PhantomData };
+static SHA384: Sha384 = Sha2 { algorithm: hash::HashAlgorithm::SHA384, _digest: PhantomData };
+
+impl<D: Digest + Clone + Send + Sync + 'static> hash::Hash for Sha2<D> {
+    fn start(&self) -> Box<dyn hash::Context> {
+        Box::new(Sha2Context(D::new()))
+    }
+    fn hash(&self, data: &[u8]) -> hash::Output {
+        hash::Output::new(&D::digest(data))
+    }
+    fn output_len(&self) -> usize {
+        D::output_size()
+    }
+    fn algorithm(&self) -> hash::HashAlgorithm {
+        self.algorithm
+    }
+}
+
+struct Sha2Context<D>(D);
+impl<D: Digest + Clone + Send + Sync + 'static> hash::Context for Sha2Context<D> {
+    fn fork_finish(&self) -> hash::Output {
+        hash::Output::new(&self.0.clone().finalize())
+    }
+    fn fork(&self) -> Box<dyn hash::Context> {
+        Box::new(Sha2Context(self.0.clone()))
+    }
+    fn finish(self: Box<Self>) -> hash::Output {
+        hash::Output::new(&self.0.finalize())
+    }
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+struct Hmac<D>(PhantomData<fn() -> D>);
+static HMAC_SHA256: Hmac<sha2::Sha256> = Hmac(PhantomData);
+static HMAC_SHA384: Hmac<sha2::Sha384> = Hmac(PhantomData);
+
+impl<D> tls_hmac::Hmac for Hmac<D>
+where
+    D: Digest + digest::Update + digest::BlockInput + digest::FixedOutput + digest::Reset + Default + Clone,
+    D: Send + Sync + 'static,
+{
+    fn with_key(&self, key: &[u8]) -> Box<dyn tls_hmac::Key> {
+        // HMAC takes keys of any length
+        Box::new(HmacKey(hmac::Hmac::<D>::new_from_slice(key).unwrap()))
+    }
+    fn hash_output_len(&self) -> usize {
+        <D as Digest>::output_size()
+    }
+}
+
+struct HmacKey<D: Digest + digest::Update + digest::BlockInput + digest::FixedOutput + digest::Reset + Default + Clone>(
+    hmac::Hmac<D>,
+);
+impl<D> tls_hmac::Key for HmacKey<D>
+where
+    D: Digest + digest::Update + digest::BlockInput + digest::FixedOutput + digest::Reset + Default + Clone,
+    D: Send + Sync + 'static,
+{
+    fn sign_concat(&self, first: &[u8], middle: &[&[u8]], last: &[u8]) -> tls_hmac::Tag {
+        let mut mac = self.0.clone();
+        mac.update(first);
+        for m in middle {
+            mac.update(m);
+        }
+        mac.update(last);
+        tls_hmac::Tag::new(&mac.finalize().into_bytes())
+    }
+    fn tag_len(&self) -> usize {
+        <D as Digest>::output_size()
+    }
+}
+
+// Cipher suites
+
+static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: 1 << 23,
+    },
+    hkdf_provider: &HkdfUsingHmac(&HMAC_SHA256),
+    aead_alg: &Aead::<aes_gcm::Aes128Gcm>(PhantomData),
+    quic: None,
+});
+
+static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS13_AES_256_GCM_SHA384,
+        hash_provider: &SHA384,
+        confidentiality_limit: 1 << 23,
+    },
+    hkdf_provider: &HkdfUsingHmac(&HMAC_SHA384),
+    aead_alg: &Aead::<aes_gcm::Aes256Gcm>(PhantomData),
+    quic: None,
+});
+
+static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite = SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: u64::MAX,
+    },
+    hkdf_provider: &HkdfUsingHmac(&HMAC_SHA256),
+    aead_alg: &Aead::<chacha20poly1305::ChaCha20Poly1305>(PhantomData),
+    quic: None,
+});
+
+/// All three AEADs have 16-byte tags and 12-byte nonces
+const TAG_LEN: usize = 16;
+
+struct Aead<A>(PhantomData<fn() -> A>);
+impl<A: AeadInPlace + KeyInit + Send + Sync + 'static> Tls13AeadAlgorithm for Aead<A> {
+    fn encrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageEncrypter> {
+        // rustls makes keys of `key_len()` bytes
+        Box::new(AeadCrypter { cipher: A::new_from_slice(key.as_ref()).unwrap(), iv })
+    }
+    fn decrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageDecrypter> {
+        Box::new(AeadCrypter { cipher: A::new_from_slice(key.as_ref()).unwrap(), iv })
+    }
+    fn key_len(&self) -> usize {
+        A::key_size()
+    }
+    fn extract_keys(&self, _key: AeadKey, _iv: Iv) -> Result<rustls::ConnectionTrafficSecrets, UnsupportedOperationError> {
+        // only needed to hand the session to a kernel TLS implementation, which Xous doesn't have
+        Err(UnsupportedOperationError)
+    }
+}
+
+struct AeadCrypter<A> {
+    cipher: A,
+    iv: Iv,
+}
+impl<A: AeadInPlace + Send + Sync> MessageEncrypter for AeadCrypter<A> {
+    fn encrypt(&mut self, msg: OutboundPlainMessage, seq: u64) -> Result<OutboundOpaqueMessage, Error> {
+        let total_len = self.encrypted_payload_len(msg.payload.len());
+        let mut payload = PrefixedPayload::with_capacity(total_len);
+        payload.extend_from_chunks(&msg.payload);
+        payload.extend_from_slice(&msg.typ.to_array());
+        let nonce = Nonce::new(&self.iv, seq).0;
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(GenericArray::from_slice(&nonce), &make_tls13_aad(total_len), payload.as_mut())
+            .or(Err(Error::EncryptError))?;
+        payload.extend_from_slice(&tag);
+        // all TLS 1.3 records claim to be TLS 1.2, see RFC 8446 section 5.1
+        Ok(OutboundOpaqueMessage::new(ContentType::ApplicationData, ProtocolVersion::TLSv1_2, payload))
+    }
+    fn encrypted_payload_len(&self, payload_len: usize) -> usize {
+        payload_len + 1 + TAG_LEN
+    }
+}
+impl<A: AeadInPlace + Send + Sync> MessageDecrypter for AeadCrypter<A> {
+    fn decrypt<'a>(&mut self, mut msg: InboundOpaqueMessage<'a>, seq: u64) -> Result<InboundPlainMessage<'a>, Error> {
+        let payload = &mut msg.payload;
+        if payload.len() < TAG_LEN {
+            return Err(Error::DecryptError);
+        }
+        let aad = make_tls13_aad(payload.len());
+        let nonce = Nonce::new(&self.iv, seq).0;
+        let plain_len = payload.len() - TAG_LEN;
+        let (plain, tag) = payload.split_at_mut(plain_len);
+        self.cipher
+            .decrypt_in_place_detached(GenericArray::from_slice(&nonce), &aad, plain, GenericArray::from_slice(tag))
+            .or(Err(Error::DecryptError))?;
+        payload.truncate(plain_len);
+        msg.into_tls13_unpadded_message()
+    }
+}
+
+// Key exchange
+
+#[derive(Debug)]
+struct X25519Group;
+static X25519: X25519Group = X25519Group;
+impl SupportedKxGroup for X25519Group {
+    fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+        let mut secret = [0u8; 32];
+        Trng.fill(&mut secret)?;
+        let secret = x25519_dalek::StaticSecret::from(secret);
+        let public = x25519_dalek::PublicKey::from(&secret);
+        Ok(Box::new(X25519Exchange { secret, public }))
+    }
+    fn name(&self) -> NamedGroup {
+        NamedGroup::X25519
+    }
+}
+
+struct X25519Exchange {
+    secret: x25519_dalek::StaticSecret,
+    public: x25519_dalek::PublicKey,
+}
+impl ActiveKeyExchange for X25519Exchange {
+    fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Result<SharedSecret, Error> {
+        let mut peer = [0u8; 32];
+        if peer_pub_key.len() != peer.len() {
+            return Err(Error::from(rustls::PeerMisbehaved::InvalidKeyShare));
+        }
+        peer.copy_from_slice(peer_pub_key);
+        let shared = self.secret.diffie_hellman(&x25519_dalek::PublicKey::from(peer));
+        // a low order point from the peer gives an all-zero secret, which RFC 8446 section 7.4.2 says to refuse
+        if shared.as_bytes().iter().all(|&b| b == 0) {
+            return Err(Error::from(rustls::PeerMisbehaved::InvalidKeyShare));
+        }
+        Ok(SharedSecret::from(&shared.as_bytes()[..]))
+    }
+    fn pub_key(&self) -> &[u8] {
+        self.public.as_bytes()
+    }
+    fn group(&self) -> NamedGroup {
+        NamedGroup::X25519
+    }
+}
+
+#[derive(Debug)]
+struct Secp256r1Group;
+static SECP256R1: Secp256r1Group = Secp256r1Group;
+impl SupportedKxGroup for Secp256r1Group {
+    fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+        // nearly every 32-byte string is a valid scalar; the odd one that isn't is just drawn again
+        let secret = loop {
+            let mut bytes = [0u8; 32];
+            Trng.fill(&mut bytes)?;
+            if let Ok(secret) = p256::SecretKey::from_slice(&bytes) {
+                break secret;
+            }
+        };
+        let public = secret.public_key().to_encoded_point(false).as_bytes().to_vec();
+        Ok(Box::new(Secp256r1Exchange { secret, public }))
+    }
+    fn name(&self) -> NamedGroup {
+        NamedGroup::secp256r1
+    }
+}
+
+struct Secp256r1Exchange {
+    secret: p256::SecretKey,
+    /// uncompressed SEC1 encoding, as TLS wants it
+    public: Vec<u8>,
+}
+impl ActiveKeyExchange for Secp256r1Exchange {
+    fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Result<SharedSecret, Error> {
+        let peer = p256::PublicKey::from_sec1_bytes(peer_pub_key)
+            .or(Err(Error::from(rustls::PeerMisbehaved::InvalidKeyShare)))?;
+        let shared = p256::ecdh::diffie_hellman(self.secret.to_nonzero_scalar(), peer.as_affine());
+        Ok(SharedSecret::from(&shared.raw_secret_bytes()[..]))
+    }
+    fn pub_key(&self) -> &[u8] {
+        &self.public
+    }
+    fn group(&self) -> NamedGroup {
+        NamedGroup::secp256r1
+    }
+}
+
+// Signature verification, for the server's certificate chain and its handshake signature
+
+static SIGNATURE_ALGORITHMS: WebPkiSupportedAlgorithms = WebPkiSupportedAlgorithms {
+    all: &[
+        ECDSA_P256_SHA256, ECDSA_P384_SHA384,
+        RSA_PKCS1_SHA256, RSA_PKCS1_SHA384, RSA_PKCS1_SHA512,
+        RSA_PSS_SHA256, RSA_PSS_SHA384, RSA_PSS_SHA512,
+    ],
+    mapping: &[
+        (SignatureScheme::ECDSA_NISTP384_SHA384, &[ECDSA_P384_SHA384]),
+        (SignatureScheme::ECDSA_NISTP256_SHA256, &[ECDSA_P256_SHA256]),
+        (SignatureScheme::RSA_PSS_SHA512, &[RSA_PSS_SHA512]),
+        (SignatureScheme::RSA_PSS_SHA384, &[RSA_PSS_SHA384]),
+        (SignatureScheme::RSA_PSS_SHA256, &[RSA_PSS_SHA256]),
+        (SignatureScheme::RSA_PKCS1_SHA512, &[RSA_PKCS1_SHA512]),
+        (SignatureScheme::RSA_PKCS1_SHA384, &[RSA_PKCS1_SHA384]),
+        (SignatureScheme::RSA_PKCS1_SHA256, &[RSA_PKCS1_SHA256]),
+    ],
+};
+
+static ECDSA_P256_SHA256: &dyn SignatureVerificationAlgorithm = &Ecdsa::P256Sha256;
+static ECDSA_P384_SHA384: &dyn SignatureVerificationAlgorithm = &Ecdsa::P384Sha384;
+static RSA_PKCS1_SHA256: &dyn SignatureVerificationAlgorithm = &Rsa::Pkcs1Sha256;
+static RSA_PKCS1_SHA384: &dyn SignatureVerificationAlgorithm = &Rsa::Pkcs1Sha384;
+static RSA_PKCS1_SHA512: &dyn SignatureVerificationAlgorithm = &Rsa::Pkcs1Sha512;
+static RSA_PSS_SHA256: &dyn SignatureVerificationAlgorithm = &Rsa::PssSha256;
+static RSA_PSS_SHA384: &dyn SignatureVerificationAlgorithm = &Rsa::PssSha384;
+static RSA_PSS_SHA512: &dyn SignatureVerificationAlgorithm = &Rsa::PssSha512;
+
+#[derive(Debug)]
+enum Ecdsa {
+    P256Sha256,
+    P384Sha384,
+}
+impl SignatureVerificationAlgorithm for Ecdsa {
+    fn verify_signature(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), InvalidSignature> {
+        use p256::ecdsa::signature::Verifier;
+        match self {
+            Ecdsa::P256Sha256 => {
+                let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(public_key).or(Err(InvalidSignature))?;
+                let signature = p256::ecdsa::Signature::from_der(signature).or(Err(InvalidSignature))?;
+                key.verify(message, &signature).or(Err(InvalidSignature))
+            }
+            Ecdsa::P384Sha384 => {
+                let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(public_key).or(Err(InvalidSignature))?;
+                let signature = p384::ecdsa::Signature::from_der(signature).or(Err(InvalidSignature))?;
+                key.verify(message, &signature).or(Err(InvalidSignature))
+            }
+        }
+    }
+    fn public_key_alg_id(&self) -> AlgorithmIdentifier {
+        match self {
+            Ecdsa::P256Sha256 => alg_id::ECDSA_P256,
+            Ecdsa::P384Sha384 => alg_id::ECDSA_P384,
+        }
+    }
+    fn signature_alg_id(&self) -> AlgorithmIdentifier {
+        match self {
+            Ecdsa::P256Sha256 => alg_id::ECDSA_SHA256,
+            Ecdsa::P384Sha384 => alg_id::ECDSA_SHA384,
+        }
+    }
+}
+
+/// RSA keys shorter than this many bytes are refused, as they are by webpki
+const RSA_MIN_MODULUS_LEN: usize = 2048 / 8;
+
+#[derive(Debug)]
+enum Rsa {
+    Pkcs1Sha256,
+    Pkcs1Sha384,
+    Pkcs1Sha512,
+    PssSha256,
+    PssSha384,
+    PssSha512,
+}
+impl SignatureVerificationAlgorithm for Rsa {
+    fn verify_signature(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), InvalidSignature> {
+        use rsa::pkcs1::DecodeRsaPublicKey;
+        use rsa::sha2::{Sha256, Sha384, Sha512};
+        use rsa::signature::Verifier;
+        use rsa::traits::PublicKeyParts;
+        let key = rsa::RsaPublicKey::from_pkcs1_der(public_key).or(Err(InvalidSignature))?;
+        if key.size() < RSA_MIN_MODULUS_LEN {
+            return Err(InvalidSignature);
+        }
+        let result = match self {
+            Rsa::Pkcs1Sha256 | Rsa::Pkcs1Sha384 | Rsa::Pkcs1Sha512 => {
+                let signature = rsa::pkcs1v15::Signature::try_from(signature).or(Err(InvalidSignature))?;
+                match self {
+                    Rsa::Pkcs1Sha256 => rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key).verify(message, &signature),
+                    Rsa::Pkcs1Sha384 => rsa::pkcs1v15::VerifyingKey::<Sha384>::new(key).verify(message, &signature),
+                    _ => rsa::pkcs1v15::VerifyingKey::<Sha512>::new(key).verify(message, &signature),
+                }
+            }
+            _ => {
+                let signature = rsa::pss::Signature::try_from(signature).or(Err(InvalidSignature))?;
+                match self {
+                    Rsa::PssSha256 => rsa::pss::VerifyingKey::<Sha256>::new(key).verify(message, &signature),
+                    Rsa::PssSha384 => rsa::pss::VerifyingKey::<Sha384>::new(key).verify(message, &signature),
+                    _ => rsa::pss::VerifyingKey::<Sha512>::new(key).verify(message, &signature),
+                }
+            }
+        };
+        result.or(Err(InvalidSignature))
+    }
+    fn public_key_alg_id(&self) -> AlgorithmIdentifier {
+        alg_id::RSA_ENCRYPTION
+    }
+    fn signature_alg_id(&self) -> AlgorithmIdentifier {
+        match self {
+            Rsa::Pkcs1Sha256 => alg_id::RSA_PKCS1_SHA256,
+            Rsa::Pkcs1Sha384 => alg_id::RSA_PKCS1_SHA384,
+            Rsa::Pkcs1Sha512 => alg_id::RSA_PKCS1_SHA512,
+            Rsa::PssSha256 => alg_id::RSA_PSS_SHA256,
+            Rsa::PssSha384 => alg_id::RSA_PSS_SHA384,
+            Rsa::PssSha512 => alg_id::RSA_PSS_SHA512,
+        }
+    }
+}
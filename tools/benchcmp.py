@@ -0,0 +1,105 @@
+#! /usr/bin/env python3
+
+import argparse
+import json
+import sys
+
+MARKER = "BENCH|"
+
+def load(path):
+    """Loads results from either a console log with BENCH| lines in it, or a file saved by --save.
+    If a log holds several runs, only the last complete one is used."""
+    with open(path, 'r', errors='replace') as f:
+        text = f.read()
+    try:
+        saved = json.loads(text)
+        if isinstance(saved, dict) and 'results' in saved:
+            return saved
+    except json.JSONDecodeError:
+        pass
+
+    run = None
+    last = None
+    for line in text.splitlines():
+        idx = line.find(MARKER)
+        if idx < 0:
+            continue
+        try:
+            record = json.loads(line[idx + len(MARKER):].strip())
+        except json.JSONDecodeError:
+            print("warning: skipping malformed line: {}".format(line.strip()), file=sys.stderr)
+            continue
+        event = record.get('event')
+        if event == 'begin':
+            run = {'version': record.get('version'), 'target': record.get('target'), 'results': {}}
+        elif event == 'end':
+            if run is not None:
+                last = run
+            run = None
+        elif run is not None:
+            run['results']["{}/{}".format(record['suite'], record['name'])] = record
+    if last is None and run is not None:
+        print("warning: {} has no complete run; using a partial one".format(path), file=sys.stderr)
+        last = run
+    if last is None:
+        print("error: no benchmark results in {}".format(path), file=sys.stderr)
+        exit(1)
+    return last
+
+def main():
+    parser = argparse.ArgumentParser(description="Extract and compare Xous benchmark results")
+    parser.add_argument(
+        "results", help="console log (or a file saved with --save) holding the results to check", type=str
+    )
+    parser.add_argument(
+        "--baseline", help="results to compare against, in either format", type=str
+    )
+    parser.add_argument(
+        "--save", help="write the results as JSON to this file, to use as a later baseline", type=str
+    )
+    parser.add_argument(
+        "--threshold", help="percentage slowdown of the median that counts as a regression (default 10)", type=float, default=10.0
+    )
+    args = parser.parse_args()
+
+    current = load(args.results)
+    print("{} ({})".format(current['version'], current['target']))
+    if args.save:
+        with open(args.save, 'w') as f:
+            json.dump(current, f, indent=2, sort_keys=True)
+
+    if not args.baseline:
+        for name, r in sorted(current['results'].items()):
+            print("  {:40} {:>14} ns".format(name, r['median_ns']))
+        exit(0)
+
+    baseline = load(args.baseline)
+    print("against {} ({})".format(baseline['version'], baseline['target']))
+    if baseline['target'] != current['target']:
+        print("warning: comparing {} results against {} results".format(current['target'], baseline['target']))
+    regressions = 0
+    for name, r in sorted(current['results'].items()):
+        base = baseline['results'].get(name)
+        if base is None:
+            print("  {:40} {:>14} ns  (new)".format(name, r['median_ns']))
+            continue
+        if base['median_ns'] == 0:
+            change = 0.0
+        else:
+            change = (r['median_ns'] - base['median_ns']) * 100.0 / base['median_ns']
+        flag = ""
+        if change > args.threshold:
+            flag = "  REGRESSION"
+            regressions += 1
+        print("  {:40} {:>14} ns  {:+7.1f}%{}".format(name, r['median_ns'], change, flag))
+    for name in sorted(set(baseline['results']) - set(current['results'])):
+        print("  {:40} missing".format(name))
+
+    if regressions:
+        print("{} regression(s) over {}%".format(regressions, args.threshold))
+        exit(1)
+    exit(0)
+
+if __name__ == "__main__":
+    main()
+    exit(0)
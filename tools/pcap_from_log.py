@@ -0,0 +1,66 @@
+#! /usr/bin/env python3
+
+import argparse
+import base64
+import re
+import sys
+
+# written by the net service for `net pcap dump`; see services/net/src/capture.rs
+BEGIN = re.compile(r"pcap begin: (\d+) bytes")
+CHUNK = re.compile(r"pcap (\d+): ([A-Za-z0-9+/=]+)")
+END = "pcap end"
+
+def load(path):
+    """Puts a pcap file back together from the lines a capture dump left in a console log.
+    If a log holds several dumps, only the last complete one is used."""
+    with open(path, 'r', errors='replace') as f:
+        lines = f.read().splitlines()
+
+    dump = None
+    last = None
+    for line in lines:
+        begin = BEGIN.search(line)
+        if begin:
+            dump = {'length': int(begin.group(1)), 'chunks': {}}
+            continue
+        if dump is None:
+            continue
+        chunk = CHUNK.search(line)
+        if chunk:
+            dump['chunks'][int(chunk.group(1))] = base64.b64decode(chunk.group(2))
+        elif END in line:
+            last = dump
+            dump = None
+    if last is None:
+        print("error: no complete capture dump in {}".format(path), file=sys.stderr)
+        exit(1)
+
+    pcap = b''
+    for index in range(len(last['chunks'])):
+        if index not in last['chunks']:
+            print("error: line {} of the dump is missing".format(index), file=sys.stderr)
+            exit(1)
+        pcap += last['chunks'][index]
+    if len(pcap) != last['length']:
+        print("error: the dump should be {} bytes, but {} came through".format(last['length'], len(pcap)), file=sys.stderr)
+        exit(1)
+    return pcap
+
+def main():
+    parser = argparse.ArgumentParser(description="Extract a packet capture dumped to the Xous console with `net pcap dump`")
+    parser.add_argument(
+        "log", help="console log holding the dump", type=str
+    )
+    parser.add_argument(
+        "pcap", help="pcap file to write, for Wireshark", type=str
+    )
+    args = parser.parse_args()
+
+    pcap = load(args.log)
+    with open(args.pcap, 'wb') as f:
+        f.write(pcap)
+    print("wrote {} bytes to {}".format(len(pcap), args.pcap))
+
+if __name__ == "__main__":
+    main()
+    exit(0)
@@ -0,0 +1,592 @@
+//! Decoding of PDDB flash images: the physical layout, the page table, and the basis, dictionary and
+//! key records. Everything here is read-only; an image is never modified.
+use pddb_format::*;
+
+use aes::{Aes256, Block, BLOCK_SIZE};
+use aes::cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray};
+use aes_gcm_siv::{AesGcmSiv, Key, Nonce};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use core::convert::TryInto;
+use core::mem::size_of;
+use core::ops::DerefMut;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// The page table and data keys of a basis.
+pub struct BasisKeys {
+    pub pt: [u8; AES_KEYSIZE],
+    pub data: [u8; AES_KEYSIZE],
+}
+
+/// Reads a key file written by the hosted backend's `dump_keys()`: a u32 count, followed by records of
+/// a 64-byte zero-padded basis name, the data key, and the page table key.
+pub fn read_key_file(path: &Path) -> Result<Vec<(String, BasisKeys)>> {
+    const RECORD_LEN: usize = BASIS_NAME_LEN + AES_KEYSIZE * 2;
+    let raw = fs::read(path)?;
+    if raw.len() < 4 {
+        return Err(Error::new(ErrorKind::InvalidData, "key file is truncated"));
+    }
+    let count = u32::from_le_bytes(raw[..4].try_into().unwrap()) as usize;
+    if raw.len() < 4 + count * RECORD_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, "key file is truncated"));
+    }
+    let mut keys = Vec::new();
+    for record in raw[4..4 + count * RECORD_LEN].chunks(RECORD_LEN) {
+        let name_len = record[..BASIS_NAME_LEN].iter().position(|&b| b == 0).unwrap_or(BASIS_NAME_LEN);
+        let name = String::from_utf8_lossy(&record[..name_len]).to_string();
+        let mut basis_keys = BasisKeys { pt: [0u8; AES_KEYSIZE], data: [0u8; AES_KEYSIZE] };
+        basis_keys.data.copy_from_slice(&record[BASIS_NAME_LEN..BASIS_NAME_LEN + AES_KEYSIZE]);
+        basis_keys.pt.copy_from_slice(&record[BASIS_NAME_LEN + AES_KEYSIZE..]);
+        keys.push((name, basis_keys));
+    }
+    Ok(keys)
+}
+
+/// A PDDB flash image. The page table is sized to the image, so the layout is derived from its length;
+/// this lets images of both the hosted backend and of a device be read.
+pub struct Image {
+    flash: Vec<u8>,
+    pt_len: usize,
+    key_phys_base: usize,
+    mbbb_phys_base: usize,
+    data_phys_base: usize,
+    dna: u64,
+}
+
+impl Image {
+    /// `dna` is the FPGA DNA of the device the image was taken from; it is bound into every page. The hosted
+    /// backend uses 0.
+    pub fn open(path: &Path, dna: u64) -> Result<Image> {
+        let flash = fs::read(path)?;
+        let pt_len = flash.len() / PAGE_SIZE * size_of::<Pte>();
+        // same derivation as in `PddbOs::new()`
+        let key_phys_base = PageAlignedPa::from(pt_len).as_usize();
+        let mbbb_phys_base = key_phys_base + PAGE_SIZE;
+        let data_phys_base = mbbb_phys_base + (MBBB_PAGES + FSCB_PAGES) * PAGE_SIZE;
+        if flash.len() <= data_phys_base {
+            return Err(Error::new(ErrorKind::InvalidData, "image is too small to hold a PDDB"));
+        }
+        Ok(Image { flash, pt_len, key_phys_base, mbbb_phys_base, data_phys_base, dna })
+    }
+
+    pub fn data_pages(&self) -> usize {
+        (self.flash.len() - self.data_phys_base) / PAGE_SIZE
+    }
+
+    /// Derives the keys of a secret basis from its name, password and KDF, as `PddbOs::basis_derive_key()` does.
+    /// The system basis can't be opened this way, as its keys are wrapped by the device's root keys.
+    pub fn basis_derive_key(&self, basis_name: &str, password: &str, kdf: BasisKdf) -> BasisKeys {
+        use sha2::{FallbackStrategy, Sha512Trunc256};
+        use digest::Digest;
+
+        // the salt base follows the version and the two wrapped system keys in the static crypto data
+        let salt_base = &self.flash[self.key_phys_base + size_of::<u32>() + WRAPPED_AES_KEYSIZE * 2..self.key_phys_base + PAGE_SIZE];
+        let mut bname_copy = [0u8; BASIS_NAME_LEN];
+        for (src, dst) in basis_name.bytes().zip(bname_copy.iter_mut()) {
+            *dst = src;
+        }
+        let mut plaintext_pw: [u8; 73] = [0; 73];
+        for (src, dst) in password.bytes().zip(plaintext_pw.iter_mut()) {
+            *dst = src;
+        }
+        plaintext_pw[72] = 0;
+
+        let mut salt = [0u8; 16];
+        let mut hasher = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+        hasher.update(&salt_base[32..]);
+        hasher.update(&bname_copy);
+        hasher.update(&plaintext_pw);
+        let result = hasher.finalize();
+        for (&src, dst) in result.iter().zip(salt.iter_mut()) {
+            *dst = src;
+        }
+
+        let mut hashed_password = [0u8; KDF_OUTPUT_LEN];
+        let hash_len = kdf.hash_password(&salt, password, &mut hashed_password);
+        let hashed_password = &hashed_password[..hash_len];
+
+        let mut keys = BasisKeys { pt: [0u8; AES_KEYSIZE], data: [0u8; AES_KEYSIZE] };
+        let hkpt = hkdf::Hkdf::<sha2::Sha256>::new(Some(&salt_base[..32]), hashed_password);
+        hkpt.expand(b"pddb page table key", &mut keys.pt).expect("invalid length specified for HKDF");
+        let hkdt = hkdf::Hkdf::<sha2::Sha256>::new(Some(&salt_base[..32]), hashed_password);
+        hkdt.expand(b"pddb data key", &mut keys.data).expect("invalid length specified for HKDF");
+        keys
+    }
+
+    /// Returns every page table entry that decrypts under `pt_key`, as (virtual address, physical page) pairs.
+    /// A blank page table page is substituted by its copy in the make-before-break buffer, as on mount.
+    fn pt_scan(&self, pt_key: &[u8; AES_KEYSIZE]) -> Vec<(VirtAddr, PhysAddr)> {
+        let cipher = Aes256::new(GenericArray::from_slice(pt_key));
+        let blank = [0xffu8; BLOCK_SIZE];
+        let mbbb = self.flash[self.mbbb_phys_base..self.mbbb_phys_base + MBBB_PAGES * PAGE_SIZE]
+            .chunks(PAGE_SIZE)
+            .find(|page| page[..BLOCK_SIZE] != blank);
+        let mut entries = Vec::new();
+        for (page_index, pt_page) in self.flash[..self.pt_len].chunks(PAGE_SIZE).enumerate() {
+            let clean_page = if pt_page[..BLOCK_SIZE] == blank { mbbb.unwrap_or(pt_page) } else { pt_page };
+            for (index, candidate) in clean_page.chunks(BLOCK_SIZE).enumerate() {
+                let mut block = Block::clone_from_slice(candidate);
+                cipher.decrypt_block(&mut block);
+                if let Some(pte) = Pte::try_from_slice(block.as_slice()) {
+                    entries.push((pte.vaddr(), ((page_index * PAGE_SIZE / BLOCK_SIZE) + index) as PhysAddr));
+                }
+            }
+        }
+        entries
+    }
+
+    fn page(&self, page: PhysAddr) -> Option<&[u8]> {
+        let start = self.data_phys_base + page as usize * PAGE_SIZE;
+        self.flash.get(start..start + PAGE_SIZE)
+    }
+
+    /// Returns the decrypted page, including the journal number at the start.
+    fn decrypt_page(&self, cipher: &AesGcmSiv<Aes256>, aad: &[u8], page: PhysAddr) -> Option<Vec<u8>> {
+        let ct_slice = self.page(page)?;
+        let nonce = &ct_slice[..size_of::<Nonce>()];
+        cipher.decrypt(Nonce::from_slice(nonce), Payload { aad, msg: &ct_slice[size_of::<Nonce>()..] }).ok()
+    }
+
+    /// Decrypts a page stored with a key commitment (the basis root). See `data_decrypt_page_with_commit()`
+    /// in the backend for the layout.
+    fn decrypt_page_with_commit(&self, key: &[u8; AES_KEYSIZE], aad: &[u8], page: PhysAddr) -> Option<Vec<u8>> {
+        use sha2::{FallbackStrategy, Sha512Trunc256};
+        use digest::Digest;
+        const KCOM_NONCE_LEN: usize = 32;
+        const KCOM_LEN: usize = 32;
+
+        let ct_slice = self.page(page)?;
+        let nonce = &ct_slice[..size_of::<Nonce>()];
+        let ct_total = &ct_slice[size_of::<Nonce>()..];
+        let nonce_comm = &ct_total[KCOM_CT_LEN..KCOM_CT_LEN + KCOM_NONCE_LEN];
+        let key_comm_stored = &ct_total[KCOM_CT_LEN + KCOM_NONCE_LEN..KCOM_CT_LEN + KCOM_NONCE_LEN + KCOM_LEN];
+        let ct_plus_mac = [&ct_total[..KCOM_CT_LEN], &ct_total[KCOM_CT_LEN + KCOM_NONCE_LEN + KCOM_LEN..]].concat();
+
+        let mut h_enc = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+        h_enc.update(key);
+        h_enc.update([0x43, 0x6f, 0x6, 0xd6, 0xd, 0x69, 0x74, 0x01, 0x01]);
+        h_enc.update(nonce_comm);
+        let k_enc = h_enc.finalize();
+        let mut h_com = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+        h_com.update(key);
+        h_com.update([0x43, 0x6f, 0x6, 0xd6, 0xd, 0x69, 0x74, 0x01, 0x02]);
+        h_com.update(nonce_comm);
+        if h_com.finalize().as_slice() != key_comm_stored {
+            return None;
+        }
+        let cipher = AesGcmSiv::<Aes256>::new(Key::from_slice(&k_enc));
+        cipher.decrypt(Nonce::from_slice(nonce), Payload { aad, msg: &ct_plus_mac }).ok()
+    }
+
+    /// Opens a basis with the given keys. Returns None if the keys don't match any basis in the image.
+    pub fn open_basis(&self, name: &str, keys: &BasisKeys) -> Option<Basis> {
+        let aad = BasisRoot { name: BasisRootName::try_from_str(name).ok()?, ..Default::default() }.aad(self.dna);
+        let cipher = AesGcmSiv::<Aes256>::new(Key::from_slice(&keys.data));
+        let root_vaddr = VirtAddr::new(VPAGE_SIZE as u64).unwrap();
+        let journal = |vaddr: VirtAddr, page: PhysAddr| -> Option<JournalType> {
+            let data = if vaddr == root_vaddr {
+                self.decrypt_page_with_commit(&keys.data, &aad, page)
+            } else {
+                self.decrypt_page(&cipher, &aad, page)
+            }?;
+            Some(JournalType::from_le_bytes(data[..size_of::<JournalType>()].try_into().unwrap()))
+        };
+
+        // resolve entries that map the same virtual address by their journal, as on mount
+        let mut map = HashMap::<VirtAddr, PhysAddr>::new();
+        let mut stale = Vec::<(VirtAddr, PhysAddr)>::new();
+        for (vaddr, page) in self.pt_scan(&keys.pt) {
+            match map.get(&vaddr).copied() {
+                Some(prev) => {
+                    let keep_new = match (journal(vaddr, prev), journal(vaddr, page)) {
+                        (Some(prev_j), Some(new_j)) => new_j > prev_j,
+                        (None, Some(_)) => true,
+                        _ => false,
+                    };
+                    if keep_new {
+                        map.insert(vaddr, page);
+                        stale.push((vaddr, prev));
+                    } else {
+                        stale.push((vaddr, page));
+                    }
+                }
+                None => {
+                    map.insert(vaddr, page);
+                }
+            }
+        }
+
+        let data = self.decrypt_page_with_commit(&keys.data, &aad, *map.get(&root_vaddr)?)?;
+        let mut root = BasisRoot::default();
+        for (&src, dst) in data[size_of::<JournalType>()..].iter().zip(root.deref_mut().iter_mut()) {
+            *dst = src;
+        }
+        if root.magic != PDDB_MAGIC {
+            return None;
+        }
+        Some(Basis {
+            image: self,
+            name: name.to_string(),
+            cipher,
+            aad,
+            version: root.version,
+            age: root.age,
+            num_dicts: root.num_dictionaries,
+            kdf: root.kdf,
+            map,
+            stale,
+        })
+    }
+}
+
+/// A dictionary header, as found on disk.
+pub struct Dict {
+    pub index: u32,
+    pub name: String,
+    pub age: u32,
+    pub num_keys: u32,
+    pub free_key_index: u32,
+    pub compacting: bool,
+}
+
+/// A key descriptor, as found on disk.
+pub struct KeyEntry {
+    pub index: u32,
+    pub name: String,
+    pub start: u64,
+    pub len: u64,
+    pub reserved: u64,
+    pub age: u32,
+}
+impl Dict {
+    /// Decodes the header at the start of a dictionary's first vpage. Returns None if the slot is free.
+    fn from_record(index: u32, record: &[u8]) -> Option<Dict> {
+        let mut dict = Dictionary::default();
+        for (&src, dst) in record.iter().zip(dict.deref_mut().iter_mut()) {
+            *dst = src;
+        }
+        if !dict.flags.valid() {
+            return None;
+        }
+        Some(Dict {
+            index,
+            name: record_name(dict.name.len, &dict.name.data).unwrap_or_else(|| format!("<bad name at index {}>", index)),
+            age: dict.age,
+            num_keys: dict.num_keys,
+            free_key_index: dict.free_key_index,
+            compacting: dict.flags.compacting(),
+        })
+    }
+}
+
+impl KeyEntry {
+    /// Decodes a key descriptor. Returns None if the slot is free.
+    fn from_record(index: u32, record: &[u8]) -> Option<KeyEntry> {
+        let mut kd = KeyDescriptor::default();
+        for (&src, dst) in record.iter().zip(kd.deref_mut().iter_mut()) {
+            *dst = src;
+        }
+        if !kd.flags.valid() {
+            return None;
+        }
+        Some(KeyEntry {
+            index,
+            name: record_name(kd.name.len, &kd.name.data).unwrap_or_else(|| format!("<bad name at index {}>", index)),
+            start: kd.start,
+            len: kd.len,
+            reserved: kd.reserved,
+            age: kd.age,
+        })
+    }
+
+    pub fn is_small(&self) -> bool {
+        self.start < SMALL_POOL_END
+    }
+}
+
+/// Problems found by `Basis::verify()`.
+#[derive(Default, Debug)]
+pub struct Report {
+    pub stale_ptes: usize,
+    pub bad_pages: usize,
+    pub orphaned: usize,
+    pub bad_dicts: usize,
+    pub bad_keys: usize,
+    /// pages set aside by a device-side `pddb fsck quarantine`; not a problem in itself
+    pub quarantined: usize,
+    /// pages of a transaction journal, which the device finishes or discards on the next mount
+    pub txn_journal: usize,
+}
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.stale_ptes == 0 && self.bad_pages == 0 && self.orphaned == 0 && self.bad_dicts == 0 && self.bad_keys == 0
+    }
+}
+
+pub struct Basis<'a> {
+    image: &'a Image,
+    pub name: String,
+    cipher: AesGcmSiv<Aes256>,
+    aad: Vec<u8>,
+    pub version: u32,
+    pub age: u32,
+    pub num_dicts: u32,
+    pub kdf: KdfRecord,
+    /// the resolved virtual to physical map
+    map: HashMap<VirtAddr, PhysAddr>,
+    /// page table entries that lost the journal resolution
+    stale: Vec<(VirtAddr, PhysAddr)>,
+}
+
+/// Names are stored as a length and a fixed-size buffer; a length that overruns the buffer means the record
+/// is damaged.
+fn record_name(len: u8, data: &[u8]) -> Option<String> {
+    data.get(..len as usize).and_then(|name| std::str::from_utf8(name).ok()).map(|name| name.to_string())
+}
+
+impl<'a> Basis<'a> {
+    pub fn pages(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Physical pages mapped by this basis, including the stale ones.
+    pub fn phys_pages(&self) -> impl Iterator<Item = PhysAddr> + '_ {
+        self.map.values().copied().chain(self.stale.iter().map(|&(_, page)| page))
+    }
+
+    /// Reads a virtual page, returning the data below the journal number.
+    fn read_vpage(&self, vaddr: u64) -> Option<Vec<u8>> {
+        let page = *self.map.get(&VirtAddr::new(vaddr)?)?;
+        self.image.decrypt_page(&self.cipher, &self.aad, page).map(|mut data| data.split_off(size_of::<JournalType>()))
+    }
+
+    /// Scans every dictionary slot, returning the valid dictionaries.
+    pub fn dicts(&self) -> Vec<Dict> {
+        let mut dicts = Vec::new();
+        for index in 1..=DICT_MAXCOUNT {
+            if let Some(dict) = self.read_vpage(index as u64 * DICT_VSIZE).and_then(|page| Dict::from_record(index as u32, &page)) {
+                dicts.push(dict);
+            }
+        }
+        dicts
+    }
+
+    /// Scans the whole descriptor area of a dictionary, returning the valid key descriptors.
+    pub fn keys(&self, dict: &Dict) -> Vec<KeyEntry> {
+        let dict_base = dict.index as u64 * DICT_VSIZE;
+        let mut keys = Vec::new();
+        for vpage in 0..(DICT_VSIZE / VPAGE_SIZE as u64) as usize {
+            let page = match self.read_vpage(dict_base + (vpage * VPAGE_SIZE) as u64) {
+                Some(page) => page,
+                None => continue,
+            };
+            for (slot, kd) in page.chunks_exact(DK_STRIDE).take(DK_PER_VPAGE).enumerate() {
+                let index = vpage * DK_PER_VPAGE + slot;
+                // index 0 is the dictionary header
+                if index == 0 {
+                    continue;
+                }
+                if let Some(key) = KeyEntry::from_record(index as u32, kd) {
+                    keys.push(key);
+                }
+            }
+        }
+        keys
+    }
+
+    /// Reads the contents of a key. Fails if any of its pages is missing or doesn't authenticate.
+    pub fn read_key(&self, key: &KeyEntry) -> Result<Vec<u8>> {
+        if key.len > key.reserved || (!key.is_small() && key.len > LARGE_FILE_MAX_SIZE) {
+            return Err(Error::new(ErrorKind::InvalidData, "descriptor has an impossible length"));
+        }
+        let lost = |vaddr: u64| Error::new(ErrorKind::InvalidData, format!("data page at va {:x} is lost", vaddr));
+        let mut data = Vec::with_capacity(key.len as usize);
+        if key.is_small() {
+            let vaddr = key.start / VPAGE_SIZE as u64 * VPAGE_SIZE as u64;
+            let offset = (key.start - vaddr) as usize;
+            let page = self.read_vpage(vaddr).ok_or_else(|| lost(vaddr))?;
+            data.extend_from_slice(page.get(offset..offset + key.len as usize).ok_or_else(|| lost(vaddr))?);
+        } else {
+            // same walk as `BasisCache::key_read()`
+            let mut cursor = 0u64;
+            while cursor < key.len {
+                let vaddr = (key.start + cursor) / VPAGE_SIZE as u64 * VPAGE_SIZE as u64;
+                let page = self.read_vpage(vaddr).ok_or_else(|| lost(vaddr))?;
+                let block_start = (cursor % VPAGE_SIZE as u64) as usize;
+                let chunk = (VPAGE_SIZE - block_start).min((key.len - cursor) as usize);
+                data.extend_from_slice(&page[block_start..block_start + chunk]);
+                cursor += chunk as u64;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Checks that every page authenticates, that every key can be read back, and that every mapped page is
+    /// reachable from a dictionary or key. Problems are printed as they are found.
+    pub fn verify(&self) -> Report {
+        let mut report = Report::default();
+        for &(vaddr, page) in self.stale.iter() {
+            println!("  {}: stale page table entry maps va {:x} to page {}", self.name, vaddr, page);
+            report.stale_ptes += 1;
+        }
+        let root_vaddr = VirtAddr::new(VPAGE_SIZE as u64).unwrap();
+        let mut unreadable = HashSet::<VirtAddr>::new();
+        for (&vaddr, &page) in self.map.iter() {
+            if vaddr != root_vaddr && self.image.decrypt_page(&self.cipher, &self.aad, page).is_none() {
+                println!("  {}: va {:x} (page {}) does not authenticate", self.name, vaddr, page);
+                report.bad_pages += 1;
+                unreadable.insert(vaddr);
+            }
+        }
+
+        let mut reachable = HashSet::<u64>::new();
+        reachable.insert(VPAGE_SIZE as u64);
+        let dicts = self.dicts();
+        if dicts.len() != self.num_dicts as usize {
+            println!("  {}: basis root records {} dictionaries, found {}", self.name, self.num_dicts, dicts.len());
+            report.bad_dicts += 1;
+        }
+        let mut names = HashSet::<&str>::new();
+        for dict in dicts.iter() {
+            if !names.insert(&dict.name) {
+                println!("  {}: dictionary {} appears twice", self.name, dict.name);
+                report.bad_dicts += 1;
+            }
+            let dict_base = dict.index as u64 * DICT_VSIZE;
+            let small_base = SMALL_POOL_START + (dict.index as u64 - 1) * DICT_VSIZE;
+            // descriptor pages, and small pools (which stay mapped once emptied), belong to the dictionary
+            for &vaddr in self.map.keys() {
+                let va = vaddr.get();
+                if (va >= dict_base && va < dict_base + DICT_VSIZE) || (va >= small_base && va < small_base + SMALL_POOL_STRIDE) {
+                    reachable.insert(va);
+                }
+            }
+            let keys = self.keys(dict);
+            if keys.len() != dict.num_keys as usize && !dict.compacting {
+                println!("  {}: {} records {} keys, found {}", self.name, dict.name, dict.num_keys, keys.len());
+                report.bad_dicts += 1;
+            }
+            for key in keys.iter() {
+                if let Err(e) = self.read_key(key) {
+                    println!("  {}: {}:{} can't be read: {}", self.name, dict.name, key.name, e);
+                    report.bad_keys += 1;
+                }
+                if !key.is_small() && key.start >= LARGE_POOL_START && key.reserved > 0 && key.reserved <= LARGE_FILE_MAX_SIZE {
+                    for vpage in key.start / VPAGE_SIZE as u64..=(key.start + key.reserved - 1) / VPAGE_SIZE as u64 {
+                        reachable.insert(vpage * VPAGE_SIZE as u64);
+                    }
+                }
+            }
+        }
+        for &vaddr in self.map.keys() {
+            if vaddr.get() >= QUARANTINE_START && vaddr.get() < QUARANTINE_END {
+                report.quarantined += 1;
+            } else if vaddr.get() >= TXN_JOURNAL_START && vaddr.get() < QUARANTINE_START {
+                report.txn_journal += 1;
+            } else if !reachable.contains(&vaddr.get()) && !unreadable.contains(&vaddr) {
+                println!("  {}: orphaned page at va {:x}", self.name, vaddr);
+                report.orphaned += 1;
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ops::Deref;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("pddb-inspect-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_layout_regions_are_ordered() {
+        // `Basis::verify()` classifies pages by these ranges, so they must not overlap
+        assert!(DICT_VSIZE * (DICT_MAXCOUNT as u64 + 1) <= SMALL_POOL_START);
+        assert!(SMALL_POOL_END <= TXN_JOURNAL_START);
+        assert!(TXN_JOURNAL_START < QUARANTINE_START);
+        assert!(QUARANTINE_START < QUARANTINE_END);
+        assert!(QUARANTINE_END < LARGE_POOL_START);
+        assert_eq!(DK_PER_VPAGE, 32);
+    }
+
+    #[test]
+    fn test_dict_record() {
+        let mut dict = Dictionary::default();
+        dict.age = 3;
+        dict.num_keys = 7;
+        dict.free_key_index = 8;
+        dict.name = DictName::try_from_str("wlan.networks").unwrap();
+        dict.flags.set_compacting(true);
+        let decoded = Dict::from_record(5, &dict.deref()[..DK_STRIDE]).unwrap();
+        assert_eq!(decoded.index, 5);
+        assert_eq!(decoded.name, "wlan.networks");
+        assert_eq!((decoded.age, decoded.num_keys, decoded.free_key_index), (3, 7, 8));
+        assert!(decoded.compacting);
+
+        dict.flags.set_valid(false);
+        assert!(Dict::from_record(5, &dict.deref()[..DK_STRIDE]).is_none());
+    }
+
+    #[test]
+    fn test_key_record() {
+        let mut kd = KeyDescriptor::default();
+        kd.start = SMALL_POOL_START + 0x40;
+        kd.len = 12;
+        kd.reserved = 16;
+        kd.age = 2;
+        kd.flags.set_valid(true);
+        kd.name = KeyName::try_from_str("ssid").unwrap();
+        let decoded = KeyEntry::from_record(33, &kd.deref()[..DK_STRIDE]).unwrap();
+        assert_eq!(decoded.index, 33);
+        assert_eq!(decoded.name, "ssid");
+        assert_eq!((decoded.start, decoded.len, decoded.reserved, decoded.age), (SMALL_POOL_START + 0x40, 12, 16, 2));
+        assert!(decoded.is_small());
+
+        // a name length that overruns the record is reported, not trusted
+        kd.name.len = 0xff;
+        let decoded = KeyEntry::from_record(33, &kd.deref()[..DK_STRIDE]).unwrap();
+        assert_eq!(decoded.name, "<bad name at index 33>");
+
+        kd.flags.set_valid(false);
+        assert!(KeyEntry::from_record(33, &kd.deref()[..DK_STRIDE]).is_none());
+    }
+
+    #[test]
+    fn test_read_key_file() {
+        let mut raw = 2u32.to_le_bytes().to_vec();
+        for (name, fill) in [(".System", 1u8), ("Basis2", 2u8)].iter() {
+            let mut record = vec![0u8; BASIS_NAME_LEN];
+            record[..name.len()].copy_from_slice(name.as_bytes());
+            record.extend_from_slice(&[*fill; AES_KEYSIZE]);
+            record.extend_from_slice(&[*fill + 0x10; AES_KEYSIZE]);
+            raw.extend_from_slice(&record);
+        }
+        let path = temp_path("keys");
+        fs::write(&path, &raw).unwrap();
+        let keys = read_key_file(&path).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].0, ".System");
+        assert_eq!(keys[1].0, "Basis2");
+        assert_eq!(keys[1].1.data, [2u8; AES_KEYSIZE]);
+        assert_eq!(keys[1].1.pt, [0x12u8; AES_KEYSIZE]);
+
+        fs::write(&path, &raw[..raw.len() - 1]).unwrap();
+        assert!(read_key_file(&path).is_err());
+        fs::write(&path, &raw[..2]).unwrap();
+        assert!(read_key_file(&path).is_err());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_open_rejects_small_image() {
+        let path = temp_path("small");
+        fs::write(&path, vec![0xffu8; PAGE_SIZE * 8]).unwrap();
+        assert!(Image::open(&path, 0).is_err());
+        fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,176 @@
+//! Inspects a PDDB flash image on the host: lists the bases, dictionaries and keys it holds, extracts
+//! key contents, and verifies the integrity of the structures.
+//!
+//! Images come from the hosted backend (`dbg_dump()` writes `tools/pddb-images/<name>.bin`, and the keys
+//! of every basis it knows to `<name>.key`), or from a device's PDDB region. The system basis can only be
+//! opened with exported keys, as on a device its keys are wrapped by the root keys; secret bases can also
+//! be opened with their password.
+//!
+//! Examples:
+//!     pddb-inspect --keys tools/pddb-images/pddb.key tools/pddb-images/pddb.bin list
+//!     pddb-inspect --basis Basis2 tools/pddb-images/pddb.bin extract mydict mykey --out value.bin
+//!     pddb-inspect --keys tools/pddb-images/pddb.key tools/pddb-images/pddb.bin verify
+mod image;
+
+use clap::{App, AppSettings, Arg, SubCommand};
+use image::*;
+use pddb_format::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+fn parse_dna(dna: &str) -> Result<u64, String> {
+    let parsed = if let Some(hex) = dna.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        dna.parse::<u64>()
+    };
+    parsed.map_err(|e| format!("invalid DNA {}: {}", dna, e))
+}
+
+/// Collects the keys for every basis named on the command line, in order. A `--basis` without a password
+/// has it read from stdin, so it doesn't have to appear in the shell history.
+fn collect_keys(image: &Image, keyfile: Option<&str>, bases: Vec<&str>) -> Result<Vec<(String, BasisKeys)>, String> {
+    let mut keys = match keyfile {
+        Some(path) => read_key_file(Path::new(path)).map_err(|e| format!("couldn't read key file {}: {}", path, e))?,
+        None => Vec::new(),
+    };
+    for spec in bases {
+        let (name, password) = match spec.split_once(':') {
+            Some((name, password)) => (name.to_string(), password.to_string()),
+            None => {
+                eprint!("password for {}: ", spec);
+                io::stderr().flush().ok();
+                let mut password = String::new();
+                io::stdin().lock().read_line(&mut password).map_err(|e| e.to_string())?;
+                (spec.to_string(), password.trim_end_matches(&['\r', '\n'][..]).to_string())
+            }
+        };
+        // the KDF of a basis is only recorded inside it, so each one it could be keyed with is tried in turn
+        eprintln!("deriving the keys for {}...", name);
+        let mut derived = None;
+        for &kdf in KDF_PROBE_ORDER.iter() {
+            let candidate = image.basis_derive_key(&name, &password, kdf);
+            if image.open_basis(&name, &candidate).is_some() {
+                derived = Some(candidate);
+                break;
+            }
+        }
+        // a miss is reported along with any other basis that doesn't open
+        keys.push((name.clone(), derived.unwrap_or_else(|| image.basis_derive_key(&name, &password, BasisKdf::DEFAULT))));
+    }
+    Ok(keys)
+}
+
+fn run() -> Result<bool, String> {
+    let matches = App::new("pddb-inspect")
+        .about("Inspects a PDDB flash image")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("image").help("PDDB flash image").required(true))
+        .arg(Arg::with_name("keys").long("keys").takes_value(true).value_name("FILE")
+            .help("key file exported by the hosted backend"))
+        .arg(Arg::with_name("basis").long("basis").takes_value(true).multiple(true).number_of_values(1)
+            .value_name("NAME[:PASSWORD]").help("secret basis to unlock; the password is read from stdin if omitted"))
+        .arg(Arg::with_name("dna").long("dna").takes_value(true).default_value("0")
+            .help("FPGA DNA of the device the image came from; 0 for hosted images"))
+        .subcommand(SubCommand::with_name("list").about("Lists the bases, dictionaries and keys"))
+        .subcommand(SubCommand::with_name("extract").about("Writes the contents of a key to stdout or a file")
+            .arg(Arg::with_name("dict").required(true))
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("from").long("from").takes_value(true).value_name("BASIS")
+                .help("basis to read from; by default the last basis on the command line that has the key"))
+            .arg(Arg::with_name("out").long("out").short("o").takes_value(true).value_name("FILE")))
+        .subcommand(SubCommand::with_name("verify").about("Checks the integrity of every unlocked basis"))
+        .get_matches();
+
+    let dna = parse_dna(matches.value_of("dna").unwrap())?;
+    let image_path = matches.value_of("image").unwrap();
+    let image = Image::open(Path::new(image_path), dna).map_err(|e| format!("couldn't open {}: {}", image_path, e))?;
+    let keys = collect_keys(&image, matches.value_of("keys"), matches.values_of("basis").map_or(Vec::new(), |v| v.collect()))?;
+    if keys.is_empty() {
+        return Err("no keys given: use --keys and/or --basis".to_string());
+    }
+    let mut bases = Vec::new();
+    for (name, basis_keys) in keys.iter() {
+        match image.open_basis(name, basis_keys) {
+            Some(basis) => bases.push(basis),
+            None => eprintln!("{}: not found in the image, or the keys are wrong", name),
+        }
+    }
+
+    match matches.subcommand() {
+        ("list", _) => {
+            for basis in bases.iter() {
+                let kdf = match BasisKdf::from_record(&basis.kdf) {
+                    Some(kdf) => format!("{:?}", kdf),
+                    None => format!("unknown KDF {:?}", basis.kdf),
+                };
+                println!("{} (version {:x}, age {}, {} pages, {})", basis.name, basis.version, basis.age, basis.pages(), kdf);
+                for dict in basis.dicts() {
+                    println!("  {} (index {}, age {}, {} keys{})", dict.name, dict.index, dict.age, dict.num_keys,
+                        if dict.compacting { ", compaction pending" } else { "" });
+                    for key in basis.keys(&dict) {
+                        println!("    {} ({} bytes of {} reserved, {} pool, age {})", key.name, key.len, key.reserved,
+                            if key.is_small() { "small" } else { "large" }, key.age);
+                    }
+                }
+            }
+            Ok(true)
+        }
+        ("extract", Some(args)) => {
+            let dict_name = args.value_of("dict").unwrap();
+            let key_name = args.value_of("key").unwrap();
+            // later bases shadow earlier ones, as on the device
+            for basis in bases.iter().rev().filter(|b| args.value_of("from").map_or(true, |from| from == b.name)) {
+                let dict = match basis.dicts().into_iter().find(|d| d.name == dict_name) {
+                    Some(dict) => dict,
+                    None => continue,
+                };
+                if let Some(key) = basis.keys(&dict).into_iter().find(|k| k.name == key_name) {
+                    let data = basis.read_key(&key).map_err(|e| format!("{}:{}: {}", dict_name, key_name, e))?;
+                    match args.value_of("out") {
+                        Some(path) => fs::write(path, &data).map_err(|e| format!("couldn't write {}: {}", path, e))?,
+                        None => io::stdout().write_all(&data).map_err(|e| e.to_string())?,
+                    }
+                    eprintln!("{} bytes from {}:{}:{}", data.len(), basis.name, dict_name, key_name);
+                    return Ok(true);
+                }
+            }
+            Err(format!("{}:{} not found", dict_name, key_name))
+        }
+        ("verify", _) => {
+            let mut clean = true;
+            let mut owners = HashMap::<PhysAddr, &str>::new();
+            for basis in bases.iter() {
+                println!("{}:", basis.name);
+                let report = basis.verify();
+                for page in basis.phys_pages() {
+                    if let Some(other) = owners.insert(page, &basis.name) {
+                        println!("  page {} is mapped by both {} and {}", page, other, basis.name);
+                        clean = false;
+                    }
+                }
+                println!("  {} pages: {:?}", basis.pages(), report);
+                clean &= report.is_clean();
+            }
+            println!("{} of {} data pages mapped by the unlocked bases: {}", owners.len(), image.data_pages(),
+                if clean { "clean" } else { "PROBLEMS FOUND" });
+            Ok(clean)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn main() {
+    env_logger::init();
+    match run() {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Symbolizes a crash report saved by the panic hook in `log-server`, against the ELF of the
+//! process that crashed.
+//!
+//! The report can be a file, or stdin. Reports exported to the console with `crash export <n>` in
+//! shellchat can be fed in straight from a serial capture: lines are taken from after the `CRASH| `
+//! marker, and anything without one is skipped once a marker has been seen.
+//!
+//! Besides the frames themselves, the registers at the call to `panic!` are worked out from the frame
+//! walk: the first frame that isn't in the panic machinery is the call site.
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+use xmas_elf::sections::{SectionData, SHF_EXECINSTR};
+use xmas_elf::symbol_table::{Entry, Type as SymbolType};
+use xmas_elf::ElfFile;
+
+/// v1 reports don't have the frame pointers of the frames, so they get no call site
+const REPORT_HEADERS: [&str; 2] = ["xous-crash-report v2", "xous-crash-report v1"];
+const EXPORT_MARKER: &str = "CRASH| ";
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: String,
+}
+
+struct Symbols {
+    /// function symbols, sorted by address
+    functions: Vec<Symbol>,
+    /// (start, end) of each executable section
+    text: Vec<(u64, u64)>,
+}
+
+impl Symbols {
+    fn load(elf: &ElfFile) -> Symbols {
+        let mut functions = Vec::new();
+        let mut text = Vec::new();
+        for section in elf.section_iter() {
+            if section.flags() & SHF_EXECINSTR != 0 {
+                text.push((section.address(), section.address() + section.size()));
+            }
+            match section.get_data(elf) {
+                Ok(SectionData::SymbolTable32(entries)) => collect(elf, entries, &mut functions),
+                Ok(SectionData::SymbolTable64(entries)) => collect(elf, entries, &mut functions),
+                _ => (),
+            }
+        }
+        functions.sort_by_key(|s| s.addr);
+        Symbols { functions, text }
+    }
+
+    fn is_text(&self, addr: u64) -> bool {
+        self.text.iter().any(|&(start, end)| addr >= start && addr < end)
+    }
+
+    fn find(&self, addr: u64) -> Option<&Symbol> {
+        let idx = match self.functions.binary_search_by_key(&addr, |s| s.addr) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let symbol = &self.functions[idx];
+        // symbols with no size are accepted, as hand-written assembly often doesn't set one
+        if symbol.size != 0 && addr >= symbol.addr + symbol.size {
+            return None;
+        }
+        Some(symbol)
+    }
+
+    /// Looks up the function containing `addr`, as "name+offset"
+    fn lookup(&self, addr: u64) -> Option<String> {
+        self.find(addr)
+            .map(|s| format!("{:#}+{:#x}", rustc_demangle::demangle(&s.name), addr - s.addr))
+    }
+
+    /// Return addresses point after the call, which may be the first instruction of the next
+    /// function, so the function is found from the call itself.
+    fn lookup_return(&self, ra: u64) -> Option<String> {
+        self.find(ra.saturating_sub(1))
+            .map(|s| format!("{:#}+{:#x}", rustc_demangle::demangle(&s.name), ra - s.addr))
+    }
+
+    /// Whether the call `ra` returns to was made from the panic machinery (std, core, the allocator's
+    /// boxed closures and the hook in `log-server`) rather than from the code that panicked
+    fn in_panic_runtime(&self, ra: u64) -> bool {
+        const PANIC_RUNTIME: [&str; 10] = [
+            "std::", "<std::", "core::", "<core::", "alloc::", "<alloc::",
+            "log_server::crash::", "rust_begin_unwind", "rust_panic", "__rust",
+        ];
+        match self.find(ra.saturating_sub(1)) {
+            Some(s) => {
+                let name = format!("{:#}", rustc_demangle::demangle(&s.name));
+                PANIC_RUNTIME.iter().any(|prefix| name.starts_with(prefix))
+            }
+            None => false,
+        }
+    }
+}
+
+fn collect<E: Entry>(elf: &ElfFile, entries: &[E], functions: &mut Vec<Symbol>) {
+    for entry in entries {
+        if let (Ok(SymbolType::Func), Ok(name)) = (entry.get_type(), entry.get_name(elf)) {
+            if entry.value() != 0 {
+                functions.push(Symbol { addr: entry.value(), size: entry.size(), name: name.to_owned() });
+            }
+        }
+    }
+}
+
+fn parse_addr(s: &str) -> Option<u64> {
+    let s = s.trim();
+    u64::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 16).ok()
+}
+
+/// Extracts the report from `input`, dropping console noise around an exported report
+fn report_lines(input: &str) -> Vec<&str> {
+    let exported = input.contains(EXPORT_MARKER);
+    let mut lines = Vec::new();
+    let mut started = false;
+    for line in input.lines() {
+        let line = if exported {
+            match line.find(EXPORT_MARKER) {
+                Some(idx) => &line[idx + EXPORT_MARKER.len()..],
+                None => continue,
+            }
+        } else {
+            line
+        };
+        let line = line.trim_end_matches('\r');
+        if REPORT_HEADERS.contains(&line) {
+            // only the last report in the input is kept
+            lines.clear();
+            started = true;
+        }
+        if started {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!(
+            "Usage: {} process.elf [report.txt]",
+            args.first().unwrap_or(&"symbolize-crash".to_owned())
+        );
+        println!("The report is read from stdin if no file is given.");
+        return;
+    }
+
+    let mut elf_data = Vec::new();
+    File::open(&args[1])
+        .and_then(|mut f| f.read_to_end(&mut elf_data))
+        .unwrap_or_else(|e| {
+            eprintln!("Unable to read ELF file {}: {}", args[1], e);
+            process::exit(1);
+        });
+    let elf = ElfFile::new(&elf_data).unwrap_or_else(|e| {
+        eprintln!("Unable to parse ELF file {}: {}", args[1], e);
+        process::exit(1);
+    });
+    let symbols = Symbols::load(&elf);
+    if symbols.functions.is_empty() {
+        eprintln!("Warning: {} has no function symbols; was it stripped?", args[1]);
+    }
+
+    let mut input = String::new();
+    match args.get(2) {
+        Some(path) => File::open(path).and_then(|mut f| f.read_to_string(&mut input)),
+        None => io::stdin().read_to_string(&mut input),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("Unable to read crash report: {}", e);
+        process::exit(1);
+    });
+    let lines = report_lines(&input);
+    if lines.is_empty() {
+        eprintln!("No crash report found (expected a line reading \"{}\")", REPORT_HEADERS[0]);
+        process::exit(1);
+    }
+
+    let show = |label: &str, addr: u64, symbol: Option<String>| {
+        println!("{:>6} {:#010x}  {}", label, addr, symbol.unwrap_or_else(|| "??".to_owned()));
+    };
+    // (return address, frame pointer it was saved under)
+    let mut frames: Vec<(u64, Option<u64>)> = Vec::new();
+    let mut stack = Vec::new();
+    let mut message = None;
+    for (idx, line) in lines.iter().enumerate() {
+        let (key, value) = match line.split_once(": ") {
+            Some(kv) => kv,
+            None => {
+                if *line == "message:" {
+                    message = Some(lines[idx + 1..].join("\n"));
+                    break;
+                }
+                continue;
+            }
+        };
+        match key {
+            "pid" | "name" | "time" => println!("{}: {}", key, value),
+            "pc" | "fp" | "sp" => {
+                if let Some(addr) = parse_addr(value) {
+                    let symbol = if key == "pc" { symbols.lookup(addr) } else { None };
+                    show(key, addr, symbol);
+                }
+            }
+            "ra" => {
+                if let Some(addr) = parse_addr(value) {
+                    show(key, addr, symbols.lookup_return(addr));
+                }
+            }
+            "frame" => {
+                let mut fields = value.split_whitespace();
+                if let Some(addr) = fields.next().and_then(parse_addr) {
+                    show(&format!("#{}", frames.len()), addr, symbols.lookup_return(addr));
+                    frames.push((addr, fields.next().and_then(parse_addr)));
+                }
+            }
+            _ => (),
+        }
+        if let Some(words) = line.strip_prefix("stack:") {
+            stack.extend(words.split_whitespace().filter_map(parse_addr));
+        }
+    }
+
+    // Frame #k was saved by a function called from the one `ra` returns to, and its frame pointer is
+    // where the stack pointer of the caller was at the call, so the caller's registers at the call
+    // are all in the walk: its own frame pointer and return address come from frame #k+1.
+    let call_site = frames.iter().position(|&(ra, _)| !symbols.in_panic_runtime(ra));
+    if let Some(k) = call_site {
+        if let (ra, Some(sp)) = frames[k] {
+            println!("registers at the call to panic (frame #{}):", k);
+            show("pc", ra, symbols.lookup_return(ra));
+            show("sp", sp, None);
+            if let Some(&(caller_ra, caller_fp)) = frames.get(k + 1) {
+                if let Some(fp) = caller_fp {
+                    show("fp", fp, None);
+                }
+                show("ra", caller_ra, symbols.lookup_return(caller_ra));
+            }
+        }
+    }
+
+    // Without frame pointers the walk above comes up short, but return addresses are still on
+    // the stack. Anything that points into .text is worth a look, though some will be stale.
+    let candidates: Vec<u64> = stack.into_iter().filter(|&w| symbols.is_text(w)).collect();
+    if !candidates.is_empty() {
+        println!("possible return addresses on the stack:");
+        for addr in candidates {
+            show("", addr, symbols.lookup_return(addr));
+        }
+    }
+    if let Some(message) = message {
+        println!("message:\n{}", message);
+    }
+}
@@ -457,6 +457,21 @@ pub enum SysCall {
         usize, /* proposed new limit */
     ),
 
+    /// Returns the memory the calling thread's stack is in: the run of pages
+    /// around its stack pointer that are mapped or reserved for this process.
+    /// The kernel doesn't record where a thread's stack was allocated, so
+    /// memory that was mapped right next to it is included too. Every address
+    /// in the range can be read without faulting.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a MemoryRange covering the stack.
+    ///
+    /// ## Errors
+    ///
+    ///     * **BadAddress**: The stack pointer isn't in memory owned by this process
+    GetStackBounds,
+
     /// This syscall does not exist. It captures all possible
     /// arguments so detailed analysis can be performed.
     Invalid(usize, usize, usize, usize, usize, usize, usize),
@@ -501,6 +516,7 @@ pub enum SysCallNumber {
     JoinThread = 36,
     SetExceptionHandler = 37,
     AdjustProcessLimit = 38,
+    GetStackBounds = 39,
     Invalid,
 }
 
@@ -545,6 +561,7 @@ impl SysCallNumber {
             36 => JoinThread,
             37 => SetExceptionHandler,
             38 => AdjustProcessLimit,
+            39 => GetStackBounds,
             _ => Invalid,
         }
     }
@@ -909,6 +926,16 @@ impl SysCall {
                 0,
                 0,
             ],
+            SysCall::GetStackBounds => [
+                SysCallNumber::GetStackBounds as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
             SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7) => [
                 SysCallNumber::Invalid as usize,
                 *a1,
@@ -1073,6 +1100,7 @@ impl SysCall {
             SysCallNumber::JoinThread => SysCall::JoinThread(a1 as _),
             SysCallNumber::SetExceptionHandler => SysCall::SetExceptionHandler(a1 as _, a2 as _),
             SysCallNumber::AdjustProcessLimit => SysCall::AdjustProcessLimit(a1, a2, a3),
+            SysCallNumber::GetStackBounds => SysCall::GetStackBounds,
             SysCallNumber::Invalid => SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
         })
     }
@@ -1758,6 +1786,17 @@ pub fn current_tid() -> core::result::Result<TID, Error> {
     })
 }
 
+/// Get the memory the current thread's stack is in. See `SysCall::GetStackBounds`.
+pub fn stack_bounds() -> core::result::Result<MemoryRange, Error> {
+    rsyscall(SysCall::GetStackBounds).and_then(|result| {
+        if let Result::MemoryRange(range) = result {
+            Ok(range)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
 pub fn destroy_server(sid: SID) -> core::result::Result<(), Error> {
     rsyscall(SysCall::DestroyServer(sid)).and_then(|result| {
         if let Result::Ok = result {
@@ -84,14 +84,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let benchmark_pkgs = [
         "benchmark",
         "benchmark-target",
-        "graphics-server",
         "ticktimer-server",
         "log-server",
         "xous-names",
-        "keyboard",
         "trng",
         "susres",
         "llio",
+        "sha2",
+        "engine-25519",
     ];
     let minimal_pkgs = [
         "ticktimer-server",
@@ -395,7 +395,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             run(
                 false,
                 &hw_pkgs,
-                Some(&["--features", "pddb/ci", "--features", "pddb/deterministic"]),
+                Some(&["--features", "pddb/ci", "--features", "pddb/deterministic", "--features", "pddb/powerloss"]),
                 false,
             )?
         }
@@ -555,6 +555,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &[],
             None,
         )?,
+        Some("benchmark-hosted") => run(false, &benchmark_pkgs, None, false)?,
+        Some("benchmark-pddb") => {
+            generate_app_menus(&Vec::<String>::new());
+            let mut pkgs = hw_pkgs.to_vec();
+            pkgs.push("benchmark");
+            pkgs.push("benchmark-target");
+            run(false, &pkgs, Some(&["--features", "benchmark/pddb-suite"]), false)?
+        }
         Some("minimal") => build_hw_image(
             false,
             env::args().nth(2),
@@ -668,6 +676,8 @@ Direct USB updates:
 Various debug configurations:
  debug                   runs a debug build using a hosted environment
  benchmark [soc.svd]     builds a benchmarking image for real hardware
+ benchmark-hosted        runs the benchmarks in a hosted environment
+ benchmark-pddb          runs the benchmarks in a full hosted environment, including the PDDB suite (runs once the PDDB is mounted)
  minimal [soc.svd]       builds a minimal image for API testing
  cbtest                  builds an image for callback testing
  trng-test [soc.svd]     builds an image for TRNG testing - urandom source seeded by TRNG+AV
@@ -677,7 +687,7 @@ Various debug configurations:
  wycheproof-import       generate binary test vectors for engine-25519 from whycheproof-import/x25519.json
  pddb-dev                PDDB testing only for live hardware
  pddb-hosted             PDDB testing in a hosted environment
- pddb-ci                 PDDB config for CI testing (eg: TRNG->deterministic for reproducible errors)
+ pddb-ci                 PDDB config for CI testing (eg: TRNG->deterministic for reproducible errors), with power loss injection
  ffi-test                builds an image for testing C-FFI bindings and integration
  tts                     builds an image with text to speech support via externally linked C executable
  usbdev                  minimal, insecure build for new USB core bringup